@@ -1,4 +1,14 @@
-use elf::{ElfBytes, abi::PT_LOAD, endian::EndianParse};
+use elf::{
+    ElfBytes,
+    abi::{
+        PF_R, PF_W, PF_X, PT_LOAD, SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, SHT_NOBITS, SHT_PROGBITS,
+    },
+    endian::EndianParse,
+    section::SectionHeader,
+    segment::ProgramHeader,
+};
+use log::info;
+use std::ops::Range;
 use thiserror::Error;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -11,7 +21,7 @@ pub enum AddressRangeType {
     Ignore,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct AddressRange {
     pub typ: AddressRangeType,
     pub to: u64,
@@ -36,7 +46,7 @@ impl Default for AddressRange {
 
 #[derive(Error, Debug)]
 pub enum AddressRangesFromElfError {
-    #[error("No segments in ELF")]
+    #[error("No loadable segments or sections in ELF")]
     NoSegments,
     #[error("ELF contains memory contents for uninitialized memory at {0:08x}")]
     MemoryContentsForUninitializedMemory(u64),
@@ -44,12 +54,117 @@ pub enum AddressRangesFromElfError {
     MemorySegmentInvalidForDevice(u64, u64),
 }
 
+/// Exposes types of the `elf` crate, so it isn't part of the supported API.
+#[doc(hidden)]
 pub fn address_ranges_from_elf<E: EndianParse>(
     file: &ElfBytes<'_, E>,
 ) -> Result<Vec<AddressRange>, AddressRangesFromElfError> {
+    address_ranges_from_segments(&elf_segments(file))
+}
+
+/// The program headers of `file`, see [`loadable_segments`].
+pub(crate) fn elf_segments<E: EndianParse>(file: &ElfBytes<'_, E>) -> Vec<ProgramHeader> {
     let segments = file
         .segments()
-        .ok_or(AddressRangesFromElfError::NoSegments)?;
+        .map(|segments| segments.iter().collect())
+        .unwrap_or_default();
+    let sections: Vec<SectionHeader> = file
+        .section_headers()
+        .map(|sections| sections.iter().collect())
+        .unwrap_or_default();
+
+    loadable_segments(segments, &sections)
+}
+
+/// The program headers, or segments made up from the allocated sections when none of them are
+/// loadable.
+///
+/// Some post-processing tools strip or zero the program headers and leave the section headers
+/// behind. Sections only know the address they run at, so the image is loaded there, which is
+/// wrong for sections like `.data` that are copied from flash at startup.
+pub(crate) fn loadable_segments(
+    segments: Vec<ProgramHeader>,
+    sections: &[SectionHeader],
+) -> Vec<ProgramHeader> {
+    if segments
+        .iter()
+        .any(|segment| segment.p_type == PT_LOAD && segment.p_memsz > 0)
+    {
+        return segments;
+    }
+
+    let from_sections: Vec<ProgramHeader> = sections
+        .iter()
+        .filter(|section| {
+            section.sh_flags & SHF_ALLOC as u64 != 0
+                && (section.sh_type == SHT_PROGBITS || section.sh_type == SHT_NOBITS)
+                && section.sh_size > 0
+        })
+        .map(|section| {
+            let mut p_flags = PF_R;
+            if section.sh_flags & SHF_WRITE as u64 != 0 {
+                p_flags |= PF_W;
+            }
+            if section.sh_flags & SHF_EXECINSTR as u64 != 0 {
+                p_flags |= PF_X;
+            }
+
+            ProgramHeader {
+                p_type: PT_LOAD,
+                p_offset: section.sh_offset,
+                p_vaddr: section.sh_addr,
+                p_paddr: section.sh_addr,
+                p_filesz: if section.sh_type == SHT_NOBITS {
+                    0
+                } else {
+                    section.sh_size
+                },
+                p_memsz: section.sh_size,
+                p_flags,
+                p_align: section.sh_addralign,
+            }
+        })
+        .collect();
+
+    if from_sections.is_empty() {
+        return segments;
+    }
+
+    info!(
+        "No loadable program headers in the ELF, using its {} allocated sections instead",
+        from_sections.len()
+    );
+    from_sections
+}
+
+/// The addresses `section` is loaded to, translated through the segment that runs it, so a
+/// section that is copied to RAM at startup covers its load image in flash.
+pub(crate) fn section_load_range(
+    section: &SectionHeader,
+    segments: &[ProgramHeader],
+) -> Range<u64> {
+    let start = segments
+        .iter()
+        .find(|segment| {
+            segment.p_type == PT_LOAD
+                && segment.p_vaddr <= section.sh_addr
+                && section.sh_addr < segment.p_vaddr.saturating_add(segment.p_memsz)
+        })
+        .map_or(section.sh_addr, |segment| {
+            segment.p_paddr + (section.sh_addr - segment.p_vaddr)
+        });
+
+    start..start.saturating_add(section.sh_size)
+}
+
+/// Same as [`address_ranges_from_elf`], but for already parsed program headers.
+#[doc(hidden)]
+pub fn address_ranges_from_segments(
+    segments: &[ProgramHeader],
+) -> Result<Vec<AddressRange>, AddressRangesFromElfError> {
+    if segments.is_empty() {
+        return Err(AddressRangesFromElfError::NoSegments);
+    }
 
     let mut ranges = Vec::new();
 
@@ -0,0 +1,142 @@
+//! Adafruit's nRF52840 boards, which all run the Adafruit nRF52 bootloader and only differ in the
+//! product id it reports.
+//!
+//! <https://github.com/adafruit/Adafruit_nRF52_Bootloader/tree/master/src/boards>
+
+use std::{borrow::Cow, time::Duration};
+
+use crate::{
+    address_range::AddressRange,
+    boards::{
+        BoardInfo, UsbDevice,
+        circuit_playground_bluefruit::{FLASH, INTER_CHUNK_DELAY, RAM, WRITE_CHUNK_SIZE},
+    },
+    info_uf2::InfoUf2,
+};
+
+const ADAFRUIT_VENDOR_ID: u16 = 0x239a;
+
+macro_rules! adafruit_nrf52840_board {
+    ($(#[$doc:meta])* $board:ident, $name:literal, $description:literal, $product_id:literal, $board_id:literal) => {
+        $(#[$doc])*
+        #[derive(Debug, Default, Clone)]
+        pub struct $board;
+
+        impl BoardInfo for $board {
+            fn is_device_board(&self, device: &UsbDevice) -> bool {
+                device.vendor_id == ADAFRUIT_VENDOR_ID && device.product_id == $product_id
+            }
+
+            fn usb_matches(&self) -> Vec<(u16, u16)> {
+                vec![(ADAFRUIT_VENDOR_ID, $product_id)]
+            }
+
+            fn family_id(&self) -> u32 {
+                0xada52840
+            }
+
+            fn page_size(&self) -> u32 {
+                256
+            }
+
+            fn flash_sector_erase_size(&self) -> u64 {
+                4096
+            }
+
+            fn board_name(&self) -> Cow<'static, str> {
+                $name.into()
+            }
+
+            fn board_description(&self) -> Option<Cow<'static, str>> {
+                Some($description.into())
+            }
+
+            fn valid_address_ranges(&self) -> Vec<AddressRange> {
+                vec![FLASH, RAM]
+            }
+
+            fn ram_address_ranges(&self) -> Vec<AddressRange> {
+                vec![RAM]
+            }
+
+            fn matches_info_uf2(&self, info: &InfoUf2) -> bool {
+                info.board_id_starts_with($board_id)
+            }
+
+            fn write_chunk_size(&self) -> usize {
+                WRITE_CHUNK_SIZE
+            }
+
+            fn inter_chunk_delay(&self) -> Option<Duration> {
+                Some(INTER_CHUNK_DELAY)
+            }
+        }
+    };
+}
+
+adafruit_nrf52840_board!(
+    /// The Adafruit Feather nRF52840 Express
+    FeatherNrf52840Express,
+    "feather_nrf52840_express",
+    "Adafruit Feather nRF52840 Express",
+    0x0029,
+    "nRF52840-Feather-"
+);
+adafruit_nrf52840_board!(
+    /// The Adafruit ItsyBitsy nRF52840 Express
+    ItsyBitsyNrf52840,
+    "itsybitsy_nrf52840",
+    "Adafruit ItsyBitsy nRF52840 Express",
+    0x0051,
+    "nRF52840-ItsyBitsy-"
+);
+adafruit_nrf52840_board!(
+    /// The Adafruit CLUE
+    ClueNrf52840,
+    "clue_nrf52840",
+    "Adafruit CLUE",
+    0x0071,
+    "nRF52840-Clue-"
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boards::{BoardIter, usb_device as device};
+
+    fn detect(device: &UsbDevice) -> Option<String> {
+        BoardIter::new()
+            .find(|board| board.is_device_board(device))
+            .map(|board| board.board_name().into_owned())
+    }
+
+    #[test]
+    fn boards_are_told_apart_by_product_id() {
+        let boards: [(&dyn BoardInfo, u16); 3] = [
+            (&FeatherNrf52840Express, 0x0029),
+            (&ItsyBitsyNrf52840, 0x0051),
+            (&ClueNrf52840, 0x0071),
+        ];
+        for (board, product_id) in boards {
+            assert_eq!(
+                detect(&device(0x239a, product_id)),
+                Some(board.board_name().into_owned())
+            );
+            assert_eq!(board.family_id(), 0xada52840);
+            assert_eq!(board.page_size(), 256);
+            assert_eq!(board.flash_sector_erase_size(), 4096);
+
+            // Neighbouring product ids, and the same id from another vendor
+            for near_miss in [product_id - 1, product_id + 1] {
+                assert!(!board.is_device_board(&device(0x239a, near_miss)));
+            }
+            assert!(!board.is_device_board(&device(0x2e8a, product_id)));
+        }
+
+        assert_eq!(
+            detect(&device(0x239a, 0x0045)).as_deref(),
+            Some("circuit_playground_bluefruit")
+        );
+        assert_eq!(detect(&device(0x239a, 0x002a)), None);
+    }
+}
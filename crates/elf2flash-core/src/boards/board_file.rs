@@ -0,0 +1,343 @@
+//! Boards defined in a TOML file instead of in code, so a new uf2 board doesn't need a release.
+//!
+//! ```toml
+//! [[board]]
+//! name = "feather_m4"
+//! vendor_id = 0x239a
+//! # One id, or a list of them
+//! product_id = [0x0022, 0x00cd]
+//! # A number, or a name from the uf2 family list
+//! family_id = "SAMD51"
+//! page_size = 256
+//! flash_sector_erase_size = 8192
+//! # Only accept blocks in the flash, after the bootloader
+//! flash_start = 0x4000
+//! flash_size = 0x7c000
+//! # The file the uf2 is written as, out.uf2 when not given
+//! uf2_filename = "CURRENT.UF2"
+//! # Write 4 KiB at a time and wait 10 ms in between, for bootloaders with small buffers
+//! write_chunk_size = 4096
+//! inter_chunk_delay_ms = 10
+//! ```
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Deserialize;
+use thiserror::Error;
+use toml::Spanned;
+
+use crate::{
+    address_range::{AddressRange, AddressRangeType},
+    boards::{CustomBoard, CustomBoardBuildError, CustomBoardBuilder, family::family_by_name},
+};
+
+#[derive(Error, Debug)]
+pub enum BoardFileError {
+    #[error("Failed to read the board file {}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("Invalid board file")]
+    Parse(#[from] toml::de::Error),
+    #[error("Line {line}: the board has no name")]
+    MissingName { line: usize },
+    #[error("Line {line}: board '{name}' has no {field}")]
+    MissingField {
+        line: usize,
+        name: String,
+        field: &'static str,
+    },
+    #[error("Line {line}: board '{name}' is already defined on line {first_line}")]
+    DuplicateName {
+        line: usize,
+        name: String,
+        first_line: usize,
+    },
+    #[error("Line {line}: board '{name}' has the unknown family '{family}'")]
+    UnknownFamily {
+        line: usize,
+        name: String,
+        family: String,
+    },
+    #[error("Line {line}: board '{name}' needs both flash_start and flash_size, or neither")]
+    PartialFlashRange { line: usize, name: String },
+    #[error("Line {line}: the flash of board '{name}' doesn't fit in the address space")]
+    FlashRangeOverflow { line: usize, name: String },
+    #[error("Line {line}: board '{name}' is invalid")]
+    InvalidBoard {
+        line: usize,
+        name: String,
+        source: CustomBoardBuildError,
+    },
+}
+
+#[derive(Deserialize)]
+struct BoardFile {
+    #[serde(default)]
+    board: Vec<Spanned<BoardEntry>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ProductIds {
+    One(u16),
+    Many(Vec<u16>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FamilyId {
+    Id(u32),
+    Name(String),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BoardEntry {
+    name: Option<String>,
+    vendor_id: Option<u16>,
+    product_id: Option<ProductIds>,
+    family_id: Option<FamilyId>,
+    page_size: Option<u32>,
+    flash_sector_erase_size: Option<u64>,
+    flash_start: Option<u64>,
+    flash_size: Option<u64>,
+    uf2_filename: Option<String>,
+    write_chunk_size: Option<usize>,
+    inter_chunk_delay_ms: Option<u64>,
+}
+
+/// Read the boards of the TOML file at `path`, see [`parse_toml`].
+pub fn load_from_toml(path: &Path) -> Result<Vec<CustomBoard>, BoardFileError> {
+    let text = fs::read_to_string(path).map_err(|source| BoardFileError::Read {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_toml(&text)
+}
+
+/// The boards of the `[[board]]` tables of `text`. Every board needs a `name`, `vendor_id`,
+/// `product_id` and `family_id`, so it only matches its own devices. The boards aren't registered,
+/// pass them to [`BoardRegistry::register`](crate::boards::BoardRegistry::register) for that.
+pub fn parse_toml(text: &str) -> Result<Vec<CustomBoard>, BoardFileError> {
+    let file: BoardFile = toml::from_str(text)?;
+
+    let mut first_lines = HashMap::new();
+    let mut boards = Vec::new();
+    for entry in file.board {
+        let line = text[..entry.span().start].matches('\n').count() + 1;
+        let entry = entry.into_inner();
+
+        let name = entry.name.ok_or(BoardFileError::MissingName { line })?;
+        if let Some(&first_line) = first_lines.get(&name.to_ascii_lowercase()) {
+            return Err(BoardFileError::DuplicateName {
+                line,
+                name,
+                first_line,
+            });
+        }
+        first_lines.insert(name.to_ascii_lowercase(), line);
+
+        let missing = |field| BoardFileError::MissingField {
+            line,
+            name: name.clone(),
+            field,
+        };
+        let vendor_id = entry.vendor_id.ok_or_else(|| missing("vendor_id"))?;
+        let product_ids = match entry.product_id.ok_or_else(|| missing("product_id"))? {
+            ProductIds::One(product_id) => vec![product_id],
+            ProductIds::Many(product_ids) if product_ids.is_empty() => {
+                return Err(missing("product_id"));
+            }
+            ProductIds::Many(product_ids) => product_ids,
+        };
+        let family_id = match entry.family_id.ok_or_else(|| missing("family_id"))? {
+            FamilyId::Id(family_id) => family_id,
+            FamilyId::Name(family) => {
+                family_by_name(&family).ok_or_else(|| BoardFileError::UnknownFamily {
+                    line,
+                    name: name.clone(),
+                    family,
+                })?
+            }
+        };
+
+        let mut builder = CustomBoardBuilder::new()
+            .board_name(name.clone())
+            .vendor_id(vendor_id)
+            .product_ids(product_ids)
+            .family_id(family_id);
+        if let Some(page_size) = entry.page_size {
+            builder = builder.page_size(page_size);
+        }
+        if let Some(size) = entry.flash_sector_erase_size {
+            builder = builder.flash_sector_erase_size(size);
+        }
+        if let Some(uf2_filename) = entry.uf2_filename {
+            builder = builder.uf2_filename(uf2_filename);
+        }
+        if let Some(chunk_size) = entry.write_chunk_size {
+            builder = builder.write_chunk_size(chunk_size);
+        }
+        if let Some(delay) = entry.inter_chunk_delay_ms {
+            builder = builder.inter_chunk_delay(Duration::from_millis(delay));
+        }
+        match (entry.flash_start, entry.flash_size) {
+            (Some(start), Some(size)) => {
+                let end =
+                    start
+                        .checked_add(size)
+                        .ok_or_else(|| BoardFileError::FlashRangeOverflow {
+                            line,
+                            name: name.clone(),
+                        })?;
+                builder = builder.address_ranges(vec![AddressRange::new(
+                    start,
+                    end,
+                    AddressRangeType::Contents,
+                )]);
+            }
+            (None, None) => {}
+            _ => return Err(BoardFileError::PartialFlashRange { line, name }),
+        }
+
+        let board = builder
+            .build()
+            .map_err(|source| BoardFileError::InvalidBoard {
+                line,
+                name: name.clone(),
+                source,
+            })?;
+        boards.push(board);
+    }
+
+    Ok(boards)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boards::{BoardInfo, usb_device as device};
+
+    const EXAMPLE: &str = include_str!("../../boards.example.toml");
+
+    #[test]
+    fn example_file_loads() {
+        let boards = parse_toml(EXAMPLE).unwrap();
+        assert!(!boards.is_empty());
+
+        let feather = boards
+            .iter()
+            .find(|board| board.board_name() == "feather_m4")
+            .unwrap();
+        assert_eq!(feather.family_id(), 0x55114460);
+        assert_eq!(feather.flash_sector_erase_size(), 8192);
+        assert!(feather.is_device_board(&device(0x239a, 0x0022)));
+        assert!(feather.is_device_board(&device(0x239a, 0x00cd)));
+        assert!(!feather.is_device_board(&device(0x239a, 0x0045)));
+        let flash = &feather.valid_address_ranges()[0];
+        assert_eq!((flash.from, flash.to), (0x4000, 0x80000));
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        let text = r#"
+[[board]]
+name = "bench"
+vendor_id = 0x1209
+product_id = 1
+family_id = 0x12345678
+
+[[board]]
+name = "Bench"
+vendor_id = 0x1209
+product_id = 2
+family_id = 0x12345678
+"#;
+        let err = parse_toml(text).unwrap_err();
+        assert!(matches!(
+            err,
+            BoardFileError::DuplicateName {
+                line: 8,
+                first_line: 2,
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Line 8: board 'Bench' is already defined on line 2"
+        );
+    }
+
+    #[test]
+    fn missing_family_id_names_the_board() {
+        let text = r#"
+[[board]]
+name = "first"
+vendor_id = 0x1209
+product_id = 1
+family_id = "RP2040"
+
+[[board]]
+name = "second"
+vendor_id = 0x1209
+product_id = 2
+"#;
+        let err = parse_toml(text).unwrap_err();
+        assert_eq!(err.to_string(), "Line 8: board 'second' has no family_id");
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        let board = |fields: &str| {
+            parse_toml(&format!(
+                "[[board]]\nname = \"bench\"\nvendor_id = 1\nproduct_id = 2\n{fields}"
+            ))
+        };
+        assert!(board("family_id = 1").is_ok());
+        assert!(matches!(
+            board("family_id = \"NOT_A_FAMILY\""),
+            Err(BoardFileError::UnknownFamily { line: 1, .. })
+        ));
+        assert!(matches!(
+            board("family_id = 1\nflash_start = 0x1000"),
+            Err(BoardFileError::PartialFlashRange { .. })
+        ));
+        assert!(matches!(
+            board("family_id = 1\npage_size = 300"),
+            Err(BoardFileError::InvalidBoard {
+                source: CustomBoardBuildError::InvalidPageSize(300),
+                ..
+            })
+        ));
+        assert!(matches!(
+            board("family_id = 1\nuf2_filename = \"fw:1.uf2\""),
+            Err(BoardFileError::InvalidBoard {
+                source: CustomBoardBuildError::InvalidUf2Filename(_),
+                ..
+            })
+        ));
+        assert_eq!(
+            board("family_id = 1\nuf2_filename = \"CURRENT.UF2\"").unwrap()[0].uf2_filename(),
+            "CURRENT.UF2"
+        );
+        assert!(matches!(
+            board("family_id = 1\nwrite_chunk_size = 100"),
+            Err(BoardFileError::InvalidBoard {
+                source: CustomBoardBuildError::InvalidWriteChunkSize(100),
+                ..
+            })
+        ));
+        assert!(matches!(
+            board("family_id = 1\nflash_bytes = 4"),
+            Err(BoardFileError::Parse(_))
+        ));
+        assert!(matches!(
+            parse_toml("[[board]]\nvendor_id = 1"),
+            Err(BoardFileError::MissingName { line: 1 })
+        ));
+    }
+}
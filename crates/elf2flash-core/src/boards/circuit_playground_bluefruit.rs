@@ -1,4 +1,23 @@
-use crate::boards::{BoardInfo, UsbDevice};
+use std::{borrow::Cow, time::Duration};
+
+use crate::{
+    address_range::{AddressRange, AddressRangeType},
+    boards::{BoardInfo, UsbDevice},
+    info_uf2::InfoUf2,
+};
+
+/// 1MiB of internal flash (the softdevice and bootloader live in here too), the same on every
+/// nRF52840 board
+pub(super) const FLASH: AddressRange =
+    AddressRange::new(0x00000000, 0x00100000, AddressRangeType::Contents);
+/// 256KiB of RAM
+pub(super) const RAM: AddressRange =
+    AddressRange::new(0x20000000, 0x20040000, AddressRangeType::Contents);
+/// The Adafruit nRF52 bootloader buffers the blocks it receives in a few KiB of RAM while it
+/// writes the flash, larger writes overrun it and blocks get dropped
+pub(super) const WRITE_CHUNK_SIZE: usize = 4096;
+/// Time for the bootloader to write the buffered blocks to flash before the next chunk
+pub(super) const INTER_CHUNK_DELAY: Duration = Duration::from_millis(10);
 
 /// This is the Circuit Playfround Bluefruit board
 #[derive(Debug, Default, Clone)]
@@ -16,11 +35,39 @@ impl BoardInfo for CircuitPlaygroundBluefruit {
         }
     }
 
+    fn usb_matches(&self) -> Vec<(u16, u16)> {
+        vec![(0x239a, 0x0045)]
+    }
+
     fn family_id(&self) -> u32 {
         0xada52840
     }
 
-    fn board_name(&self) -> String {
-        "circuit_playground_bluefruit".to_string()
+    fn board_name(&self) -> Cow<'static, str> {
+        "circuit_playground_bluefruit".into()
+    }
+
+    fn board_description(&self) -> Option<Cow<'static, str>> {
+        Some("Adafruit Circuit Playground Bluefruit".into())
+    }
+
+    fn valid_address_ranges(&self) -> Vec<AddressRange> {
+        vec![FLASH, RAM]
+    }
+
+    fn ram_address_ranges(&self) -> Vec<AddressRange> {
+        vec![RAM]
+    }
+
+    fn matches_info_uf2(&self, info: &InfoUf2) -> bool {
+        info.board_id_starts_with("nRF52840-CircuitPlayground-")
+    }
+
+    fn write_chunk_size(&self) -> usize {
+        WRITE_CHUNK_SIZE
+    }
+
+    fn inter_chunk_delay(&self) -> Option<Duration> {
+        Some(INTER_CHUNK_DELAY)
     }
 }
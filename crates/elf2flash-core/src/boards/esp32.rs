@@ -0,0 +1,176 @@
+//! The ESP32-S2 and ESP32-S3 running TinyUF2. TinyUF2 writes the uf2 file into the OTA app
+//! partition, so block addresses are offsets into that partition rather than into the flash.
+//!
+//! <https://github.com/adafruit/tinyuf2/tree/master/ports/espressif/boards>
+
+use std::borrow::Cow;
+
+use crate::{
+    address_range::{AddressRange, AddressRangeType},
+    boards::{BoardInfo, UsbDevice},
+    info_uf2::InfoUf2,
+};
+
+/// Offsets into the app partition, which is never larger than the 16MiB flash
+const APP_PARTITION: AddressRange =
+    AddressRange::new(0x00000000, 0x01000000, AddressRangeType::Contents);
+
+const ESPRESSIF_VENDOR_ID: u16 = 0x303a;
+const ADAFRUIT_VENDOR_ID: u16 = 0x239a;
+
+/// The vendor and product ids of TinyUF2 on ESP32-S2 boards
+const ESP32S2_DEVICES: &[(u16, u16)] = &[
+    // Saola-1 WROVER and WROOM
+    (ESPRESSIF_VENDOR_ID, 0x7000),
+    (ESPRESSIF_VENDOR_ID, 0x7001),
+    // Kaluga-1
+    (ESPRESSIF_VENDOR_ID, 0x7002),
+    // Feather ESP32-S2
+    (ADAFRUIT_VENDOR_ID, 0x00eb),
+    // Metro ESP32-S2
+    (ADAFRUIT_VENDOR_ID, 0x00df),
+];
+
+/// The vendor and product ids of TinyUF2 on ESP32-S3 boards
+const ESP32S3_DEVICES: &[(u16, u16)] = &[
+    // ESP32-S3-DevKitC-1 and DevKitM-1
+    (ESPRESSIF_VENDOR_ID, 0x7003),
+    (ESPRESSIF_VENDOR_ID, 0x7004),
+    // Feather ESP32-S3
+    (ADAFRUIT_VENDOR_ID, 0x0113),
+];
+
+fn is_listed(devices: &[(u16, u16)], device: &UsbDevice) -> bool {
+    devices.contains(&(device.vendor_id, device.product_id))
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Esp32S2;
+
+impl BoardInfo for Esp32S2 {
+    fn is_device_board(&self, device: &UsbDevice) -> bool {
+        is_listed(ESP32S2_DEVICES, device)
+    }
+
+    fn usb_matches(&self) -> Vec<(u16, u16)> {
+        ESP32S2_DEVICES.to_vec()
+    }
+
+    fn family_id(&self) -> u32 {
+        0xbfdd4eee
+    }
+
+    fn board_name(&self) -> Cow<'static, str> {
+        "esp32s2".into()
+    }
+
+    fn board_description(&self) -> Option<Cow<'static, str>> {
+        Some("ESP32-S2 with TinyUF2".into())
+    }
+
+    fn valid_address_ranges(&self) -> Vec<AddressRange> {
+        vec![APP_PARTITION]
+    }
+
+    fn matches_info_uf2(&self, info: &InfoUf2) -> bool {
+        info.board_id_starts_with("ESP32S2") || info.board_id_starts_with("ESP32-S2")
+    }
+
+    fn needs_eject(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Esp32S3;
+
+impl BoardInfo for Esp32S3 {
+    fn is_device_board(&self, device: &UsbDevice) -> bool {
+        is_listed(ESP32S3_DEVICES, device)
+    }
+
+    fn usb_matches(&self) -> Vec<(u16, u16)> {
+        ESP32S3_DEVICES.to_vec()
+    }
+
+    fn family_id(&self) -> u32 {
+        0xc47e5767
+    }
+
+    fn board_name(&self) -> Cow<'static, str> {
+        "esp32s3".into()
+    }
+
+    fn board_description(&self) -> Option<Cow<'static, str>> {
+        Some("ESP32-S3 with TinyUF2".into())
+    }
+
+    fn valid_address_ranges(&self) -> Vec<AddressRange> {
+        vec![APP_PARTITION]
+    }
+
+    fn matches_info_uf2(&self, info: &InfoUf2) -> bool {
+        info.board_id_starts_with("ESP32S3") || info.board_id_starts_with("ESP32-S3")
+    }
+
+    fn needs_eject(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        NoProgress, Uf2Options,
+        boards::{BoardIter, usb_device},
+        pages_to_uf2,
+        uf2::Uf2Block,
+    };
+
+    fn detect(vendor_id: u16, product_id: u16) -> Option<String> {
+        let device = usb_device(vendor_id, product_id);
+        BoardIter::new()
+            .find(|board| board.is_device_board(&device))
+            .map(|board| board.board_name().into_owned())
+    }
+
+    #[test]
+    fn tinyuf2_devices_are_detected() {
+        assert_eq!(detect(0x239a, 0x00eb).as_deref(), Some("esp32s2"));
+        assert_eq!(detect(0x303a, 0x7000).as_deref(), Some("esp32s2"));
+        assert_eq!(detect(0x303a, 0x7003).as_deref(), Some("esp32s3"));
+        assert_eq!(detect(0x239a, 0x0113).as_deref(), Some("esp32s3"));
+
+        // The Feather ESP32-S2 running CircuitPython, not its bootloader
+        assert_eq!(detect(0x239a, 0x80eb), None);
+        // The ROM's DFU mode isn't a uf2 drive
+        assert_eq!(detect(0x303a, 0x0002), None);
+    }
+
+    #[test]
+    fn partition_offsets_are_accepted() {
+        for board in [&Esp32S2 as &dyn BoardInfo, &Esp32S3] {
+            let convert = |address| {
+                let mut uf2 = Vec::new();
+                pages_to_uf2(
+                    address,
+                    &[0xa5; 512],
+                    &mut uf2,
+                    board,
+                    &Uf2Options::default(),
+                    NoProgress,
+                )
+                .map(|_| uf2)
+            };
+
+            let uf2 = convert(0).unwrap();
+            let block = Uf2Block::from_bytes(uf2[..512].try_into().unwrap()).unwrap();
+            assert_eq!(block.target_addr(), 0);
+            assert_eq!(block.family_id(), Some(board.family_id()));
+
+            // An absolute flash address, as an ESP-IDF app is linked to, is outside the partition
+            assert!(convert(0x42000000).is_err());
+        }
+    }
+}
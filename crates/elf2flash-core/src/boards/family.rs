@@ -0,0 +1,163 @@
+//! The well-known uf2 family ids, from the
+//! [uf2families.json](https://github.com/microsoft/uf2/blob/master/utils/uf2families.json) list.
+
+/// A family id with the names it goes by in the official list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Family {
+    pub id: u32,
+    /// Short name, e.g. `SAMD51`, accepted wherever a family id is
+    pub short_name: &'static str,
+    pub description: &'static str,
+}
+
+const fn family(id: u32, short_name: &'static str, description: &'static str) -> Family {
+    Family {
+        id,
+        short_name,
+        description,
+    }
+}
+
+pub const FAMILIES: &[Family] = &[
+    family(0x16573617, "ATMEGA32", "Microchip (Atmel) ATmega32"),
+    family(0x1851780a, "SAML21", "Microchip (Atmel) SAML21"),
+    family(0x1b57745f, "NRF52", "Nordic NRF52"),
+    family(0x1c5f21b0, "ESP32", "ESP32"),
+    family(0x1e1f432d, "STM32L1", "ST STM32L1xx"),
+    family(0x202e3a91, "STM32L0", "ST STM32L0xx"),
+    family(0x21460ff0, "STM32WL", "ST STM32WLxx"),
+    family(0x2abc77ec, "LPC55", "NXP LPC55xx"),
+    family(0x300f5633, "STM32G0", "ST STM32G0xx"),
+    family(0x31d228c6, "GD32F350", "GD32F350"),
+    family(0x04240bdf, "STM32L5", "ST STM32L5xx"),
+    family(0x4c71240a, "STM32G4", "ST STM32G4xx"),
+    family(0x4fb2d5bd, "MIMXRT10XX", "NXP i.MX RT10XX"),
+    family(0x53b80f00, "STM32F7", "ST STM32F7xx"),
+    family(0x55114460, "SAMD51", "Microchip (Atmel) SAMD51"),
+    family(0x57755a57, "STM32F4", "ST STM32F4xx"),
+    family(0x5a18069b, "FX2", "Cypress FX2"),
+    family(0x5d1a0a2e, "STM32F2", "ST STM32F2xx"),
+    family(0x5ee21072, "STM32F1", "ST STM32F103"),
+    family(0x621e937a, "NRF52833", "Nordic NRF52833"),
+    family(0x647824b6, "STM32F0", "ST STM32F0xx"),
+    family(0x68ed2b88, "SAMD21", "Microchip (Atmel) SAMD21"),
+    family(0x6b846188, "STM32F3", "ST STM32F3xx"),
+    family(0x6d0922fa, "STM32F407", "ST STM32F407"),
+    family(0x6db66082, "STM32H7", "ST STM32H7xx"),
+    family(0x70d16653, "STM32WB", "ST STM32WBxx"),
+    family(0x7eab61ed, "ESP8266", "ESP8266"),
+    family(0x7f83e793, "KL32L2", "NXP KL32L2x"),
+    family(0x8fb060fe, "STM32F407VG", "ST STM32F407VG"),
+    family(0xada52840, "NRF52840", "Nordic NRF52840"),
+    family(0xbfdd4eee, "ESP32S2", "ESP32-S2"),
+    family(0xc47e5767, "ESP32S3", "ESP32-S3"),
+    family(0xd42ba06c, "ESP32C3", "ESP32-C3"),
+    family(0x2b88d29c, "ESP32C2", "ESP32-C2"),
+    family(0x332726f6, "ESP32H2", "ESP32-H2"),
+    family(0x540ddf62, "ESP32C6", "ESP32-C6"),
+    family(0x3d308e94, "ESP32P4", "ESP32-P4"),
+    family(0xde1270b7, "BL602", "Boufallo 602"),
+    family(0xe08f7564, "RTL8710B", "Realtek AmebaZ RTL8710B"),
+    family(0x3379cfe2, "RTL8720C", "Realtek AmebaZ2 RTL8720C"),
+    family(0x9fffd543, "RTL8710A", "Realtek Ameba1 RTL8710A"),
+    family(0xe48bff56, "RP2040", "Raspberry Pi RP2040"),
+    family(
+        0xe48bff57,
+        "RP2XXX_ABSOLUTE",
+        "Raspberry Pi Microcontrollers: Absolute (unpartitioned) download",
+    ),
+    family(
+        0xe48bff58,
+        "RP2XXX_DATA",
+        "Raspberry Pi Microcontrollers: Data partition download",
+    ),
+    family(
+        0xe48bff59,
+        "RP2350_ARM_S",
+        "Raspberry Pi RP2350, Secure Arm image",
+    ),
+    family(
+        0xe48bff5a,
+        "RP2350_RISCV",
+        "Raspberry Pi RP2350, RISC-V image",
+    ),
+    family(
+        0xe48bff5b,
+        "RP2350_ARM_NS",
+        "Raspberry Pi RP2350, Non-secure Arm image",
+    ),
+    family(0x00ff6919, "STM32L4", "ST STM32L4xx"),
+    family(0x9af03e33, "GD32VF103", "GigaDevice GD32VF103"),
+    family(0x4f6ace52, "CSK4", "LISTENAI CSK300x/400x"),
+    family(0x6e7348a8, "CSK6", "LISTENAI CSK60xx"),
+    family(0x11de784a, "M0SENSE", "M0SENSE BL702"),
+    family(0x9517422f, "RZA1LU", "Renesas RZ/A1LU (R7S7210xx)"),
+    family(0x2dc309c5, "STM32F411xE", "ST STM32F411xE"),
+    family(0x06d1097b, "STM32F411xC", "ST STM32F411xC"),
+    family(0x72721d4e, "NRF52832xxAA", "Nordic NRF52832xxAA"),
+    family(0x6f752678, "NRF52832xxAB", "Nordic NRF52832xxAB"),
+    family(0xa0c97b8e, "AT32F415", "ArteryTek AT32F415"),
+    family(0x699b62ec, "CH32V", "WCH CH32V2xx and CH32V3xx"),
+    family(0x7be8976d, "RA4M1", "Renesas RA4M1"),
+];
+
+/// Look up a family id by its short name, ignoring case, e.g. `samd51` or `RP2040`.
+pub fn family_by_name(name: &str) -> Option<u32> {
+    FAMILIES
+        .iter()
+        .find(|family| family.short_name.eq_ignore_ascii_case(name))
+        .map(|family| family.id)
+}
+
+/// The short name of a family id, `None` if it isn't in the list.
+pub fn name_for_family(id: u32) -> Option<&'static str> {
+    FAMILIES
+        .iter()
+        .find(|family| family.id == id)
+        .map(|family| family.short_name)
+}
+
+/// The family id in hex, followed by its name if it is known, e.g. `0xe48bff56 (RP2040)`.
+pub fn describe_family(id: u32) -> String {
+    match name_for_family(id) {
+        Some(name) => format!("{id:#x} ({name})"),
+        None => format!("{id:#x}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn looks_up_names_ignoring_case() {
+        assert_eq!(family_by_name("RP2040"), Some(0xe48bff56));
+        assert_eq!(family_by_name("samd51"), Some(0x55114460));
+        assert_eq!(family_by_name("Esp32s3"), Some(0xc47e5767));
+        assert_eq!(family_by_name("nrf52832XXaa"), Some(0x72721d4e));
+
+        assert_eq!(name_for_family(0xe48bff5a), Some("RP2350_RISCV"));
+        assert_eq!(describe_family(0xada52840), "0xada52840 (NRF52840)");
+    }
+
+    #[test]
+    fn unknown_families() {
+        assert_eq!(family_by_name("RP2040X"), None);
+        assert_eq!(family_by_name(""), None);
+        assert_eq!(name_for_family(0x12345678), None);
+        assert_eq!(describe_family(0x12345678), "0x12345678");
+    }
+
+    #[test]
+    fn ids_and_names_are_unique() {
+        let ids: HashSet<u32> = FAMILIES.iter().map(|family| family.id).collect();
+        assert_eq!(ids.len(), FAMILIES.len());
+
+        let names: HashSet<String> = FAMILIES
+            .iter()
+            .map(|family| family.short_name.to_ascii_lowercase())
+            .collect();
+        assert_eq!(names.len(), FAMILIES.len());
+    }
+}
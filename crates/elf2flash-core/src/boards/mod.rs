@@ -1,27 +1,110 @@
+mod adafruit_nrf52840;
+mod board_file;
 mod circuit_playground_bluefruit;
+mod esp32;
+pub mod family;
+mod registry;
 mod rp2040;
-mod rp2350;
+pub mod rp2350;
 
+pub use adafruit_nrf52840::{ClueNrf52840, FeatherNrf52840Express, ItsyBitsyNrf52840};
+pub use board_file::{BoardFileError, load_from_toml, parse_toml};
 pub use circuit_playground_bluefruit::CircuitPlaygroundBluefruit;
+pub use esp32::{Esp32S2, Esp32S3};
+pub use registry::BoardRegistry;
 pub use rp2040::RP2040;
-pub use rp2350::RP2350;
+pub use rp2350::{RP2350, RP2350ArmNs, RP2350RiscV};
+use std::{borrow::Cow, sync::Arc, time::Duration};
 use thiserror::Error;
 
-/// This is a helper struct, which allows you to iterate over every board defined
+use crate::{
+    address_range::AddressRange,
+    info_uf2::InfoUf2,
+    uf2::{UF2_BLOCK_SIZE, Uf2Block},
+};
+
+/// Smallest page size a board may use
+pub const MIN_PAGE_SIZE: u32 = 64;
+/// Largest page size that fits in a single block, the data area of a uf2 block
+pub const MAX_PAGE_SIZE: u32 = 476;
+/// Largest page size a board may use. Pages larger than [`MAX_PAGE_SIZE`] are split over several
+/// blocks, see [`Uf2Options::payload_size`](crate::Uf2Options::payload_size)
+pub const MAX_SPLIT_PAGE_SIZE: u32 = 64 * 1024;
+
+/// Pages are located with `addr & (page_size - 1)`, so the page size has to be a power of two.
+pub(crate) fn is_valid_page_size(page_size: u32) -> bool {
+    page_size.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_SPLIT_PAGE_SIZE).contains(&page_size)
+}
+
+/// A page is split into whole blocks, each of which has to fit its payload.
+pub(crate) fn is_valid_payload_size(payload_size: u32, page_size: u32) -> bool {
+    payload_size.is_power_of_two()
+        && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&payload_size)
+        && payload_size <= page_size
+}
+
+/// Flash sectors are filled with whole pages.
+pub(crate) fn is_valid_erase_size(erase_size: u64, page_size: u32) -> bool {
+    erase_size != 0 && erase_size.is_multiple_of(page_size as u64)
+}
+
+/// The file the uf2 is written as when the board doesn't name another one
+pub const DEFAULT_UF2_FILENAME: &str = "out.uf2";
+
+/// How many bytes of the uf2 file are written at once when the board doesn't say
+pub const DEFAULT_WRITE_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Chunks hold whole uf2 blocks.
+pub(crate) fn is_valid_write_chunk_size(chunk_size: usize) -> bool {
+    chunk_size != 0 && chunk_size.is_multiple_of(UF2_BLOCK_SIZE)
+}
+
+/// The characters a short name may use besides letters and digits.
+const SHORT_NAME_SYMBOLS: &str = "!#$%&'()-@^_`{}~";
+
+/// The characters a long file name may use besides letters, digits and non-ASCII characters,
+/// the ones fatfs accepts.
+const LONG_NAME_SYMBOLS: &str = "$%'-_@~`!(){}^#&.+,;=[] ";
+
+/// Whether `name` is a legal 8.3 FAT short name like `CURRENT.UF2`, which bootloaders that don't
+/// read long file names see as it is. Lowercase letters are accepted, FAT stores them uppercased.
+pub fn is_fat_short_name(name: &str) -> bool {
+    let (base, extension) = name.split_once('.').unwrap_or((name, ""));
+    let is_short_char = |c: char| c.is_ascii_alphanumeric() || SHORT_NAME_SYMBOLS.contains(c);
+
+    (1..=8).contains(&base.len())
+        && extension.len() <= 3
+        && !name.ends_with('.')
+        && base.chars().chain(extension.chars()).all(is_short_char)
+}
+
+/// Whether a file called `name` can be created in the root directory of a FAT volume, with a
+/// long file name entry if it isn't a short name.
+pub fn is_valid_fat_name(name: &str) -> bool {
+    let is_long_char = |c: char| {
+        c.is_ascii_alphanumeric()
+            || LONG_NAME_SYMBOLS.contains(c)
+            || ('\u{80}'..='\u{ffff}').contains(&c)
+    };
+
+    !name.is_empty()
+        && name.len() <= 255
+        // Windows drops trailing dots and spaces, which leaves "." and ".." empty
+        && !name.ends_with(['.', ' '])
+        && name.chars().all(is_long_char)
+}
+
+/// This is a helper struct, which allows you to iterate over every board in the
+/// [`BoardRegistry`]
 pub struct BoardIter {
-    inner: std::vec::IntoIter<Box<dyn BoardInfo>>,
+    inner: std::vec::IntoIter<Arc<dyn BoardInfo>>,
 }
 
 impl BoardIter {
-    /// Creates a new BoardIter
+    /// Creates a new BoardIter, over the boards registered at the time
     pub fn new() -> Self {
         Self {
-            inner: vec![
-                Box::new(RP2040::default()) as Box<dyn BoardInfo>,
-                Box::new(RP2350::default()),
-                Box::new(CircuitPlaygroundBluefruit::default()),
-            ]
-            .into_iter(),
+            inner: BoardRegistry::snapshot().into_iter(),
         }
     }
 
@@ -38,13 +121,16 @@ impl BoardIter {
 impl Iterator for BoardIter {
     type Item = Box<dyn BoardInfo>;
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next()
+        self.inner
+            .next()
+            .map(|board| Box::new(board) as Box<dyn BoardInfo>)
     }
 }
 
-/// This is the version of the firmware on the usb device
+/// This is the version of the firmware on the usb device, its `bcdDevice`. Versions compare by
+/// major, minor and then sub-minor number.
 #[allow(unused)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct UsbVersion(pub u8, pub u8, pub u8);
 
 /// This is the usb device information from the usb device. It is possible to generate this information with something like
@@ -57,51 +143,263 @@ pub struct UsbDevice {
     pub vendor_id: u16,
     pub product_id: u16,
     pub version: UsbVersion,
+    /// The serial number string, `None` when the device has none or it couldn't be read
+    pub serial_number: Option<String>,
+}
+
+/// A device with these USB ids and no serial number, for the tests.
+#[cfg(test)]
+pub(crate) fn usb_device(vendor_id: u16, product_id: u16) -> UsbDevice {
+    UsbDevice {
+        bus_number: 1,
+        address: 2,
+        vendor_id,
+        product_id,
+        version: UsbVersion(1, 0, 0),
+        serial_number: None,
+    }
 }
 
-/// This trait helps by allowing for definitions of multiple different boards.
-pub trait BoardInfo {
+/// This trait helps by allowing for definitions of multiple different boards. Boards are shared
+/// between threads by the [`BoardRegistry`], so they have to be `Send` and `Sync`.
+pub trait BoardInfo: Send + Sync {
     /// Check if the board is connected to the specified UsbDevice
     fn is_device_board(&self, device: &UsbDevice) -> bool;
 
+    /// Optional, the vendor and product ids of the devices [`BoardInfo::is_device_board`]
+    /// recognizes, for listing the boards. Empty when they can't be listed, the default.
+    fn usb_matches(&self) -> Vec<(u16, u16)> {
+        Vec::new()
+    }
+
     /// Returns the proper family id to use for the uf2 device
     fn family_id(&self) -> u32;
 
-    /// Optional, just sent to a sensible default of 256, as long as it is less than 512 - 32 it should be okay, but boards very, and so does the bootloader firmware
+    /// Optional, just sent to a sensible default of 256. It must be a power of two between
+    /// [`MIN_PAGE_SIZE`] and [`MAX_PAGE_SIZE`], but boards vary, and so does the bootloader firmware.
+    /// Larger pages, up to [`MAX_SPLIT_PAGE_SIZE`], need a smaller
+    /// [`Uf2Options::payload_size`](crate::Uf2Options::payload_size) to be converted
     fn page_size(&self) -> u32 {
         256
     }
 
-    /// Optional, with a default erase size of 4096
+    /// Optional, with a default erase size of 4096, must be a multiple of the page size
     fn flash_sector_erase_size(&self) -> u64 {
         4096
     }
 
-    /// Get the board's name
-    fn board_name(&self) -> String;
+    /// Get the board's name, what `--board` takes. Names are unique among the registered
+    /// boards, ignoring case.
+    fn board_name(&self) -> Cow<'static, str>;
+
+    /// Optional, a human friendly name like `Raspberry Pi RP2040`, for listing the boards
+    fn board_description(&self) -> Option<Cow<'static, str>> {
+        None
+    }
+
+    /// Memory regions a uf2 for this board may write to, conversion fails for pages outside of
+    /// them. An empty list (the default) skips the check.
+    fn valid_address_ranges(&self) -> Vec<AddressRange> {
+        Vec::new()
+    }
+
+    /// The RAM regions among [`BoardInfo::valid_address_ranges`]. Programs that only load into
+    /// these are converted into a RAM-only uf2, with no flash sectors filled.
+    fn ram_address_ranges(&self) -> Vec<AddressRange> {
+        Vec::new()
+    }
+
+    /// Blocks written before the program blocks of a flash image, e.g. the RP2350's absolute
+    /// block. They keep their own block numbering, the program blocks are numbered as if the
+    /// preamble wasn't there.
+    fn preamble_blocks(&self) -> Vec<Uf2Block> {
+        Vec::new()
+    }
+
+    /// Whether the bootloader volume with this `INFO_UF2.TXT` belongs to the board. Used to
+    /// recognize devices with USB ids the board doesn't know, so it is optional, the default
+    /// matches nothing.
+    fn matches_info_uf2(&self, _info: &InfoUf2) -> bool {
+        false
+    }
+
+    /// Optional, whether the bootloader only boots the new firmware once the host ejects its
+    /// volume, as TinyUF2 does. The default is false, for bootloaders that reboot as soon as the
+    /// last block is written.
+    fn needs_eject(&self) -> bool {
+        false
+    }
+
+    /// Optional, the name the uf2 is written as in the root of the bootloader volume, a short or
+    /// long FAT file name. Most bootloaders take any name, the default is [`DEFAULT_UF2_FILENAME`].
+    fn uf2_filename(&self) -> &str {
+        DEFAULT_UF2_FILENAME
+    }
+
+    /// Optional, how many bytes of the uf2 file are written to the volume at once, a multiple of
+    /// the uf2 block size. Bootloaders with little RAM to buffer blocks need smaller writes than
+    /// the default [`DEFAULT_WRITE_CHUNK_SIZE`].
+    fn write_chunk_size(&self) -> usize {
+        DEFAULT_WRITE_CHUNK_SIZE
+    }
+
+    /// Optional, how long to wait between two chunks, for bootloaders that need time to write
+    /// the blocks they received to flash. The default doesn't wait.
+    fn inter_chunk_delay(&self) -> Option<Duration> {
+        None
+    }
 }
 
+/// Implements [`BoardInfo`] for pointers to a board by forwarding every method, the provided ones
+/// included, so the conversion functions take a board by value as well as by reference.
+macro_rules! forward_board_info {
+    ($($pointer:ty),*) => {$(
+        impl<T: BoardInfo + ?Sized> BoardInfo for $pointer {
+            fn is_device_board(&self, device: &UsbDevice) -> bool {
+                (**self).is_device_board(device)
+            }
+
+            fn usb_matches(&self) -> Vec<(u16, u16)> {
+                (**self).usb_matches()
+            }
+
+            fn family_id(&self) -> u32 {
+                (**self).family_id()
+            }
+
+            fn page_size(&self) -> u32 {
+                (**self).page_size()
+            }
+
+            fn flash_sector_erase_size(&self) -> u64 {
+                (**self).flash_sector_erase_size()
+            }
+
+            fn board_name(&self) -> Cow<'static, str> {
+                (**self).board_name()
+            }
+
+            fn board_description(&self) -> Option<Cow<'static, str>> {
+                (**self).board_description()
+            }
+
+            fn valid_address_ranges(&self) -> Vec<AddressRange> {
+                (**self).valid_address_ranges()
+            }
+
+            fn ram_address_ranges(&self) -> Vec<AddressRange> {
+                (**self).ram_address_ranges()
+            }
+
+            fn preamble_blocks(&self) -> Vec<Uf2Block> {
+                (**self).preamble_blocks()
+            }
+
+            fn matches_info_uf2(&self, info: &InfoUf2) -> bool {
+                (**self).matches_info_uf2(info)
+            }
+
+            fn needs_eject(&self) -> bool {
+                (**self).needs_eject()
+            }
+
+            fn uf2_filename(&self) -> &str {
+                (**self).uf2_filename()
+            }
+
+            fn write_chunk_size(&self) -> usize {
+                (**self).write_chunk_size()
+            }
+
+            fn inter_chunk_delay(&self) -> Option<Duration> {
+                (**self).inter_chunk_delay()
+            }
+        }
+    )*};
+}
+
+forward_board_info!(&T, Box<T>, Arc<T>);
+
 /// A builder for the CustomBoard struct, which can be passed into the elf2uf2 function
 #[derive(Debug, Clone)]
 pub struct CustomBoardBuilder {
     vendor_id: Option<u16>,
-    product_id: Option<u16>,
+    product_ids: Vec<u16>,
+    serial_number: Option<String>,
+    version_range: Option<(UsbVersion, UsbVersion)>,
     family_id: Option<u32>,
     board_name: Option<String>,
     page_size: Option<u32>,
     flash_sector_erase_size: Option<u64>,
+    address_ranges: Option<Vec<AddressRange>>,
+    ram_address_ranges: Option<Vec<AddressRange>>,
+    preamble_blocks: Vec<Uf2Block>,
+    needs_eject: bool,
+    uf2_filename: Option<String>,
+    write_chunk_size: Option<usize>,
+    inter_chunk_delay: Option<Duration>,
 }
 
 impl CustomBoardBuilder {
     pub fn new() -> Self {
         Self {
             vendor_id: None,
-            product_id: None,
+            product_ids: Vec::new(),
+            serial_number: None,
+            version_range: None,
             family_id: None,
             board_name: None,
             page_size: None,
             flash_sector_erase_size: None,
+            address_ranges: None,
+            ram_address_ranges: None,
+            preamble_blocks: Vec::new(),
+            needs_eject: false,
+            uf2_filename: None,
+            write_chunk_size: None,
+            inter_chunk_delay: None,
+        }
+    }
+
+    /// A builder with every parameter of `board`, to override some of them. The USB ids are copied
+    /// when they all have the same vendor id, otherwise the board accepts any device.
+    pub fn from_board(board: &dyn BoardInfo) -> Self {
+        let mut builder = Self::new()
+            .board_name(board.board_name())
+            .family_id(board.family_id())
+            .page_size(board.page_size())
+            .flash_sector_erase_size(board.flash_sector_erase_size())
+            .address_ranges(board.valid_address_ranges())
+            .ram_address_ranges(board.ram_address_ranges())
+            .preamble_blocks(board.preamble_blocks())
+            .needs_eject(board.needs_eject())
+            .uf2_filename(board.uf2_filename())
+            .write_chunk_size(board.write_chunk_size());
+        builder.inter_chunk_delay = board.inter_chunk_delay();
+
+        let usb_matches = board.usb_matches();
+        if let Some(&(vendor_id, _)) = usb_matches.first()
+            && usb_matches.iter().all(|&(vendor, _)| vendor == vendor_id)
+        {
+            builder = builder
+                .vendor_id(vendor_id)
+                .product_ids(usb_matches.iter().map(|&(_, product)| product).collect());
         }
+        builder
+    }
+
+    /// Replace the family id, flash sector erase size and page size with the ones given, the
+    /// `None`s keep their current value.
+    pub fn apply_overrides(
+        mut self,
+        family_id: Option<u32>,
+        flash_sector_erase_size: Option<u64>,
+        page_size: Option<u32>,
+    ) -> Self {
+        self.family_id = family_id.or(self.family_id);
+        self.flash_sector_erase_size = flash_sector_erase_size.or(self.flash_sector_erase_size);
+        self.page_size = page_size.or(self.page_size);
+        self
     }
 
     pub fn vendor_id(mut self, vendor_id: u16) -> Self {
@@ -109,8 +407,36 @@ impl CustomBoardBuilder {
         self
     }
 
+    /// Replace the USB ids, e.g. the ones copied by [`CustomBoardBuilder::from_board`]. `None`
+    /// accepts any vendor or product id.
+    pub fn usb_ids(mut self, vendor_id: Option<u16>, product_id: Option<u16>) -> Self {
+        self.vendor_id = vendor_id;
+        self.product_ids = product_id.into_iter().collect();
+        self
+    }
+
     pub fn product_id(mut self, product_id: u16) -> Self {
-        self.product_id = Some(product_id);
+        self.product_ids = vec![product_id];
+        self
+    }
+
+    /// Accept devices with any of these product ids, for boards whose bootloader changed its id
+    /// over time. An empty list accepts any product id.
+    pub fn product_ids(mut self, product_ids: Vec<u16>) -> Self {
+        self.product_ids = product_ids;
+        self
+    }
+
+    /// Only accept the device with this serial number, to pin the board to one of several
+    /// identical devices. Devices whose serial number can't be read don't match.
+    pub fn serial_number<S: Into<String>>(mut self, serial_number: S) -> Self {
+        self.serial_number = Some(serial_number.into());
+        self
+    }
+
+    /// Only accept devices whose firmware version is between `min` and `max`, both included.
+    pub fn version_range(mut self, min: UsbVersion, max: UsbVersion) -> Self {
+        self.version_range = Some((min, max));
         self
     }
 
@@ -134,16 +460,97 @@ impl CustomBoardBuilder {
         self
     }
 
+    /// Restrict the addresses the uf2 may write to, see [`BoardInfo::valid_address_ranges`]. Without
+    /// this any address is accepted.
+    pub fn address_ranges(mut self, address_ranges: Vec<AddressRange>) -> Self {
+        self.address_ranges = Some(address_ranges);
+        self
+    }
+
+    /// Mark which addresses are RAM, see [`BoardInfo::ram_address_ranges`].
+    pub fn ram_address_ranges(mut self, ram_address_ranges: Vec<AddressRange>) -> Self {
+        self.ram_address_ranges = Some(ram_address_ranges);
+        self
+    }
+
+    /// Blocks to write before the program, see [`BoardInfo::preamble_blocks`]. Pass an empty list
+    /// (the default) to leave them out.
+    pub fn preamble_blocks(mut self, preamble_blocks: Vec<Uf2Block>) -> Self {
+        self.preamble_blocks = preamble_blocks;
+        self
+    }
+
+    /// Eject the volume after writing, see [`BoardInfo::needs_eject`].
+    pub fn needs_eject(mut self, needs_eject: bool) -> Self {
+        self.needs_eject = needs_eject;
+        self
+    }
+
+    /// Write the uf2 as `uf2_filename`, see [`BoardInfo::uf2_filename`]. It has to be a valid FAT
+    /// file name, see [`is_valid_fat_name`].
+    pub fn uf2_filename<S: Into<String>>(mut self, uf2_filename: S) -> Self {
+        self.uf2_filename = Some(uf2_filename.into());
+        self
+    }
+
+    /// Write the uf2 file in chunks of this many bytes, see [`BoardInfo::write_chunk_size`].
+    pub fn write_chunk_size(mut self, write_chunk_size: usize) -> Self {
+        self.write_chunk_size = Some(write_chunk_size);
+        self
+    }
+
+    /// Wait this long between two chunks, see [`BoardInfo::inter_chunk_delay`].
+    pub fn inter_chunk_delay(mut self, delay: Duration) -> Self {
+        self.inter_chunk_delay = Some(delay);
+        self
+    }
+
     pub fn build(self) -> Result<CustomBoard, CustomBoardBuildError> {
+        let family_id = self
+            .family_id
+            .ok_or(CustomBoardBuildError::FamilyIdRequired)?;
+
+        let page_size = self.page_size.unwrap_or(256);
+        if !is_valid_page_size(page_size) {
+            return Err(CustomBoardBuildError::InvalidPageSize(page_size));
+        }
+
+        let erase_size = self.flash_sector_erase_size.unwrap_or(4096);
+        if !is_valid_erase_size(erase_size, page_size) {
+            return Err(CustomBoardBuildError::InvalidEraseSize {
+                erase_size,
+                page_size,
+            });
+        }
+
+        if let Some(name) = &self.uf2_filename
+            && !is_valid_fat_name(name)
+        {
+            return Err(CustomBoardBuildError::InvalidUf2Filename(name.clone()));
+        }
+
+        if let Some(chunk_size) = self.write_chunk_size
+            && !is_valid_write_chunk_size(chunk_size)
+        {
+            return Err(CustomBoardBuildError::InvalidWriteChunkSize(chunk_size));
+        }
+
         Ok(CustomBoard {
             vendor_id: self.vendor_id,
-            product_id: self.product_id,
-            family_id: self
-                .family_id
-                .ok_or(CustomBoardBuildError::FamilyIdRequired)?,
+            product_ids: self.product_ids,
+            serial_number: self.serial_number,
+            version_range: self.version_range,
+            family_id,
             board_name: self.board_name,
             page_size: self.page_size,
             flash_sector_erase_size: self.flash_sector_erase_size,
+            address_ranges: self.address_ranges,
+            ram_address_ranges: self.ram_address_ranges,
+            preamble_blocks: self.preamble_blocks,
+            needs_eject: self.needs_eject,
+            uf2_filename: self.uf2_filename,
+            write_chunk_size: self.write_chunk_size,
+            inter_chunk_delay: self.inter_chunk_delay,
         })
     }
 }
@@ -152,17 +559,38 @@ impl CustomBoardBuilder {
 pub enum CustomBoardBuildError {
     #[error("family_id is required")]
     FamilyIdRequired,
+    #[error(
+        "page_size {0} must be a power of two between {MIN_PAGE_SIZE} and {MAX_SPLIT_PAGE_SIZE}"
+    )]
+    InvalidPageSize(u32),
+    #[error(
+        "flash_sector_erase_size {erase_size} must be a non-zero multiple of the page size {page_size}"
+    )]
+    InvalidEraseSize { erase_size: u64, page_size: u32 },
+    #[error("uf2_filename '{0}' isn't a valid FAT file name")]
+    InvalidUf2Filename(String),
+    #[error("write_chunk_size {0} must be a non-zero multiple of {UF2_BLOCK_SIZE}")]
+    InvalidWriteChunkSize(usize),
 }
 
 /// A struct, which can be passed into the elf2uf2 function, this can be constructed via the CustomBoardBuilder struct.
 #[derive(Debug, Clone)]
 pub struct CustomBoard {
     vendor_id: Option<u16>,
-    product_id: Option<u16>,
+    product_ids: Vec<u16>,
+    serial_number: Option<String>,
+    version_range: Option<(UsbVersion, UsbVersion)>,
     family_id: u32,
     board_name: Option<String>,
     page_size: Option<u32>,
     flash_sector_erase_size: Option<u64>,
+    address_ranges: Option<Vec<AddressRange>>,
+    ram_address_ranges: Option<Vec<AddressRange>>,
+    preamble_blocks: Vec<Uf2Block>,
+    needs_eject: bool,
+    uf2_filename: Option<String>,
+    write_chunk_size: Option<usize>,
+    inter_chunk_delay: Option<Duration>,
 }
 
 impl BoardInfo for CustomBoard {
@@ -173,21 +601,41 @@ impl BoardInfo for CustomBoard {
             return false;
         }
 
-        if let Some(vendor_id) = self.product_id
-            && device.product_id != vendor_id
+        if let Some(serial_number) = &self.serial_number
+            && device.serial_number.as_ref() != Some(serial_number)
         {
             return false;
         }
 
-        true
+        if let Some((min, max)) = &self.version_range
+            && !(min..=max).contains(&&device.version)
+        {
+            return false;
+        }
+
+        self.product_ids.is_empty() || self.product_ids.contains(&device.product_id)
+    }
+
+    fn usb_matches(&self) -> Vec<(u16, u16)> {
+        match self.vendor_id {
+            Some(vendor_id) => self
+                .product_ids
+                .iter()
+                .map(|&product_id| (vendor_id, product_id))
+                .collect(),
+            None => Vec::new(),
+        }
     }
 
     fn family_id(&self) -> u32 {
         self.family_id
     }
 
-    fn board_name(&self) -> String {
-        self.board_name.clone().unwrap_or("custom".to_string())
+    fn board_name(&self) -> Cow<'static, str> {
+        match &self.board_name {
+            Some(board_name) => board_name.clone().into(),
+            None => "custom".into(),
+        }
     }
 
     fn page_size(&self) -> u32 {
@@ -197,4 +645,208 @@ impl BoardInfo for CustomBoard {
     fn flash_sector_erase_size(&self) -> u64 {
         self.flash_sector_erase_size.unwrap_or(4096)
     }
+
+    fn valid_address_ranges(&self) -> Vec<AddressRange> {
+        self.address_ranges.clone().unwrap_or_default()
+    }
+
+    fn ram_address_ranges(&self) -> Vec<AddressRange> {
+        self.ram_address_ranges.clone().unwrap_or_default()
+    }
+
+    fn preamble_blocks(&self) -> Vec<Uf2Block> {
+        self.preamble_blocks.clone()
+    }
+
+    fn needs_eject(&self) -> bool {
+        self.needs_eject
+    }
+
+    fn uf2_filename(&self) -> &str {
+        self.uf2_filename.as_deref().unwrap_or(DEFAULT_UF2_FILENAME)
+    }
+
+    fn write_chunk_size(&self) -> usize {
+        self.write_chunk_size.unwrap_or(DEFAULT_WRITE_CHUNK_SIZE)
+    }
+
+    fn inter_chunk_delay(&self) -> Option<Duration> {
+        self.inter_chunk_delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(serial_number: Option<&str>, version: UsbVersion) -> UsbDevice {
+        UsbDevice {
+            version,
+            serial_number: serial_number.map(str::to_string),
+            ..usb_device(0x2e8a, 0x0003)
+        }
+    }
+
+    fn pico() -> CustomBoardBuilder {
+        CustomBoardBuilder::new()
+            .vendor_id(0x2e8a)
+            .product_id(0x0003)
+            .family_id(0xe48bff56)
+    }
+
+    #[test]
+    fn board_is_pinned_by_serial_number() {
+        let board = pico().serial_number("E6614103E7452D2F").build().unwrap();
+        let version = || UsbVersion(1, 0, 0);
+
+        assert!(board.is_device_board(&device(Some("E6614103E7452D2F"), version())));
+        assert!(!board.is_device_board(&device(Some("E66138935F2A7E2B"), version())));
+        assert!(!board.is_device_board(&device(None, version())));
+
+        // Without a serial number every device matches
+        let board = pico().build().unwrap();
+        assert!(board.is_device_board(&device(Some("E66138935F2A7E2B"), version())));
+        assert!(board.is_device_board(&device(None, version())));
+    }
+
+    #[test]
+    fn from_board_reproduces_the_board() {
+        for base in BoardIter::new() {
+            let board = CustomBoardBuilder::from_board(base.as_ref())
+                .apply_overrides(None, None, None)
+                .build()
+                .unwrap();
+
+            assert_eq!(board.board_name(), base.board_name());
+            assert_eq!(board.family_id(), base.family_id());
+            assert_eq!(board.page_size(), base.page_size());
+            assert_eq!(
+                board.flash_sector_erase_size(),
+                base.flash_sector_erase_size()
+            );
+            assert_eq!(board.valid_address_ranges(), base.valid_address_ranges());
+            assert_eq!(board.ram_address_ranges(), base.ram_address_ranges());
+            assert_eq!(board.preamble_blocks(), base.preamble_blocks());
+            assert_eq!(board.needs_eject(), base.needs_eject());
+            assert_eq!(board.uf2_filename(), base.uf2_filename());
+            assert_eq!(board.write_chunk_size(), base.write_chunk_size());
+            assert_eq!(board.inter_chunk_delay(), base.inter_chunk_delay());
+        }
+
+        let board = CustomBoardBuilder::from_board(&RP2040).build().unwrap();
+        assert_eq!(board.usb_matches(), RP2040.usb_matches());
+        assert!(!board.is_device_board(&UsbDevice {
+            vendor_id: 0x239a,
+            ..device(None, UsbVersion(1, 0, 0))
+        }));
+    }
+
+    #[test]
+    fn overrides_beat_the_base_board() {
+        let board = CustomBoardBuilder::from_board(&RP2350)
+            .apply_overrides(Some(0xe48bff5a), Some(8192), Some(512))
+            .usb_ids(None, None)
+            .build()
+            .unwrap();
+
+        assert_eq!(board.board_name(), "rp2350");
+        assert_eq!(board.family_id(), 0xe48bff5a);
+        assert_eq!(board.flash_sector_erase_size(), 8192);
+        assert_eq!(board.page_size(), 512);
+        assert_eq!(board.preamble_blocks(), RP2350.preamble_blocks());
+        assert!(board.usb_matches().is_empty());
+        assert!(board.is_device_board(&UsbDevice {
+            vendor_id: 0x239a,
+            ..device(None, UsbVersion(1, 0, 0))
+        }));
+    }
+
+    #[test]
+    fn only_tinyuf2_boards_need_an_eject() {
+        let ejected: Vec<_> = BoardIter::new()
+            .filter(|board| board.needs_eject())
+            .map(|board| board.board_name())
+            .collect();
+        assert_eq!(ejected, ["esp32s2", "esp32s3"]);
+
+        let board = pico().build().unwrap();
+        assert!(!board.needs_eject());
+        assert!(pico().needs_eject(true).build().unwrap().needs_eject());
+        assert!(
+            CustomBoardBuilder::from_board(&Esp32S3)
+                .build()
+                .unwrap()
+                .needs_eject()
+        );
+    }
+
+    #[test]
+    fn uf2_filename_is_checked() {
+        assert_eq!(pico().build().unwrap().uf2_filename(), "out.uf2");
+        let board = pico().uf2_filename("CURRENT.UF2").build().unwrap();
+        assert_eq!(board.uf2_filename(), "CURRENT.UF2");
+        assert!(pico().uf2_filename("firmware latest.uf2").build().is_ok());
+
+        for name in ["", "fw/out.uf2", "out?.uf2", "out.uf2.", "a\"b.uf2"] {
+            assert!(
+                matches!(
+                    pico().uf2_filename(name).build(),
+                    Err(CustomBoardBuildError::InvalidUf2Filename(_))
+                ),
+                "{name:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn write_chunks_hold_whole_blocks() {
+        let board = pico().build().unwrap();
+        assert_eq!(board.write_chunk_size(), DEFAULT_WRITE_CHUNK_SIZE);
+        assert_eq!(board.inter_chunk_delay(), None);
+
+        let board = pico()
+            .write_chunk_size(2048)
+            .inter_chunk_delay(Duration::from_millis(5))
+            .build()
+            .unwrap();
+        assert_eq!(board.write_chunk_size(), 2048);
+        assert_eq!(board.inter_chunk_delay(), Some(Duration::from_millis(5)));
+
+        for chunk_size in [0, 1000] {
+            assert!(matches!(
+                pico().write_chunk_size(chunk_size).build(),
+                Err(CustomBoardBuildError::InvalidWriteChunkSize(_))
+            ));
+        }
+        assert_eq!(FeatherNrf52840Express.write_chunk_size(), 4096);
+        assert!(CircuitPlaygroundBluefruit.inter_chunk_delay().is_some());
+    }
+
+    #[test]
+    fn short_names_are_told_apart() {
+        assert!(is_fat_short_name("OUT.UF2"));
+        assert!(is_fat_short_name("current.uf2"));
+        assert!(is_fat_short_name("FIRMWARE"));
+        assert!(!is_fat_short_name("FIRMWARE1.UF2"));
+        assert!(!is_fat_short_name("OUT.UF2X"));
+        assert!(!is_fat_short_name("A.B.UF2"));
+        assert!(!is_fat_short_name("MY FW.UF2"));
+        assert!(!is_fat_short_name(".UF2"));
+        assert!(!is_fat_short_name("OUT."));
+        assert!(is_valid_fat_name("A.B.UF2"));
+    }
+
+    #[test]
+    fn version_range_is_inclusive() {
+        let board = pico()
+            .version_range(UsbVersion(1, 0, 0), UsbVersion(2, 1, 0))
+            .build()
+            .unwrap();
+
+        assert!(board.is_device_board(&device(None, UsbVersion(1, 0, 0))));
+        assert!(board.is_device_board(&device(None, UsbVersion(1, 9, 9))));
+        assert!(board.is_device_board(&device(None, UsbVersion(2, 1, 0))));
+        assert!(!board.is_device_board(&device(None, UsbVersion(0, 9, 0))));
+        assert!(!board.is_device_board(&device(None, UsbVersion(2, 1, 1))));
+    }
 }
@@ -0,0 +1,196 @@
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+
+use crate::boards::{
+    BoardInfo, BoardIter, CircuitPlaygroundBluefruit, ClueNrf52840, Esp32S2, Esp32S3,
+    FeatherNrf52840Express, ItsyBitsyNrf52840, RP2040, RP2350, RP2350ArmNs, RP2350RiscV,
+};
+
+static BOARDS: OnceLock<RwLock<Vec<Arc<dyn BoardInfo>>>> = OnceLock::new();
+
+/// The boards [`BoardIter`] goes over, the built-in boards and the ones registered at runtime.
+///
+/// An application embedding elf2flash-core registers its own boards here, so they are looked up
+/// by name and recognized on the USB bus like the built-in ones.
+///
+/// ```
+/// use std::borrow::Cow;
+///
+/// use elf2flash_core::boards::{BoardInfo, BoardIter, BoardRegistry, UsbDevice};
+///
+/// struct Feather;
+///
+/// impl BoardInfo for Feather {
+///     fn is_device_board(&self, device: &UsbDevice) -> bool {
+///         device.vendor_id == 0x239a && device.product_id == 0x00cd
+///     }
+///
+///     fn family_id(&self) -> u32 {
+///         0x55114460
+///     }
+///
+///     fn board_name(&self) -> Cow<'static, str> {
+///         "feather_m4".into()
+///     }
+/// }
+///
+/// BoardRegistry::register(Feather);
+/// assert!(BoardIter::find_by_name("feather_m4").is_some());
+/// ```
+pub struct BoardRegistry;
+
+impl BoardRegistry {
+    fn boards() -> &'static RwLock<Vec<Arc<dyn BoardInfo>>> {
+        BOARDS.get_or_init(|| {
+            RwLock::new(vec![
+                Arc::new(RP2040),
+                Arc::new(RP2350),
+                // Recognize the same devices as the RP2350, which comes first, so they are only
+                // used when picked by name
+                Arc::new(RP2350RiscV),
+                Arc::new(RP2350ArmNs),
+                Arc::new(CircuitPlaygroundBluefruit),
+                Arc::new(FeatherNrf52840Express),
+                Arc::new(ItsyBitsyNrf52840),
+                Arc::new(ClueNrf52840),
+                Arc::new(Esp32S2),
+                Arc::new(Esp32S3),
+            ])
+        })
+    }
+
+    /// Add `board` to the registry. Registered boards come before the built-in ones, the most
+    /// recent first, so they win when several boards recognize a device or share a name.
+    pub fn register(board: impl BoardInfo + 'static) {
+        Self::boards()
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(0, Arc::new(board));
+    }
+
+    /// Remove the boards called `name`, ignoring case, e.g. one that was only registered for a
+    /// while. Returns whether there was one.
+    pub fn unregister(name: &str) -> bool {
+        let mut boards = Self::boards()
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let before = boards.len();
+        boards.retain(|board| !board.board_name().eq_ignore_ascii_case(name));
+        boards.len() != before
+    }
+
+    /// Every board in the registry, as it is when called.
+    pub fn iter() -> BoardIter {
+        BoardIter::new()
+    }
+
+    /// The board called `name`, ignoring case.
+    pub fn find_by_name(name: &str) -> Option<Arc<dyn BoardInfo>> {
+        Self::snapshot()
+            .into_iter()
+            .find(|board| board.board_name().eq_ignore_ascii_case(name))
+    }
+
+    /// A copy of the boards, so the lock isn't held while they are used.
+    pub(crate) fn snapshot() -> Vec<Arc<dyn BoardInfo>> {
+        Self::boards()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boards::{UsbDevice, usb_device};
+    use std::{borrow::Cow, collections::HashSet};
+
+    struct FakeBoard;
+
+    impl BoardInfo for FakeBoard {
+        fn is_device_board(&self, device: &UsbDevice) -> bool {
+            device.vendor_id == 0xf0f0 && device.product_id == 0x0001
+        }
+
+        fn family_id(&self) -> u32 {
+            0x0badf00d
+        }
+
+        fn board_name(&self) -> Cow<'static, str> {
+            "fake_board".into()
+        }
+    }
+
+    #[test]
+    fn registered_boards_are_recognized() {
+        let device = usb_device(0xf0f0, 0x0001);
+        assert!(BoardRegistry::find_by_name("fake_board").is_none());
+        assert!(BoardIter::new().all(|board| !board.is_device_board(&device)));
+
+        BoardRegistry::register(FakeBoard);
+        // The registry is shared by every test, the board is removed again before asserting
+        let found = BoardIter::new().find(|board| board.is_device_board(&device));
+        let by_name = BoardRegistry::find_by_name("FAKE_BOARD").map(|board| board.family_id());
+        let by_iter_name = BoardIter::find_by_name("fake_board").map(|board| board.family_id());
+        assert!(BoardRegistry::unregister("fake_board"));
+
+        assert_eq!(found.unwrap().board_name(), "fake_board");
+        assert_eq!(by_name, Some(0x0badf00d));
+        assert_eq!(by_iter_name, Some(0x0badf00d));
+        assert!(BoardRegistry::find_by_name("fake_board").is_none());
+        assert!(!BoardRegistry::unregister("fake_board"));
+
+        // The built-in boards are still there
+        assert!(BoardRegistry::iter().any(|board| board.board_name() == "rp2040"));
+    }
+
+    #[test]
+    fn board_names_are_unique() {
+        let mut names = HashSet::new();
+        for board in BoardRegistry::snapshot() {
+            let name = board.board_name();
+            assert!(!name.is_empty());
+            assert!(
+                names.insert(name.to_ascii_lowercase()),
+                "board '{name}' is defined twice"
+            );
+        }
+
+        let built_in = [
+            "rp2040",
+            "rp2350",
+            "rp2350-riscv",
+            "rp2350-arm-ns",
+            "circuit_playground_bluefruit",
+            "feather_nrf52840_express",
+            "itsybitsy_nrf52840",
+            "clue_nrf52840",
+            "esp32s2",
+            "esp32s3",
+        ];
+        for name in built_in {
+            let board = BoardRegistry::find_by_name(name).unwrap();
+            assert!(board.board_description().is_some_and(|d| !d.is_empty()));
+        }
+    }
+
+    #[test]
+    fn listed_usb_ids_are_recognized() {
+        for board in BoardRegistry::snapshot() {
+            for (vendor_id, product_id) in board.usb_matches() {
+                let device = usb_device(vendor_id, product_id);
+                assert!(
+                    board.is_device_board(&device),
+                    "{} doesn't recognize {vendor_id:04x}:{product_id:04x}",
+                    board.board_name()
+                );
+            }
+        }
+        assert!(
+            !BoardRegistry::find_by_name("rp2040")
+                .unwrap()
+                .usb_matches()
+                .is_empty()
+        );
+    }
+}
@@ -1,4 +1,15 @@
-use crate::boards::{BoardInfo, UsbDevice};
+use std::borrow::Cow;
+
+use crate::{
+    address_range::{AddressRange, AddressRangeType},
+    boards::{BoardInfo, UsbDevice},
+    info_uf2::InfoUf2,
+};
+
+/// 16MiB of XIP flash
+const FLASH: AddressRange = AddressRange::new(0x10000000, 0x11000000, AddressRangeType::Contents);
+/// SRAM0-5, 264KiB
+const RAM: AddressRange = AddressRange::new(0x20000000, 0x20042000, AddressRangeType::Contents);
 
 #[derive(Debug, Default, Clone)]
 pub struct RP2040;
@@ -14,11 +25,31 @@ impl BoardInfo for RP2040 {
         }
     }
 
+    fn usb_matches(&self) -> Vec<(u16, u16)> {
+        vec![(0x2e8a, 0x0003)]
+    }
+
     fn family_id(&self) -> u32 {
         0xe48bff56
     }
 
-    fn board_name(&self) -> String {
-        "rp2040".to_string()
+    fn board_name(&self) -> Cow<'static, str> {
+        "rp2040".into()
+    }
+
+    fn board_description(&self) -> Option<Cow<'static, str>> {
+        Some("Raspberry Pi RP2040".into())
+    }
+
+    fn valid_address_ranges(&self) -> Vec<AddressRange> {
+        vec![FLASH, RAM]
+    }
+
+    fn ram_address_ranges(&self) -> Vec<AddressRange> {
+        vec![RAM]
+    }
+
+    fn matches_info_uf2(&self, info: &InfoUf2) -> bool {
+        info.board_id.as_deref() == Some("RPI-RP2")
     }
 }
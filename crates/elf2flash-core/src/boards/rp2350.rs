@@ -1,17 +1,65 @@
-use crate::boards::{BoardInfo, UsbDevice};
+//! The RP2350, and its variants for the family ids of its RISC-V and non-secure Arm images.
+
+use elf::{
+    ElfBytes,
+    abi::{EM_ARM, EM_RISCV, PT_LOAD},
+    endian::AnyEndian,
+};
+use log::info;
+
+use std::borrow::Cow;
+
+use crate::{
+    address_range::{AddressRange, AddressRangeType},
+    boards::{BoardInfo, UsbDevice, family::describe_family},
+    info_uf2::InfoUf2,
+    uf2::{BlockHeaderTemplate, FileSizeField, UF2_ABSOLUTE_FAMILY_ID, Uf2Block},
+};
+
+/// 16MiB of XIP flash
+const FLASH: AddressRange = AddressRange::new(0x10000000, 0x11000000, AddressRangeType::Contents);
+/// SRAM0-9, 520KiB
+const RAM: AddressRange = AddressRange::new(0x20000000, 0x20082000, AddressRangeType::Contents);
+
+/// Where the absolute block is written, the last page of the 16MiB flash window
+const ABSOLUTE_BLOCK_ADDR: u32 = 0x10ffff00;
+
+/// The block picotool puts at the start of RP2350 uf2 files (the RP2350-E10 workaround).
+///
+/// Without it, the bootrom of A2 chips with a partition table may write the image into the wrong
+/// partition. It claims to be the first of two blocks, so the bootrom never considers the absolute
+/// family complete.
+fn absolute_block() -> Uf2Block {
+    BlockHeaderTemplate::new(FileSizeField::FamilyId(UF2_ABSOLUTE_FAMILY_ID))
+        .num_blocks(2)
+        .block(ABSOLUTE_BLOCK_ADDR, 0, [0xef; 476])
+}
+
+/// The bootrom only looks for the block holding the IMAGE_DEF in the first 4KiB of an image
+const BLOCK_SEARCH_SIZE: usize = 4096;
+const PICOBIN_BLOCK_MARKER_START: u32 = 0xffffded3;
+const PICOBIN_BLOCK_MARKER_END: u32 = 0xab123579;
+const PICOBIN_BLOCK_ITEM_IMAGE_TYPE: u8 = 0x42;
+const PICOBIN_BLOCK_ITEM_LAST: u8 = 0xff;
+
+/// The vendor and product id of the RP2350 bootrom's uf2 drive
+const BOOTROM_USB_ID: (u16, u16) = (0x2e8a, 0x000f);
+
+/// Whether `device` is the RP2350 bootrom's uf2 drive
+fn is_rp2350_bootrom(device: &UsbDevice) -> bool {
+    (device.vendor_id, device.product_id) == BOOTROM_USB_ID
+}
 
 #[derive(Debug, Default, Clone)]
 pub struct RP2350;
 
 impl BoardInfo for RP2350 {
     fn is_device_board(&self, device: &UsbDevice) -> bool {
-        if device.vendor_id != 0x2e8a {
-            return false;
-        }
-        match device.product_id {
-            0x000f => true,
-            _ => false,
-        }
+        is_rp2350_bootrom(device)
+    }
+
+    fn usb_matches(&self) -> Vec<(u16, u16)> {
+        vec![BOOTROM_USB_ID]
     }
 
     fn family_id(&self) -> u32 {
@@ -19,7 +67,257 @@ impl BoardInfo for RP2350 {
         0xe48bff59
     }
 
-    fn board_name(&self) -> String {
-        "rp2350".to_string()
+    fn board_name(&self) -> Cow<'static, str> {
+        "rp2350".into()
+    }
+
+    fn board_description(&self) -> Option<Cow<'static, str>> {
+        Some("Raspberry Pi RP2350, Secure Arm programs".into())
+    }
+
+    fn valid_address_ranges(&self) -> Vec<AddressRange> {
+        vec![FLASH, RAM]
+    }
+
+    fn ram_address_ranges(&self) -> Vec<AddressRange> {
+        vec![RAM]
+    }
+
+    fn preamble_blocks(&self) -> Vec<Uf2Block> {
+        vec![absolute_block()]
+    }
+
+    fn matches_info_uf2(&self, info: &InfoUf2) -> bool {
+        info.board_id.as_deref() == Some("RP2350")
+    }
+}
+
+/// The RP2350 running a program built for its Hazard3 RISC-V cores. It is the same device as
+/// [`RP2350`], so it is only used when picked by name.
+#[derive(Debug, Default, Clone)]
+pub struct RP2350RiscV;
+
+impl BoardInfo for RP2350RiscV {
+    fn is_device_board(&self, device: &UsbDevice) -> bool {
+        is_rp2350_bootrom(device)
+    }
+
+    fn usb_matches(&self) -> Vec<(u16, u16)> {
+        vec![BOOTROM_USB_ID]
+    }
+
+    fn family_id(&self) -> u32 {
+        0xe48bff5a
+    }
+
+    fn board_name(&self) -> Cow<'static, str> {
+        "rp2350-riscv".into()
+    }
+
+    fn board_description(&self) -> Option<Cow<'static, str>> {
+        Some("Raspberry Pi RP2350, RISC-V programs".into())
+    }
+
+    fn valid_address_ranges(&self) -> Vec<AddressRange> {
+        vec![FLASH, RAM]
+    }
+
+    fn ram_address_ranges(&self) -> Vec<AddressRange> {
+        vec![RAM]
+    }
+
+    fn preamble_blocks(&self) -> Vec<Uf2Block> {
+        vec![absolute_block()]
+    }
+}
+
+/// The RP2350 running a non-secure Arm program, which the bootrom only boots from a partition
+/// marked as non-secure.
+#[derive(Debug, Default, Clone)]
+pub struct RP2350ArmNs;
+
+impl BoardInfo for RP2350ArmNs {
+    fn is_device_board(&self, device: &UsbDevice) -> bool {
+        is_rp2350_bootrom(device)
+    }
+
+    fn usb_matches(&self) -> Vec<(u16, u16)> {
+        vec![BOOTROM_USB_ID]
+    }
+
+    fn family_id(&self) -> u32 {
+        0xe48bff5b
+    }
+
+    fn board_name(&self) -> Cow<'static, str> {
+        "rp2350-arm-ns".into()
+    }
+
+    fn board_description(&self) -> Option<Cow<'static, str>> {
+        Some("Raspberry Pi RP2350, Non-secure Arm programs".into())
+    }
+
+    fn valid_address_ranges(&self) -> Vec<AddressRange> {
+        vec![FLASH, RAM]
+    }
+
+    fn ram_address_ranges(&self) -> Vec<AddressRange> {
+        vec![RAM]
+    }
+
+    fn preamble_blocks(&self) -> Vec<Uf2Block> {
+        vec![absolute_block()]
+    }
+}
+
+/// What the IMAGE_DEF of an image says it is built for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImageType {
+    Arm { non_secure: bool },
+    RiscV,
+}
+
+/// The image type item of the first picobin block in `data`, the start of an image. Blocks are
+/// word aligned, and made up of a start marker, items and an end marker.
+fn image_type(data: &[u8]) -> Option<ImageType> {
+    let words: Vec<u32> = data[..data.len().min(BLOCK_SEARCH_SIZE)]
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes(word.try_into().expect("chunks are words")))
+        .collect();
+    let start = words
+        .iter()
+        .position(|&word| word == PICOBIN_BLOCK_MARKER_START)?;
+
+    let mut index = start + 1;
+    while let Some(&item) = words.get(index) {
+        let [typ, size, flags_low, flags_high] = item.to_le_bytes();
+        if item == PICOBIN_BLOCK_MARKER_END || typ == PICOBIN_BLOCK_ITEM_LAST {
+            return None;
+        }
+        if typ & 0x7f == PICOBIN_BLOCK_ITEM_IMAGE_TYPE {
+            let flags = u16::from_le_bytes([flags_low, flags_high]);
+            // Bits 8-10 are the cpu, 0 for Arm and 1 for RISC-V. Bits 4-5 the Arm security, 1 for
+            // non-secure
+            return match (flags >> 8) & 0x7 {
+                0 => Some(ImageType::Arm {
+                    non_secure: (flags >> 4) & 0x3 == 1,
+                }),
+                1 => Some(ImageType::RiscV),
+                _ => None,
+            };
+        }
+
+        // Items with the top bit of the type set have a two byte size
+        let size = if typ & 0x80 == 0 {
+            size as usize
+        } else {
+            u16::from_le_bytes([size, flags_low]) as usize
+        };
+        if size == 0 {
+            return None;
+        }
+        index += size;
+    }
+    None
+}
+
+/// The RP2350 family id an ELF is built for: from the IMAGE_DEF block at the start of its lowest
+/// loadable segment, or from its machine when it has none. `None` for an ELF that is neither Arm
+/// nor RISC-V.
+///
+/// Exposes types of the `elf` crate, so it isn't part of the supported API.
+#[doc(hidden)]
+pub fn infer_family(elf: &ElfBytes<AnyEndian>) -> Option<u32> {
+    let first_segment = elf.segments().and_then(|segments| {
+        segments
+            .iter()
+            .filter(|segment| segment.p_type == PT_LOAD && segment.p_filesz > 0)
+            .min_by_key(|segment| segment.p_paddr)
+    });
+    let image_type = first_segment
+        .and_then(|segment| elf.segment_data(&segment).ok())
+        .and_then(image_type);
+
+    let (family_id, reason) = match image_type {
+        Some(ImageType::RiscV) => (RP2350RiscV.family_id(), "its IMAGE_DEF is for RISC-V"),
+        Some(ImageType::Arm { non_secure: true }) => (
+            RP2350ArmNs.family_id(),
+            "its IMAGE_DEF is for non-secure Arm",
+        ),
+        Some(ImageType::Arm { non_secure: false }) => {
+            (RP2350.family_id(), "its IMAGE_DEF is for secure Arm")
+        }
+        None => match elf.ehdr.e_machine {
+            EM_RISCV => (RP2350RiscV.family_id(), "it is built for RISC-V"),
+            EM_ARM => (RP2350.family_id(), "it is built for Arm"),
+            _ => return None,
+        },
+    };
+    info!(
+        "Inferred family id {} from the ELF, {reason}",
+        describe_family(family_id)
+    );
+    Some(family_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_elf::{TestElf, TestSegment};
+
+    /// A picobin block with just an image type item of `flags`
+    fn image_def(flags: u16) -> Vec<u8> {
+        let [low, high] = flags.to_le_bytes();
+        [
+            PICOBIN_BLOCK_MARKER_START.to_le_bytes(),
+            [PICOBIN_BLOCK_ITEM_IMAGE_TYPE, 1, low, high],
+            [PICOBIN_BLOCK_ITEM_LAST, 1, 0, 0],
+            0u32.to_le_bytes(),
+            PICOBIN_BLOCK_MARKER_END.to_le_bytes(),
+        ]
+        .concat()
+    }
+
+    fn infer(machine: u16, data: Vec<u8>) -> Option<u32> {
+        let elf = TestElf {
+            machine,
+            ..TestElf::new(vec![
+                TestSegment::load(0x10001000, vec![0x22; 64]),
+                TestSegment::load(0x10000000, data),
+            ])
+        }
+        .build();
+        infer_family(&ElfBytes::minimal_parse(&elf).unwrap())
+    }
+
+    #[test]
+    fn family_follows_the_elf_machine() {
+        assert_eq!(infer(EM_ARM, vec![0x11; 64]), Some(0xe48bff59));
+        assert_eq!(infer(EM_RISCV, vec![0x11; 64]), Some(0xe48bff5a));
+        // x86-64
+        assert_eq!(infer(62, vec![0x11; 64]), None);
+
+        let fixture = include_bytes!("../../tests/rp2350/flash_image.elf");
+        assert_eq!(
+            infer_family(&ElfBytes::minimal_parse(fixture).unwrap()),
+            Some(0xe48bff59)
+        );
+    }
+
+    #[test]
+    fn image_def_wins_over_the_machine() {
+        // After the vector table, as the pico-sdk places it
+        let with_block = |flags| [vec![0; 0x110], image_def(flags)].concat();
+
+        // EXE, RP2350, RISC-V
+        assert_eq!(infer(EM_ARM, with_block(0x1101)), Some(0xe48bff5a));
+        // EXE, RP2350, Arm, secure
+        assert_eq!(infer(EM_RISCV, with_block(0x1021)), Some(0xe48bff59));
+        // EXE, RP2350, Arm, non-secure
+        assert_eq!(infer(EM_ARM, with_block(0x1011)), Some(0xe48bff5b));
+
+        // Blocks past the first 4KiB aren't looked at
+        let late = [vec![0; BLOCK_SEARCH_SIZE], image_def(0x1101)].concat();
+        assert_eq!(infer(EM_ARM, late), Some(0xe48bff59));
     }
 }
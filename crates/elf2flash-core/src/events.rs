@@ -0,0 +1,74 @@
+//! Structured records of what a conversion did to the program besides copying it, for library
+//! users that want more than the log. They are handed to [`ProgressReporter::event`] before the
+//! blocks are written, and kept in [`ConversionSummary::events`].
+//!
+//! [`ProgressReporter::event`]: crate::ProgressReporter::event
+//! [`ConversionSummary::events`]: crate::ConversionSummary::events
+
+use std::fmt;
+
+use crate::boards::family::describe_family;
+
+/// Why a loadable segment was left out of the uf2 file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IgnoreReason {
+    /// The segment only reserves memory, like a `.bss`, without any file contents to write
+    Uninitialized,
+    /// The segment loads into an address range whose contents are ignored
+    IgnoredRange,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConversionEvent {
+    /// No blocks are written for the `size` bytes of memory of the loadable segment at `addr`
+    SegmentIgnored {
+        addr: u64,
+        size: u64,
+        reason: IgnoreReason,
+    },
+    /// An empty page was added at `addr` to fill a touched flash sector, see
+    /// [`Uf2Options::fill_sectors`](crate::Uf2Options::fill_sectors)
+    PaddingPageInserted { addr: u64 },
+    /// Every byte of the page at `addr` was excluded, so it isn't written, see
+    /// [`Uf2Options::exclude_ranges`](crate::Uf2Options::exclude_ranges)
+    PageExcluded { addr: u64 },
+    /// An input of [`elf2uf2_multi`](crate::elf2uf2_multi) is written with the family id `to`
+    /// instead of the board's `from`
+    FamilyOverride { from: u32, to: u32 },
+}
+
+impl fmt::Display for ConversionEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionEvent::SegmentIgnored {
+                addr,
+                size,
+                reason: IgnoreReason::Uninitialized,
+            } => write!(
+                f,
+                "Ignored the uninitialized segment at {addr:#010x} ({size} bytes)"
+            ),
+            ConversionEvent::SegmentIgnored {
+                addr,
+                size,
+                reason: IgnoreReason::IgnoredRange,
+            } => write!(
+                f,
+                "Ignored the segment at {addr:#010x} ({size} bytes), it loads into an ignored \
+                 address range"
+            ),
+            ConversionEvent::PaddingPageInserted { addr } => {
+                write!(f, "Inserted a padding page at {addr:#010x}")
+            }
+            ConversionEvent::PageExcluded { addr } => {
+                write!(f, "Excluded the page at {addr:#010x}")
+            }
+            ConversionEvent::FamilyOverride { from, to } => write!(
+                f,
+                "Writing family id {} instead of the board's {}",
+                describe_family(*to),
+                describe_family(*from)
+            ),
+        }
+    }
+}
@@ -0,0 +1,365 @@
+//! UF2 extension tags, stored after the payload of a block when [`UF2_FLAG_EXTENSION_TAGS_PRESENT`]
+//! is set.
+//!
+//! Every tag starts on a 4 byte boundary with a one byte size (including the 4 byte tag header)
+//! and a 3 byte little endian designator, the list ends with a zero sized tag.
+
+use thiserror::Error;
+use zerocopy::FromBytes;
+
+use crate::uf2::{
+    UF2_BLOCK_SIZE, UF2_FLAG_EXTENSION_TAGS_PRESENT, UF2_FLAG_MD5_PRESENT, UF2_TAG_DESCRIPTION,
+    UF2_TAG_DEVICE_TYPE_ID, UF2_TAG_FIRMWARE_VERSION, Uf2BlockData, Uf2BlockHeader,
+};
+
+/// Size of the MD5 area at the end of the data of a block with [`UF2_FLAG_MD5_PRESENT`]
+pub const MD5_AREA_SIZE: usize = 24;
+
+const TAG_HEADER_SIZE: usize = 4;
+const DATA_SIZE: usize = size_of::<Uf2BlockData>();
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtensionTag {
+    /// MD5 of the payloads of each contiguous range of pages, written to the MD5 area of the
+    /// range's last block (the spec stores it there instead of in a tag), see [`Md5Area`]
+    Md5Checksum,
+    /// Semver version of the firmware
+    FirmwareVersion(String),
+    /// Description of the device the firmware is for
+    Description(String),
+    /// Device type identifier, a hash of the vendor and device name
+    DeviceTypeId(u32),
+    /// Any other tag, `designator` must fit in 24 bits
+    RawTag { designator: u32, data: Vec<u8> },
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ExtensionTagError {
+    #[error("extension tag designator {0:#x} doesn't fit in 24 bits")]
+    InvalidDesignator(u32),
+    #[error("extension tag {designator:#08x} is {size} bytes, a tag can be at most 255 bytes")]
+    TagTooLarge { designator: u32, size: usize },
+    #[error(
+        "extension tags need {needed} bytes, only {available} are left after the {payload_size} byte payload"
+    )]
+    Overflow {
+        needed: usize,
+        available: usize,
+        payload_size: u32,
+    },
+    #[error("malformed extension tag at offset {0} of the block data")]
+    Malformed(usize),
+}
+
+impl ExtensionTag {
+    fn designator(&self) -> Option<u32> {
+        match self {
+            ExtensionTag::Md5Checksum => None,
+            ExtensionTag::FirmwareVersion(_) => Some(UF2_TAG_FIRMWARE_VERSION),
+            ExtensionTag::Description(_) => Some(UF2_TAG_DESCRIPTION),
+            ExtensionTag::DeviceTypeId(_) => Some(UF2_TAG_DEVICE_TYPE_ID),
+            ExtensionTag::RawTag { designator, .. } => Some(*designator),
+        }
+    }
+
+    fn data(&self) -> Vec<u8> {
+        match self {
+            ExtensionTag::Md5Checksum => Vec::new(),
+            ExtensionTag::FirmwareVersion(s) | ExtensionTag::Description(s) => {
+                s.as_bytes().to_vec()
+            }
+            ExtensionTag::DeviceTypeId(id) => id.to_le_bytes().to_vec(),
+            ExtensionTag::RawTag { data, .. } => data.clone(),
+        }
+    }
+
+    /// The tag for `designator`, [`ExtensionTag::RawTag`] if it's unknown or its data is invalid
+    fn from_raw(designator: u32, data: &[u8]) -> Self {
+        let string = String::from_utf8(data.to_vec()).ok();
+
+        match (designator, string) {
+            (UF2_TAG_FIRMWARE_VERSION, Some(s)) => ExtensionTag::FirmwareVersion(s),
+            (UF2_TAG_DESCRIPTION, Some(s)) => ExtensionTag::Description(s),
+            (UF2_TAG_DEVICE_TYPE_ID, _) if data.len() == 4 => {
+                ExtensionTag::DeviceTypeId(u32::from_le_bytes(data.try_into().unwrap()))
+            }
+            _ => ExtensionTag::RawTag {
+                designator,
+                data: data.to_vec(),
+            },
+        }
+    }
+}
+
+/// Extension tags encoded for the final block of a uf2 file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EncodedTags {
+    /// The tags followed by the terminating tag, empty if there are none
+    pub bytes: Vec<u8>,
+    /// Whether [`ExtensionTag::Md5Checksum`] was requested
+    pub md5: bool,
+}
+
+impl EncodedTags {
+    /// Encode `tags`, checking they fit in the data area behind a `payload_size` byte payload.
+    pub fn new(tags: &[ExtensionTag], payload_size: u32) -> Result<Self, ExtensionTagError> {
+        let mut encoded = Self::default();
+
+        for tag in tags {
+            let Some(designator) = tag.designator() else {
+                encoded.md5 = true;
+                continue;
+            };
+
+            if designator > 0xff_ffff {
+                return Err(ExtensionTagError::InvalidDesignator(designator));
+            }
+
+            let data = tag.data();
+            let size = TAG_HEADER_SIZE + data.len();
+            if size > u8::MAX as usize {
+                return Err(ExtensionTagError::TagTooLarge { designator, size });
+            }
+
+            encoded.bytes.push(size as u8);
+            encoded
+                .bytes
+                .extend_from_slice(&designator.to_le_bytes()[..3]);
+            encoded.bytes.extend_from_slice(&data);
+            encoded.bytes.resize(align4(encoded.bytes.len()), 0);
+        }
+
+        if !encoded.bytes.is_empty() {
+            encoded.bytes.extend_from_slice(&[0; TAG_HEADER_SIZE]);
+        }
+
+        let start = align4(payload_size as usize);
+        let end = if encoded.md5 {
+            DATA_SIZE - MD5_AREA_SIZE
+        } else {
+            DATA_SIZE
+        };
+        let available = end.saturating_sub(start);
+
+        if encoded.bytes.len() > available {
+            return Err(ExtensionTagError::Overflow {
+                needed: encoded.bytes.len(),
+                available,
+                payload_size,
+            });
+        }
+
+        Ok(encoded)
+    }
+
+    /// The flags to add to the block holding the tags
+    pub fn flags(&self) -> u32 {
+        let mut flags = 0;
+        if !self.bytes.is_empty() {
+            flags |= UF2_FLAG_EXTENSION_TAGS_PRESENT;
+        }
+        if self.md5 {
+            flags |= UF2_FLAG_MD5_PRESENT;
+        }
+        flags
+    }
+
+    /// Copy the tags behind the `payload_size` byte payload in `data`.
+    pub fn write(&self, data: &mut Uf2BlockData, payload_size: u32) {
+        let start = align4(payload_size as usize);
+        data[start..start + self.bytes.len()].copy_from_slice(&self.bytes);
+    }
+}
+
+/// The MD5 area at the end of the data of a block, checksumming `length` bytes from `address`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Md5Area {
+    pub address: u32,
+    pub length: u32,
+    pub md5: [u8; 16],
+}
+
+impl Md5Area {
+    pub fn write(&self, data: &mut Uf2BlockData) {
+        let area = &mut data[DATA_SIZE - MD5_AREA_SIZE..];
+        area[..4].copy_from_slice(&self.address.to_le_bytes());
+        area[4..8].copy_from_slice(&self.length.to_le_bytes());
+        area[8..].copy_from_slice(&self.md5);
+    }
+
+    /// Read the MD5 area of a serialized block, `None` if [`UF2_FLAG_MD5_PRESENT`] isn't set.
+    pub fn read(block: &[u8; UF2_BLOCK_SIZE]) -> Option<Self> {
+        let (header, data) = split_block(block);
+        if header.flags & UF2_FLAG_MD5_PRESENT == 0 {
+            return None;
+        }
+
+        let area = &data[DATA_SIZE - MD5_AREA_SIZE..];
+        Some(Self {
+            address: u32::from_le_bytes(area[..4].try_into().unwrap()),
+            length: u32::from_le_bytes(area[4..8].try_into().unwrap()),
+            md5: area[8..].try_into().unwrap(),
+        })
+    }
+}
+
+/// Parse the extension tags of a serialized block, including [`ExtensionTag::Md5Checksum`] if
+/// the block has an MD5 area.
+pub fn parse_extension_tags(
+    block: &[u8; UF2_BLOCK_SIZE],
+) -> Result<Vec<ExtensionTag>, ExtensionTagError> {
+    let (header, data) = split_block(block);
+    let mut tags = Vec::new();
+
+    let end = if header.flags & UF2_FLAG_MD5_PRESENT != 0 {
+        tags.push(ExtensionTag::Md5Checksum);
+        DATA_SIZE - MD5_AREA_SIZE
+    } else {
+        DATA_SIZE
+    };
+
+    if header.flags & UF2_FLAG_EXTENSION_TAGS_PRESENT == 0 {
+        return Ok(tags);
+    }
+
+    let mut offset = align4(header.payload_size as usize);
+    while offset + TAG_HEADER_SIZE <= end {
+        let size = data[offset] as usize;
+        if size == 0 {
+            break;
+        }
+        if size < TAG_HEADER_SIZE || offset + size > end {
+            return Err(ExtensionTagError::Malformed(offset));
+        }
+
+        let designator =
+            u32::from_le_bytes([data[offset + 1], data[offset + 2], data[offset + 3], 0]);
+        tags.push(ExtensionTag::from_raw(
+            designator,
+            &data[offset + TAG_HEADER_SIZE..offset + size],
+        ));
+
+        offset += align4(size);
+    }
+
+    Ok(tags)
+}
+
+fn split_block(block: &[u8; UF2_BLOCK_SIZE]) -> (Uf2BlockHeader, &[u8]) {
+    let (header, rest) = Uf2BlockHeader::read_from_prefix(block)
+        .expect("A uf2 block is always larger than its header");
+    (header, &rest[..DATA_SIZE])
+}
+
+fn align4(n: usize) -> usize {
+    n.next_multiple_of(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(encoded: &EncodedTags, payload_size: u32) -> [u8; UF2_BLOCK_SIZE] {
+        let header = Uf2BlockHeader {
+            magic_start0: 0,
+            magic_start1: 0,
+            flags: encoded.flags(),
+            target_addr: 0,
+            payload_size,
+            block_no: 0,
+            num_blocks: 1,
+            file_size: 0,
+        };
+        let mut data: Uf2BlockData = [0xaa; 476];
+        data[payload_size as usize..].fill(0);
+        encoded.write(&mut data, payload_size);
+
+        let mut block = [0; UF2_BLOCK_SIZE];
+        block[..32].copy_from_slice(zerocopy::IntoBytes::as_bytes(&header));
+        block[32..32 + DATA_SIZE].copy_from_slice(&data);
+        block
+    }
+
+    #[test]
+    fn tags_round_trip() {
+        let tags = vec![
+            ExtensionTag::FirmwareVersion("1.2.3".to_string()),
+            ExtensionTag::Description("Pico W".to_string()),
+            ExtensionTag::DeviceTypeId(0x1234_5678),
+            ExtensionTag::RawTag {
+                designator: 0x0be9f7,
+                data: vec![0, 1, 0, 0],
+            },
+        ];
+
+        let encoded = EncodedTags::new(&tags, 255).unwrap();
+        assert_eq!(encoded.flags(), UF2_FLAG_EXTENSION_TAGS_PRESENT);
+        // Size 9, version designator, "1.2.3", padded to 12 bytes
+        assert_eq!(
+            encoded.bytes[..12],
+            [9, 0xbc, 0xc7, 0x9f, b'1', b'.', b'2', b'.', b'3', 0, 0, 0]
+        );
+        assert_eq!(encoded.bytes[encoded.bytes.len() - 4..], [0; 4]);
+
+        assert_eq!(parse_extension_tags(&block(&encoded, 255)).unwrap(), tags);
+    }
+
+    #[test]
+    fn md5_uses_the_md5_area() {
+        let tags = vec![
+            ExtensionTag::Md5Checksum,
+            ExtensionTag::FirmwareVersion("0.1.0".to_string()),
+        ];
+        let encoded = EncodedTags::new(&tags, 256).unwrap();
+        assert_eq!(
+            encoded.flags(),
+            UF2_FLAG_EXTENSION_TAGS_PRESENT | UF2_FLAG_MD5_PRESENT
+        );
+
+        let mut block = block(&encoded, 256);
+        let area = Md5Area {
+            address: 0x1000_0000,
+            length: 512,
+            md5: [7; 16],
+        };
+        let mut data: Uf2BlockData = block[32..32 + DATA_SIZE].try_into().unwrap();
+        area.write(&mut data);
+        block[32..32 + DATA_SIZE].copy_from_slice(&data);
+
+        assert_eq!(Md5Area::read(&block), Some(area));
+        assert_eq!(parse_extension_tags(&block).unwrap(), tags);
+    }
+
+    #[test]
+    fn rejects_tags_that_dont_fit() {
+        let long = ExtensionTag::Description("x".repeat(200));
+
+        assert!(EncodedTags::new(std::slice::from_ref(&long), 256).is_ok());
+        assert_eq!(
+            EncodedTags::new(&[long, ExtensionTag::Md5Checksum], 256),
+            Err(ExtensionTagError::Overflow {
+                needed: 208,
+                available: 196,
+                payload_size: 256
+            })
+        );
+        assert_eq!(
+            EncodedTags::new(&[ExtensionTag::Description("x".repeat(252))], 0),
+            Err(ExtensionTagError::TagTooLarge {
+                designator: UF2_TAG_DESCRIPTION,
+                size: 256
+            })
+        );
+        assert_eq!(
+            EncodedTags::new(
+                &[ExtensionTag::RawTag {
+                    designator: 0x100_0000,
+                    data: Vec::new()
+                }],
+                256
+            ),
+            Err(ExtensionTagError::InvalidDesignator(0x100_0000))
+        );
+        assert_eq!(EncodedTags::new(&[], 476), Ok(EncodedTags::default()));
+    }
+}
@@ -0,0 +1,152 @@
+//! The `INFO_UF2.TXT` every uf2 bootloader puts in the root of its volume. Its `Board-ID` names
+//! the board more reliably than the USB ids, which some bootloaders share between boards or
+//! report inconsistently.
+//!
+//! ```
+//! use elf2flash_core::info_uf2::InfoUf2;
+//!
+//! let info = InfoUf2::parse("UF2 Bootloader v3.0\nModel: Raspberry Pi RP2\nBoard-ID: RPI-RP2\n");
+//! assert_eq!(info.board_id.as_deref(), Some("RPI-RP2"));
+//! assert_eq!(info.bootloader_version.as_deref(), Some("v3.0"));
+//! ```
+
+use crate::boards::family::family_by_name;
+
+/// What a bootloader says about itself in `INFO_UF2.TXT`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InfoUf2 {
+    /// The version after `Bootloader` on the first line, e.g. `v3.0` or `0.6.0`
+    pub bootloader_version: Option<String>,
+    /// The `Model:` line, a human readable name like `Raspberry Pi RP2`
+    pub model: Option<String>,
+    /// The `Board-ID:` line, e.g. `RPI-RP2` or `nRF52840-Feather-revD`
+    pub board_id: Option<String>,
+    /// The family ids the bootloader lists as accepted, most don't list any
+    pub families: Vec<u32>,
+}
+
+impl InfoUf2 {
+    /// Parse the contents of `INFO_UF2.TXT`. Unknown lines are ignored, so this never fails, a
+    /// file without any of the known lines gives an empty `InfoUf2`.
+    pub fn parse(text: &str) -> Self {
+        let mut info = InfoUf2 {
+            bootloader_version: text.lines().next().and_then(|line| {
+                let (_, version) = line.split_once("Bootloader ")?;
+                version.split_whitespace().next().map(str::to_string)
+            }),
+            ..Default::default()
+        };
+
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "model" => info.model = Some(value.to_string()),
+                "board-id" => info.board_id = Some(value.to_string()),
+                "family" | "family-id" | "families" => info.families.extend(
+                    value
+                        .split([',', ' '])
+                        .filter(|family| !family.is_empty())
+                        .filter_map(parse_family),
+                ),
+                _ => {}
+            }
+        }
+        info
+    }
+
+    /// Whether the `Board-ID` starts with `prefix`, ignoring case. Board ids usually end with
+    /// the board revision, so boards match them by prefix.
+    pub fn board_id_starts_with(&self, prefix: &str) -> bool {
+        self.board_id.as_deref().is_some_and(|board_id| {
+            board_id
+                .get(..prefix.len())
+                .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+        })
+    }
+}
+
+/// A family id in hex like `0xe48bff56`, or a family name like `RP2040`
+fn parse_family(family: &str) -> Option<u32> {
+    match family.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => family_by_name(family),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boards::BoardIter;
+
+    const RP2040: &str = "UF2 Bootloader v3.0\r\nModel: Raspberry Pi RP2\r\nBoard-ID: RPI-RP2\r\n";
+    const RP2350: &str =
+        "UF2 Bootloader v1.0\r\nModel: Raspberry Pi RP2350\r\nBoard-ID: RP2350\r\n";
+    const FEATHER_NRF52840: &str = "UF2 Bootloader 0.6.0 lib/nrfx (v2.0.0) lib/tinyusb \
+        (0.10.1-293-gaf8e5a90) lib/uf2 (remotes/origin/configupdate-9-gadbb8c7)\r\n\
+        Model: Adafruit Feather nRF52840 Express\r\n\
+        Board-ID: nRF52840-Feather-revD\r\n\
+        SoftDevice: S140 version 6.1.1\r\n\
+        Date: Jun 19 2021\r\n";
+    const TINYUF2: &str = "TinyUF2 Bootloader 0.10.2 - tinyusb (0.12.0-145-g9775e7691)\r\n\
+        Model: Adafruit Feather ESP32-S2\r\n\
+        Board-ID: ESP32S2-Feather-revC\r\n\
+        Family: 0xbfdd4eee\r\n";
+
+    fn detect(text: &str) -> Option<String> {
+        let info = InfoUf2::parse(text);
+        BoardIter::new()
+            .find(|board| board.matches_info_uf2(&info))
+            .map(|board| board.board_name().into_owned())
+    }
+
+    #[test]
+    fn samples_are_parsed() {
+        assert_eq!(
+            InfoUf2::parse(RP2040),
+            InfoUf2 {
+                bootloader_version: Some("v3.0".to_string()),
+                model: Some("Raspberry Pi RP2".to_string()),
+                board_id: Some("RPI-RP2".to_string()),
+                families: Vec::new(),
+            }
+        );
+
+        let nrf52 = InfoUf2::parse(FEATHER_NRF52840);
+        assert_eq!(nrf52.bootloader_version.as_deref(), Some("0.6.0"));
+        assert_eq!(
+            nrf52.model.as_deref(),
+            Some("Adafruit Feather nRF52840 Express")
+        );
+        assert_eq!(nrf52.board_id.as_deref(), Some("nRF52840-Feather-revD"));
+
+        let tinyuf2 = InfoUf2::parse(TINYUF2);
+        assert_eq!(tinyuf2.bootloader_version.as_deref(), Some("0.10.2"));
+        assert_eq!(tinyuf2.families, [0xbfdd4eee]);
+        assert_eq!(
+            InfoUf2::parse("Family-ID: 0xe48bff59, RP2350_RISCV\n").families,
+            [0xe48bff59, 0xe48bff5a]
+        );
+
+        assert_eq!(InfoUf2::parse("not an info file"), InfoUf2::default());
+    }
+
+    #[test]
+    fn boards_are_found_by_board_id() {
+        assert_eq!(detect(RP2040).as_deref(), Some("rp2040"));
+        assert_eq!(detect(RP2350).as_deref(), Some("rp2350"));
+        assert_eq!(
+            detect(FEATHER_NRF52840).as_deref(),
+            Some("feather_nrf52840_express")
+        );
+        assert_eq!(detect(TINYUF2).as_deref(), Some("esp32s2"));
+        assert_eq!(
+            detect("Board-ID: nRF52840-CircuitPlayground-revD").as_deref(),
+            Some("circuit_playground_bluefruit")
+        );
+        assert_eq!(detect("Board-ID: SAMD51J19A-Feather-v0"), None);
+        assert_eq!(detect(""), None);
+    }
+}
@@ -0,0 +1,253 @@
+//! Telling the files elf2flash takes apart by their first bytes, and reading the memory image in an
+//! Intel HEX file.
+//!
+//! ```
+//! use elf2flash_core::input::{InputFormat, parse_intel_hex};
+//!
+//! let hex = ":0400000001020304F2\n:00000001FF\n";
+//! assert_eq!(InputFormat::detect(hex.as_bytes()), InputFormat::IntelHex);
+//! assert_eq!(parse_intel_hex(hex).unwrap(), [(0, vec![1, 2, 3, 4])]);
+//! ```
+
+use std::fmt;
+
+use thiserror::Error;
+
+use crate::uf2::UF2_MAGIC_START0;
+
+/// The kinds of files elf2flash takes, told apart by [`InputFormat::detect`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// An ELF file, starting with `\x7fELF`
+    Elf,
+    /// An already built uf2 file, starting with the magic of a uf2 block
+    Uf2,
+    /// Intel HEX records, lines starting with `:`
+    IntelHex,
+    /// Anything else, taken as the bytes of a memory image
+    Binary,
+}
+
+impl InputFormat {
+    /// The format of a file starting with `bytes`, whatever its extension.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(b"\x7fELF") {
+            InputFormat::Elf
+        } else if bytes.starts_with(&UF2_MAGIC_START0.to_le_bytes()) {
+            InputFormat::Uf2
+        } else if starts_with_intel_hex_record(bytes) {
+            InputFormat::IntelHex
+        } else {
+            InputFormat::Binary
+        }
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InputFormat::Elf => "ELF",
+            InputFormat::Uf2 => "uf2",
+            InputFormat::IntelHex => "Intel HEX",
+            InputFormat::Binary => "raw binary",
+        })
+    }
+}
+
+/// Whether the first line of `bytes` is an Intel HEX record, after the byte order mark or blank
+/// lines some editors add.
+fn starts_with_intel_hex_record(bytes: &[u8]) -> bool {
+    let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+    let start = bytes
+        .iter()
+        .position(|byte| !byte.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+    let line = bytes[start..]
+        .split(|&byte| byte == b'\n' || byte == b'\r')
+        .next()
+        .unwrap_or_default();
+    line.strip_prefix(b":").is_some_and(is_record_digits)
+}
+
+/// Whether `digits` could be the hex digits of a record, at least its length, address, type and
+/// checksum
+fn is_record_digits(digits: &[u8]) -> bool {
+    digits.len() >= 10 && digits.len().is_multiple_of(2) && digits.iter().all(u8::is_ascii_hexdigit)
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum IntelHexError {
+    #[error("Line {line} is not an Intel HEX record")]
+    InvalidRecord { line: usize },
+    #[error("Line {line} has the checksum {found:#04x}, but its bytes add up to {expected:#04x}")]
+    ChecksumMismatch {
+        line: usize,
+        found: u8,
+        expected: u8,
+    },
+    #[error("Line {line} has the unknown record type {record_type:#04x}")]
+    UnknownRecordType { line: usize, record_type: u8 },
+}
+
+/// The memory image in the Intel HEX `text`, as runs of bytes at consecutive addresses with the
+/// address each starts at, in the order of the file.
+///
+/// Extended segment and extended linear address records move the records after them. The start
+/// address records are ignored, uf2 bootloaders boot the image the way they always do. Nothing
+/// after the end of file record is read.
+pub fn parse_intel_hex(text: &str) -> Result<Vec<(u64, Vec<u8>)>, IntelHexError> {
+    let mut chunks: Vec<(u64, Vec<u8>)> = Vec::new();
+    // Added to the address of every data record, set by the extended address records
+    let mut base = 0;
+
+    for (index, line) in text.trim_start_matches('\u{feff}').lines().enumerate() {
+        let line_no = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let invalid = IntelHexError::InvalidRecord { line: line_no };
+        let record = decode_record(line).ok_or(invalid)?;
+        let (&found, bytes) = record.split_last().expect("records have a checksum");
+        let expected = bytes
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+            .wrapping_neg();
+        if found != expected {
+            return Err(IntelHexError::ChecksumMismatch {
+                line: line_no,
+                found,
+                expected,
+            });
+        }
+
+        let (&[len, address_high, address_low, record_type], data) = bytes
+            .split_first_chunk()
+            .expect("records have a length, address and type");
+        if data.len() != usize::from(len) {
+            return Err(IntelHexError::InvalidRecord { line: line_no });
+        }
+
+        match (record_type, data) {
+            (0x00, _) => {
+                let address = base + u64::from(u16::from_be_bytes([address_high, address_low]));
+                match chunks.last_mut() {
+                    Some((start, bytes)) if *start + bytes.len() as u64 == address => {
+                        bytes.extend_from_slice(data)
+                    }
+                    _ => chunks.push((address, data.to_vec())),
+                }
+            }
+            (0x01, _) => break,
+            (0x02, &[high, low]) => base = u64::from(u16::from_be_bytes([high, low])) << 4,
+            (0x04, &[high, low]) => base = u64::from(u16::from_be_bytes([high, low])) << 16,
+            (0x03 | 0x05, _) => {}
+            (0x02 | 0x04, _) => return Err(IntelHexError::InvalidRecord { line: line_no }),
+            (record_type, _) => {
+                return Err(IntelHexError::UnknownRecordType {
+                    line: line_no,
+                    record_type,
+                });
+            }
+        }
+    }
+    Ok(chunks)
+}
+
+/// The bytes of a `:`-prefixed record line.
+fn decode_record(line: &str) -> Option<Vec<u8>> {
+    let digits = line.strip_prefix(':')?.as_bytes();
+    if !is_record_digits(digits) {
+        return None;
+    }
+    digits
+        .chunks_exact(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX: &str = "\
+:020000041000EA
+:10000000000102030405060708090A0B0C0D0E0F78
+:0800100010111213141516174C
+:040020002021222356
+:040000051000000CDB
+:00000001FF
+:040030003031323306
+";
+
+    #[test]
+    fn formats_are_told_apart_by_their_first_bytes() {
+        assert_eq!(
+            InputFormat::detect(include_bytes!("../tests/rp2040/hello_usb.elf")),
+            InputFormat::Elf
+        );
+        let mut uf2 = UF2_MAGIC_START0.to_le_bytes().to_vec();
+        uf2.resize(512, 0);
+        assert_eq!(InputFormat::detect(&uf2), InputFormat::Uf2);
+        assert_eq!(InputFormat::detect(HEX.as_bytes()), InputFormat::IntelHex);
+        assert_eq!(
+            InputFormat::detect(b"\xef\xbb\xbf\r\n:00000001FF\r\n"),
+            InputFormat::IntelHex
+        );
+
+        assert_eq!(
+            InputFormat::detect(&[0x00, 0x20, 0x04, 0x20]),
+            InputFormat::Binary
+        );
+        assert_eq!(InputFormat::detect(b":not hex"), InputFormat::Binary);
+        assert_eq!(InputFormat::detect(b""), InputFormat::Binary);
+    }
+
+    #[test]
+    fn records_are_read_into_runs_of_bytes() {
+        assert_eq!(
+            parse_intel_hex(HEX).unwrap(),
+            [
+                (0x1000_0000, (0x00..0x18).collect()),
+                (0x1000_0020, vec![0x20, 0x21, 0x22, 0x23]),
+            ]
+        );
+
+        assert_eq!(
+            parse_intel_hex(":020000021000EC\n:0100100042AD\n").unwrap(),
+            [(0x10010, vec![0x42])]
+        );
+    }
+
+    #[test]
+    fn broken_records_name_their_line() {
+        assert_eq!(
+            parse_intel_hex(":00000001FF\n").unwrap(),
+            Vec::<(u64, Vec<u8>)>::new()
+        );
+        assert_eq!(
+            parse_intel_hex(":0100000042BD\n\n0100000042BD\n"),
+            Err(IntelHexError::InvalidRecord { line: 3 })
+        );
+        assert_eq!(
+            parse_intel_hex(":0100000042BE\n"),
+            Err(IntelHexError::ChecksumMismatch {
+                line: 1,
+                found: 0xbe,
+                expected: 0xbd
+            })
+        );
+        assert_eq!(
+            parse_intel_hex(":0200000042BC\n"),
+            Err(IntelHexError::InvalidRecord { line: 1 })
+        );
+        assert_eq!(
+            parse_intel_hex(":0000000AF6\n"),
+            Err(IntelHexError::UnknownRecordType {
+                line: 1,
+                record_type: 0x0a
+            })
+        );
+    }
+}
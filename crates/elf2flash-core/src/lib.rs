@@ -1,33 +1,138 @@
+//! Convert ELF files into uf2 files for the boards in [`boards`].
+//!
+//! The supported API is everything in [`prelude`], along with the [`boards`], [`events`],
+//! [`extension`], [`info_uf2`], [`input`], [`pages`], [`progress`], [`transforms`], [`usb_cdc`]
+//! and [`warnings`] modules, and the constants, [`uf2::Uf2Block`], [`uf2::merge`] and
+//! [`uf2::verify_against_elf`] in [`uf2`]. Items hidden from
+//! these docs, like the raw block layouts in [`uf2`], are used by the `elf2flash` command line
+//! tool and may change in any release.
+//!
+//! ```
+//! use std::io::Cursor;
+//! use elf2flash_core::prelude::*;
+//!
+//! let elf = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+//! let mut uf2 = Vec::new();
+//! let summary = Uf2Writer::new(&RP2040)
+//!     .write(Cursor::new(elf), &mut uf2, NoProgress)
+//!     .unwrap();
+//! assert_eq!(uf2.len(), summary.num_blocks as usize * 512);
+//! ```
+
 use std::{
-    collections::HashSet,
-    io::{Cursor, Write},
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque, btree_map},
+    io::{Cursor, Read, Seek, Write},
+    iter::{self, Peekable},
+    mem,
+    ops::Range,
+    time::Duration,
+    vec,
 };
 
-use ::elf::{ElfBytes, ParseError, endian::AnyEndian};
-use assert_into::AssertInto;
+use ::elf::{
+    ElfStream, ParseError,
+    abi::{EM_ARM, EM_RISCV, PT_LOAD},
+    endian::AnyEndian,
+    segment::ProgramHeader,
+};
 use log::debug;
 use thiserror::Error;
-use zerocopy::IntoBytes;
 
 use crate::{
-    address_range::AddressRangesFromElfError,
-    boards::BoardInfo,
-    elf::{get_page_fragments, realize_page},
+    address_range::AddressRange,
+    address_range::{AddressRangesFromElfError, loadable_segments, section_load_range},
+    boards::{
+        BoardInfo, MAX_PAGE_SIZE, MAX_SPLIT_PAGE_SIZE, MIN_PAGE_SIZE, UsbDevice,
+        family::describe_family,
+    },
+    events::ConversionEvent,
+    extension::{EncodedTags, ExtensionTag, ExtensionTagError, Md5Area},
+    pages::{AddressRangesExt, PageFragment, exclude_ranges, realize_page, segment_page_fragments},
+    progress::ProgressWrite,
+    transforms::PageTransform,
     uf2::{
-        UF2_FLAG_FAMILY_ID_PRESENT, UF2_MAGIC_END, UF2_MAGIC_START0, UF2_MAGIC_START1,
-        Uf2BlockData, Uf2BlockFooter, Uf2BlockHeader,
+        BlockHeaderTemplate, FileSizeField, UF2_BLOCK_SIZE, UF2_FLAG_MD5_PRESENT,
+        UF2_FLAG_NOT_MAIN_FLASH, Uf2Block, Uf2BlockData,
     },
+    warnings::{WarningCode, Warnings},
 };
 
 pub mod address_range;
 pub mod boards;
-pub mod elf;
+pub mod events;
+pub mod extension;
+pub mod info_uf2;
+pub mod input;
+pub mod pages;
+pub mod prelude;
+pub mod progress;
+pub mod transforms;
 pub mod uf2;
+pub mod usb_cdc;
+pub mod warnings;
+
+/// The page layout, re-exported under its old name for crates that used it before it moved to
+/// [`pages`].
+#[doc(hidden)]
+#[deprecated(since = "0.1.0", note = "moved to `elf2flash_core::pages`")]
+pub mod elf {
+    pub use crate::pages::{
+        AddressRangesExt, PageFragment, get_page_fragments, get_page_fragments_from_segments,
+        realize_page,
+    };
+}
+
+#[cfg(test)]
+mod test_elf;
+#[cfg(any(test, feature = "test-support"))]
+pub mod testing;
+
+/// What a [`ProgressReporter`] is currently reporting on, see [`ProgressReporter::phase`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressPhase {
+    /// Converting an elf into uf2 blocks
+    Converting,
+    /// Writing the uf2 file to a board
+    Writing { board_name: String },
+    /// Reading written data back to check it
+    Verifying,
+}
+
+/// Where in the uf2 file an operation is, see [`ProgressReporter::detail`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgressDetail {
+    pub phase: ProgressPhase,
+    /// Index of the block in the uf2 file, counting every family's blocks
+    pub block_no: u32,
+    /// Address the block writes to
+    pub target_addr: u32,
+    /// Offset of the block in the uf2 file
+    pub file_offset: u64,
+}
 
 pub trait ProgressReporter {
+    /// Called before [`ProgressReporter::start`] to say what the progress is about, does nothing
+    /// by default.
+    fn phase(&mut self, _phase: ProgressPhase) {}
+    /// Called before a block, or a chunk of blocks starting with it, is written, e.g. to show the
+    /// address being written. Does nothing by default.
+    fn detail(&mut self, _detail: ProgressDetail) {}
+    /// Called after [`ProgressReporter::phase`] for every [`ConversionEvent`] of the conversion,
+    /// before any block is written. Does nothing by default.
+    fn event(&mut self, _event: ConversionEvent) {}
     fn start(&mut self, total_bytes: usize);
     fn advance(&mut self, bytes: usize);
     fn finish(&mut self);
+    /// Called instead of [`ProgressReporter::finish`] when the operation is cancelled part way.
+    fn cancel(&mut self) {
+        self.finish();
+    }
+    /// Polled between blocks, returning true stops the operation with
+    /// [`Elf2Uf2Error::Cancelled`].
+    fn should_cancel(&self) -> bool {
+        false
+    }
 }
 
 pub struct NoProgress;
@@ -37,6 +142,34 @@ impl ProgressReporter for NoProgress {
     fn finish(&mut self) {}
 }
 
+/// Lends a reporter to a function taking one by value, so it can be looked at afterwards.
+impl<R: ProgressReporter + ?Sized> ProgressReporter for &mut R {
+    fn phase(&mut self, phase: ProgressPhase) {
+        (**self).phase(phase);
+    }
+    fn detail(&mut self, detail: ProgressDetail) {
+        (**self).detail(detail);
+    }
+    fn event(&mut self, event: ConversionEvent) {
+        (**self).event(event);
+    }
+    fn start(&mut self, total_bytes: usize) {
+        (**self).start(total_bytes);
+    }
+    fn advance(&mut self, bytes: usize) {
+        (**self).advance(bytes);
+    }
+    fn finish(&mut self) {
+        (**self).finish();
+    }
+    fn cancel(&mut self) {
+        (**self).cancel();
+    }
+    fn should_cancel(&self) -> bool {
+        (**self).should_cancel()
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum Elf2Uf2Error {
     #[error("Failed to get address ranges from elf")]
@@ -47,11 +180,197 @@ pub enum Elf2Uf2Error {
     RealizePageError(#[from] std::io::Error),
     #[error("The input file has no memory pages")]
     InputFileNoMemoryPagesError,
+    #[error(
+        "The input file has no contents to write, its loadable segments are all uninitialized: {}",
+        describe_segments(.segments)
+    )]
+    OnlyUninitializedSegments {
+        /// `(address, memory size)` of every skipped segment
+        segments: Vec<(u64, u64)>,
+    },
+    #[error(
+        "ELF segments overlap with different contents at {:#08x}->{:#08x} and {:#08x}->{:#08x}",
+        .page_addr + .first.0,
+        .page_addr + .first.0 + .first.1,
+        .page_addr + .second.0,
+        .page_addr + .second.0 + .second.1
+    )]
+    OverlappingSegments {
+        /// Address of the page both segments write to
+        page_addr: u64,
+        /// `(page offset, length)` of the fragment that was placed first
+        first: (u64, u64),
+        /// `(page offset, length)` of the fragment that overlaps it
+        second: (u64, u64),
+    },
+    #[error("Invalid uf2 extension tags: {0}")]
+    ExtensionTagError(#[from] ExtensionTagError),
+    #[error(
+        "The page size {0} must be a power of two between {MIN_PAGE_SIZE} and {MAX_SPLIT_PAGE_SIZE}"
+    )]
+    InvalidPageSize(u32),
+    #[error(
+        "The payload size {payload_size} must be a power of two between {MIN_PAGE_SIZE} and \
+         {MAX_PAGE_SIZE}, no larger than the page size {page_size}"
+    )]
+    InvalidPayloadSize { payload_size: u32, page_size: u32 },
+    #[error(
+        "The flash sector erase size {erase_size} must be a non-zero multiple of the page size \
+         {page_size}"
+    )]
+    InvalidEraseSize { erase_size: u64, page_size: u32 },
+    #[error(
+        "Inputs {first} and {second} both write to {address:#08x} with family id {family_id:#x}"
+    )]
+    OverlappingInputs {
+        family_id: u32,
+        address: u32,
+        /// Index of the input that wrote the page first
+        first: usize,
+        /// Index of the input that writes it again
+        second: usize,
+    },
+    #[error("The ELF has no section named '{0}' to exclude")]
+    UnknownSection(String),
+    #[error("The address {0:#x} doesn't fit in the 32 bit target address of a uf2 block")]
+    AddressOutOfRange(u64),
+    #[error("The address offset {offset} must be a multiple of the page size {page_size}")]
+    UnalignedAddressOffset { offset: i64, page_size: u32 },
+    #[error("Moving the page at {page_addr:#08x} by the address offset {offset} overflows")]
+    AddressOffsetOverflow { page_addr: u64, offset: i64 },
+    #[error("The conversion was cancelled")]
+    Cancelled,
+}
+
+/// Options for how the uf2 file is generated.
+#[derive(Debug, Clone)]
+pub struct Uf2Options {
+    /// Set [`UF2_FLAG_NOT_MAIN_FLASH`] on every block and skip filling flash sectors, even if the
+    /// program doesn't only load into the board's RAM (which is detected automatically)
+    pub not_main_flash: bool,
+    /// Extension tags to add to the final block, e.g. the firmware version
+    pub extension_tags: Vec<ExtensionTag>,
+    /// Byte written to the parts of a page the program doesn't cover, including whole filler
+    /// pages. Erased NOR flash reads as `0xff`, so padding with it lets a flashed image compare
+    /// equal to untouched flash.
+    pub block_padding_byte: u8,
+    /// Add empty blocks for the pages of every touched flash sector the program leaves out, so
+    /// each sector is written whole. Bootloaders that erase a sector before writing its first
+    /// block can lose the rest of the sector otherwise, turning this off only makes sense for
+    /// bootloaders known to erase per page, where it keeps images with large gaps small.
+    pub fill_sectors: bool,
+    /// Also fill the pages after the program in the last flash sector it touches, which
+    /// [`fill_sectors`](Self::fill_sectors) leaves out, so the file covers whole sectors. For
+    /// bootloaders that only erase the pages they write, where old data would survive behind the
+    /// program otherwise. Needs [`fill_sectors`](Self::fill_sectors).
+    pub pad_final_sector: bool,
+    /// Run on the contents of every flash page before it is written into its block, e.g.
+    /// [`transforms::rp2040_boot2_checksum`]
+    pub page_transforms: Vec<PageTransform>,
+    /// Write the checksum the RP2040 bootrom expects into the second stage bootloader at
+    /// `0x10000000`, after the [`page_transforms`](Self::page_transforms). Without a valid
+    /// checksum the chip silently doesn't boot, projects that don't link pico-sdk's boot2 easily
+    /// get it wrong. Ignored for other boards.
+    pub fix_rp2040_boot2: bool,
+    /// Added to the target address of every page once the program was checked against the board's
+    /// address ranges, e.g. `0x8000` to place an application linked for the start of flash behind
+    /// a bootloader. Must be a multiple of the page size, flash sectors are filled at the moved
+    /// addresses.
+    pub address_offset: i64,
+    /// Address ranges to leave out of the uf2, e.g. a filesystem region the firmware must not
+    /// overwrite. The ranges are target addresses, with the
+    /// [`address_offset`](Self::address_offset) already applied. Pages wholly inside a range are dropped and never added to fill a sector. Pages
+    /// that are only partly inside keep the program bytes outside the range, the bytes inside are
+    /// padding, since a block always writes the whole page.
+    pub exclude_ranges: Vec<Range<u64>>,
+    /// ELF sections to leave out like [`exclude_ranges`](Self::exclude_ranges), by name. A section
+    /// is excluded where it is loaded, which is in flash for sections like `.data` that are
+    /// copied to RAM at startup, moved by the [`address_offset`](Self::address_offset).
+    pub exclude_sections: Vec<String>,
+    /// Set [`UF2_FLAG_NOT_MAIN_FLASH`] on the blocks of programs detected to only load into RAM.
+    /// They are laid out the same either way, without filling flash sectors or preamble blocks.
+    pub flag_ram_only: bool,
+    /// Bytes of a page written by each block, `None` for the whole page. A smaller payload splits
+    /// every page into several blocks with consecutive target addresses, for bootloaders that
+    /// expect e.g. 256 byte payloads on a board with 4 KiB pages. Pages larger than
+    /// [`MAX_PAGE_SIZE`] don't fit in one block and need it.
+    pub payload_size: Option<u32>,
+    /// Leave [`UF2_FLAG_FAMILY_ID_PRESENT`](uf2::UF2_FLAG_FAMILY_ID_PRESENT) off every block and
+    /// write the size of the uf2 file into the field that holds the family id otherwise, as the
+    /// spec had it before family ids. Some older bootloaders reject blocks with the flag. Preamble
+    /// blocks keep their own family id, and [`elf2uf2_multi`] ignores this, its inputs are told
+    /// apart by their family ids.
+    pub omit_family_id: bool,
+}
+
+impl Uf2Options {
+    /// Options reproducing the output of [elf2uf2-rs](https://github.com/JoNil/elf2uf2-rs), to
+    /// check a switch from it by comparing the uf2 files. For an RP2040 ELF the blocks are
+    /// identical, the defaults already match elf2uf2-rs except:
+    ///
+    /// - elf2uf2-rs never sets [`UF2_FLAG_NOT_MAIN_FLASH`], so
+    ///   [`flag_ram_only`](Self::flag_ram_only) is off.
+    ///
+    /// The differences no option covers:
+    ///
+    /// - elf2uf2-rs decides a program runs from RAM by its entry point, not by where all of its
+    ///   pages are, so an ELF with its entry point in RAM but pages in flash gets no sector fill
+    ///   from elf2uf2-rs.
+    /// - Only the RP2040 output is covered, other boards keep their family id and preamble
+    ///   blocks, e.g. the RP2350's absolute block.
+    pub fn elf2uf2_rs_compat() -> Self {
+        Self {
+            flag_ram_only: false,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for Uf2Options {
+    fn default() -> Self {
+        Self {
+            not_main_flash: false,
+            extension_tags: Vec::new(),
+            block_padding_byte: 0,
+            fill_sectors: true,
+            pad_final_sector: false,
+            page_transforms: Vec::new(),
+            fix_rp2040_boot2: false,
+            address_offset: 0,
+            exclude_ranges: Vec::new(),
+            exclude_sections: Vec::new(),
+            flag_ram_only: true,
+            payload_size: None,
+            omit_family_id: false,
+        }
+    }
+}
+
+/// What a conversion produced.
+#[derive(Debug, Clone, Default)]
+pub struct ConversionSummary {
+    /// The number of blocks in the uf2 file
+    pub num_blocks: u32,
+    /// How many of those blocks are empty padding, added to fill touched flash sectors
+    pub filler_blocks: u32,
+    /// Whether the blocks were laid out as not main flash, see [`Uf2Options::not_main_flash`].
+    /// They are flagged as such unless [`Uf2Options::flag_ram_only`] is off for a program detected
+    /// to only load into RAM
+    pub not_main_flash: bool,
+    /// Whether the program likely enables USB CDC, see [`usb_cdc::likely_has_cdc`]. Only known
+    /// when a single ELF is converted and its symbol table could be read, `None` otherwise
+    pub likely_has_cdc: Option<bool>,
+    pub warnings: Warnings,
+    /// What the conversion did besides copying the program, in the order it happened
+    pub events: Vec<ConversionEvent>,
 }
 
 /// Convert a file to a uf2 file. Give an input, and it generates an output. If you don't want to provide a family_id or reporter, then the family_id defaults to
 /// the rp2040's family id. Just pass in the NoProgress struct to reporter you do not wish to have progress reporting.
 ///
+/// The input only has to be seekable, pages are read from it as the blocks are written, so large
+/// images never have to be held in memory.
+///
 /// # Examples
 ///
 /// ```
@@ -61,136 +380,2503 @@ pub enum Elf2Uf2Error {
 /// log::set_max_level(log::LevelFilter::Debug);
 /// let bytes_in = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
 /// let mut bytes_out = Vec::new();
-/// let board = boards::RP2040::default();
-/// elf2uf2(bytes_in, &mut bytes_out, &board, NoProgress).unwrap();
+/// elf2uf2(Cursor::new(bytes_in), &mut bytes_out, boards::RP2040::default(), NoProgress).unwrap();
+///
+/// // Boards can be passed by reference too, e.g. one built at runtime
+/// let board = boards::CustomBoardBuilder::new()
+///     .family_id(0xe48bff56)
+///     .build()
+///     .unwrap();
+/// let mut bytes_out = Vec::new();
+/// elf2uf2(Cursor::new(bytes_in), &mut bytes_out, &board, NoProgress).unwrap();
 /// ```
 pub fn elf2uf2(
-    input: impl AsRef<[u8]>,
-    mut output: impl Write,
-    board: &dyn BoardInfo,
-    mut reporter: impl ProgressReporter,
+    input: impl Read + Seek,
+    output: impl Write,
+    board: impl BoardInfo,
+    reporter: impl ProgressReporter,
 ) -> Result<(), Elf2Uf2Error> {
-    let input = input.as_ref();
-    let file = ElfBytes::<AnyEndian>::minimal_parse(input)?;
+    elf2uf2_with_options(input, output, board, &Uf2Options::default(), reporter)?;
+    Ok(())
+}
 
-    let page_size = board.page_size();
-    let flash_sector_erase_size = board.flash_sector_erase_size();
-    let family_id = board.family_id();
+/// Same as [`elf2uf2`], with explicit [`Uf2Options`], returning a summary of the conversion.
+pub fn elf2uf2_with_options(
+    input: impl Read + Seek,
+    output: impl Write,
+    board: impl BoardInfo,
+    options: &Uf2Options,
+    reporter: impl ProgressReporter,
+) -> Result<ConversionSummary, Elf2Uf2Error> {
+    let blocks = Uf2BlockIterator::with_options(input, board, options)?;
 
-    let mut pages = get_page_fragments(&file, page_size)?;
+    log::debug!("Writing program");
+    write_blocks(blocks, output, reporter)
+}
 
-    if pages.is_empty() {
-        return Err(Elf2Uf2Error::InputFileNoMemoryPagesError);
+/// The size of a uf2 file, as computed by [`elf2uf2_size`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Uf2SizeEstimate {
+    /// The number of blocks, including preamble and filler blocks
+    pub num_blocks: u32,
+    /// The size of the file in bytes
+    pub total_bytes: u64,
+}
+
+/// Compute the size of the uf2 file [`elf2uf2`] would write for `input`, without reading the page
+/// contents, e.g. to check there is room for it before writing anything.
+///
+/// The pages are laid out exactly like a conversion does, so the estimate is always the final
+/// size.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+/// use elf2flash_core::{elf2uf2_size, boards};
+///
+/// let elf = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+/// let size = elf2uf2_size(Cursor::new(elf), &boards::RP2040).unwrap();
+/// assert_eq!(size.total_bytes, size.num_blocks as u64 * 512);
+/// ```
+pub fn elf2uf2_size(
+    input: impl Read + Seek,
+    board: impl BoardInfo,
+) -> Result<Uf2SizeEstimate, Elf2Uf2Error> {
+    elf2uf2_size_with_options(input, board, &Uf2Options::default())
+}
+
+/// Same as [`elf2uf2_size`], with explicit [`Uf2Options`].
+pub fn elf2uf2_size_with_options(
+    mut input: impl Read + Seek,
+    board: impl BoardInfo,
+    options: &Uf2Options,
+) -> Result<Uf2SizeEstimate, Elf2Uf2Error> {
+    let num_blocks = elf_layout(&mut input, &board, options)?.summary.num_blocks;
+    Ok(Uf2SizeEstimate {
+        num_blocks,
+        total_bytes: num_blocks as u64 * UF2_BLOCK_SIZE as u64,
+    })
+}
+
+/// Build a uf2 file that writes `data` to `address`, e.g. to update a settings sector without
+/// flashing the whole application again.
+///
+/// The data is laid out like a single ELF segment loaded at `address`, so it is checked against
+/// the board's address ranges, and with [`Uf2Options::fill_sectors`] the pages before it in its
+/// first flash sector are written with the padding byte.
+///
+/// # Examples
+///
+/// ```
+/// use elf2flash_core::{NoProgress, Uf2Options, boards, pages_to_uf2};
+///
+/// let settings = [0x5a; 256];
+/// let mut uf2 = Vec::new();
+/// let summary = pages_to_uf2(
+///     0x101ff000,
+///     &settings,
+///     &mut uf2,
+///     &boards::RP2040,
+///     &Uf2Options::default(),
+///     NoProgress,
+/// )
+/// .unwrap();
+/// assert_eq!(summary.num_blocks, 1);
+/// ```
+pub fn pages_to_uf2(
+    address: u64,
+    data: &[u8],
+    output: impl Write,
+    board: impl BoardInfo,
+    options: &Uf2Options,
+    reporter: impl ProgressReporter,
+) -> Result<ConversionSummary, Elf2Uf2Error> {
+    let blocks = Uf2BlockIterator::from_segments(
+        Cursor::new(data),
+        &[load_segment(address, 0, data.len())],
+        &options.exclude_ranges,
+        &board,
+        options,
+    )?;
+
+    log::debug!("Writing {} bytes at {address:#08x}", data.len());
+    write_blocks(blocks, output, reporter)
+}
+
+/// A segment loading the `len` bytes at `offset` of the input to `address`.
+fn load_segment(address: u64, offset: usize, len: usize) -> ProgramHeader {
+    ProgramHeader {
+        p_type: PT_LOAD,
+        p_offset: offset as u64,
+        p_vaddr: address,
+        p_paddr: address,
+        p_filesz: len as u64,
+        p_memsz: len as u64,
+        p_flags: 0,
+        p_align: 0,
     }
+}
 
-    let touched_sectors: HashSet<u64> = pages
-        .keys()
-        .map(|addr| addr / flash_sector_erase_size)
-        .collect();
+/// Write out all `blocks`, reporting the progress.
+fn write_blocks(
+    mut blocks: Uf2BlockIterator<impl Read + Seek>,
+    output: impl Write,
+    mut reporter: impl ProgressReporter,
+) -> Result<ConversionSummary, Elf2Uf2Error> {
+    let summary = blocks.summary().clone();
 
-    let last_page_addr = *pages
-        .last_key_value()
-        .expect("Impossible error occurred since pages is garunteed to have a last page")
-        .0;
-    for sector in touched_sectors {
-        let mut page = sector * flash_sector_erase_size;
+    reporter.phase(ProgressPhase::Converting);
+    for event in &summary.events {
+        reporter.event(event.clone());
+    }
+    let mut output = ProgressWrite::new(output, &mut reporter, blocks.total_bytes());
 
-        while page < (sector + 1) * flash_sector_erase_size {
-            if page < last_page_addr && !pages.contains_key(&page) {
-                pages.insert(page, Vec::new());
-            }
-            page += page_size as u64;
+    let mut index = 0;
+    while let Some(block) = blocks.next_block() {
+        if output.should_cancel() {
+            output.cancel();
+            return Err(Elf2Uf2Error::Cancelled);
         }
+        let block = block?;
+        output.detail(converting_detail(index, &block));
+        output.write_all(&block.to_bytes())?;
+        index += 1;
+    }
+
+    // The output is flushed before the progress bar is allowed to finish
+    output.finish()?;
+
+    Ok(summary)
+}
+
+/// The detail reported before writing `block`, the `index`th block of a uf2 file.
+fn converting_detail(index: u32, block: &Uf2Block) -> ProgressDetail {
+    ProgressDetail {
+        phase: ProgressPhase::Converting,
+        block_no: index,
+        target_addr: block.target_addr(),
+        file_offset: index as u64 * UF2_BLOCK_SIZE as u64,
     }
+}
+
+/// Convert several ELF files, each with its own family id, into a single uf2 file.
+///
+/// This is how e.g. an RP2350 uf2 can carry both an ARM and a RISC-V image. The blocks of every
+/// input are written back-to-back, in order. Blocks are numbered per family, so inputs sharing a
+/// family id are numbered as one image, and must not write to the same pages.
+///
+/// # Examples
+///
+/// ```
+/// use elf2flash_core::{elf2uf2_multi, boards, NoProgress};
+///
+/// let elf = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+/// let mut bytes_out = Vec::new();
+/// elf2uf2_multi(&[(elf, 0xe48bff56)], &mut bytes_out, &boards::RP2040, NoProgress).unwrap();
+/// ```
+pub fn elf2uf2_multi(
+    inputs: &[(impl AsRef<[u8]>, u32)],
+    output: impl Write,
+    board: impl BoardInfo,
+    reporter: impl ProgressReporter,
+) -> Result<(), Elf2Uf2Error> {
+    elf2uf2_multi_with_options(inputs, output, board, &Uf2Options::default(), reporter)?;
+    Ok(())
+}
 
-    let mut block_header = Uf2BlockHeader {
-        magic_start0: UF2_MAGIC_START0,
-        magic_start1: UF2_MAGIC_START1,
-        flags: UF2_FLAG_FAMILY_ID_PRESENT,
-        target_addr: 0,
-        payload_size: page_size,
-        block_no: 0,
-        num_blocks: pages.len() as u32,
-        file_size: family_id,
+/// Same as [`elf2uf2_multi`], with explicit [`Uf2Options`] applied to every input.
+pub fn elf2uf2_multi_with_options(
+    inputs: &[(impl AsRef<[u8]>, u32)],
+    output: impl Write,
+    board: impl BoardInfo,
+    options: &Uf2Options,
+    mut reporter: impl ProgressReporter,
+) -> Result<ConversionSummary, Elf2Uf2Error> {
+    let mut summary = ConversionSummary {
+        not_main_flash: true,
+        ..Default::default()
+    };
+    // The inputs are told apart by their family ids, they can't be left out
+    let options = &Uf2Options {
+        omit_family_id: false,
+        ..options.clone()
     };
+    let mut images = Vec::new();
+    let mut family_blocks: HashMap<u32, u32> = HashMap::new();
+    let mut page_owners: HashMap<(u32, u32), usize> = HashMap::new();
 
-    let mut block_data: Uf2BlockData = [0; 476];
+    for (index, (elf, family_id)) in inputs.iter().enumerate() {
+        if *family_id != board.family_id() {
+            summary.events.push(ConversionEvent::FamilyOverride {
+                from: board.family_id(),
+                to: *family_id,
+            });
+        }
+        let family_board = FamilyBoard {
+            board: &board,
+            family_id: *family_id,
+        };
+        let mut blocks =
+            Uf2BlockIterator::with_options(Cursor::new(elf.as_ref()), &family_board, options)?;
+
+        summary.num_blocks += blocks.num_blocks();
+        summary.filler_blocks += blocks.summary().filler_blocks;
+        summary.not_main_flash &= blocks.summary().not_main_flash;
+        summary.warnings.extend(blocks.summary().warnings.clone());
+        summary
+            .events
+            .extend(blocks.summary().events.iter().cloned());
+
+        let blocks = iter::from_fn(|| blocks.next_block()).collect::<Result<Vec<_>, _>>()?;
+        for block in &blocks {
+            let address = block.target_addr();
+            if let Some(&first) = page_owners.get(&(*family_id, address)) {
+                return Err(Elf2Uf2Error::OverlappingInputs {
+                    family_id: *family_id,
+                    address,
+                    first,
+                    second: index,
+                });
+            }
+            page_owners.insert((*family_id, address), index);
+        }
+
+        *family_blocks.entry(*family_id).or_default() += blocks.len() as u32;
+        images.push((*family_id, blocks));
+    }
 
-    let block_footer = Uf2BlockFooter {
-        magic_end: UF2_MAGIC_END,
+    // The preamble is only written once, not once per input
+    let preamble = if summary.not_main_flash {
+        Vec::new()
+    } else {
+        board.preamble_blocks()
     };
+    summary.num_blocks += preamble.len() as u32;
 
-    log::debug!("Writing program");
+    log::debug!("Writing {} programs", images.len());
 
-    reporter.start(pages.len() * 512);
+    reporter.phase(ProgressPhase::Converting);
+    for event in &summary.events {
+        reporter.event(event.clone());
+    }
+    let mut output = ProgressWrite::new(
+        output,
+        &mut reporter,
+        summary.num_blocks as usize * UF2_BLOCK_SIZE,
+    );
 
-    let last_page_num = pages.len() - 1;
+    let mut index = 0;
+    for block in &preamble {
+        output.detail(converting_detail(index, block));
+        output.write_all(&block.to_bytes())?;
+        index += 1;
+    }
 
-    for (page_num, (target_addr, fragments)) in pages.into_iter().enumerate() {
-        block_header.target_addr = target_addr as u32;
-        block_header.block_no = page_num.assert_into();
+    let mut block_numbers: HashMap<u32, u32> = HashMap::new();
+    for (family_id, blocks) in images {
+        for mut block in blocks {
+            if output.should_cancel() {
+                output.cancel();
+                return Err(Elf2Uf2Error::Cancelled);
+            }
+            let block_no = block_numbers.entry(family_id).or_default();
 
-        debug!(
-            "Page {} / {} {:#08x}",
-            block_header.block_no as u32,
-            block_header.num_blocks as u32,
-            block_header.target_addr as u32
-        );
+            block.set_block_no(*block_no);
+            block.set_num_blocks(family_blocks[&family_id]);
+            *block_no += 1;
+
+            output.detail(converting_detail(index, &block));
+            output.write_all(&block.to_bytes())?;
+            index += 1;
+        }
+    }
 
-        block_data.iter_mut().for_each(|v| *v = 0);
+    output.finish()?;
 
-        realize_page(
-            &mut Cursor::new(input),
-            &fragments,
-            &mut block_data,
-            page_size,
-        )?;
+    Ok(summary)
+}
 
-        output.write_all(block_header.as_bytes())?;
-        output.write_all(block_data.as_bytes())?;
-        output.write_all(block_footer.as_bytes())?;
+/// Writes uf2 files for a board, with the same [`Uf2Options`] for every file.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+/// use elf2flash_core::{NoProgress, Uf2Options, Uf2Writer, boards, extension::ExtensionTag};
+///
+/// let elf = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+/// let writer = Uf2Writer::new(&boards::RP2040).options(Uf2Options {
+///     extension_tags: vec![ExtensionTag::FirmwareVersion("1.0.0".to_string())],
+///     ..Default::default()
+/// });
+///
+/// let mut uf2 = Vec::new();
+/// writer.write(Cursor::new(elf), &mut uf2, NoProgress).unwrap();
+/// ```
+pub struct Uf2Writer<'a> {
+    board: &'a dyn BoardInfo,
+    options: Uf2Options,
+}
 
-        if page_num != last_page_num {
-            reporter.advance(512);
+impl<'a> Uf2Writer<'a> {
+    pub fn new(board: &'a dyn BoardInfo) -> Self {
+        Self {
+            board,
+            options: Uf2Options::default(),
         }
     }
 
-    // Drop the output before the progress bar is allowd to finish
-    drop(output);
+    pub fn options(mut self, options: Uf2Options) -> Self {
+        self.options = options;
+        self
+    }
 
-    reporter.advance(512);
+    /// Convert a single ELF file, see [`elf2uf2_with_options`].
+    pub fn write(
+        &self,
+        input: impl Read + Seek,
+        output: impl Write,
+        reporter: impl ProgressReporter,
+    ) -> Result<ConversionSummary, Elf2Uf2Error> {
+        elf2uf2_with_options(input, output, self.board, &self.options, reporter)
+    }
 
-    reporter.finish();
+    /// Convert several ELF files with their own family ids, see [`elf2uf2_multi_with_options`].
+    pub fn write_multi(
+        &self,
+        inputs: &[(impl AsRef<[u8]>, u32)],
+        output: impl Write,
+        reporter: impl ProgressReporter,
+    ) -> Result<ConversionSummary, Elf2Uf2Error> {
+        elf2uf2_multi_with_options(inputs, output, self.board, &self.options, reporter)
+    }
 
-    Ok(())
+    /// Write `data` to `address`, see [`pages_to_uf2`].
+    pub fn write_pages(
+        &self,
+        address: u64,
+        data: &[u8],
+        output: impl Write,
+        reporter: impl ProgressReporter,
+    ) -> Result<ConversionSummary, Elf2Uf2Error> {
+        pages_to_uf2(address, data, output, self.board, &self.options, reporter)
+    }
+
+    /// The blocks of a single ELF file, for callers that write them out themselves.
+    pub fn blocks<R: Read + Seek>(&self, input: R) -> Result<Uf2BlockIterator<R>, Elf2Uf2Error> {
+        Uf2BlockIterator::with_options(input, self.board, &self.options)
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// A board with its family id replaced and no preamble, used for the inputs of
+/// [`elf2uf2_multi`].
+struct FamilyBoard<'a> {
+    board: &'a dyn BoardInfo,
+    family_id: u32,
+}
 
-    #[test]
-    pub fn hello_usb() {
-        log::set_max_level(log::LevelFilter::Debug);
-        let bytes_in = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
-        let mut bytes_out = Vec::new();
-        let board = boards::RP2040::default();
-        elf2uf2(bytes_in, &mut bytes_out, &board, NoProgress).unwrap();
+impl BoardInfo for FamilyBoard<'_> {
+    fn is_device_board(&self, device: &UsbDevice) -> bool {
+        self.board.is_device_board(device)
+    }
 
-        assert_eq!(bytes_out, include_bytes!("../tests/rp2040/hello_usb.uf2"));
+    fn usb_matches(&self) -> Vec<(u16, u16)> {
+        self.board.usb_matches()
     }
 
-    #[test]
-    pub fn hello_serial() {
-        log::set_max_level(log::LevelFilter::Debug);
-        let bytes_in = &include_bytes!("../tests/rp2040/hello_serial.elf")[..];
-        let mut bytes_out = Vec::new();
-        let board = boards::RP2040::default();
-        elf2uf2(bytes_in, &mut bytes_out, &board, NoProgress).unwrap();
+    fn family_id(&self) -> u32 {
+        self.family_id
+    }
 
-        assert_eq!(
-            bytes_out,
-            include_bytes!("../tests/rp2040/hello_serial.uf2")
+    fn page_size(&self) -> u32 {
+        self.board.page_size()
+    }
+
+    fn flash_sector_erase_size(&self) -> u64 {
+        self.board.flash_sector_erase_size()
+    }
+
+    fn board_name(&self) -> Cow<'static, str> {
+        self.board.board_name()
+    }
+
+    fn board_description(&self) -> Option<Cow<'static, str>> {
+        self.board.board_description()
+    }
+
+    fn needs_eject(&self) -> bool {
+        self.board.needs_eject()
+    }
+
+    fn uf2_filename(&self) -> &str {
+        self.board.uf2_filename()
+    }
+
+    fn write_chunk_size(&self) -> usize {
+        self.board.write_chunk_size()
+    }
+
+    fn inter_chunk_delay(&self) -> Option<Duration> {
+        self.board.inter_chunk_delay()
+    }
+
+    fn valid_address_ranges(&self) -> Vec<AddressRange> {
+        self.board.valid_address_ranges()
+    }
+
+    fn ram_address_ranges(&self) -> Vec<AddressRange> {
+        self.board.ram_address_ranges()
+    }
+}
+
+/// Lazily converts an ELF file into 512 byte UF2 blocks.
+///
+/// The page layout is computed from the program headers when the iterator is created, the page
+/// contents are only read from the input when the block containing them is requested.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+/// use elf2flash_core::{Uf2BlockIterator, boards};
+///
+/// let bytes_in = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+/// let blocks = Uf2BlockIterator::new(Cursor::new(bytes_in), &boards::RP2040).unwrap();
+/// assert_eq!(blocks.total_bytes(), blocks.num_blocks() as usize * 512);
+///
+/// for block in blocks {
+///     let block: [u8; 512] = block.unwrap();
+/// }
+/// ```
+pub struct Uf2BlockIterator<R> {
+    input: R,
+    /// Blocks emitted before the program, see [`BoardInfo::preamble_blocks`]
+    preamble: vec::IntoIter<Uf2Block>,
+    pages: Peekable<btree_map::IntoIter<u64, Vec<PageFragment>>>,
+    page_size: u32,
+    payload_size: u32,
+    template: BlockHeaderTemplate,
+    padding_byte: u8,
+    page_transforms: Vec<PageTransform>,
+    block_no: u32,
+    num_blocks: u32,
+    summary: ConversionSummary,
+    extension_tags: EncodedTags,
+    /// Running MD5 of the payloads of the current contiguous range of pages, with the address of
+    /// its first page and the bytes hashed
+    md5: Option<(md5::Context, u32, u32)>,
+    /// The blocks of the current page not returned yet, when it is split over several blocks
+    split_blocks: VecDeque<Uf2Block>,
+}
+
+impl<R: Read + Seek> Uf2BlockIterator<R> {
+    /// Parse the ELF headers from `input` and lay out the pages for `board`.
+    pub fn new(input: R, board: impl BoardInfo) -> Result<Self, Elf2Uf2Error> {
+        Self::with_options(input, board, &Uf2Options::default())
+    }
+
+    /// Same as [`Uf2BlockIterator::new`], with explicit [`Uf2Options`].
+    pub fn with_options(
+        mut input: R,
+        board: impl BoardInfo,
+        options: &Uf2Options,
+    ) -> Result<Self, Elf2Uf2Error> {
+        let layout = elf_layout(&mut input, &board, options)?;
+        Ok(Self::from_layout(input, layout, &board, options))
+    }
+
+    /// Lay out the pages of `segments`, whose file offsets point into `input`, leaving out the
+    /// `excluded` address ranges.
+    fn from_segments(
+        mut input: R,
+        segments: &[ProgramHeader],
+        excluded: &[Range<u64>],
+        board: &dyn BoardInfo,
+        options: &Uf2Options,
+    ) -> Result<Self, Elf2Uf2Error> {
+        let layout = segments_layout(&mut input, segments, excluded, board, options)?;
+        Ok(Self::from_layout(input, layout, board, options))
+    }
+
+    /// Get ready to write the blocks of `layout`, reading the page contents from `input`.
+    fn from_layout(
+        input: R,
+        layout: PageLayout,
+        board: &dyn BoardInfo,
+        options: &Uf2Options,
+    ) -> Self {
+        let PageLayout {
+            pages,
+            preamble,
+            flags,
+            summary,
+            extension_tags,
+            payload_size,
+        } = layout;
+
+        let mut page_transforms = options.page_transforms.clone();
+        if options.fix_rp2040_boot2 {
+            if board.family_id() == boards::RP2040.family_id() {
+                page_transforms.push(transforms::rp2040_boot2_checksum());
+            } else {
+                debug!("Not an RP2040, leaving the boot2 checksum alone");
+            }
+        }
+
+        let first_page_addr = *pages
+            .first_key_value()
+            .expect("build_page_map never returns an empty page map")
+            .0 as u32;
+
+        let num_blocks = pages.len() as u32 * (board.page_size() / payload_size);
+        let field = if options.omit_family_id {
+            FileSizeField::Unflagged(summary.num_blocks * UF2_BLOCK_SIZE as u32)
+        } else {
+            FileSizeField::FamilyId(board.family_id())
+        };
+        Self {
+            input,
+            preamble: preamble.into_iter(),
+            num_blocks,
+            pages: pages.into_iter().peekable(),
+            page_size: board.page_size(),
+            payload_size,
+            template: BlockHeaderTemplate::new(field)
+                .add_flags(flags)
+                .payload_size(payload_size)
+                .num_blocks(num_blocks),
+            padding_byte: options.block_padding_byte,
+            page_transforms,
+            block_no: 0,
+            summary,
+            md5: extension_tags
+                .md5
+                .then(|| (md5::Context::new(), first_page_addr, 0)),
+            extension_tags,
+            split_blocks: VecDeque::new(),
+        }
+    }
+}
+
+impl Uf2BlockIterator<Cursor<Vec<u8>>> {
+    /// Lay out the pages of a memory image, every run of bytes with the address it is loaded at,
+    /// as [`input::parse_intel_hex`] returns them or a binary file at its base address.
+    ///
+    /// ```
+    /// use elf2flash_core::{Uf2BlockIterator, Uf2Options, boards};
+    ///
+    /// let image = [(0x10000000, vec![0x5a; 300])];
+    /// let blocks =
+    ///     Uf2BlockIterator::from_image(&image, &boards::RP2040, &Uf2Options::default()).unwrap();
+    /// // 300 bytes span two 256 byte pages
+    /// assert_eq!(blocks.num_blocks(), 2);
+    /// ```
+    pub fn from_image(
+        image: &[(u64, Vec<u8>)],
+        board: impl BoardInfo,
+        options: &Uf2Options,
+    ) -> Result<Self, Elf2Uf2Error> {
+        let mut data = Vec::new();
+        let segments: Vec<ProgramHeader> = image
+            .iter()
+            .map(|(address, bytes)| {
+                let segment = load_segment(*address, data.len(), bytes.len());
+                data.extend_from_slice(bytes);
+                segment
+            })
+            .collect();
+
+        Self::from_segments(
+            Cursor::new(data),
+            &segments,
+            &options.exclude_ranges,
+            &board,
+            options,
+        )
+    }
+}
+
+/// Everything about the blocks of a uf2 file except the page contents, so laying out doesn't read
+/// more of the input than the ELF headers.
+struct PageLayout {
+    pages: BTreeMap<u64, Vec<PageFragment>>,
+    /// Blocks emitted before the program, see [`BoardInfo::preamble_blocks`]
+    preamble: Vec<Uf2Block>,
+    flags: u32,
+    summary: ConversionSummary,
+    extension_tags: EncodedTags,
+    /// Bytes of a page written by each block, dividing the page size
+    payload_size: u32,
+}
+
+/// Parse the ELF headers from `input` and lay out its pages, resolving the sections to exclude.
+fn elf_layout(
+    input: &mut (impl Read + Seek),
+    board: &dyn BoardInfo,
+    options: &Uf2Options,
+) -> Result<PageLayout, Elf2Uf2Error> {
+    let mut elf = ElfStream::<AnyEndian, _>::open_stream(&mut *input)?;
+    let segments = loadable_segments(elf.segments().clone(), elf.section_headers());
+
+    let mut excluded = options.exclude_ranges.clone();
+    for name in &options.exclude_sections {
+        let section = elf
+            .section_header_by_name(name)?
+            .ok_or_else(|| Elf2Uf2Error::UnknownSection(name.clone()))?;
+        let range = section_load_range(section, &segments);
+        let range = range.start.saturating_add_signed(options.address_offset)
+            ..range.end.saturating_add_signed(options.address_offset);
+        debug!(
+            "Excluding section {name} at {:#08x}->{:#08x}",
+            range.start, range.end
         );
+        excluded.push(range);
+    }
+
+    // Only a hint for the serial monitor, a symbol table it can't read doesn't stop the conversion
+    let likely_has_cdc = usb_cdc::scan(&mut elf)
+        .inspect_err(|err| debug!("Failed to look for USB CDC in the program: {err}"))
+        .ok();
+    let machine = elf.ehdr.e_machine;
+
+    let mut layout = segments_layout(input, &segments, &excluded, board, options)?;
+    layout.summary.likely_has_cdc = likely_has_cdc;
+    if let Some(message) = architecture_mismatch(machine, board.family_id()) {
+        layout
+            .summary
+            .warnings
+            .push(WarningCode::ArchitectureMismatch, message);
+    }
+    Ok(layout)
+}
+
+/// A warning when an ELF built for `machine` is written with a family id for the other
+/// architecture of a chip that runs both, like the RP2350's Arm and RISC-V cores.
+fn architecture_mismatch(machine: u16, family_id: u32) -> Option<String> {
+    let architecture = |machine| match machine {
+        EM_ARM => Some("Arm"),
+        EM_RISCV => Some("RISC-V"),
+        _ => None,
+    };
+    let family_machine = match family_id {
+        0xe48bff59 | 0xe48bff5b => EM_ARM,
+        0xe48bff5a => EM_RISCV,
+        _ => return None,
+    };
+    if machine == family_machine {
+        return None;
+    }
+
+    Some(format!(
+        "The ELF is a {} program, but family id {} is for {} programs, the board may be the \
+         wrong variant (e.g. rp2350 or rp2350-riscv)",
+        architecture(machine)?,
+        describe_family(family_id),
+        architecture(family_machine)?
+    ))
+}
+
+/// Lay out the pages of `segments`, whose file offsets point into `input`, leaving out the
+/// `excluded` address ranges and filling flash sectors.
+fn segments_layout(
+    input: &mut (impl Read + Seek),
+    segments: &[ProgramHeader],
+    excluded: &[Range<u64>],
+    board: &dyn BoardInfo,
+    options: &Uf2Options,
+) -> Result<PageLayout, Elf2Uf2Error> {
+    let page_size = board.page_size();
+    if !boards::is_valid_page_size(page_size) {
+        return Err(Elf2Uf2Error::InvalidPageSize(page_size));
+    }
+    let payload_size = options.payload_size.unwrap_or(page_size);
+    if !boards::is_valid_payload_size(payload_size, page_size) {
+        return Err(Elf2Uf2Error::InvalidPayloadSize {
+            payload_size,
+            page_size,
+        });
+    }
+    let blocks_per_page = page_size / payload_size;
+    let erase_size = board.flash_sector_erase_size();
+    if !boards::is_valid_erase_size(erase_size, page_size) {
+        return Err(Elf2Uf2Error::InvalidEraseSize {
+            erase_size,
+            page_size,
+        });
+    }
+
+    let mut summary = ConversionSummary::default();
+    let mut pages = build_page_map(
+        segments,
+        excluded,
+        options.address_offset,
+        board,
+        input,
+        &mut summary.events,
+    )?;
+    let extension_tags = EncodedTags::new(&options.extension_tags, payload_size)?;
+
+    let mut flags = 0;
+    let mut preamble = Vec::new();
+    if options.not_main_flash || is_ram_only(&pages, board) {
+        debug!("Generating a RAM-only uf2");
+        if options.not_main_flash || options.flag_ram_only {
+            flags |= UF2_FLAG_NOT_MAIN_FLASH;
+        }
+        summary.not_main_flash = true;
+    } else {
+        preamble = board.preamble_blocks();
+
+        let content_blocks = pages.len() * blocks_per_page as usize;
+        if options.fill_sectors {
+            summary.filler_blocks = fill_flash_sectors(
+                &mut pages,
+                excluded,
+                board,
+                options.pad_final_sector,
+                &mut summary.events,
+            ) * blocks_per_page;
+        }
+
+        if summary.filler_blocks as usize > content_blocks {
+            summary.warnings.push(
+                WarningCode::FillerInflation,
+                format!(
+                    "Filling flash sectors added {} empty blocks to {} blocks with contents, \
+                     the flash sector erase size ({}) may be too large",
+                    summary.filler_blocks,
+                    content_blocks,
+                    board.flash_sector_erase_size()
+                ),
+            );
+        }
+    }
+    summary.num_blocks = preamble.len() as u32 + pages.len() as u32 * blocks_per_page;
+
+    Ok(PageLayout {
+        pages,
+        preamble,
+        flags,
+        summary,
+        extension_tags,
+        payload_size,
+    })
+}
+
+impl<R> Uf2BlockIterator<R> {
+    /// The total number of blocks the UF2 file will contain, including preamble blocks
+    pub fn num_blocks(&self) -> u32 {
+        self.summary.num_blocks
+    }
+
+    /// The total size of the UF2 file in bytes
+    pub fn total_bytes(&self) -> usize {
+        self.num_blocks() as usize * UF2_BLOCK_SIZE
+    }
+
+    /// The layout of the UF2 file and the warnings raised while laying it out
+    pub fn summary(&self) -> &ConversionSummary {
+        &self.summary
+    }
+}
+
+impl<R: Read + Seek> Uf2BlockIterator<R> {
+    /// The next block, before it is serialized
+    fn next_block(&mut self) -> Option<Result<Uf2Block, Elf2Uf2Error>> {
+        if let Some(block) = self.preamble.next() {
+            return Some(Ok(block));
+        }
+
+        if let Some(block) = self.split_blocks.pop_front() {
+            return Some(Ok(block));
+        }
+
+        let (page_addr, fragments) = self.pages.next()?;
+
+        debug!(
+            "Page {} / {} {:#08x}",
+            self.block_no, self.num_blocks, page_addr as u32
+        );
+
+        let mut page = vec![self.padding_byte; self.page_size as usize];
+        if let Err(err) = realize_page(&mut self.input, &fragments, &mut page, self.page_size) {
+            return Some(Err(err.into()));
+        }
+
+        for transform in &self.page_transforms {
+            transform.apply(page_addr, &mut page);
+        }
+
+        // An MD5 area covers one contiguous range of pages, and goes in the range's last block
+        let mut md5_area = None;
+        if let Some(state) = &mut self.md5 {
+            state.0.consume(&page);
+            state.2 += self.page_size;
+            let range_end = page_addr + u64::from(self.page_size);
+            let next = self.pages.peek().map(|(next, _)| *next);
+            if next != Some(range_end) {
+                let next_range = (md5::Context::new(), next.unwrap_or(0) as u32, 0);
+                let (md5, address, length) = mem::replace(state, next_range);
+                md5_area = Some(Md5Area {
+                    address,
+                    length,
+                    md5: md5.finalize().0,
+                });
+            }
+        }
+
+        let blocks_per_page = (self.page_size / self.payload_size) as usize;
+        for (index, payload) in page.chunks_exact(self.payload_size as usize).enumerate() {
+            let target_addr = page_addr as u32 + index as u32 * self.payload_size;
+            let is_final_block = self.block_no + 1 == self.num_blocks;
+            let md5_area = md5_area.filter(|_| index + 1 == blocks_per_page);
+
+            let mut template = self.template;
+            if is_final_block {
+                template = template.add_flags(self.extension_tags.flags());
+            }
+            if md5_area.is_some() {
+                template = template.add_flags(UF2_FLAG_MD5_PRESENT);
+            }
+
+            // Bytes past the payload stay zero, the uf2 spec requires it
+            let mut block_data: Uf2BlockData = [0; 476];
+            block_data[..payload.len()].copy_from_slice(payload);
+
+            if is_final_block {
+                self.extension_tags
+                    .write(&mut block_data, self.payload_size);
+            }
+            if let Some(md5_area) = md5_area {
+                md5_area.write(&mut block_data);
+            }
+
+            self.split_blocks
+                .push_back(template.block(target_addr, self.block_no, block_data));
+            self.block_no += 1;
+        }
+
+        self.split_blocks.pop_front().map(Ok)
+    }
+}
+
+impl<R: Read + Seek> Iterator for Uf2BlockIterator<R> {
+    type Item = Result<[u8; UF2_BLOCK_SIZE], Elf2Uf2Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_block()?.map(|block| block.to_bytes()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let blocks_per_page = (self.page_size / self.payload_size) as usize;
+        let len =
+            self.preamble.len() + self.split_blocks.len() + self.pages.len() * blocks_per_page;
+        (len, Some(len))
+    }
+}
+
+impl<R: Read + Seek> ExactSizeIterator for Uf2BlockIterator<R> {}
+
+/// Lay out the pages of the program, checking that they lie within the board's valid address
+/// ranges, both where the ELF loads them and once moved by `address_offset`. The segments and
+/// pages left out are recorded in `events`.
+fn build_page_map(
+    segments: &[ProgramHeader],
+    excluded: &[Range<u64>],
+    address_offset: i64,
+    board: &dyn BoardInfo,
+    input: &mut (impl Read + Seek),
+    events: &mut Vec<ConversionEvent>,
+) -> Result<BTreeMap<u64, Vec<PageFragment>>, Elf2Uf2Error> {
+    let page_size = board.page_size();
+
+    let pages = segment_page_fragments(segments, page_size, input, events)?;
+    if pages.is_empty() {
+        check_uninitialized_only(segments)?;
+    }
+    check_target_addresses(&pages)?;
+    check_valid_ranges(&pages, board)?;
+
+    let mut pages = offset_pages(pages, address_offset, page_size)?;
+    exclude_ranges(&mut pages, excluded, events);
+
+    if pages.is_empty() {
+        return Err(Elf2Uf2Error::InputFileNoMemoryPagesError);
+    }
+    check_target_addresses(&pages)?;
+    if address_offset != 0 {
+        check_valid_ranges(&pages, board)?;
+    }
+
+    Ok(pages)
+}
+
+/// Check that the part of every page its fragments cover lies within the board's valid address
+/// ranges, if it has any.
+fn check_valid_ranges(
+    pages: &BTreeMap<u64, Vec<PageFragment>>,
+    board: &dyn BoardInfo,
+) -> Result<(), Elf2Uf2Error> {
+    let valid_ranges = board.valid_address_ranges();
+    if !valid_ranges.is_empty() {
+        for (page_addr, fragments) in pages {
+            let (start, end) = page_span(*page_addr, fragments, board.page_size());
+
+            valid_ranges
+                .as_slice()
+                .check_address_range(start, start, end - start, false)?;
+        }
+    }
+    Ok(())
+}
+
+/// Fail with [`Elf2Uf2Error::OnlyUninitializedSegments`] when the loadable segments only reserve
+/// memory, like a `.bss`, without any file contents to write.
+fn check_uninitialized_only(segments: &[ProgramHeader]) -> Result<(), Elf2Uf2Error> {
+    let loadable: Vec<_> = segments
+        .iter()
+        .filter(|segment| segment.p_type == PT_LOAD && segment.p_memsz > 0)
+        .collect();
+
+    if !loadable.is_empty() && loadable.iter().all(|segment| segment.p_filesz == 0) {
+        return Err(Elf2Uf2Error::OnlyUninitializedSegments {
+            segments: loadable
+                .iter()
+                .map(|segment| (segment.p_paddr, segment.p_memsz))
+                .collect(),
+        });
+    }
+    Ok(())
+}
+
+fn describe_segments(segments: &[(u64, u64)]) -> String {
+    segments
+        .iter()
+        .map(|(addr, size)| format!("{addr:#08x} ({size} bytes)"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Check that every page fits the u32 target address of a block.
+///
+/// 64-bit ELFs can place pages where the u32 target address of a block can't reach, the pages
+/// are sorted so only the last one needs checking.
+fn check_target_addresses(pages: &BTreeMap<u64, Vec<PageFragment>>) -> Result<(), Elf2Uf2Error> {
+    match pages.last_key_value() {
+        Some((&last_page, _)) if u32::try_from(last_page).is_err() => {
+            Err(Elf2Uf2Error::AddressOutOfRange(last_page))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Move every page by `offset`, which must keep them aligned to the page size.
+fn offset_pages(
+    pages: BTreeMap<u64, Vec<PageFragment>>,
+    offset: i64,
+    page_size: u32,
+) -> Result<BTreeMap<u64, Vec<PageFragment>>, Elf2Uf2Error> {
+    if offset == 0 {
+        return Ok(pages);
+    }
+    if !offset.unsigned_abs().is_multiple_of(page_size as u64) {
+        return Err(Elf2Uf2Error::UnalignedAddressOffset { offset, page_size });
+    }
+
+    debug!("Moving every page by {offset} bytes");
+    pages
+        .into_iter()
+        .map(|(page_addr, fragments)| {
+            page_addr
+                .checked_add_signed(offset)
+                .map(|moved| (moved, fragments))
+                .ok_or(Elf2Uf2Error::AddressOffsetOverflow { page_addr, offset })
+        })
+        .collect()
+}
+
+/// The address range of a page that is actually covered by its fragments
+fn page_span(page_addr: u64, fragments: &[PageFragment], page_size: u32) -> (u64, u64) {
+    let start = fragments.iter().map(|f| f.page_offset).min().unwrap_or(0);
+    let end = fragments
+        .iter()
+        .map(|f| f.page_offset + f.bytes)
+        .max()
+        .unwrap_or(page_size as u64);
+
+    (page_addr + start, page_addr + end)
+}
+
+/// Whether every page lies within the board's RAM, never true for boards without RAM ranges.
+fn is_ram_only(pages: &BTreeMap<u64, Vec<PageFragment>>, board: &dyn BoardInfo) -> bool {
+    let ram_ranges = board.ram_address_ranges();
+
+    !ram_ranges.is_empty()
+        && pages.iter().all(|(page_addr, fragments)| {
+            let (start, end) = page_span(*page_addr, fragments, board.page_size());
+            ram_ranges.iter().any(|r| r.from <= start && end <= r.to)
+        })
+}
+
+/// Add the empty pages needed to fill every touched flash erase sector, returning how many were
+/// added and recording each in `events`. The last sector is only filled up to the last page,
+/// unless `pad_final_sector` is set.
+fn fill_flash_sectors(
+    pages: &mut BTreeMap<u64, Vec<PageFragment>>,
+    excluded: &[Range<u64>],
+    board: &dyn BoardInfo,
+    pad_final_sector: bool,
+    events: &mut Vec<ConversionEvent>,
+) -> u32 {
+    let page_size = board.page_size();
+    let flash_sector_erase_size = board.flash_sector_erase_size();
+
+    let mut filler_pages = 0;
+    let touched_sectors: BTreeSet<u64> = pages
+        .keys()
+        .map(|addr| addr / flash_sector_erase_size)
+        .collect();
+
+    let last_page_addr = *pages
+        .last_key_value()
+        .expect("Impossible error occurred since pages is garunteed to have a last page")
+        .0;
+    for sector in touched_sectors {
+        let mut page = sector * flash_sector_erase_size;
+
+        while page < (sector + 1) * flash_sector_erase_size {
+            let is_excluded = excluded
+                .iter()
+                .any(|range| range.start <= page && page + page_size as u64 <= range.end);
+            let before_end = page < last_page_addr || pad_final_sector;
+            if before_end && !pages.contains_key(&page) && !is_excluded {
+                pages.insert(page, Vec::new());
+                events.push(ConversionEvent::PaddingPageInserted { addr: page });
+                filler_pages += 1;
+            }
+            page += page_size as u64;
+        }
+    }
+
+    filler_pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        boards::{CustomBoardBuildError, CustomBoardBuilder},
+        pages::get_page_fragments,
+        test_elf::{TestElf, TestSegment},
+        testing::{ProgressCall, RecordingReporter},
+        uf2::UF2_FLAG_FAMILY_ID_PRESENT,
+    };
+    use ::elf::ElfBytes;
+    use std::io::Cursor;
+
+    #[test]
+    pub fn hello_usb() {
+        log::set_max_level(log::LevelFilter::Debug);
+        let bytes_in = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+        let mut bytes_out = Vec::new();
+        let board = boards::RP2040::default();
+        elf2uf2(Cursor::new(bytes_in), &mut bytes_out, &board, NoProgress).unwrap();
+
+        assert_eq!(bytes_out, include_bytes!("../tests/rp2040/hello_usb.uf2"));
+    }
+
+    #[test]
+    pub fn hello_serial() {
+        log::set_max_level(log::LevelFilter::Debug);
+        let bytes_in = &include_bytes!("../tests/rp2040/hello_serial.elf")[..];
+        let mut bytes_out = Vec::new();
+        let board = boards::RP2040::default();
+        elf2uf2(Cursor::new(bytes_in), &mut bytes_out, &board, NoProgress).unwrap();
+
+        assert_eq!(
+            bytes_out,
+            include_bytes!("../tests/rp2040/hello_serial.uf2")
+        );
+    }
+
+    #[test]
+    pub fn family_id_can_be_omitted() {
+        let bytes_in = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+        let golden = &include_bytes!("../tests/rp2040/hello_usb.uf2")[..];
+        let convert = |omit_family_id| {
+            let mut bytes_out = Vec::new();
+            let options = Uf2Options {
+                omit_family_id,
+                ..Default::default()
+            };
+            elf2uf2_with_options(
+                Cursor::new(bytes_in),
+                &mut bytes_out,
+                &boards::RP2040,
+                &options,
+                NoProgress,
+            )
+            .unwrap();
+            bytes_out
+        };
+
+        assert_eq!(convert(false), golden);
+
+        // The same blocks, with the family id flag cleared and the file size in its place
+        let mut expected = golden.to_vec();
+        for block in expected.chunks_mut(UF2_BLOCK_SIZE) {
+            let flags = u32::from_le_bytes(block[8..12].try_into().unwrap());
+            block[8..12].copy_from_slice(&(flags & !uf2::UF2_FLAG_FAMILY_ID_PRESENT).to_le_bytes());
+            block[28..32].copy_from_slice(&(golden.len() as u32).to_le_bytes());
+        }
+        let omitted = convert(true);
+        assert_eq!(omitted, expected);
+
+        let first = Uf2Block::from_bytes(omitted[..UF2_BLOCK_SIZE].try_into().unwrap()).unwrap();
+        assert_eq!(first.family_id(), None);
+        assert_eq!(
+            first.file_size_field(),
+            uf2::FileSizeField::Unflagged(golden.len() as u32)
+        );
+
+        // Multi-family files keep them
+        let mut bytes_out = Vec::new();
+        elf2uf2_multi_with_options(
+            &[(bytes_in, boards::RP2040.family_id())],
+            &mut bytes_out,
+            &boards::RP2040,
+            &Uf2Options {
+                omit_family_id: true,
+                ..Default::default()
+            },
+            NoProgress,
+        )
+        .unwrap();
+        assert_eq!(bytes_out, golden);
+    }
+
+    #[test]
+    pub fn block_iterator_streams_hello_usb() {
+        let bytes_in = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+        let expected = include_bytes!("../tests/rp2040/hello_usb.uf2");
+
+        let blocks = Uf2BlockIterator::new(Cursor::new(bytes_in), &boards::RP2040).unwrap();
+        assert_eq!(blocks.total_bytes(), expected.len());
+        assert_eq!(
+            blocks.size_hint(),
+            (
+                blocks.num_blocks() as usize,
+                Some(blocks.num_blocks() as usize)
+            )
+        );
+
+        let bytes_out: Vec<u8> = blocks.flat_map(|block| block.unwrap()).collect();
+        assert_eq!(bytes_out, expected);
+    }
+
+    #[test]
+    pub fn overlapping_segments_error() {
+        let elf = TestElf::new(vec![
+            TestSegment::load(0x10000000, vec![0x11; 64]),
+            TestSegment::load(0x10000020, vec![0x22; 64]),
+        ])
+        .build();
+
+        let err = elf2uf2(Cursor::new(elf), Vec::new(), &boards::RP2040, NoProgress).unwrap_err();
+        assert!(matches!(
+            err,
+            Elf2Uf2Error::OverlappingSegments {
+                page_addr: 0x10000000,
+                first: (0, 64),
+                second: (0x20, 64),
+            }
+        ));
+    }
+
+    #[test]
+    pub fn identical_overlapping_segments_are_deduplicated() {
+        let elf = TestElf::new(vec![
+            TestSegment::load(0x10000000, vec![0x11; 64]),
+            TestSegment::load(0x10000000, vec![0x11; 64]),
+            TestSegment::load(0x10000020, vec![0x11; 64]),
+        ])
+        .build();
+
+        let file = ElfBytes::<AnyEndian>::minimal_parse(&elf).unwrap();
+        let pages = get_page_fragments(&file, 256).unwrap();
+        assert_eq!(pages[&0x10000000].len(), 2);
+
+        let mut bytes_out = Vec::new();
+        elf2uf2(
+            Cursor::new(&elf),
+            &mut bytes_out,
+            &boards::RP2040,
+            NoProgress,
+        )
+        .unwrap();
+        assert_eq!(&bytes_out[32..32 + 96], &[0x11; 96]);
+        assert_eq!(&bytes_out[32 + 96..32 + 256], &[0; 160]);
+    }
+
+    #[test]
+    pub fn big_endian_elf() {
+        let bytes_in = &include_bytes!("../tests/rp2040/big_endian.elf")[..];
+        let mut bytes_out = Vec::new();
+        elf2uf2(
+            Cursor::new(bytes_in),
+            &mut bytes_out,
+            &boards::RP2040,
+            NoProgress,
+        )
+        .unwrap();
+
+        // Segment contents are copied as they are, only the headers are big endian
+        let expected: Vec<u8> = (0..256u32).map(|i| (i * 7 % 251) as u8).collect();
+        assert_eq!(&bytes_out[32..32 + 256], &expected[..]);
+        assert_eq!(bytes_out, include_bytes!("../tests/rp2040/big_endian.uf2"));
+    }
+
+    #[test]
+    pub fn high_addresses_are_out_of_range() {
+        let bytes_in = &include_bytes!("../tests/riscv64/high_addresses.elf")[..];
+
+        let board = CustomBoardBuilder::new()
+            .family_id(boards::RP2350.family_id())
+            .build()
+            .unwrap();
+        for board in [&boards::RP2350 as &dyn BoardInfo, &board] {
+            let err = elf2uf2(Cursor::new(bytes_in), Vec::new(), board, NoProgress).unwrap_err();
+            assert!(matches!(err, Elf2Uf2Error::AddressOutOfRange(0x2000000000)));
+        }
+    }
+
+    #[test]
+    pub fn segment_end_overflow_errors() {
+        let elf = TestElf {
+            class64: true,
+            ..TestElf::new(vec![TestSegment::load(u64::MAX - 0x3f, vec![0x11; 0x80])])
+        }
+        .build();
+
+        let file = ElfBytes::<AnyEndian>::minimal_parse(&elf).unwrap();
+        assert!(matches!(
+            get_page_fragments(&file, 256),
+            Err(Elf2Uf2Error::AddressOutOfRange(0xffffffffffffffc0))
+        ));
+        let err = elf2uf2(Cursor::new(&elf), Vec::new(), &boards::RP2040, NoProgress).unwrap_err();
+        assert!(matches!(
+            err,
+            Elf2Uf2Error::AddressOutOfRange(0xffffffffffffffc0)
+        ));
+    }
+
+    #[test]
+    pub fn settings_sector_pages() {
+        let settings: Vec<u8> = b"elf2flash settings v1\0"
+            .iter()
+            .copied()
+            .cycle()
+            .take(512)
+            .collect();
+
+        let mut bytes_out = Vec::new();
+        let summary = pages_to_uf2(
+            0x101ff800,
+            &settings,
+            &mut bytes_out,
+            &boards::RP2040,
+            &Uf2Options::default(),
+            NoProgress,
+        )
+        .unwrap();
+
+        // The pages before the settings in their sector are filled, the ones after aren't
+        assert_eq!((summary.num_blocks, summary.filler_blocks), (10, 8));
+        assert_eq!(
+            bytes_out,
+            include_bytes!("../tests/rp2040/settings_sector.uf2")
+        );
+    }
+
+    #[test]
+    pub fn pages_to_uf2_checks_the_board() {
+        let write = |address, data: &[u8]| {
+            pages_to_uf2(
+                address,
+                data,
+                Vec::new(),
+                &boards::RP2040,
+                &Uf2Options::default(),
+                NoProgress,
+            )
+        };
+
+        assert!(matches!(
+            write(0x30000000, &[0x11; 16]),
+            Err(Elf2Uf2Error::AddressRangesError(
+                AddressRangesFromElfError::MemorySegmentInvalidForDevice(0x30000000, 0x30000010)
+            ))
+        ));
+        assert!(matches!(
+            write(0x101ff000, &[]),
+            Err(Elf2Uf2Error::InputFileNoMemoryPagesError)
+        ));
+        assert!(matches!(
+            write(0x1_0000_0000, &[0x11; 16]),
+            Err(Elf2Uf2Error::AddressOutOfRange(0x1_0000_0000))
+        ));
+        assert!(write(0x20000000, &[0x11; 16]).unwrap().not_main_flash);
+    }
+
+    #[test]
+    pub fn pages_to_uf2_unaligned_data() {
+        let mut bytes_out = Vec::new();
+        pages_to_uf2(
+            0x10000010,
+            &[0x11; 0x100],
+            &mut bytes_out,
+            &boards::RP2040,
+            &Uf2Options {
+                block_padding_byte: 0xff,
+                ..Default::default()
+            },
+            NoProgress,
+        )
+        .unwrap();
+
+        let blocks: Vec<_> = bytes_out.chunks_exact(UF2_BLOCK_SIZE).collect();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(&blocks[0][32..32 + 0x10], &[0xff; 0x10]);
+        assert_eq!(&blocks[0][32 + 0x10..32 + 0x100], &[0x11; 0xf0]);
+        assert_eq!(&blocks[1][32..32 + 0x10], &[0x11; 0x10]);
+        assert_eq!(&blocks[1][32 + 0x10..32 + 0x100], &[0xff; 0xf0]);
+    }
+
+    #[test]
+    pub fn segment_outside_board_memory_errors() {
+        let elf = TestElf::new(vec![TestSegment::load(0x30000000, vec![0x11; 64])]).build();
+
+        let err = elf2uf2(Cursor::new(&elf), Vec::new(), &boards::RP2040, NoProgress).unwrap_err();
+        assert!(matches!(
+            err,
+            Elf2Uf2Error::AddressRangesError(
+                AddressRangesFromElfError::MemorySegmentInvalidForDevice(0x30000000, 0x30000040)
+            )
+        ));
+
+        // Custom boards only check the ranges they were given
+        let board = CustomBoardBuilder::new()
+            .family_id(boards::RP2040.family_id())
+            .build()
+            .unwrap();
+        elf2uf2(Cursor::new(&elf), Vec::new(), &board, NoProgress).unwrap();
+
+        let board = CustomBoardBuilder::new()
+            .family_id(boards::RP2040.family_id())
+            .address_ranges(boards::RP2040.valid_address_ranges())
+            .build()
+            .unwrap();
+        assert!(elf2uf2(Cursor::new(&elf), Vec::new(), &board, NoProgress).is_err());
+    }
+
+    #[test]
+    pub fn ram_only() {
+        let bytes_in = &include_bytes!("../tests/rp2040/ram_only.elf")[..];
+        let mut bytes_out = Vec::new();
+        elf2uf2(
+            Cursor::new(bytes_in),
+            &mut bytes_out,
+            &boards::RP2040,
+            NoProgress,
+        )
+        .unwrap();
+
+        assert_eq!(bytes_out, include_bytes!("../tests/rp2040/ram_only.uf2"));
+    }
+
+    #[test]
+    pub fn size_matches_conversion() {
+        let cases: [(&[u8], &dyn BoardInfo); 3] = [
+            (
+                include_bytes!("../tests/rp2040/hello_usb.elf"),
+                &boards::RP2040,
+            ),
+            (
+                include_bytes!("../tests/rp2040/ram_only.elf"),
+                &boards::RP2040,
+            ),
+            (
+                include_bytes!("../tests/rp2350/flash_image.elf"),
+                &boards::RP2350,
+            ),
+        ];
+        let filled = Uf2Options {
+            fill_sectors: true,
+            ..Default::default()
+        };
+        for (elf, board) in cases {
+            for options in [&Uf2Options::default(), &filled] {
+                let mut uf2 = Vec::new();
+                let summary =
+                    elf2uf2_with_options(Cursor::new(elf), &mut uf2, board, options, NoProgress)
+                        .unwrap();
+                let size = elf2uf2_size_with_options(Cursor::new(elf), board, options).unwrap();
+                assert_eq!(size.num_blocks, summary.num_blocks);
+                assert_eq!(size.total_bytes, uf2.len() as u64);
+            }
+        }
+    }
+
+    #[test]
+    pub fn only_uninitialized_segments() {
+        let convert =
+            |elf: &[u8]| elf2uf2(Cursor::new(elf), Vec::new(), &boards::RP2040, NoProgress);
+
+        let err = convert(include_bytes!("../tests/rp2040/bss_only.elf")).unwrap_err();
+        assert!(matches!(
+            &err,
+            Elf2Uf2Error::OnlyUninitializedSegments { segments } if segments == &[(0x20000000, 0x400)]
+        ));
+
+        // A flash segment that lost its contents, e.g. linked without a text section
+        let err = convert(include_bytes!("../tests/rp2040/flash_no_contents.elf")).unwrap_err();
+        assert!(matches!(
+            &err,
+            Elf2Uf2Error::OnlyUninitializedSegments { segments }
+                if segments == &[(0x10000000, 0x1000), (0x20000000, 0x400)]
+        ));
+        assert_eq!(
+            err.to_string(),
+            "The input file has no contents to write, its loadable segments are all \
+             uninitialized: 0x10000000 (4096 bytes), 0x20000000 (1024 bytes)"
+        );
+
+        // A zero-length segment doesn't even reserve memory, there is nothing to list
+        assert!(matches!(
+            convert(&TestElf::new(vec![TestSegment::load(0x10000000, [])]).build()),
+            Err(Elf2Uf2Error::InputFileNoMemoryPagesError)
+        ));
+    }
+
+    #[test]
+    pub fn forced_not_main_flash() {
+        let bytes_in = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+        let options = Uf2Options {
+            not_main_flash: true,
+            ..Default::default()
+        };
+
+        let blocks =
+            Uf2BlockIterator::with_options(Cursor::new(bytes_in), &boards::RP2040, &options)
+                .unwrap();
+        for block in blocks {
+            let flags = u32::from_le_bytes(block.unwrap()[8..12].try_into().unwrap());
+            assert_eq!(flags, UF2_FLAG_FAMILY_ID_PRESENT | UF2_FLAG_NOT_MAIN_FLASH);
+        }
+    }
+
+    #[test]
+    pub fn elf2uf2_rs_compat() {
+        let options = Uf2Options::elf2uf2_rs_compat();
+        let convert = |elf: &[u8]| {
+            let mut uf2 = Vec::new();
+            elf2uf2_with_options(
+                Cursor::new(elf),
+                &mut uf2,
+                &boards::RP2040,
+                &options,
+                NoProgress,
+            )
+            .unwrap();
+            uf2
+        };
+
+        // Converted by elf2uf2-rs, checked against it by `elf2uf2_rs_goldens`
+        assert_eq!(
+            convert(include_bytes!("../tests/rp2040/hello_usb.elf")),
+            include_bytes!("../tests/rp2040/hello_usb.uf2")
+        );
+        assert_eq!(
+            convert(include_bytes!("../tests/rp2040/hello_serial.elf")),
+            include_bytes!("../tests/rp2040/hello_serial.uf2")
+        );
+
+        // Laid out like before, only without the not main flash flag
+        let ram_only = convert(include_bytes!("../tests/rp2040/ram_only.elf"));
+        let flagged = include_bytes!("../tests/rp2040/ram_only.uf2");
+        assert_eq!(ram_only.len(), flagged.len());
+        for (block, flagged) in ram_only
+            .chunks(UF2_BLOCK_SIZE)
+            .zip(flagged.chunks(UF2_BLOCK_SIZE))
+        {
+            let flags = u32::from_le_bytes(block[8..12].try_into().unwrap());
+            assert_eq!(flags, UF2_FLAG_FAMILY_ID_PRESENT);
+            assert_eq!(block[12..], flagged[12..]);
+        }
+    }
+
+    /// Converts the ELFs of [`elf2uf2_rs_compat`] with the `elf2uf2-rs` on the `PATH` and checks
+    /// it still writes the goldens, or with `ELF2FLASH_BLESS=1` writes them again. Run it with
+    /// `cargo test -p elf2flash-core elf2uf2_rs_goldens -- --ignored`.
+    #[test]
+    #[ignore = "needs elf2uf2-rs on the PATH"]
+    pub fn elf2uf2_rs_goldens() {
+        let tests = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/rp2040");
+        let out_dir = std::env::temp_dir().join(format!("elf2uf2-rs-{}", std::process::id()));
+        std::fs::create_dir_all(&out_dir).unwrap();
+        for name in ["hello_usb", "hello_serial"] {
+            let golden = tests.join(format!("{name}.uf2"));
+            let output = out_dir.join(format!("{name}.uf2"));
+            let status = std::process::Command::new("elf2uf2-rs")
+                .arg(tests.join(format!("{name}.elf")))
+                .arg(&output)
+                .status()
+                .expect("elf2uf2-rs isn't on the PATH");
+            assert!(status.success());
+
+            let converted = std::fs::read(&output).unwrap();
+            if std::env::var_os("ELF2FLASH_BLESS").is_some() {
+                std::fs::write(&golden, &converted).unwrap();
+            } else {
+                assert!(
+                    converted == std::fs::read(&golden).unwrap(),
+                    "elf2uf2-rs converts {name}.elf differently from the golden"
+                );
+            }
+        }
+        std::fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    pub fn filler_inflation_warning() {
+        let elf = TestElf::new(vec![
+            TestSegment::load(0x10000000, vec![0x11; 16]),
+            TestSegment::load(0x10001000, vec![0x22; 16]),
+        ])
+        .build();
+
+        let summary = elf2uf2_with_options(
+            Cursor::new(&elf),
+            Vec::new(),
+            &boards::RP2040,
+            &Uf2Options::default(),
+            NoProgress,
+        )
+        .unwrap();
+        assert_eq!(summary.num_blocks, 17);
+        assert_eq!(summary.filler_blocks, 15);
+        assert!(summary.warnings.contains(WarningCode::FillerInflation));
+
+        let bytes_in = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+        let blocks = Uf2BlockIterator::new(Cursor::new(bytes_in), &boards::RP2040).unwrap();
+        assert!(blocks.summary().warnings.is_empty());
+    }
+
+    #[test]
+    pub fn architecture_mismatch_warning() {
+        let convert = |machine, board: &dyn BoardInfo| {
+            let elf = TestElf {
+                machine,
+                ..TestElf::new(vec![TestSegment::load(0x10000000, vec![0x11; 256])])
+            }
+            .build();
+            elf2uf2_with_options(
+                Cursor::new(&elf),
+                Vec::new(),
+                board,
+                &Uf2Options::default(),
+                NoProgress,
+            )
+            .unwrap()
+            .warnings
+        };
+
+        let warnings = convert(EM_RISCV, &boards::RP2350);
+        assert!(warnings.contains(WarningCode::ArchitectureMismatch));
+        assert_eq!(
+            warnings.iter().next().unwrap().message,
+            "The ELF is a RISC-V program, but family id 0xe48bff59 (RP2350_ARM_S) is for Arm \
+             programs, the board may be the wrong variant (e.g. rp2350 or rp2350-riscv)"
+        );
+        assert!(convert(EM_ARM, &boards::RP2350RiscV).contains(WarningCode::ArchitectureMismatch));
+        assert!(convert(EM_ARM, &boards::RP2350ArmNs).is_empty());
+        assert!(convert(EM_RISCV, &boards::RP2350RiscV).is_empty());
+        // The RP2040 only has Arm cores, there is no other variant to pick
+        assert!(convert(EM_RISCV, &boards::RP2040).is_empty());
+    }
+
+    #[test]
+    pub fn sparse_without_sector_fill() {
+        // A bootloader and an application half a megabyte apart
+        let elf = TestElf::new(vec![
+            TestSegment::load(0x10000000, vec![0x11; 300]),
+            TestSegment::load(0x10080000, vec![0x22; 16]),
+        ])
+        .build();
+        let convert = |fill_sectors| {
+            Uf2BlockIterator::with_options(
+                Cursor::new(&elf),
+                &boards::RP2040,
+                &Uf2Options {
+                    fill_sectors,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        let filled = convert(true);
+        assert_eq!(filled.num_blocks(), 17);
+        assert_eq!(filled.summary().filler_blocks, 14);
+
+        let sparse = convert(false);
+        assert_eq!(sparse.num_blocks(), 3);
+        assert_eq!(sparse.summary().filler_blocks, 0);
+        let addrs: Vec<u32> = sparse
+            .map(|block| u32::from_le_bytes(block.unwrap()[12..16].try_into().unwrap()))
+            .collect();
+        assert_eq!(addrs, [0x10000000, 0x10000100, 0x10080000]);
+    }
+
+    #[test]
+    pub fn pad_final_sector() {
+        // Ends 0x500 bytes into its second flash sector
+        let elf = TestElf::new(vec![TestSegment::load(0x10000000, vec![0x11; 0x1500])]).build();
+        let convert = |pad_final_sector| {
+            Uf2BlockIterator::with_options(
+                Cursor::new(&elf),
+                &boards::RP2040,
+                &Uf2Options {
+                    pad_final_sector,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        assert_eq!(convert(false).num_blocks(), 21);
+
+        let padded = convert(true);
+        assert_eq!(padded.num_blocks(), 32);
+        assert_eq!(padded.summary().filler_blocks, 11);
+        let addrs: Vec<u32> = padded
+            .map(|block| u32::from_le_bytes(block.unwrap()[12..16].try_into().unwrap()))
+            .collect();
+        assert_eq!(addrs.last(), Some(&0x10001f00));
+
+        // Without filling sectors nothing is padded
+        let sparse = Uf2BlockIterator::with_options(
+            Cursor::new(&elf),
+            &boards::RP2040,
+            &Uf2Options {
+                fill_sectors: false,
+                pad_final_sector: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(sparse.num_blocks(), 21);
+    }
+
+    #[test]
+    pub fn address_offset() {
+        let elf = TestElf::new(vec![TestSegment::load(0x10000000, vec![0x11; 0x300])]).build();
+        let convert = |address_offset| {
+            Uf2BlockIterator::with_options(
+                Cursor::new(&elf),
+                &boards::RP2040,
+                &Uf2Options {
+                    address_offset,
+                    ..Default::default()
+                },
+            )
+        };
+        let target_addrs = |blocks: Uf2BlockIterator<_>| -> Vec<u32> {
+            blocks
+                .map(|block| u32::from_le_bytes(block.unwrap()[12..16].try_into().unwrap()))
+                .collect()
+        };
+
+        let addrs = target_addrs(convert(0x8000).unwrap());
+        assert_eq!(addrs, [0x10008000, 0x10008100, 0x10008200]);
+
+        // Half a sector in, the sector is filled from its start at the moved address
+        let addrs = target_addrs(convert(0x800).unwrap());
+        assert_eq!(addrs.len(), 11);
+        assert_eq!(addrs[0], 0x10000000);
+        assert_eq!(&addrs[8..], [0x10000800, 0x10000900, 0x10000a00]);
+
+        // Moved out of the flash
+        assert!(matches!(
+            convert(-0x100),
+            Err(Elf2Uf2Error::AddressRangesError(
+                AddressRangesFromElfError::MemorySegmentInvalidForDevice(..)
+            ))
+        ));
+
+        assert!(matches!(
+            convert(0x80),
+            Err(Elf2Uf2Error::UnalignedAddressOffset {
+                offset: 0x80,
+                page_size: 256
+            })
+        ));
+        assert!(matches!(
+            convert(-0x20000000),
+            Err(Elf2Uf2Error::AddressOffsetOverflow {
+                page_addr: 0x10000000,
+                offset: -0x20000000
+            })
+        ));
+        assert!(matches!(
+            convert(0x100000000),
+            Err(Elf2Uf2Error::AddressOutOfRange(0x110000200))
+        ));
+    }
+
+    #[test]
+    pub fn exclude_ranges() {
+        let elf = TestElf::new(vec![
+            TestSegment::load(0x10000000, vec![0x11; 0x300]),
+            TestSegment::load(0x10080000, vec![0x22; 16]),
+        ])
+        .build();
+        let convert = |excluded: Option<Range<u64>>| {
+            Uf2BlockIterator::with_options(
+                Cursor::new(&elf),
+                &boards::RP2040,
+                &Uf2Options {
+                    exclude_ranges: excluded.into_iter().collect(),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        assert_eq!(convert(None).num_blocks(), 17);
+        assert_eq!(convert(Some(0x10080000..0x10081000)).num_blocks(), 3);
+
+        // An excluded page isn't brought back to fill its sector
+        let blocks = convert(Some(0x10000100..0x10000200));
+        assert_eq!(blocks.num_blocks(), 16);
+        let addrs: Vec<u32> = blocks
+            .map(|block| u32::from_le_bytes(block.unwrap()[12..16].try_into().unwrap()))
+            .collect();
+        assert!(!addrs.contains(&0x10000100));
+        assert!(addrs.contains(&0x10080000));
+    }
+
+    #[test]
+    pub fn exclude_range_splits_pages() {
+        let elf = TestElf::new(vec![TestSegment::load(0x10000000, vec![0x11; 0x100])]).build();
+
+        let blocks: Vec<_> = Uf2BlockIterator::with_options(
+            Cursor::new(&elf),
+            &boards::RP2040,
+            &Uf2Options {
+                exclude_ranges: vec![0x10000010..0x10000020, 0x10000040..0x10000050],
+                fill_sectors: false,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .map(|block| block.unwrap())
+        .collect();
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(&blocks[0][32..48], &[0x11; 16]);
+        assert_eq!(&blocks[0][48..64], &[0; 16]);
+        assert_eq!(&blocks[0][64..96], &[0x11; 32]);
+        assert_eq!(&blocks[0][96..112], &[0; 16]);
+        assert_eq!(&blocks[0][112..32 + 256], &[0x11; 0xb0]);
+    }
+
+    #[test]
+    pub fn exclude_sections() {
+        let bytes_in = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+        let convert = |exclude_sections: &[&str]| {
+            Uf2BlockIterator::with_options(
+                Cursor::new(bytes_in),
+                &boards::RP2040,
+                &Uf2Options {
+                    exclude_sections: exclude_sections.iter().map(|s| s.to_string()).collect(),
+                    ..Default::default()
+                },
+            )
+        };
+
+        assert_eq!(convert(&[]).unwrap().num_blocks(), 89);
+
+        // .data runs at 0x200000c0, but is loaded from flash right after .binary_info
+        let blocks: Vec<_> = convert(&[".data"])
+            .unwrap()
+            .map(|block| block.unwrap())
+            .collect();
+        assert_eq!(blocks.len(), 79);
+        let last_page = blocks
+            .iter()
+            .find(|block| u32::from_le_bytes(block[12..16].try_into().unwrap()) == 0x10004e00)
+            .unwrap();
+        assert_eq!(&last_page[32 + 0x1c..32 + 256], &[0; 0xe4]);
+
+        assert!(matches!(
+            convert(&[".fs"]),
+            Err(Elf2Uf2Error::UnknownSection(name)) if name == ".fs"
+        ));
+    }
+
+    #[test]
+    pub fn cancelled_between_blocks() {
+        let bytes_in = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+        let golden = &include_bytes!("../tests/rp2040/hello_usb.uf2")[..];
+        let reporter = || RecordingReporter::cancel_after(3 * UF2_BLOCK_SIZE);
+
+        let mut bytes_out = Vec::new();
+        let result = elf2uf2_with_options(
+            Cursor::new(bytes_in),
+            &mut bytes_out,
+            &boards::RP2040,
+            &Uf2Options::default(),
+            reporter(),
+        );
+        assert!(matches!(result, Err(Elf2Uf2Error::Cancelled)));
+        assert_eq!(bytes_out, golden[..3 * UF2_BLOCK_SIZE]);
+
+        let mut bytes_out = Vec::new();
+        let result = elf2uf2_multi_with_options(
+            &[(bytes_in, boards::RP2040.family_id())],
+            &mut bytes_out,
+            &boards::RP2040,
+            &Uf2Options::default(),
+            reporter(),
+        );
+        assert!(matches!(result, Err(Elf2Uf2Error::Cancelled)));
+        assert_eq!(bytes_out.len(), 3 * UF2_BLOCK_SIZE);
+    }
+
+    /// The phase is reported first, then progress starts and finishes
+    fn assert_phase_before_start(recorder: &RecordingReporter) {
+        let calls: Vec<_> = recorder
+            .calls
+            .iter()
+            .filter(|call| {
+                matches!(
+                    call,
+                    ProgressCall::Phase(_) | ProgressCall::Start(_) | ProgressCall::Finish
+                )
+            })
+            .collect();
+        assert!(
+            matches!(
+                calls[..],
+                [
+                    ProgressCall::Phase(ProgressPhase::Converting),
+                    ProgressCall::Start(_),
+                    ProgressCall::Finish
+                ]
+            ),
+            "{calls:?}"
+        );
+    }
+
+    #[test]
+    pub fn phase_is_reported_before_start() {
+        let bytes_in = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+
+        let mut recorder = RecordingReporter::default();
+        elf2uf2_with_options(
+            Cursor::new(bytes_in),
+            &mut Vec::new(),
+            &boards::RP2040,
+            &Uf2Options::default(),
+            &mut recorder,
+        )
+        .unwrap();
+        assert_phase_before_start(&recorder);
+
+        let mut recorder = RecordingReporter::default();
+        elf2uf2_multi_with_options(
+            &[(bytes_in, boards::RP2040.family_id())],
+            &mut Vec::new(),
+            &boards::RP2040,
+            &Uf2Options::default(),
+            &mut recorder,
+        )
+        .unwrap();
+        assert_phase_before_start(&recorder);
+    }
+
+    #[test]
+    pub fn detail_is_reported_for_every_block() {
+        let bytes_in = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+        let golden = &include_bytes!("../tests/rp2040/hello_usb.uf2")[..];
+        let expected: Vec<ProgressDetail> = golden
+            .chunks(UF2_BLOCK_SIZE)
+            .enumerate()
+            .map(|(index, block)| ProgressDetail {
+                phase: ProgressPhase::Converting,
+                block_no: index as u32,
+                target_addr: u32::from_le_bytes(block[12..16].try_into().unwrap()),
+                file_offset: (index * UF2_BLOCK_SIZE) as u64,
+            })
+            .collect();
+        assert_eq!(expected.len(), 89);
+        assert_eq!(expected[1].target_addr, 0x10000100);
+
+        let mut recorder = RecordingReporter::default();
+        elf2uf2(
+            Cursor::new(bytes_in),
+            &mut Vec::new(),
+            &boards::RP2040,
+            &mut recorder,
+        )
+        .unwrap();
+        assert_eq!(recorder.details(), expected);
+
+        let mut recorder = RecordingReporter::default();
+        elf2uf2_multi(
+            &[(bytes_in, boards::RP2040.family_id())],
+            &mut Vec::new(),
+            &boards::RP2040,
+            &mut recorder,
+        )
+        .unwrap();
+        assert_eq!(recorder.details(), expected);
+    }
+
+    #[test]
+    pub fn events_are_reported() {
+        use crate::events::IgnoreReason;
+
+        // A .bss has no pages, and the excluded page isn't filled back in
+        let elf = TestElf::new(vec![
+            TestSegment::load(0x10000000, vec![0x11; 0x300]),
+            TestSegment {
+                memsz: 0x400,
+                ..TestSegment::load(0x20000000, [])
+            },
+        ])
+        .build();
+        let options = Uf2Options {
+            exclude_ranges: iter::once(0x10000100..0x10000200).collect(),
+            ..Default::default()
+        };
+        let mut recorder = RecordingReporter::default();
+        let summary = elf2uf2_with_options(
+            Cursor::new(&elf),
+            &mut Vec::new(),
+            &boards::RP2040,
+            &options,
+            &mut recorder,
+        )
+        .unwrap();
+        let events = recorder.events();
+        assert_eq!(
+            events,
+            [
+                ConversionEvent::SegmentIgnored {
+                    addr: 0x20000000,
+                    size: 0x400,
+                    reason: IgnoreReason::Uninitialized,
+                },
+                ConversionEvent::PageExcluded { addr: 0x10000100 },
+            ]
+        );
+        assert_eq!(summary.events, events);
+
+        let mut recorder = RecordingReporter::default();
+        pages_to_uf2(
+            0x10000200,
+            &[0x5a; 256],
+            &mut Vec::new(),
+            &boards::RP2040,
+            &Uf2Options::default(),
+            &mut recorder,
+        )
+        .unwrap();
+        let events = recorder.events();
+        assert_eq!(
+            events,
+            [
+                ConversionEvent::PaddingPageInserted { addr: 0x10000000 },
+                ConversionEvent::PaddingPageInserted { addr: 0x10000100 },
+            ]
+        );
+
+        let mut recorder = RecordingReporter::default();
+        elf2uf2_multi(
+            &[(&elf[..], 0x12345678)],
+            &mut Vec::new(),
+            &boards::RP2040,
+            &mut recorder,
+        )
+        .unwrap();
+        let events = recorder.events();
+        assert_eq!(
+            events[0],
+            ConversionEvent::FamilyOverride {
+                from: boards::RP2040.family_id(),
+                to: 0x12345678,
+            }
+        );
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    pub fn fixes_zeroed_boot2_checksum() {
+        let golden = &include_bytes!("../tests/rp2040/hello_usb.uf2")[..];
+        let mut elf = include_bytes!("../tests/rp2040/hello_usb.elf").to_vec();
+
+        // Zero the checksum of the boot2 linked into the elf
+        let boot2 = &golden[32..32 + 256];
+        let offset = elf
+            .windows(boot2.len())
+            .position(|window| window == boot2)
+            .expect("the elf contains the boot2 of the golden uf2");
+        elf[offset + 252..offset + 256].fill(0);
+
+        let convert = |fix_rp2040_boot2| {
+            let mut bytes_out = Vec::new();
+            let options = Uf2Options {
+                fix_rp2040_boot2,
+                ..Default::default()
+            };
+            elf2uf2_with_options(
+                Cursor::new(&elf),
+                &mut bytes_out,
+                &boards::RP2040,
+                &options,
+                NoProgress,
+            )
+            .unwrap();
+            bytes_out
+        };
+
+        assert_ne!(convert(false), golden);
+        assert_eq!(convert(true), golden);
+
+        // A valid checksum is left as it is
+        let mut bytes_out = Vec::new();
+        elf2uf2_with_options(
+            Cursor::new(&include_bytes!("../tests/rp2040/hello_usb.elf")[..]),
+            &mut bytes_out,
+            &boards::RP2040,
+            &Uf2Options {
+                fix_rp2040_boot2: true,
+                ..Default::default()
+            },
+            NoProgress,
+        )
+        .unwrap();
+        assert_eq!(bytes_out, golden);
+    }
+
+    #[test]
+    pub fn block_padding_byte() {
+        let elf = TestElf::new(vec![
+            TestSegment::load(0x10000010, vec![0x11; 16]),
+            TestSegment::load(0x10001000, vec![0x22; 16]),
+        ])
+        .build();
+        let convert = |block_padding_byte| {
+            let options = Uf2Options {
+                block_padding_byte,
+                ..Default::default()
+            };
+            Uf2BlockIterator::with_options(Cursor::new(&elf), &boards::RP2040, &options)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+        let zeroed = convert(0);
+        let padded = convert(0xff);
+        assert_eq!(padded.len(), 17);
+
+        let mut first_page = [0xff; 256];
+        first_page[0x10..0x20].fill(0x11);
+        let mut last_page = [0xff; 256];
+        last_page[..0x10].fill(0x22);
+        for (i, (zeroed, padded)) in zeroed.iter().zip(&padded).enumerate() {
+            // Headers and the bytes past the page are left alone
+            assert_eq!(zeroed[..32], padded[..32]);
+            assert_eq!(zeroed[32 + 256..], padded[32 + 256..]);
+            assert!(padded[32 + 256..UF2_BLOCK_SIZE - 4].iter().all(|&b| b == 0));
+
+            let page = &padded[32..32 + 256];
+            match i {
+                0 => assert_eq!(page, first_page),
+                16 => assert_eq!(page, last_page),
+                _ => assert!(page.iter().all(|&b| b == 0xff)),
+            }
+        }
+    }
+
+    #[test]
+    pub fn extension_tags_in_final_block() {
+        let bytes_in = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+        let options = Uf2Options {
+            extension_tags: vec![
+                ExtensionTag::FirmwareVersion("1.0.0".to_string()),
+                ExtensionTag::Md5Checksum,
+            ],
+            ..Default::default()
+        };
+        let blocks: Vec<_> =
+            Uf2BlockIterator::with_options(Cursor::new(bytes_in), &boards::RP2040, &options)
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+        let plain: Vec<u8> = include_bytes!("../tests/rp2040/hello_usb.uf2").to_vec();
+
+        let (last, rest) = blocks.split_last().unwrap();
+        assert_eq!(rest.concat(), plain[..plain.len() - UF2_BLOCK_SIZE]);
+        assert_eq!(
+            extension::parse_extension_tags(last).unwrap(),
+            [
+                ExtensionTag::Md5Checksum,
+                ExtensionTag::FirmwareVersion("1.0.0".to_string())
+            ]
+        );
+
+        let mut md5 = md5::Context::new();
+        for block in &blocks {
+            md5.consume(&block[32..32 + 256]);
+        }
+        let area = Md5Area::read(last).unwrap();
+        assert_eq!(area.address, 0x10000000);
+        assert_eq!(area.length, blocks.len() as u32 * 256);
+        assert_eq!(area.md5, md5.finalize().0);
+
+        let options = Uf2Options {
+            extension_tags: vec![ExtensionTag::Description("x".repeat(251))],
+            ..Default::default()
+        };
+        assert!(matches!(
+            Uf2BlockIterator::with_options(Cursor::new(bytes_in), &boards::RP2040, &options),
+            Err(Elf2Uf2Error::ExtensionTagError(
+                ExtensionTagError::Overflow { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    pub fn md5_area_per_contiguous_range() {
+        let elf = TestElf::new(vec![
+            TestSegment::load(0x10000000, vec![0x11; 16]),
+            TestSegment::load(0x10002000, vec![0x22; 16]),
+        ])
+        .build();
+        let options = Uf2Options {
+            extension_tags: vec![ExtensionTag::Md5Checksum],
+            ..Default::default()
+        };
+        let blocks: Vec<_> =
+            Uf2BlockIterator::with_options(Cursor::new(&elf), &boards::RP2040, &options)
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+        // The first flash sector is filled, the final one isn't
+        assert_eq!(blocks.len(), 17);
+
+        let areas: Vec<_> = blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, block)| Some((index, Md5Area::read(block)?)))
+            .collect();
+        assert_eq!(areas.len(), 2);
+        for ((index, area), (range, address)) in areas
+            .iter()
+            .zip([(0..16, 0x10000000), (16..17, 0x10002000)])
+        {
+            assert_eq!(*index, range.end - 1);
+            assert_eq!(area.length, range.len() as u32 * 256);
+            let mut md5 = md5::Context::new();
+            for block in &blocks[range] {
+                md5.consume(&block[32..32 + 256]);
+            }
+            assert_eq!(area.address, address);
+            assert_eq!(area.md5, md5.finalize().0);
+        }
+    }
+
+    #[test]
+    pub fn rp2350_absolute_block() {
+        let bytes_in = &include_bytes!("../tests/rp2350/flash_image.elf")[..];
+        let mut bytes_out = Vec::new();
+        let summary = elf2uf2_with_options(
+            Cursor::new(bytes_in),
+            &mut bytes_out,
+            &boards::RP2350,
+            &Uf2Options::default(),
+            NoProgress,
+        )
+        .unwrap();
+
+        assert_eq!(bytes_out, include_bytes!("../tests/rp2350/flash_image.uf2"));
+        assert_eq!(summary.num_blocks, 4);
+
+        let header = |block: &[u8]| {
+            let field = |i: usize| u32::from_le_bytes(block[i * 4..i * 4 + 4].try_into().unwrap());
+            // target address, block number, number of blocks, family id
+            (field(3), field(5), field(6), field(7))
+        };
+        let blocks: Vec<&[u8]> = bytes_out.chunks(UF2_BLOCK_SIZE).collect();
+        assert_eq!(header(blocks[0]), (0x10ffff00, 0, 2, 0xe48bff57));
+        assert_eq!(header(blocks[1]), (0x10000000, 0, 3, 0xe48bff59));
+        assert_eq!(header(blocks[3]), (0x10000200, 2, 3, 0xe48bff59));
+
+        // Custom boards only get a preamble if the builder is given one
+        let board = CustomBoardBuilder::new()
+            .family_id(boards::RP2350.family_id())
+            .build()
+            .unwrap();
+        let blocks = Uf2BlockIterator::new(Cursor::new(bytes_in), &board).unwrap();
+        assert_eq!(blocks.num_blocks(), 3);
+    }
+
+    #[test]
+    pub fn sections_without_program_headers() {
+        // flash_image.elf with its program header zeroed, and section headers for the image and
+        // a .bss in RAM
+        let bytes_in = &include_bytes!("../tests/rp2350/flash_image_no_phdrs.elf")[..];
+        let mut bytes_out = Vec::new();
+        elf2uf2(
+            Cursor::new(bytes_in),
+            &mut bytes_out,
+            &boards::RP2350,
+            NoProgress,
+        )
+        .unwrap();
+        assert_eq!(bytes_out, include_bytes!("../tests/rp2350/flash_image.uf2"));
+
+        let file = ElfBytes::<AnyEndian>::minimal_parse(bytes_in).unwrap();
+        let ranges = address_range::address_ranges_from_elf(&file).unwrap();
+        assert_eq!(
+            ranges
+                .iter()
+                .map(|range| (range.from, range.to, range.typ))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    0x10000000,
+                    0x10000258,
+                    address_range::AddressRangeType::Contents
+                ),
+                (
+                    0x20000000,
+                    0x20000100,
+                    address_range::AddressRangeType::NoContents
+                ),
+            ]
+        );
+        assert_eq!(get_page_fragments(&file, 256).unwrap().len(), 3);
+    }
+
+    /// A board with any page and erase size, unlike the ones the builder accepts
+    struct PageLayout(u32, u64);
+
+    impl BoardInfo for PageLayout {
+        fn is_device_board(&self, _device: &UsbDevice) -> bool {
+            false
+        }
+
+        fn family_id(&self) -> u32 {
+            boards::RP2040.family_id()
+        }
+
+        fn page_size(&self) -> u32 {
+            self.0
+        }
+
+        fn flash_sector_erase_size(&self) -> u64 {
+            self.1
+        }
+
+        fn board_name(&self) -> Cow<'static, str> {
+            "page_layout".into()
+        }
+    }
+
+    #[test]
+    pub fn invalid_page_sizes_are_rejected() {
+        let bytes_in = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+
+        for page_size in [0, 3, 500, 2 * MAX_SPLIT_PAGE_SIZE] {
+            let board = PageLayout(page_size, 4096);
+            assert!(matches!(
+                elf2uf2(Cursor::new(bytes_in), Vec::new(), &board, NoProgress),
+                Err(Elf2Uf2Error::InvalidPageSize(size)) if size == page_size
+            ));
+
+            let built = CustomBoardBuilder::new()
+                .family_id(0xe48bff56)
+                .page_size(page_size)
+                .build();
+            assert!(matches!(
+                built,
+                Err(CustomBoardBuildError::InvalidPageSize(size)) if size == page_size
+            ));
+        }
+
+        for erase_size in [0, 1000] {
+            let board = PageLayout(256, erase_size);
+            assert!(matches!(
+                elf2uf2(Cursor::new(bytes_in), Vec::new(), &board, NoProgress),
+                Err(Elf2Uf2Error::InvalidEraseSize { .. })
+            ));
+
+            let built = CustomBoardBuilder::new()
+                .family_id(0xe48bff56)
+                .flash_sector_erase_size(erase_size)
+                .build();
+            assert!(matches!(
+                built,
+                Err(CustomBoardBuildError::InvalidEraseSize { .. })
+            ));
+        }
+
+        let board = PageLayout(64, 4096);
+        elf2uf2(Cursor::new(bytes_in), Vec::new(), &board, NoProgress).unwrap();
+
+        // Pages that don't fit in a block need a smaller payload
+        let board = PageLayout(4096, 4096);
+        assert!(matches!(
+            elf2uf2(Cursor::new(bytes_in), Vec::new(), &board, NoProgress),
+            Err(Elf2Uf2Error::InvalidPayloadSize {
+                payload_size: 4096,
+                page_size: 4096
+            })
+        ));
+        for payload_size in [32, 100, 512] {
+            let options = Uf2Options {
+                payload_size: Some(payload_size),
+                ..Default::default()
+            };
+            assert!(matches!(
+                elf2uf2_with_options(
+                    Cursor::new(bytes_in),
+                    Vec::new(),
+                    &boards::RP2040,
+                    &options,
+                    NoProgress
+                ),
+                Err(Elf2Uf2Error::InvalidPayloadSize { .. })
+            ));
+        }
+    }
+
+    #[test]
+    pub fn pages_are_split_into_payload_sized_blocks() {
+        let data: Vec<u8> = (0..4096).map(|i| (i / 256) as u8).collect();
+        let options = Uf2Options {
+            payload_size: Some(256),
+            ..Default::default()
+        };
+
+        let mut bytes_out = Vec::new();
+        let summary = pages_to_uf2(
+            0x10000000,
+            &data,
+            &mut bytes_out,
+            PageLayout(4096, 4096),
+            &options,
+            NoProgress,
+        )
+        .unwrap();
+        assert_eq!(summary.num_blocks, 16);
+        assert_eq!(bytes_out.len(), 16 * UF2_BLOCK_SIZE);
+
+        for (index, block) in bytes_out.chunks_exact(UF2_BLOCK_SIZE).enumerate() {
+            let block = Uf2Block::from_bytes(block.try_into().unwrap()).unwrap();
+            assert_eq!(block.target_addr(), 0x10000000 + index as u32 * 256);
+            assert_eq!(block.block_no(), index as u32);
+            assert_eq!(block.num_blocks(), 16);
+            assert_eq!(block.payload_size(), 256);
+            assert_eq!(block.payload(), &data[index * 256..][..256]);
+        }
+
+        // A payload of the whole page is the default
+        let bytes_in = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+        let mut whole_pages = Vec::new();
+        elf2uf2_with_options(
+            Cursor::new(bytes_in),
+            &mut whole_pages,
+            &boards::RP2040,
+            &Uf2Options {
+                payload_size: Some(256),
+                ..Default::default()
+            },
+            NoProgress,
+        )
+        .unwrap();
+        assert_eq!(
+            whole_pages,
+            &include_bytes!("../tests/rp2040/hello_usb.uf2")[..]
+        );
+    }
+
+    #[test]
+    pub fn multi_family_output() {
+        const ARM: u32 = 0xe48bff59;
+        const RISCV: u32 = 0xe48bff5a;
+
+        let arm = TestElf::new(vec![TestSegment::load(0x10000000, vec![1; 600])]).build();
+        let riscv = TestElf::new(vec![TestSegment::load(0x10000000, vec![2; 300])]).build();
+        let extra = TestElf::new(vec![TestSegment::load(0x10010000, vec![3; 16])]).build();
+
+        let mut bytes_out = Vec::new();
+        let summary = elf2uf2_multi_with_options(
+            &[(&arm, ARM), (&riscv, RISCV), (&extra, ARM)],
+            &mut bytes_out,
+            &boards::RP2350,
+            &Uf2Options::default(),
+            NoProgress,
+        )
+        .unwrap();
+
+        let headers: Vec<_> = bytes_out
+            .chunks(UF2_BLOCK_SIZE)
+            .map(|block| {
+                let block = Uf2Block::from_bytes(block.try_into().unwrap()).unwrap();
+                (
+                    block.family_id().unwrap(),
+                    block.block_no(),
+                    block.num_blocks(),
+                )
+            })
+            .collect();
+
+        // The absolute block once, then each family numbered on its own
+        assert_eq!(
+            headers,
+            [
+                (uf2::UF2_ABSOLUTE_FAMILY_ID, 0, 2),
+                (ARM, 0, 4),
+                (ARM, 1, 4),
+                (ARM, 2, 4),
+                (RISCV, 0, 2),
+                (RISCV, 1, 2),
+                (ARM, 3, 4),
+            ]
+        );
+        assert_eq!(summary.num_blocks, 7);
+
+        let err = elf2uf2_multi(
+            &[(&arm, ARM), (&riscv, ARM)],
+            Vec::new(),
+            &boards::RP2350,
+            NoProgress,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            Elf2Uf2Error::OverlappingInputs {
+                family_id: ARM,
+                address: 0x10000000,
+                first: 0,
+                second: 1
+            }
+        ));
     }
 }
@@ -0,0 +1,499 @@
+//! The pages an ELF writes, for flashers that program them some other way than through a uf2 file,
+//! e.g. over SWD.
+//!
+//! [`PageMap::from_elf`] reads the contents of every page into memory. For large images the
+//! fragments from [`get_page_fragments`] say where the bytes of each page are in the file, so
+//! [`realize_page`] can read one page at a time.
+//!
+//! ```
+//! use elf2flash_core::pages::PageMap;
+//!
+//! let elf = include_bytes!("../tests/rp2040/hello_usb.elf");
+//! let map = PageMap::from_elf(elf, 256).unwrap();
+//!
+//! let (&first, boot2) = map.pages().first_key_value().unwrap();
+//! assert_eq!(first, 0x10000000);
+//! assert_eq!(boot2.len(), 256);
+//! ```
+
+use crate::{
+    Elf2Uf2Error,
+    address_range::{
+        self, AddressRange, AddressRangeType, AddressRangesFromElfError,
+        address_ranges_from_segments, elf_segments,
+    },
+    events::{ConversionEvent, IgnoreReason},
+};
+use assert_into::AssertInto;
+use elf::{
+    ElfBytes,
+    abi::PT_LOAD,
+    endian::{AnyEndian, EndianParse},
+    segment::ProgramHeader,
+};
+use log::debug;
+use std::{
+    cmp::min,
+    collections::BTreeMap,
+    io::{Cursor, Read, Seek, SeekFrom},
+    ops::Range,
+};
+
+/// A run of bytes of the ELF file that is loaded into a page.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PageFragment {
+    /// Where the bytes start in the ELF file
+    pub file_offset: u64,
+    /// Where the bytes start in the page
+    pub page_offset: u64,
+    /// How many bytes are loaded
+    pub bytes: u64,
+}
+
+/// Read the `fragments` of a page from the ELF `input` into `buf`, which must hold at least
+/// `page_size` bytes. The bytes no fragment covers are left as they are, so `buf` is filled with
+/// the padding first.
+///
+/// ```
+/// use std::io::Cursor;
+/// use elf::{ElfBytes, endian::AnyEndian};
+/// use elf2flash_core::pages::{get_page_fragments, realize_page};
+///
+/// let elf = include_bytes!("../tests/rp2040/hello_usb.elf");
+/// let file = ElfBytes::<AnyEndian>::minimal_parse(elf).unwrap();
+/// let fragments = get_page_fragments(&file, 256).unwrap();
+///
+/// let mut page = [0xff; 256];
+/// realize_page(&mut Cursor::new(elf), &fragments[&0x10000000], &mut page, 256).unwrap();
+/// ```
+pub fn realize_page(
+    input: &mut (impl Read + Seek),
+    fragments: &[PageFragment],
+    buf: &mut [u8],
+    page_size: u32,
+) -> Result<(), std::io::Error> {
+    assert!(buf.len() >= page_size.assert_into());
+
+    for frag in fragments {
+        assert!(
+            frag.page_offset < page_size as u64
+                && frag.page_offset + frag.bytes <= page_size as u64
+        );
+
+        input.seek(SeekFrom::Start(frag.file_offset.assert_into()))?;
+
+        input.read_exact(
+            &mut buf[frag.page_offset.assert_into()..(frag.page_offset + frag.bytes).assert_into()],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Split the loadable segments of `file` into the fragments of every page they write, keyed by the
+/// page address. `page_size` must be a power of two.
+///
+/// Pages are only returned for the file contents of the segments, uninitialized memory like a
+/// `.bss` is left out. Segments may overlap where they load identical bytes.
+pub fn get_page_fragments<E: EndianParse>(
+    file: &ElfBytes<E>,
+    page_size: u32,
+) -> Result<BTreeMap<u64, Vec<PageFragment>>, Elf2Uf2Error> {
+    let segments = elf_segments(file);
+
+    // Look up the file bytes behind a fragment from the segment that contains them
+    let bytes_at = |offset: u64, len: u64| -> Result<&[u8], Elf2Uf2Error> {
+        let segment = segments
+            .iter()
+            .find(|s| s.p_offset <= offset && offset + len <= s.p_offset + s.p_filesz)
+            .expect("Fragments always lie within the file contents of a segment");
+        let start = (offset - segment.p_offset) as usize;
+        Ok(&file.segment_data(segment)?[start..start + len as usize])
+    };
+
+    collect_page_fragments(
+        &segments,
+        page_size,
+        &mut |first, second, len| Ok(bytes_at(first, len)? == bytes_at(second, len)?),
+        &mut Vec::new(),
+    )
+}
+
+/// Same as [`get_page_fragments`], but for already parsed program headers, e.g. from an
+/// [`elf::ElfStream`]. The `input` is only read when segments overlap, to check if they are
+/// identical.
+pub fn get_page_fragments_from_segments(
+    segments: &[ProgramHeader],
+    page_size: u32,
+    input: &mut (impl Read + Seek),
+) -> Result<BTreeMap<u64, Vec<PageFragment>>, Elf2Uf2Error> {
+    segment_page_fragments(segments, page_size, input, &mut Vec::new())
+}
+
+/// Same as [`get_page_fragments_from_segments`], recording the segments left out in `events`.
+pub(crate) fn segment_page_fragments(
+    segments: &[ProgramHeader],
+    page_size: u32,
+    input: &mut (impl Read + Seek),
+    events: &mut Vec<ConversionEvent>,
+) -> Result<BTreeMap<u64, Vec<PageFragment>>, Elf2Uf2Error> {
+    let same_bytes = &mut |first, second, len| {
+        let mut first_bytes = vec![0; len as usize];
+        input.seek(SeekFrom::Start(first))?;
+        input.read_exact(&mut first_bytes)?;
+
+        let mut second_bytes = vec![0; len as usize];
+        input.seek(SeekFrom::Start(second))?;
+        input.read_exact(&mut second_bytes)?;
+
+        Ok(first_bytes == second_bytes)
+    };
+    collect_page_fragments(segments, page_size, same_bytes, events)
+}
+
+/// Split the loadable segments into page fragments.
+///
+/// `same_bytes(first_offset, second_offset, len)` is called when two fragments overlap, if the
+/// file contents behind both are identical the overlap is benign and is allowed. Segments without
+/// any pages are recorded in `events`.
+fn collect_page_fragments(
+    segments: &[ProgramHeader],
+    page_size: u32,
+    same_bytes: &mut dyn FnMut(u64, u64, u64) -> Result<bool, Elf2Uf2Error>,
+    events: &mut Vec<ConversionEvent>,
+) -> Result<BTreeMap<u64, Vec<PageFragment>>, Elf2Uf2Error> {
+    // Segment ends are computed with plain additions below
+    if let Some(segment) = segments.iter().find(|segment| {
+        segment.p_type == PT_LOAD && segment.p_paddr.checked_add(segment.p_memsz).is_none()
+    }) {
+        return Err(Elf2Uf2Error::AddressOutOfRange(segment.p_paddr));
+    }
+
+    let ranges = address_ranges_from_segments(segments)?;
+
+    let mut pages = BTreeMap::<u64, Vec<PageFragment>>::new();
+
+    for segment in segments {
+        if segment.p_type == PT_LOAD && segment.p_memsz > 0 {
+            let mapped_size = min(segment.p_filesz, segment.p_memsz);
+
+            if mapped_size > 0 {
+                let ar = ranges.as_slice().check_address_range(
+                    segment.p_paddr,
+                    segment.p_vaddr,
+                    mapped_size,
+                    false,
+                )?;
+
+                if ar.typ != AddressRangeType::Contents {
+                    debug!("ignored");
+                    events.push(ConversionEvent::SegmentIgnored {
+                        addr: segment.p_paddr,
+                        size: segment.p_memsz,
+                        reason: IgnoreReason::IgnoredRange,
+                    });
+                    continue;
+                }
+
+                let mut addr = segment.p_paddr;
+                let mut remaining = mapped_size;
+                let mut file_offset = segment.p_offset;
+
+                while remaining > 0 {
+                    let off = addr & (page_size - 1) as u64;
+                    let len = min(remaining, page_size as u64 - off);
+
+                    // list of fragments
+                    let fragments = pages.entry(addr - off).or_default();
+
+                    let fragment = PageFragment {
+                        file_offset,
+                        page_offset: off,
+                        bytes: len,
+                    };
+
+                    // note if filesz is zero, we want zero init which is handled because the
+                    // statement above creates an empty page fragment list
+                    // check overlap with any existing fragments
+                    let mut duplicate = false;
+                    for existing in fragments.iter() {
+                        let start = existing.page_offset.max(off);
+                        let end = (existing.page_offset + existing.bytes).min(off + len);
+                        if start >= end {
+                            continue;
+                        }
+
+                        if !same_bytes(
+                            existing.file_offset + (start - existing.page_offset),
+                            fragment.file_offset + (start - off),
+                            end - start,
+                        )? {
+                            return Err(Elf2Uf2Error::OverlappingSegments {
+                                page_addr: addr - off,
+                                first: (existing.page_offset, existing.bytes),
+                                second: (off, len),
+                            });
+                        }
+
+                        debug!(
+                            "Segments map identical bytes to {:#08x}->{:#08x}",
+                            addr - off + start,
+                            addr - off + end
+                        );
+                        duplicate |= existing.page_offset == off && existing.bytes == len;
+                    }
+
+                    if !duplicate {
+                        fragments.push(fragment);
+                    }
+                    addr += len;
+                    file_offset += len;
+                    remaining -= len;
+                }
+                if segment.p_memsz > segment.p_filesz {
+                    // we have some uninitialized data too
+                    ranges.as_slice().check_address_range(
+                        segment.p_paddr + segment.p_filesz,
+                        segment.p_vaddr + segment.p_filesz,
+                        segment.p_memsz - segment.p_filesz,
+                        true,
+                    )?;
+                }
+            } else {
+                events.push(ConversionEvent::SegmentIgnored {
+                    addr: segment.p_paddr,
+                    size: segment.p_memsz,
+                    reason: IgnoreReason::Uninitialized,
+                });
+            }
+        }
+    }
+
+    Ok(pages)
+}
+
+/// Cut the `excluded` address ranges out of the fragments, dropping the pages left without any
+/// and recording them in `events`.
+pub(crate) fn exclude_ranges(
+    pages: &mut BTreeMap<u64, Vec<PageFragment>>,
+    excluded: &[Range<u64>],
+    events: &mut Vec<ConversionEvent>,
+) {
+    if excluded.is_empty() {
+        return;
+    }
+
+    let mut emptied = Vec::new();
+    for (&page_addr, fragments) in pages.iter_mut() {
+        if fragments.is_empty() {
+            continue;
+        }
+        for range in excluded.iter().filter(|range| !range.is_empty()) {
+            *fragments = fragments
+                .iter()
+                .flat_map(|fragment| fragment_without(page_addr, fragment, range))
+                .collect();
+        }
+        if fragments.is_empty() {
+            emptied.push(page_addr);
+        }
+    }
+
+    for page_addr in emptied {
+        debug!("Excluded page {page_addr:#08x}");
+        events.push(ConversionEvent::PageExcluded { addr: page_addr });
+        pages.remove(&page_addr);
+    }
+}
+
+/// The parts of `fragment`, in the page at `page_addr`, before and after `range`.
+fn fragment_without(
+    page_addr: u64,
+    fragment: &PageFragment,
+    range: &Range<u64>,
+) -> impl Iterator<Item = PageFragment> {
+    let start = page_addr + fragment.page_offset;
+    let end = start + fragment.bytes;
+
+    let before = (start < range.start).then(|| PageFragment {
+        bytes: end.min(range.start) - start,
+        ..*fragment
+    });
+    let after = (end > range.end).then(|| {
+        let skipped = range.end.max(start) - start;
+        PageFragment {
+            file_offset: fragment.file_offset + skipped,
+            page_offset: fragment.page_offset + skipped,
+            bytes: fragment.bytes - skipped,
+        }
+    });
+
+    before.into_iter().chain(after)
+}
+
+#[doc(hidden)]
+pub trait AddressRangesExt<'a>: IntoIterator<Item = &'a AddressRange> + Clone {
+    fn range_for(&self, addr: u64) -> Option<&'a AddressRange> {
+        self.clone()
+            .into_iter()
+            .find(|r| r.from <= addr && r.to > addr)
+    }
+
+    fn check_address_range(
+        &self,
+        addr: u64,
+        vaddr: u64,
+        size: u64,
+        uninitialized: bool,
+    ) -> Result<AddressRange, AddressRangesFromElfError> {
+        for range in self.clone().into_iter() {
+            if range.from <= addr && range.to >= addr + size {
+                if range.typ == address_range::AddressRangeType::NoContents && !uninitialized {
+                    return Err(
+                        AddressRangesFromElfError::MemoryContentsForUninitializedMemory(addr),
+                    );
+                }
+
+                debug!(
+                    "{} segment {:#08x}->{:#08x} ({:#08x}->{:#08x})",
+                    if uninitialized {
+                        "Uninitialized"
+                    } else {
+                        "Mapped"
+                    },
+                    addr,
+                    addr + size,
+                    vaddr,
+                    vaddr + size
+                );
+                return Ok(*range);
+            }
+        }
+        Err(AddressRangesFromElfError::MemorySegmentInvalidForDevice(
+            addr,
+            addr + size,
+        ))
+    }
+}
+
+impl<'a, T> AddressRangesExt<'a> for T where T: IntoIterator<Item = &'a AddressRange> + Clone {}
+
+/// The contents of every page an ELF writes, keyed by the page address.
+///
+/// The parts of a page no segment covers are zero, like the padding of a uf2 file with the default
+/// [`Uf2Options`](crate::Uf2Options).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageMap {
+    page_size: u32,
+    pages: BTreeMap<u64, Vec<u8>>,
+}
+
+impl PageMap {
+    /// Read the pages of the ELF file in `bytes`, `page_size` must be a power of two from
+    /// [`MIN_PAGE_SIZE`](crate::boards::MIN_PAGE_SIZE) to
+    /// [`MAX_SPLIT_PAGE_SIZE`](crate::boards::MAX_SPLIT_PAGE_SIZE).
+    pub fn from_elf(bytes: &[u8], page_size: u32) -> Result<Self, Elf2Uf2Error> {
+        if !crate::boards::is_valid_page_size(page_size) {
+            return Err(Elf2Uf2Error::InvalidPageSize(page_size));
+        }
+
+        let file = ElfBytes::<AnyEndian>::minimal_parse(bytes)?;
+        let mut input = Cursor::new(bytes);
+
+        let mut pages = BTreeMap::new();
+        for (page_addr, fragments) in get_page_fragments(&file, page_size)? {
+            let mut page = vec![0; page_size as usize];
+            realize_page(&mut input, &fragments, &mut page, page_size)?;
+            pages.insert(page_addr, page);
+        }
+
+        Ok(Self { page_size, pages })
+    }
+
+    pub fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    /// The contents of every page, keyed by the page address
+    pub fn pages(&self) -> &BTreeMap<u64, Vec<u8>> {
+        &self.pages
+    }
+
+    pub fn into_pages(self) -> BTreeMap<u64, Vec<u8>> {
+        self.pages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uf2::{UF2_BLOCK_SIZE, Uf2Block};
+
+    /// The payload of every block of a uf2 file, keyed by the target address
+    fn uf2_pages(uf2: &[u8]) -> BTreeMap<u64, Vec<u8>> {
+        uf2.chunks_exact(UF2_BLOCK_SIZE)
+            .map(|block| Uf2Block::from_bytes(block.try_into().unwrap()).unwrap())
+            .map(|block| (block.target_addr() as u64, block.payload().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn page_map_matches_the_uf2_payloads() {
+        let cases: [(&[u8], &[u8]); 3] = [
+            (
+                include_bytes!("../tests/rp2040/hello_usb.elf"),
+                include_bytes!("../tests/rp2040/hello_usb.uf2"),
+            ),
+            (
+                include_bytes!("../tests/rp2040/big_endian.elf"),
+                include_bytes!("../tests/rp2040/big_endian.uf2"),
+            ),
+            (
+                include_bytes!("../tests/rp2040/ram_only.elf"),
+                include_bytes!("../tests/rp2040/ram_only.uf2"),
+            ),
+        ];
+
+        for (elf, uf2) in cases {
+            let map = PageMap::from_elf(elf, 256).unwrap();
+            let mut expected = uf2_pages(uf2);
+
+            // The uf2 files also have the empty pages filling the flash sectors
+            expected.retain(|addr, _| map.pages().contains_key(addr));
+            assert_eq!(map.pages(), &expected);
+            assert!(
+                uf2_pages(uf2)
+                    .iter()
+                    .filter(|(addr, _)| !map.pages().contains_key(addr))
+                    .all(|(_, page)| page.iter().all(|&byte| byte == 0))
+            );
+        }
+    }
+
+    #[test]
+    fn page_map_uses_the_sections_without_program_headers() {
+        let map =
+            PageMap::from_elf(include_bytes!("../tests/rp2350/flash_image.elf"), 256).unwrap();
+        let no_phdrs = PageMap::from_elf(
+            include_bytes!("../tests/rp2350/flash_image_no_phdrs.elf"),
+            256,
+        )
+        .unwrap();
+        assert!(!map.pages().is_empty());
+        assert_eq!(map, no_phdrs);
+    }
+
+    #[test]
+    fn page_map_checks_the_page_size() {
+        let elf = include_bytes!("../tests/rp2040/hello_usb.elf");
+        assert!(matches!(
+            PageMap::from_elf(elf, 300),
+            Err(Elf2Uf2Error::InvalidPageSize(300))
+        ));
+
+        let map = PageMap::from_elf(elf, 4096).unwrap();
+        assert_eq!(map.page_size(), 4096);
+        assert!(map.pages().values().all(|page| page.len() == 4096));
+        assert!(map.into_pages().keys().all(|addr| addr % 4096 == 0));
+    }
+}
@@ -0,0 +1,14 @@
+//! The supported items for converting ELF files, `use elf2flash_core::prelude::*` brings them all
+//! into scope.
+
+pub use crate::{
+    ConversionSummary, Elf2Uf2Error, NoProgress, ProgressDetail, ProgressPhase, ProgressReporter,
+    Uf2BlockIterator, Uf2Options, Uf2SizeEstimate, Uf2Writer,
+    boards::*,
+    elf2uf2, elf2uf2_multi, elf2uf2_multi_with_options, elf2uf2_size, elf2uf2_size_with_options,
+    elf2uf2_with_options,
+    events::ConversionEvent,
+    extension::ExtensionTag,
+    pages_to_uf2,
+    warnings::{Warning, WarningCode, Warnings},
+};
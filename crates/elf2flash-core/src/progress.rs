@@ -0,0 +1,247 @@
+//! [`Read`] and [`Write`] adapters that report the bytes passing through them to a
+//! [`ProgressReporter`].
+//!
+//! Both adapters call [`ProgressReporter::start`] when created, [`ProgressReporter::advance`] with
+//! the byte count of every successful read or write, and [`ProgressReporter::finish`] exactly once,
+//! or [`ProgressReporter::cancel`] instead if a [`ProgressWrite`] is cancelled.
+
+use std::io::{self, Read, Write};
+
+use crate::{ProgressDetail, ProgressReporter};
+
+/// Reports every byte written to the inner writer.
+///
+/// Call [`ProgressWrite::finish`] once everything is written, it flushes the inner writer before
+/// the reporter is finished, so buffered bytes are on their way before the progress completes.
+pub struct ProgressWrite<'a, W> {
+    inner: W,
+    reporter: &'a mut dyn ProgressReporter,
+}
+
+impl<'a, W: Write> ProgressWrite<'a, W> {
+    /// Wrap `inner`, starting `reporter` with the number of bytes that are going to be written.
+    pub fn new(inner: W, reporter: &'a mut dyn ProgressReporter, total_bytes: usize) -> Self {
+        reporter.start(total_bytes);
+        Self { inner, reporter }
+    }
+
+    /// Flush the inner writer, finish the reporter and return the inner writer.
+    ///
+    /// The reporter is finished even if the flush fails, the flush error is returned afterwards.
+    pub fn finish(mut self) -> io::Result<W> {
+        let flushed = self.inner.flush();
+        self.reporter.finish();
+        flushed.map(|_| self.inner)
+    }
+
+    /// Whether the reporter asks for the write to stop, see [`ProgressReporter::should_cancel`].
+    pub fn should_cancel(&self) -> bool {
+        self.reporter.should_cancel()
+    }
+
+    /// Pass where the write is to the reporter, see [`ProgressReporter::detail`].
+    pub fn detail(&mut self, detail: ProgressDetail) {
+        self.reporter.detail(detail);
+    }
+
+    /// Cancel the reporter and return the inner writer, without flushing it.
+    pub fn cancel(self) -> W {
+        self.reporter.cancel();
+        self.inner
+    }
+}
+
+impl<W: Write> Write for ProgressWrite<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if written > 0 {
+            self.reporter.advance(written);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reports every byte read from the inner reader.
+///
+/// The reporter is finished when the inner reader reaches its end, or by
+/// [`ProgressRead::finish`] if the caller stops reading early, whichever comes first.
+pub struct ProgressRead<'a, R> {
+    inner: R,
+    reporter: &'a mut dyn ProgressReporter,
+    finished: bool,
+}
+
+impl<'a, R: Read> ProgressRead<'a, R> {
+    /// Wrap `inner`, starting `reporter` with the number of bytes that are going to be read.
+    pub fn new(inner: R, reporter: &'a mut dyn ProgressReporter, total_bytes: usize) -> Self {
+        reporter.start(total_bytes);
+        Self {
+            inner,
+            reporter,
+            finished: false,
+        }
+    }
+
+    /// Finish the reporter, if the end of the input wasn't reached yet, and return the inner reader.
+    pub fn finish(mut self) -> R {
+        self.finish_once();
+        self.inner
+    }
+
+    fn finish_once(&mut self) {
+        if !self.finished {
+            self.finished = true;
+            self.reporter.finish();
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressRead<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if read > 0 {
+            self.reporter.advance(read);
+        } else if !buf.is_empty() {
+            self.finish_once();
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ProgressCall, RecordingReporter};
+    use std::{cell::Cell, io::Cursor, rc::Rc};
+
+    /// Accepts at most 300 bytes per write and notes when it was flushed
+    struct SlowWriter(Vec<u8>, Rc<Cell<bool>>);
+
+    impl Write for SlowWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(300);
+            self.0.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.1.set(true);
+            Ok(())
+        }
+    }
+
+    /// Records into a [`RecordingReporter`], checking the writer was flushed before finishing
+    struct FlushedBeforeFinish<'a>(&'a mut RecordingReporter, Rc<Cell<bool>>);
+
+    impl ProgressReporter for FlushedBeforeFinish<'_> {
+        fn start(&mut self, total_bytes: usize) {
+            self.0.start(total_bytes);
+        }
+
+        fn advance(&mut self, bytes: usize) {
+            self.0.advance(bytes);
+        }
+
+        fn finish(&mut self) {
+            assert!(self.1.get(), "finished before the writer was flushed");
+            self.0.finish();
+        }
+
+        fn cancel(&mut self) {
+            self.0.cancel();
+        }
+    }
+
+    #[test]
+    fn write_reports_accepted_bytes_and_finishes_after_flush() {
+        let flushed = Rc::new(Cell::new(false));
+        let mut recorder = RecordingReporter::default();
+        let mut reporter = FlushedBeforeFinish(&mut recorder, flushed.clone());
+
+        let mut writer = ProgressWrite::new(SlowWriter(Vec::new(), flushed), &mut reporter, 1024);
+        writer.write_all(&[1; 512]).unwrap();
+        writer.write_all(&[2; 512]).unwrap();
+        let inner = writer.finish().unwrap();
+
+        assert_eq!(inner.0.len(), 1024);
+        assert!(inner.1.get());
+        assert_eq!(
+            recorder.calls,
+            [
+                ProgressCall::Start(1024),
+                ProgressCall::Advance(300),
+                ProgressCall::Advance(212),
+                ProgressCall::Advance(300),
+                ProgressCall::Advance(212),
+                ProgressCall::Finish,
+            ]
+        );
+    }
+
+    #[test]
+    fn write_cancel_skips_flush_and_finish() {
+        let mut recorder = RecordingReporter::default();
+
+        let mut writer =
+            ProgressWrite::new(SlowWriter(Vec::new(), Rc::default()), &mut recorder, 1024);
+        writer.write_all(&[1; 512]).unwrap();
+        let inner = writer.cancel();
+
+        assert_eq!(inner.0.len(), 512);
+        assert!(!inner.1.get());
+        assert_eq!(
+            recorder.calls,
+            [
+                ProgressCall::Start(1024),
+                ProgressCall::Advance(300),
+                ProgressCall::Advance(212),
+                ProgressCall::Cancel,
+            ]
+        );
+    }
+
+    #[test]
+    fn read_finishes_once_at_end_of_input() {
+        let mut recorder = RecordingReporter::default();
+
+        let mut reader = ProgressRead::new(Cursor::new(vec![7; 100]), &mut recorder, 100);
+        let mut buf = [0; 64];
+        assert_eq!(reader.read(&mut buf).unwrap(), 64);
+        assert_eq!(reader.read(&mut buf).unwrap(), 36);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        reader.finish();
+
+        assert_eq!(
+            recorder.calls,
+            [
+                ProgressCall::Start(100),
+                ProgressCall::Advance(64),
+                ProgressCall::Advance(36),
+                ProgressCall::Finish,
+            ]
+        );
+    }
+
+    #[test]
+    fn read_finishes_when_stopped_early() {
+        let mut recorder = RecordingReporter::default();
+
+        let mut reader = ProgressRead::new(Cursor::new(vec![7; 100]), &mut recorder, 100);
+        reader.read_exact(&mut [0; 10]).unwrap();
+        reader.finish();
+
+        assert_eq!(
+            recorder.calls,
+            [
+                ProgressCall::Start(100),
+                ProgressCall::Advance(10),
+                ProgressCall::Finish,
+            ]
+        );
+    }
+}
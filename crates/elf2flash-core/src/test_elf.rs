@@ -0,0 +1,121 @@
+//! Builds small synthetic ELF files for tests.
+
+use elf::abi::{EM_ARM, PT_LOAD};
+
+pub struct TestSegment {
+    pub p_type: u32,
+    pub paddr: u64,
+    pub vaddr: u64,
+    pub data: Vec<u8>,
+    pub memsz: u64,
+}
+
+impl TestSegment {
+    /// A loadable segment whose physical and virtual address are both `addr`
+    pub fn load(addr: u64, data: impl Into<Vec<u8>>) -> Self {
+        let data = data.into();
+        Self {
+            p_type: PT_LOAD,
+            paddr: addr,
+            vaddr: addr,
+            memsz: data.len() as u64,
+            data,
+        }
+    }
+}
+
+pub struct TestElf {
+    pub class64: bool,
+    pub big_endian: bool,
+    pub machine: u16,
+    pub segments: Vec<TestSegment>,
+}
+
+impl Default for TestElf {
+    fn default() -> Self {
+        Self {
+            class64: false,
+            big_endian: false,
+            machine: EM_ARM,
+            segments: Vec::new(),
+        }
+    }
+}
+
+impl TestElf {
+    pub fn new(segments: Vec<TestSegment>) -> Self {
+        Self {
+            segments,
+            ..Default::default()
+        }
+    }
+
+    fn put(&self, out: &mut Vec<u8>, value: u64, size: usize) {
+        let bytes = value.to_le_bytes();
+        if self.big_endian {
+            out.extend(bytes[..size].iter().rev());
+        } else {
+            out.extend_from_slice(&bytes[..size]);
+        }
+    }
+
+    /// Put an address sized value
+    fn put_addr(&self, out: &mut Vec<u8>, value: u64) {
+        self.put(out, value, if self.class64 { 8 } else { 4 });
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let (ehsize, phentsize) = if self.class64 { (64, 56) } else { (52, 32) };
+        let phoff = ehsize;
+        let mut data_offset = phoff + phentsize * self.segments.len() as u64;
+
+        let mut out = vec![
+            0x7f,
+            b'E',
+            b'L',
+            b'F',
+            if self.class64 { 2 } else { 1 },
+            if self.big_endian { 2 } else { 1 },
+            1,
+        ];
+        out.resize(16, 0);
+        self.put(&mut out, 2, 2); // e_type: EXEC
+        self.put(&mut out, self.machine as u64, 2);
+        self.put(&mut out, 1, 4); // e_version
+        self.put_addr(&mut out, self.segments.first().map_or(0, |s| s.vaddr)); // e_entry
+        self.put_addr(&mut out, phoff);
+        self.put_addr(&mut out, 0); // e_shoff
+        self.put(&mut out, 0, 4); // e_flags
+        self.put(&mut out, ehsize, 2);
+        self.put(&mut out, phentsize, 2);
+        self.put(&mut out, self.segments.len() as u64, 2);
+        self.put(&mut out, if self.class64 { 64 } else { 40 }, 2); // e_shentsize
+        self.put(&mut out, 0, 2); // e_shnum
+        self.put(&mut out, 0, 2); // e_shstrndx
+        assert_eq!(out.len(), ehsize as usize);
+
+        for segment in &self.segments {
+            let filesz = segment.data.len() as u64;
+            self.put(&mut out, segment.p_type as u64, 4);
+            if self.class64 {
+                self.put(&mut out, 0x5, 4); // p_flags
+            }
+            self.put_addr(&mut out, data_offset);
+            self.put_addr(&mut out, segment.vaddr);
+            self.put_addr(&mut out, segment.paddr);
+            self.put_addr(&mut out, filesz);
+            self.put_addr(&mut out, segment.memsz);
+            if !self.class64 {
+                self.put(&mut out, 0x5, 4); // p_flags
+            }
+            self.put_addr(&mut out, 4); // p_align
+            data_offset += filesz;
+        }
+
+        for segment in &self.segments {
+            out.extend_from_slice(&segment.data);
+        }
+
+        out
+    }
+}
@@ -0,0 +1,111 @@
+//! Helpers for the tests of this crate and of the crates built on it, enabled by the
+//! `test-support` feature.
+
+use crate::{ProgressDetail, ProgressPhase, ProgressReporter, events::ConversionEvent};
+
+/// A call made to a [`RecordingReporter`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressCall {
+    Phase(ProgressPhase),
+    Detail(ProgressDetail),
+    Event(ConversionEvent),
+    Start(usize),
+    Advance(usize),
+    Finish,
+    Cancel,
+}
+
+/// A [`ProgressReporter`] keeping every call made to it in order. Pass it as `&mut recorder` and
+/// look at its calls afterwards.
+#[derive(Debug, Default)]
+pub struct RecordingReporter {
+    pub calls: Vec<ProgressCall>,
+    /// Ask to cancel once this many bytes were advanced
+    pub cancel_after: Option<usize>,
+}
+
+impl RecordingReporter {
+    /// A recorder asking to cancel once `bytes` were advanced
+    pub fn cancel_after(bytes: usize) -> Self {
+        Self {
+            cancel_after: Some(bytes),
+            ..Default::default()
+        }
+    }
+
+    pub fn phases(&self) -> Vec<ProgressPhase> {
+        self.calls
+            .iter()
+            .filter_map(|call| match call {
+                ProgressCall::Phase(phase) => Some(phase.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn details(&self) -> Vec<ProgressDetail> {
+        self.calls
+            .iter()
+            .filter_map(|call| match call {
+                ProgressCall::Detail(detail) => Some(detail.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn events(&self) -> Vec<ConversionEvent> {
+        self.calls
+            .iter()
+            .filter_map(|call| match call {
+                ProgressCall::Event(event) => Some(event.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Bytes advanced in total
+    pub fn advanced(&self) -> usize {
+        self.calls
+            .iter()
+            .map(|call| match call {
+                ProgressCall::Advance(bytes) => *bytes,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+impl ProgressReporter for RecordingReporter {
+    fn phase(&mut self, phase: ProgressPhase) {
+        self.calls.push(ProgressCall::Phase(phase));
+    }
+
+    fn detail(&mut self, detail: ProgressDetail) {
+        self.calls.push(ProgressCall::Detail(detail));
+    }
+
+    fn event(&mut self, event: ConversionEvent) {
+        self.calls.push(ProgressCall::Event(event));
+    }
+
+    fn start(&mut self, total_bytes: usize) {
+        self.calls.push(ProgressCall::Start(total_bytes));
+    }
+
+    fn advance(&mut self, bytes: usize) {
+        self.calls.push(ProgressCall::Advance(bytes));
+    }
+
+    fn finish(&mut self) {
+        self.calls.push(ProgressCall::Finish);
+    }
+
+    fn cancel(&mut self) {
+        self.calls.push(ProgressCall::Cancel);
+    }
+
+    fn should_cancel(&self) -> bool {
+        self.cancel_after
+            .is_some_and(|limit| self.advanced() >= limit)
+    }
+}
@@ -0,0 +1,165 @@
+//! Changes made to the contents of a page after it is read from the elf, before it is written
+//! into its uf2 block.
+//!
+//! Transforms are added to [`Uf2Options::page_transforms`](crate::Uf2Options::page_transforms)
+//! and run in order on every flash page, including the empty ones added to fill flash sectors.
+//! They run before the MD5 checksum is taken, so it covers the transformed contents.
+//!
+//! ```
+//! use std::io::Cursor;
+//! use elf2flash_core::{NoProgress, Uf2Options, Uf2Writer, boards, transforms};
+//!
+//! let elf = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+//! let writer = Uf2Writer::new(&boards::RP2040).options(Uf2Options {
+//!     page_transforms: vec![transforms::rp2040_boot2_checksum()],
+//!     ..Default::default()
+//! });
+//!
+//! let mut uf2 = Vec::new();
+//! writer.write(Cursor::new(elf), &mut uf2, NoProgress).unwrap();
+//! ```
+
+use std::{fmt, sync::Arc};
+
+use log::debug;
+
+type TransformFn = dyn Fn(u64, &mut [u8]) + Send + Sync;
+
+/// A function run on the contents of every page, see the [module docs](self).
+///
+/// It is called with the target address of the page and its `page_size` bytes, the rest of the
+/// block's data area is left alone since the uf2 spec requires it to be zero.
+#[derive(Clone)]
+pub struct PageTransform(Arc<TransformFn>);
+
+impl PageTransform {
+    pub fn new(transform: impl Fn(u64, &mut [u8]) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(transform))
+    }
+
+    /// Run the transform on the page at `address`.
+    pub fn apply(&self, address: u64, page: &mut [u8]) {
+        (self.0)(address, page)
+    }
+}
+
+impl fmt::Debug for PageTransform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PageTransform")
+    }
+}
+
+/// Address of the second stage bootloader the RP2040 bootrom loads from flash
+pub const RP2040_BOOT2_ADDRESS: u64 = 0x10000000;
+
+/// Size of the RP2040 second stage bootloader, including its checksum
+pub const RP2040_BOOT2_SIZE: usize = 256;
+
+/// Write the checksum the RP2040 bootrom expects into the last 4 bytes of the second stage
+/// bootloader, so a boot2 that was patched or built without it still boots.
+///
+/// The checksum is the CRC-32/MPEG-2 of the first 252 bytes, stored little endian. Pages that
+/// don't hold the whole boot2, and boot2s whose checksum already matches, are left alone. This is
+/// what [`Uf2Options::fix_rp2040_boot2`](crate::Uf2Options::fix_rp2040_boot2) adds for RP2040
+/// boards.
+pub fn rp2040_boot2_checksum() -> PageTransform {
+    PageTransform::new(|address, page| {
+        let Some(offset) = RP2040_BOOT2_ADDRESS.checked_sub(address) else {
+            return;
+        };
+        let Some(boot2) = page.get_mut(offset as usize..offset as usize + RP2040_BOOT2_SIZE) else {
+            return;
+        };
+
+        let (code, checksum) = boot2.split_at_mut(RP2040_BOOT2_SIZE - 4);
+        let expected = crc32_mpeg2(code).to_le_bytes();
+        if checksum == expected {
+            debug!(
+                "The boot2 checksum {:#010x} is already valid",
+                u32::from_le_bytes(expected)
+            );
+        } else {
+            debug!(
+                "Replacing the boot2 checksum {:#010x} with {:#010x}",
+                u32::from_le_bytes(checksum.try_into().expect("the checksum is 4 bytes")),
+                u32::from_le_bytes(expected)
+            );
+            checksum.copy_from_slice(&expected);
+        }
+    })
+}
+
+/// CRC-32/MPEG-2, the unreflected CRC-32 without a final xor that the RP2040 bootrom uses.
+pub fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x80000000 != 0 {
+                (crc << 1) ^ 0x04c11db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        NoProgress, Uf2Options, boards, elf2uf2_with_options,
+        test_elf::{TestElf, TestSegment},
+        uf2::UF2_BLOCK_SIZE,
+    };
+
+    /// The payload of the block that writes to `address`
+    fn payload_at(uf2: &[u8], address: u32) -> &[u8] {
+        uf2.chunks_exact(UF2_BLOCK_SIZE)
+            .find(|block| u32::from_le_bytes(block[12..16].try_into().unwrap()) == address)
+            .map(|block| &block[32..32 + 256])
+            .unwrap()
+    }
+
+    #[test]
+    fn crc32_mpeg2_check_value() {
+        assert_eq!(crc32_mpeg2(b"123456789"), 0x0376e6e7);
+    }
+
+    #[test]
+    fn boot2_checksum_matches_the_bootrom() {
+        // hello_usb.elf links a boot2 that already carries a valid checksum
+        let golden = &include_bytes!("../tests/rp2040/hello_usb.uf2")[..];
+        let boot2 = payload_at(golden, 0x10000000);
+        assert_eq!(boot2[252..256], crc32_mpeg2(&boot2[..252]).to_le_bytes());
+
+        let code: Vec<u8> = (0..252).map(|i| i as u8).collect();
+        let elf = TestElf::new(vec![
+            TestSegment::load(0x10000000, code.clone()),
+            TestSegment::load(0x10000100, vec![0x33; 256]),
+        ])
+        .build();
+
+        let mut uf2 = Vec::new();
+        elf2uf2_with_options(
+            Cursor::new(elf),
+            &mut uf2,
+            &boards::RP2040,
+            &Uf2Options {
+                page_transforms: vec![rp2040_boot2_checksum()],
+                ..Default::default()
+            },
+            NoProgress,
+        )
+        .unwrap();
+
+        let boot2 = payload_at(&uf2, 0x10000000);
+        assert_eq!(boot2[..252], code);
+        assert_eq!(boot2[252..256], crc32_mpeg2(&code).to_le_bytes());
+        // Later pages are left alone
+        assert_eq!(payload_at(&uf2, 0x10000100), [0x33; 256]);
+    }
+}
@@ -1,20 +1,40 @@
 #![allow(dead_code)]
 
 use static_assertions::const_assert;
-use std::mem;
+use std::{
+    collections::{BTreeMap, HashMap, hash_map},
+    io::{Cursor, Read, Write},
+    mem,
+};
+use thiserror::Error;
 use zerocopy::{FromBytes, Immutable, IntoBytes};
 
+use crate::{Elf2Uf2Error, Uf2Options, boards::BoardInfo, elf_layout, pages::realize_page};
+
 pub const UF2_MAGIC_START0: u32 = 0x0A324655;
 pub const UF2_MAGIC_START1: u32 = 0x9E5D5157;
 pub const UF2_MAGIC_END: u32 = 0x0AB16F30;
 
+/// Size of a single serialized UF2 block
+pub const UF2_BLOCK_SIZE: usize = 512;
+
+/// Family id of blocks that are written regardless of the partition table on the RP2350
+pub const UF2_ABSOLUTE_FAMILY_ID: u32 = 0xe48bff57;
+
 pub const UF2_FLAG_NOT_MAIN_FLASH: u32 = 0x00000001;
 pub const UF2_FLAG_FILE_CONTAINER: u32 = 0x00001000;
 pub const UF2_FLAG_FAMILY_ID_PRESENT: u32 = 0x00002000;
 pub const UF2_FLAG_MD5_PRESENT: u32 = 0x00004000;
+pub const UF2_FLAG_EXTENSION_TAGS_PRESENT: u32 = 0x00008000;
+
+pub const UF2_TAG_FIRMWARE_VERSION: u32 = 0x9fc7bc;
+pub const UF2_TAG_DESCRIPTION: u32 = 0x650d9d;
+pub const UF2_TAG_DEVICE_TYPE_ID: u32 = 0xc8a729;
 
+/// The raw header of a block, its layout follows the spec but the field names may change.
+#[doc(hidden)]
 #[repr(packed)]
-#[derive(IntoBytes, FromBytes, Immutable)]
+#[derive(IntoBytes, FromBytes, Immutable, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Uf2BlockHeader {
     pub magic_start0: u32,
     pub magic_start1: u32,
@@ -23,11 +43,14 @@ pub struct Uf2BlockHeader {
     pub payload_size: u32,
     pub block_no: u32,
     pub num_blocks: u32,
-    pub file_size: u32, // or familyID
+    /// Holds the family id or the file size, see [`FileSizeField`]
+    pub file_size: u32,
 }
 
+#[doc(hidden)]
 pub type Uf2BlockData = [u8; 476];
 
+#[doc(hidden)]
 #[repr(packed)]
 #[derive(IntoBytes, FromBytes, Immutable)]
 pub struct Uf2BlockFooter {
@@ -40,5 +63,888 @@ const_assert!(
     mem::size_of::<Uf2BlockHeader>()
         + mem::size_of::<Uf2BlockData>()
         + mem::size_of::<Uf2BlockFooter>()
-        == 512
+        == UF2_BLOCK_SIZE
 );
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum Uf2BlockError {
+    #[error("The block doesn't start and end with the uf2 magic numbers")]
+    InvalidMagic,
+    #[error("The payload size {0} is larger than the {DATA_SIZE} bytes of data in a block")]
+    PayloadTooLarge(u32),
+}
+
+const DATA_SIZE: usize = mem::size_of::<Uf2BlockData>();
+
+/// A single uf2 block, parsed from or serialized to its 512 bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uf2Block {
+    header: Uf2BlockHeader,
+    data: Uf2BlockData,
+}
+
+impl Uf2Block {
+    /// A block from its raw parts, the end magic is added when it is serialized.
+    #[doc(hidden)]
+    pub fn from_parts(header: Uf2BlockHeader, data: Uf2BlockData) -> Self {
+        Self { header, data }
+    }
+
+    /// Parse a block, checking the magic numbers and the payload size.
+    pub fn from_bytes(bytes: &[u8; UF2_BLOCK_SIZE]) -> Result<Self, Uf2BlockError> {
+        let (header, rest) = Uf2BlockHeader::read_from_prefix(bytes)
+            .expect("A uf2 block is always larger than its header");
+        let (data, footer) = Uf2BlockData::read_from_prefix(rest)
+            .expect("A uf2 block is always larger than its header and data");
+        let footer = Uf2BlockFooter::read_from_bytes(footer)
+            .expect("The footer is what is left after the header and data");
+
+        if header.magic_start0 != UF2_MAGIC_START0
+            || header.magic_start1 != UF2_MAGIC_START1
+            || footer.magic_end != UF2_MAGIC_END
+        {
+            return Err(Uf2BlockError::InvalidMagic);
+        }
+        if header.payload_size as usize > DATA_SIZE {
+            return Err(Uf2BlockError::PayloadTooLarge(header.payload_size));
+        }
+
+        Ok(Self { header, data })
+    }
+
+    /// Serialize the block, adding the end magic.
+    pub fn to_bytes(&self) -> [u8; UF2_BLOCK_SIZE] {
+        let footer = Uf2BlockFooter {
+            magic_end: UF2_MAGIC_END,
+        };
+
+        let mut block = [0; UF2_BLOCK_SIZE];
+        let (header_bytes, rest) = block.split_at_mut(mem::size_of::<Uf2BlockHeader>());
+        let (data_bytes, footer_bytes) = rest.split_at_mut(DATA_SIZE);
+        header_bytes.copy_from_slice(self.header.as_bytes());
+        data_bytes.copy_from_slice(self.data.as_bytes());
+        footer_bytes.copy_from_slice(footer.as_bytes());
+        block
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.header.flags
+    }
+
+    pub fn target_addr(&self) -> u32 {
+        self.header.target_addr
+    }
+
+    pub fn payload_size(&self) -> u32 {
+        self.header.payload_size
+    }
+
+    pub fn block_no(&self) -> u32 {
+        self.header.block_no
+    }
+
+    pub fn set_block_no(&mut self, block_no: u32) {
+        self.header.block_no = block_no;
+    }
+
+    pub fn num_blocks(&self) -> u32 {
+        self.header.num_blocks
+    }
+
+    pub fn set_num_blocks(&mut self, num_blocks: u32) {
+        self.header.num_blocks = num_blocks;
+    }
+
+    /// The family id, `None` if [`UF2_FLAG_FAMILY_ID_PRESENT`] isn't set and the field holds the
+    /// file size instead
+    pub fn family_id(&self) -> Option<u32> {
+        match self.file_size_field() {
+            FileSizeField::FamilyId(family_id) => Some(family_id),
+            _ => None,
+        }
+    }
+
+    /// What the last header field holds, going by the flags.
+    pub fn file_size_field(&self) -> FileSizeField {
+        FileSizeField::from_header(&self.header)
+    }
+
+    /// The bytes written to [`Uf2Block::target_addr`]
+    pub fn payload(&self) -> &[u8] {
+        &self.data[..(self.header.payload_size as usize).min(DATA_SIZE)]
+    }
+
+    /// All 476 bytes of data, including whatever follows the payload, like extension tags
+    pub fn data(&self) -> &[u8; DATA_SIZE] {
+        &self.data
+    }
+}
+
+/// What the last field of a block header holds, `fileSize` in the spec.
+///
+/// The flags of a block say how the field is read, so [`BlockHeaderTemplate`] derives them from
+/// this instead of having them set separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileSizeField {
+    /// The family id of the board the block is for, flagged with [`UF2_FLAG_FAMILY_ID_PRESENT`]
+    FamilyId(u32),
+    /// The size of the file the block is part of, flagged with [`UF2_FLAG_FILE_CONTAINER`]
+    FileSize(u32),
+    /// The size of the file without either flag, as the field was defined before family ids and
+    /// file containers were added to the spec
+    Unflagged(u32),
+    /// Neither flag is set and the field is zero
+    None,
+}
+
+impl FileSizeField {
+    /// The flag bits that say how the field is read
+    pub fn flags(&self) -> u32 {
+        match self {
+            Self::FamilyId(_) => UF2_FLAG_FAMILY_ID_PRESENT,
+            Self::FileSize(_) => UF2_FLAG_FILE_CONTAINER,
+            Self::Unflagged(_) | Self::None => 0,
+        }
+    }
+
+    /// The value of the field
+    pub fn value(&self) -> u32 {
+        match *self {
+            Self::FamilyId(family_id) => family_id,
+            Self::FileSize(file_size) | Self::Unflagged(file_size) => file_size,
+            Self::None => 0,
+        }
+    }
+
+    /// Read the field of `header`, a family id takes precedence if both flags are set.
+    fn from_header(header: &Uf2BlockHeader) -> Self {
+        if header.flags & UF2_FLAG_FAMILY_ID_PRESENT != 0 {
+            Self::FamilyId(header.file_size)
+        } else if header.flags & UF2_FLAG_FILE_CONTAINER != 0 {
+            Self::FileSize(header.file_size)
+        } else if header.file_size != 0 {
+            Self::Unflagged(header.file_size)
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// Everything the blocks of one image share, to build their headers from.
+///
+/// The flags that say what the last header field holds always come from the [`FileSizeField`],
+/// they are removed from the flags passed to [`BlockHeaderTemplate::add_flags`].
+///
+/// ```
+/// use elf2flash_core::uf2::{
+///     BlockHeaderTemplate, FileSizeField, UF2_FLAG_FAMILY_ID_PRESENT, UF2_FLAG_NOT_MAIN_FLASH,
+/// };
+///
+/// let template = BlockHeaderTemplate::new(FileSizeField::FamilyId(0xe48bff56))
+///     .add_flags(UF2_FLAG_NOT_MAIN_FLASH)
+///     .num_blocks(2);
+/// let block = template.block(0x20000000, 1, [0; 476]);
+///
+/// assert_eq!(block.family_id(), Some(0xe48bff56));
+/// assert_eq!(block.flags(), UF2_FLAG_FAMILY_ID_PRESENT | UF2_FLAG_NOT_MAIN_FLASH);
+/// assert_eq!((block.block_no(), block.num_blocks()), (1, 2));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeaderTemplate {
+    field: FileSizeField,
+    flags: u32,
+    payload_size: u32,
+    num_blocks: u32,
+}
+
+impl BlockHeaderTemplate {
+    /// Blocks with 256 byte payloads and no flags besides the ones for `field`.
+    pub fn new(field: FileSizeField) -> Self {
+        Self {
+            field,
+            flags: 0,
+            payload_size: 256,
+            num_blocks: 0,
+        }
+    }
+
+    /// Set `flags` on every block, besides the ones for the [`FileSizeField`].
+    pub fn add_flags(mut self, flags: u32) -> Self {
+        self.flags |= flags & !(UF2_FLAG_FAMILY_ID_PRESENT | UF2_FLAG_FILE_CONTAINER);
+        self
+    }
+
+    pub fn payload_size(mut self, payload_size: u32) -> Self {
+        self.payload_size = payload_size;
+        self
+    }
+
+    /// The number of blocks in the image
+    pub fn num_blocks(mut self, num_blocks: u32) -> Self {
+        self.num_blocks = num_blocks;
+        self
+    }
+
+    /// The header of block `block_no`, writing to `target_addr`.
+    #[doc(hidden)]
+    pub fn header(&self, target_addr: u32, block_no: u32) -> Uf2BlockHeader {
+        Uf2BlockHeader {
+            magic_start0: UF2_MAGIC_START0,
+            magic_start1: UF2_MAGIC_START1,
+            flags: self.flags | self.field.flags(),
+            target_addr,
+            payload_size: self.payload_size,
+            block_no,
+            num_blocks: self.num_blocks,
+            file_size: self.field.value(),
+        }
+    }
+
+    /// Block `block_no`, writing the start of `data` to `target_addr`.
+    pub fn block(&self, target_addr: u32, block_no: u32, data: Uf2BlockData) -> Uf2Block {
+        Uf2Block::from_parts(self.header(target_addr, block_no), data)
+    }
+}
+
+/// What [`merge`] wrote.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeSummary {
+    /// The number of blocks in the merged file
+    pub num_blocks: u32,
+    /// The number of blocks of every family id, in the order the families first appear. Blocks
+    /// without a family id are counted under `None`.
+    pub families: Vec<(Option<u32>, u32)>,
+    /// Blocks left out because an earlier input already writes the same bytes to the same address
+    pub duplicate_blocks: u32,
+}
+
+#[derive(Error, Debug)]
+pub enum Uf2MergeError {
+    #[error("Failed to read or write a uf2 file")]
+    Io(#[from] std::io::Error),
+    #[error("Input {input} is not a uf2 file, its size is not a multiple of {UF2_BLOCK_SIZE}")]
+    InvalidLength { input: usize },
+    #[error("Block {block} of input {input} is not a valid uf2 block")]
+    InvalidBlock { input: usize, block: usize },
+    #[error(
+        "Inputs {first} and {second} both write to {address:#08x} with {}",
+        family_name(.family_id)
+    )]
+    OverlappingBlocks {
+        family_id: Option<u32>,
+        address: u32,
+        /// Index of the input that wrote the address first
+        first: usize,
+        /// Index of the input that writes it again
+        second: usize,
+    },
+}
+
+fn family_name(family_id: &Option<u32>) -> String {
+    match family_id {
+        Some(family_id) => format!("family id {family_id:#x}"),
+        None => "no family id".to_string(),
+    }
+}
+
+/// Combine several uf2 files into one, e.g. a bootloader and an application.
+///
+/// The blocks are written in the order of the inputs, and renumbered so every family is a single
+/// image again. Blocks of the same family must not write to the same address, unless they write
+/// the same bytes, in which case only the first one is kept. Blocks with the
+/// [`UF2_ABSOLUTE_FAMILY_ID`] keep their numbering, which works around RP2350-E10.
+pub fn merge(
+    inputs: &mut [impl Read],
+    mut output: impl Write,
+) -> Result<MergeSummary, Uf2MergeError> {
+    let mut blocks: Vec<Uf2Block> = Vec::new();
+    // The input and the index in `blocks` of the block written to every (family, address)
+    let mut written: HashMap<(Option<u32>, u32), (usize, usize)> = HashMap::new();
+    let mut summary = MergeSummary::default();
+
+    for (input_index, input) in inputs.iter_mut().enumerate() {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+
+        if data.len() % UF2_BLOCK_SIZE != 0 {
+            return Err(Uf2MergeError::InvalidLength { input: input_index });
+        }
+
+        for (block_index, block) in data.chunks_exact(UF2_BLOCK_SIZE).enumerate() {
+            let block = Uf2Block::from_bytes(
+                block
+                    .try_into()
+                    .expect("chunks_exact always yields whole blocks"),
+            )
+            .map_err(|_| Uf2MergeError::InvalidBlock {
+                input: input_index,
+                block: block_index,
+            })?;
+
+            let family_id = block.family_id();
+            match written.entry((family_id, block.target_addr())) {
+                hash_map::Entry::Occupied(entry) => {
+                    let (first, index) = *entry.get();
+                    if blocks[index].payload() != block.payload() {
+                        return Err(Uf2MergeError::OverlappingBlocks {
+                            family_id,
+                            address: block.target_addr(),
+                            first,
+                            second: input_index,
+                        });
+                    }
+                    summary.duplicate_blocks += 1;
+                }
+                hash_map::Entry::Vacant(entry) => {
+                    entry.insert((input_index, blocks.len()));
+                    blocks.push(block);
+                }
+            }
+        }
+    }
+
+    for block in &blocks {
+        let family_id = block.family_id();
+        match summary.families.iter_mut().find(|(id, _)| *id == family_id) {
+            Some((_, count)) => *count += 1,
+            None => summary.families.push((family_id, 1)),
+        }
+    }
+
+    let mut block_nos: HashMap<Option<u32>, u32> = HashMap::new();
+    for mut block in blocks {
+        let family_id = block.family_id();
+        if family_id != Some(UF2_ABSOLUTE_FAMILY_ID) {
+            let block_no = block_nos.entry(family_id).or_default();
+            block.set_block_no(*block_no);
+            block.set_num_blocks(
+                summary
+                    .families
+                    .iter()
+                    .find(|(id, _)| *id == family_id)
+                    .map_or(0, |(_, count)| *count),
+            );
+            *block_no += 1;
+        }
+
+        output.write_all(&block.to_bytes())?;
+        summary.num_blocks += 1;
+    }
+
+    output.flush()?;
+
+    Ok(summary)
+}
+
+/// Binary images larger than this are refused by [`to_bin`], blocks spread that far apart are
+/// more likely to target different memories than a single flash.
+pub const MAX_BIN_SIZE: usize = 64 * 1024 * 1024;
+
+/// The main flash contents of a uf2 file, as returned by [`to_bin`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BinImage {
+    /// The address of the first byte of `data`
+    pub base_address: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum Uf2ToBinError {
+    #[error("The uf2 file size is not a multiple of {UF2_BLOCK_SIZE}")]
+    InvalidLength,
+    #[error("Block {0} is not a valid uf2 block")]
+    InvalidBlock(usize),
+    #[error("The uf2 file has no blocks for the main flash")]
+    NoFlashBlocks,
+    #[error("The uf2 file holds images for {} and {}", family_name(.0), family_name(.1))]
+    MultipleFamilies(Option<u32>, Option<u32>),
+    #[error("The image would be {size} bytes, more than the {MAX_BIN_SIZE} byte limit")]
+    TooLarge { size: usize },
+}
+
+/// Flatten the main flash blocks of a uf2 file into a binary image, filling the gaps between
+/// them with `padding`.
+///
+/// Blocks flagged as not main flash or file container are left out, as are blocks with the
+/// [`UF2_ABSOLUTE_FAMILY_ID`]. The remaining blocks must all have the same family id.
+pub fn to_bin(uf2: &[u8], padding: u8) -> Result<BinImage, Uf2ToBinError> {
+    if !uf2.len().is_multiple_of(UF2_BLOCK_SIZE) {
+        return Err(Uf2ToBinError::InvalidLength);
+    }
+
+    let mut blocks = Vec::new();
+    for (index, block) in uf2.chunks_exact(UF2_BLOCK_SIZE).enumerate() {
+        let block = Uf2Block::from_bytes(
+            block
+                .try_into()
+                .expect("chunks_exact always yields whole blocks"),
+        )
+        .map_err(|_| Uf2ToBinError::InvalidBlock(index))?;
+
+        if block.flags() & (UF2_FLAG_NOT_MAIN_FLASH | UF2_FLAG_FILE_CONTAINER) != 0
+            || block.family_id() == Some(UF2_ABSOLUTE_FAMILY_ID)
+        {
+            continue;
+        }
+        if let Some(first) = blocks.first().map(Uf2Block::family_id)
+            && first != block.family_id()
+        {
+            return Err(Uf2ToBinError::MultipleFamilies(first, block.family_id()));
+        }
+        blocks.push(block);
+    }
+
+    let start = blocks
+        .iter()
+        .map(Uf2Block::target_addr)
+        .min()
+        .ok_or(Uf2ToBinError::NoFlashBlocks)?;
+    let end = blocks
+        .iter()
+        .map(|block| block.target_addr() as u64 + block.payload_size() as u64)
+        .max()
+        .unwrap_or_default();
+
+    let size = (end - start as u64) as usize;
+    if size > MAX_BIN_SIZE {
+        return Err(Uf2ToBinError::TooLarge { size });
+    }
+
+    let mut data = vec![padding; size];
+    for block in &blocks {
+        let offset = (block.target_addr() - start) as usize;
+        data[offset..offset + block.payload().len()].copy_from_slice(block.payload());
+    }
+
+    Ok(BinImage {
+        base_address: start,
+        data,
+    })
+}
+
+/// What [`verify_against_elf`] found, every list holds target addresses in ascending order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// The number of blocks compared against the ELF
+    pub blocks_checked: u32,
+    /// Blocks whose payload doesn't hold the bytes the ELF loads to their page
+    pub mismatched: Vec<u32>,
+    /// Pages the ELF loads bytes to that no block writes
+    pub missing: Vec<u32>,
+    /// Blocks writing to pages the ELF doesn't load anything to, and that don't fill its flash
+    /// sectors either
+    pub extra: Vec<u32>,
+}
+
+impl VerifyReport {
+    /// Whether the uf2 file writes exactly what the ELF loads
+    pub fn is_consistent(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum VerifyError {
+    #[error("Failed to read the uf2 file")]
+    Io(#[from] std::io::Error),
+    #[error("The uf2 file size is not a multiple of {UF2_BLOCK_SIZE}")]
+    InvalidLength,
+    #[error("Block {0} is not a valid uf2 block")]
+    InvalidBlock(usize),
+    #[error("Failed to lay out the pages of the ELF")]
+    Elf(#[from] Elf2Uf2Error),
+}
+
+/// Check that `uf2` was converted from `elf` for `board`, e.g. to catch a stale uf2 left behind by
+/// a failed build.
+///
+/// The pages are laid out from the ELF like a conversion with the default [`Uf2Options`] does, and
+/// the bytes the ELF loads are compared against the payload of the block writing to each page.
+/// Padding isn't compared, so files converted with another padding byte or without filling flash
+/// sectors still verify. Blocks of other families, like the RP2350's absolute block, are skipped.
+pub fn verify_against_elf(
+    mut uf2: impl Read,
+    elf: impl AsRef<[u8]>,
+    board: impl BoardInfo,
+) -> Result<VerifyReport, VerifyError> {
+    let mut data = Vec::new();
+    uf2.read_to_end(&mut data)?;
+    if !data.len().is_multiple_of(UF2_BLOCK_SIZE) {
+        return Err(VerifyError::InvalidLength);
+    }
+
+    // A page written more than once ends up with the last block's payload
+    let mut blocks = BTreeMap::new();
+    for (index, block) in data.chunks_exact(UF2_BLOCK_SIZE).enumerate() {
+        let block = Uf2Block::from_bytes(
+            block
+                .try_into()
+                .expect("chunks_exact always yields whole blocks"),
+        )
+        .map_err(|_| VerifyError::InvalidBlock(index))?;
+
+        if block
+            .family_id()
+            .is_none_or(|family_id| family_id == board.family_id())
+        {
+            blocks.insert(block.target_addr() as u64, block);
+        }
+    }
+
+    let mut input = Cursor::new(elf.as_ref());
+    let layout = elf_layout(&mut input, &board, &Uf2Options::default())?;
+
+    let mut report = VerifyReport {
+        blocks_checked: blocks.len() as u32,
+        ..Default::default()
+    };
+    let page_size = board.page_size();
+    let mut page = vec![0; page_size as usize];
+    for (&addr, fragments) in &layout.pages {
+        let Some(block) = blocks.get(&addr) else {
+            if !fragments.is_empty() {
+                report.missing.push(addr as u32);
+            }
+            continue;
+        };
+
+        if block.payload_size() != page_size {
+            report.mismatched.push(addr as u32);
+            continue;
+        }
+
+        // Only the bytes the ELF loads are overwritten, the padding is taken from the block
+        page.copy_from_slice(block.payload());
+        realize_page(&mut input, fragments, &mut page, page_size).map_err(Elf2Uf2Error::from)?;
+        if page != block.payload() {
+            report.mismatched.push(addr as u32);
+        }
+    }
+
+    report.extra = blocks
+        .keys()
+        .filter(|addr| !layout.pages.contains_key(addr))
+        .map(|&addr| addr as u32)
+        .collect();
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(family_id: u32, target_addr: u32, fill: u8) -> [u8; UF2_BLOCK_SIZE] {
+        BlockHeaderTemplate::new(FileSizeField::FamilyId(family_id))
+            .num_blocks(9)
+            .block(target_addr, 7, [fill; 476])
+            .to_bytes()
+    }
+
+    fn uf2(blocks: &[[u8; UF2_BLOCK_SIZE]]) -> Vec<u8> {
+        blocks.concat()
+    }
+
+    fn headers(uf2: &[u8]) -> Vec<(u32, u32, u32, u32)> {
+        uf2.chunks_exact(UF2_BLOCK_SIZE)
+            .map(|block| {
+                let (header, _) = Uf2BlockHeader::read_from_prefix(block).unwrap();
+                (
+                    header.file_size,
+                    header.target_addr,
+                    header.block_no,
+                    header.num_blocks,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fixture_blocks_round_trip() {
+        for uf2 in [
+            &include_bytes!("../tests/rp2040/hello_usb.uf2")[..],
+            &include_bytes!("../tests/rp2040/hello_serial.uf2")[..],
+            &include_bytes!("../tests/rp2040/ram_only.uf2")[..],
+            &include_bytes!("../tests/rp2350/flash_image.uf2")[..],
+        ] {
+            for bytes in uf2.chunks_exact(UF2_BLOCK_SIZE) {
+                let bytes: &[u8; UF2_BLOCK_SIZE] = bytes.try_into().unwrap();
+                let block = Uf2Block::from_bytes(bytes).unwrap();
+
+                assert_eq!(&block.to_bytes(), bytes);
+                assert_eq!(block.payload().len(), block.payload_size() as usize);
+                assert_eq!(
+                    block.payload(),
+                    &bytes[32..32 + block.payload_size() as usize]
+                );
+                assert!(block.family_id().is_some());
+                assert!(block.block_no() < block.num_blocks());
+            }
+        }
+    }
+
+    #[test]
+    fn template_family_id() {
+        let block = BlockHeaderTemplate::new(FileSizeField::FamilyId(0xe48bff56))
+            .add_flags(UF2_FLAG_NOT_MAIN_FLASH)
+            .block(0x20000000, 0, [0; 476]);
+
+        assert_eq!(
+            block.flags(),
+            UF2_FLAG_FAMILY_ID_PRESENT | UF2_FLAG_NOT_MAIN_FLASH
+        );
+        assert_eq!({ block.header.file_size }, 0xe48bff56);
+        assert_eq!(block.family_id(), Some(0xe48bff56));
+        assert_eq!(block.file_size_field(), FileSizeField::FamilyId(0xe48bff56));
+    }
+
+    #[test]
+    fn template_file_size() {
+        let block = BlockHeaderTemplate::new(FileSizeField::FileSize(1234))
+            .payload_size(128)
+            .num_blocks(10)
+            .block(0, 3, [0; 476]);
+
+        assert_eq!(block.flags(), UF2_FLAG_FILE_CONTAINER);
+        assert_eq!({ block.header.file_size }, 1234);
+        assert_eq!(block.family_id(), None);
+        assert_eq!(block.file_size_field(), FileSizeField::FileSize(1234));
+        assert_eq!(block.payload().len(), 128);
+        assert_eq!((block.block_no(), block.num_blocks()), (3, 10));
+    }
+
+    #[test]
+    fn template_no_file_size_field() {
+        let block = BlockHeaderTemplate::new(FileSizeField::None).block(0x10000000, 0, [0; 476]);
+
+        assert_eq!(block.flags(), 0);
+        assert_eq!({ block.header.file_size }, 0);
+        assert_eq!(block.family_id(), None);
+        assert_eq!(block.file_size_field(), FileSizeField::None);
+    }
+
+    #[test]
+    fn template_unflagged_file_size() {
+        let block =
+            BlockHeaderTemplate::new(FileSizeField::Unflagged(1024)).block(0x10000000, 0, [0; 476]);
+
+        assert_eq!(block.flags(), 0);
+        assert_eq!({ block.header.file_size }, 1024);
+        assert_eq!(block.family_id(), None);
+        assert_eq!(block.file_size_field(), FileSizeField::Unflagged(1024));
+    }
+
+    #[test]
+    fn template_flags_follow_the_field() {
+        // Flags for the other interpretation can't be added, the field decides them
+        let template = BlockHeaderTemplate::new(FileSizeField::FileSize(512))
+            .add_flags(UF2_FLAG_FAMILY_ID_PRESENT | UF2_FLAG_FILE_CONTAINER | UF2_FLAG_MD5_PRESENT);
+        assert_eq!(
+            { template.header(0, 0).flags },
+            UF2_FLAG_FILE_CONTAINER | UF2_FLAG_MD5_PRESENT
+        );
+
+        let template = BlockHeaderTemplate::new(FileSizeField::None)
+            .add_flags(UF2_FLAG_FAMILY_ID_PRESENT | UF2_FLAG_FILE_CONTAINER);
+        assert_eq!({ template.header(0, 0).flags }, 0);
+    }
+
+    #[test]
+    fn rejects_invalid_blocks() {
+        let valid = block(0xe48bff56, 0x10000000, 1);
+
+        let mut bytes = valid;
+        bytes[UF2_BLOCK_SIZE - 1] = 0;
+        assert_eq!(
+            Uf2Block::from_bytes(&bytes),
+            Err(Uf2BlockError::InvalidMagic)
+        );
+
+        let mut bytes = valid;
+        bytes[16..20].copy_from_slice(&477u32.to_le_bytes());
+        assert_eq!(
+            Uf2Block::from_bytes(&bytes),
+            Err(Uf2BlockError::PayloadTooLarge(477))
+        );
+    }
+
+    #[test]
+    fn merges_and_renumbers_per_family() {
+        let absolute = block(UF2_ABSOLUTE_FAMILY_ID, 0x10ffff00, 0xef);
+        let bootloader = uf2(&[
+            absolute,
+            block(0xe48bff59, 0x10000000, 1),
+            block(0xe48bff59, 0x10000100, 1),
+        ]);
+        let riscv = uf2(&[absolute, block(0xe48bff5a, 0x10000000, 2)]);
+        let application = uf2(&[block(0xe48bff59, 0x10010000, 3)]);
+
+        let mut output = Vec::new();
+        let summary = merge(
+            &mut [&bootloader[..], &riscv[..], &application[..]],
+            &mut output,
+        )
+        .unwrap();
+
+        assert_eq!(
+            summary,
+            MergeSummary {
+                num_blocks: 5,
+                families: vec![
+                    (Some(UF2_ABSOLUTE_FAMILY_ID), 1),
+                    (Some(0xe48bff59), 3),
+                    (Some(0xe48bff5a), 1),
+                ],
+                duplicate_blocks: 1,
+            }
+        );
+        assert_eq!(
+            headers(&output),
+            [
+                (UF2_ABSOLUTE_FAMILY_ID, 0x10ffff00, 7, 9),
+                (0xe48bff59, 0x10000000, 0, 3),
+                (0xe48bff59, 0x10000100, 1, 3),
+                (0xe48bff5a, 0x10000000, 0, 1),
+                (0xe48bff59, 0x10010000, 2, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_overlapping_and_invalid_inputs() {
+        let first = uf2(&[block(0xe48bff56, 0x10000000, 1)]);
+        let second = uf2(&[
+            block(0xe48bff59, 0x10000000, 2),
+            block(0xe48bff56, 0x10000000, 2),
+        ]);
+        let err = merge(&mut [&first[..], &second[..]], Vec::new()).unwrap_err();
+        assert!(matches!(
+            err,
+            Uf2MergeError::OverlappingBlocks {
+                family_id: Some(0xe48bff56),
+                address: 0x10000000,
+                first: 0,
+                second: 1,
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "Inputs 0 and 1 both write to 0x10000000 with family id 0xe48bff56"
+        );
+
+        let mut corrupt = first.clone();
+        corrupt[UF2_BLOCK_SIZE - 1] = 0;
+        assert!(matches!(
+            merge(&mut [&first[..], &corrupt[..]], Vec::new()),
+            Err(Uf2MergeError::InvalidBlock { input: 1, block: 0 })
+        ));
+        assert!(matches!(
+            merge(&mut [&first[..UF2_BLOCK_SIZE - 1]], Vec::new()),
+            Err(Uf2MergeError::InvalidLength { input: 0 })
+        ));
+    }
+
+    #[test]
+    fn flattens_main_flash_blocks() {
+        let mut ram = block(0xe48bff56, 0x20000000, 9);
+        ram[8..12]
+            .copy_from_slice(&(UF2_FLAG_NOT_MAIN_FLASH | UF2_FLAG_FAMILY_ID_PRESENT).to_le_bytes());
+        let file = uf2(&[
+            block(UF2_ABSOLUTE_FAMILY_ID, 0x10ffff00, 0xef),
+            block(0xe48bff56, 0x10000200, 2),
+            ram,
+            block(0xe48bff56, 0x10000000, 1),
+        ]);
+
+        let image = to_bin(&file, 0xff).unwrap();
+        assert_eq!(image.base_address, 0x10000000);
+        assert_eq!(image.data.len(), 0x300);
+        assert_eq!(image.data[..0x100], [1; 0x100]);
+        assert_eq!(image.data[0x100..0x200], [0xff; 0x100]);
+        assert_eq!(image.data[0x200..], [2; 0x100]);
+
+        assert_eq!(
+            to_bin(
+                &uf2(&[
+                    block(0xe48bff56, 0x10000000, 1),
+                    block(0xe48bff59, 0x10000100, 1)
+                ]),
+                0xff
+            ),
+            Err(Uf2ToBinError::MultipleFamilies(
+                Some(0xe48bff56),
+                Some(0xe48bff59)
+            ))
+        );
+        assert_eq!(to_bin(&[], 0xff), Err(Uf2ToBinError::NoFlashBlocks));
+        assert_eq!(
+            to_bin(&file[..UF2_BLOCK_SIZE + 1], 0xff),
+            Err(Uf2ToBinError::InvalidLength)
+        );
+        assert_eq!(
+            to_bin(
+                &uf2(&[
+                    block(0xe48bff56, 0x00000000, 1),
+                    block(0xe48bff56, 0x10000000, 1)
+                ]),
+                0xff
+            ),
+            Err(Uf2ToBinError::TooLarge { size: 0x10000100 })
+        );
+    }
+
+    #[test]
+    fn verify_against_elf_finds_stale_blocks() {
+        let elf = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+        let good = &include_bytes!("../tests/rp2040/hello_usb.uf2")[..];
+        let verify = |uf2: &[u8]| verify_against_elf(uf2, elf, &crate::boards::RP2040).unwrap();
+
+        let report = verify(good);
+        assert!(report.is_consistent(), "{report:?}");
+        assert_eq!(report.blocks_checked, 89);
+
+        // Other padding and no filler pages write the same program
+        let options = Uf2Options {
+            block_padding_byte: 0xff,
+            fill_sectors: false,
+            ..Default::default()
+        };
+        let mut padded = Vec::new();
+        crate::elf2uf2_with_options(
+            Cursor::new(elf),
+            &mut padded,
+            &crate::boards::RP2040,
+            &options,
+            crate::NoProgress,
+        )
+        .unwrap();
+        assert!(verify(&padded).is_consistent());
+
+        let mut changed = good.to_vec();
+        changed[UF2_BLOCK_SIZE + 32] ^= 0xff;
+        assert_eq!(verify(&changed).mismatched, [0x10000100]);
+
+        let last_addr =
+            Uf2Block::from_bytes(good[good.len() - UF2_BLOCK_SIZE..].try_into().unwrap())
+                .unwrap()
+                .target_addr();
+        let truncated = verify(&good[..good.len() - UF2_BLOCK_SIZE]);
+        assert_eq!(truncated.missing, [last_addr]);
+        assert!(truncated.mismatched.is_empty() && truncated.extra.is_empty());
+
+        let mut extended = good.to_vec();
+        extended.extend_from_slice(&block(0xe48bff56, 0x10100000, 0));
+        // Blocks of other families are skipped
+        extended.extend_from_slice(&block(0xe48bff59, 0x10000000, 0));
+        assert_eq!(verify(&extended).extra, [0x10100000]);
+
+        let stale = verify(include_bytes!("../tests/rp2040/hello_serial.uf2"));
+        assert!(!stale.is_consistent());
+        assert!(!stale.mismatched.is_empty());
+
+        assert!(matches!(
+            verify_against_elf(&good[1..], elf, &crate::boards::RP2040),
+            Err(VerifyError::InvalidLength)
+        ));
+    }
+}
@@ -0,0 +1,133 @@
+//! Guessing whether a firmware enables USB CDC, the USB serial port a deploy can attach to.
+//!
+//! Nothing in an ELF says so outright, so this looks for the traces the common USB stacks leave:
+//! tinyusb's CDC class driver symbols, and the strings pico-sdk's `stdio_usb` puts in `.rodata`
+//! for its USB descriptors. The strings still work for stripped binaries.
+//!
+//! ```
+//! use std::io::Cursor;
+//! use elf2flash_core::usb_cdc::likely_has_cdc;
+//!
+//! let elf = &include_bytes!("../tests/rp2040/hello_usb.elf")[..];
+//! assert!(likely_has_cdc(Cursor::new(elf)).unwrap());
+//! ```
+
+use std::io::{Read, Seek};
+
+use elf::{
+    ElfStream, ParseError,
+    endian::{AnyEndian, EndianParse},
+};
+
+use crate::Elf2Uf2Error;
+
+/// Prefixes of the symbols of USB CDC implementations
+const CDC_SYMBOL_PREFIXES: &[&str] = &["tud_cdc_", "stdio_usb_"];
+
+/// Strings USB CDC implementations leave in `.rodata`, pico-sdk's default interface name and its
+/// stdio driver's binary info
+const CDC_STRINGS: &[&[u8]] = &[b"Board CDC", b"USB stdin / stdout"];
+
+/// Whether the firmware in the ELF `input` likely enables USB CDC, see the [module docs](self).
+pub fn likely_has_cdc(input: impl Read + Seek) -> Result<bool, Elf2Uf2Error> {
+    let mut elf = ElfStream::<AnyEndian, _>::open_stream(input)?;
+    Ok(scan(&mut elf)?)
+}
+
+/// [`likely_has_cdc`] for an already opened ELF.
+pub(crate) fn scan<E: EndianParse, S: Read + Seek>(
+    elf: &mut ElfStream<E, S>,
+) -> Result<bool, ParseError> {
+    if let Some((symbols, names)) = elf.symbol_table()? {
+        for symbol in symbols.iter() {
+            let name = names.get(symbol.st_name as usize)?;
+            if CDC_SYMBOL_PREFIXES
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+            {
+                return Ok(true);
+            }
+        }
+    }
+
+    let Some(rodata) = elf.section_header_by_name(".rodata")?.copied() else {
+        return Ok(false);
+    };
+    let (data, compression) = elf.section_data(&rodata)?;
+    if compression.is_some() {
+        return Ok(false);
+    }
+
+    Ok(CDC_STRINGS
+        .iter()
+        .any(|string| data.windows(string.len()).any(|window| window == *string)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_elf::{TestElf, TestSegment};
+    use std::io::Cursor;
+
+    fn has_cdc(elf: &[u8]) -> bool {
+        likely_has_cdc(Cursor::new(elf)).unwrap()
+    }
+
+    #[test]
+    fn finds_cdc_in_usb_firmware() {
+        assert!(has_cdc(include_bytes!("../tests/rp2040/hello_usb.elf")));
+    }
+
+    #[test]
+    fn uart_firmware_has_no_cdc() {
+        assert!(!has_cdc(include_bytes!("../tests/rp2040/hello_serial.elf")));
+    }
+
+    #[test]
+    fn conversion_summary_records_cdc() {
+        let summary = |elf: &[u8]| {
+            crate::Uf2BlockIterator::new(Cursor::new(elf), &crate::boards::RP2040)
+                .unwrap()
+                .summary()
+                .likely_has_cdc
+        };
+        assert_eq!(
+            summary(include_bytes!("../tests/rp2040/hello_usb.elf")),
+            Some(true)
+        );
+        assert_eq!(
+            summary(include_bytes!("../tests/rp2040/hello_serial.elf")),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn unreadable_symbol_table_still_converts() {
+        let mut elf = include_bytes!("../tests/rp2040/hello_usb.elf").to_vec();
+        let word = |elf: &[u8], at: usize| u32::from_le_bytes(elf[at..at + 4].try_into().unwrap());
+        let half = |elf: &[u8], at: usize| u16::from_le_bytes(elf[at..at + 2].try_into().unwrap());
+        let (shoff, shnum, shstrndx) = (
+            word(&elf, 0x20) as usize,
+            half(&elf, 0x30),
+            half(&elf, 0x32),
+        );
+        // Point the string table of the symbols past the end of the file
+        let strtab = (0..shnum)
+            .filter(|&index| index != shstrndx)
+            .map(|index| shoff + 40 * index as usize)
+            .find(|&header| word(&elf, header + 4) == elf::abi::SHT_STRTAB)
+            .unwrap();
+        elf[strtab + 16..strtab + 20].copy_from_slice(&0xffff_0000u32.to_le_bytes());
+
+        assert!(likely_has_cdc(Cursor::new(&elf)).is_err());
+        let blocks =
+            crate::Uf2BlockIterator::new(Cursor::new(&elf), &crate::boards::RP2040).unwrap();
+        assert_eq!(blocks.summary().likely_has_cdc, None);
+    }
+
+    #[test]
+    fn no_sections_no_cdc() {
+        let elf = TestElf::new(vec![TestSegment::load(0x10000000, b"Board CDC")]).build();
+        assert!(!has_cdc(&elf));
+    }
+}
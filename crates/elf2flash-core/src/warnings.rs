@@ -0,0 +1,198 @@
+//! Warnings collected over a whole conversion or deploy, so they can be summarized at the end
+//! instead of getting lost in the log.
+
+use std::{fmt, str::FromStr};
+
+use thiserror::Error;
+
+/// Stable identifiers for everything that can go wrong without failing the run.
+///
+/// The string form returned by [`WarningCode::as_str`] is part of the command line interface (e.g.
+/// `--deny-warning filler-inflation`) and must not change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum WarningCode {
+    /// Filling the touched flash sectors added more padding blocks than blocks with contents
+    FillerInflation,
+    /// No recognized board was plugged in, so generic uf2 devices were used
+    GenericDeviceFallback,
+    /// A plugged in device was not flashed
+    DeviceSkipped,
+    /// The bootloader volume could not be backed up completely
+    BackupIncomplete,
+    /// Writing the uf2 file to a device failed
+    WriteFailed,
+    /// A `--device` selector matched no plugged in device
+    SelectorUnmatched,
+    /// A file or partition on the bootloader volume could not be dumped
+    DumpIncomplete,
+    /// The firmware read back from a device is not a consistent uf2 file
+    ReadbackInconsistent,
+    /// The ELF is built for another architecture than the family id is for
+    ArchitectureMismatch,
+    /// A uf2 file was written to a board of another family than its blocks
+    FamilyMismatch,
+    /// What was written couldn't be read back for `--verify`, as the bootloader doesn't keep it
+    VerifySkipped,
+}
+
+impl WarningCode {
+    pub const ALL: [WarningCode; 11] = [
+        WarningCode::FillerInflation,
+        WarningCode::GenericDeviceFallback,
+        WarningCode::DeviceSkipped,
+        WarningCode::BackupIncomplete,
+        WarningCode::WriteFailed,
+        WarningCode::SelectorUnmatched,
+        WarningCode::DumpIncomplete,
+        WarningCode::ReadbackInconsistent,
+        WarningCode::ArchitectureMismatch,
+        WarningCode::FamilyMismatch,
+        WarningCode::VerifySkipped,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WarningCode::FillerInflation => "filler-inflation",
+            WarningCode::GenericDeviceFallback => "generic-device-fallback",
+            WarningCode::DeviceSkipped => "device-skipped",
+            WarningCode::BackupIncomplete => "backup-incomplete",
+            WarningCode::WriteFailed => "write-failed",
+            WarningCode::SelectorUnmatched => "selector-unmatched",
+            WarningCode::DumpIncomplete => "dump-incomplete",
+            WarningCode::ReadbackInconsistent => "readback-inconsistent",
+            WarningCode::ArchitectureMismatch => "architecture-mismatch",
+            WarningCode::FamilyMismatch => "family-mismatch",
+            WarningCode::VerifySkipped => "verify-skipped",
+        }
+    }
+}
+
+impl fmt::Display for WarningCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("unknown warning code '{0}'")]
+pub struct UnknownWarningCode(pub String);
+
+impl FromStr for WarningCode {
+    type Err = UnknownWarningCode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|code| code.as_str() == s)
+            .ok_or_else(|| UnknownWarningCode(s.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub code: WarningCode,
+    pub message: String,
+}
+
+/// An ordered ledger of the warnings raised during a run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Warnings {
+    entries: Vec<Warning>,
+}
+
+impl Warnings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a warning, it is also logged right away.
+    pub fn push(&mut self, code: WarningCode, message: impl Into<String>) {
+        let message = message.into();
+        log::warn!("{message}");
+        self.entries.push(Warning { code, message });
+    }
+
+    /// Append the warnings of `other` that aren't recorded yet, without logging them again.
+    pub fn extend(&mut self, other: Warnings) {
+        for warning in other.entries {
+            if !self.entries.contains(&warning) {
+                self.entries.push(warning);
+            }
+        }
+    }
+
+    pub fn contains(&self, code: WarningCode) -> bool {
+        self.entries.iter().any(|warning| warning.code == code)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Warning> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The consolidated section printed at the end of a run, `None` if there were no warnings.
+    pub fn summary(&self) -> Option<String> {
+        if self.entries.is_empty() {
+            return None;
+        }
+
+        let mut summary = format!(
+            "Completed with {} warning{}:",
+            self.entries.len(),
+            if self.entries.len() == 1 { "" } else { "s" }
+        );
+        for warning in &self.entries {
+            summary.push_str(&format!("\n    [{}] {}", warning.code, warning.message));
+        }
+        Some(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_round_trip() {
+        for code in WarningCode::ALL {
+            assert_eq!(code.as_str().parse(), Ok(code));
+        }
+        assert_eq!(
+            "nope".parse::<WarningCode>(),
+            Err(UnknownWarningCode("nope".to_string()))
+        );
+    }
+
+    #[test]
+    fn summary_lists_every_warning() {
+        let mut warnings = Warnings::new();
+        assert_eq!(warnings.summary(), None);
+
+        warnings.push(WarningCode::WriteFailed, "Failed to write out.uf2");
+        assert_eq!(
+            warnings.summary().unwrap(),
+            "Completed with 1 warning:\n    [write-failed] Failed to write out.uf2"
+        );
+
+        let mut other = Warnings::new();
+        other.push(WarningCode::DeviceSkipped, "Skipped device 1");
+        other.push(WarningCode::WriteFailed, "Failed to write out.uf2");
+        warnings.extend(other);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings.contains(WarningCode::DeviceSkipped));
+        assert!(!warnings.contains(WarningCode::FillerInflation));
+        assert!(
+            warnings
+                .summary()
+                .unwrap()
+                .starts_with("Completed with 2 warnings:")
+        );
+    }
+}
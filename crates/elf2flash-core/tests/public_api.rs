@@ -0,0 +1,484 @@
+//! Fails to compile when a supported item is removed, renamed or changes its signature, bump the
+//! major version when any of these need to change.
+
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    io::{Cursor, Read, Seek, Write},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use elf::{ElfBytes, endian::AnyEndian};
+use elf2flash_core::{
+    events::IgnoreReason,
+    extension::{EncodedTags, ExtensionTagError, Md5Area, parse_extension_tags},
+    info_uf2::InfoUf2,
+    input::{InputFormat, IntelHexError, parse_intel_hex},
+    pages::{
+        PageFragment, PageMap, get_page_fragments, get_page_fragments_from_segments, realize_page,
+    },
+    prelude::*,
+    progress::{ProgressRead, ProgressWrite},
+    transforms::{
+        PageTransform, RP2040_BOOT2_ADDRESS, RP2040_BOOT2_SIZE, crc32_mpeg2, rp2040_boot2_checksum,
+    },
+    uf2::{
+        BinImage, BlockHeaderTemplate, FileSizeField, MAX_BIN_SIZE, MergeSummary,
+        UF2_ABSOLUTE_FAMILY_ID, UF2_BLOCK_SIZE, UF2_FLAG_EXTENSION_TAGS_PRESENT,
+        UF2_FLAG_FAMILY_ID_PRESENT, UF2_FLAG_FILE_CONTAINER, UF2_FLAG_MD5_PRESENT,
+        UF2_FLAG_NOT_MAIN_FLASH, UF2_MAGIC_END, UF2_MAGIC_START0, UF2_MAGIC_START1,
+        UF2_TAG_DESCRIPTION, UF2_TAG_DEVICE_TYPE_ID, UF2_TAG_FIRMWARE_VERSION, Uf2Block,
+        Uf2BlockError, Uf2MergeError, Uf2ToBinError, VerifyError, VerifyReport, merge, to_bin,
+        verify_against_elf,
+    },
+    usb_cdc::likely_has_cdc,
+    warnings::UnknownWarningCode,
+};
+
+type Input<'a> = Cursor<&'a [u8]>;
+
+const HELLO_USB: &[u8] = include_bytes!("rp2040/hello_usb.elf");
+const HELLO_USB_UF2: &[u8] = include_bytes!("rp2040/hello_usb.uf2");
+
+#[test]
+fn conversion_functions() {
+    let board: &dyn BoardInfo = &RP2040;
+    let options = Uf2Options::default();
+    let mut output = Vec::new();
+
+    let _: Result<(), Elf2Uf2Error> =
+        elf2uf2(Cursor::new(HELLO_USB), &mut output, board, NoProgress);
+    let _: Result<ConversionSummary, Elf2Uf2Error> = elf2uf2_with_options(
+        Cursor::new(HELLO_USB),
+        &mut output,
+        board,
+        &options,
+        NoProgress,
+    );
+    let _: Result<(), Elf2Uf2Error> =
+        elf2uf2_multi(&[(HELLO_USB, 0xe48bff56)], &mut output, board, NoProgress);
+    let _: Result<ConversionSummary, Elf2Uf2Error> = elf2uf2_multi_with_options(
+        &[(HELLO_USB, 0xe48bff56)],
+        &mut output,
+        board,
+        &options,
+        NoProgress,
+    );
+    let _: Result<ConversionSummary, Elf2Uf2Error> = pages_to_uf2(
+        0x101ff000,
+        &[0; 256],
+        &mut output,
+        board,
+        &options,
+        NoProgress,
+    );
+    let _: Result<Uf2SizeEstimate, Elf2Uf2Error> = elf2uf2_size(Cursor::new(HELLO_USB), board);
+    let _: Result<Uf2SizeEstimate, Elf2Uf2Error> =
+        elf2uf2_size_with_options(Cursor::new(HELLO_USB), board, &options);
+}
+
+#[test]
+fn writer_and_iterator() {
+    let writer: Uf2Writer = Uf2Writer::new(&RP2040).options(Uf2Options::default());
+    let mut output = Vec::new();
+
+    let _: Result<ConversionSummary, Elf2Uf2Error> =
+        writer.write(Cursor::new(HELLO_USB), &mut output, NoProgress);
+    let _: Result<ConversionSummary, Elf2Uf2Error> =
+        writer.write_multi(&[(HELLO_USB, 0xe48bff56)], &mut output, NoProgress);
+    let _: Result<ConversionSummary, Elf2Uf2Error> =
+        writer.write_pages(0x101ff000, &[0; 256], &mut output, NoProgress);
+
+    let blocks: Uf2BlockIterator<Input> = writer.blocks(Cursor::new(HELLO_USB)).unwrap();
+    let _: u32 = blocks.num_blocks();
+    let _: usize = blocks.total_bytes();
+    let _: &ConversionSummary = blocks.summary();
+    let _: Result<bool, Elf2Uf2Error> = likely_has_cdc(Cursor::new(HELLO_USB));
+
+    let _: Result<Uf2BlockIterator<Input>, Elf2Uf2Error> =
+        Uf2BlockIterator::new(Cursor::new(HELLO_USB), &RP2040);
+    let _: Result<Uf2BlockIterator<Input>, Elf2Uf2Error> =
+        Uf2BlockIterator::with_options(Cursor::new(HELLO_USB), &RP2040, &Uf2Options::default());
+    let _: Result<Uf2BlockIterator<Cursor<Vec<u8>>>, Elf2Uf2Error> = Uf2BlockIterator::from_image(
+        &[(0x10000000, vec![0; 256])],
+        &RP2040,
+        &Uf2Options::default(),
+    );
+
+    fn block_iterator<I: ExactSizeIterator<Item = Result<[u8; UF2_BLOCK_SIZE], Elf2Uf2Error>>>() {}
+    block_iterator::<Uf2BlockIterator<Input>>();
+}
+
+#[test]
+fn options_and_summary() {
+    let Uf2Options {
+        not_main_flash: _,
+        extension_tags: _,
+        block_padding_byte: _,
+        fill_sectors: _,
+        pad_final_sector: _,
+        page_transforms: _,
+        fix_rp2040_boot2: _,
+        address_offset: _,
+        exclude_ranges: _,
+        exclude_sections: _,
+        flag_ram_only: _,
+        payload_size: _,
+        omit_family_id: _,
+    } = Uf2Options::default();
+    let _: Uf2Options = Uf2Options::elf2uf2_rs_compat();
+    let ConversionSummary {
+        num_blocks: _,
+        filler_blocks: _,
+        not_main_flash: _,
+        likely_has_cdc: _,
+        warnings: _,
+        events: _,
+    } = ConversionSummary::default();
+
+    fn error<E: std::error::Error + Send + Sync + 'static>() {}
+    error::<Elf2Uf2Error>();
+    error::<ExtensionTagError>();
+    error::<CustomBoardBuildError>();
+    error::<UnknownWarningCode>();
+    error::<Uf2MergeError>();
+    error::<Uf2BlockError>();
+    error::<IntelHexError>();
+}
+
+#[test]
+fn page_transforms() {
+    let transform: PageTransform = PageTransform::new(|_address: u64, _page: &mut [u8]| {});
+    transform.apply(RP2040_BOOT2_ADDRESS, &mut [0; RP2040_BOOT2_SIZE]);
+    let _: PageTransform = rp2040_boot2_checksum();
+    let _: u32 = crc32_mpeg2(&[]);
+}
+
+#[test]
+fn boards_by_value_or_reference() {
+    fn board_info<B: BoardInfo>() {}
+    board_info::<&RP2040>();
+    board_info::<&dyn BoardInfo>();
+    board_info::<Box<dyn BoardInfo>>();
+
+    fn convert(board: impl BoardInfo) -> Vec<u8> {
+        let mut output = Vec::new();
+        elf2uf2(Cursor::new(HELLO_USB), &mut output, board, NoProgress).unwrap();
+        output
+    }
+    let custom = CustomBoardBuilder::new()
+        .family_id(0xe48bff56)
+        .build()
+        .unwrap();
+    let boxed = BoardIter::find_by_name("rp2040").unwrap();
+
+    let outputs = [
+        convert(RP2040),
+        convert(&RP2040),
+        convert(&custom),
+        convert(boxed.as_ref()),
+        convert(&boxed),
+        convert(boxed),
+    ];
+    for output in outputs {
+        assert_eq!(output, HELLO_USB_UF2);
+    }
+}
+
+#[test]
+fn boards() {
+    fn board<B: BoardInfo + Default>() {}
+    board::<RP2040>();
+    board::<RP2350>();
+    board::<RP2350RiscV>();
+    board::<RP2350ArmNs>();
+    board::<CircuitPlaygroundBluefruit>();
+    board::<FeatherNrf52840Express>();
+    board::<ItsyBitsyNrf52840>();
+    board::<ClueNrf52840>();
+    board::<Esp32S2>();
+    board::<Esp32S3>();
+
+    let _: fn() -> BoardIter = BoardIter::new;
+    let _: fn(&str) -> Option<Box<dyn BoardInfo>> = BoardIter::find_by_name;
+    let _: fn(CustomBoard) = BoardRegistry::register;
+    let _: fn(&str) -> bool = BoardRegistry::unregister;
+    let _: fn() -> BoardIter = BoardRegistry::iter;
+    let _: fn(&str) -> Option<Arc<dyn BoardInfo>> = BoardRegistry::find_by_name;
+    let _: Cow<'static, str> = RP2040.board_name();
+    let _: Option<Cow<'static, str>> = RP2040.board_description();
+    let _: Vec<(u16, u16)> = RP2040.usb_matches();
+    let _: bool = RP2040.needs_eject();
+    let _: fn(CustomBoardBuilder, bool) -> CustomBoardBuilder = CustomBoardBuilder::needs_eject;
+    let _: &str = RP2040.uf2_filename();
+    let _: fn(CustomBoardBuilder, String) -> CustomBoardBuilder =
+        CustomBoardBuilder::uf2_filename::<String>;
+    let _: fn(&str) -> bool = is_fat_short_name;
+    let _: fn(&str) -> bool = is_valid_fat_name;
+    let _: &str = DEFAULT_UF2_FILENAME;
+    let _: usize = RP2040.write_chunk_size();
+    let _: Option<Duration> = RP2040.inter_chunk_delay();
+    let _: usize = DEFAULT_WRITE_CHUNK_SIZE;
+    let _: fn(CustomBoardBuilder, usize) -> CustomBoardBuilder =
+        CustomBoardBuilder::write_chunk_size;
+    let _: fn(CustomBoardBuilder, Duration) -> CustomBoardBuilder =
+        CustomBoardBuilder::inter_chunk_delay;
+    let _: fn(&Path) -> Result<Vec<CustomBoard>, BoardFileError> = load_from_toml;
+    let _: fn(&str) -> Result<Vec<CustomBoard>, BoardFileError> = parse_toml;
+    let _: fn() -> CustomBoardBuilder = CustomBoardBuilder::new;
+    let _: fn(CustomBoardBuilder, String) -> CustomBoardBuilder =
+        CustomBoardBuilder::serial_number::<String>;
+    let _: fn(CustomBoardBuilder, UsbVersion, UsbVersion) -> CustomBoardBuilder =
+        CustomBoardBuilder::version_range;
+    let _: fn(&dyn BoardInfo) -> CustomBoardBuilder = CustomBoardBuilder::from_board;
+    let _: fn(CustomBoardBuilder, Option<u32>, Option<u64>, Option<u32>) -> CustomBoardBuilder =
+        CustomBoardBuilder::apply_overrides;
+    let _: fn(CustomBoardBuilder, Option<u16>, Option<u16>) -> CustomBoardBuilder =
+        CustomBoardBuilder::usb_ids;
+    let _: fn(CustomBoardBuilder) -> Result<CustomBoard, CustomBoardBuildError> =
+        CustomBoardBuilder::build;
+    let _: [u32; 3] = [MIN_PAGE_SIZE, MAX_PAGE_SIZE, MAX_SPLIT_PAGE_SIZE];
+    let _ = UsbVersion(2, 0, 0);
+    let _: Option<UsbDevice> = None;
+    let _ = |device: UsbDevice| -> (UsbVersion, Option<String>) {
+        (device.version, device.serial_number)
+    };
+
+    let _: &[family::Family] = family::FAMILIES;
+    let _: Option<u32> = family::family_by_name("RP2040");
+    let _: Option<&str> = family::name_for_family(0xe48bff56);
+    let _: String = family::describe_family(0xe48bff56);
+
+    let info: InfoUf2 = InfoUf2::parse("UF2 Bootloader v3.0\nBoard-ID: RPI-RP2\n");
+    let InfoUf2 {
+        bootloader_version: _,
+        model: _,
+        board_id: _,
+        families: _,
+    } = info.clone();
+    let _: bool = info.board_id_starts_with("RPI");
+    let _: bool = RP2040.matches_info_uf2(&info);
+}
+
+#[test]
+fn input_formats() {
+    let format: InputFormat = InputFormat::detect(HELLO_USB);
+    match format {
+        InputFormat::Elf | InputFormat::Uf2 | InputFormat::IntelHex | InputFormat::Binary => {}
+    }
+    let _: String = format.to_string();
+    let _: Result<Vec<(u64, Vec<u8>)>, IntelHexError> = parse_intel_hex(":00000001FF\n");
+}
+
+#[test]
+fn extension_tags() {
+    let _ = [
+        ExtensionTag::Md5Checksum,
+        ExtensionTag::FirmwareVersion(String::new()),
+        ExtensionTag::Description(String::new()),
+        ExtensionTag::DeviceTypeId(0),
+        ExtensionTag::RawTag {
+            designator: 0,
+            data: Vec::new(),
+        },
+    ];
+    let _: fn(&[ExtensionTag], u32) -> Result<EncodedTags, ExtensionTagError> = EncodedTags::new;
+    let _: Option<Md5Area> = None;
+    let _ = parse_extension_tags;
+}
+
+#[test]
+fn progress_and_warnings() {
+    fn reporter<P: ProgressReporter>() {}
+    reporter::<NoProgress>();
+
+    fn write<W: Write>() {}
+    write::<ProgressWrite<Vec<u8>>>();
+    let _: Vec<u8> = ProgressWrite::new(Vec::new(), &mut NoProgress, 0).cancel();
+    NoProgress.cancel();
+    let _: bool = NoProgress.should_cancel();
+    let _: bool = ProgressWrite::new(Vec::new(), &mut NoProgress, 0).should_cancel();
+    NoProgress.phase(ProgressPhase::Converting);
+    let _ = [
+        ProgressPhase::Writing {
+            board_name: String::new(),
+        },
+        ProgressPhase::Verifying,
+    ];
+    let detail = ProgressDetail {
+        phase: ProgressPhase::Converting,
+        block_no: 0,
+        target_addr: 0x10000000,
+        file_offset: 0,
+    };
+    ProgressWrite::new(Vec::new(), &mut NoProgress, 0).detail(detail.clone());
+    NoProgress.detail(detail);
+    NoProgress.event(ConversionEvent::PaddingPageInserted { addr: 0x10000000 });
+    let _ = [
+        ConversionEvent::SegmentIgnored {
+            addr: 0x20000000,
+            size: 0x400,
+            reason: IgnoreReason::Uninitialized,
+        },
+        ConversionEvent::SegmentIgnored {
+            addr: 0x20000000,
+            size: 0x400,
+            reason: IgnoreReason::IgnoredRange,
+        },
+        ConversionEvent::PageExcluded { addr: 0x10000000 },
+        ConversionEvent::FamilyOverride {
+            from: 0xe48bff56,
+            to: 0xe48bff59,
+        },
+    ];
+    fn read<R: Read>() {}
+    read::<ProgressRead<Cursor<Vec<u8>>>>();
+    fn seek<R: Read + Seek>() {}
+    seek::<Input>();
+
+    let _: [WarningCode; 11] = WarningCode::ALL;
+    let Warning {
+        code: _,
+        message: _,
+    } = Warning {
+        code: WarningCode::FillerInflation,
+        message: String::new(),
+    };
+    let _: fn() -> Warnings = Warnings::new;
+    let _: fn(&Warnings) -> Option<String> = Warnings::summary;
+}
+
+#[test]
+fn uf2_constants() {
+    let _: [u32; 13] = [
+        UF2_MAGIC_START0,
+        UF2_MAGIC_START1,
+        UF2_MAGIC_END,
+        UF2_ABSOLUTE_FAMILY_ID,
+        UF2_FLAG_NOT_MAIN_FLASH,
+        UF2_FLAG_FILE_CONTAINER,
+        UF2_FLAG_FAMILY_ID_PRESENT,
+        UF2_FLAG_MD5_PRESENT,
+        UF2_FLAG_EXTENSION_TAGS_PRESENT,
+        UF2_TAG_FIRMWARE_VERSION,
+        UF2_TAG_DESCRIPTION,
+        UF2_TAG_DEVICE_TYPE_ID,
+        UF2_BLOCK_SIZE as u32,
+    ];
+}
+
+#[test]
+fn uf2_block() {
+    let bytes: [u8; UF2_BLOCK_SIZE] = HELLO_USB_UF2[..UF2_BLOCK_SIZE].try_into().unwrap();
+    let mut block: Uf2Block = Uf2Block::from_bytes(&bytes).unwrap();
+    let _: [u8; UF2_BLOCK_SIZE] = block.to_bytes();
+    let _: [u32; 5] = [
+        block.flags(),
+        block.target_addr(),
+        block.payload_size(),
+        block.block_no(),
+        block.num_blocks(),
+    ];
+    let _: Option<u32> = block.family_id();
+    let _: FileSizeField = block.file_size_field();
+    let _: &[u8] = block.payload();
+    let _: &[u8; 476] = block.data();
+    block.set_block_no(0);
+    block.set_num_blocks(1);
+    let _ = [
+        Uf2BlockError::InvalidMagic,
+        Uf2BlockError::PayloadTooLarge(0),
+    ];
+}
+
+#[test]
+fn uf2_block_header_template() {
+    let field = FileSizeField::FamilyId(0xe48bff56);
+    let _ = [
+        FileSizeField::FileSize(0),
+        FileSizeField::Unflagged(0),
+        FileSizeField::None,
+    ];
+    let _: [u32; 2] = [field.flags(), field.value()];
+    let template: BlockHeaderTemplate = BlockHeaderTemplate::new(field)
+        .add_flags(UF2_FLAG_NOT_MAIN_FLASH)
+        .payload_size(256)
+        .num_blocks(1);
+    let _: Uf2Block = template.block(0x10000000, 0, [0; 476]);
+}
+
+#[test]
+fn uf2_merge() {
+    let MergeSummary {
+        num_blocks: _,
+        families: _,
+        duplicate_blocks: _,
+    } = merge(&mut [&[][..]], Vec::new()).unwrap();
+}
+
+#[test]
+fn uf2_to_bin() {
+    let BinImage {
+        base_address: _,
+        data: _,
+    } = to_bin(HELLO_USB_UF2, 0xff).unwrap();
+    let _: usize = MAX_BIN_SIZE;
+    let _ = [
+        Uf2ToBinError::InvalidLength,
+        Uf2ToBinError::InvalidBlock(0),
+        Uf2ToBinError::NoFlashBlocks,
+        Uf2ToBinError::MultipleFamilies(None, None),
+        Uf2ToBinError::TooLarge { size: 0 },
+    ];
+}
+
+#[test]
+fn uf2_verify() {
+    let report = verify_against_elf(HELLO_USB_UF2, HELLO_USB, &RP2040).unwrap();
+    let _: bool = report.is_consistent();
+    let VerifyReport {
+        blocks_checked: _,
+        mismatched: _,
+        missing: _,
+        extra: _,
+    } = report;
+    let _ = [VerifyError::InvalidLength, VerifyError::InvalidBlock(0)];
+    fn error<E: std::error::Error + Send + Sync + 'static>() {}
+    error::<VerifyError>();
+}
+
+#[test]
+fn pages() {
+    let map: PageMap = PageMap::from_elf(HELLO_USB, 256).unwrap();
+    let _: u32 = map.page_size();
+    let _: &BTreeMap<u64, Vec<u8>> = map.pages();
+    let _: BTreeMap<u64, Vec<u8>> = map.into_pages();
+
+    let file = ElfBytes::<AnyEndian>::minimal_parse(HELLO_USB).unwrap();
+    let fragments: BTreeMap<u64, Vec<PageFragment>> = get_page_fragments(&file, 256).unwrap();
+    let PageFragment {
+        file_offset: _,
+        page_offset: _,
+        bytes: _,
+    } = fragments[&0x10000000][0];
+    let _: Result<BTreeMap<u64, Vec<PageFragment>>, Elf2Uf2Error> =
+        get_page_fragments_from_segments(&[], 256, &mut Cursor::new(HELLO_USB));
+    let mut page = [0; 256];
+    let _: Result<(), std::io::Error> = realize_page(
+        &mut Cursor::new(HELLO_USB),
+        &fragments[&0x10000000],
+        &mut page,
+        256,
+    );
+}
+
+#[test]
+#[allow(deprecated)]
+fn compatibility_shims() {
+    let fragments = [elf2flash_core::elf::PageFragment::default()];
+    let mut page = [0; 256];
+    let _: Result<(), std::io::Error> =
+        elf2flash_core::elf::realize_page(&mut Cursor::new(HELLO_USB), &fragments, &mut page, 256);
+}
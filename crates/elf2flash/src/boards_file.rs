@@ -0,0 +1,108 @@
+use anyhow::{Context, Result};
+use elf2flash_core::boards::{BoardRegistry, load_from_toml};
+use std::{ffi::OsString, path::PathBuf};
+
+use crate::config_file::config_dir;
+
+/// The value of `--boards-file` in `args`. The boards have to be registered before clap checks
+/// the `--board` names, so the flag is looked for before the command line is parsed.
+pub fn boards_file_arg(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--boards-file" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg
+            .to_str()
+            .and_then(|arg| arg.strip_prefix("--boards-file="))
+        {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// `$XDG_CONFIG_HOME/elf2flash/boards.toml`, or `~/.config/elf2flash/boards.toml`
+pub fn default_boards_file() -> Option<PathBuf> {
+    Some(config_dir()?.join("boards.toml"))
+}
+
+/// Register the boards of `--boards-file`, or of the default boards file when it exists.
+/// Returns the file they were loaded from.
+pub fn register_boards_file(args: &[OsString]) -> Result<Option<PathBuf>> {
+    let path = match boards_file_arg(args) {
+        Some(path) => path,
+        None => match default_boards_file() {
+            Some(path) if path.is_file() => path,
+            _ => return Ok(None),
+        },
+    };
+
+    let boards =
+        load_from_toml(&path).with_context(|| format!("Failed to load {}", path.display()))?;
+    boards.into_iter().for_each(BoardRegistry::register);
+    Ok(Some(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{board_parser, test_support::Unregister};
+    use std::fs;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn flag_is_found_before_parsing() {
+        assert_eq!(
+            boards_file_arg(&args(&["elf2flash", "--boards-file", "a.toml", "convert"])),
+            Some(PathBuf::from("a.toml"))
+        );
+        assert_eq!(
+            boards_file_arg(&args(&["elf2flash", "deploy", "--boards-file=b.toml"])),
+            Some(PathBuf::from("b.toml"))
+        );
+        assert_eq!(
+            boards_file_arg(&args(&["elf2flash", "convert", "--", "--boards-file"])),
+            None
+        );
+        assert_eq!(boards_file_arg(&args(&["elf2flash", "convert"])), None);
+    }
+
+    #[test]
+    fn board_parser_accepts_boards_from_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("boards.toml");
+        fs::write(
+            &path,
+            "[[board]]\nname = \"lab_board\"\nvendor_id = 0x1209\nproduct_id = 0x4c42\n\
+             family_id = \"SAMD21\"\n",
+        )
+        .unwrap();
+
+        assert!(board_parser("lab_board").is_err());
+        let _lab_board = Unregister("lab_board");
+        let loaded = register_boards_file(&args(&[
+            "elf2flash",
+            "--boards-file",
+            path.to_str().unwrap(),
+        ]))
+        .unwrap();
+        assert_eq!(loaded, Some(path));
+        assert_eq!(board_parser("LAB_BOARD").unwrap(), "lab_board");
+
+        let missing = dir.path().join("missing.toml");
+        let err = register_boards_file(&args(&[
+            "elf2flash",
+            "--boards-file",
+            missing.to_str().unwrap(),
+        ]))
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("Failed to read the board file"));
+    }
+}
@@ -0,0 +1,78 @@
+//! Stopping a deploy with Ctrl+C in between writes, so the device is released and the terminal
+//! restored instead of the process being killed mid-write.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::{Context, Result};
+use thiserror::Error;
+
+use crate::interactive;
+
+#[derive(Error, Debug)]
+#[error("Cancelled by Ctrl+C")]
+pub struct Cancelled;
+
+/// Shared flag telling long running loops to stop, checked at points where stopping leaves the
+/// device in a known state.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// A token that is only cancelled by [`CancellationToken::cancel`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token cancelled by Ctrl+C. The Ctrl+C handler can only be set once per process.
+    ///
+    /// Without a terminal there may be no console to install the handler on, e.g. in a Windows
+    /// service, the run then goes on with a token nothing cancels.
+    pub fn ctrl_c() -> Result<Self> {
+        let token = Self::new();
+        let handler = token.clone();
+        match ctrlc::set_handler(move || handler.cancel()) {
+            Ok(()) => Ok(token),
+            Err(err) if !interactive::is_interactive() => {
+                log::debug!("Not handling Ctrl+C: {err}");
+                Ok(token)
+            }
+            Err(err) => Err(err).context("Failed to set the Ctrl+C handler"),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Fail with [`Cancelled`] once the token is cancelled.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_the_cancellation() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(token.check().is_ok());
+
+        clone.cancel();
+        assert!(token.is_cancelled());
+        assert!(token.check().is_err());
+    }
+}
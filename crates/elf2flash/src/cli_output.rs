@@ -0,0 +1,169 @@
+//! How the logs, the closing summary and the final error look on the terminal. Errors are red,
+//! warnings yellow and a summary of devices written green, so a warning stands out of the device
+//! enumeration around it. Colors are only written to a terminal, unless `--color always` says
+//! otherwise, and never with `NO_COLOR` set.
+
+use std::{
+    env,
+    fmt::Display,
+    io::{self, IsTerminal},
+    sync::OnceLock,
+};
+
+use clap::ValueEnum;
+use env_logger::fmt::style::{AnsiColor, Style};
+use log::Level;
+
+const ERROR: Style = AnsiColor::Red.on_default().bold();
+const WARN: Style = AnsiColor::Yellow.on_default();
+const SUCCESS: Style = AnsiColor::Green.on_default();
+
+/// When to color the output, for `--color`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// On a terminal, unless NO_COLOR is set
+    #[default]
+    Auto,
+    /// Even when piped, or with NO_COLOR set
+    Always,
+    /// Not at all
+    Never,
+}
+
+/// Whether a stream is colored, given the `--color` and whether `NO_COLOR` is set to something.
+/// `--color always` wins over `NO_COLOR`, as the flag was given for this run.
+pub fn use_color(choice: ColorChoice, no_color: bool, is_terminal: bool) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => !no_color && is_terminal,
+    }
+}
+
+/// Whether stdout and stderr are colored
+#[derive(Debug, Clone, Copy)]
+struct Streams {
+    stdout: bool,
+    stderr: bool,
+}
+
+static COLOR: OnceLock<Streams> = OnceLock::new();
+
+/// Decide once at startup which of stdout and stderr are colored.
+pub fn init(choice: ColorChoice) {
+    let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let _ = COLOR.set(Streams {
+        stdout: use_color(choice, no_color, io::stdout().is_terminal()),
+        stderr: use_color(choice, no_color, io::stderr().is_terminal()),
+    });
+}
+
+/// Whether stdout is colored, not before [`init`].
+pub fn stdout_color() -> bool {
+    COLOR.get().is_some_and(|streams| streams.stdout)
+}
+
+/// Whether stderr is colored, not before [`init`].
+pub fn stderr_color() -> bool {
+    COLOR.get().is_some_and(|streams| streams.stderr)
+}
+
+fn paint(text: impl Display, style: Style, color: bool) -> String {
+    if color {
+        format!("{style}{text}{style:#}")
+    } else {
+        text.to_string()
+    }
+}
+
+/// A log record as a line: info as it is, the other levels behind their name, e.g.
+/// `WARN: Device 3-1 was skipped`.
+pub fn format_record(level: Level, message: impl Display, color: bool) -> String {
+    match level {
+        Level::Info => message.to_string(),
+        Level::Error => paint(format_args!("{level}: {message}"), ERROR, color),
+        Level::Warn => paint(format_args!("{level}: {message}"), WARN, color),
+        Level::Debug | Level::Trace => format!("{level}: {message}"),
+    }
+}
+
+/// The error a run failed with, e.g. `Error: Input file firmware.elf doesn't exist`.
+pub fn format_error(err: impl Display, color: bool) -> String {
+    paint(format_args!("Error: {err}"), ERROR, color)
+}
+
+/// A line of the closing summary, green when it tells of a success and red otherwise.
+pub fn format_outcome(line: &str, success: bool, color: bool) -> String {
+    paint(line, if success { SUCCESS } else { ERROR }, color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_follows_the_flag_then_no_color_then_the_terminal() {
+        use ColorChoice::{Always, Auto, Never};
+
+        // (choice, no_color, is_terminal) => colored
+        let cases = [
+            ((Auto, false, true), true),
+            ((Auto, false, false), false),
+            ((Auto, true, true), false),
+            ((Always, true, false), true),
+            ((Never, false, true), false),
+        ];
+        for ((choice, no_color, is_terminal), expected) in cases {
+            assert_eq!(
+                use_color(choice, no_color, is_terminal),
+                expected,
+                "--color {choice:?}, NO_COLOR {no_color}, terminal {is_terminal}"
+            );
+        }
+    }
+
+    #[test]
+    fn records_are_colored_by_level() {
+        assert_eq!(
+            format_record(Level::Error, "Device gone", true),
+            "\x1b[1m\x1b[31mERROR: Device gone\x1b[0m"
+        );
+        assert_eq!(
+            format_record(Level::Warn, "Device skipped", true),
+            "\x1b[33mWARN: Device skipped\x1b[0m"
+        );
+        assert_eq!(format_record(Level::Info, "Flashing", true), "Flashing");
+        assert_eq!(format_record(Level::Debug, "Opened", true), "DEBUG: Opened");
+
+        assert_eq!(
+            format_record(Level::Error, "Device gone", false),
+            "ERROR: Device gone"
+        );
+        assert_eq!(
+            format_record(Level::Warn, "Device skipped", false),
+            "WARN: Device skipped"
+        );
+        assert_eq!(format_record(Level::Info, "Flashing", false), "Flashing");
+    }
+
+    #[test]
+    fn errors_and_outcomes_are_colored() {
+        assert_eq!(
+            format_error("No device found", true),
+            "\x1b[1m\x1b[31mError: No device found\x1b[0m"
+        );
+        assert_eq!(
+            format_error("No device found", false),
+            "Error: No device found"
+        );
+        assert_eq!(
+            format_outcome("rp2040 (bus 3, addr 16): 512 bytes", true, true),
+            "\x1b[32mrp2040 (bus 3, addr 16): 512 bytes\x1b[0m"
+        );
+        assert_eq!(
+            format_outcome("rp2040 (bus 3, addr 16): failed", false, true),
+            "\x1b[1m\x1b[31mrp2040 (bus 3, addr 16): failed\x1b[0m"
+        );
+        assert_eq!(format_outcome("Total", true, false), "Total");
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::Result;
+use clap::Args;
+use elf2flash_core::boards::{
+    BoardInfo, BoardIter,
+    family::{describe_family, name_for_family},
+};
+use serde::Serialize;
+
+use crate::output;
+
+#[derive(Args, Debug)]
+pub struct BoardsArgs {
+    /// Print the boards as a JSON array instead of a table
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UsbId {
+    pub vendor_id: u16,
+    pub product_id: u16,
+}
+
+/// What `--board` accepts, and what it means for the conversion and for finding devices.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BoardRow {
+    pub name: String,
+    pub description: Option<String>,
+    pub family_id: u32,
+    /// Short name of the family id, if it is in the uf2 family list
+    pub family: Option<String>,
+    pub page_size: u32,
+    pub flash_sector_erase_size: u64,
+    /// The devices recognized as the board, empty when the board can't list them
+    pub usb_ids: Vec<UsbId>,
+}
+
+impl BoardRow {
+    pub fn new(board: &dyn BoardInfo) -> Self {
+        Self {
+            name: board.board_name().into_owned(),
+            description: board.board_description().map(|d| d.into_owned()),
+            family_id: board.family_id(),
+            family: name_for_family(board.family_id()).map(str::to_string),
+            page_size: board.page_size(),
+            flash_sector_erase_size: board.flash_sector_erase_size(),
+            usb_ids: board
+                .usb_matches()
+                .into_iter()
+                .map(|(vendor_id, product_id)| UsbId {
+                    vendor_id,
+                    product_id,
+                })
+                .collect(),
+        }
+    }
+
+    fn cells(&self) -> [String; 6] {
+        let usb_ids = match self.usb_ids.as_slice() {
+            [] => "-".to_string(),
+            usb_ids => usb_ids
+                .iter()
+                .map(|id| format!("{:04x}:{:04x}", id.vendor_id, id.product_id))
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        [
+            self.name.clone(),
+            describe_family(self.family_id),
+            self.page_size.to_string(),
+            self.flash_sector_erase_size.to_string(),
+            usb_ids,
+            self.description.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+/// `rows` as a table with a header, every column but the last padded to its widest cell.
+pub fn render_table(rows: &[BoardRow]) -> String {
+    let header = [
+        "NAME",
+        "FAMILY ID",
+        "PAGE SIZE",
+        "ERASE SIZE",
+        "USB IDS",
+        "DESCRIPTION",
+    ]
+    .map(str::to_string);
+    let lines: Vec<[String; 6]> = std::iter::once(header)
+        .chain(rows.iter().map(BoardRow::cells))
+        .collect();
+
+    let mut widths = [0; 6];
+    for line in &lines {
+        for (width, cell) in widths.iter_mut().zip(line) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut table = String::new();
+    for line in &lines {
+        let (last, padded) = line.split_last().expect("rows have six cells");
+        for (cell, width) in padded.iter().zip(widths) {
+            table.push_str(&format!("{cell:width$}  "));
+        }
+        table.push_str(last);
+        table.truncate(table.trim_end().len());
+        table.push('\n');
+    }
+    table
+}
+
+/// List every board `--board` accepts, the built-in ones and those from the boards file.
+pub fn boards(args: BoardsArgs) -> Result<()> {
+    let rows: Vec<BoardRow> = BoardIter::new()
+        .map(|board| BoardRow::new(board.as_ref()))
+        .collect();
+
+    if output::is_json() {
+        output::emit("boards", Some(&rows), None)?;
+    } else if args.json {
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+    } else {
+        print!("{}", render_table(&rows));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elf2flash_core::boards::{CustomBoardBuilder, RP2040};
+
+    #[test]
+    fn boards_without_usb_ids_or_description_are_listed() {
+        let custom = CustomBoardBuilder::new()
+            .board_name("bench")
+            .family_id(0x12345678)
+            .page_size(512)
+            .build()
+            .unwrap();
+        let rows = [BoardRow::new(&RP2040), BoardRow::new(&custom)];
+
+        assert_eq!(
+            render_table(&rows),
+            "NAME    FAMILY ID            PAGE SIZE  ERASE SIZE  USB IDS    DESCRIPTION\n\
+             rp2040  0xe48bff56 (RP2040)  256        4096        2e8a:0003  Raspberry Pi RP2040\n\
+             bench   0x12345678           512        4096        -\n"
+        );
+
+        let json = serde_json::to_value(&rows).unwrap();
+        assert_eq!(json[0]["family"], "RP2040");
+        assert_eq!(json[0]["usb_ids"][0]["product_id"], 3);
+        assert!(json[1]["description"].is_null());
+    }
+}
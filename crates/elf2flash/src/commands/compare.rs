@@ -0,0 +1,396 @@
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use elf2flash_core::{
+    boards::{BoardIter, family::describe_family},
+    uf2::{
+        UF2_ABSOLUTE_FAMILY_ID, UF2_BLOCK_SIZE, UF2_FLAG_EXTENSION_TAGS_PRESENT,
+        UF2_FLAG_MD5_PRESENT, UF2_FLAG_NOT_MAIN_FLASH, Uf2Block,
+    },
+};
+use std::{collections::BTreeMap, fmt, fs, path::PathBuf};
+
+/// Differences printed before the rest are only counted
+const MAX_PRINTED: usize = 20;
+
+/// The flash sector size of families no known board has
+const DEFAULT_SECTOR_SIZE: u32 = 4096;
+
+/// Flags that only tell the bootloader about the block, the flashed bytes are the same with or
+/// without them. The RP2040 and RP2350 bootroms load RAM blocks whether or not they are flagged as
+/// not main flash.
+const BENIGN_FLAGS: u32 =
+    UF2_FLAG_NOT_MAIN_FLASH | UF2_FLAG_MD5_PRESENT | UF2_FLAG_EXTENSION_TAGS_PRESENT;
+
+#[derive(Args, Debug)]
+pub struct CompareArgs {
+    /// The uf2 file to compare against, e.g. the output of the converter being replaced
+    pub expected: PathBuf,
+
+    /// The uf2 file to check
+    pub actual: PathBuf,
+}
+
+/// A page of a uf2 file, blocks of different families writing to the same address are different
+/// pages.
+type PageKey = (Option<u32>, u32);
+
+/// Which of the compared files a page is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Expected,
+    Actual,
+}
+
+/// A way two uf2 files differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// Both files write the same pages, in a different order
+    BlockOrder,
+    /// A page both files write has different flags
+    Flags {
+        page: PageKey,
+        expected: u32,
+        actual: u32,
+    },
+    /// A page only one of the files writes. `sector_fill` is set when it only holds one repeated
+    /// byte and the other file writes other pages of its flash sector, like the pages a converter
+    /// fills the touched sectors with
+    OnlyIn {
+        side: Side,
+        page: PageKey,
+        sector_fill: bool,
+    },
+    /// A page both files write with different contents
+    Contents { page: PageKey },
+}
+
+impl Difference {
+    /// Whether the difference doesn't change what ends up on the board: blocks are written to
+    /// their address in any order, sector fill policies only differ in filler pages of the sectors
+    /// both files write, and [`BENIGN_FLAGS`] don't change the payload. A page of a sector only
+    /// one file writes is flashed, even when it is all 0x00 or 0xff.
+    pub fn is_benign(&self) -> bool {
+        match self {
+            Difference::BlockOrder => true,
+            Difference::Flags {
+                expected, actual, ..
+            } => (expected ^ actual) & !BENIGN_FLAGS == 0,
+            Difference::OnlyIn {
+                page, sector_fill, ..
+            } => *sector_fill || page.0 == Some(UF2_ABSOLUTE_FAMILY_ID),
+            Difference::Contents { .. } => false,
+        }
+    }
+}
+
+fn describe_page((family_id, address): &PageKey) -> String {
+    match family_id {
+        Some(family_id) => format!("{address:#010x} of family {}", describe_family(*family_id)),
+        None => format!("{address:#010x}"),
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::BlockOrder => write!(f, "the blocks are in a different order"),
+            Difference::Flags {
+                page,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} has flags {actual:#010x} instead of {expected:#010x}",
+                describe_page(page)
+            ),
+            Difference::OnlyIn {
+                side,
+                page,
+                sector_fill,
+            } => {
+                let side = match side {
+                    Side::Expected => "expected",
+                    Side::Actual => "actual",
+                };
+                let fill = if *sector_fill { " sector fill" } else { "" };
+                write!(
+                    f,
+                    "{}{fill} is only in the {side} file",
+                    describe_page(page)
+                )
+            }
+            Difference::Contents { page } => {
+                write!(f, "{} has different contents", describe_page(page))
+            }
+        }
+    }
+}
+
+/// The pages of a uf2 file in the order they are written, a page written twice keeps the contents
+/// the bootloader is left with.
+fn pages(uf2: &[u8]) -> Result<(Vec<PageKey>, BTreeMap<PageKey, Uf2Block>)> {
+    if !uf2.len().is_multiple_of(UF2_BLOCK_SIZE) {
+        bail!("The file isn't a whole number of {UF2_BLOCK_SIZE} byte blocks");
+    }
+
+    let mut order = Vec::new();
+    let mut pages = BTreeMap::new();
+    for (index, block) in uf2.chunks_exact(UF2_BLOCK_SIZE).enumerate() {
+        let block = Uf2Block::from_bytes(block.try_into().expect("chunks are whole blocks"))
+            .with_context(|| format!("Block {index} is invalid"))?;
+        let key = (block.family_id(), block.target_addr());
+        if pages.insert(key, block).is_none() {
+            order.push(key);
+        }
+    }
+    Ok((order, pages))
+}
+
+/// The flash sector `address` is in, with the sector size of the board of `family_id`.
+fn sector_of(family_id: Option<u32>, address: u32) -> u32 {
+    let sector_size = family_id
+        .and_then(|family_id| BoardIter::new().find(|board| board.family_id() == family_id))
+        .map_or(DEFAULT_SECTOR_SIZE, |board| {
+            board.flash_sector_erase_size() as u32
+        });
+    address / sector_size
+}
+
+/// Compare the pages two uf2 files write, ignoring block numbers.
+pub fn compare_uf2(expected: &[u8], actual: &[u8]) -> Result<Vec<Difference>> {
+    let (expected_order, expected) = pages(expected).context("Invalid expected uf2 file")?;
+    let (actual_order, actual) = pages(actual).context("Invalid actual uf2 file")?;
+
+    let mut differences = Vec::new();
+
+    let in_both = |order: &[PageKey], other: &BTreeMap<PageKey, Uf2Block>| {
+        order
+            .iter()
+            .filter(|key| other.contains_key(key))
+            .copied()
+            .collect::<Vec<_>>()
+    };
+    if in_both(&expected_order, &actual) != in_both(&actual_order, &expected) {
+        differences.push(Difference::BlockOrder);
+    }
+
+    let is_sector_fill = |block: &Uf2Block, other: &BTreeMap<PageKey, Uf2Block>| {
+        let payload = block.payload();
+        let sector = sector_of(block.family_id(), block.target_addr());
+        payload.iter().all(|&byte| Some(&byte) == payload.first())
+            && other.keys().any(|&(family_id, address)| {
+                family_id == block.family_id() && sector_of(family_id, address) == sector
+            })
+    };
+
+    for (page, block) in &expected {
+        let Some(other) = actual.get(page) else {
+            differences.push(Difference::OnlyIn {
+                side: Side::Expected,
+                page: *page,
+                sector_fill: is_sector_fill(block, &actual),
+            });
+            continue;
+        };
+        if block.flags() != other.flags() {
+            differences.push(Difference::Flags {
+                page: *page,
+                expected: block.flags(),
+                actual: other.flags(),
+            });
+        }
+        if block.payload() != other.payload() {
+            differences.push(Difference::Contents { page: *page });
+        }
+    }
+
+    for (page, block) in &actual {
+        if !expected.contains_key(page) {
+            differences.push(Difference::OnlyIn {
+                side: Side::Actual,
+                page: *page,
+                sector_fill: is_sector_fill(block, &expected),
+            });
+        }
+    }
+
+    Ok(differences)
+}
+
+pub fn compare(args: CompareArgs) -> Result<()> {
+    let CompareArgs { expected, actual } = args;
+
+    let read = |path: &PathBuf| {
+        fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+    };
+    let differences = compare_uf2(&read(&expected)?, &read(&actual)?)?;
+
+    if differences.is_empty() {
+        log::info!(
+            "{} and {} write the same pages",
+            expected.display(),
+            actual.display()
+        );
+        return Ok(());
+    }
+
+    for difference in differences.iter().take(MAX_PRINTED) {
+        let kind = if difference.is_benign() {
+            "benign"
+        } else {
+            "differs"
+        };
+        log::info!("{kind}: {difference}");
+    }
+    if differences.len() > MAX_PRINTED {
+        log::info!("... and {} more", differences.len() - MAX_PRINTED);
+    }
+
+    let significant = differences.iter().filter(|d| !d.is_benign()).count();
+    if significant > 0 {
+        bail!(
+            "{significant} of the {} differences change what is flashed",
+            differences.len()
+        );
+    }
+
+    log::info!(
+        "All {} differences are benign, both files flash the same",
+        differences.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elf2flash_core::{
+        NoProgress, Uf2Options,
+        boards::{BoardInfo, RP2040},
+        elf2uf2_with_options, pages_to_uf2,
+    };
+    use std::io::Cursor;
+
+    const HELLO_USB: &[u8] = include_bytes!("../../../elf2flash-core/tests/rp2040/hello_usb.elf");
+    const RAM_ONLY: &[u8] = include_bytes!("../../../elf2flash-core/tests/rp2040/ram_only.elf");
+
+    fn convert(elf: &[u8], options: &Uf2Options) -> Vec<u8> {
+        let mut uf2 = Vec::new();
+        elf2uf2_with_options(Cursor::new(elf), &mut uf2, &RP2040, options, NoProgress).unwrap();
+        uf2
+    }
+
+    #[test]
+    fn identical_files_have_no_differences() {
+        let uf2 = convert(HELLO_USB, &Uf2Options::default());
+        assert_eq!(compare_uf2(&uf2, &uf2).unwrap(), []);
+    }
+
+    #[test]
+    fn compat_differences_are_benign() {
+        let compat = convert(RAM_ONLY, &Uf2Options::elf2uf2_rs_compat());
+        let flagged = convert(RAM_ONLY, &Uf2Options::default());
+        let differences = compare_uf2(&compat, &flagged).unwrap();
+        assert!(!differences.is_empty());
+        assert!(differences.iter().all(|d| matches!(
+            d,
+            Difference::Flags { expected, actual, .. }
+                if expected ^ actual == UF2_FLAG_NOT_MAIN_FLASH
+        )));
+        assert!(differences.iter().all(Difference::is_benign));
+
+        // The pages before the data in its flash sector are only filled in one of them
+        let write = |options: &Uf2Options| {
+            let mut uf2 = Vec::new();
+            pages_to_uf2(
+                0x10000400,
+                &[0x5a; 256],
+                &mut uf2,
+                &RP2040,
+                options,
+                NoProgress,
+            )
+            .unwrap();
+            uf2
+        };
+        let unfilled = Uf2Options {
+            fill_sectors: false,
+            ..Default::default()
+        };
+        let differences =
+            compare_uf2(&write(&Uf2Options::elf2uf2_rs_compat()), &write(&unfilled)).unwrap();
+        assert!(!differences.is_empty());
+        assert!(differences.iter().all(|d| matches!(
+            d,
+            Difference::OnlyIn {
+                side: Side::Expected,
+                sector_fill: true,
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn erased_pages_of_other_sectors_are_not_benign() {
+        let write = |pages: &[(u64, u8)]| {
+            let mut uf2 = Vec::new();
+            for &(address, byte) in pages {
+                pages_to_uf2(
+                    address,
+                    &[byte; 256],
+                    &mut uf2,
+                    &RP2040,
+                    &Uf2Options {
+                        fill_sectors: false,
+                        ..Default::default()
+                    },
+                    NoProgress,
+                )
+                .unwrap();
+            }
+            uf2
+        };
+        let program = write(&[(0x10000000, 0x5a)]);
+
+        // A page of 0xff or 0x00 in a sector of its own is real data, e.g. a cleared config page
+        for byte in [0xff, 0x00] {
+            let differences =
+                compare_uf2(&program, &write(&[(0x10000000, 0x5a), (0x10001000, byte)])).unwrap();
+            assert_eq!(
+                differences,
+                [Difference::OnlyIn {
+                    side: Side::Actual,
+                    page: (Some(RP2040.family_id()), 0x10001000),
+                    sector_fill: false,
+                }]
+            );
+            assert!(!differences[0].is_benign());
+        }
+    }
+
+    #[test]
+    fn changed_contents_are_not_benign() {
+        let uf2 = convert(HELLO_USB, &Uf2Options::default());
+        let mut changed = uf2.clone();
+        // A byte of the first block's payload
+        changed[32] ^= 0xff;
+
+        let differences = compare_uf2(&uf2, &changed).unwrap();
+        assert_eq!(
+            differences,
+            [Difference::Contents {
+                page: (Some(RP2040.family_id()), 0x10000000)
+            }]
+        );
+        assert!(!differences[0].is_benign());
+
+        // Reordered blocks still write the same
+        let mut reordered = uf2.clone();
+        let (first, second) = reordered.split_at_mut(UF2_BLOCK_SIZE);
+        first.swap_with_slice(&mut second[..UF2_BLOCK_SIZE]);
+        assert_eq!(
+            compare_uf2(&uf2, &reordered).unwrap(),
+            [Difference::BlockOrder]
+        );
+    }
+}
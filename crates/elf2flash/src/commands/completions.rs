@@ -0,0 +1,63 @@
+use std::io::{self, Write};
+
+use anyhow::{Result, bail};
+use clap::{Args, CommandFactory};
+use clap_complete::Shell;
+
+use crate::{Cli, output};
+
+#[derive(Args, Debug)]
+pub struct CompletionsArgs {
+    /// Shell to print the script of
+    #[clap(value_enum)]
+    pub shell: Shell,
+}
+
+/// Write the completion script of `shell` to `out`.
+pub fn write_completions(shell: Shell, out: &mut dyn Write) {
+    clap_complete::generate(shell, &mut Cli::command(), "elf2flash", out);
+}
+
+/// Print the completion script of a shell, e.g. `elf2flash completions bash > elf2flash.bash`.
+pub fn completions(args: CompletionsArgs) -> Result<()> {
+    if output::is_json() {
+        bail!("completions prints the script on stdout, it can't be used with --format json");
+    }
+    let mut stdout = io::stdout().lock();
+    write_completions(args.shell, &mut stdout);
+    stdout.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::register_board;
+    use elf2flash_core::boards::CustomBoardBuilder;
+
+    #[test]
+    fn bash_completes_every_board() {
+        let _completion_board = register_board(
+            "completion_board",
+            CustomBoardBuilder::new()
+                .board_name("completion_board")
+                .vendor_id(0x1209)
+                .product_id(0xc0de)
+                .family_id(0xc0dec0de)
+                .build()
+                .unwrap(),
+        );
+
+        let mut script = Vec::new();
+        write_completions(Shell::Bash, &mut script);
+        let script = String::from_utf8(script).unwrap();
+
+        let boards = script
+            .lines()
+            .find(|line| line.contains("compgen -W") && line.contains("rp2040"))
+            .expect("--board completes the board names");
+        for board in ["rp2040", "rp2350", "completion_board"] {
+            assert!(boards.contains(board), "{board} is missing from {boards}");
+        }
+    }
+}
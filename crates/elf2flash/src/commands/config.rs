@@ -0,0 +1,89 @@
+use anyhow::Result;
+use clap::{Args, Subcommand};
+
+use crate::{
+    config_file::{ConfigEntry, ConfigLayers, PROJECT_CONFIG_FILE, global_config_file},
+    output,
+};
+
+#[derive(Args, Debug)]
+pub struct ConfigArgs {
+    #[clap(subcommand)]
+    pub command: ConfigCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the settings the config files add up to, each with the file it comes from
+    Show,
+}
+
+/// `entries` as `key = value` lines, each followed by the file it comes from.
+pub fn render_entries(entries: &[ConfigEntry]) -> String {
+    let lines: Vec<String> = entries
+        .iter()
+        .map(|entry| format!("{} = {}", entry.key, entry.value))
+        .collect();
+    let width = lines.iter().map(String::len).max().unwrap_or(0);
+
+    let mut rendered = String::new();
+    for (line, entry) in lines.iter().zip(entries) {
+        rendered.push_str(&format!("{line:width$}  # {}\n", entry.source.display()));
+    }
+    rendered
+}
+
+pub fn config(args: ConfigArgs, layers: &ConfigLayers) -> Result<()> {
+    match args.command {
+        ConfigCommand::Show => {
+            let entries = layers.entries();
+            if output::is_json() {
+                output::emit("config", Some(&entries), None)?;
+                return Ok(());
+            }
+
+            if layers.files.is_empty() {
+                let global = global_config_file()
+                    .map(|path| format!(" or {}", path.display()))
+                    .unwrap_or_default();
+                println!(
+                    "No config file found, looked for {PROJECT_CONFIG_FILE} in the current \
+                     directory and its parents{global}"
+                );
+                return Ok(());
+            }
+            for (path, _) in &layers.files {
+                println!("# Loaded {}", path.display());
+            }
+            print!("{}", render_entries(&entries));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn entries_are_aligned_with_their_source() {
+        let entries = [
+            ConfigEntry {
+                key: "board".to_string(),
+                value: toml::Value::from("rp2350"),
+                source: PathBuf::from("/work/elf2flash.toml"),
+            },
+            ConfigEntry {
+                key: "serial".to_string(),
+                value: toml::Value::from(true),
+                source: PathBuf::from("/home/me/.config/elf2flash/config.toml"),
+            },
+        ];
+        assert_eq!(
+            render_entries(&entries),
+            "board = \"rp2350\"  # /work/elf2flash.toml\n\
+             serial = true     # /home/me/.config/elf2flash/config.toml\n"
+        );
+    }
+}
@@ -0,0 +1,495 @@
+//! Converting many ELF files in one run, listed in a TOML manifest or found in a directory.
+//!
+//! A manifest lists one `[[target]]` per ELF, paths are relative to the manifest:
+//!
+//! ```toml
+//! output_dir = "dist"
+//!
+//! [[target]]
+//! input = "builds/blinky.elf"
+//! board = "rp2040"
+//!
+//! [[target]]
+//! input = "builds/blinky-samd.elf"
+//! family = "SAMD51"
+//! output = "blinky-samd51.uf2"
+//! ```
+//!
+//! Targets fall back to the `--board`, `--family`, `--page-size` and `--flash-sector-erase-size`
+//! given on the command line. A failed target doesn't stop the others, every target gets a result.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use anyhow::{Context, Result, bail};
+use clap::{Args, ValueHint};
+use elf2flash_core::{
+    NoProgress, Uf2Options,
+    boards::{BoardInfo, CustomBoard},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    commands::convert::{BoardSpec, convert_file, resolve_board},
+    family_parser, output,
+};
+
+#[derive(Args, Debug, Default)]
+pub struct BatchArgs {
+    /// Convert the targets of a TOML manifest instead of a single input
+    #[clap(
+        long,
+        value_name = "MANIFEST",
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["input", "output", "batch_dir"]
+    )]
+    pub batch: Option<PathBuf>,
+
+    /// Convert every .elf file in this directory, for the board given by --board or --family
+    #[clap(
+        long,
+        value_name = "DIR",
+        value_hint = ValueHint::DirPath,
+        conflicts_with_all = ["input", "output"]
+    )]
+    pub batch_dir: Option<PathBuf>,
+
+    /// Directory the uf2 files of a batch are written to, named <input stem>.uf2 unless the
+    /// manifest names them. Defaults to the manifest's output_dir, or the directory of the
+    /// manifest or --batch-dir
+    #[clap(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+    pub output_dir: Option<PathBuf>,
+
+    /// Number of conversions of a batch to run at once, 0 for one per CPU
+    #[clap(long, value_name = "N", default_value_t = 1)]
+    pub jobs: usize,
+
+    /// Print the results of a batch as a JSON array once done
+    #[clap(long)]
+    pub json: bool,
+}
+
+impl BatchArgs {
+    pub fn is_batch(&self) -> bool {
+        self.batch.is_some() || self.batch_dir.is_some()
+    }
+}
+
+/// A family id in a manifest, either a number or a name like `"RP2040"`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ManifestFamily {
+    Id(u32),
+    Name(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestTarget {
+    pub input: PathBuf,
+    /// Defaults to `<input stem>.uf2` in the output directory
+    pub output: Option<PathBuf>,
+    pub board: Option<String>,
+    pub family: Option<ManifestFamily>,
+    pub page_size: Option<u32>,
+    pub flash_sector_erase_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Where outputs without a path of their own are written
+    pub output_dir: Option<PathBuf>,
+    #[serde(default, rename = "target")]
+    pub targets: Vec<ManifestTarget>,
+}
+
+/// One conversion of a batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Job {
+    input: PathBuf,
+    output: PathBuf,
+    board: BoardSpec,
+}
+
+/// The outcome of one conversion, `error` is set if it failed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BatchResult {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub board_name: Option<String>,
+    pub family_id: Option<u32>,
+    /// Size of the uf2 file in bytes
+    pub size: Option<u64>,
+    /// CRC-32 of the uf2 file
+    pub crc32: Option<u32>,
+    pub warnings: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// `<stem>.uf2` in `output_dir`, keeping every dot of the stem, `fw.v1.2.elf` is `fw.v1.2.uf2`.
+fn output_for(input: &Path, output_dir: &Path) -> PathBuf {
+    let mut name = input
+        .file_stem()
+        .unwrap_or(input.as_os_str())
+        .to_os_string();
+    name.push(".uf2");
+    output_dir.join(name)
+}
+
+fn manifest_jobs(path: &Path, defaults: &BoardSpec, output_dir: Option<&Path>) -> Result<Vec<Job>> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let manifest: Manifest =
+        toml::from_str(&text).with_context(|| format!("Invalid manifest {}", path.display()))?;
+
+    let base = path.parent().unwrap_or(Path::new(""));
+    let output_dir = match (output_dir, &manifest.output_dir) {
+        (Some(dir), _) => dir.to_path_buf(),
+        (None, Some(dir)) => base.join(dir),
+        (None, None) => base.to_path_buf(),
+    };
+
+    manifest
+        .targets
+        .into_iter()
+        .map(|target| {
+            let family = match target.family {
+                Some(ManifestFamily::Id(id)) => Some(id),
+                Some(ManifestFamily::Name(name)) => Some(family_parser(&name).map_err(|err| {
+                    anyhow::anyhow!("{}: {err} '{name}'", target.input.display())
+                })?),
+                None => defaults.family,
+            };
+            let input = base.join(&target.input);
+            let output = match &target.output {
+                Some(output) => output_dir.join(output),
+                None => output_for(&input, &output_dir),
+            };
+
+            Ok(Job {
+                board: BoardSpec {
+                    board: target.board.or_else(|| defaults.board.clone()),
+                    family,
+                    flash_sector_erase_size: target
+                        .flash_sector_erase_size
+                        .or(defaults.flash_sector_erase_size),
+                    page_size: target.page_size.or(defaults.page_size),
+                    ..defaults.clone()
+                },
+                input,
+                output,
+            })
+        })
+        .collect()
+}
+
+fn dir_jobs(dir: &Path, defaults: &BoardSpec, output_dir: Option<&Path>) -> Result<Vec<Job>> {
+    let output_dir = output_dir.unwrap_or(dir);
+
+    let mut inputs = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "elf") {
+            inputs.push(path);
+        }
+    }
+    inputs.sort();
+
+    Ok(inputs
+        .into_iter()
+        .map(|input| Job {
+            output: output_for(&input, output_dir),
+            input,
+            board: defaults.clone(),
+        })
+        .collect())
+}
+
+fn run_job(job: &Job, board: &Result<CustomBoard, String>, options: &Uf2Options) -> BatchResult {
+    let mut result = BatchResult {
+        input: job.input.clone(),
+        output: job.output.clone(),
+        board_name: job.board.board.clone(),
+        ..Default::default()
+    };
+
+    let board = match board {
+        Ok(board) => board,
+        Err(err) => {
+            result.error = Some(err.clone());
+            return result;
+        }
+    };
+    result.family_id = Some(board.family_id());
+
+    let converted = convert_file(&job.input, &job.output, board, options, &[], NoProgress)
+        .and_then(|summary| Ok((summary, fs::read(&job.output)?)));
+    match converted {
+        Ok((summary, uf2)) => {
+            result.size = Some(uf2.len() as u64);
+            result.crc32 = Some(crc32fast::hash(&uf2));
+            result.warnings = summary
+                .warnings
+                .iter()
+                .map(|warning| warning.message.clone())
+                .collect();
+        }
+        Err(err) => result.error = Some(format!("{err:#}")),
+    }
+    result
+}
+
+/// Run every job, `jobs` at a time, returning the results in the order of `jobs`.
+fn run_jobs(jobs: &[Job], options: &Uf2Options, threads: usize) -> Vec<BatchResult> {
+    // Every distinct board is only resolved once
+    let mut boards: HashMap<&BoardSpec, Result<CustomBoard, String>> = HashMap::new();
+    for job in jobs {
+        boards
+            .entry(&job.board)
+            .or_insert_with(|| resolve_board(&job.board).map_err(|err| format!("{err:#}")));
+    }
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![None; jobs.len()]);
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, jobs.len().max(1)) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(job) = jobs.get(index) else {
+                        break;
+                    };
+                    let result = run_job(job, &boards[&job.board], options);
+                    results.lock().unwrap()[index] = Some(result);
+                }
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every job is run"))
+        .collect()
+}
+
+/// Convert every target of `--batch` or `--batch-dir`, failing once all ran if any of them failed.
+pub fn convert_batch(args: BatchArgs, defaults: &BoardSpec, options: &Uf2Options) -> Result<()> {
+    let BatchArgs {
+        batch,
+        batch_dir,
+        output_dir,
+        jobs,
+        json,
+    } = args;
+
+    let targets = match (batch, batch_dir) {
+        (Some(manifest), _) => manifest_jobs(&manifest, defaults, output_dir.as_deref())?,
+        (None, Some(dir)) => dir_jobs(&dir, defaults, output_dir.as_deref())?,
+        (None, None) => unreachable!("convert_batch is only called for a batch"),
+    };
+    if targets.is_empty() {
+        bail!("The batch has no ELF files to convert");
+    }
+
+    let threads = match jobs {
+        0 => thread::available_parallelism().map_or(1, |threads| threads.get()),
+        jobs => jobs,
+    };
+    log::info!(
+        "Converting {} ELF files, {threads} at a time",
+        targets.len()
+    );
+
+    let results = run_jobs(&targets, options, threads);
+
+    for result in &results {
+        match (&result.error, result.size, result.crc32) {
+            (None, Some(size), Some(crc32)) => log::info!(
+                "{:?} -> {:?}: {size} bytes, crc32 {crc32:08x}",
+                result.input,
+                result.output
+            ),
+            (error, _, _) => log::error!(
+                "{:?}: {}",
+                result.input,
+                error.as_deref().unwrap_or("failed")
+            ),
+        }
+        for warning in &result.warnings {
+            log::warn!("{:?}: {warning}", result.input);
+        }
+    }
+
+    let failed = results
+        .iter()
+        .filter(|result| result.error.is_some())
+        .count();
+    let error = (failed > 0).then(|| format!("{failed} of {} conversions failed", results.len()));
+
+    if output::is_json() {
+        output::emit("convert", Some(&results), error.as_deref())?;
+    } else if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    }
+
+    if let Some(error) = error {
+        bail!(error);
+    }
+    log::info!("Converted {} ELF files", results.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../elf2flash-core/tests");
+
+    /// A directory with the bundled rp2040 and rp2350 ELFs, and an ELF that isn't one
+    fn fixture_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, fixture) in [
+            ("hello_usb.elf", "rp2040/hello_usb.elf"),
+            ("flash_image.elf", "rp2350/flash_image.elf"),
+        ] {
+            fs::copy(Path::new(FIXTURES).join(fixture), dir.path().join(name)).unwrap();
+        }
+        fs::write(dir.path().join("broken.elf"), b"not an elf").unwrap();
+        dir
+    }
+
+    fn golden(fixture: &str) -> Vec<u8> {
+        fs::read(Path::new(FIXTURES).join(fixture)).unwrap()
+    }
+
+    #[test]
+    fn manifest_batch_collects_failures() {
+        let dir = fixture_dir();
+        let manifest = dir.path().join("targets.toml");
+        fs::write(
+            &manifest,
+            r#"
+output_dir = "out"
+
+[[target]]
+input = "hello_usb.elf"
+board = "rp2040"
+
+[[target]]
+input = "flash_image.elf"
+board = "rp2350"
+output = "image.uf2"
+
+[[target]]
+input = "broken.elf"
+family = "RP2040"
+"#,
+        )
+        .unwrap();
+        fs::create_dir(dir.path().join("out")).unwrap();
+
+        let jobs = manifest_jobs(&manifest, &BoardSpec::default(), None).unwrap();
+        assert_eq!(jobs[0].output, dir.path().join("out/hello_usb.uf2"));
+        assert_eq!(jobs[1].output, dir.path().join("out/image.uf2"));
+        assert_eq!(jobs[2].board.family, Some(0xe48bff56));
+
+        let results = run_jobs(&jobs, &Uf2Options::default(), 2);
+
+        for (result, fixture) in results
+            .iter()
+            .zip(["rp2040/hello_usb.uf2", "rp2350/flash_image.uf2"])
+        {
+            let golden = golden(fixture);
+            assert_eq!(result.error, None);
+            assert_eq!(fs::read(&result.output).unwrap(), golden);
+            assert_eq!(result.size, Some(golden.len() as u64));
+            assert_eq!(result.crc32, Some(crc32fast::hash(&golden)));
+        }
+
+        let broken = &results[2];
+        assert!(broken.error.is_some());
+        assert_eq!(broken.crc32, None);
+        assert!(!broken.output.exists(), "the partial output is removed");
+    }
+
+    #[test]
+    fn batch_dir_uses_the_shared_board() {
+        let dir = fixture_dir();
+        let defaults = BoardSpec {
+            board: Some("rp2040".to_string()),
+            ..Default::default()
+        };
+
+        let jobs = dir_jobs(dir.path(), &defaults, None).unwrap();
+        let inputs: Vec<_> = jobs
+            .iter()
+            .map(|job| job.input.file_name().unwrap())
+            .collect();
+        assert_eq!(inputs, ["broken.elf", "flash_image.elf", "hello_usb.elf"]);
+
+        let results = run_jobs(&jobs, &Uf2Options::default(), 1);
+        assert!(results[0].error.is_some());
+        assert_eq!(results[2].error, None);
+        assert_eq!(
+            fs::read(dir.path().join("hello_usb.uf2")).unwrap(),
+            golden("rp2040/hello_usb.uf2")
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&results).unwrap()).unwrap();
+        assert_eq!(json[2]["board_name"], "rp2040");
+        assert_eq!(json[2]["crc32"], results[2].crc32.unwrap());
+    }
+
+    #[test]
+    fn unknown_board_fails_only_its_targets() {
+        let dir = fixture_dir();
+        let jobs = vec![
+            Job {
+                input: dir.path().join("hello_usb.elf"),
+                output: dir.path().join("a.uf2"),
+                board: BoardSpec::default(),
+            },
+            Job {
+                input: dir.path().join("hello_usb.elf"),
+                output: dir.path().join("b.uf2"),
+                board: BoardSpec {
+                    board: Some("rp2040".to_string()),
+                    ..Default::default()
+                },
+            },
+        ];
+
+        let results = run_jobs(&jobs, &Uf2Options::default(), 2);
+        assert_eq!(
+            results[0].error.as_deref(),
+            Some("Must provide --board or --family")
+        );
+        assert_eq!(results[1].error, None);
+    }
+
+    #[test]
+    fn outputs_keep_dotted_stems() {
+        let dir = Path::new("out");
+        assert_eq!(
+            output_for(Path::new("fw/fw.v1.2.elf"), dir),
+            dir.join("fw.v1.2.uf2")
+        );
+        assert_eq!(
+            output_for(Path::new("blinky.elf"), dir),
+            dir.join("blinky.uf2")
+        );
+        assert_eq!(output_for(Path::new("blinky"), dir), dir.join("blinky.uf2"));
+    }
+}
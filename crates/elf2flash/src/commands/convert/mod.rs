@@ -0,0 +1,694 @@
+use anyhow::{Context, Result, anyhow, bail};
+use clap::{Args, ValueEnum, ValueHint};
+use elf2flash_core::{
+    ConversionSummary, Elf2Uf2Error, ProgressReporter, Uf2Options,
+    boards::{
+        BoardInfo, BoardIter, CustomBoard, CustomBoardBuildError, CustomBoardBuilder,
+        family::describe_family,
+    },
+    elf2uf2_multi_with_options, elf2uf2_with_options,
+    extension::ExtensionTag,
+    uf2::UF2_BLOCK_SIZE,
+};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Cursor, IsTerminal, Read, Seek, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use thiserror::Error;
+
+use crate::{
+    BoardValueParser, byte_parser,
+    cancel::{CancellationToken, Cancelled},
+    commands::{
+        convert::batch::{BatchArgs, convert_batch},
+        input_path::check_input_file,
+    },
+    family_parser, num_parser,
+    output::{self, ConvertOutput},
+    progress_bar::ProgressBarReporter,
+    size_parser, usb_id_parser,
+};
+
+pub mod batch;
+
+/// UF2 extension tags added to the final block
+#[derive(Args, Debug, Default, Clone)]
+pub struct ExtensionTagArgs {
+    /// Firmware version to embed, shown by some bootloaders (e.g. on SAMD boards)
+    #[clap(long, value_name = "VERSION")]
+    pub firmware_version: Option<String>,
+
+    /// Device description to embed
+    #[clap(long, value_name = "TEXT")]
+    pub description: Option<String>,
+
+    /// Device type identifier to embed
+    #[clap(long, value_name = "ID", value_parser = num_parser)]
+    pub device_type_id: Option<u32>,
+
+    /// Store an MD5 checksum of the firmware in the final block
+    #[clap(long)]
+    pub md5: bool,
+}
+
+impl ExtensionTagArgs {
+    pub fn tags(self) -> Vec<ExtensionTag> {
+        let mut tags = Vec::new();
+        if let Some(version) = self.firmware_version {
+            tags.push(ExtensionTag::FirmwareVersion(version));
+        }
+        if let Some(description) = self.description {
+            tags.push(ExtensionTag::Description(description));
+        }
+        if let Some(id) = self.device_type_id {
+            tags.push(ExtensionTag::DeviceTypeId(id));
+        }
+        if self.md5 {
+            tags.push(ExtensionTag::Md5Checksum);
+        }
+        tags
+    }
+}
+
+/// Parts of the ELF to leave out of the uf2
+#[derive(Args, Debug, Default, Clone)]
+pub struct ExcludeArgs {
+    /// Leave the pages of this address range out, e.g. 0x10100000..0x10200000 to keep a
+    /// filesystem in flash, can be repeated or comma separated
+    #[clap(long, value_name = "START..END", value_parser = range_parser, value_delimiter = ',')]
+    pub exclude: Vec<Range<u64>>,
+
+    /// Leave the pages of this ELF section out, e.g. .fs, can be repeated or comma separated
+    #[clap(long, value_name = "NAME", value_delimiter = ',')]
+    pub exclude_section: Vec<String>,
+}
+
+/// An address range like `0x10100000..0x10200000`, the end is exclusive
+fn range_parser(s: &str) -> Result<Range<u64>, &'static str> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or("expected an address range like 0x10100000..0x10200000")?;
+    let (start, end) = (num_parser(start)?, num_parser(end)?);
+    if start > end {
+        return Err("the range ends before it starts");
+    }
+    Ok(start as u64..end as u64)
+}
+
+/// A signed address offset like `0x8000` or `-0x8000`
+pub(crate) fn offset_parser(s: &str) -> Result<i64, &'static str> {
+    match s.strip_prefix('-') {
+        Some(s) => num_parser(s).map(|offset| -(offset as i64)),
+        None => num_parser(s).map(i64::from),
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ConvertArgs {
+    /// Input ELF file, - to read it from stdin
+    #[clap(required_unless_present_any = ["batch", "batch_dir"], value_hint = ValueHint::FilePath)]
+    pub input: Option<PathBuf>,
+
+    /// Output UF2 file, - to write it to stdout
+    #[clap(required_unless_present_any = ["batch", "batch_dir"], value_hint = ValueHint::FilePath)]
+    pub output: Option<PathBuf>,
+
+    /// Explicit board (rp2040, rp2350, circuit_playground_bluefruit, etc.) [env: ELF2FLASH_BOARD]
+    #[clap(short, long, value_parser = BoardValueParser, hide_possible_values = true)]
+    pub board: Option<String>,
+
+    /// Override family ID, either a number or a name from the uf2 family list (e.g. SAMD51)
+    /// [env: ELF2FLASH_FAMILY]
+    #[clap(short, long, value_parser = family_parser)]
+    pub family: Option<u32>,
+
+    /// Flash erase sector size
+    #[clap(short = 'e', long, value_parser = size_parser)]
+    pub flash_sector_erase_size: Option<u64>,
+
+    /// Page size
+    #[clap(short, long, value_parser = num_parser)]
+    pub page_size: Option<u32>,
+
+    /// Only treat devices with this USB vendor id as the board, e.g. 0x2e8a
+    #[clap(long, value_name = "ID", value_parser = usb_id_parser)]
+    pub vendor_id: Option<u16>,
+
+    /// Only treat devices with this USB product id as the board
+    #[clap(long, value_name = "ID", value_parser = usb_id_parser)]
+    pub product_id: Option<u16>,
+
+    /// Bytes of a page each block writes, e.g. 256 for a bootloader that expects 256 byte payloads
+    /// on a board with 4 KiB pages. Every page is written whole by default
+    #[clap(long, value_parser = num_parser)]
+    pub payload_size: Option<u32>,
+
+    /// Leave the family id out of every block and write the file size in its place, as the
+    /// original uf2 spec did, for older bootloaders that reject the family id flag
+    #[clap(long, conflicts_with_all = ["family", "extra_inputs"])]
+    pub no_family: bool,
+
+    /// Generate a RAM-only uf2 (sets the not main flash flag), detected automatically for
+    /// programs that only load into the board's RAM
+    #[clap(long)]
+    pub ram: bool,
+
+    /// Byte to pad the unused parts of pages and the filler pages with, e.g. 0xff to match erased
+    /// flash
+    #[clap(long, value_name = "BYTE", value_parser = byte_parser, default_value = "0")]
+    pub pad_byte: u8,
+
+    /// Only write the pages the program covers, instead of every page of each touched flash
+    /// sector. Smaller for images with large gaps, but bootloaders that erase whole sectors can
+    /// lose the pages left out
+    #[clap(long)]
+    pub no_sector_fill: bool,
+
+    /// Also fill the pages after the program in the last flash sector it touches, for bootloaders
+    /// that only erase the pages they write, where old data would survive behind the program
+    #[clap(long, conflicts_with = "no_sector_fill")]
+    pub pad_final_sector: bool,
+
+    /// Write the checksum the RP2040 bootrom expects into the second stage bootloader, for
+    /// projects whose boot2 lacks it. Only applies to RP2040 boards
+    #[clap(long)]
+    pub fix_boot2: bool,
+
+    /// Move the program by this many bytes, e.g. 0x8000 for an application linked for the start
+    /// of flash that goes behind a bootloader. Must be a multiple of the page size
+    #[clap(
+        long,
+        value_name = "OFFSET",
+        value_parser = offset_parser,
+        allow_hyphen_values = true,
+        default_value = "0"
+    )]
+    pub offset: i64,
+
+    #[clap(flatten)]
+    pub extension_tags: ExtensionTagArgs,
+
+    #[clap(flatten)]
+    pub exclude: ExcludeArgs,
+
+    /// Another ELF to add to the uf2 with its own family id, e.g. the RISC-V build of an RP2350
+    /// project, can be repeated
+    #[clap(long = "extra-input", value_name = "elf=PATH,family=ID")]
+    pub extra_inputs: Vec<ExtraInput>,
+
+    /// Reproduce the output of another converter, to compare against it with `compare` before
+    /// switching. Can't be combined with the options that change the output
+    #[clap(
+        long,
+        value_name = "TOOL",
+        conflicts_with_all = [
+            "payload_size",
+            "no_family",
+            "ram",
+            "pad_byte",
+            "no_sector_fill",
+            "pad_final_sector",
+            "fix_boot2",
+            "offset",
+            "firmware_version",
+            "description",
+            "device_type_id",
+            "md5",
+            "exclude",
+            "exclude_section",
+            "extra_inputs",
+        ]
+    )]
+    pub compat: Option<Compat>,
+
+    #[clap(flatten)]
+    pub batch: BatchArgs,
+}
+
+/// Converters `--compat` reproduces the output of.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compat {
+    /// elf2uf2-rs, the blocks only differ for RAM-only programs, which aren't flagged as not main
+    /// flash
+    #[value(name = "elf2uf2-rs")]
+    Elf2uf2Rs,
+}
+
+impl Compat {
+    /// The options the other options of a conversion are applied on top of
+    pub fn options(self) -> Uf2Options {
+        match self {
+            Compat::Elf2uf2Rs => Uf2Options::elf2uf2_rs_compat(),
+        }
+    }
+}
+
+/// An additional input of a multi-family uf2, parsed from `elf=<path>,family=<id>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtraInput {
+    pub elf: PathBuf,
+    pub family: u32,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ExtraInputParseError {
+    #[error("expected elf=<path>,family=<id>, missing {0}")]
+    Missing(&'static str),
+    #[error("unknown key '{0}', expected elf or family")]
+    UnknownKey(String),
+    #[error("invalid family id '{0}'")]
+    InvalidFamily(String),
+}
+
+impl FromStr for ExtraInput {
+    type Err = ExtraInputParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut elf = None;
+        let mut family = None;
+
+        for part in s.split(',') {
+            match part.split_once('=') {
+                Some(("elf", path)) => elf = Some(PathBuf::from(path)),
+                Some(("family", id)) => {
+                    family = Some(
+                        family_parser(id)
+                            .map_err(|_| ExtraInputParseError::InvalidFamily(id.to_string()))?,
+                    )
+                }
+                _ => return Err(ExtraInputParseError::UnknownKey(part.to_string())),
+            }
+        }
+
+        Ok(Self {
+            elf: elf.ok_or(ExtraInputParseError::Missing("elf"))?,
+            family: family.ok_or(ExtraInputParseError::Missing("family"))?,
+        })
+    }
+}
+
+/// Which board a conversion is for, a known board with optional overrides, or just a family id.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BoardSpec {
+    pub board: Option<String>,
+    pub family: Option<u32>,
+    pub flash_sector_erase_size: Option<u64>,
+    pub page_size: Option<u32>,
+    /// The USB ids of the devices the board applies to, any device when not given
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    /// The name the uf2 is written as on the bootloader volume, the board's own when not given
+    pub uf2_filename: Option<String>,
+    /// How the uf2 is written to the bootloader volume, the board's own when not given
+    pub write_chunk_size: Option<usize>,
+    pub inter_chunk_delay: Option<Duration>,
+}
+
+impl BoardSpec {
+    /// Apply the overrides and USB ids of the spec to `builder`. The USB ids are always replaced,
+    /// without them the board applies to any device.
+    pub fn apply_to(&self, builder: CustomBoardBuilder) -> CustomBoardBuilder {
+        let mut builder = builder
+            .apply_overrides(self.family, self.flash_sector_erase_size, self.page_size)
+            .usb_ids(self.vendor_id, self.product_id);
+        if let Some(uf2_filename) = &self.uf2_filename {
+            builder = builder.uf2_filename(uf2_filename.as_str());
+        }
+        if let Some(chunk_size) = self.write_chunk_size {
+            builder = builder.write_chunk_size(chunk_size);
+        }
+        if let Some(delay) = self.inter_chunk_delay {
+            builder = builder.inter_chunk_delay(delay);
+        }
+        builder
+    }
+}
+
+/// Build the board described by `spec`, the overrides always win over the known board's values.
+pub fn resolve_board(spec: &BoardSpec) -> Result<CustomBoard> {
+    let builder = match &spec.board {
+        Some(board_name) => {
+            log::info!("Looking up board definition for {board_name}");
+
+            let Some(base) = BoardIter::new().find(|b| b.board_name() == *board_name) else {
+                return Err(anyhow!("Unknown board: {board_name}"));
+            };
+            CustomBoardBuilder::from_board(base.as_ref())
+        }
+        None => CustomBoardBuilder::new(),
+    };
+
+    // Require at least family_id in some form
+    spec.apply_to(builder).build().map_err(|err| match err {
+        CustomBoardBuildError::FamilyIdRequired => anyhow!("Must provide --board or --family"),
+        err => err.into(),
+    })
+}
+
+/// The path that stands for stdin as the input of `convert`, and for stdout as its output.
+pub const STDIO_PATH: &str = "-";
+
+pub fn is_stdio(path: &Path) -> bool {
+    path == Path::new(STDIO_PATH)
+}
+
+/// `path` for the logs, with `-` named as the `stream` it stands for.
+fn describe_path(path: &Path, stream: &str) -> String {
+    if is_stdio(path) {
+        stream.to_string()
+    } else {
+        path.display().to_string()
+    }
+}
+
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Open the ELF `input`. A file is streamed page by page during the conversion, stdin can't seek
+/// and is read whole first.
+fn open_elf(input: &Path) -> Result<Box<dyn ReadSeek>> {
+    if is_stdio(input) {
+        let mut elf = Vec::new();
+        io::stdin()
+            .lock()
+            .read_to_end(&mut elf)
+            .context("Failed to read the ELF from stdin")?;
+        return Ok(Box::new(Cursor::new(elf)));
+    }
+    check_input_file(input)?;
+    Ok(Box::new(BufReader::new(File::open(input).with_context(
+        || format!("Failed to open {}", input.display()),
+    )?)))
+}
+
+/// Convert the ELF `input`, plus any `extra_inputs` with their own family ids, into the uf2 file
+/// `output`. Either can be [`STDIO_PATH`].
+///
+/// The output file is removed again if the conversion fails, a cancelled conversion returns
+/// [`Cancelled`].
+pub fn convert_file(
+    input: &Path,
+    output: &Path,
+    board: &CustomBoard,
+    options: &Uf2Options,
+    extra_inputs: &[ExtraInput],
+    progress: impl ProgressReporter,
+) -> Result<ConversionSummary> {
+    let input_file = open_elf(input)?;
+
+    if is_stdio(output) {
+        let mut writer = BufWriter::new(io::stdout().lock());
+        let summary = convert_elf(
+            input_file,
+            &mut writer,
+            board,
+            options,
+            extra_inputs,
+            progress,
+        )?;
+        writer
+            .flush()
+            .context("Failed to write the uf2 to stdout")?;
+        return Ok(summary);
+    }
+
+    let output_file =
+        File::create(output).with_context(|| format!("Failed to create {}", output.display()))?;
+    let mut writer = BufWriter::new(output_file);
+
+    let result = convert_elf(
+        input_file,
+        &mut writer,
+        board,
+        options,
+        extra_inputs,
+        progress,
+    );
+    if result.is_err() {
+        // Don't leave a truncated uf2 behind
+        drop(writer);
+        if let Err(err) = fs::remove_file(output) {
+            log::warn!("Failed to remove the partial {}: {err}", output.display());
+        }
+    }
+    result
+}
+
+/// Convert the ELF `input`, plus any `extra_inputs`, into the uf2 `writer`.
+fn convert_elf(
+    input: impl Read + Seek,
+    writer: impl Write,
+    board: &CustomBoard,
+    options: &Uf2Options,
+    extra_inputs: &[ExtraInput],
+    progress: impl ProgressReporter,
+) -> Result<ConversionSummary> {
+    let result = if extra_inputs.is_empty() {
+        elf2uf2_with_options(input, writer, board, options, progress).map_err(Into::into)
+    } else {
+        convert_multi(input, board, options, extra_inputs, writer, progress)
+    };
+
+    match result {
+        Err(err) if matches!(err.downcast_ref(), Some(Elf2Uf2Error::Cancelled)) => {
+            Err(Cancelled.into())
+        }
+        result => result,
+    }
+}
+
+/// A likely cause to print next to a failed conversion, for errors that don't explain themselves.
+pub fn conversion_hint(err: &anyhow::Error) -> Option<&'static str> {
+    err.chain().find_map(|cause| match cause.downcast_ref() {
+        Some(Elf2Uf2Error::OnlyUninitializedSegments { .. }) => Some(
+            "The ELF may be linked to run from RAM only, or be missing its text section, \
+                 check the linker script and the memory layout it was built for",
+        ),
+        _ => None,
+    })
+}
+
+fn convert_multi(
+    mut input: impl Read,
+    board: &CustomBoard,
+    options: &Uf2Options,
+    extra_inputs: &[ExtraInput],
+    writer: impl Write,
+    progress: impl ProgressReporter,
+) -> Result<ConversionSummary> {
+    // Multi-family conversions need every ELF in memory
+    let mut elf = Vec::new();
+    input.read_to_end(&mut elf)?;
+    let mut inputs = vec![(elf, board.family_id())];
+    for extra in extra_inputs {
+        log::info!(
+            "Adding {:?} with family id {}",
+            extra.elf,
+            describe_family(extra.family)
+        );
+        check_input_file(&extra.elf)?;
+        let elf = fs::read(&extra.elf)
+            .with_context(|| format!("Failed to read {}", extra.elf.display()))?;
+        inputs.push((elf, extra.family));
+    }
+
+    Ok(elf2uf2_multi_with_options(
+        &inputs, writer, board, options, progress,
+    )?)
+}
+
+pub fn convert(args: ConvertArgs) -> Result<()> {
+    let ConvertArgs {
+        input,
+        output,
+        board,
+        family,
+        flash_sector_erase_size,
+        page_size,
+        vendor_id,
+        product_id,
+        payload_size,
+        no_family,
+        ram,
+        pad_byte,
+        no_sector_fill,
+        pad_final_sector,
+        fix_boot2,
+        offset,
+        extension_tags,
+        exclude,
+        extra_inputs,
+        compat,
+        batch,
+    } = args;
+
+    let options = Uf2Options {
+        not_main_flash: ram,
+        extension_tags: extension_tags.tags(),
+        block_padding_byte: pad_byte,
+        fill_sectors: !no_sector_fill,
+        pad_final_sector,
+        fix_rp2040_boot2: fix_boot2,
+        address_offset: offset,
+        exclude_ranges: exclude.exclude,
+        exclude_sections: exclude.exclude_section,
+        payload_size,
+        omit_family_id: no_family,
+        ..compat.map_or_else(Uf2Options::default, Compat::options)
+    };
+    // The family id is never written without a family, any id lets a board be built from the
+    // other flags
+    let family = family.or((no_family && board.is_none()).then_some(0));
+    let spec = BoardSpec {
+        board,
+        family,
+        flash_sector_erase_size,
+        page_size,
+        vendor_id,
+        product_id,
+        ..Default::default()
+    };
+
+    if batch.is_batch() {
+        return convert_batch(batch, &spec, &options);
+    }
+
+    // clap requires both unless a batch is given
+    let (Some(input), Some(output)) = (input, output) else {
+        unreachable!("clap requires an input and an output without --batch or --batch-dir");
+    };
+
+    if is_stdio(&output) {
+        if output::is_json() {
+            bail!("--format json prints its document on stdout, write the uf2 to a file instead");
+        }
+        // Pipes and files take the bytes as they are, also on Windows, where only the console
+        // would reject them
+        if io::stdout().is_terminal() {
+            bail!("Refusing to write the uf2 to a terminal, redirect stdout to a file or a pipe");
+        }
+    }
+
+    log::info!("Reading ELF file from {}", describe_path(&input, "stdin"));
+
+    let custom_board = resolve_board(&spec)?;
+
+    log::info!("Converting ELF → UF2");
+
+    let cancel = CancellationToken::ctrl_c()?;
+    let progress = ProgressBarReporter::new().with_cancellation(cancel);
+    let summary = convert_file(
+        &input,
+        &output,
+        &custom_board,
+        &options,
+        &extra_inputs,
+        progress,
+    )?;
+
+    log::info!("Wrote UF2 to {}", describe_path(&output, "stdout"));
+
+    if let Some(warnings) = summary.warnings.summary() {
+        log::warn!("{warnings}");
+    }
+
+    let result = ConvertOutput {
+        blocks: summary.num_blocks,
+        bytes: u64::from(summary.num_blocks) * UF2_BLOCK_SIZE as u64,
+        family_id: custom_board.family_id(),
+        input,
+        output,
+    };
+    output::emit("convert", Some(&result), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elf2flash_core::NoProgress;
+
+    #[test]
+    fn parses_extra_inputs() {
+        assert_eq!(
+            "elf=riscv.elf,family=0xe48bff5a".parse(),
+            Ok(ExtraInput {
+                elf: PathBuf::from("riscv.elf"),
+                family: 0xe48bff5a
+            })
+        );
+        assert_eq!(
+            "family=12,elf=a.elf".parse::<ExtraInput>().unwrap().family,
+            12
+        );
+        assert_eq!(
+            "elf=riscv.elf".parse::<ExtraInput>(),
+            Err(ExtraInputParseError::Missing("family"))
+        );
+        assert_eq!(
+            "elf=a.elf,family=riscv".parse::<ExtraInput>(),
+            Err(ExtraInputParseError::InvalidFamily("riscv".to_string()))
+        );
+        assert_eq!(
+            "path=a.elf".parse::<ExtraInput>(),
+            Err(ExtraInputParseError::UnknownKey("path=a.elf".to_string()))
+        );
+    }
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(offset_parser("0x8000"), Ok(0x8000));
+        assert_eq!(offset_parser("-0x8000"), Ok(-0x8000));
+        assert_eq!(offset_parser("4096"), Ok(4096));
+        assert!(offset_parser("-").is_err());
+        assert!(offset_parser("--0x8000").is_err());
+    }
+
+    #[test]
+    fn parses_exclude_ranges() {
+        assert_eq!(
+            range_parser("0x10100000..0x10200000"),
+            Ok(0x10100000..0x10200000)
+        );
+        assert_eq!(range_parser("0..4096"), Ok(0..4096));
+        assert!(range_parser("0x10100000").is_err());
+        assert!(range_parser("0x10200000..0x10100000").is_err());
+        assert!(range_parser("0x10100000..end").is_err());
+    }
+
+    #[test]
+    fn hints_at_uninitialized_only_elfs() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.uf2");
+        let board = resolve_board(&BoardSpec {
+            board: Some("rp2040".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let convert = |input: &str| {
+            convert_file(
+                Path::new(input),
+                &output,
+                &board,
+                &Uf2Options::default(),
+                &[],
+                NoProgress,
+            )
+            .unwrap_err()
+        };
+
+        let err = convert(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../elf2flash-core/tests/rp2040/flash_no_contents.elf"
+        ));
+        assert!(conversion_hint(&err).unwrap().contains("text section"));
+        assert!(!output.exists());
+
+        let err = convert("missing.elf");
+        assert_eq!(conversion_hint(&err), None);
+    }
+}
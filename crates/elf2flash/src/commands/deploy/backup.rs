@@ -0,0 +1,508 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use elf2flash_core::{
+    Elf2Uf2Error,
+    boards::BoardInfo,
+    uf2::{UF2_BLOCK_SIZE, Uf2Block},
+    warnings::{WarningCode, Warnings},
+};
+use fatfs::{FileSystem, ReadWriteSeek};
+use thiserror::Error;
+use usbh_fatfs::{list_dir, read_file};
+
+use crate::commands::deploy::to_usb::uf2_blocks;
+
+/// Files larger than this are not backed up, bootloader volumes only expose a few small files and
+/// at most one image of the flash contents.
+pub const MAX_BACKUP_FILE_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Name of the manifest written next to the backed up files.
+pub const MANIFEST_FILE_NAME: &str = "manifest.txt";
+
+#[derive(Debug, Clone)]
+pub struct BackupOptions {
+    /// Skip (or fail on, if `required`) files larger than this many bytes
+    pub max_file_size: u64,
+    /// Treat a file that couldn't be backed up as an error instead of a warning
+    pub required: bool,
+}
+
+impl Default for BackupOptions {
+    fn default() -> Self {
+        Self {
+            max_file_size: MAX_BACKUP_FILE_SIZE,
+            required: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupEntry {
+    pub name: String,
+    pub size: u64,
+    pub crc32: u32,
+}
+
+/// Describes what was backed up from a bootloader volume.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupManifest {
+    pub board_name: String,
+    pub family_id: u32,
+    pub entries: Vec<BackupEntry>,
+}
+
+impl BackupManifest {
+    /// Render the manifest, one `<crc32> <size> <name>` line per file after a small header.
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "# board: {}\n# family: {:#010x}\n",
+            self.board_name, self.family_id
+        );
+        for entry in &self.entries {
+            text.push_str(&format!(
+                "{:08x} {} {}\n",
+                entry.crc32, entry.size, entry.name
+            ));
+        }
+        text
+    }
+
+    /// Parse a manifest previously written by [`BackupManifest::to_text`].
+    pub fn parse(text: &str) -> Result<Self, BackupError> {
+        let mut board_name = None;
+        let mut family_id = None;
+        let mut entries = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let corrupt = || BackupError::CorruptManifest {
+                line: index + 1,
+                content: line.to_string(),
+            };
+
+            if let Some(header) = line.strip_prefix("# ") {
+                let (key, value) = header.split_once(": ").ok_or_else(corrupt)?;
+                match key {
+                    "board" => board_name = Some(value.to_string()),
+                    "family" => {
+                        let value = value.strip_prefix("0x").ok_or_else(corrupt)?;
+                        family_id = Some(u32::from_str_radix(value, 16).map_err(|_| corrupt())?);
+                    }
+                    _ => (),
+                }
+                continue;
+            }
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let mut parts = line.splitn(3, ' ');
+            let (Some(crc32), Some(size), Some(name)) = (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(corrupt());
+            };
+
+            entries.push(BackupEntry {
+                name: name.to_string(),
+                size: size.parse().map_err(|_| corrupt())?,
+                crc32: u32::from_str_radix(crc32, 16).map_err(|_| corrupt())?,
+            });
+        }
+
+        Ok(Self {
+            board_name: board_name.ok_or(BackupError::IncompleteManifest("board"))?,
+            family_id: family_id.ok_or(BackupError::IncompleteManifest("family"))?,
+            entries,
+        })
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum BackupError {
+    #[error("No backup with a {MANIFEST_FILE_NAME} found in {0}")]
+    NoBackupFound(PathBuf),
+    #[error("Failed to read backup {0}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("Backup manifest is corrupt at line {line}: {content:?}")]
+    CorruptManifest { line: usize, content: String },
+    #[error("Backup manifest is missing the {0} header")]
+    IncompleteManifest(&'static str),
+    #[error("Backup does not contain {0}")]
+    FileMissing(String),
+    #[error(
+        "Backup of {name} is corrupt, expected crc32 {expected:08x} ({expected_size} bytes) but found {actual:08x} ({actual_size} bytes)"
+    )]
+    ChecksumMismatch {
+        name: String,
+        expected: u32,
+        expected_size: u64,
+        actual: u32,
+        actual_size: u64,
+    },
+    #[error("Backup of {name} is not a valid uf2 file")]
+    NotUf2 { name: String },
+    #[error(
+        "Backup was taken from a board with family id {backup:#x}, but the target board has family id {board:#x}"
+    )]
+    FamilyMismatch { backup: u32, board: u32 },
+}
+
+/// A backed up file that passed its manifest checksum.
+#[derive(Debug, Clone)]
+pub struct VerifiedBackupFile {
+    pub dir: PathBuf,
+    pub manifest: BackupManifest,
+    pub entry: BackupEntry,
+    pub data: Vec<u8>,
+}
+
+/// Find the backup to restore from `from`.
+///
+/// `from` is either a single backup directory (containing a manifest), or a directory of backups
+/// created by `deploy --backup`, in which case the newest one is picked.
+pub fn find_backup(from: &Path) -> Result<PathBuf, BackupError> {
+    if from.join(MANIFEST_FILE_NAME).is_file() {
+        return Ok(from.to_path_buf());
+    }
+
+    let read_dir = fs::read_dir(from).map_err(|err| BackupError::Io(from.to_path_buf(), err))?;
+
+    // Backup directories are named after their creation time, so the newest sorts last
+    read_dir
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.join(MANIFEST_FILE_NAME).is_file())
+        .max()
+        .ok_or_else(|| BackupError::NoBackupFound(from.to_path_buf()))
+}
+
+/// Load `name` from the backup in `dir`, checking it against the manifest.
+pub fn load_backup_file(dir: &Path, name: &str) -> Result<VerifiedBackupFile, BackupError> {
+    let manifest_path = dir.join(MANIFEST_FILE_NAME);
+    let text = fs::read_to_string(&manifest_path)
+        .map_err(|err| BackupError::Io(manifest_path.clone(), err))?;
+    let manifest = BackupManifest::parse(&text)?;
+
+    let entry = manifest
+        .entries
+        .iter()
+        .find(|entry| entry.name.eq_ignore_ascii_case(name))
+        .cloned()
+        .ok_or_else(|| BackupError::FileMissing(name.to_string()))?;
+
+    let path = dir.join(&entry.name);
+    let data = fs::read(&path).map_err(|err| BackupError::Io(path.clone(), err))?;
+
+    let actual = crc32fast::hash(&data);
+    if actual != entry.crc32 || data.len() as u64 != entry.size {
+        return Err(BackupError::ChecksumMismatch {
+            name: entry.name,
+            expected: entry.crc32,
+            expected_size: entry.size,
+            actual,
+            actual_size: data.len() as u64,
+        });
+    }
+
+    Ok(VerifiedBackupFile {
+        dir: dir.to_path_buf(),
+        manifest,
+        entry,
+        data,
+    })
+}
+
+impl VerifiedBackupFile {
+    /// Check that the backup is a uf2 file which can be flashed onto a board with `family_id`.
+    pub fn check_uf2_for_family(&self, family_id: u32) -> Result<(), BackupError> {
+        if self.manifest.family_id != family_id {
+            return Err(BackupError::FamilyMismatch {
+                backup: self.manifest.family_id,
+                board: family_id,
+            });
+        }
+
+        let not_uf2 = || BackupError::NotUf2 {
+            name: self.entry.name.clone(),
+        };
+
+        if self.data.is_empty() || !self.data.len().is_multiple_of(UF2_BLOCK_SIZE) {
+            return Err(not_uf2());
+        }
+
+        for block in self.data.chunks_exact(UF2_BLOCK_SIZE) {
+            let block = Uf2Block::from_bytes(
+                block
+                    .try_into()
+                    .expect("chunks_exact always yields whole blocks"),
+            )
+            .map_err(|_| not_uf2())?;
+
+            if let Some(backup) = block.family_id().filter(|&backup| backup != family_id) {
+                return Err(BackupError::FamilyMismatch {
+                    backup,
+                    board: family_id,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The backed up uf2 file as a stream of blocks, only valid after
+    /// [`check_uf2_for_family`](Self::check_uf2_for_family) succeeded.
+    pub fn uf2_blocks(
+        &self,
+    ) -> impl ExactSizeIterator<Item = Result<[u8; UF2_BLOCK_SIZE], Elf2Uf2Error>> + '_ {
+        uf2_blocks(&self.data)
+    }
+}
+
+/// Create a new, timestamped directory for a backup of `board` under `root`.
+pub fn create_backup_dir(root: &Path, board: &dyn BoardInfo) -> Result<PathBuf> {
+    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let base = format!("{timestamp}-{}", board.board_name());
+
+    let mut dir = root.join(&base);
+    let mut n = 1;
+    while dir.exists() {
+        dir = root.join(format!("{base}-{n}"));
+        n += 1;
+    }
+
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create backup directory {}", dir.display()))?;
+
+    Ok(dir)
+}
+
+/// Copy every regular file in the root directory of `fatfs` into `dest` and write a manifest.
+///
+/// Files that can't be read are skipped with a warning, unless `options.required` is set.
+pub fn backup_volume<T: ReadWriteSeek>(
+    fatfs: &FileSystem<T>,
+    board: &dyn BoardInfo,
+    dest: &Path,
+    options: &BackupOptions,
+    warnings: &mut Warnings,
+) -> Result<BackupManifest> {
+    let mut manifest = BackupManifest {
+        board_name: board.board_name().into_owned(),
+        family_id: board.family_id(),
+        entries: Vec::new(),
+    };
+
+    let entries = list_dir(fatfs, "").context("Failed to list the bootloader volume")?;
+
+    for entry in entries.into_iter().filter(|entry| !entry.is_dir) {
+        let data = match read_file(fatfs, &entry.name, Some(options.max_file_size)) {
+            Ok(data) => data,
+            Err(err) => {
+                if options.required {
+                    return Err(err).with_context(|| format!("Failed to back up {}", entry.name));
+                }
+                warnings.push(
+                    WarningCode::BackupIncomplete,
+                    format!(
+                        "Skipping backup of {}: {:#}",
+                        entry.name,
+                        anyhow::Error::from(err)
+                    ),
+                );
+                continue;
+            }
+        };
+
+        let path = dest.join(&entry.name);
+        fs::write(&path, &data)
+            .with_context(|| format!("Failed to write backup file {}", path.display()))?;
+
+        manifest.entries.push(BackupEntry {
+            name: entry.name,
+            size: data.len() as u64,
+            crc32: crc32fast::hash(&data),
+        });
+    }
+
+    let manifest_path = dest.join(MANIFEST_FILE_NAME);
+    fs::write(&manifest_path, manifest.to_text()).with_context(|| {
+        format!(
+            "Failed to write backup manifest {}",
+            manifest_path.display()
+        )
+    })?;
+
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{commands::deploy::to_usb::write_uf2_file, test_support::fat_image};
+    use elf2flash_core::{
+        NoProgress,
+        boards::{DEFAULT_WRITE_CHUNK_SIZE, RP2040, RP2350},
+    };
+    use fatfs::FsOptions;
+
+    const HELLO_USB_UF2: &[u8] =
+        include_bytes!("../../../../elf2flash-core/tests/rp2040/hello_usb.uf2");
+
+    /// Back up a volume holding `CURRENT.UF2`, returning the backup root
+    fn backup_of(current: &[u8]) -> tempfile::TempDir {
+        let mut image = fat_image(&[("INFO_UF2.TXT", b"info"), ("CURRENT.UF2", current)]);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+
+        let root = tempfile::tempdir().unwrap();
+        let dest = create_backup_dir(root.path(), &RP2040).unwrap();
+        backup_volume(
+            &fatfs,
+            &RP2040,
+            &dest,
+            &BackupOptions::default(),
+            &mut Warnings::new(),
+        )
+        .unwrap();
+        root
+    }
+
+    #[test]
+    fn backs_up_root_files() {
+        let current = vec![0xA5; 2048];
+        let mut image = fat_image(&[
+            ("INFO_UF2.TXT", b"UF2 Bootloader v1.0\n"),
+            ("CURRENT.UF2", &current),
+        ]);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+        fatfs.root_dir().create_dir("SUBDIR").unwrap();
+
+        let dest = tempfile::tempdir().unwrap();
+        let manifest = backup_volume(
+            &fatfs,
+            &RP2040,
+            dest.path(),
+            &BackupOptions::default(),
+            &mut Warnings::new(),
+        )
+        .unwrap();
+
+        assert_eq!(manifest.family_id, 0xe48bff56);
+        assert_eq!(manifest.entries.len(), 2);
+
+        let entry = manifest
+            .entries
+            .iter()
+            .find(|entry| entry.name == "CURRENT.UF2")
+            .unwrap();
+        assert_eq!(entry.size, 2048);
+        assert_eq!(entry.crc32, crc32fast::hash(&current));
+        assert_eq!(fs::read(dest.path().join("CURRENT.UF2")).unwrap(), current);
+
+        let text = fs::read_to_string(dest.path().join(MANIFEST_FILE_NAME)).unwrap();
+        assert_eq!(text, manifest.to_text());
+        assert!(!dest.path().join("SUBDIR").exists());
+    }
+
+    #[test]
+    fn oversized_files_are_skipped_unless_required() {
+        let mut image = fat_image(&[("INFO_UF2.TXT", b"info"), ("CURRENT.UF2", &[0; 4096])]);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+        let dest = tempfile::tempdir().unwrap();
+
+        let mut options = BackupOptions {
+            max_file_size: 1024,
+            required: false,
+        };
+        let mut warnings = Warnings::new();
+        let manifest =
+            backup_volume(&fatfs, &RP2040, dest.path(), &options, &mut warnings).unwrap();
+        assert_eq!(manifest.entries.len(), 1);
+        assert_eq!(manifest.entries[0].name, "INFO_UF2.TXT");
+        assert!(warnings.contains(WarningCode::BackupIncomplete));
+
+        options.required = true;
+        assert!(
+            backup_volume(&fatfs, &RP2040, dest.path(), &options, &mut Warnings::new()).is_err()
+        );
+    }
+
+    #[test]
+    fn manifest_round_trips() {
+        let manifest = BackupManifest {
+            board_name: "rp2040".to_string(),
+            family_id: 0xe48bff56,
+            entries: vec![BackupEntry {
+                name: "CURRENT UF2 COPY.UF2".to_string(),
+                size: 1024,
+                crc32: 0x0badf00d,
+            }],
+        };
+        assert_eq!(
+            BackupManifest::parse(&manifest.to_text()).unwrap(),
+            manifest
+        );
+
+        assert!(matches!(
+            BackupManifest::parse("# board: rp2040\n# family: 0xe48bff56\nnope 12 A.TXT\n"),
+            Err(BackupError::CorruptManifest { line: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn restores_backed_up_uf2() {
+        let root = backup_of(HELLO_USB_UF2);
+
+        let dir = find_backup(root.path()).unwrap();
+        let backup = load_backup_file(&dir, "current.uf2").unwrap();
+        backup.check_uf2_for_family(RP2040.family_id()).unwrap();
+
+        let mut image = fat_image(&[]);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+        let mut warnings = Warnings::new();
+        write_uf2_file(
+            &fatfs,
+            backup.uf2_blocks(),
+            &RP2040,
+            DEFAULT_WRITE_CHUNK_SIZE,
+            NoProgress,
+            &mut warnings,
+        )
+        .unwrap();
+        assert!(warnings.is_empty());
+
+        assert_eq!(read_file(&fatfs, "out.uf2", None).unwrap(), HELLO_USB_UF2);
+    }
+
+    #[test]
+    fn rejects_corrupt_or_foreign_backups() {
+        let root = backup_of(HELLO_USB_UF2);
+        let dir = find_backup(root.path()).unwrap();
+
+        let backup = load_backup_file(&dir, "CURRENT.UF2").unwrap();
+        assert!(matches!(
+            backup.check_uf2_for_family(RP2350.family_id()),
+            Err(BackupError::FamilyMismatch { .. })
+        ));
+
+        let mut corrupt = HELLO_USB_UF2.to_vec();
+        corrupt[600] ^= 0xff;
+        fs::write(dir.join("CURRENT.UF2"), corrupt).unwrap();
+        assert!(matches!(
+            load_backup_file(&dir, "CURRENT.UF2"),
+            Err(BackupError::ChecksumMismatch { .. })
+        ));
+
+        let not_uf2 = backup_of(&[0; 1024]);
+        let dir = find_backup(not_uf2.path()).unwrap();
+        assert!(matches!(
+            load_backup_file(&dir, "CURRENT.UF2")
+                .unwrap()
+                .check_uf2_for_family(RP2040.family_id()),
+            Err(BackupError::NotUf2 { .. })
+        ));
+    }
+}
@@ -0,0 +1,295 @@
+//! The files `deploy` writes: an ELF converted for every board, a uf2 file written as it is, or
+//! the memory image in an Intel HEX or raw binary file.
+
+use std::{fs, io::Cursor, path::Path};
+
+use anyhow::{Context, Result, bail};
+use elf::{ElfBytes, endian::AnyEndian};
+use elf2flash_core::{
+    ConversionSummary, Elf2Uf2Error, Uf2BlockIterator, Uf2Options,
+    boards::{BoardInfo, RP2350, RP2350ArmNs, RP2350RiscV, family::describe_family, rp2350},
+    input::{InputFormat, parse_intel_hex},
+    uf2::{UF2_ABSOLUTE_FAMILY_ID, UF2_BLOCK_SIZE, Uf2Block},
+    warnings::{WarningCode, Warnings},
+};
+
+use crate::commands::{deploy::to_usb::uf2_blocks, input_path::check_input_file};
+
+/// The uf2 blocks of an input for one board
+pub type InputBlocks<'a> =
+    Box<dyn ExactSizeIterator<Item = Result<[u8; UF2_BLOCK_SIZE], Elf2Uf2Error>> + 'a>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeployInput {
+    /// An ELF, converted for every board
+    Elf(Vec<u8>),
+    /// A uf2 file written as it is, with the family ids of its blocks in the order they appear
+    Uf2 { data: Vec<u8>, families: Vec<u32> },
+    /// Runs of bytes with the address each is loaded at
+    Image(Vec<(u64, Vec<u8>)>),
+}
+
+impl DeployInput {
+    /// Read the input file at `path`, see [`DeployInput::parse`].
+    pub fn read(path: &Path, base: Option<u32>) -> Result<Self> {
+        check_input_file(path)?;
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(data, base)
+    }
+
+    /// Tell the format of `data` by its first bytes, whatever the extension of its file. Anything
+    /// that isn't an ELF, uf2 or Intel HEX file is a raw binary, loaded at `base`.
+    pub fn parse(data: Vec<u8>, base: Option<u32>) -> Result<Self> {
+        let format = InputFormat::detect(&data);
+        log::info!("Input format: {format}");
+        if base.is_some() && format != InputFormat::Binary {
+            log::warn!("--base is ignored, it only applies to raw binaries");
+        }
+
+        match format {
+            InputFormat::Elf => Ok(DeployInput::Elf(data)),
+            InputFormat::Uf2 => {
+                let families = uf2_families(&data)?;
+                Ok(DeployInput::Uf2 { data, families })
+            }
+            InputFormat::IntelHex => {
+                let text = String::from_utf8(data).context("The Intel HEX input isn't text")?;
+                let image = parse_intel_hex(&text).context("Failed to read the Intel HEX input")?;
+                Ok(DeployInput::Image(image))
+            }
+            InputFormat::Binary => {
+                let Some(base) = base else {
+                    bail!(
+                        "The input isn't an ELF, uf2 or Intel HEX file, pass --base with the \
+                         address to load it at as a raw binary"
+                    );
+                };
+                Ok(DeployInput::Image(vec![(u64::from(base), data)]))
+            }
+        }
+    }
+
+    /// The uf2 blocks of the input for `board`, with the summary of the conversion. A uf2 input
+    /// is passed through without looking at the board or `options`.
+    pub fn blocks(
+        &self,
+        board: &dyn BoardInfo,
+        options: &Uf2Options,
+    ) -> Result<(InputBlocks<'_>, ConversionSummary), Elf2Uf2Error> {
+        match self {
+            DeployInput::Elf(elf) => {
+                let blocks = Uf2BlockIterator::with_options(Cursor::new(elf), board, options)?;
+                let summary = blocks.summary().clone();
+                Ok((Box::new(blocks), summary))
+            }
+            DeployInput::Image(image) => {
+                let blocks = Uf2BlockIterator::from_image(image, board, options)?;
+                let summary = blocks.summary().clone();
+                Ok((Box::new(blocks), summary))
+            }
+            DeployInput::Uf2 { data, .. } => {
+                let summary = ConversionSummary {
+                    num_blocks: (data.len() / UF2_BLOCK_SIZE) as u32,
+                    ..Default::default()
+                };
+                Ok((Box::new(uf2_blocks(data)), summary))
+            }
+        }
+    }
+
+    /// Fail when the input is a uf2 file none of whose blocks are for the family of `board`, the
+    /// bootloader would ignore all of them. With `force` it is only a warning.
+    pub fn check_family(
+        &self,
+        board: &dyn BoardInfo,
+        force: bool,
+        warnings: &mut Warnings,
+    ) -> Result<()> {
+        let DeployInput::Uf2 { families, .. } = self else {
+            return Ok(());
+        };
+        // Every RP2350 takes the absolute blocks, they carry no hint of the image's family
+        let families: Vec<u32> = families
+            .iter()
+            .copied()
+            .filter(|&family_id| family_id != UF2_ABSOLUTE_FAMILY_ID)
+            .collect();
+        if families.is_empty() || families.contains(&board.family_id()) {
+            return Ok(());
+        }
+
+        let message = format!(
+            "The uf2 input is for family {}, but board '{}' has family {}",
+            families
+                .iter()
+                .map(|&family_id| describe_family(family_id))
+                .collect::<Vec<_>>()
+                .join(", "),
+            board.board_name(),
+            describe_family(board.family_id())
+        );
+        if !force {
+            bail!("{message}, pass --force-family to write it anyway");
+        }
+        warnings.push(WarningCode::FamilyMismatch, message);
+        Ok(())
+    }
+
+    /// The RP2350 family id the input is for, `None` when it can't be told: inferred from an ELF
+    /// by [`rp2350::infer_family`], or the RP2350 family of a uf2 input's blocks.
+    pub fn rp2350_family(&self) -> Option<u32> {
+        match self {
+            DeployInput::Elf(elf) => {
+                let elf = ElfBytes::<AnyEndian>::minimal_parse(elf)
+                    .inspect_err(|err| log::debug!("Failed to parse the ELF: {err}"))
+                    .ok()?;
+                rp2350::infer_family(&elf)
+            }
+            DeployInput::Uf2 { families, .. } => {
+                let rp2350 = [
+                    RP2350.family_id(),
+                    RP2350RiscV.family_id(),
+                    RP2350ArmNs.family_id(),
+                ];
+                families
+                    .iter()
+                    .copied()
+                    .find(|family_id| rp2350.contains(family_id))
+            }
+            DeployInput::Image(_) => None,
+        }
+    }
+}
+
+/// The family ids of the blocks of a uf2 file, each once, failing when it isn't made of valid
+/// uf2 blocks.
+fn uf2_families(data: &[u8]) -> Result<Vec<u32>> {
+    if !data.len().is_multiple_of(UF2_BLOCK_SIZE) {
+        bail!("The uf2 input isn't made of whole {UF2_BLOCK_SIZE} byte blocks");
+    }
+
+    let mut families = Vec::new();
+    for (index, block) in uf2_blocks(data).enumerate() {
+        let block = block.expect("uf2_blocks never fails");
+        let block = Uf2Block::from_bytes(&block)
+            .with_context(|| format!("Block {index} of the uf2 input isn't a valid uf2 block"))?;
+        if let Some(family_id) = block.family_id()
+            && !families.contains(&family_id)
+        {
+            families.push(family_id);
+        }
+    }
+    Ok(families)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elf2flash_core::{
+        boards::{CircuitPlaygroundBluefruit, RP2040},
+        input::IntelHexError,
+    };
+
+    const HELLO_USB: &[u8] =
+        include_bytes!("../../../../elf2flash-core/tests/rp2040/hello_usb.elf");
+    const HELLO_USB_UF2: &[u8] =
+        include_bytes!("../../../../elf2flash-core/tests/rp2040/hello_usb.uf2");
+
+    fn flash_image(input: &DeployInput) -> Vec<(u32, Vec<u8>)> {
+        let (blocks, summary) = input.blocks(&RP2040, &Uf2Options::default()).unwrap();
+        assert_eq!(blocks.len(), summary.num_blocks as usize);
+        blocks
+            .map(|block| {
+                let block = Uf2Block::from_bytes(&block.unwrap()).unwrap();
+                (block.target_addr(), block.payload().to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn inputs_are_told_apart_by_their_contents() {
+        let elf = DeployInput::parse(HELLO_USB.to_vec(), None).unwrap();
+        assert_eq!(elf, DeployInput::Elf(HELLO_USB.to_vec()));
+
+        let uf2 = DeployInput::parse(HELLO_USB_UF2.to_vec(), None).unwrap();
+        assert_eq!(
+            uf2,
+            DeployInput::Uf2 {
+                data: HELLO_USB_UF2.to_vec(),
+                families: vec![RP2040.family_id()],
+            }
+        );
+        let (blocks, _) = uf2.blocks(&RP2040, &Uf2Options::default()).unwrap();
+        assert_eq!(
+            blocks.map(Result::unwrap).collect::<Vec<_>>().concat(),
+            HELLO_USB_UF2
+        );
+
+        let hex = ":020000041000EA\n:0400000001020304F2\n:00000001FF\n";
+        let hex = DeployInput::parse(hex.as_bytes().to_vec(), None).unwrap();
+        assert_eq!(
+            hex,
+            DeployInput::Image(vec![(0x10000000, vec![1, 2, 3, 4])])
+        );
+        let mut page = vec![1, 2, 3, 4];
+        page.resize(256, 0);
+        assert_eq!(flash_image(&hex), [(0x10000000, page.clone())]);
+
+        // A binary named like anything, only its contents count
+        let bin = DeployInput::parse(vec![1, 2, 3, 4], Some(0x10000000)).unwrap();
+        assert_eq!(flash_image(&bin), [(0x10000000, page)]);
+        let err = DeployInput::parse(vec![1, 2, 3, 4], None).unwrap_err();
+        assert!(err.to_string().contains("--base"), "{err}");
+    }
+
+    #[test]
+    fn broken_inputs_are_refused() {
+        let err = DeployInput::parse(HELLO_USB_UF2[..1000].to_vec(), None).unwrap_err();
+        assert!(err.to_string().contains("whole 512 byte blocks"), "{err}");
+
+        let mut uf2 = HELLO_USB_UF2.to_vec();
+        uf2[512 + 508] ^= 0xff;
+        let err = DeployInput::parse(uf2, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Block 1 of the uf2 input isn't a valid uf2 block"
+        );
+
+        let err = DeployInput::parse(b":0100000042BE\n".to_vec(), None).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<IntelHexError>(),
+            Some(&IntelHexError::ChecksumMismatch {
+                line: 1,
+                found: 0xbe,
+                expected: 0xbd
+            })
+        );
+    }
+
+    #[test]
+    fn uf2_for_another_family_names_both() {
+        let uf2 = DeployInput::parse(HELLO_USB_UF2.to_vec(), None).unwrap();
+        let mut warnings = Warnings::new();
+        uf2.check_family(&RP2040, false, &mut warnings).unwrap();
+
+        let err = uf2
+            .check_family(&CircuitPlaygroundBluefruit, false, &mut warnings)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The uf2 input is for family 0xe48bff56 (RP2040), but board \
+             'circuit_playground_bluefruit' has family 0xada52840 (NRF52840), pass --force-family \
+             to write it anyway"
+        );
+        assert!(warnings.is_empty());
+
+        uf2.check_family(&CircuitPlaygroundBluefruit, true, &mut warnings)
+            .unwrap();
+        assert!(warnings.contains(WarningCode::FamilyMismatch));
+
+        // ELF inputs are converted for the board's family
+        let elf = DeployInput::parse(HELLO_USB.to_vec(), None).unwrap();
+        elf.check_family(&CircuitPlaygroundBluefruit, false, &mut warnings)
+            .unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+//! Cross-checking the board a device is recognized as against the `--board` or `--family` given.
+//! A program for another chip is ignored by the bootloader at best, so a disagreement is only
+//! flashed once confirmed.
+
+use anyhow::{Result, bail};
+use elf2flash_core::boards::{BoardInfo, BoardIter, family::describe_family};
+
+use crate::{commands::convert::BoardSpec, interactive};
+
+/// The family ids of the RP2350, which runs Arm and RISC-V programs alike
+const RP2350_FAMILIES: [u32; 3] = [0xe48bff59, 0xe48bff5a, 0xe48bff5b];
+
+/// Whether programs for the two families run on the same chip.
+fn same_chip(a: u32, b: u32) -> bool {
+    a == b || (RP2350_FAMILIES.contains(&a) && RP2350_FAMILIES.contains(&b))
+}
+
+/// What to do with a device, see [`decide`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Nothing to cross-check, or the families agree
+    Flash,
+    /// The families disagree, and `--force` says to flash anyway
+    Forced,
+    /// The families disagree, ask on the terminal
+    Ask,
+    /// The families disagree, and there is no terminal to ask on
+    Refuse,
+}
+
+/// What to do with a device recognized with the `detected` family, when the `--board` or
+/// `--family` given asks for the `requested` one. Only a device with both is cross-checked.
+pub fn decide(
+    detected: Option<u32>,
+    requested: Option<u32>,
+    force: bool,
+    is_tty: bool,
+) -> Decision {
+    match (detected, requested) {
+        (Some(detected), Some(requested)) if !same_chip(detected, requested) => {
+            if force {
+                Decision::Forced
+            } else if is_tty {
+                Decision::Ask
+            } else {
+                Decision::Refuse
+            }
+        }
+        _ => Decision::Flash,
+    }
+}
+
+/// The family `spec` asks for and how it was asked for, e.g. `--board rp2040 (family 0xe48bff56
+/// (RP2040))`. `None` without a `--board` or `--family`.
+fn requested(spec: &BoardSpec) -> Option<(u32, String)> {
+    if let Some(family) = spec.family {
+        return Some((family, format!("--family {}", describe_family(family))));
+    }
+    let board = BoardIter::find_by_name(spec.board.as_deref()?)?;
+    Some((
+        board.family_id(),
+        format!(
+            "--board {} (family {})",
+            board.board_name(),
+            describe_family(board.family_id())
+        ),
+    ))
+}
+
+/// Cross-check a device recognized as `detected` against the `--board` or `--family` of `spec`.
+/// Returns whether they disagree and flashing it as `spec` asks was confirmed, fails when it
+/// wasn't.
+pub fn confirm_board(
+    detected: &dyn BoardInfo,
+    spec: &BoardSpec,
+    force: bool,
+    device: &str,
+) -> Result<bool> {
+    let Some((family, asked_for)) = requested(spec) else {
+        return Ok(false);
+    };
+    let decision = decide(
+        Some(detected.family_id()),
+        Some(family),
+        force,
+        interactive::is_interactive(),
+    );
+
+    let mismatch = format!(
+        "Device {device} is recognized as {} (family {}), but {asked_for} was given",
+        detected.board_name(),
+        describe_family(detected.family_id())
+    );
+    match decision {
+        Decision::Flash => return Ok(false),
+        Decision::Forced => log::warn!("{mismatch}, flashing it anyway because of --force"),
+        Decision::Ask => {
+            log::warn!("{mismatch}");
+            if !interactive::ask("Flash it anyway?")? {
+                bail!("Not flashing device {device}, nothing was written");
+            }
+        }
+        Decision::Refuse => bail!("{mismatch}, pass --force to flash it anyway"),
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RP2040: u32 = 0xe48bff56;
+    const RP2350_ARM_S: u32 = 0xe48bff59;
+    const RP2350_RISCV: u32 = 0xe48bff5a;
+
+    #[test]
+    fn only_disagreeing_families_are_cross_checked() {
+        use Decision::{Ask, Flash, Forced, Refuse};
+
+        // (detected, requested) => decision for (force, is_tty) of
+        // (false, false), (false, true), (true, false) and (true, true)
+        let cases = [
+            ((None, None), [Flash, Flash, Flash, Flash]),
+            ((Some(RP2040), None), [Flash, Flash, Flash, Flash]),
+            ((None, Some(RP2040)), [Flash, Flash, Flash, Flash]),
+            ((Some(RP2040), Some(RP2040)), [Flash, Flash, Flash, Flash]),
+            (
+                (Some(RP2350_ARM_S), Some(RP2350_RISCV)),
+                [Flash, Flash, Flash, Flash],
+            ),
+            (
+                (Some(RP2350_ARM_S), Some(RP2040)),
+                [Refuse, Ask, Forced, Forced],
+            ),
+            (
+                (Some(RP2040), Some(RP2350_ARM_S)),
+                [Refuse, Ask, Forced, Forced],
+            ),
+        ];
+
+        for ((detected, requested), decisions) in cases {
+            for (index, expected) in decisions.into_iter().enumerate() {
+                let (force, is_tty) = (index >= 2, index % 2 == 1);
+                assert_eq!(
+                    decide(detected, requested, force, is_tty),
+                    expected,
+                    "{detected:x?} {requested:x?} force {force} tty {is_tty}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn the_family_wins_over_the_board() {
+        let spec = BoardSpec {
+            board: Some("rp2040".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            requested(&spec),
+            Some((
+                RP2040,
+                "--board rp2040 (family 0xe48bff56 (RP2040))".to_string()
+            ))
+        );
+
+        let spec = BoardSpec {
+            family: Some(RP2350_RISCV),
+            ..spec
+        };
+        assert_eq!(
+            requested(&spec),
+            Some((
+                RP2350_RISCV,
+                "--family 0xe48bff5a (RP2350_RISCV)".to_string()
+            ))
+        );
+        assert_eq!(requested(&BoardSpec::default()), None);
+    }
+}
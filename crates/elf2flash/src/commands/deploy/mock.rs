@@ -0,0 +1,149 @@
+//! Deploying onto a FAT image file instead of a USB device, so the deploy flow can be exercised
+//! end to end without hardware.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use elf2flash_core::{
+    Elf2Uf2Error, Uf2Options,
+    boards::BoardInfo,
+    uf2::UF2_BLOCK_SIZE,
+    warnings::{WarningCode, Warnings},
+};
+use fatfs::{FileSystem, FsOptions};
+
+use crate::{
+    cancel::CancellationToken,
+    commands::deploy::{
+        input::DeployInput,
+        to_usb::{check_free_space, read_uf2_file, write_uf2_file},
+        verify::{Verification, verify_uf2},
+    },
+    progress_bar::{ProgressBarReporter, log_event},
+};
+
+/// A volume that takes `delay` for every write, like a slow device
+struct SlowVolume<T> {
+    inner: T,
+    delay: Duration,
+}
+
+impl<T: Read> Read for SlowVolume<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<T: Write> Write for SlowVolume<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        thread::sleep(self.delay);
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Seek> Seek for SlowVolume<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// Write the converted `input` as the board's uf2 file onto the FAT volume in the `image` file,
+/// returning the size of the uf2 file.
+pub fn deploy_to_image(
+    input: &DeployInput,
+    image: &Path,
+    board: &dyn BoardInfo,
+    options: &Uf2Options,
+    write_delay: Duration,
+    warnings: &mut Warnings,
+    cancel: &CancellationToken,
+) -> Result<u64> {
+    let (blocks, summary) = input.blocks(board, options)?;
+    warnings.extend(summary.warnings);
+    summary.events.iter().for_each(log_event);
+
+    deploy_blocks_to_image(blocks, image, board, write_delay, warnings, cancel)?;
+    Ok(summary.num_blocks as u64 * UF2_BLOCK_SIZE as u64)
+}
+
+/// Write already converted `blocks` as the board's uf2 file onto the FAT volume in the `image` file.
+pub fn deploy_blocks_to_image(
+    blocks: impl ExactSizeIterator<Item = Result<[u8; UF2_BLOCK_SIZE], Elf2Uf2Error>>,
+    image: &Path,
+    board: &dyn BoardInfo,
+    write_delay: Duration,
+    warnings: &mut Warnings,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let fatfs = mount_image(image, write_delay)?;
+
+    if let Err(err) = check_free_space(
+        &fatfs,
+        board.uf2_filename(),
+        (blocks.len() * UF2_BLOCK_SIZE) as u64,
+    ) {
+        warnings.push(
+            WarningCode::WriteFailed,
+            format!("Skipped writing to board '{}': {err:#}", board.board_name()),
+        );
+        return Ok(());
+    }
+
+    log::info!(
+        "Writing firmware to board '{}' (volume image {})",
+        board.board_name(),
+        image.display()
+    );
+
+    write_uf2_file(
+        &fatfs,
+        blocks,
+        board,
+        board.write_chunk_size(),
+        ProgressBarReporter::labeled(format!("{} {}", board.board_name(), image.display()))
+            .with_cancellation(cancel.clone()),
+        warnings,
+    )
+}
+
+/// Read the board's uf2 file back from the FAT volume in the `image` file, and compare it with the
+/// converted `input`.
+pub fn verify_image(
+    input: &DeployInput,
+    image: &Path,
+    board: &dyn BoardInfo,
+    options: &Uf2Options,
+    warnings: &mut Warnings,
+) -> Result<Option<Verification>> {
+    let (blocks, _) = input.blocks(board, options)?;
+    let expected = blocks.collect::<Result<Vec<_>, _>>()?.concat();
+    let read_back = || read_uf2_file(&mount_image(image, Duration::ZERO)?, board.uf2_filename());
+    Ok(verify_uf2(board, &expected, read_back, warnings))
+}
+
+/// Mount the FAT volume in the `image` file, every write to it taking `write_delay`.
+fn mount_image(image: &Path, write_delay: Duration) -> Result<FileSystem<SlowVolume<File>>> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(image)
+        .with_context(|| format!("Failed to open the volume image {}", image.display()))?;
+    FileSystem::new(
+        SlowVolume {
+            inner: file,
+            delay: write_delay,
+        },
+        FsOptions::new(),
+    )
+    .with_context(|| format!("Failed to mount the volume image {}", image.display()))
+}
@@ -1,156 +1,1249 @@
-use std::{fs::File, io::Read};
+use std::{
+    cell::OnceCell,
+    collections::HashMap,
+    ffi::OsStr,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
-use anyhow::Result;
+use anyhow::{Result, anyhow, bail};
+use clap::{Args, Parser, ValueEnum, ValueHint};
 use elf2flash_core::{
-    boards::{BoardIter, CustomBoardBuilder},
-    elf2uf2,
+    Uf2Options,
+    boards::{
+        BoardInfo, BoardIter, CustomBoard, CustomBoardBuilder, RP2350, UsbDevice,
+        family::describe_family, is_fat_short_name, is_valid_fat_name,
+    },
+    uf2::UF2_BLOCK_SIZE,
+    warnings::{WarningCode, Warnings},
 };
+use serde::{Deserialize, Serialize};
+use usbh_fatfs::{FatPartition, usbh_scsi::select::serial_number};
 
 use crate::{
-    commands::deploy::to_usb::{deploy_to_usb, get_plugged_in_boards, list_uf2_partitions},
-    progress_bar::ProgressBarReporter,
+    BoardValueParser,
+    cancel::{CancellationToken, Cancelled},
+    commands::convert::{BoardSpec, ExcludeArgs, ExtensionTagArgs, offset_parser},
+    commands::deploy::{
+        backup::{BackupOptions, backup_volume, create_backup_dir},
+        input::DeployInput,
+        mismatch::confirm_board,
+        mock::{deploy_to_image, verify_image},
+        mount::{MountTable, MountedVolume, deploy_to_volume},
+        partition::{PartitionSelector, choose_partitions},
+        reboot::{RebootFilter, reboot_into_bootsel},
+        report::{BugReport, DeployReport, DeviceReport, device_reports},
+        save::{SavedUf2, check_save_path},
+        select::{DeviceSelector, check_missing_selectors, select_devices},
+        summary::print_summary,
+        to_picoboot::{deploy_over_picoboot, has_picoboot},
+        to_usb::{
+            PluggedInDevice, WriteOptions, board_uf2_partitions, check_free_space, deploy_to_usb,
+            get_plugged_in_boards, plugged_in_descriptors, recognize_generic_device, usb_timeout,
+            with_partition_fs,
+        },
+        verify::Verification,
+        wait::{DEFAULT_WAIT_SECS, POLL_INTERVAL, wait_for_devices},
+        watch::watch,
+    },
+    commands::monitor::{MonitorOptions, monitor_port, pump::InputArgs, reconnect::ReconnectArgs},
+    exit_code::CliError,
+    family_parser, num_parser,
+    output::{self, DeployOutput},
+    progress_bar::log_event,
+    serial::{SerialArgs, SerialOutputArgs, SerialSettings},
+    size_parser, usb_id_parser,
 };
 
+pub mod backup;
+pub mod input;
+pub mod mismatch;
+pub mod mock;
+pub mod mount;
+pub mod partition;
+pub mod reboot;
+pub mod report;
+pub mod save;
+pub mod select;
+pub mod summary;
+pub mod to_picoboot;
 pub mod to_usb;
+pub mod verify;
+pub mod wait;
+pub mod watch;
 
-pub fn deploy(
-    input: String,
-    board: Option<String>,
-    family: Option<u32>,
-    flash_sector_erase_size: Option<u64>,
-    page_size: Option<u32>,
-    serial: bool,
-    term: bool,
-) -> Result<()> {
-    let serial_ports_before = serialport::available_ports()?;
+#[derive(Args, Debug, Clone)]
+pub struct DeployArgs {
+    /// Input file: an ELF, a uf2 file written as it is, an Intel HEX file, or a raw binary with
+    /// --base. The format is told by the contents, not the extension
+    #[clap(value_hint = ValueHint::FilePath)]
+    pub input: PathBuf,
 
-    log::info!("Getting input file from {:?}", input);
+    /// Same options as convert… [env: ELF2FLASH_BOARD]
+    #[clap(short, long, value_parser = BoardValueParser, hide_possible_values = true)]
+    pub board: Option<String>,
 
-    let mut input = File::open(input)?;
-    let mut buf = Vec::new();
-    input.read_to_end(&mut buf)?;
-    let input = buf;
+    /// Override family ID, either a number or a name from the uf2 family list (e.g. SAMD51)
+    /// [env: ELF2FLASH_FAMILY]
+    #[clap(short, long, value_parser = family_parser)]
+    pub family: Option<u32>,
 
-    log::info!("Getting plugged in boards\n");
+    /// Flash erase sector size
+    #[clap(short = 'e', long, value_parser = size_parser)]
+    pub flash_sector_erase_size: Option<u64>,
 
-    let plugged_in_boards = get_plugged_in_boards()?;
+    /// Page size
+    #[clap(short, long, value_parser = num_parser)]
+    pub page_size: Option<u32>,
 
-    if plugged_in_boards.is_empty() {
-        log::warn!("No uf2 devices found.");
-        return Ok(());
-    } else {
-        log::info!("Found board(s):");
-        for board in &plugged_in_boards {
-            if let Some(board) = &board.1 {
-                log::info!(
-                    "    board: {} (family id: {:#x})",
-                    board.board_name(),
-                    board.family_id(),
-                )
-            } else {
-                log::info!("    unorganized uf2 device");
-            }
+    /// Only flash devices with this USB vendor id, e.g. 0x2e8a
+    #[clap(long, value_name = "ID", value_parser = usb_id_parser)]
+    pub vendor_id: Option<u16>,
+
+    /// Only flash devices with this USB product id
+    #[clap(long, value_name = "ID", value_parser = usb_id_parser)]
+    pub product_id: Option<u16>,
+
+    /// Wait up to this many seconds (30 without a number) for a device to flash to show up, for
+    /// boards that are still entering their bootloader
+    #[clap(long, value_name = "SECS", num_args = 0..=1, require_equals = true)]
+    pub wait: Option<Option<u64>>,
+
+    /// Reboot running RP2040 and RP2350 boards matching --board, --family and --device into
+    /// BOOTSEL first, through a 1200 baud touch of their USB serial port or their reset interface,
+    /// then wait for them like --wait
+    #[clap(long)]
+    pub reboot: bool,
+
+    /// Keep running and deploy again every time the input file changes, e.g. when it is rebuilt,
+    /// until Ctrl+C
+    #[clap(long, conflicts_with = "serial")]
+    pub watch: bool,
+
+    /// Connect to serial after deploy. Firmware that doesn't look like it enables USB CDC only gets
+    /// a short wait for its port, --serial=force waits the full 20 seconds regardless
+    #[clap(
+        short,
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "auto"
+    )]
+    pub serial: Option<SerialMode>,
+
+    /// Don't connect to serial after deploy, even when `serial = true` is in the config
+    #[clap(long, conflicts_with = "serial")]
+    pub no_serial: bool,
+
+    /// Send termination message on Ctrl+C
+    #[clap(short, long)]
+    pub term: bool,
+
+    /// Don't send the termination message, even when `term = true` is in the config
+    #[clap(long, conflicts_with = "term")]
+    pub no_term: bool,
+
+    #[clap(flatten)]
+    pub serial_options: SerialArgs,
+
+    #[clap(flatten)]
+    pub output_options: SerialOutputArgs,
+
+    #[clap(flatten)]
+    pub input_options: InputArgs,
+
+    #[clap(flatten)]
+    pub reconnect_options: ReconnectArgs,
+
+    /// Read back what was written and fail the deploy if it differs: the flash over PICOBOOT, which
+    /// RP2040 and RP2350 boards are written through with it, otherwise the uf2 file on the volume
+    /// before the bootloader reboots
+    #[clap(long)]
+    pub verify: bool,
+
+    /// Before writing, copy the files visible on the bootloader volume into a timestamped folder
+    /// inside this directory
+    #[clap(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+    pub backup: Option<PathBuf>,
+
+    /// Abort the deploy if any file on the volume couldn't be backed up
+    #[clap(long, requires = "backup")]
+    pub backup_required: bool,
+
+    /// Also save the uf2 file flashed to this path, creating the directories it is in
+    #[clap(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub save_uf2: Option<PathBuf>,
+
+    /// Replace the --save-uf2 file if it already exists
+    #[clap(long, requires = "save_uf2")]
+    pub overwrite: bool,
+
+    /// Detect the boards and convert the input for them without writing anything, with
+    /// --save-uf2 to keep the uf2 file a deploy would write
+    #[clap(long, conflicts_with_all = ["reboot", "verify", "serial"])]
+    pub dry_run: bool,
+
+    /// Deploy a RAM-only uf2 (sets the not main flash flag), detected automatically for programs
+    /// that only load into the board's RAM
+    #[clap(long)]
+    pub ram: bool,
+
+    /// Only write the pages the program covers, instead of every page of each touched flash
+    /// sector. Smaller for images with large gaps, but bootloaders that erase whole sectors can
+    /// lose the pages left out
+    #[clap(long)]
+    pub no_sector_fill: bool,
+
+    /// Also fill the pages after the program in the last flash sector it touches, for bootloaders
+    /// that only erase the pages they write, where old data would survive behind the program
+    #[clap(long, conflicts_with = "no_sector_fill")]
+    pub pad_final_sector: bool,
+
+    /// Write the checksum the RP2040 bootrom expects into the second stage bootloader, for
+    /// projects whose boot2 lacks it. Only applies to RP2040 boards
+    #[clap(long)]
+    pub fix_boot2: bool,
+
+    /// Move the program by this many bytes, e.g. 0x8000 for an application linked for the start
+    /// of flash that goes behind a bootloader. Must be a multiple of the page size
+    #[clap(
+        long,
+        value_name = "OFFSET",
+        value_parser = offset_parser,
+        allow_hyphen_values = true,
+        default_value = "0"
+    )]
+    pub offset: i64,
+
+    /// Address to load a raw binary input at, e.g. 0x10000000
+    #[clap(long, value_name = "ADDRESS", value_parser = num_parser)]
+    pub base: Option<u32>,
+
+    /// Write a uf2 input even to boards of another family than its blocks
+    #[clap(long)]
+    pub force_family: bool,
+
+    /// Flash devices recognized as another chip than the --board or --family given, without
+    /// asking first
+    #[clap(long)]
+    pub force: bool,
+
+    /// Only flash the matching devices (serial:<SERIAL>, port:<BUS-PORTS>, vidpid:<VID>:<PID>,
+    /// label:<LABEL> or index:<N>), can be repeated or comma separated
+    #[clap(long = "device", value_name = "SELECTOR", value_delimiter = ',')]
+    pub devices: Vec<DeviceSelector>,
+
+    /// Flash the uf2 partition with this index (from 0) or volume label, instead of the one
+    /// picked for the board, when a device has several
+    #[clap(long, value_name = "INDEX|LABEL", conflicts_with = "all_partitions")]
+    pub partition: Option<PartitionSelector>,
+
+    /// Flash every uf2 partition of a device, not just the one picked for the board
+    #[clap(long)]
+    pub all_partitions: bool,
+
+    #[clap(flatten)]
+    pub extension_tags: ExtensionTagArgs,
+
+    #[clap(flatten)]
+    pub exclude: ExcludeArgs,
+
+    /// Don't fail when a --device selector matches no device
+    #[clap(long, requires = "devices")]
+    pub allow_missing: bool,
+
+    /// Succeed when no uf2 device is plugged in, instead of failing with exit code 2
+    #[clap(long)]
+    pub allow_no_device: bool,
+
+    /// Print the found devices and the warnings of the run as JSON once done
+    #[clap(long)]
+    pub json: bool,
+
+    /// Fail the deploy if a warning with this code was raised (e.g. write-failed), can be repeated
+    /// or comma separated
+    #[clap(long, value_name = "CODE", value_delimiter = ',')]
+    pub deny_warning: Vec<WarningCode>,
+
+    /// Write the uf2 file in chunks of exactly the board's size (16 KiB for most boards) or the
+    /// --chunk-size, instead of rounding them up to the volume's cluster size
+    #[clap(long)]
+    pub chunk_size_exact: bool,
+
+    /// Write the uf2 file this many bytes at a time instead of the board's chunk size, a multiple
+    /// of 512
+    #[clap(long, value_name = "BYTES", value_parser = chunk_size_parser)]
+    pub chunk_size: Option<usize>,
+
+    /// Wait this many milliseconds between two chunks, for bootloaders that drop blocks when they
+    /// are written too fast
+    #[clap(long, value_name = "MS")]
+    pub chunk_delay_ms: Option<u64>,
+
+    /// Write the uf2 as this file instead of the board's own, out.uf2 for the built-in boards.
+    /// 8.3 names like CURRENT.UF2 are written as they are, others get a long file name entry
+    #[clap(long, value_name = "NAME", value_parser = target_name_parser)]
+    pub target_name: Option<String>,
+
+    /// How to write to the devices: through raw USB access, by copying onto the volume the OS
+    /// mounted, through raw USB access falling back to the mounted volume (auto, the default), or
+    /// straight to the flash of RP2040 and RP2350 boards over PICOBOOT
+    #[clap(long, value_enum, env = "ELF2FLASH_BACKEND")]
+    pub backend: Option<Backend>,
+
+    /// Once done or failed, save the versions in use, the found devices, the warnings and the error
+    /// of the run to this file, with serial numbers and the home directory redacted, to attach to
+    /// an issue
+    #[clap(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub bug_report: Option<PathBuf>,
+
+    /// Keep the serial numbers of the devices in the --bug-report
+    #[clap(long, requires = "bug_report")]
+    pub bug_report_serials: bool,
+
+    /// Deploy onto the FAT volume in this image file instead of the connected devices, needs a
+    /// board from --board, ELF2FLASH_BOARD or a config file
+    #[clap(long, value_name = "IMAGE", value_hint = ValueHint::FilePath, hide = true)]
+    pub mock_volume: Option<PathBuf>,
+
+    /// Milliseconds every write to the --mock-volume takes
+    #[clap(
+        long,
+        value_name = "MS",
+        hide = true,
+        requires = "mock_volume",
+        default_value_t = 0
+    )]
+    pub mock_write_delay: u64,
+}
+
+impl DeployArgs {
+    /// `deploy <input>` without any flags, with clap's defaults, for commands that deploy with a
+    /// few of the fields set.
+    pub fn new(input: PathBuf) -> Self {
+        #[derive(Parser)]
+        struct Deploy {
+            #[clap(flatten)]
+            args: DeployArgs,
         }
+        Deploy::parse_from([OsStr::new("deploy"), input.as_os_str()]).args
     }
+}
 
-    log::info!("\n");
+/// How long `--serial` waits for the port of the flashed firmware.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SerialMode {
+    /// Wait the full time only when the firmware likely enables USB CDC
+    Auto,
+    /// Always wait the full time
+    Force,
+}
 
-    for plugged_in_board in plugged_in_boards {
-        let (_usb, plugged_in_board, mut storage_usb) = plugged_in_board;
-        let custom_board = if let Some(board) = plugged_in_board {
-            CustomBoardBuilder::new()
-                .board_name(board.board_name())
-                .family_id(family.unwrap_or(board.family_id()))
-                .flash_sector_erase_size(
-                    flash_sector_erase_size.unwrap_or(board.flash_sector_erase_size()),
-                )
-                .page_size(page_size.unwrap_or(board.page_size()))
-        } else if let Some(ref board) = board {
-            let board = BoardIter::new()
-                .into_iter()
-                .find(|b| &b.board_name() == board)
-                .expect("Should be impossible for unrecognized board to appear here");
-
-            CustomBoardBuilder::new()
-                .board_name(board.board_name())
-                .family_id(family.unwrap_or(board.family_id()))
-                .flash_sector_erase_size(
-                    flash_sector_erase_size.unwrap_or(board.flash_sector_erase_size()),
-                )
-                .page_size(page_size.unwrap_or(board.page_size()))
-        } else {
-            let family = match family {
-                Some(family) => family,
-                None => {
-                    log::info!("Cannot flash to generic uf2 device without a family id specified");
-                    continue;
+/// How `deploy` writes the uf2 file onto a device.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Raw USB access, falling back to the mounted volume when the device can't be opened
+    #[default]
+    Auto,
+    /// Only write through raw USB access
+    Raw,
+    /// Only copy onto the volume the OS mounted
+    Mount,
+    /// Erase and write the flash of RP2040 and RP2350 boards through the PICOBOOT interface of
+    /// their bootrom
+    Picoboot,
+}
+
+/// Where a device gets its uf2 file written.
+enum Target {
+    /// A uf2 partition written through raw USB access
+    Partition(FatPartition),
+    /// The volume the OS mounted for the device
+    Volume(MountedVolume),
+    /// The flash of the device, written over PICOBOOT
+    Picoboot,
+}
+
+/// How long `--serial` looks for the new port
+const SERIAL_WAIT: Duration = Duration::from_secs(20);
+/// How long it looks when the firmware doesn't look like it has a USB serial port, in case the
+/// heuristic missed it
+const SERIAL_WAIT_WITHOUT_CDC: Duration = Duration::from_secs(2);
+/// How often the serial ports are listed while looking for the new one
+const SERIAL_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A file name the uf2 can be written as in the root of the bootloader volume
+fn target_name_parser(s: &str) -> Result<String, &'static str> {
+    if is_valid_fat_name(s) {
+        Ok(s.to_string())
+    } else {
+        Err("invalid FAT file name, it can't contain / \\ : * ? \" < > | or end in a dot or space")
+    }
+}
+
+/// A chunk size for `--chunk-size`, whole uf2 blocks
+fn chunk_size_parser(s: &str) -> Result<usize, &'static str> {
+    let chunk_size = s.parse::<usize>().map_err(|_| "invalid number of bytes")?;
+    if chunk_size == 0 || !chunk_size.is_multiple_of(UF2_BLOCK_SIZE) {
+        return Err("the chunk size must be a non-zero multiple of 512");
+    }
+    Ok(chunk_size)
+}
+
+/// How long `--serial` looks for the new port, the `--usb-timeout-ms` if given, see
+/// [`SerialMode`]. The full time when it isn't known whether the program has USB CDC, like for a
+/// uf2, hex or bin input.
+pub fn serial_wait(
+    mode: SerialMode,
+    likely_has_cdc: Option<bool>,
+    usb_timeout: Option<Duration>,
+) -> Duration {
+    if let Some(timeout) = usb_timeout {
+        timeout
+    } else if mode == SerialMode::Force || likely_has_cdc != Some(false) {
+        SERIAL_WAIT
+    } else {
+        SERIAL_WAIT_WITHOUT_CDC
+    }
+}
+
+/// The board to flash `usb` as: the `--board` if it recognizes the device or the device wasn't
+/// recognized, the board it was recognized as, or a generic uf2 device with the `--family`. The
+/// rest of `spec` overrides the board's values. `None` for a generic device without a family id.
+///
+/// Preferring the `--board` for its own devices picks between variants of one chip, like
+/// `rp2350-riscv` for a device recognized as an `rp2350`.
+///
+/// The USB ids of `spec` are kept on the board, so [`BoardInfo::is_device_board`] only accepts the
+/// devices that have them, the same way the built-in boards recognize their devices.
+pub fn device_board(
+    usb: &UsbDevice,
+    detected: Option<&dyn BoardInfo>,
+    spec: &BoardSpec,
+) -> Result<Option<CustomBoard>> {
+    let named = spec
+        .board
+        .as_deref()
+        .map(|name| {
+            BoardIter::find_by_name(name)
+                .expect("Should be impossible for unrecognized board to appear here")
+        })
+        .filter(|named| detected.is_none() || named.is_device_board(usb));
+
+    let builder = match named.as_deref().or(detected) {
+        Some(board) => CustomBoardBuilder::from_board(board),
+        None if spec.family.is_some() => CustomBoardBuilder::new().board_name("generic_uf2"),
+        None => return Ok(None),
+    };
+
+    Ok(Some(spec.apply_to(builder).build()?))
+}
+
+/// `spec` for a device recognized as `detected`. An RP2350 runs Arm and RISC-V programs with
+/// different family ids, so unless a `--board` or `--family` is given, it gets the family `infer`
+/// finds from the ELF.
+fn device_spec(
+    spec: &BoardSpec,
+    detected: Option<&dyn BoardInfo>,
+    infer: impl FnOnce() -> Option<u32>,
+) -> BoardSpec {
+    let is_rp2350 = detected.is_some_and(|board| board.board_name() == RP2350.board_name());
+    if !is_rp2350 || spec.board.is_some() || spec.family.is_some() {
+        return spec.clone();
+    }
+
+    BoardSpec {
+        family: infer(),
+        ..spec.clone()
+    }
+}
+
+/// Whether `usb` is written over PICOBOOT. The bootroms reboot on the last block without keeping
+/// the file, so with `--verify` their flash is read back over PICOBOOT, unless a backup of the
+/// volume is wanted or another backend was picked.
+fn writes_over_picoboot(backend: Backend, verify: bool, backup: bool, usb: &UsbDevice) -> bool {
+    match backend {
+        Backend::Picoboot => true,
+        Backend::Auto => verify && !backup && has_picoboot(usb),
+        Backend::Raw | Backend::Mount => false,
+    }
+}
+
+/// Log the outcome of a `--verify`, and return whether it passed.
+fn report_verification(verification: &Verification) -> bool {
+    if verification.passed() {
+        log::info!("{verification}");
+    } else {
+        log::error!("{verification}");
+    }
+    verification.passed()
+}
+
+/// The generic devices [`has_device_to_flash`] already recognized, by bus number and address. See
+/// [`recognize_generic_device`] for the values.
+type GenericBoards = HashMap<(u8, u8), Option<Option<Box<dyn BoardInfo>>>>;
+
+/// Whether any of `devices`, listed by [`plugged_in_descriptors`], would be flashed: accepted by
+/// its board for `spec` the same way the deploy checks it, and selected by the `--device`
+/// `selectors`.
+///
+/// A device is only opened once its board is accepted, for the serial number and labels the
+/// selectors need. When no device is recognized, the generic ones are recognized from their
+/// `INFO_UF2.TXT` like [`get_plugged_in_boards`] does, but only the first time they show up.
+fn has_device_to_flash(
+    devices: &mut [PluggedInDevice],
+    spec: &BoardSpec,
+    selectors: &[DeviceSelector],
+    generic_boards: &mut GenericBoards,
+) -> bool {
+    let fallback = devices.iter().all(|(_, board, _)| board.is_none());
+    let by_serial = selectors
+        .iter()
+        .any(|selector| matches!(selector, DeviceSelector::Serial(_)));
+    let by_label = selectors
+        .iter()
+        .any(|selector| matches!(selector, DeviceSelector::Label(_)));
+
+    devices
+        .iter_mut()
+        .enumerate()
+        .any(|(index, (usb, board, storage_usb))| {
+            let detected = match board {
+                Some(board) => Some(&**board),
+                None if fallback => {
+                    let generic = generic_boards
+                        .entry((usb.bus_number, usb.address))
+                        .or_insert_with(|| recognize_generic_device(usb, storage_usb));
+                    match generic {
+                        Some(board) => board.as_deref(),
+                        None => return false,
+                    }
                 }
+                None => return false,
             };
-            let mut board = CustomBoardBuilder::new()
-                .board_name("generic_uf2")
-                .family_id(family);
 
-            if let Some(flash_sector_erase_size) = flash_sector_erase_size {
-                board = board.flash_sector_erase_size(flash_sector_erase_size);
+            let accepted = device_board(usb, detected, spec)
+                .is_ok_and(|board| board.is_some_and(|board| board.is_device_board(usb)));
+            if !accepted || selectors.is_empty() {
+                return accepted;
             }
 
-            if let Some(page_size) = page_size {
-                board = board.page_size(page_size);
+            let mut report = DeviceReport::new(index, usb, detected, storage_usb);
+            if by_serial {
+                report.serial = serial_number(&storage_usb.usb_device);
+            }
+            if by_label {
+                report.read_labels(storage_usb);
             }
+            selectors.iter().any(|selector| selector.matches(&report))
+        })
+}
 
-            board
+pub fn deploy(args: DeployArgs) -> Result<()> {
+    if args.watch {
+        if output::is_json() {
+            bail!("--watch deploys more than once, it can't be used with --format json");
+        }
+        // Only a file that was there before watching is refused, each deploy replaces the last
+        if let Some(path) = &args.save_uf2 {
+            check_save_path(path, args.overwrite)?;
+        }
+        let args = DeployArgs {
+            overwrite: true,
+            ..args
         };
+        let cancel = CancellationToken::ctrl_c()?;
+        let input = args.input.clone();
+        return watch(&input, &cancel, || deploy_once(args.clone(), &cancel));
+    }
+
+    // With --term, Ctrl+C is left alone until the serial port is open, then it sends the
+    // termination message
+    let cancel = if args.serial.is_some() && args.term {
+        CancellationToken::new()
+    } else {
+        CancellationToken::ctrl_c()?
+    };
+    deploy_once(args, &cancel)
+}
 
-        let custom_board = custom_board
-            .build()
-            .expect("Should be able to build custom boarod");
+/// Convert and write the input to the plugged in boards once.
+fn deploy_once(args: DeployArgs, cancel: &CancellationToken) -> Result<()> {
+    log::info!("Getting input file from {}", args.input.display());
+    let input = DeployInput::read(&args.input, args.base)?;
+    deploy_input(&input, args, cancel)
+}
 
-        let partitions = match list_uf2_partitions(&custom_board, &mut storage_usb) {
-            Ok(partitions) => partitions,
-            Err(_err) => continue,
-        };
+/// Write `input` to the plugged in boards once, as the flags of `args` say. Its input file and
+/// `--base` are left alone, for commands writing an input of their own through the deploy.
+pub fn deploy_input(
+    input: &DeployInput,
+    args: DeployArgs,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let mut bug_report = args.bug_report.clone().map(|path| BugReport {
+        path,
+        keep_serials: args.bug_report_serials,
+        selector_serials: args
+            .devices
+            .iter()
+            .filter_map(|selector| match selector {
+                DeviceSelector::Serial(serial) => Some(serial.clone()),
+                _ => None,
+            })
+            .collect(),
+    });
+    let mut reports = Vec::new();
+    let mut warnings = Warnings::new();
+    let deployed = deploy_devices(
+        input,
+        args,
+        cancel,
+        &mut reports,
+        &mut warnings,
+        &mut bug_report,
+    );
+    // Not saved yet when the deploy stopped on an error
+    if let (Err(err), Some(bug_report)) = (&deployed, bug_report)
+        && let Err(save_err) = bug_report.save(&reports, &warnings, Some(format!("{err:#}")))
+    {
+        log::warn!("{save_err:#}");
+    }
+    deployed
+}
 
-        let mut output = Vec::new();
+/// [`deploy_input`], leaving the devices found and the warnings raised in `reports` and
+/// `warnings`. The `bug_report` is taken once it is saved.
+fn deploy_devices(
+    input: &DeployInput,
+    args: DeployArgs,
+    cancel: &CancellationToken,
+    reports: &mut Vec<DeviceReport>,
+    warnings: &mut Warnings,
+    bug_report: &mut Option<BugReport>,
+) -> Result<()> {
+    let DeployArgs {
+        input: _,
+        base: _,
+        force_family,
+        force,
+        board,
+        family,
+        flash_sector_erase_size,
+        page_size,
+        vendor_id,
+        product_id,
+        wait,
+        reboot,
+        watch: _,
+        serial,
+        no_serial: _,
+        term,
+        no_term: _,
+        serial_options,
+        output_options,
+        input_options,
+        reconnect_options,
+        verify,
+        backup,
+        backup_required,
+        save_uf2,
+        overwrite,
+        dry_run,
+        ram,
+        no_sector_fill,
+        pad_final_sector,
+        fix_boot2,
+        offset,
+        devices,
+        partition: partition_selector,
+        all_partitions,
+        extension_tags,
+        exclude,
+        allow_missing,
+        allow_no_device,
+        json,
+        deny_warning,
+        chunk_size_exact,
+        chunk_size,
+        chunk_delay_ms,
+        target_name,
+        backend,
+        bug_report: _,
+        bug_report_serials: _,
+        mock_volume,
+        mock_write_delay,
+    } = args;
 
-        log::info!("Converting elf to uf2 file");
+    let backend = backend.unwrap_or_default();
+    if let Some(path) = &save_uf2 {
+        check_save_path(path, overwrite)?;
+    }
+    let mut saved_uf2 = save_uf2.map(SavedUf2::new);
+    if serial.is_some() && output::is_json() {
+        bail!("--serial prints the board's output on stdout, it can't be used with --format json");
+    }
 
-        elf2uf2(
-            &input,
-            &mut output,
-            &custom_board,
-            ProgressBarReporter::new(),
+    if let Some(name) = target_name
+        .as_deref()
+        .filter(|name| !is_fat_short_name(name))
+    {
+        log::info!("{name} isn't an 8.3 short name, it is written with a long file name entry");
+    }
+
+    let options = Uf2Options {
+        not_main_flash: ram,
+        extension_tags: extension_tags.tags(),
+        fill_sectors: !no_sector_fill,
+        pad_final_sector,
+        fix_rp2040_boot2: fix_boot2,
+        address_offset: offset,
+        exclude_ranges: exclude.exclude,
+        exclude_sections: exclude.exclude_section,
+        ..Default::default()
+    };
+
+    let spec = BoardSpec {
+        board,
+        family,
+        flash_sector_erase_size,
+        page_size,
+        vendor_id,
+        product_id,
+        uf2_filename: target_name,
+        write_chunk_size: chunk_size,
+        inter_chunk_delay: chunk_delay_ms.map(Duration::from_millis),
+    };
+
+    if let Some(image) = mock_volume {
+        let board = spec
+            .board
+            .as_deref()
+            .and_then(BoardIter::find_by_name)
+            .ok_or_else(|| anyhow!("--mock-volume needs a --board"))?;
+        let board = spec
+            .apply_to(CustomBoardBuilder::from_board(board.as_ref()))
+            .build()?;
+        let mut warnings = Warnings::new();
+        input.check_family(&board, force_family, &mut warnings)?;
+        if let Some(saved_uf2) = &mut saved_uf2 {
+            saved_uf2.save(input, &board, &options)?;
+        }
+        if dry_run {
+            log::info!("Dry run, not writing to {}", image.display());
+            return Ok(());
+        }
+        let started = Instant::now();
+        let bytes = deploy_to_image(
+            input,
+            &image,
+            &board,
+            &options,
+            Duration::from_millis(mock_write_delay),
+            &mut warnings,
+            cancel,
         )?;
+        let verification = if verify {
+            verify_image(input, &image, &board, &options, &mut warnings)?
+        } else {
+            None
+        };
+
+        if let Some(summary) = warnings.summary() {
+            log::warn!("{summary}");
+        }
+        // Failed writes of the uf2 file are raised as warnings
+        let write_failed = warnings
+            .iter()
+            .find(|warning| warning.code == WarningCode::WriteFailed)
+            .map(|warning| warning.message.clone());
+        let error = write_failed.clone().or_else(|| {
+            verification
+                .as_ref()
+                .filter(|verification| !report_verification(verification))
+                .map(ToString::to_string)
+        });
+        let result = DeployOutput {
+            device: DeviceReport {
+                board_name: Some(board.board_name().into_owned()),
+                labels: vec![image.display().to_string()],
+                ..Default::default()
+            },
+            board: board.board_name().into_owned(),
+            success: error.is_none(),
+            error: error.clone(),
+            bytes,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+            verification,
+        };
+        print_summary(std::slice::from_ref(&result));
+        if output::is_json() {
+            output::emit("deploy", Some([result]), error.as_deref())?;
+        } else if json {
+            let reports = [result.device];
+            println!("{}", DeployReport::new(&reports, &warnings).to_json()?);
+        }
+        if let Some(error) = write_failed {
+            bail!(CliError::WriteFailed(error));
+        }
+        if let Some(error) = error {
+            bail!(CliError::VerificationFailed(error));
+        }
+        return Ok(());
+    }
+
+    let mut wait = wait.map(|secs| secs.unwrap_or(DEFAULT_WAIT_SECS));
+    if reboot {
+        if reboot_into_bootsel(&RebootFilter::new(&devices, &spec)) > 0 {
+            wait.get_or_insert(DEFAULT_WAIT_SECS);
+        } else {
+            log::warn!(
+                "Found no running board to reboot into BOOTSEL, hold BOOTSEL while resetting the \
+                 board instead"
+            );
+        }
+    }
+
+    log::info!("Getting plugged in boards\n");
+
+    let mut plugged_in_boards = match wait {
+        Some(secs) => {
+            // Polled from the descriptors, the devices are only listed in full once one matches
+            let mut generic_boards = GenericBoards::new();
+            wait_for_devices(
+                |_| plugged_in_descriptors(),
+                |plugged| has_device_to_flash(plugged, &spec, &devices, &mut generic_boards),
+                Duration::from_secs(secs),
+                POLL_INTERVAL,
+                cancel,
+            )?;
+            get_plugged_in_boards(warnings)?
+        }
+        None => get_plugged_in_boards(warnings)?,
+    };
+    // Taken once the boards are in their bootloader, a rebooted board's old port is gone by then
+    let serial_ports_before = serialport::available_ports()?;
+    let mut likely_has_cdc = None;
+
+    if plugged_in_boards.is_empty() {
+        if !allow_no_device {
+            bail!(CliError::NoDevice);
+        }
+        log::warn!("No uf2 devices found.");
+        return output::emit("deploy", Some(Vec::<DeployOutput>::new()), None);
+    }
+
+    *reports = device_reports(&mut plugged_in_boards, &devices);
+
+    let selection = (!devices.is_empty()).then(|| select_devices(reports, &devices));
+
+    log::info!("Found board(s):");
+    for ((_, board, _), report) in plugged_in_boards.iter().zip(reports.iter()) {
+        let device = if let Some(board) = board {
+            format!(
+                "board: {} (family id: {})",
+                board.board_name(),
+                describe_family(board.family_id())
+            )
+        } else {
+            "unorganized uf2 device".to_string()
+        };
+
+        let selected = match selection.as_ref().map(|s| s.matched_by[report.index]) {
+            None => String::new(),
+            Some(Some(selector)) => format!(" [selected by {}]", devices[selector]),
+            Some(None) => " [not selected]".to_string(),
+        };
+
+        log::info!(
+            "    {}: {device}, {}{selected}",
+            report.index,
+            report.details()
+        );
+    }
+
+    if let Some(selection) = &selection {
+        check_missing_selectors(selection, &devices, reports, allow_missing, warnings)?;
+    }
+
+    log::info!("\n");
+
+    let rp2350_family = OnceCell::new();
+    let mut mount_table = None;
+    // Devices written over raw USB or PICOBOOT, whose mounted volumes are no other device's
+    let mut flashed_devices = Vec::new();
+    let mut results = Vec::new();
+    // Kept to exit with the code of what went wrong, when no device was flashed or with
+    // --deny-warning write-failed
+    let mut write_error = None;
+    for (index, plugged_in_board) in plugged_in_boards.into_iter().enumerate() {
+        cancel.check()?;
+
+        if selection.as_ref().is_some_and(|s| !s.is_selected(index)) {
+            continue;
+        }
+
+        let (usb, plugged_in_board, mut storage_usb) = plugged_in_board;
+        let spec = device_spec(&spec, plugged_in_board.as_deref(), || {
+            *rp2350_family.get_or_init(|| {
+                let family = input.rp2350_family();
+                if family.is_some() {
+                    log::info!("Pass --family to use another family id for the RP2350");
+                }
+                family
+            })
+        });
+        let confirmed = match plugged_in_board.as_deref() {
+            Some(detected) => confirm_board(detected, &spec, force, &reports[index].summary())?,
+            None => false,
+        };
+        // A confirmed --board is flashed as given, not as the board the device was recognized as
+        let detected = plugged_in_board
+            .as_deref()
+            .filter(|_| !confirmed || spec.board.is_none());
+        let Some(custom_board) = device_board(&usb, detected, &spec)? else {
+            warnings.push(
+                WarningCode::DeviceSkipped,
+                format!(
+                    "Skipped device {}, cannot flash to generic uf2 device without a family id specified",
+                    reports[index].summary()
+                ),
+            );
+            continue;
+        };
+
+        if !custom_board.is_device_board(&usb) {
+            warnings.push(
+                WarningCode::DeviceSkipped,
+                format!(
+                    "Skipped device {}, it doesn't have the --vendor-id and --product-id given",
+                    reports[index].summary()
+                ),
+            );
+            continue;
+        }
+
+        // Refused before touching the volume, the bootloader would ignore every block
+        input.check_family(&custom_board, force_family, warnings)?;
+
+        if dry_run {
+            let (_, summary) = input.blocks(&custom_board, &options)?;
+            warnings.extend(summary.warnings);
+            summary.events.iter().for_each(log_event);
+            if let Some(saved_uf2) = &mut saved_uf2 {
+                saved_uf2.save(input, &custom_board, &options)?;
+            }
+            log::info!(
+                "Dry run, not writing {} bytes to board '{}' of device {}",
+                summary.num_blocks as u64 * UF2_BLOCK_SIZE as u64,
+                custom_board.board_name(),
+                reports[index].summary()
+            );
+            continue;
+        }
+
+        let picoboot = writes_over_picoboot(backend, verify, backup.is_some(), &usb);
+        let raw = (!picoboot && matches!(backend, Backend::Auto | Backend::Raw))
+            .then(|| board_uf2_partitions(&custom_board, &mut storage_usb));
+        let targets = match raw {
+            None if picoboot => vec![Target::Picoboot],
+            Some(Ok(partitions)) => {
+                let found = partitions.len();
+                let chosen = choose_partitions(
+                    partitions,
+                    &custom_board,
+                    partition_selector.as_ref(),
+                    all_partitions,
+                );
+                match chosen {
+                    Ok(chosen) => {
+                        if let [partition] = chosen.as_slice()
+                            && found > 1
+                        {
+                            log::info!(
+                                "Flashing partition '{}', one of the {found} uf2 partitions of \
+                                 device {}, pass --partition or --all-partitions to choose",
+                                partition.volume_label.trim(),
+                                reports[index].summary()
+                            );
+                        }
+                        chosen.into_iter().map(Target::Partition).collect()
+                    }
+                    Err(err) => {
+                        warnings.push(
+                            WarningCode::DeviceSkipped,
+                            format!("Skipped device {}, {err:#}", reports[index].summary()),
+                        );
+                        continue;
+                    }
+                }
+            }
+            Some(Err(err)) if backend == Backend::Raw => {
+                warnings.push(
+                    WarningCode::DeviceSkipped,
+                    format!(
+                        "Skipped device {}, failed to find its uf2 partition: {err:#}",
+                        reports[index].summary()
+                    ),
+                );
+                continue;
+            }
+            raw => {
+                if let Some(Err(err)) = &raw {
+                    log::info!(
+                        "Failed to access device {} over USB, looking for the volume the OS mounted for it: {err:#}",
+                        reports[index].summary()
+                    );
+                }
+                let table = mount_table.get_or_insert_with(MountTable::read);
+                for (flashed_usb, flashed_board) in flashed_devices.drain(..) {
+                    table.drop_volume(&flashed_usb, &flashed_board);
+                }
+                let volume = table.take_volume(&usb, plugged_in_board.as_deref());
+                let Some(volume) = volume else {
+                    let cause = match raw {
+                        Some(Err(err)) => {
+                            format!("failed to find its uf2 partition ({err:#}) and ")
+                        }
+                        _ => String::new(),
+                    };
+                    warnings.push(
+                        WarningCode::DeviceSkipped,
+                        format!(
+                            "Skipped device {}, {cause}no mounted uf2 volume could be matched to it",
+                            reports[index].summary()
+                        ),
+                    );
+                    continue;
+                };
+                vec![Target::Volume(volume)]
+            }
+        };
+        if !matches!(targets.as_slice(), [Target::Volume(_)]) {
+            flashed_devices.push((usb.clone(), custom_board.clone()));
+        }
+
+        for target in targets {
+            match (&backup, &target) {
+                (None, _) => {}
+                (Some(backup), Target::Partition(partition)) => {
+                    let dest = create_backup_dir(backup, &custom_board)?;
+                    let options = BackupOptions {
+                        required: backup_required,
+                        ..Default::default()
+                    };
+
+                    log::info!("Backing up bootloader volume to {}", dest.display());
+
+                    match with_partition_fs(partition, &custom_board, &mut storage_usb, |fatfs| {
+                        backup_volume(fatfs, &custom_board, &dest, &options, warnings)
+                    }) {
+                        Ok(manifest) => log::info!(
+                            "Backed up {} file(s) to {}",
+                            manifest.entries.len(),
+                            dest.display()
+                        ),
+                        Err(err) if backup_required => return Err(err),
+                        Err(err) => warnings.push(
+                            WarningCode::BackupIncomplete,
+                            format!("Failed to back up bootloader volume: {err:#}"),
+                        ),
+                    }
+                }
+                (Some(_), Target::Volume(_) | Target::Picoboot) => {
+                    let message = match &target {
+                        Target::Volume(volume) => format!(
+                            "Didn't back up {}, backups need raw USB access",
+                            volume.path.display()
+                        ),
+                        _ => format!(
+                            "Didn't back up board '{}', PICOBOOT has no volume to back up",
+                            custom_board.board_name()
+                        ),
+                    };
+                    if backup_required {
+                        bail!(message);
+                    }
+                    warnings.push(WarningCode::BackupIncomplete, message);
+                }
+            }
 
-        for partition in partitions {
             log::info!("\n");
-            match deploy_to_usb(
-                &output,
-                &partition,
-                &custom_board,
-                &mut storage_usb,
-                ProgressBarReporter::new(),
-            ) {
-                Ok(_) => (),
-                Err(err) => log::error!(
-                    "Failed to deploy to usb with board: {:#?}\n with error: ({:?})",
-                    custom_board,
-                    err
+
+            let started = Instant::now();
+            let mut result = DeployOutput {
+                device: reports[index].clone(),
+                board: custom_board.board_name().into_owned(),
+                success: false,
+                error: None,
+                bytes: 0,
+                elapsed_ms: 0,
+                verification: None,
+            };
+
+            // The uf2 blocks of an ELF are converted while they are written to the board
+            let (blocks, summary) = input.blocks(&custom_board, &options)?;
+            let total_bytes = summary.num_blocks as u64 * UF2_BLOCK_SIZE as u64;
+            result.bytes = total_bytes;
+            if let Target::Partition(partition) = &target
+                && let Err(err) =
+                    with_partition_fs(partition, &custom_board, &mut storage_usb, |fatfs| {
+                        check_free_space(fatfs, custom_board.uf2_filename(), total_bytes)
+                    })
+            {
+                let message = format!(
+                    "Skipped writing to board '{}': {err:#}",
+                    custom_board.board_name()
+                );
+                warnings.push(WarningCode::WriteFailed, message.clone());
+                write_error.get_or_insert(err);
+                results.push(DeployOutput {
+                    error: Some(message),
+                    elapsed_ms: started.elapsed().as_millis() as u64,
+                    ..result
+                });
+                continue;
+            }
+
+            warnings.extend(summary.warnings);
+            summary.events.iter().for_each(log_event);
+            likely_has_cdc = likely_has_cdc.or(summary.likely_has_cdc);
+            if let Some(saved_uf2) = &mut saved_uf2 {
+                saved_uf2.save(input, &custom_board, &options)?;
+            }
+
+            let warnings_before = warnings.len();
+            let deployed = match &target {
+                Target::Partition(partition) => deploy_to_usb(
+                    blocks,
+                    partition,
+                    &custom_board,
+                    &mut storage_usb,
+                    WriteOptions {
+                        chunk_size_exact,
+                        verify,
+                    },
+                    warnings,
+                    cancel,
+                ),
+                Target::Volume(volume) => {
+                    deploy_to_volume(blocks, volume, &custom_board, verify, warnings, cancel)
+                }
+                Target::Picoboot => deploy_over_picoboot(
+                    blocks,
+                    &storage_usb.usb_device,
+                    &custom_board,
+                    usb.product_id,
+                    verify,
+                    cancel,
                 ),
+            };
+            match deployed {
+                Ok(verification) => {
+                    // Failed writes of the uf2 file are raised as warnings
+                    result.error = warnings
+                        .iter()
+                        .skip(warnings_before)
+                        .find(|warning| warning.code == WarningCode::WriteFailed)
+                        .map(|warning| warning.message.clone());
+                    if let Some(message) = &result.error {
+                        write_error
+                            .get_or_insert_with(|| anyhow!(CliError::WriteFailed(message.clone())));
+                    }
+                    if let Some(verification) = &verification
+                        && !report_verification(verification)
+                    {
+                        result.error.get_or_insert_with(|| verification.to_string());
+                    }
+                    result.verification = verification;
+                }
+                Err(err) if err.is::<Cancelled>() => {
+                    // Give the interface back now, a reset on drop can keep the device from being
+                    // claimed again for seconds
+                    storage_usb.release();
+                    return Err(err);
+                }
+                Err(err) => {
+                    let message = format!(
+                        "Failed to deploy to board '{}' with error: {err:#}",
+                        custom_board.board_name()
+                    );
+                    warnings.push(WarningCode::WriteFailed, message.clone());
+                    write_error.get_or_insert(err);
+                    result.error = Some(message);
+                }
             }
+            result.success = result.error.is_none();
+            result.elapsed_ms = started.elapsed().as_millis() as u64;
+            results.push(result);
         }
     }
 
-    if serial {
-        use std::process;
-        use std::sync::{Arc, Mutex};
-        use std::time::Duration;
-        use std::{io, thread};
+    print_summary(&results);
+    if let Some(summary) = warnings.summary() {
+        log::warn!("{summary}");
+    }
 
-        let mut counter = 0;
+    let flashed = results.iter().filter(|result| result.success).count();
+    let failed_verifications = results
+        .iter()
+        .filter(|result| result.verification.as_ref().is_some_and(|v| !v.passed()))
+        .count();
+    let failure = match deny_warning.iter().find(|&&code| warnings.contains(code)) {
+        Some(&code) => {
+            let message = format!("Warning '{code}' was raised and is denied by --deny-warning");
+            match write_error.filter(|_| code == WarningCode::WriteFailed) {
+                Some(err) => Some(err.context(message)),
+                None => Some(anyhow!(message)),
+            }
+        }
+        // A device that failed next to others that were flashed stays a warning
+        None if flashed == 0 && write_error.is_some() => {
+            write_error.map(|err| err.context("No device was flashed"))
+        }
+        None => (failed_verifications > 0).then(|| {
+            anyhow!(CliError::VerificationFailed(format!(
+                "Verification failed on {failed_verifications} device(s)"
+            )))
+        }),
+    };
+    let error = failure.as_ref().map(ToString::to_string);
+    if let Some(bug_report) = bug_report.take() {
+        let error = failure.as_ref().map(|failure| format!("{failure:#}"));
+        bug_report.save(reports, warnings, error)?;
+    }
+    if output::is_json() {
+        output::emit("deploy", Some(&results), error.as_deref())?;
+    } else if json {
+        println!("{}", DeployReport::new(reports, &warnings).to_json()?);
+    }
+    if let Some(failure) = failure {
+        return Err(failure);
+    }
+
+    if let Some(mode) = serial {
+        use std::thread;
+
+        let wait = serial_wait(mode, likely_has_cdc, usb_timeout());
 
         log::info!("\n\nLooking for microcontroller serial...");
+        if wait < SERIAL_WAIT && usb_timeout().is_none() {
+            log::info!(
+                "The firmware doesn't look like it enables USB CDC (no tinyusb CDC symbols or \
+                 pico-sdk stdio_usb strings), only waiting {} seconds for its serial port. Use \
+                 --serial=force to wait the full {} seconds",
+                wait.as_secs(),
+                SERIAL_WAIT.as_secs()
+            );
+        }
 
+        let started = Instant::now();
         let serial_port_info = 'find_loop: loop {
+            cancel.check()?;
+
             for port in serialport::available_ports()? {
                 if !serial_ports_before.contains(&port) {
                     println!("Found microcontroller serial on {}", &port.port_name);
@@ -158,76 +1251,232 @@ pub fn deploy(
                 }
             }
 
-            counter += 1;
-
-            if counter == 100 {
+            let elapsed = started.elapsed();
+            if elapsed >= wait {
+                log::warn!(
+                    "No new serial port showed up within {} ms",
+                    wait.as_millis()
+                );
                 break None;
             }
 
-            thread::sleep(Duration::from_millis(200));
+            thread::sleep(SERIAL_POLL_INTERVAL.min(wait - elapsed));
         };
 
         if let Some(serial_port_info) = serial_port_info {
-            for _ in 0..100 {
-                if let Ok(port) = serialport::new(&serial_port_info.port_name, 115200)
-                    .timeout(Duration::from_millis(100))
-                    .flow_control(serialport::FlowControl::None)
-                    .open()
-                {
-                    let port = Arc::new(Mutex::new(port));
-
-                    let handler = {
-                        let port = port.clone();
-                        move || {
-                            let mut port =
-                                port.lock().expect("Should be able to aquire lock for port");
-                            port.write_all(b"elf2flash-term\r\n").ok();
-                            port.flush().ok();
-                            process::exit(0);
-                        }
-                    };
+            let options = MonitorOptions {
+                settings: SerialSettings::from(serial_options),
+                term,
+                reconnect: reconnect_options.window(),
+                output: output_options,
+                exit_key: input_options.forwarding(),
+            };
+            monitor_port(&serial_port_info.port_name, &options, cancel)?;
+        }
+    }
 
-                    if term {
-                        ctrlc::set_handler(handler.clone()).expect("Error setting Ctrl-C handler");
-                    }
+    Ok(())
+}
 
-                    let data_terminal_ready_succeeded = {
-                        let mut port = port.lock().expect("Should be able to aquire lock for port");
-                        port.write_data_terminal_ready(true).is_ok()
-                    };
-                    if data_terminal_ready_succeeded {
-                        let mut serial_buf = [0; 1024];
-                        loop {
-                            let read = {
-                                let mut port =
-                                    port.lock().expect("Should be able to aquire lock for port");
-                                port.read(&mut serial_buf)
-                            };
-
-                            match read {
-                                Ok(t) => {
-                                    use std::io::Write;
-
-                                    io::stdout().write_all(&serial_buf[..t])?;
-                                    io::stdout().flush()?;
-                                }
-                                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => (),
-                                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
-                                    if term {
-                                        handler();
-                                    }
-                                    return Err(e.into());
-                                }
-                                Err(e) => return Err(e.into()),
-                            }
-                        }
-                    }
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::usb_device;
+    use elf2flash_core::boards::{RP2040, RP2350};
+    use std::path::Path;
 
-                thread::sleep(Duration::from_millis(200));
-            }
+    #[test]
+    fn serial_waits_less_without_cdc() {
+        assert_eq!(serial_wait(SerialMode::Auto, Some(true), None), SERIAL_WAIT);
+        assert_eq!(
+            serial_wait(SerialMode::Auto, Some(false), None),
+            SERIAL_WAIT_WITHOUT_CDC
+        );
+        assert_eq!(
+            serial_wait(SerialMode::Force, Some(false), None),
+            SERIAL_WAIT
+        );
+    }
+
+    #[test]
+    fn serial_waits_fully_when_cdc_is_unknown() {
+        // A uf2, hex or bin input isn't scanned for USB CDC
+        let input = DeployInput::Uf2 {
+            data: Vec::new(),
+            families: Vec::new(),
+        };
+        let (_, summary) = input.blocks(&RP2040, &Uf2Options::default()).unwrap();
+        assert_eq!(summary.likely_has_cdc, None);
+        assert_eq!(
+            serial_wait(SerialMode::Auto, summary.likely_has_cdc, None),
+            SERIAL_WAIT
+        );
+    }
+
+    #[test]
+    fn usb_timeout_bounds_the_serial_wait() {
+        let timeout = Duration::from_millis(1500);
+        for (mode, likely_has_cdc) in [
+            (SerialMode::Auto, Some(true)),
+            (SerialMode::Auto, Some(false)),
+            (SerialMode::Auto, None),
+            (SerialMode::Force, Some(false)),
+        ] {
+            assert_eq!(serial_wait(mode, likely_has_cdc, Some(timeout)), timeout);
         }
     }
 
-    Ok(())
+    #[test]
+    fn bootroms_are_verified_over_picoboot() {
+        let pico = usb_device(0x2e8a, 0x0003);
+        let pico2 = usb_device(0x2e8a, 0x000f);
+        let feather = usb_device(0x239a, 0x0029);
+
+        assert!(writes_over_picoboot(Backend::Auto, true, false, &pico));
+        assert!(writes_over_picoboot(Backend::Auto, true, false, &pico2));
+        assert!(!writes_over_picoboot(Backend::Auto, true, false, &feather));
+        assert!(!writes_over_picoboot(Backend::Auto, false, false, &pico));
+        // The backup needs the volume
+        assert!(!writes_over_picoboot(Backend::Auto, true, true, &pico));
+        assert!(!writes_over_picoboot(Backend::Raw, true, false, &pico));
+        assert!(writes_over_picoboot(
+            Backend::Picoboot,
+            false,
+            false,
+            &feather
+        ));
+    }
+
+    #[test]
+    fn usb_ids_limit_the_devices_flashed() {
+        let pico = usb_device(0x2e8a, 0x0003);
+        let bluefruit = usb_device(0x239a, 0x0045);
+
+        // Without ids, a recognized board is flashed as whatever device it was found on
+        let board = device_board(&pico, Some(&RP2040), &BoardSpec::default())
+            .unwrap()
+            .unwrap();
+        assert!(board.is_device_board(&pico));
+        assert!(board.is_device_board(&bluefruit));
+
+        // A fully custom board only applies to the devices with its ids
+        let spec = BoardSpec {
+            family: Some(0x12345678),
+            vendor_id: Some(0x239a),
+            ..Default::default()
+        };
+        let board = device_board(&bluefruit, None, &spec).unwrap().unwrap();
+        assert_eq!(board.board_name(), "generic_uf2");
+        assert!(!board.is_device_board(&pico));
+        assert!(board.is_device_board(&bluefruit));
+
+        let spec = BoardSpec {
+            product_id: Some(0x0046),
+            ..spec
+        };
+        let board = device_board(&bluefruit, None, &spec).unwrap().unwrap();
+        assert!(!board.is_device_board(&bluefruit));
+
+        // The ids also narrow a --board down
+        let spec = BoardSpec {
+            board: Some("rp2040".to_string()),
+            vendor_id: Some(0x2e8a),
+            product_id: Some(0x000f),
+            ..Default::default()
+        };
+        let board = device_board(&bluefruit, None, &spec).unwrap().unwrap();
+        assert_eq!(board.family_id(), RP2040.family_id());
+        assert!(!board.is_device_board(&pico));
+
+        // A generic device can't be flashed without a family id
+        assert!(
+            device_board(&bluefruit, None, &BoardSpec::default())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn rp2350_family_is_inferred_from_the_elf() {
+        let fixture = |path: &str| {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("../elf2flash-core/tests")
+                .join(path);
+            DeployInput::read(&path, None).unwrap().rp2350_family()
+        };
+        let arm = fixture("rp2350/flash_image.elf");
+        let riscv = fixture("riscv64/high_addresses.elf");
+        assert_eq!(arm, Some(0xe48bff59));
+        assert_eq!(riscv, Some(0xe48bff5a));
+
+        let spec = device_spec(&BoardSpec::default(), Some(&RP2350), || riscv);
+        assert_eq!(spec.family, riscv);
+        let board = device_board(&rp2350(), Some(&RP2350), &spec)
+            .unwrap()
+            .unwrap();
+        assert_eq!(board.family_id(), 0xe48bff5a);
+        assert_eq!(board.board_name(), "rp2350");
+
+        // --family and --board win, and other boards are left alone
+        let family = BoardSpec {
+            family: Some(0xe48bff59),
+            ..Default::default()
+        };
+        assert_eq!(device_spec(&family, Some(&RP2350), || riscv), family);
+        let board = BoardSpec {
+            board: Some("rp2350-arm-ns".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(device_spec(&board, Some(&RP2350), || riscv), board);
+        assert_eq!(
+            device_spec(&BoardSpec::default(), Some(&RP2040), || riscv),
+            BoardSpec::default()
+        );
+    }
+
+    fn rp2350() -> UsbDevice {
+        usb_device(0x2e8a, 0x000f)
+    }
+
+    #[test]
+    fn board_variant_replaces_the_detected_board() {
+        let rp2350 = rp2350();
+        let spec = BoardSpec {
+            board: Some("rp2350-riscv".to_string()),
+            ..Default::default()
+        };
+
+        let board = device_board(&rp2350, Some(&RP2350), &spec)
+            .unwrap()
+            .unwrap();
+        assert_eq!(board.board_name(), "rp2350-riscv");
+        assert_eq!(board.family_id(), 0xe48bff5a);
+        assert_eq!(board.preamble_blocks(), RP2350.preamble_blocks());
+
+        // Another recognized board keeps its own definition
+        let pico = usb_device(0x2e8a, 0x0003);
+        let board = device_board(&pico, Some(&RP2040), &spec).unwrap().unwrap();
+        assert_eq!(board.family_id(), RP2040.family_id());
+    }
+
+    #[test]
+    fn target_name_replaces_the_board_filename() {
+        let spec = BoardSpec {
+            uf2_filename: Some(target_name_parser("CURRENT.UF2").unwrap()),
+            ..Default::default()
+        };
+        let board = device_board(&rp2350(), Some(&RP2350), &spec)
+            .unwrap()
+            .unwrap();
+        assert_eq!(board.uf2_filename(), "CURRENT.UF2");
+
+        let board = device_board(&rp2350(), Some(&RP2350), &BoardSpec::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(board.uf2_filename(), "out.uf2");
+
+        assert!(target_name_parser("firmware latest.uf2").is_ok());
+        assert!(target_name_parser("build/out.uf2").is_err());
+        assert!(target_name_parser("").is_err());
+    }
 }
@@ -0,0 +1,595 @@
+//! Deploying through the volume the operating system mounted for a uf2 bootloader, for devices
+//! that can't be opened for raw USB access, like on Windows where the USBSTOR driver owns the mass
+//! storage interface, or on Linux without the udev rules.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use elf2flash_core::{
+    Elf2Uf2Error, ProgressReporter,
+    boards::{BoardInfo, UsbDevice},
+    info_uf2::InfoUf2,
+    uf2::UF2_BLOCK_SIZE,
+    warnings::{WarningCode, Warnings},
+};
+
+use crate::{
+    cancel::CancellationToken,
+    commands::deploy::{
+        to_usb::{MAX_INFO_UF2_LEN, board_for_info, write_uf2_chunks, writing_phase},
+        verify::{Verification, verify_uf2},
+    },
+    progress_bar::ProgressBarReporter,
+};
+
+/// The filesystems uf2 bootloaders format their volume with, as Linux names them
+const FAT_FS_TYPES: [&str; 3] = ["vfat", "msdos", "exfat"];
+
+/// A mounted filesystem, with the device it is on when the OS tells.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountPoint {
+    pub path: PathBuf,
+    /// The device node, like `/dev/sdb1`
+    pub source: Option<PathBuf>,
+}
+
+/// A mounted volume with an `INFO_UF2.TXT` in its root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountedVolume {
+    pub path: PathBuf,
+    pub source: Option<PathBuf>,
+    pub info: InfoUf2,
+}
+
+/// A link in `/dev/disk/by-id` and the device node it points to. USB disks are named after their
+/// vendor, product and serial number, like `usb-RPI_RP2_E0C9125B0D9B-0:0-part1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskLink {
+    pub name: String,
+    pub target: PathBuf,
+}
+
+/// The uf2 volumes the OS mounted, read once and handed out to the devices they belong to.
+#[derive(Debug, Default)]
+pub struct MountTable {
+    pub volumes: Vec<MountedVolume>,
+    pub links: Vec<DiskLink>,
+}
+
+impl MountTable {
+    /// Look through the mounted volumes of this OS for uf2 volumes.
+    pub fn read() -> Self {
+        let table = Self {
+            volumes: uf2_volumes(mount_points()),
+            links: disk_links(),
+        };
+        log::debug!("Found mounted uf2 volume(s): {:?}", table.volumes);
+        table
+    }
+
+    /// Take the volume of the device `usb`, recognized as `board`, out of the table. Tried in
+    /// order, a volume is the device's when:
+    /// - a disk link with the device's serial number points to the volume's device node
+    /// - it is the only volume whose `INFO_UF2.TXT` belongs to `board`
+    /// - it is the only volume left, and its `INFO_UF2.TXT` doesn't name a board other than `board`,
+    ///   or any board at all for a generic device
+    pub fn take_volume(
+        &mut self,
+        usb: &UsbDevice,
+        board: Option<&dyn BoardInfo>,
+    ) -> Option<MountedVolume> {
+        let index = self
+            .volume_by_serial(usb)
+            .or_else(|| self.volume_by_board_id(board?))
+            .or_else(|| {
+                only(0..self.volumes.len())
+                    .filter(|&index| !contradicts(board, &self.volumes[index].info))
+            })?;
+        Some(self.volumes.remove(index))
+    }
+
+    /// Drop the volume of the device `usb`, flashed as `board` without going through the table,
+    /// so it isn't handed to another device. Only a volume known to be the device's is dropped,
+    /// by its serial number or as the only volume whose `INFO_UF2.TXT` belongs to `board`.
+    pub fn drop_volume(&mut self, usb: &UsbDevice, board: &dyn BoardInfo) {
+        if let Some(index) = self
+            .volume_by_serial(usb)
+            .or_else(|| self.volume_by_board_id(board))
+        {
+            let volume = self.volumes.remove(index);
+            log::debug!(
+                "Dropped mounted volume {}, its device was flashed",
+                volume.path.display()
+            );
+        }
+    }
+
+    fn volume_by_board_id(&self, board: &dyn BoardInfo) -> Option<usize> {
+        only(
+            self.volumes
+                .iter()
+                .enumerate()
+                .filter(|(_, volume)| board.matches_info_uf2(&volume.info))
+                .map(|(index, _)| index),
+        )
+    }
+
+    fn volume_by_serial(&self, usb: &UsbDevice) -> Option<usize> {
+        let serial = usb.serial_number.as_deref().filter(|s| !s.is_empty())?;
+        let tag = format!("_{serial}-");
+        self.volumes.iter().position(|volume| {
+            volume.source.as_ref().is_some_and(|source| {
+                self.links.iter().any(|link| {
+                    link.name.starts_with("usb-")
+                        && link.name.contains(&tag)
+                        && link.target == *source
+                })
+            })
+        })
+    }
+}
+
+/// Whether `info` belongs to a known board that isn't `board`. A generic device, without a
+/// board, is contradicted by any known board.
+fn contradicts(board: Option<&dyn BoardInfo>, info: &InfoUf2) -> bool {
+    !board.is_some_and(|board| board.matches_info_uf2(info)) && board_for_info(info).is_some()
+}
+
+/// The item of an iterator with exactly one.
+fn only<T>(mut items: impl Iterator<Item = T>) -> Option<T> {
+    match (items.next(), items.next()) {
+        (Some(item), None) => Some(item),
+        _ => None,
+    }
+}
+
+/// The FAT filesystems in a Linux mount table like `/proc/self/mounts`.
+pub fn parse_mount_table(table: &str) -> Vec<MountPoint> {
+    table
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (source, path, fs_type) = (fields.next()?, fields.next()?, fields.next()?);
+            FAT_FS_TYPES.contains(&fs_type).then(|| MountPoint {
+                path: PathBuf::from(unescape_mount_field(path)),
+                source: Some(PathBuf::from(unescape_mount_field(source))),
+            })
+        })
+        .collect()
+}
+
+/// Undo the octal escapes a mount table uses for spaces, tabs, newlines and backslashes.
+fn unescape_mount_field(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(start) = rest.find('\\') {
+        unescaped.push_str(&rest[..start]);
+        let code = rest
+            .get(start + 1..start + 4)
+            .and_then(|octal| u8::from_str_radix(octal, 8).ok());
+        match code {
+            Some(code) => {
+                unescaped.push(char::from(code));
+                rest = &rest[start + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(target_os = "linux")]
+fn mount_points() -> Vec<MountPoint> {
+    match fs::read_to_string("/proc/self/mounts") {
+        Ok(table) => parse_mount_table(&table),
+        Err(err) => {
+            log::debug!("Failed to read the mount table: {err}");
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn mount_points() -> Vec<MountPoint> {
+    let Ok(volumes) = fs::read_dir("/Volumes") else {
+        return Vec::new();
+    };
+    volumes
+        .filter_map(Result::ok)
+        .map(|entry| MountPoint {
+            path: entry.path(),
+            source: None,
+        })
+        .collect()
+}
+
+#[cfg(windows)]
+fn mount_points() -> Vec<MountPoint> {
+    // A: and B: are left out, looking at an empty floppy drive can block for seconds
+    (b'C'..=b'Z')
+        .map(|letter| MountPoint {
+            path: PathBuf::from(format!("{}:\\", char::from(letter))),
+            source: None,
+        })
+        .filter(|mount| mount.path.is_dir())
+        .collect()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn mount_points() -> Vec<MountPoint> {
+    Vec::new()
+}
+
+#[cfg(target_os = "linux")]
+fn disk_links() -> Vec<DiskLink> {
+    let Ok(links) = fs::read_dir("/dev/disk/by-id") else {
+        return Vec::new();
+    };
+    links
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            Some(DiskLink {
+                name: entry.file_name().into_string().ok()?,
+                target: fs::canonicalize(entry.path()).ok()?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn disk_links() -> Vec<DiskLink> {
+    Vec::new()
+}
+
+/// The `mounts` with an `INFO_UF2.TXT` in their root.
+pub fn uf2_volumes(mounts: Vec<MountPoint>) -> Vec<MountedVolume> {
+    mounts
+        .into_iter()
+        .filter_map(|mount| {
+            let info = read_info_uf2_file(&mount.path)?;
+            Some(MountedVolume {
+                path: mount.path,
+                source: mount.source,
+                info,
+            })
+        })
+        .collect()
+}
+
+/// The `INFO_UF2.TXT` in the root of `dir`, its name compared ignoring case as FAT does.
+fn read_info_uf2_file(dir: &Path) -> Option<InfoUf2> {
+    let entry = fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .find(|entry| entry.file_name().eq_ignore_ascii_case("INFO_UF2.TXT"))?;
+
+    let mut contents = Vec::new();
+    if let Err(err) = File::open(entry.path())
+        .and_then(|file| file.take(MAX_INFO_UF2_LEN).read_to_end(&mut contents))
+    {
+        log::debug!("Failed to read {}: {err}", entry.path().display());
+    }
+    Some(InfoUf2::parse(&String::from_utf8_lossy(&contents)))
+}
+
+/// A file whose flush waits until its data is on the device, not only in the OS's cache.
+struct SyncedFile(File);
+
+impl Write for SyncedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.sync_all()
+    }
+}
+
+/// Write the uf2 `blocks` into the root of a mounted `volume` through the OS, as the file
+/// [`BoardInfo::uf2_filename`] names, chunked like [`deploy_to_usb`] writes them.
+///
+/// With `verify`, the file is read back once written. The OS may answer from its cache, so this
+/// only shows the write went through to it.
+///
+/// [`deploy_to_usb`]: crate::commands::deploy::to_usb::deploy_to_usb
+pub fn deploy_to_volume(
+    blocks: impl ExactSizeIterator<Item = Result<[u8; UF2_BLOCK_SIZE], Elf2Uf2Error>>,
+    volume: &MountedVolume,
+    board: &dyn BoardInfo,
+    verify: bool,
+    warnings: &mut Warnings,
+    cancel: &CancellationToken,
+) -> Result<Option<Verification>> {
+    log::info!(
+        "Writing firmware to board '{}' through its volume {}",
+        board.board_name(),
+        volume.path.display()
+    );
+
+    let path = volume.path.join(board.uf2_filename());
+    let label = format!("{} {}", board.board_name(), volume.path.display());
+    let mut progress = ProgressBarReporter::labeled(label).with_cancellation(cancel.clone());
+    progress.phase(writing_phase(board));
+
+    let mut written = Vec::new();
+    let blocks = blocks.inspect(|block| {
+        if let (true, Ok(block)) = (verify, block) {
+            written.extend_from_slice(block);
+        }
+    });
+    match File::create(&path) {
+        Ok(file) => write_uf2_chunks(
+            SyncedFile(file),
+            blocks,
+            board,
+            board.write_chunk_size(),
+            progress,
+            warnings,
+            || fs::remove_file(&path),
+        )?,
+        Err(err) => {
+            warnings.push(
+                WarningCode::WriteFailed,
+                format!(
+                    "Failed to create {} on board '{}': {err}",
+                    path.display(),
+                    board.board_name()
+                ),
+            );
+            return Ok(verify.then(|| Verification::unreadable(blocks.len() * UF2_BLOCK_SIZE)));
+        }
+    }
+
+    let verification = if verify {
+        let read_back =
+            || fs::read(&path).with_context(|| format!("Failed to read {}", path.display()));
+        verify_uf2(board, &written, read_back, warnings)
+    } else {
+        None
+    };
+
+    if board.needs_eject() {
+        log::warn!(
+            "Eject {} to boot the firmware on board '{}', it can't be ejected without raw USB access",
+            volume.path.display(),
+            board.board_name()
+        );
+    }
+    Ok(verification)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::usb_device;
+    use elf2flash_core::boards::{CircuitPlaygroundBluefruit, RP2040};
+
+    const PROC_MOUNTS: &str = "\
+sysfs /sys sysfs rw,nosuid,nodev,noexec,relatime 0 0
+/dev/nvme0n1p2 / ext4 rw,relatime 0 0
+/dev/nvme0n1p1 /boot/efi vfat rw,relatime,fmask=0077,dmask=0077 0 0
+/dev/sdb1 /media/user/RPI-RP2 vfat rw,nosuid,nodev,relatime,uid=1000 0 0
+/dev/sdc /run/media/user/CPLAYBTBOOT\\040(2) msdos rw,nosuid,nodev 0 0
+tmpfs /run/user/1000 tmpfs rw,nosuid,nodev 0 0
+";
+
+    fn mount(path: &str, source: &str) -> MountPoint {
+        MountPoint {
+            path: PathBuf::from(path),
+            source: Some(PathBuf::from(source)),
+        }
+    }
+
+    fn volume(path: &str, source: &str, info: &str) -> MountedVolume {
+        MountedVolume {
+            path: PathBuf::from(path),
+            source: Some(PathBuf::from(source)),
+            info: InfoUf2::parse(info),
+        }
+    }
+
+    fn link(name: &str, target: &str) -> DiskLink {
+        DiskLink {
+            name: name.to_string(),
+            target: PathBuf::from(target),
+        }
+    }
+
+    fn usb(serial: Option<&str>) -> UsbDevice {
+        UsbDevice {
+            serial_number: serial.map(str::to_string),
+            ..usb_device(0x2e8a, 0x0003)
+        }
+    }
+
+    #[test]
+    fn fat_mounts_are_read_from_the_mount_table() {
+        assert_eq!(
+            parse_mount_table(PROC_MOUNTS),
+            [
+                mount("/boot/efi", "/dev/nvme0n1p1"),
+                mount("/media/user/RPI-RP2", "/dev/sdb1"),
+                mount("/run/media/user/CPLAYBTBOOT (2)", "/dev/sdc"),
+            ]
+        );
+        assert_eq!(unescape_mount_field(r"a\011b\134c\d"), "a\tb\\c\\d");
+    }
+
+    #[test]
+    fn volumes_are_matched_to_their_device() {
+        let rp2040 = volume("/media/user/RPI-RP2", "/dev/sdb1", "Board-ID: RPI-RP2");
+        let cpb = volume(
+            "/media/user/CPLAYBTBOOT",
+            "/dev/sdc",
+            "Board-ID: nRF52840-CircuitPlayground-revD",
+        );
+        let table = || MountTable {
+            volumes: vec![rp2040.clone(), cpb.clone()],
+            links: vec![
+                link("usb-RPI_RP2_E0C9125B0D9B-0:0", "/dev/sdb"),
+                link("usb-RPI_RP2_E0C9125B0D9B-0:0-part1", "/dev/sdb1"),
+                link("usb-Adafruit_nRF_UF2_6C1E4A2B1F5D-0:0", "/dev/sdc"),
+            ],
+        };
+
+        // The serial number names the device node of the volume
+        assert_eq!(
+            table().take_volume(&usb(Some("6C1E4A2B1F5D")), None),
+            Some(cpb.clone())
+        );
+        // Without a link, the Board-ID tells the boards apart
+        assert_eq!(
+            table().take_volume(&usb(Some("0123456789AB")), Some(&RP2040)),
+            Some(rp2040.clone())
+        );
+        assert_eq!(table().take_volume(&usb(None), None), None);
+
+        // A volume goes to one device only
+        let mut table = table();
+        assert_eq!(
+            table.take_volume(&usb(None), Some(&CircuitPlaygroundBluefruit)),
+            Some(cpb)
+        );
+        // The volume left belongs to another board
+        assert_eq!(
+            table.take_volume(&usb(None), Some(&CircuitPlaygroundBluefruit)),
+            None
+        );
+        // Nor to a generic device
+        assert_eq!(table.take_volume(&usb(None), None), None);
+        assert_eq!(table.take_volume(&usb(None), Some(&RP2040)), Some(rp2040));
+
+        // A Board-ID no board knows doesn't tell against the device
+        let unknown = volume(
+            "/media/user/UF2BOOT",
+            "/dev/sdd1",
+            "Board-ID: Mystery-Board",
+        );
+        let table = || MountTable {
+            volumes: vec![unknown.clone()],
+            links: Vec::new(),
+        };
+        assert_eq!(
+            table().take_volume(&usb(None), Some(&CircuitPlaygroundBluefruit)),
+            Some(unknown.clone())
+        );
+        assert_eq!(table().take_volume(&usb(None), None), Some(unknown));
+    }
+
+    #[test]
+    fn volumes_of_flashed_devices_are_dropped() {
+        let rp2040 = volume("/media/user/RPI-RP2", "/dev/sdb1", "Board-ID: RPI-RP2");
+        let cpb = volume(
+            "/media/user/CPLAYBTBOOT",
+            "/dev/sdc",
+            "Board-ID: nRF52840-CircuitPlayground-revD",
+        );
+        let table = || MountTable {
+            volumes: vec![rp2040.clone(), cpb.clone()],
+            links: vec![link("usb-RPI_RP2_E0C9125B0D9B-0:0-part1", "/dev/sdb1")],
+        };
+
+        // By the serial number of the flashed device
+        let mut by_serial = table();
+        by_serial.drop_volume(&usb(Some("E0C9125B0D9B")), &CircuitPlaygroundBluefruit);
+        assert_eq!(by_serial.volumes, vec![cpb.clone()]);
+
+        // By its Board-ID, the RP2040 left isn't taken by a device that couldn't be accessed
+        let mut by_board_id = table();
+        by_board_id.drop_volume(&usb(None), &CircuitPlaygroundBluefruit);
+        assert_eq!(by_board_id.volumes, vec![rp2040.clone()]);
+        assert_eq!(
+            by_board_id.take_volume(&usb(None), Some(&CircuitPlaygroundBluefruit)),
+            None
+        );
+
+        // A volume that can't be told to be the device's is kept
+        let mut unmatched = MountTable {
+            volumes: vec![rp2040.clone(), rp2040],
+            links: Vec::new(),
+        };
+        unmatched.drop_volume(&usb(None), &RP2040);
+        assert_eq!(unmatched.volumes.len(), 2);
+    }
+
+    #[test]
+    fn uf2_volumes_are_found_by_info_uf2() {
+        let root = tempfile::tempdir().unwrap();
+        let uf2 = root.path().join("RPI-RP2");
+        let other = root.path().join("USB-STICK");
+        fs::create_dir(&uf2).unwrap();
+        fs::create_dir(&other).unwrap();
+        fs::write(uf2.join("info_uf2.txt"), "Board-ID: RPI-RP2\n").unwrap();
+        fs::write(other.join("README.TXT"), "not a bootloader").unwrap();
+
+        let volumes = uf2_volumes(vec![
+            MountPoint {
+                path: other,
+                source: None,
+            },
+            MountPoint {
+                path: uf2.clone(),
+                source: None,
+            },
+        ]);
+        assert_eq!(volumes.len(), 1);
+        assert_eq!(volumes[0].path, uf2);
+        assert_eq!(volumes[0].info.board_id.as_deref(), Some("RPI-RP2"));
+    }
+
+    #[test]
+    fn uf2_is_copied_onto_the_volume() {
+        let dir = tempfile::tempdir().unwrap();
+        let volume = MountedVolume {
+            path: dir.path().to_path_buf(),
+            source: None,
+            info: InfoUf2::default(),
+        };
+
+        let blocks = (0..40).map(|_| Ok([0x55; UF2_BLOCK_SIZE]));
+        let mut warnings = Warnings::new();
+        deploy_to_volume(
+            blocks,
+            &volume,
+            &RP2040,
+            false,
+            &mut warnings,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+
+        assert!(warnings.is_empty(), "{warnings:?}");
+        let written = fs::read(dir.path().join(RP2040.uf2_filename())).unwrap();
+        assert_eq!(written, [0x55; 40 * UF2_BLOCK_SIZE]);
+    }
+
+    #[test]
+    fn copied_uf2_is_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let volume = MountedVolume {
+            path: dir.path().to_path_buf(),
+            source: None,
+            info: InfoUf2::default(),
+        };
+
+        let blocks = (0..4).map(|_| Ok([0x55; UF2_BLOCK_SIZE]));
+        let verification = deploy_to_volume(
+            blocks,
+            &volume,
+            &RP2040,
+            true,
+            &mut Warnings::new(),
+            &CancellationToken::new(),
+        )
+        .unwrap();
+        assert!(verification.unwrap().passed());
+    }
+}
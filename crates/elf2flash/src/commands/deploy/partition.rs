@@ -0,0 +1,230 @@
+//! Picking the partition of a device to flash. Bootloaders expose a single uf2 volume, but an
+//! RP2350 with a partition table can show more FAT regions, and flashing the wrong one takes a
+//! minute and can confuse the bootloader.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Result, bail};
+use elf2flash_core::{boards::BoardInfo, info_uf2::InfoUf2};
+use usbh_fatfs::FatPartition;
+
+/// Names one of the uf2 partitions of a device, for `--partition`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionSelector {
+    /// The position among the partitions holding an `INFO_UF2.TXT`, from 0
+    Index(usize),
+    /// The volume label, ignoring case
+    Label(String),
+}
+
+impl FromStr for PartitionSelector {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("expected a partition index or volume label");
+        }
+        Ok(match s.parse() {
+            Ok(index) => PartitionSelector::Index(index),
+            Err(_) => PartitionSelector::Label(s.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for PartitionSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionSelector::Index(index) => write!(f, "{index}"),
+            PartitionSelector::Label(label) => write!(f, "'{label}'"),
+        }
+    }
+}
+
+/// Whether the volume label of `partition` names `board`, either its name or the `Board-ID` of
+/// its bootloader, ignoring case.
+fn label_matches(partition: &FatPartition, info: &InfoUf2, board: &dyn BoardInfo) -> bool {
+    let label = partition.volume_label.trim();
+    !label.is_empty()
+        && (label.eq_ignore_ascii_case(&board.board_name())
+            || info
+                .board_id
+                .as_deref()
+                .is_some_and(|id| label.eq_ignore_ascii_case(id)))
+}
+
+/// The uf2 partitions of a device, the best one to flash for `board` first. A bootloader saying
+/// it is the board ranks first, then a volume labeled for it, partitions ranked the same keep
+/// their order.
+pub fn rank_partitions(
+    partitions: Vec<(FatPartition, InfoUf2)>,
+    board: &dyn BoardInfo,
+) -> Vec<FatPartition> {
+    let mut ranked: Vec<_> = partitions
+        .into_iter()
+        .map(|(partition, info)| {
+            let rank = (
+                board.matches_info_uf2(&info),
+                label_matches(&partition, &info, board),
+            );
+            (rank, partition)
+        })
+        .collect();
+    ranked.sort_by(|(a, _), (b, _)| b.cmp(a));
+    ranked.into_iter().map(|(_, partition)| partition).collect()
+}
+
+/// The uf2 partitions of a device to flash for `board`: the one `selector` names, every one with
+/// `all`, or else the best ranked one.
+pub fn choose_partitions(
+    partitions: Vec<(FatPartition, InfoUf2)>,
+    board: &dyn BoardInfo,
+    selector: Option<&PartitionSelector>,
+    all: bool,
+) -> Result<Vec<FatPartition>> {
+    if all {
+        return Ok(partitions
+            .into_iter()
+            .map(|(partition, _)| partition)
+            .collect());
+    }
+    let Some(selector) = selector else {
+        return Ok(rank_partitions(partitions, board)
+            .into_iter()
+            .take(1)
+            .collect());
+    };
+
+    let labels: Vec<String> = partitions
+        .iter()
+        .map(|(partition, _)| format!("'{}'", partition.volume_label.trim()))
+        .collect();
+    let chosen =
+        partitions
+            .into_iter()
+            .enumerate()
+            .find(|(index, (partition, _))| match selector {
+                PartitionSelector::Index(wanted) => index == wanted,
+                PartitionSelector::Label(label) => {
+                    partition.volume_label.trim().eq_ignore_ascii_case(label)
+                }
+            });
+    match chosen {
+        Some((_, (partition, _))) => Ok(vec![partition]),
+        None => bail!(
+            "no uf2 partition {selector}, its uf2 partitions are {}",
+            labels.join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use elf2flash_core::boards::rp2350::RP2350;
+    use usbh_fatfs::{
+        bootsector::{Attributes, Partition},
+        fatfs::FatType,
+    };
+
+    use super::*;
+
+    fn partition(id: usize, label: &str) -> FatPartition {
+        let first_byte = 512 + id as u64 * 0x100000;
+        FatPartition {
+            inner: Partition {
+                id,
+                first_byte,
+                len: 0x100000,
+                attributes: Attributes::MBR {
+                    bootable: false,
+                    type_code: 14,
+                },
+            },
+            volume_id: id as u32,
+            volume_label: label.to_string(),
+            fat_type: FatType::Fat16,
+            cluster_size: 4096,
+            first_byte,
+            length: 0x100000,
+        }
+    }
+
+    fn info(board_id: &str) -> InfoUf2 {
+        InfoUf2::parse(&format!("UF2 Bootloader v1.0\nBoard-ID: {board_id}\n"))
+    }
+
+    fn labels(partitions: &[FatPartition]) -> Vec<&str> {
+        partitions
+            .iter()
+            .map(|partition| partition.volume_label.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn the_bootloader_of_the_board_ranks_first() {
+        let partitions = vec![
+            (partition(0, "DATA"), InfoUf2::default()),
+            (partition(1, "OTHER"), info("Some-Other-Board")),
+            (partition(2, "RP2350"), InfoUf2::default()),
+            (partition(3, "BOOT"), info("RP2350")),
+        ];
+        assert_eq!(
+            labels(&rank_partitions(partitions, &RP2350)),
+            ["BOOT", "RP2350", "DATA", "OTHER"]
+        );
+
+        // Nothing to tell them apart, the first one stays first
+        let partitions = vec![
+            (partition(0, "DATA"), InfoUf2::default()),
+            (partition(1, "MORE"), InfoUf2::default()),
+        ];
+        assert_eq!(
+            labels(&rank_partitions(partitions, &RP2350)),
+            ["DATA", "MORE"]
+        );
+    }
+
+    #[test]
+    fn one_partition_is_chosen_unless_asked_for_all() {
+        let partitions = || {
+            vec![
+                (partition(0, "DATA"), InfoUf2::default()),
+                (partition(1, "RP2350"), info("RP2350")),
+            ]
+        };
+
+        let chosen = |selector: Option<PartitionSelector>, all| {
+            choose_partitions(partitions(), &RP2350, selector.as_ref(), all)
+                .map(|partitions| labels(&partitions).join(" "))
+                .map_err(|err| err.to_string())
+        };
+        assert_eq!(chosen(None, false), Ok("RP2350".to_string()));
+        assert_eq!(chosen(None, true), Ok("DATA RP2350".to_string()));
+        assert_eq!(
+            chosen(Some("0".parse().unwrap()), false),
+            Ok("DATA".to_string())
+        );
+        assert_eq!(
+            chosen(Some("data".parse().unwrap()), false),
+            Ok("DATA".to_string())
+        );
+        assert_eq!(
+            chosen(Some("2".parse().unwrap()), false),
+            Err("no uf2 partition 2, its uf2 partitions are 'DATA', 'RP2350'".to_string())
+        );
+        assert_eq!(
+            chosen(Some("x".parse().unwrap()), false).unwrap_err(),
+            "no uf2 partition 'x', its uf2 partitions are 'DATA', 'RP2350'"
+        );
+    }
+
+    #[test]
+    fn parses_partition_selectors() {
+        assert_eq!("1".parse(), Ok(PartitionSelector::Index(1)));
+        assert_eq!(
+            "RP2350".parse(),
+            Ok(PartitionSelector::Label("RP2350".to_string()))
+        );
+        assert!("".parse::<PartitionSelector>().is_err());
+    }
+}
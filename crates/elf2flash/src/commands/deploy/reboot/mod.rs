@@ -0,0 +1,212 @@
+//! `deploy --reboot`: putting running RP2040 and RP2350 boards into their BOOTSEL bootloader, so
+//! nobody has to hold the button while resetting them.
+//!
+//! Firmware using pico-sdk's `stdio_usb` reboots into BOOTSEL on a 1200 baud touch of its serial
+//! port, see [`touch`], and on a request to its reset interface, see [`picoboot`]. An RP2350 in
+//! BOOTSEL with its mass storage turned off is rebooted over PICOBOOT with it turned back on.
+
+use std::collections::HashSet;
+
+use elf2flash_core::boards::{BoardInfo, BoardIter, RP2040, RP2350, RP2350ArmNs, RP2350RiscV};
+
+use crate::commands::{convert::BoardSpec, deploy::select::DeviceSelector};
+
+pub mod picoboot;
+pub mod touch;
+
+/// The USB vendor id of Raspberry Pi
+pub const RASPBERRY_PI_VID: u16 = 0x2e8a;
+
+/// A chip that can be rebooted into BOOTSEL.
+struct Chip {
+    /// The product id of pico-sdk firmware with `stdio_usb`
+    application_pid: u16,
+    /// The product id of the bootrom
+    bootsel_pid: u16,
+    families: fn() -> Vec<u32>,
+}
+
+const CHIPS: [Chip; 2] = [
+    Chip {
+        application_pid: 0x000a,
+        bootsel_pid: 0x0003,
+        families: || vec![RP2040.family_id()],
+    },
+    Chip {
+        application_pid: 0x0009,
+        bootsel_pid: 0x000f,
+        families: || {
+            vec![
+                RP2350.family_id(),
+                RP2350RiscV.family_id(),
+                RP2350ArmNs.family_id(),
+            ]
+        },
+    },
+];
+
+/// The chip of a Raspberry Pi device, running pico-sdk firmware or in BOOTSEL.
+fn chip(vendor_id: u16, product_id: u16) -> Option<&'static Chip> {
+    if vendor_id != RASPBERRY_PI_VID {
+        return None;
+    }
+    CHIPS
+        .iter()
+        .find(|chip| chip.application_pid == product_id || chip.bootsel_pid == product_id)
+}
+
+/// Whether a device with these USB ids is an RP2040 or RP2350 running pico-sdk firmware.
+pub fn is_application_mode(vendor_id: u16, product_id: u16) -> bool {
+    chip(vendor_id, product_id).is_some_and(|chip| chip.application_pid == product_id)
+}
+
+/// The running boards `--reboot` reboots: those the `--device` selectors, `--board`, `--family`
+/// and USB ids of the deploy would flash once they are in BOOTSEL.
+pub struct RebootFilter<'a> {
+    selectors: &'a [DeviceSelector],
+    family_id: Option<u32>,
+    vendor_id: Option<u16>,
+    product_id: Option<u16>,
+}
+
+impl<'a> RebootFilter<'a> {
+    pub fn new(selectors: &'a [DeviceSelector], spec: &BoardSpec) -> Self {
+        let family_id = spec.family.or_else(|| {
+            spec.board
+                .as_deref()
+                .and_then(BoardIter::find_by_name)
+                .map(|board| board.family_id())
+        });
+        Self {
+            selectors,
+            family_id,
+            vendor_id: spec.vendor_id,
+            product_id: spec.product_id,
+        }
+    }
+
+    /// Whether the device with these USB ids, serial number and port path is rebooted. Its ids
+    /// are those of the firmware, or of an RP2350 bootrom, the selectors and USB ids of the deploy
+    /// are matched against the bootrom's. A `label:` or `index:` selector is only known once the
+    /// device is in BOOTSEL, so it doesn't pick any running board.
+    pub fn matches(
+        &self,
+        vendor_id: u16,
+        product_id: u16,
+        serial: Option<&str>,
+        port: Option<&str>,
+    ) -> bool {
+        let Some(chip) = chip(vendor_id, product_id) else {
+            return false;
+        };
+        if self
+            .family_id
+            .is_some_and(|family| !(chip.families)().contains(&family))
+            || self.vendor_id.is_some_and(|vid| vid != RASPBERRY_PI_VID)
+            || self.product_id.is_some_and(|pid| pid != chip.bootsel_pid)
+        {
+            return false;
+        }
+
+        self.selectors.is_empty()
+            || self.selectors.iter().any(|selector| match selector {
+                DeviceSelector::Serial(wanted) => serial == Some(wanted.as_str()),
+                DeviceSelector::Port(wanted) => port == Some(wanted.as_str()),
+                DeviceSelector::VidPid(vid, pid) => {
+                    *vid == RASPBERRY_PI_VID && *pid == chip.bootsel_pid
+                }
+                DeviceSelector::Label(_) | DeviceSelector::Index(_) => false,
+            })
+    }
+}
+
+/// Ask every running board `filter` matches to reboot into BOOTSEL, and return how many were
+/// asked. Failures are logged, a board that can't be rebooted is left running.
+pub fn reboot_into_bootsel(filter: &RebootFilter) -> usize {
+    let touched = touch::touch_serial_ports(filter);
+    // A touched board may still be listed while it reboots, it isn't asked a second time
+    let serials: HashSet<String> = touched.iter().flatten().cloned().collect();
+    let requested = match picoboot::request_bootsel(filter, &serials) {
+        Ok(requested) => requested,
+        Err(err) => {
+            log::debug!("Failed to look for boards with a reset interface: {err:#}");
+            0
+        }
+    };
+
+    touched.len() + requested
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_pico_sdk_firmware_is_rebooted() {
+        assert!(is_application_mode(0x2e8a, 0x000a));
+        assert!(is_application_mode(0x2e8a, 0x0009));
+        // The BOOTSEL bootloaders of the RP2040 and RP2350
+        assert!(!is_application_mode(0x2e8a, 0x0003));
+        assert!(!is_application_mode(0x2e8a, 0x000f));
+        assert!(!is_application_mode(0x239a, 0x000a));
+    }
+
+    fn spec(board: Option<&str>) -> BoardSpec {
+        BoardSpec {
+            board: board.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn without_filters_every_running_board_is_rebooted() {
+        let spec = spec(None);
+        let filter = RebootFilter::new(&[], &spec);
+        assert!(filter.matches(0x2e8a, 0x000a, None, None));
+        assert!(filter.matches(0x2e8a, 0x0009, Some("E661"), Some("1-2")));
+        assert!(!filter.matches(0x239a, 0x000a, None, None));
+    }
+
+    #[test]
+    fn only_boards_of_the_deployed_chip_are_rebooted() {
+        let rp2040 = spec(Some("rp2040"));
+        let filter = RebootFilter::new(&[], &rp2040);
+        assert!(filter.matches(0x2e8a, 0x000a, None, None));
+        assert!(!filter.matches(0x2e8a, 0x0009, None, None));
+
+        let rp2350 = BoardSpec {
+            family: Some(RP2350RiscV.family_id()),
+            ..Default::default()
+        };
+        let filter = RebootFilter::new(&[], &rp2350);
+        assert!(filter.matches(0x2e8a, 0x0009, None, None));
+        assert!(filter.matches(0x2e8a, 0x000f, None, None));
+        assert!(!filter.matches(0x2e8a, 0x000a, None, None));
+
+        let feather = spec(Some("feather_nrf52840_express"));
+        assert!(!RebootFilter::new(&[], &feather).matches(0x2e8a, 0x000a, None, None));
+    }
+
+    #[test]
+    fn only_selected_boards_are_rebooted() {
+        let spec = spec(None);
+        let selectors = [
+            DeviceSelector::Serial("E661".to_string()),
+            DeviceSelector::Port("3-1.4".to_string()),
+        ];
+        let filter = RebootFilter::new(&selectors, &spec);
+        assert!(filter.matches(0x2e8a, 0x000a, Some("E661"), None));
+        assert!(filter.matches(0x2e8a, 0x0009, None, Some("3-1.4")));
+        assert!(!filter.matches(0x2e8a, 0x000a, Some("E662"), Some("3-1.5")));
+        assert!(!filter.matches(0x2e8a, 0x000a, None, None));
+
+        // Matched against the ids of the bootrom the board reboots into
+        let selectors = [DeviceSelector::VidPid(0x2e8a, 0x0003)];
+        let filter = RebootFilter::new(&selectors, &spec);
+        assert!(filter.matches(0x2e8a, 0x000a, None, None));
+        assert!(!filter.matches(0x2e8a, 0x0009, None, None));
+
+        let selectors = [DeviceSelector::Index(0)];
+        assert!(!RebootFilter::new(&selectors, &spec).matches(0x2e8a, 0x000a, None, None));
+    }
+}
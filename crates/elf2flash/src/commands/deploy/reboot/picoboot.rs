@@ -0,0 +1,161 @@
+//! Rebooting into BOOTSEL over the vendor interfaces of Raspberry Pi devices: the reset interface
+//! of firmware with `stdio_usb`, and the PICOBOOT interface of the RP2350 bootrom.
+//!
+//! The RP2040 bootrom's PICOBOOT can only reboot into the flashed program, so an RP2040 in
+//! BOOTSEL is left alone.
+
+use std::{collections::HashSet, time::Duration};
+
+use anyhow::{Context, Result};
+use usbh_fatfs::{
+    rusb::{self, Device, Direction, Recipient, RequestType, UsbContext},
+    usbh_scsi::select::{port_path, serial_number},
+};
+
+use crate::{
+    commands::deploy::{
+        reboot::{RASPBERRY_PI_VID, RebootFilter},
+        to_usb::{usb_session, usb_timeout},
+    },
+    picoboot::{Picoboot, PicobootCommand, RusbTransport},
+};
+
+const VENDOR_CLASS: u8 = 0xff;
+const MASS_STORAGE_CLASS: u8 = 0x08;
+/// Interface protocol of pico-sdk's reset interface, PICOBOOT has 0
+const RESET_INTERFACE_PROTOCOL: u8 = 0x01;
+/// Request to the reset interface to reboot into BOOTSEL
+const RESET_REQUEST_BOOTSEL: u8 = 0x01;
+
+/// Product id of the RP2350 bootrom
+const RP2350_BOOTSEL_PID: u16 = 0x000f;
+
+/// How long the bootrom waits before rebooting, time for it to acknowledge the command
+const REBOOT_DELAY_MS: u32 = 500;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The request type, request, value and index of the control transfer asking the reset interface
+/// `interface` to reboot into BOOTSEL, with every interface of the bootloader turned on.
+pub fn bootsel_reset_request(interface: u8) -> (u8, u8, u16, u16) {
+    let request_type = rusb::request_type(Direction::Out, RequestType::Class, Recipient::Interface);
+    (request_type, RESET_REQUEST_BOOTSEL, 0, u16::from(interface))
+}
+
+/// How a device can be asked to reboot into BOOTSEL.
+enum Interface {
+    /// The reset interface with this number
+    Reset(u8),
+    /// The PICOBOOT interface of the bootrom
+    Picoboot,
+}
+
+/// Ask every Raspberry Pi device `filter` matches with a reset interface, or an RP2350 bootrom
+/// without mass storage, to reboot into BOOTSEL, except those with a serial number in `skip`.
+/// Returns how many were asked.
+pub fn request_bootsel(filter: &RebootFilter, skip: &HashSet<String>) -> Result<usize> {
+    let mut requested = 0;
+    for device in usb_session()?.context().devices()?.iter() {
+        let Ok(descriptor) = device.device_descriptor() else {
+            continue;
+        };
+        if descriptor.vendor_id() != RASPBERRY_PI_VID {
+            continue;
+        }
+        let serial = serial_number(&device);
+        if !filter.matches(
+            descriptor.vendor_id(),
+            descriptor.product_id(),
+            serial.as_deref(),
+            port_path(&device).as_deref(),
+        ) {
+            continue;
+        }
+        let interface = match find_interface(&device, descriptor.product_id()) {
+            Ok(Some(interface)) => interface,
+            Ok(None) => continue,
+            Err(err) => {
+                log::debug!("Failed to read the interfaces of a Raspberry Pi device: {err:#}");
+                continue;
+            }
+        };
+        if serial.is_some_and(|serial| skip.contains(&serial)) {
+            continue;
+        }
+
+        let description = format!(
+            "device {:04x}:{:04x} on bus {} address {}",
+            descriptor.vendor_id(),
+            descriptor.product_id(),
+            device.bus_number(),
+            device.address()
+        );
+        match request(&device, &interface) {
+            Ok(()) => {
+                log::info!("Rebooting {description} into BOOTSEL");
+                requested += 1;
+            }
+            Err(err) => log::warn!("Failed to reboot {description} into BOOTSEL: {err:#}"),
+        }
+    }
+    Ok(requested)
+}
+
+/// The interface `device` can be asked to reboot into BOOTSEL through, if any.
+fn find_interface<T: UsbContext>(device: &Device<T>, product_id: u16) -> Result<Option<Interface>> {
+    let config = device.active_config_descriptor()?;
+    let descriptors: Vec<_> = config
+        .interfaces()
+        .flat_map(|interface| interface.descriptors())
+        .collect();
+
+    if let Some(reset) = descriptors
+        .iter()
+        .find(|d| d.class_code() == VENDOR_CLASS && d.protocol_code() == RESET_INTERFACE_PROTOCOL)
+    {
+        return Ok(Some(Interface::Reset(reset.interface_number())));
+    }
+
+    // A bootrom that has mass storage is already where the deploy wants it
+    if product_id != RP2350_BOOTSEL_PID
+        || descriptors
+            .iter()
+            .any(|d| d.class_code() == MASS_STORAGE_CLASS)
+    {
+        return Ok(None);
+    }
+    let has_picoboot = descriptors
+        .iter()
+        .any(|d| d.class_code() == VENDOR_CLASS && d.protocol_code() == 0);
+    Ok(has_picoboot.then_some(Interface::Picoboot))
+}
+
+fn request<T: UsbContext>(device: &Device<T>, interface: &Interface) -> Result<()> {
+    match *interface {
+        Interface::Reset(number) => {
+            let handle = device.open().context("Failed to open the device")?;
+            let (request_type, request, value, index) = bootsel_reset_request(number);
+            let timeout = usb_timeout().unwrap_or(TIMEOUT);
+            match handle.write_control(request_type, request, value, index, &[], timeout) {
+                // The device may reset before it answers
+                Ok(_) | Err(rusb::Error::NoDevice | rusb::Error::Io | rusb::Error::Pipe) => Ok(()),
+                Err(err) => Err(err).context("The reset request failed"),
+            }
+        }
+        Interface::Picoboot => {
+            let mut picoboot = Picoboot::new(RusbTransport::open(device)?)?;
+            picoboot.command(&PicobootCommand::reboot2_bootsel(REBOOT_DELAY_MS), &[])?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reset_request_is_a_class_request_to_the_interface() {
+        assert_eq!(bootsel_reset_request(2), (0x21, 0x01, 0, 2));
+    }
+}
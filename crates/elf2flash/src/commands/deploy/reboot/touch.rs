@@ -0,0 +1,65 @@
+//! The 1200 baud touch: opening the USB serial port of pico-sdk firmware at 1200 baud and dropping
+//! DTR, which `stdio_usb` takes as a request to reboot into BOOTSEL.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serialport::{SerialPortInfo, SerialPortType};
+
+use crate::commands::deploy::reboot::{RebootFilter, is_application_mode};
+
+/// The baud rate `stdio_usb` watches for
+pub const TOUCH_BAUD_RATE: u32 = 1200;
+
+/// Touch the serial port of every running RP2040 and RP2350 `filter` matches, and return the USB
+/// serial number of each touched port, `None` where the OS doesn't tell it.
+pub fn touch_serial_ports(filter: &RebootFilter) -> Vec<Option<String>> {
+    let ports = match serialport::available_ports() {
+        Ok(ports) => ports,
+        Err(err) => {
+            log::debug!("Failed to list the serial ports: {err}");
+            return Vec::new();
+        }
+    };
+
+    let mut touched = Vec::new();
+    for port in ports {
+        let Some(serial_number) = application_port(&port, filter) else {
+            continue;
+        };
+        match touch(&port.port_name) {
+            Ok(()) => {
+                log::info!("Rebooting the board on {} into BOOTSEL", port.port_name);
+                touched.push(serial_number);
+            }
+            Err(err) => log::warn!("{err:#}"),
+        }
+    }
+    touched
+}
+
+/// The USB serial number of `port` when it belongs to pico-sdk firmware `filter` matches. The OS
+/// doesn't tell the USB port path of a serial port, a board picked by `port:` is rebooted through
+/// its reset interface instead.
+fn application_port(port: &SerialPortInfo, filter: &RebootFilter) -> Option<Option<String>> {
+    match &port.port_type {
+        SerialPortType::UsbPort(usb)
+            if is_application_mode(usb.vid, usb.pid)
+                && filter.matches(usb.vid, usb.pid, usb.serial_number.as_deref(), None) =>
+        {
+            Some(usb.serial_number.clone())
+        }
+        _ => None,
+    }
+}
+
+/// Open `port_name` at [`TOUCH_BAUD_RATE`] and drop DTR, the board reboots once it is closed.
+pub fn touch(port_name: &str) -> Result<()> {
+    let mut port = serialport::new(port_name, TOUCH_BAUD_RATE)
+        .timeout(Duration::from_millis(100))
+        .open()
+        .with_context(|| format!("Failed to open {port_name} for the 1200 baud touch"))?;
+    port.write_data_terminal_ready(false)
+        .with_context(|| format!("Failed to drop DTR on {port_name}"))?;
+    Ok(())
+}
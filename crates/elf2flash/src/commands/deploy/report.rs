@@ -0,0 +1,331 @@
+use std::{borrow::Cow, fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+
+use elf2flash_core::{
+    boards::{BoardInfo, UsbDevice},
+    warnings::Warnings,
+};
+use serde::Serialize;
+use usbh_fatfs::{
+    FatPartition,
+    usbh_scsi::select::{SelectorTarget, port_path},
+};
+
+use crate::{
+    commands::deploy::{
+        select::DeviceSelector,
+        to_usb::{PluggedInDevice, SessionUsb},
+    },
+    diagnostics::{self, Environment, Redaction},
+};
+
+/// What is known about a plugged in uf2 device, used to select and describe devices.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DeviceReport {
+    /// Position of the device in the list of plugged in devices
+    pub index: usize,
+    /// Name of the recognized board, `None` for generic uf2 devices
+    pub board_name: Option<String>,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub bus_number: u8,
+    pub address: u8,
+    /// Physical port path, formatted like `3-1.4` (bus, then the hub ports)
+    pub port: Option<String>,
+    /// USB serial number string, if the device reports one
+    pub serial: Option<String>,
+    /// Volume labels of the FAT partitions on the device
+    pub labels: Vec<String>,
+}
+
+impl DeviceReport {
+    /// The report for a plugged in device, from its descriptors and its place on the bus. The
+    /// device isn't opened, its labels are left empty until [`DeviceReport::read_labels`].
+    pub fn new(
+        index: usize,
+        usb_device: &UsbDevice,
+        board: Option<&dyn BoardInfo>,
+        storage_usb: &SessionUsb,
+    ) -> Self {
+        Self {
+            index,
+            board_name: board.map(|board| board.board_name().into_owned()),
+            vendor_id: usb_device.vendor_id,
+            product_id: usb_device.product_id,
+            bus_number: usb_device.bus_number,
+            address: usb_device.address,
+            port: port_path(&storage_usb.usb_device),
+            serial: usb_device.serial_number.clone(),
+            labels: Vec::new(),
+        }
+    }
+
+    /// Read the volume labels of the FAT partitions on the device, which opens it. They are left
+    /// empty if the partitions can't be listed.
+    pub fn read_labels(&mut self, storage_usb: &mut SessionUsb) {
+        self.labels = match FatPartition::list_partitions(storage_usb) {
+            Ok(partitions) => partitions
+                .into_iter()
+                .map(|partition| partition.volume_label.trim().to_string())
+                .collect(),
+            Err(err) => {
+                log::debug!(
+                    "Failed to list partitions of device {}: {:#}",
+                    self.index,
+                    anyhow::Error::from(err)
+                );
+                Vec::new()
+            }
+        };
+    }
+
+    /// One line description, e.g. `rp2040 2e8a:0003 port 3-1.4 serial E661... label RPI-RP2`
+    pub fn summary(&self) -> String {
+        format!(
+            "{} {}",
+            self.board_name.as_deref().unwrap_or("generic uf2 device"),
+            self.details()
+        )
+    }
+
+    /// Same as [`DeviceReport::summary`], without the board name
+    pub fn details(&self) -> String {
+        let mut details = format!("{:04x}:{:04x}", self.vendor_id, self.product_id);
+        if let Some(port) = &self.port {
+            details.push_str(&format!(" port {port}"));
+        }
+        if let Some(serial) = &self.serial {
+            details.push_str(&format!(" serial {serial}"));
+        }
+        for label in &self.labels {
+            details.push_str(&format!(" label {label}"));
+        }
+        details
+    }
+}
+
+/// The reports of the plugged in `devices`, see [`DeviceReport::new`]. The devices are only opened
+/// for their labels when one of `selectors` selects by label.
+pub fn device_reports(
+    devices: &mut [PluggedInDevice],
+    selectors: &[DeviceSelector],
+) -> Vec<DeviceReport> {
+    let by_label = selectors
+        .iter()
+        .any(|selector| matches!(selector, DeviceSelector::Label(_)));
+
+    devices
+        .iter_mut()
+        .enumerate()
+        .map(|(index, (usb, board, storage_usb))| {
+            let mut report = DeviceReport::new(index, usb, board.as_deref(), storage_usb);
+            if by_label {
+                report.read_labels(storage_usb);
+            }
+            report
+        })
+        .collect()
+}
+
+impl SelectorTarget for DeviceReport {
+    fn index(&self) -> usize {
+        self.index
+    }
+
+    fn vendor_id(&self) -> u16 {
+        self.vendor_id
+    }
+
+    fn product_id(&self) -> u16 {
+        self.product_id
+    }
+
+    fn port(&self) -> Option<&str> {
+        self.port.as_deref()
+    }
+
+    fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+
+    fn labels(&self) -> &[String] {
+        &self.labels
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct WarningReport<'a> {
+    code: &'static str,
+    message: Cow<'a, str>,
+}
+
+/// Output of `deploy --json` and `--bug-report`, warning codes use the same strings as
+/// `--deny-warning`.
+#[derive(Debug, Serialize)]
+pub struct DeployReport<'a> {
+    environment: Environment,
+    devices: Cow<'a, [DeviceReport]>,
+    warnings: Vec<WarningReport<'a>>,
+    /// What the deploy failed with, only in bug reports
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl<'a> DeployReport<'a> {
+    pub fn new(devices: &'a [DeviceReport], warnings: &'a Warnings) -> Self {
+        let warnings = warnings
+            .iter()
+            .map(|warning| WarningReport {
+                code: warning.code.as_str(),
+                message: Cow::Borrowed(&warning.message),
+            })
+            .collect();
+
+        Self {
+            environment: diagnostics::environment(),
+            devices: Cow::Borrowed(devices),
+            warnings,
+            error: None,
+        }
+    }
+
+    /// Remove what `redaction` leaves out from the serial numbers and warning messages.
+    pub fn redact(&mut self, redaction: &Redaction) {
+        for device in self.devices.to_mut() {
+            device.serial = device
+                .serial
+                .as_deref()
+                .map(|serial| redaction.serial(serial));
+        }
+        for warning in &mut self.warnings {
+            warning.message = Cow::Owned(redaction.text(&warning.message));
+        }
+        if let Some(error) = &mut self.error {
+            *error = redaction.text(error);
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// Where `--bug-report` saves the report of a deploy, on errors as well.
+pub struct BugReport {
+    pub path: PathBuf,
+    pub keep_serials: bool,
+    /// Serial numbers of the `--device` selectors, which can be in warnings and errors even when
+    /// no device has them
+    pub selector_serials: Vec<String>,
+}
+
+impl BugReport {
+    /// Save the report of the `devices` found and the `warnings` raised, with the `error` the
+    /// deploy failed with, redacted.
+    pub fn save(
+        &self,
+        devices: &[DeviceReport],
+        warnings: &Warnings,
+        error: Option<String>,
+    ) -> Result<()> {
+        let serials = devices
+            .iter()
+            .filter_map(|device| device.serial.clone())
+            .chain(self.selector_serials.iter().cloned())
+            .collect();
+        let mut report = DeployReport::new(devices, warnings);
+        report.error = error;
+        report.redact(&Redaction::new(self.keep_serials, serials));
+        fs::write(&self.path, report.to_json()?)
+            .with_context(|| format!("Failed to save the bug report to {}", self.path.display()))?;
+        log::info!("Saved bug report to {:?}", self.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elf2flash_core::warnings::WarningCode;
+
+    #[test]
+    fn json_uses_stable_warning_codes() {
+        let devices = [DeviceReport {
+            index: 0,
+            board_name: Some("rp2040".to_string()),
+            vendor_id: 0x2e8a,
+            product_id: 0x0003,
+            serial: Some("E661".to_string()),
+            ..Default::default()
+        }];
+        let mut warnings = Warnings::new();
+        warnings.push(WarningCode::FillerInflation, "Filled 15 blocks");
+
+        let json: serde_json::Value =
+            serde_json::from_str(&DeployReport::new(&devices, &warnings).to_json().unwrap())
+                .unwrap();
+
+        assert_eq!(json["devices"][0]["board_name"], "rp2040");
+        assert_eq!(json["devices"][0]["vendor_id"], 0x2e8a);
+        assert_eq!(json["devices"][0]["port"], serde_json::Value::Null);
+        assert_eq!(json["warnings"][0]["code"], "filler-inflation");
+        assert_eq!(json["warnings"][0]["message"], "Filled 15 blocks");
+        assert_eq!(json["environment"]["version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn bug_report_is_redacted() {
+        let devices = [DeviceReport {
+            serial: Some("E661".to_string()),
+            ..Default::default()
+        }];
+        let mut warnings = Warnings::new();
+        warnings.push(
+            WarningCode::SelectorUnmatched,
+            "No device matched serial:E661",
+        );
+
+        let mut report = DeployReport::new(&devices, &warnings);
+        report.redact(&Redaction::new(false, vec!["E661".to_string()]));
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+
+        assert_eq!(json["devices"][0]["serial"], diagnostics::REDACTED);
+        assert_eq!(
+            json["warnings"][0]["message"],
+            "No device matched serial:<redacted>"
+        );
+        // The report borrowed from is left alone
+        assert_eq!(devices[0].serial.as_deref(), Some("E661"));
+    }
+
+    #[test]
+    fn bug_report_redacts_selector_serials() {
+        let dir = tempfile::tempdir().unwrap();
+        let bug_report = BugReport {
+            path: dir.path().join("report.json"),
+            keep_serials: false,
+            selector_serials: vec!["E661".to_string()],
+        };
+        let mut warnings = Warnings::new();
+        warnings.push(
+            WarningCode::SelectorUnmatched,
+            "No device matched serial:E661",
+        );
+        bug_report
+            .save(
+                &[],
+                &warnings,
+                Some("No device matched serial:E661".to_string()),
+            )
+            .unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&bug_report.path).unwrap()).unwrap();
+        assert_eq!(
+            json["warnings"][0]["message"],
+            "No device matched serial:<redacted>"
+        );
+        assert_eq!(json["error"], "No device matched serial:<redacted>");
+    }
+}
@@ -0,0 +1,70 @@
+//! Keeping a copy of the flashed uf2 file, for `deploy --save-uf2`.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use elf2flash_core::{Uf2Options, boards::BoardInfo};
+
+use crate::commands::deploy::input::DeployInput;
+
+/// Fail when `path` exists and `overwrite` wasn't given, before anything is flashed.
+pub fn check_save_path(path: &Path, overwrite: bool) -> Result<()> {
+    if !overwrite && path.exists() {
+        bail!(
+            "{} already exists, pass --overwrite to replace it",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Where the uf2 file flashed is saved. Only the first one is, the devices of one deploy are
+/// usually all the same board.
+pub struct SavedUf2 {
+    path: PathBuf,
+    /// The board and contents of the uf2 file saved so far
+    saved: Option<(String, Vec<u8>)>,
+}
+
+impl SavedUf2 {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, saved: None }
+    }
+
+    /// Save the uf2 file `input` converts to for `board`, creating the directories it is in.
+    /// Once a file was saved, another board only gets a warning when its uf2 file differs.
+    pub fn save(
+        &mut self,
+        input: &DeployInput,
+        board: &dyn BoardInfo,
+        options: &Uf2Options,
+    ) -> Result<()> {
+        let (blocks, _) = input.blocks(board, options)?;
+        let uf2 = blocks.collect::<Result<Vec<_>, _>>()?.concat();
+
+        if let Some((saved_board, saved)) = &self.saved {
+            if *saved != uf2 {
+                log::warn!(
+                    "{} holds the uf2 file flashed to board '{saved_board}', not the different \
+                     one flashed to board '{}'",
+                    self.path.display(),
+                    board.board_name()
+                );
+            }
+            return Ok(());
+        }
+
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::write(&self.path, &uf2)
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        log::info!("Saved {} bytes to {}", uf2.len(), self.path.display());
+        self.saved = Some((board.board_name().into_owned(), uf2));
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+use anyhow::bail;
+use elf2flash_core::warnings::{WarningCode, Warnings};
+pub use usbh_fatfs::usbh_scsi::select::{DeviceSelector, DeviceSelectorParseError};
+
+use crate::{commands::deploy::report::DeviceReport, exit_code::CliError};
+
+/// The outcome of matching selectors against the plugged in devices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    /// For every device, the index of the first selector it satisfied
+    pub matched_by: Vec<Option<usize>>,
+    /// Indices of the selectors that didn't match any device
+    pub missing: Vec<usize>,
+}
+
+impl Selection {
+    pub fn is_selected(&self, device: usize) -> bool {
+        self.matched_by[device].is_some()
+    }
+}
+
+/// Match `selectors` against `reports`, a device is selected if it satisfies any of them.
+pub fn select_devices(reports: &[DeviceReport], selectors: &[DeviceSelector]) -> Selection {
+    let matched_by = reports
+        .iter()
+        .map(|report| selectors.iter().position(|s| s.matches(report)))
+        .collect();
+
+    let missing = selectors
+        .iter()
+        .enumerate()
+        .filter(|(_, selector)| !reports.iter().any(|report| selector.matches(report)))
+        .map(|(i, _)| i)
+        .collect();
+
+    Selection {
+        matched_by,
+        missing,
+    }
+}
+
+/// Fail if any selector matched no device, or only record it as a warning with `allow_missing`.
+pub fn check_missing_selectors(
+    selection: &Selection,
+    selectors: &[DeviceSelector],
+    reports: &[DeviceReport],
+    allow_missing: bool,
+    warnings: &mut Warnings,
+) -> anyhow::Result<()> {
+    if selection.missing.is_empty() {
+        return Ok(());
+    }
+
+    let missing: Vec<String> = selection
+        .missing
+        .iter()
+        .map(|&selector| selectors[selector].to_string())
+        .collect();
+
+    if allow_missing {
+        warnings.push(
+            WarningCode::SelectorUnmatched,
+            format!("No device matched {}", missing.join(", ")),
+        );
+        Ok(())
+    } else {
+        let present: Vec<String> = reports.iter().map(|r| r.summary()).collect();
+        bail!(CliError::NoDeviceMatched {
+            missing: missing.join(", "),
+            found: present.join("\n    "),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(index: usize, serial: &str, port: &str) -> DeviceReport {
+        DeviceReport {
+            index,
+            board_name: Some("rp2040".to_string()),
+            vendor_id: 0x2e8a,
+            product_id: 0x0003,
+            port: Some(port.to_string()),
+            serial: Some(serial.to_string()),
+            labels: vec!["RPI-RP2".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_selectors() {
+        assert_eq!(
+            "serial:E661184".parse(),
+            Ok(DeviceSelector::Serial("E661184".to_string()))
+        );
+        assert_eq!(
+            "port:3-1.4".parse(),
+            Ok(DeviceSelector::Port("3-1.4".to_string()))
+        );
+        assert_eq!(
+            "vidpid:2e8a:0x0003".parse(),
+            Ok(DeviceSelector::VidPid(0x2e8a, 0x0003))
+        );
+        assert_eq!("index:2".parse(), Ok(DeviceSelector::Index(2)));
+        assert_eq!(
+            "LABEL:RPI-RP2".parse(),
+            Ok(DeviceSelector::Label("RPI-RP2".to_string()))
+        );
+
+        assert_eq!(
+            "E661184".parse::<DeviceSelector>(),
+            Err(DeviceSelectorParseError::MissingKind)
+        );
+        assert_eq!(
+            "usb:1".parse::<DeviceSelector>(),
+            Err(DeviceSelectorParseError::UnknownKind("usb".to_string()))
+        );
+        assert_eq!(
+            "serial:".parse::<DeviceSelector>(),
+            Err(DeviceSelectorParseError::EmptyValue)
+        );
+        assert!(matches!(
+            "vidpid:2e8a".parse::<DeviceSelector>(),
+            Err(DeviceSelectorParseError::InvalidVidPid(_))
+        ));
+        assert!(matches!(
+            "index:-1".parse::<DeviceSelector>(),
+            Err(DeviceSelectorParseError::InvalidIndex(_))
+        ));
+
+        for selector in [
+            "serial:E661184",
+            "port:3-1.4",
+            "vidpid:2e8a:0003",
+            "index:2",
+        ] {
+            assert_eq!(
+                selector.parse::<DeviceSelector>().unwrap().to_string(),
+                selector
+            );
+        }
+    }
+
+    #[test]
+    fn matches_devices_and_reports_missing_selectors() {
+        let reports = [
+            report(0, "AAAA", "3-1.1"),
+            report(1, "BBBB", "3-1.2"),
+            report(2, "CCCC", "3-1.4"),
+        ];
+        let selectors = [
+            DeviceSelector::Serial("BBBB".to_string()),
+            DeviceSelector::Port("3-1.4".to_string()),
+            DeviceSelector::Serial("DDDD".to_string()),
+            DeviceSelector::Index(1),
+        ];
+
+        let selection = select_devices(&reports, &selectors);
+        assert_eq!(selection.matched_by, [None, Some(0), Some(1)]);
+        assert_eq!(selection.missing, [2]);
+        assert!(!selection.is_selected(0));
+
+        assert!(DeviceSelector::VidPid(0x2e8a, 0x0003).matches(&reports[0]));
+        assert!(DeviceSelector::Label("rpi-rp2".to_string()).matches(&reports[0]));
+        assert!(!DeviceSelector::Serial("AAAA".to_string()).matches(&DeviceReport::default()));
+    }
+
+    #[test]
+    fn missing_selectors_fail_unless_allowed() {
+        let reports = [report(0, "AAAA", "3-1.1")];
+        let selectors = [DeviceSelector::Serial("DDDD".to_string())];
+        let selection = select_devices(&reports, &selectors);
+
+        let mut warnings = Warnings::new();
+        let err = check_missing_selectors(&selection, &selectors, &reports, false, &mut warnings)
+            .unwrap_err();
+        assert!(err.to_string().contains("serial:DDDD"));
+        assert!(err.to_string().contains("serial AAAA"));
+        assert!(warnings.is_empty());
+
+        check_missing_selectors(&selection, &selectors, &reports, true, &mut warnings).unwrap();
+        assert!(warnings.contains(WarningCode::SelectorUnmatched));
+    }
+}
@@ -0,0 +1,170 @@
+//! The closing lines of a deploy, telling how long each device took to be written and how fast.
+
+use std::time::Duration;
+
+use crate::{
+    cli_output::{self, format_outcome},
+    output::{self, DeployOutput},
+    progress_bar,
+};
+
+/// `bytes` written in `elapsed`, e.g. `184320 bytes in 1.43 s (125.9 KiB/s)`.
+pub fn throughput(bytes: u64, elapsed: Duration) -> String {
+    format!(
+        "{bytes} bytes in {} ({})",
+        format_duration(elapsed),
+        format_rate(bytes, elapsed)
+    )
+}
+
+/// Seconds with two decimals, with the minutes split off for the slow bootloaders, e.g. `1.43 s`
+/// or `2 min 05 s`.
+pub fn format_duration(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 60 {
+        format!("{:.2} s", elapsed.as_secs_f64())
+    } else {
+        format!("{} min {:02} s", secs / 60, secs % 60)
+    }
+}
+
+/// Bytes per second in the largest unit under 1024 of it, e.g. `125.9 KiB/s`.
+pub fn format_rate(bytes: u64, elapsed: Duration) -> String {
+    // Writes quicker than the millisecond the time is kept in still get a rate
+    let per_sec = bytes as f64 / elapsed.as_secs_f64().max(0.001);
+    if per_sec < 1024.0 {
+        format!("{per_sec:.0} B/s")
+    } else if per_sec < 1024.0 * 1024.0 {
+        format!("{:.1} KiB/s", per_sec / 1024.0)
+    } else {
+        format!("{:.1} MiB/s", per_sec / (1024.0 * 1024.0))
+    }
+}
+
+/// The device a result is about, e.g. `rp2350 (bus 3, addr 16)`.
+fn device_name(result: &DeployOutput) -> String {
+    match result.device.bus_number {
+        // Not a USB device, the image of --mock-volume
+        0 => format!("{} ({})", result.board, result.device.labels.join(", ")),
+        bus => format!(
+            "{} (bus {bus}, addr {})",
+            result.board, result.device.address
+        ),
+    }
+}
+
+/// A line per device, and the total when there were several.
+pub fn summary_lines(results: &[DeployOutput]) -> Vec<String> {
+    let mut lines: Vec<String> = results
+        .iter()
+        .map(|result| {
+            let elapsed = Duration::from_millis(result.elapsed_ms);
+            if result.success {
+                format!(
+                    "{}: {}",
+                    device_name(result),
+                    throughput(result.bytes, elapsed)
+                )
+            } else {
+                format!(
+                    "{}: failed after {}",
+                    device_name(result),
+                    format_duration(elapsed)
+                )
+            }
+        })
+        .collect();
+
+    if results.len() > 1 {
+        let written: Vec<&DeployOutput> = results.iter().filter(|r| r.success).collect();
+        let bytes = written.iter().map(|r| r.bytes).sum();
+        // The devices are written one after the other
+        let elapsed = Duration::from_millis(results.iter().map(|r| r.elapsed_ms).sum());
+        lines.push(format!(
+            "Total: {}, {} of {} devices written",
+            throughput(bytes, elapsed),
+            written.len(),
+            results.len()
+        ));
+    }
+    lines
+}
+
+/// Print the [`summary_lines`], whatever the verbosity, --quiet included, green for the devices
+/// written and red for those that failed. With --format json they are left to the document
+/// instead.
+pub fn print_summary(results: &[DeployOutput]) {
+    if output::is_json() {
+        return;
+    }
+    let all_written = results.iter().all(|result| result.success);
+    let color = cli_output::stdout_color();
+    progress_bar::suspend(|| {
+        for (index, line) in summary_lines(results).iter().enumerate() {
+            // The total follows the lines of the devices
+            let success = results
+                .get(index)
+                .map_or(all_written, |result| result.success);
+            println!("{}", format_outcome(line, success, color));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::deploy::report::DeviceReport;
+
+    #[test]
+    fn sizes_durations_and_rates_are_formatted() {
+        assert_eq!(
+            throughput(184320, Duration::from_millis(1430)),
+            "184320 bytes in 1.43 s (125.9 KiB/s)"
+        );
+        assert_eq!(format_duration(Duration::from_millis(80)), "0.08 s");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2 min 05 s");
+        assert_eq!(format_rate(512, Duration::from_secs(2)), "256 B/s");
+        assert_eq!(format_rate(3 << 20, Duration::from_secs(2)), "1.5 MiB/s");
+        assert_eq!(format_rate(1024, Duration::ZERO), "1000.0 KiB/s");
+    }
+
+    fn result(address: u8, bytes: u64, elapsed_ms: u64, success: bool) -> DeployOutput {
+        DeployOutput {
+            device: DeviceReport {
+                bus_number: 3,
+                address,
+                ..Default::default()
+            },
+            board: "rp2350".to_string(),
+            success,
+            error: None,
+            bytes,
+            elapsed_ms,
+            verification: None,
+        }
+    }
+
+    #[test]
+    fn total_is_added_for_several_devices() {
+        let one = [result(16, 184320, 1430, true)];
+        assert_eq!(
+            summary_lines(&one),
+            ["rp2350 (bus 3, addr 16): 184320 bytes in 1.43 s (125.9 KiB/s)"]
+        );
+
+        let three = [
+            result(16, 184320, 1430, true),
+            result(17, 184320, 1430, true),
+            result(18, 184320, 140, false),
+        ];
+        assert_eq!(
+            summary_lines(&three),
+            [
+                "rp2350 (bus 3, addr 16): 184320 bytes in 1.43 s (125.9 KiB/s)",
+                "rp2350 (bus 3, addr 17): 184320 bytes in 1.43 s (125.9 KiB/s)",
+                "rp2350 (bus 3, addr 18): failed after 0.14 s",
+                "Total: 368640 bytes in 3.00 s (120.0 KiB/s), 2 of 3 devices written",
+            ]
+        );
+    }
+}
@@ -0,0 +1,374 @@
+//! `deploy --backend picoboot`: erasing and writing flash through the PICOBOOT interface of an
+//! RP2040 or RP2350 bootrom. Windows binds that interface to WinUSB on its own, so it works where
+//! raw access to the mass storage interface doesn't.
+
+use anyhow::{Context, Result, bail};
+use elf2flash_core::{
+    ProgressDetail, ProgressReporter,
+    boards::{BoardInfo, RP2040, RP2350, UsbDevice},
+    uf2::{UF2_ABSOLUTE_FAMILY_ID, UF2_BLOCK_SIZE, UF2_FLAG_NOT_MAIN_FLASH, Uf2Block},
+};
+use usbh_fatfs::rusb::{Device, UsbContext};
+
+use crate::{
+    cancel::{CancellationToken, Cancelled},
+    commands::deploy::{
+        input::InputBlocks,
+        to_usb::writing_phase,
+        verify::{Verification, compare_flash},
+    },
+    picoboot::{ExclusiveAccess, Picoboot, PicobootCommand, RusbTransport, Transport},
+    progress_bar::{ProgressBarReporter, device_label},
+};
+
+/// Product id of the RP2040 bootrom, which reboots with REBOOT instead of REBOOT2
+const RP2040_BOOTSEL_PID: u16 = 0x0003;
+/// Top of the RP2040's SRAM, the stack REBOOT is given
+const RP2040_SRAM_END: u32 = 0x2004_2000;
+/// How long the bootrom waits before rebooting, time for it to acknowledge the command
+const REBOOT_DELAY_MS: u32 = 500;
+
+/// Whether `device` is the bootrom of an RP2040 or RP2350, which has a PICOBOOT interface.
+pub fn has_picoboot(device: &UsbDevice) -> bool {
+    RP2040.is_device_board(device) || RP2350.is_device_board(device)
+}
+
+/// Bytes written with one WRITE command, whole consecutive pages of at most a flash sector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlashWrite {
+    pub address: u32,
+    pub data: Vec<u8>,
+    /// The uf2 block the first page comes from
+    pub block_no: u32,
+}
+
+/// The pages of the uf2 `blocks` for `board`, sorted by address and merged into [`FlashWrite`]s.
+/// Blocks for another family are left out, like the bootloader would ignore them. So are the
+/// blocks of [`UF2_ABSOLUTE_FAMILY_ID`], the RP2350-E10 workaround in front of RP2350 images, which
+/// the bootrom drops but PICOBOOT would write over the last sector of a 16 MiB flash.
+pub fn flash_writes(blocks: InputBlocks<'_>, board: &dyn BoardInfo) -> Result<Vec<FlashWrite>> {
+    let page_size = board.page_size();
+    let sector_size = board.flash_sector_erase_size() as usize;
+
+    let mut pages = Vec::new();
+    for (block_no, block) in blocks.enumerate() {
+        let block = Uf2Block::from_bytes(&block?)
+            .with_context(|| format!("Block {block_no} isn't a valid uf2 block"))?;
+        if block
+            .family_id()
+            .is_some_and(|family| family != board.family_id() || family == UF2_ABSOLUTE_FAMILY_ID)
+        {
+            continue;
+        }
+        if block.flags() & UF2_FLAG_NOT_MAIN_FLASH != 0 {
+            bail!("RAM-only images can't be written over PICOBOOT, use another --backend");
+        }
+        if !block.target_addr().is_multiple_of(page_size) || block.payload_size() != page_size {
+            bail!(
+                "Block {block_no} at {:#010x} isn't a whole flash page of {page_size} bytes",
+                block.target_addr()
+            );
+        }
+        pages.push((
+            block.target_addr(),
+            block.payload().to_vec(),
+            block_no as u32,
+        ));
+    }
+    pages.sort_by_key(|(address, _, _)| *address);
+
+    let mut writes: Vec<FlashWrite> = Vec::new();
+    for (address, data, block_no) in pages {
+        match writes.last_mut() {
+            Some(last) if last.address as u64 + last.data.len() as u64 > address as u64 => {
+                bail!("Two blocks write the page at {address:#010x}");
+            }
+            Some(last)
+                if last.address as u64 + last.data.len() as u64 == address as u64
+                    && last.data.len() + data.len() <= sector_size =>
+            {
+                last.data.extend_from_slice(&data);
+            }
+            _ => writes.push(FlashWrite {
+                address,
+                data,
+                block_no,
+            }),
+        }
+    }
+    Ok(writes)
+}
+
+/// The sectors `writes` touch, as the address and size of each run of consecutive sectors.
+pub fn sectors_to_erase(writes: &[FlashWrite], sector_size: u32) -> Vec<(u32, u32)> {
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for write in writes {
+        let first = write.address / sector_size * sector_size;
+        let end = (write.address + write.data.len() as u32).div_ceil(sector_size) * sector_size;
+        match runs.last_mut() {
+            Some((start, size)) if *start + *size >= first => *size = (*size).max(end - *start),
+            _ => runs.push((first, end - first)),
+        }
+    }
+    runs
+}
+
+/// Write the uf2 `blocks` to the flash of `device` through its PICOBOOT interface, and reboot it
+/// into the new program once done. With `verify`, the flash is read back before the reboot.
+pub fn deploy_over_picoboot<T: UsbContext>(
+    blocks: InputBlocks<'_>,
+    device: &Device<T>,
+    board: &dyn BoardInfo,
+    product_id: u16,
+    verify: bool,
+    cancel: &CancellationToken,
+) -> Result<Option<Verification>> {
+    let writes = flash_writes(blocks, board)?;
+    let transport = RusbTransport::open(device).context("Failed to open the PICOBOOT interface")?;
+    let label = device_label(&board.board_name(), device.bus_number(), device.address());
+    let mut progress = ProgressBarReporter::labeled(label).with_cancellation(cancel.clone());
+    write_flash(
+        &mut Picoboot::new(transport)?,
+        &writes,
+        board,
+        product_id,
+        verify,
+        &mut progress,
+    )
+}
+
+/// Erase the sectors `writes` touch, write them, and reboot, reporting the writes to `progress`.
+/// Once cancelled, the mass storage interface is given back and the device stays in its
+/// bootloader. With `verify`, the written ranges are read back and compared before the reboot.
+pub fn write_flash<T: Transport>(
+    picoboot: &mut Picoboot<T>,
+    writes: &[FlashWrite],
+    board: &dyn BoardInfo,
+    product_id: u16,
+    verify: bool,
+    progress: &mut dyn ProgressReporter,
+) -> Result<Option<Verification>> {
+    picoboot
+        .exclusive_access(ExclusiveAccess::Exclusive)
+        .context("Failed to get exclusive access to the bootloader")?;
+    picoboot.exit_xip()?;
+
+    let sectors = sectors_to_erase(writes, board.flash_sector_erase_size() as u32);
+    log::info!("Erasing {} flash range(s)", sectors.len());
+    for (address, size) in sectors {
+        picoboot
+            .flash_erase(address, size)
+            .with_context(|| format!("Failed to erase {size} bytes at {address:#010x}"))?;
+    }
+
+    progress.phase(writing_phase(board));
+    progress.start(writes.iter().map(|write| write.data.len()).sum());
+    for write in writes {
+        if progress.should_cancel() {
+            progress.cancel();
+            picoboot.exclusive_access(ExclusiveAccess::NotExclusive)?;
+            return Err(Cancelled.into());
+        }
+
+        progress.detail(ProgressDetail {
+            phase: writing_phase(board),
+            block_no: write.block_no,
+            target_addr: write.address,
+            file_offset: u64::from(write.block_no) * UF2_BLOCK_SIZE as u64,
+        });
+        picoboot
+            .write(write.address, &write.data)
+            .with_context(|| {
+                format!(
+                    "Failed to write {} bytes at {:#010x}",
+                    write.data.len(),
+                    write.address
+                )
+            })?;
+        progress.advance(write.data.len());
+    }
+    progress.finish();
+
+    let verification = verify.then(|| read_back(picoboot, writes)).transpose()?;
+
+    let reboot = if product_id == RP2040_BOOTSEL_PID {
+        PicobootCommand::reboot(0, RP2040_SRAM_END, REBOOT_DELAY_MS)
+    } else {
+        PicobootCommand::reboot2_normal(REBOOT_DELAY_MS)
+    };
+    picoboot
+        .command(&reboot, &[])
+        .context("Failed to reboot the board")?;
+    Ok(verification)
+}
+
+/// Read each of `writes` back from flash and compare it with what was written.
+fn read_back<T: Transport>(
+    picoboot: &mut Picoboot<T>,
+    writes: &[FlashWrite],
+) -> Result<Verification> {
+    log::info!("Reading back {} flash range(s)", writes.len());
+    let mut verification = Verification::default();
+    for write in writes {
+        let actual = picoboot
+            .read(write.address, write.data.len() as u32)
+            .with_context(|| {
+                format!(
+                    "Failed to read back {} bytes at {:#010x}",
+                    write.data.len(),
+                    write.address
+                )
+            })?;
+        verification.merge(compare_flash(write.address, &write.data, &actual));
+    }
+    Ok(verification)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::deploy::input::DeployInput;
+    use crate::test_support::MockTransport;
+    use elf2flash_core::{
+        NoProgress, Uf2Options,
+        boards::{RP2040, RP2350},
+    };
+
+    /// 5 KiB at the start of flash and a page further on, sector filling left off
+    fn image() -> DeployInput {
+        DeployInput::Image(vec![
+            (0x1000_0000, vec![0x11; 5 * 1024]),
+            (0x1001_0100, vec![0x22; 256]),
+        ])
+    }
+
+    fn writes(input: &DeployInput) -> Vec<FlashWrite> {
+        let options = Uf2Options {
+            fill_sectors: false,
+            ..Default::default()
+        };
+        let (blocks, _) = input.blocks(&RP2040, &options).unwrap();
+        flash_writes(blocks, &RP2040).unwrap()
+    }
+
+    #[test]
+    fn pages_are_merged_up_to_a_sector() {
+        let writes = writes(&image());
+        assert_eq!(
+            writes
+                .iter()
+                .map(|w| (w.address, w.data.len(), w.block_no))
+                .collect::<Vec<_>>(),
+            [
+                (0x1000_0000, 4096, 0),
+                (0x1000_1000, 1024, 16),
+                (0x1001_0100, 256, 20),
+            ]
+        );
+        assert_eq!(
+            sectors_to_erase(&writes, 4096),
+            [(0x1000_0000, 0x2000), (0x1001_0000, 0x1000)]
+        );
+    }
+
+    #[test]
+    fn rp2350_absolute_block_is_not_written() {
+        let elf = std::fs::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../elf2flash-core/tests/rp2350/flash_image.elf"
+        ))
+        .unwrap();
+        let input = DeployInput::Elf(elf);
+        let (blocks, _) = input.blocks(&RP2350, &Uf2Options::default()).unwrap();
+        let writes = flash_writes(blocks, &RP2350).unwrap();
+
+        assert!(!writes.is_empty());
+        let last_sector = 0x10ff_f000..0x1100_0000;
+        for write in &writes {
+            let end = write.address + write.data.len() as u32;
+            assert!(end <= last_sector.start, "{:#010x}", write.address);
+        }
+        for (start, size) in sectors_to_erase(&writes, 4096) {
+            assert!(start + size <= last_sector.start, "{start:#010x}");
+        }
+    }
+
+    #[test]
+    fn flash_is_erased_written_and_rebooted() {
+        let writes = writes(&image());
+        let mut picoboot = Picoboot::new(MockTransport::default()).unwrap();
+        write_flash(
+            &mut picoboot,
+            &writes,
+            &RP2040,
+            RP2040_BOOTSEL_PID,
+            false,
+            &mut NoProgress,
+        )
+        .unwrap();
+
+        let transport = picoboot.into_inner();
+        let commands = transport.commands();
+        assert_eq!(
+            commands.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            // Exclusive access, exit XIP, two erases, three writes and the reboot
+            [0x01, 0x06, 0x03, 0x03, 0x05, 0x05, 0x05, 0x02]
+        );
+        assert_eq!(commands[2].1, [0x1000_0000, 0x2000]);
+        assert_eq!(commands[4].1, [0x1000_0000, 4096]);
+        assert_eq!(commands[7].1, [0, RP2040_SRAM_END, REBOOT_DELAY_MS]);
+        assert!(transport.written.contains(&vec![0x22; 256]));
+        assert_eq!(transport.acks, 8);
+    }
+
+    #[test]
+    fn rp2350_reboots_with_reboot2() {
+        let writes = writes(&image());
+        let mut picoboot = Picoboot::new(MockTransport::default()).unwrap();
+        write_flash(
+            &mut picoboot,
+            &writes,
+            &RP2040,
+            0x000f,
+            false,
+            &mut NoProgress,
+        )
+        .unwrap();
+
+        let commands = picoboot.into_inner().commands();
+        assert_eq!(
+            commands.last().unwrap(),
+            &(0x0a, vec![0, REBOOT_DELAY_MS, 0, 0])
+        );
+    }
+
+    #[test]
+    fn flash_is_read_back_before_the_reboot() {
+        let writes = writes(&image());
+        let mut picoboot = Picoboot::new(MockTransport::default()).unwrap();
+        let verification = write_flash(
+            &mut picoboot,
+            &writes,
+            &RP2040,
+            RP2040_BOOTSEL_PID,
+            true,
+            &mut NoProgress,
+        )
+        .unwrap();
+        assert_eq!(
+            verification.unwrap().to_string(),
+            "Verification passed (5376 bytes)"
+        );
+
+        let commands = picoboot.into_inner().commands();
+        assert_eq!(
+            commands
+                .iter()
+                .rev()
+                .take(4)
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>(),
+            [0x02, 0x84, 0x84, 0x84]
+        );
+    }
+}
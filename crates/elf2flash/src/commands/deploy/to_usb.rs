@@ -1,17 +1,165 @@
-use std::io::Write;
+use std::{
+    io::{self, Read, Write},
+    sync::OnceLock,
+    thread,
+    time::Duration,
+};
 
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
 use elf2flash_core::{
-    ProgressReporter,
-    boards::{BoardInfo, BoardIter, UsbDevice, UsbVersion},
+    Elf2Uf2Error, ProgressDetail, ProgressPhase, ProgressReporter,
+    boards::{BoardInfo, BoardIter, UsbDevice, UsbVersion, family::describe_family},
+    info_uf2::InfoUf2,
+    progress::ProgressWrite,
+    uf2::{UF2_BLOCK_SIZE, Uf2Block},
+    warnings::{WarningCode, Warnings},
+};
+use fatfs::{FileSystem, FsOptions, ReadWriteSeek};
+use usbh_fatfs::{
+    FatPartition, PartitionView, StorageUsb, rusb,
+    usbh_scsi::{
+        select::{ListOptions, PortPath, serial_number},
+        session::UsbSession,
+        storage::block_device::UsbBlockDevice,
+    },
 };
-use fatfs::{FileSystem, FsOptions};
-use usbh_fatfs::{FatPartition, PartitionView, StorageUsb};
 
-pub fn get_plugged_in_boards() -> Result<Vec<(UsbDevice, Option<Box<dyn BoardInfo>>, StorageUsb)>> {
-    let mut boards_found = Vec::new();
+use crate::{
+    cancel::{CancellationToken, Cancelled},
+    commands::deploy::verify::{Verification, verify_uf2},
+    progress_bar::{ProgressBarReporter, device_label},
+};
+
+/// Chunks are never rounded up to more than this, so very large clusters don't buffer the whole
+/// file in memory
+const MAX_CHUNK_SIZE: usize = 256 * 1024;
+
+/// `INFO_UF2.TXT` is a few lines, anything past this isn't read
+pub(crate) const MAX_INFO_UF2_LEN: u64 = 4096;
+
+/// A plugged in device, listed through the shared [`usb_session`]
+pub type SessionUsb = StorageUsb<rusb::Context>;
+
+/// A plugged in device with the board it is recognized as, `None` for generic devices
+pub type PluggedInDevice = (UsbDevice, Option<Box<dyn BoardInfo>>, SessionUsb);
+
+static USB_SESSION: OnceLock<UsbSession> = OnceLock::new();
+
+static LIST_OPTIONS: OnceLock<ListOptions> = OnceLock::new();
+
+static USB_TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+/// The libusb session every command lists and opens devices through, created on first use.
+pub fn usb_session() -> Result<&'static UsbSession> {
+    if let Some(session) = USB_SESSION.get() {
+        return Ok(session);
+    }
+
+    let session = UsbSession::new().context("Failed to initialize libusb")?;
+    // Another thread may have won the race, its session is the one everyone uses
+    Ok(USB_SESSION.get_or_init(|| session))
+}
+
+/// Only list the devices at or behind `roots` from now on, every device if it's empty. Set once at
+/// startup from `--usb-root`, later calls are ignored.
+pub fn set_usb_roots(roots: Vec<PortPath>) {
+    let _ = LIST_OPTIONS.set(ListOptions { roots });
+}
+
+fn list_options() -> &'static ListOptions {
+    LIST_OPTIONS.get_or_init(ListOptions::default)
+}
+
+/// Give up on a USB transfer after `timeout` from now on, instead of the default of each
+/// transfer. Set once at startup from `--usb-timeout-ms`, later calls are ignored.
+pub fn set_usb_timeout(timeout: Duration) {
+    let _ = USB_TIMEOUT.set(timeout);
+}
+
+/// The `--usb-timeout-ms` given, `None` for the default of each transfer
+pub fn usb_timeout() -> Option<Duration> {
+    USB_TIMEOUT.get().copied()
+}
+
+/// An already built uf2 file as a stream of blocks for [`deploy_to_usb`], a partial block at the
+/// end is left out.
+pub fn uf2_blocks(
+    data: &[u8],
+) -> impl ExactSizeIterator<Item = Result<[u8; UF2_BLOCK_SIZE], Elf2Uf2Error>> + '_ {
+    data.chunks_exact(UF2_BLOCK_SIZE).map(|block| {
+        Ok(block
+            .try_into()
+            .expect("chunks_exact always yields whole blocks"))
+    })
+}
+
+/// Round `chunk_size` up to a multiple of the volume's cluster size.
+///
+/// fatfs updates a cluster once for every write that touches it, so chunks smaller than a cluster
+/// make it rewrite the same cluster several times. With `exact` the chunk size is left alone.
+pub fn cluster_aligned_chunk_size(chunk_size: usize, cluster_size: u32, exact: bool) -> usize {
+    let cluster_size = cluster_size as usize;
+    if exact || cluster_size == 0 || chunk_size.is_multiple_of(cluster_size) {
+        return chunk_size;
+    }
+
+    let aligned = chunk_size
+        .next_multiple_of(cluster_size)
+        .min(MAX_CHUNK_SIZE);
+    log::info!(
+        "Writing in chunks of {aligned} bytes instead of {chunk_size}, to match the volume's \
+         {cluster_size} byte clusters"
+    );
+    aligned
+}
+
+/// The board `usb_device` is, out of the built-in boards and the ones registered in the
+/// [`BoardRegistry`](elf2flash_core::boards::BoardRegistry).
+pub fn recognize_board(usb_device: &UsbDevice) -> Option<Box<dyn BoardInfo>> {
+    BoardIter::new().find(|b| b.is_device_board(usb_device))
+}
+
+/// The board whose bootloader wrote `info`, for devices whose USB ids no board knows.
+pub fn board_for_info(info: &InfoUf2) -> Option<Box<dyn BoardInfo>> {
+    BoardIter::new().find(|b| b.matches_info_uf2(info))
+}
+
+/// Recognize an unknown device from the `INFO_UF2.TXT` on its uf2 `partitions`.
+fn recognize_from_info_uf2(
+    description: &str,
+    partitions: &[(FatPartition, InfoUf2)],
+) -> Option<Box<dyn BoardInfo>> {
+    let (board, info) = partitions
+        .iter()
+        .find_map(|(_, info)| Some((board_for_info(info)?, info)))?;
+
+    log::info!(
+        "Recognized {description} as board '{}' from its Board-ID '{}'",
+        board.board_name(),
+        info.board_id.as_deref().unwrap_or_default()
+    );
+    Some(board)
+}
+
+/// Every plugged in USB mass storage device.
+pub fn plugged_in_devices() -> Result<Vec<PluggedInDevice>> {
+    list_devices(true)
+}
+
+/// Every plugged in USB mass storage device, from the descriptors libusb already has. None of the
+/// devices is opened, so their serial numbers are left out.
+pub fn plugged_in_descriptors() -> Result<Vec<PluggedInDevice>> {
+    list_devices(false)
+}
+
+fn list_devices(read_serials: bool) -> Result<Vec<PluggedInDevice>> {
+    let session = usb_session()?;
+    let mut devices = Vec::new();
 
-    for usb in StorageUsb::list_usbs()? {
+    for mut usb in StorageUsb::list_usbs_in_with(session, list_options())? {
+        if let Some(timeout) = usb_timeout() {
+            usb.set_timeout(timeout);
+        }
         let desc = match usb.usb_device.device_descriptor() {
             Ok(d) => d,
             Err(_) => continue,
@@ -24,260 +172,987 @@ pub fn get_plugged_in_boards() -> Result<Vec<(UsbDevice, Option<Box<dyn BoardInf
             vendor_id: desc.vendor_id(),
             product_id: desc.product_id(),
             version: UsbVersion(version.0, version.1, version.2),
+            serial_number: read_serials
+                .then(|| serial_number(&usb.usb_device))
+                .flatten(),
         };
 
-        if let Some(board) = BoardIter::new().find(|b| b.is_device_board(&usb_device)) {
-            boards_found.push((usb_device, Some(board), usb));
+        let board = recognize_board(&usb_device);
+        devices.push((usb_device, board, usb));
+    }
+
+    Ok(devices)
+}
+
+/// The generic devices that may be uf2 bootloaders, recognized from the `INFO_UF2.TXT` on their
+/// volumes when its Board-ID belongs to a board. Each is released again without a reset, and one
+/// whose volumes were read without finding an `INFO_UF2.TXT`, like an external disk, is left out.
+/// One that can't be read is kept, it may still be flashed through the volume the OS mounted.
+fn uf2_generic_devices(devices: Vec<PluggedInDevice>) -> Vec<PluggedInDevice> {
+    devices
+        .into_iter()
+        .filter_map(|(usb_device, _, mut usb)| {
+            let board = recognize_generic_device(&usb_device, &mut usb)?;
+            Some((usb_device, board, usb))
+        })
+        .collect()
+}
+
+/// Recognize a generic device from the `INFO_UF2.TXT` on its volumes, releasing it again without
+/// a reset. `None` when its volumes were read without finding an `INFO_UF2.TXT`, and `Some(None)`
+/// when it may still be a uf2 bootloader of no known board, see [`uf2_generic_devices`].
+pub fn recognize_generic_device(
+    usb_device: &UsbDevice,
+    usb: &mut SessionUsb,
+) -> Option<Option<Box<dyn BoardInfo>>> {
+    let description = format!(
+        "device {:04x}:{:04x}",
+        usb_device.vendor_id, usb_device.product_id
+    );
+    let partitions = uf2_partitions(&description, usb);
+    usb.release();
+    match partitions {
+        Ok(partitions) if partitions.is_empty() => {
+            log::debug!("Skipping {description}, none of its volumes is a uf2 one");
+            None
         }
+        Ok(partitions) => Some(recognize_from_info_uf2(&description, &partitions)),
+        Err(_) => Some(None),
     }
+}
+
+/// The plugged in devices recognized as a board, or the generic devices that may be uf2
+/// bootloaders when there are none. Only then are the generic devices opened, see
+/// [`uf2_generic_devices`].
+pub fn get_plugged_in_boards(warnings: &mut Warnings) -> Result<Vec<PluggedInDevice>> {
+    let (mut boards_found, generic_devices): (Vec<_>, Vec<_>) = plugged_in_devices()?
+        .into_iter()
+        .partition(|(_, board, _)| board.is_some());
 
     if boards_found.is_empty() {
-        log::warn!("No recognized boards found, falling back to generic UF2 devices");
-
-        for usb in StorageUsb::list_usbs()? {
-            let desc = match usb.usb_device.device_descriptor() {
-                Ok(d) => d,
-                Err(_) => continue,
-            };
-
-            let version = desc.device_version();
-            let usb_device = UsbDevice {
-                bus_number: usb.usb_device.bus_number(),
-                address: usb.usb_device.address(),
-                vendor_id: desc.vendor_id(),
-                product_id: desc.product_id(),
-                version: UsbVersion(version.0, version.1, version.2),
-            };
-
-            boards_found.push((usb_device, None, usb));
-        }
+        warnings.push(
+            WarningCode::GenericDeviceFallback,
+            "No recognized boards found, falling back to generic UF2 devices",
+        );
+        boards_found = uf2_generic_devices(generic_devices);
     }
 
     Ok(boards_found)
 }
 
-pub fn list_uf2_partitions(
+/// The partitions of `storage_usb` holding an `INFO_UF2.TXT`, with its contents, for flashing
+/// `board`.
+pub fn board_uf2_partitions(
     board: &dyn BoardInfo,
-    storage_usb: &mut StorageUsb,
-) -> Result<Vec<FatPartition>> {
-    let mut uf2_partitions = Vec::new();
-    let partitions = match FatPartition::list_partitions(storage_usb) {
-        Ok(part) => part,
-        Err(err) => {
-            log::warn!(
-                "Failed to list partitions for board '{}' (family id {:#x}): {err:?}",
-                board.board_name(),
-                board.family_id()
-            );
+    storage_usb: &mut SessionUsb,
+) -> Result<Vec<(FatPartition, InfoUf2)>> {
+    let description = format!(
+        "board '{}' (family id {})",
+        board.board_name(),
+        describe_family(board.family_id())
+    );
+    uf2_partitions(&description, storage_usb)
+}
 
-            bail!(
-                "Failed to list partitions for board '{}' (family id {:#x}): {err:?}",
-                board.board_name(),
-                board.family_id()
-            )
-        }
-    };
+/// The partitions of `storage_usb` holding an `INFO_UF2.TXT`, with its contents. `description`
+/// names the device in the logs and errors. A partition that can't be opened or mounted is logged
+/// and skipped.
+pub fn uf2_partitions(
+    description: &str,
+    storage_usb: &mut SessionUsb,
+) -> Result<Vec<(FatPartition, InfoUf2)>> {
+    let mut skipped = Vec::new();
+    let partitions = read_uf2_partitions(description, storage_usb, &mut skipped);
+    for err in skipped {
+        log::error!("{err:#}");
+    }
+    partitions
+}
+
+/// Like [`uf2_partitions`], but the errors of the partitions that can't be opened or mounted are
+/// pushed to `skipped` instead of logged.
+pub fn read_uf2_partitions(
+    description: &str,
+    storage_usb: &mut SessionUsb,
+    skipped: &mut Vec<anyhow::Error>,
+) -> Result<Vec<(FatPartition, InfoUf2)>> {
+    let mut uf2_partitions = Vec::new();
+    let partitions = FatPartition::list_partitions(storage_usb)
+        .with_context(|| format!("Failed to list partitions for {description}"))
+        .inspect_err(|err| log::warn!("{err:#}"))?;
     for partition in partitions {
-        let opened = match storage_usb.open() {
+        let opened = match storage_usb
+            .open()
+            .with_context(|| format!("Failed to open USB mass storage for {description}"))
+        {
             Ok(opened) => opened,
             Err(err) => {
-                log::error!(
-                    "Failed to open USB mass storage for board '{}' (family id {:#x}): {err:?}",
-                    board.board_name(),
-                    board.family_id()
-                );
+                skipped.push(err);
                 continue;
             }
         };
-        let mut block_device = match opened.block_device() {
+        let mut block_device = match opened
+            .block_device()
+            .with_context(|| format!("Failed to get block device for {description}"))
+        {
             Ok(dev) => dev,
             Err(err) => {
-                log::error!(
-                    "Failed to get block device for board '{}' (family id {:#x}): {err:?}",
-                    board.board_name(),
-                    board.family_id()
-                );
+                skipped.push(err);
                 continue;
             }
         };
 
-        let part_view = match PartitionView::new(
-            &mut block_device,
-            partition.first_byte,
-            partition.length,
-        ) {
-            Ok(part) => part,
-            Err(err) => {
-                log::error!(
-                    "Failed to create new parition view for board '{}' (family id {:#x}): {err:?}",
-                    board.board_name(),
-                    board.family_id()
-                );
-                bail!(
-                    "Failed to create new parition view for board '{}' (family id {:#x}): {err:?}",
-                    board.board_name(),
-                    board.family_id()
-                );
-            }
-        };
+        let part_view =
+            PartitionView::new(&mut block_device, partition.first_byte, partition.length)
+                .with_context(|| format!("Failed to create new parition view for {description}"))
+                .inspect_err(|err| log::error!("{err:#}"))?;
 
-        let fatfs = match FileSystem::new(part_view, FsOptions::new()) {
+        let fatfs = match FileSystem::new(part_view, FsOptions::new())
+            .with_context(|| format!("Failed to mount FAT filesystem on {description}"))
+        {
             Ok(fs) => fs,
             Err(err) => {
-                log::error!(
-                    "Failed to mount FAT filesystem on board '{}' (family id {:#x}): {err:?}",
-                    board.board_name(),
-                    board.family_id()
-                );
+                skipped.push(err);
                 continue;
             }
         };
-        let mut contains_info_uf2 = false;
-        for item in fatfs.root_dir().iter() {
-            let item = match item {
-                Ok(item) => item,
-                Err(err) => {
-                    log::debug!(
-                        "Failed to read item on FAT filesystem on board '{}' (family id {:#x}): {err:?}",
-                        board.board_name(),
-                        board.family_id()
-                    );
-                    continue;
-                }
-            };
-            let name = item.file_name();
-            if name.contains("INFO_UF2.TXT") {
-                contains_info_uf2 = true;
-            }
-        }
-
-        if !contains_info_uf2 {
-            log::debug!(
-                "Partition on board '{}' does not contain INFO_UF2.TXT, skipping",
-                board.board_name()
-            );
+        let Some(info) = read_info_uf2(&fatfs, description) else {
+            log::debug!("Partition on {description} does not contain INFO_UF2.TXT, skipping");
             continue;
-        }
+        };
 
         log::debug!(
-            "Found partition on board '{}' that contains INFO_UF2.TXT",
-            board.board_name()
+            "Found {:?} partition on {description} that contains INFO_UF2.TXT",
+            partition.fat_type
         );
 
-        uf2_partitions.push(partition);
+        uf2_partitions.push((partition, info));
     }
 
     Ok(uf2_partitions)
 }
 
-pub fn deploy_to_usb<B: AsRef<[u8]>>(
-    out_file: B,
+/// Whether the root directory of a mounted FAT filesystem holds the `INFO_UF2.TXT` every UF2
+/// bootloader exposes.
+///
+/// Only the directory entries are read, so it works the same on the FAT12 volumes of the smallest
+/// bootloaders, whose root directory has a fixed number of entries like FAT16's.
+pub fn contains_info_uf2<T: ReadWriteSeek>(fatfs: &FileSystem<T>, board: &dyn BoardInfo) -> bool {
+    read_info_uf2(fatfs, &format!("board '{}'", board.board_name())).is_some()
+}
+
+/// The `INFO_UF2.TXT` in the root directory of a mounted FAT filesystem, read as the directory is
+/// searched for it. A file that can't be read gives an empty [`InfoUf2`], the volume is still a
+/// UF2 one. `description` names the device in the logs.
+pub fn read_info_uf2<T: ReadWriteSeek>(
+    fatfs: &FileSystem<T>,
+    description: &str,
+) -> Option<InfoUf2> {
+    let mut info_uf2 = None;
+    for item in fatfs.root_dir().iter() {
+        let item = match item {
+            Ok(item) => item,
+            Err(err) => {
+                log::debug!(
+                    "Failed to read item on FAT filesystem on {description}: {:#}",
+                    anyhow::Error::from(err)
+                );
+                continue;
+            }
+        };
+        let name = item.file_name();
+        if name.contains("INFO_UF2.TXT") {
+            let mut contents = Vec::new();
+            if let Err(err) = item
+                .to_file()
+                .take(MAX_INFO_UF2_LEN)
+                .read_to_end(&mut contents)
+            {
+                log::debug!("Failed to read INFO_UF2.TXT on {description}: {err}");
+            }
+            info_uf2 = Some(InfoUf2::parse(&String::from_utf8_lossy(&contents)));
+        }
+    }
+    info_uf2
+}
+
+/// Mount the FAT filesystem on `partition` and run `f` against it.
+///
+/// The filesystem is unmounted (and flushed) once `f` returns.
+pub fn with_partition_fs<R>(
     partition: &FatPartition,
     board: &dyn BoardInfo,
-    storage_usb: &mut StorageUsb,
-    mut progress: impl ProgressReporter,
-) -> anyhow::Result<()> {
-    progress.start(out_file.as_ref().len());
+    storage_usb: &mut SessionUsb,
+    f: impl FnOnce(&FileSystem<PartitionView<&mut UsbBlockDevice<'_, rusb::Context>>>) -> Result<R>,
+) -> Result<R> {
+    let on_board = || {
+        format!(
+            "board '{}' (family id {})",
+            board.board_name(),
+            describe_family(board.family_id())
+        )
+    };
+
+    let opened = storage_usb
+        .open()
+        .with_context(|| format!("Failed to open USB mass storage for {}", on_board()))
+        .inspect_err(|err| log::error!("{err:#}"))?;
+
+    let mut block_device = opened
+        .block_device()
+        .with_context(|| format!("Failed to get block device for {}", on_board()))
+        .inspect_err(|err| log::error!("{err:#}"))?;
 
+    let part_view = PartitionView::new(&mut block_device, partition.first_byte, partition.length)
+        .with_context(|| format!("Failed to create new parition view for {}", on_board()))
+        .inspect_err(|err| log::error!("{err:#}"))?;
+
+    let fatfs = FileSystem::new(part_view, FsOptions::new())
+        .with_context(|| format!("Failed to mount FAT filesystem on {}", on_board()))?;
+
+    f(&fatfs)
+}
+
+/// How [`deploy_to_usb`] writes the uf2 file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// Write chunks of exactly the board's chunk size, see [`cluster_aligned_chunk_size`]
+    pub chunk_size_exact: bool,
+    /// Read the file back before the volume is ejected, and compare it with what was written
+    pub verify: bool,
+}
+
+pub fn deploy_to_usb(
+    blocks: impl ExactSizeIterator<Item = Result<[u8; UF2_BLOCK_SIZE], Elf2Uf2Error>>,
+    partition: &FatPartition,
+    board: &dyn BoardInfo,
+    storage_usb: &mut SessionUsb,
+    options: WriteOptions,
+    warnings: &mut Warnings,
+    cancel: &CancellationToken,
+) -> anyhow::Result<Option<Verification>> {
     log::info!(
-        "Writing firmware to board '{}' (family id {:#x})",
+        "Writing firmware to board '{}' (family id {})",
         board.board_name(),
-        board.family_id()
+        describe_family(board.family_id())
     );
 
-    let opened = match storage_usb.open() {
-        Ok(opened) => opened,
-        Err(err) => {
-            log::error!(
-                "Failed to open USB mass storage for board '{}' (family id {:#x}): {err:?}",
-                board.board_name(),
-                board.family_id()
-            );
-            bail!(
-                "Failed to open USB mass storage for board '{}' (family id {:#x}): {err:?}",
-                board.board_name(),
-                board.family_id()
-            );
+    // Blocks are converted and written one chunk at a time
+    let chunk_size = cluster_aligned_chunk_size(
+        board.write_chunk_size(),
+        partition.cluster_size,
+        options.chunk_size_exact,
+    );
+
+    let mut written = Vec::new();
+    let blocks = blocks.inspect(|block| {
+        if let (true, Ok(block)) = (options.verify, block) {
+            written.extend_from_slice(block);
         }
+    });
+    let label = device_label(
+        &board.board_name(),
+        storage_usb.usb_device.bus_number(),
+        storage_usb.usb_device.address(),
+    );
+    with_partition_fs(partition, board, storage_usb, |fatfs| {
+        write_uf2_file(
+            fatfs,
+            blocks,
+            board,
+            chunk_size,
+            ProgressBarReporter::labeled(label).with_cancellation(cancel.clone()),
+            warnings,
+        )
+    })?;
+
+    // The filesystem is unmounted and flushed by now, so the file is read from the device
+    let verification = if options.verify {
+        let read_back = || {
+            with_partition_fs(partition, board, storage_usb, |fatfs| {
+                read_uf2_file(fatfs, board.uf2_filename())
+            })
+        };
+        verify_uf2(board, &written, read_back, warnings)
+    } else {
+        None
     };
 
-    let mut block_device = match opened.block_device() {
-        Ok(dev) => dev,
-        Err(err) => {
-            log::error!(
-                "Failed to get block device for board '{}' (family id {:#x}): {err:?}",
-                board.board_name(),
-                board.family_id()
-            );
-            bail!(
-                "Failed to get block device for board '{}' (family id {:#x}): {err:?}",
-                board.board_name(),
-                board.family_id()
+    if board.needs_eject() {
+        log::info!("Ejecting volume so the bootloader reboots");
+        if let Err(err) = eject(storage_usb) {
+            log::warn!(
+                "Failed to eject the volume of board '{}', eject it to boot the firmware: {err:#}",
+                board.board_name()
             );
         }
-    };
+    }
+    Ok(verification)
+}
+
+/// The uf2 file `filename` in the root directory of a mounted FAT filesystem.
+pub fn read_uf2_file<T: ReadWriteSeek>(fatfs: &FileSystem<T>, filename: &str) -> Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    fatfs
+        .root_dir()
+        .open_file(filename)
+        .with_context(|| format!("Failed to open {filename}"))?
+        .read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+/// Send the bootloader the eject an operating system sends when the drive is ejected.
+fn eject(storage_usb: &mut SessionUsb) -> Result<()> {
+    storage_usb
+        .open()
+        .context("Failed to open USB mass storage")?
+        .eject()
+        .context("START STOP UNIT failed")?;
+    Ok(())
+}
+
+/// Fail when a uf2 file `filename` of `total_bytes` doesn't fit on a mounted FAT filesystem, so a
+/// write that can only fail part way through is never started.
+///
+/// A file with the same name left on the volume is overwritten, so its clusters count as free.
+/// The free clusters are counted by fatfs, which also decodes the 12 bit entries of FAT12 volumes.
+pub fn check_free_space<T: ReadWriteSeek>(
+    fatfs: &FileSystem<T>,
+    filename: &str,
+    total_bytes: u64,
+) -> Result<()> {
+    let stats = fatfs
+        .stats()
+        .context("Failed to read the free space of the volume")?;
+    let cluster_size = u64::from(stats.cluster_size());
+    let mut available = u64::from(stats.free_clusters()) * cluster_size;
 
-    let part_view = match PartitionView::new(
-        &mut block_device,
-        partition.first_byte,
-        partition.length as u64,
-    ) {
-        Ok(part) => part,
+    let existing = fatfs
+        .root_dir()
+        .iter()
+        .filter_map(Result::ok)
+        .find(|entry| entry.file_name().eq_ignore_ascii_case(filename));
+    if let Some(existing) = existing {
+        available += existing.len().div_ceil(cluster_size) * cluster_size;
+    }
+
+    if total_bytes > available {
+        bail!("{filename} needs {total_bytes} bytes, but only {available} bytes are free");
+    }
+    Ok(())
+}
+
+/// Write the uf2 `blocks` into the root directory of a mounted FAT filesystem, as the file
+/// [`BoardInfo::uf2_filename`] names.
+///
+/// Blocks are pulled from the iterator `chunk_size` bytes at a time, so a
+/// [`Uf2BlockIterator`](elf2flash_core::Uf2BlockIterator) converting an elf never has to hold the
+/// whole uf2 file in memory.
+///
+/// `progress` is asked whether to cancel before every chunk, once it does the partial file is
+/// removed and [`Cancelled`] returned. Between two chunks it waits for the board's
+/// [`BoardInfo::inter_chunk_delay`].
+pub fn write_uf2_file<T: ReadWriteSeek>(
+    fatfs: &FileSystem<T>,
+    blocks: impl ExactSizeIterator<Item = Result<[u8; UF2_BLOCK_SIZE], Elf2Uf2Error>>,
+    board: &dyn BoardInfo,
+    chunk_size: usize,
+    mut progress: impl ProgressReporter,
+    warnings: &mut Warnings,
+) -> anyhow::Result<()> {
+    let filename = board.uf2_filename();
+    progress.phase(writing_phase(board));
+
+    match fatfs.root_dir().create_file(filename) {
+        Ok(file) => write_uf2_chunks(file, blocks, board, chunk_size, progress, warnings, || {
+            fatfs.root_dir().remove(filename)
+        }),
         Err(err) => {
-            log::error!(
-                "Failed to create new parition view for board '{}' (family id {:#x}): {err:?}",
-                board.board_name(),
-                board.family_id()
+            warnings.push(
+                WarningCode::WriteFailed,
+                format!(
+                    "Failed to create {filename} on board '{}': {:#}",
+                    board.board_name(),
+                    anyhow::Error::from(err)
+                ),
             );
-            bail!(
-                "Failed to create new parition view for board '{}' (family id {:#x}): {err:?}",
-                board.board_name(),
-                board.family_id()
+            Ok(())
+        }
+    }
+}
+
+/// The phase a write of the uf2 file to `board` reports.
+pub(crate) fn writing_phase(board: &dyn BoardInfo) -> ProgressPhase {
+    ProgressPhase::Writing {
+        board_name: board.board_name().into_owned(),
+    }
+}
+
+/// Write the uf2 `blocks` into the just created uf2 `file`, chunked and cancelled like
+/// [`write_uf2_file`] describes. `remove` deletes the partial file once cancelled.
+pub(crate) fn write_uf2_chunks(
+    file: impl Write,
+    blocks: impl ExactSizeIterator<Item = Result<[u8; UF2_BLOCK_SIZE], Elf2Uf2Error>>,
+    board: &dyn BoardInfo,
+    chunk_size: usize,
+    mut progress: impl ProgressReporter,
+    warnings: &mut Warnings,
+    remove: impl FnOnce() -> io::Result<()>,
+) -> anyhow::Result<()> {
+    let total_bytes = blocks.len() * UF2_BLOCK_SIZE;
+    let filename = board.uf2_filename();
+    let phase = writing_phase(board);
+
+    let mut file = ProgressWrite::new(file, &mut progress, total_bytes);
+    let mut chunk = Vec::with_capacity(chunk_size);
+    let mut blocks = blocks.peekable();
+    let mut written = 0;
+    // Where the chunk being collected starts
+    let mut detail = None;
+
+    while let Some(block) = blocks.next() {
+        let block = block?;
+        if chunk.is_empty() {
+            let block_no = (written / UF2_BLOCK_SIZE) as u32;
+            detail = Uf2Block::from_bytes(&block)
+                .ok()
+                .map(|block| ProgressDetail {
+                    phase: phase.clone(),
+                    block_no,
+                    target_addr: block.target_addr(),
+                    file_offset: written as u64,
+                });
+        }
+        chunk.extend_from_slice(&block);
+
+        if chunk.len() < chunk_size && blocks.peek().is_some() {
+            continue;
+        }
+
+        if file.should_cancel() {
+            drop(file.cancel());
+            if let Err(err) = remove() {
+                log::warn!(
+                    "Failed to remove the partial {filename}: {:#}",
+                    anyhow::Error::from(err)
+                );
+            }
+            return Err(Cancelled.into());
+        }
+
+        if let Some(detail) = detail.take() {
+            file.detail(detail);
+        }
+
+        // Progress advances with every write the volume accepts, which can be part of a chunk
+        if let Err(err) = file.write_all(&chunk) {
+            // The rest of the file would fail the same way
+            warnings.push(
+                WarningCode::WriteFailed,
+                format!(
+                    "Failed to write {filename} to board '{}': {:#}",
+                    board.board_name(),
+                    anyhow::Error::from(err)
+                ),
             );
+            break;
         }
-    };
+        written += chunk.len();
+        chunk.clear();
 
-    let fatfs = match FileSystem::new(part_view, FsOptions::new()) {
-        Ok(fs) => fs,
-        Err(err) => {
-            bail!(
-                "Failed to mount FAT filesystem on board '{}' (family id {:#x}): {err:?}",
+        if let Some(delay) = board.inter_chunk_delay()
+            && blocks.peek().is_some()
+        {
+            thread::sleep(delay);
+        }
+    }
+
+    if let Err(err) = file.finish() {
+        warnings.push(
+            WarningCode::WriteFailed,
+            format!(
+                "Failed to flush {filename} to board '{}': {:#}",
                 board.board_name(),
-                board.family_id()
+                anyhow::Error::from(err)
+            ),
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        FAT_IMAGE_SIZE, FAT12_ROOT_DIR_ENTRIES, FailingDisk, fat_image, fat12_image,
+        register_board, usb_device,
+    };
+    use elf2flash_core::{
+        NoProgress, Uf2BlockIterator,
+        boards::{CustomBoardBuilder, DEFAULT_WRITE_CHUNK_SIZE, RP2040},
+        testing::RecordingReporter,
+        uf2::verify_against_elf,
+    };
+    use fatfs::{FatType, FormatVolumeOptions};
+    use std::{
+        borrow::Cow,
+        io::{self, Cursor, Read, Seek, SeekFrom},
+        time::{Duration, Instant},
+    };
+    use usbh_fatfs::read_file;
+
+    /// An in-memory volume that records the size of the write commands it receives
+    struct RecordingDisk {
+        image: Cursor<Vec<u8>>,
+        writes: Vec<usize>,
+    }
+
+    impl Read for RecordingDisk {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.image.read(buf)
+        }
+    }
+
+    impl Write for RecordingDisk {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let written = self.image.write(buf)?;
+            self.writes.push(written);
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.image.flush()
+        }
+    }
+
+    impl Seek for RecordingDisk {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.image.seek(pos)
+        }
+    }
+
+    /// Write `blocks` uf2 blocks for `board` onto a volume with 32 KiB clusters, returning the
+    /// sizes of the write commands
+    fn record_writes(board: &dyn BoardInfo, blocks: usize, chunk_size: usize) -> Vec<usize> {
+        let mut image = Cursor::new(vec![0u8; FAT_IMAGE_SIZE]);
+        fatfs::format_volume(
+            &mut image,
+            FormatVolumeOptions::new().bytes_per_cluster(32 * 1024),
+        )
+        .unwrap();
+        image.set_position(0);
+
+        let mut disk = RecordingDisk {
+            image,
+            writes: Vec::new(),
+        };
+        let fatfs = FileSystem::new(&mut disk, FsOptions::new()).unwrap();
+        let blocks = (0..blocks).map(|_| Ok([0; UF2_BLOCK_SIZE]));
+
+        let mut warnings = Warnings::new();
+        write_uf2_file(&fatfs, blocks, board, chunk_size, NoProgress, &mut warnings).unwrap();
+        assert!(warnings.is_empty());
+        drop(fatfs);
+
+        disk.writes
+    }
+
+    /// Write a 256 KiB uf2 file onto a volume with 32 KiB clusters, returning the write commands
+    fn count_writes(chunk_size: usize) -> usize {
+        record_writes(&RP2040, 512, chunk_size).len()
+    }
+
+    #[test]
+    fn chunks_are_rounded_up_to_whole_clusters() {
+        assert_eq!(
+            cluster_aligned_chunk_size(DEFAULT_WRITE_CHUNK_SIZE, 32 * 1024, false),
+            32 * 1024
+        );
+        assert_eq!(
+            cluster_aligned_chunk_size(DEFAULT_WRITE_CHUNK_SIZE, 32 * 1024, true),
+            DEFAULT_WRITE_CHUNK_SIZE
+        );
+        assert_eq!(
+            cluster_aligned_chunk_size(DEFAULT_WRITE_CHUNK_SIZE, 4096, false),
+            DEFAULT_WRITE_CHUNK_SIZE
+        );
+        assert_eq!(
+            cluster_aligned_chunk_size(DEFAULT_WRITE_CHUNK_SIZE, 1024 * 1024, false),
+            MAX_CHUNK_SIZE
+        );
+
+        let unaligned = count_writes(DEFAULT_WRITE_CHUNK_SIZE);
+        let aligned = count_writes(cluster_aligned_chunk_size(
+            DEFAULT_WRITE_CHUNK_SIZE,
+            32 * 1024,
+            false,
+        ));
+        assert!(
+            aligned < unaligned,
+            "{aligned} writes with aligned chunks, {unaligned} without"
+        );
+    }
+
+    #[test]
+    fn uf2_is_written_as_the_board_filename() {
+        for name in ["CURRENT.UF2", "firmware-v1.2.uf2"] {
+            let mut image = fat_image(&[]);
+            let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+            let board = CustomBoardBuilder::from_board(&RP2040)
+                .uf2_filename(name)
+                .build()
+                .unwrap();
+            let blocks = (0..8).map(|_| Ok([0; UF2_BLOCK_SIZE]));
+
+            let mut warnings = Warnings::new();
+            write_uf2_file(
+                &fatfs,
+                blocks,
+                &board,
+                DEFAULT_WRITE_CHUNK_SIZE,
+                NoProgress,
+                &mut warnings,
+            )
+            .unwrap();
+            assert!(warnings.is_empty());
+
+            let names: Vec<String> = fatfs
+                .root_dir()
+                .iter()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            assert_eq!(names, [name]);
+            assert_eq!(
+                read_file(&fatfs, name, None).unwrap().len(),
+                8 * UF2_BLOCK_SIZE
             );
         }
-    };
+    }
+
+    #[test]
+    fn chunks_follow_the_board_write_chunk_size() {
+        let board = CustomBoardBuilder::from_board(&RP2040)
+            .write_chunk_size(2048)
+            .inter_chunk_delay(Duration::from_millis(20))
+            .build()
+            .unwrap();
+        let chunk_size = cluster_aligned_chunk_size(board.write_chunk_size(), 512, false);
+
+        let started = Instant::now();
+        let writes = record_writes(&board, 16, chunk_size);
+        // Three waits between the four chunks
+        assert!(started.elapsed() >= Duration::from_millis(60));
+
+        // The metadata writes of fatfs are smaller than a block
+        let data: Vec<_> = writes
+            .into_iter()
+            .filter(|&len| len >= UF2_BLOCK_SIZE)
+            .collect();
+        assert_eq!(data, [2048; 4]);
+    }
+
+    #[test]
+    fn full_volume_raises_write_failed() {
+        let mut image = fat_image(&[]);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+
+        // More blocks than fit on the volume
+        let blocks = (0..FAT_IMAGE_SIZE / UF2_BLOCK_SIZE + 1).map(|_| Ok([0; UF2_BLOCK_SIZE]));
+
+        let mut warnings = Warnings::new();
+        write_uf2_file(
+            &fatfs,
+            blocks,
+            &RP2040,
+            DEFAULT_WRITE_CHUNK_SIZE,
+            NoProgress,
+            &mut warnings,
+        )
+        .unwrap();
+
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings.contains(WarningCode::WriteFailed));
+    }
+
+    #[test]
+    fn free_space_is_checked_before_writing() {
+        let mut image = fat_image(&[]);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+        let free = {
+            let stats = fatfs.stats().unwrap();
+            u64::from(stats.free_clusters()) * u64::from(stats.cluster_size())
+        };
+        check_free_space(&fatfs, "out.uf2", free).unwrap();
+        let err = check_free_space(&fatfs, "out.uf2", free + 1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "out.uf2 needs {} bytes, but only {free} bytes are free",
+                free + 1
+            )
+        );
+
+        // The clusters of an earlier out.uf2 are reused
+        let mut old = fatfs.root_dir().create_file("OUT.UF2").unwrap();
+        old.write_all(&[0; 4096]).unwrap();
+        drop(old);
+        check_free_space(&fatfs, "out.uf2", free).unwrap();
+        // Only when it has the name that is written
+        assert!(check_free_space(&fatfs, "CURRENT.UF2", free).is_err());
+    }
+
+    #[test]
+    fn fat12_volume_is_found_written_and_verified() {
+        const HELLO_USB: &[u8] =
+            include_bytes!("../../../../elf2flash-core/tests/rp2040/hello_usb.elf");
+
+        let mut image = fat12_image(&[("INFO_UF2.TXT", b"UF2 Bootloader"), ("INDEX.HTM", b"")]);
+        {
+            let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+            assert_eq!(fatfs.fat_type(), FatType::Fat12);
+            assert!(contains_info_uf2(&fatfs, &RP2040));
+
+            let blocks = Uf2BlockIterator::new(Cursor::new(HELLO_USB), &RP2040).unwrap();
+            check_free_space(&fatfs, "out.uf2", blocks.total_bytes() as u64).unwrap();
+
+            let mut warnings = Warnings::new();
+            let chunk_size =
+                cluster_aligned_chunk_size(DEFAULT_WRITE_CHUNK_SIZE, fatfs.cluster_size(), false);
+            write_uf2_file(
+                &fatfs,
+                blocks,
+                &RP2040,
+                chunk_size,
+                NoProgress,
+                &mut warnings,
+            )
+            .unwrap();
+            assert!(warnings.is_empty());
+        }
+
+        // Mounted again, as the next deploy would find it
+        image.set_position(0);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+        let uf2 = read_file(&fatfs, "out.uf2", None).unwrap();
+        let report = verify_against_elf(uf2.as_slice(), HELLO_USB, &RP2040).unwrap();
+        assert!(report.is_consistent());
+        assert!(report.blocks_checked > 0);
+    }
+
+    #[test]
+    fn full_fat12_root_directory_raises_write_failed() {
+        let mut image = fat12_image(&[("INFO_UF2.TXT", b"info")]);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+
+        // FAT12's root directory can't grow, fill the entries left with short names
+        let filled = (0..FAT12_ROOT_DIR_ENTRIES)
+            .take_while(|i| fatfs.root_dir().create_file(&format!("F{i}.TXT")).is_ok())
+            .count();
+        assert!(filled < FAT12_ROOT_DIR_ENTRIES as usize);
+        assert!(contains_info_uf2(&fatfs, &RP2040));
+
+        let mut warnings = Warnings::new();
+        write_uf2_file(
+            &fatfs,
+            (0..4).map(|_| Ok([0; UF2_BLOCK_SIZE])),
+            &RP2040,
+            DEFAULT_WRITE_CHUNK_SIZE,
+            NoProgress,
+            &mut warnings,
+        )
+        .unwrap();
+
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings.contains(WarningCode::WriteFailed));
+    }
+
+    #[test]
+    fn unknown_device_is_recognized_from_info_uf2() {
+        let mut image = fat12_image(&[
+            (
+                "INFO_UF2.TXT",
+                b"UF2 Bootloader 0.6.0 lib/nrfx (v2.0.0)\r\n\
+                  Model: Adafruit ItsyBitsy nRF52840 Express\r\n\
+                  Board-ID: nRF52840-ItsyBitsy-revA\r\n",
+            ),
+            ("INDEX.HTM", b""),
+        ]);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
 
-    match fatfs.root_dir().create_file("out.uf2") {
-        Ok(mut file) => {
-            const CHUNK_SIZE: usize = 16 * 1024; // tune this
+        let info = read_info_uf2(&fatfs, "device 239a:ffff").unwrap();
+        assert_eq!(info.bootloader_version.as_deref(), Some("0.6.0"));
+        let board = board_for_info(&info).unwrap();
+        assert_eq!(board.board_name(), "itsybitsy_nrf52840");
+        assert_eq!(board.family_id(), 0xada52840);
 
-            for chunk in out_file.as_ref().chunks(CHUNK_SIZE) {
-                match file.write_all(chunk) {
-                    Ok(_) => (),
-                    Err(err) => log::error!(
-                        "Failed to write out.uf2 to board '{}': {err:?}",
-                        board.board_name()
+        // A bootloader nobody knows is still a uf2 volume, just not a known board
+        let mut image = fat_image(&[("INFO_UF2.TXT", b"UF2 Bootloader v1.1\nBoard-ID: ACME-1\n")]);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+        assert!(contains_info_uf2(&fatfs, &RP2040));
+        let info = read_info_uf2(&fatfs, "device 1234:5678").unwrap();
+        assert!(board_for_info(&info).is_none());
+
+        let mut image = fat_image(&[("README.TXT", b"not a bootloader")]);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+        assert_eq!(read_info_uf2(&fatfs, "device 1234:5678"), None);
+    }
+
+    #[test]
+    fn failed_create_names_the_usb_cause() {
+        let mut disk = FailingDisk::new(fat_image(&[]));
+        let failing = disk.failing.clone();
+        let fatfs = FileSystem::new(&mut disk, FsOptions::new()).unwrap();
+        failing.set(true);
+
+        let blocks = (0..64).map(|_| Ok([0; UF2_BLOCK_SIZE]));
+        let mut warnings = Warnings::new();
+        write_uf2_file(
+            &fatfs,
+            blocks,
+            &RP2040,
+            DEFAULT_WRITE_CHUNK_SIZE,
+            NoProgress,
+            &mut warnings,
+        )
+        .unwrap();
+
+        let warning = warnings.iter().next().unwrap();
+        assert_eq!(warning.code, WarningCode::WriteFailed);
+        assert_eq!(
+            warning.message,
+            "Failed to create out.uf2 on board 'rp2040': READ(10) failed: bulk transfer failed: \
+             Operation timed out"
+        );
+    }
+
+    #[test]
+    fn cancelled_write_stops_before_the_next_chunk() {
+        let mut image = fat_image(&[]);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+        let blocks = (0..64).map(|_| Ok([0; UF2_BLOCK_SIZE]));
+
+        let mut recorder = RecordingReporter::cancel_after(1);
+        let mut warnings = Warnings::new();
+        let err = write_uf2_file(
+            &fatfs,
+            blocks,
+            &RP2040,
+            DEFAULT_WRITE_CHUNK_SIZE,
+            &mut recorder,
+            &mut warnings,
+        )
+        .unwrap_err();
+
+        assert!(err.is::<Cancelled>());
+        assert!(warnings.is_empty());
+        assert_eq!(recorder.advanced(), DEFAULT_WRITE_CHUNK_SIZE);
+        // The partial file is removed
+        assert!(fatfs.root_dir().open_file("out.uf2").is_err());
+    }
+
+    #[test]
+    fn writing_phase_names_the_board() {
+        let mut image = fat_image(&[]);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+        let blocks = (0..4).map(|_| Ok([0; UF2_BLOCK_SIZE]));
+
+        let mut recorder = RecordingReporter::default();
+        write_uf2_file(
+            &fatfs,
+            blocks,
+            &RP2040,
+            DEFAULT_WRITE_CHUNK_SIZE,
+            &mut recorder,
+            &mut Warnings::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            recorder.phases(),
+            [ProgressPhase::Writing {
+                board_name: "rp2040".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn detail_is_reported_for_every_chunk() {
+        let golden = include_bytes!("../../../../elf2flash-core/tests/rp2040/hello_usb.uf2");
+        let mut image = fat_image(&[]);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+
+        let mut recorder = RecordingReporter::default();
+        write_uf2_file(
+            &fatfs,
+            uf2_blocks(golden),
+            &RP2040,
+            DEFAULT_WRITE_CHUNK_SIZE,
+            &mut recorder,
+            &mut Warnings::new(),
+        )
+        .unwrap();
+        let details = recorder.details();
+
+        let blocks_per_chunk = DEFAULT_WRITE_CHUNK_SIZE / UF2_BLOCK_SIZE;
+        let expected: Vec<_> = (0..golden.len() / UF2_BLOCK_SIZE)
+            .step_by(blocks_per_chunk)
+            .map(|block_no| {
+                let offset = block_no * UF2_BLOCK_SIZE;
+                ProgressDetail {
+                    phase: ProgressPhase::Writing {
+                        board_name: "rp2040".to_string(),
+                    },
+                    block_no: block_no as u32,
+                    target_addr: u32::from_le_bytes(
+                        golden[offset + 12..offset + 16].try_into().unwrap(),
                     ),
+                    file_offset: offset as u64,
                 }
-                progress.advance(chunk.len()); // only once per chunk
+            })
+            .collect();
+        assert_eq!(details.len(), 3);
+        assert_eq!(details, expected);
+    }
+
+    #[test]
+    fn commands_share_one_usb_session() {
+        let sessions: Vec<_> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..8).map(|_| scope.spawn(|| usb_session().ok())).collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+
+        // Without usable libusb every thread fails alike, otherwise they all get the same session
+        assert!(sessions.iter().all(|session| match (session, sessions[0]) {
+            (Some(session), Some(first)) => std::ptr::eq(*session, first),
+            (session, first) => session.is_none() && first.is_none(),
+        }));
+    }
+
+    #[test]
+    fn registered_boards_are_recognized() {
+        struct BenchBoard;
+
+        impl BoardInfo for BenchBoard {
+            fn is_device_board(&self, device: &UsbDevice) -> bool {
+                device.vendor_id == 0xbe0c && device.product_id == 0x0002
             }
 
-            if let Err(err) = file.flush() {
-                log::error!(
-                    "Failed to flush out.uf2 to board '{}': {err:?}",
-                    board.board_name()
-                );
+            fn family_id(&self) -> u32 {
+                0xbe0cbe0c
+            }
+
+            fn board_name(&self) -> Cow<'static, str> {
+                "bench_board".into()
             }
-            progress.finish();
-        }
-        Err(err) => {
-            log::error!(
-                "Failed to create out.uf2 on board '{}': {err:?}",
-                board.board_name()
-            );
         }
-    }
 
-    Ok(())
+        let device = usb_device(0xbe0c, 0x0002);
+        assert!(recognize_board(&device).is_none());
+
+        let _bench_board = register_board("bench_board", BenchBoard);
+        let board = recognize_board(&device).unwrap();
+        assert_eq!(board.board_name(), "bench_board");
+        assert_eq!(board.family_id(), 0xbe0cbe0c);
+    }
 }
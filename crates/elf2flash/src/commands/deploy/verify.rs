@@ -0,0 +1,231 @@
+//! `deploy --verify`: comparing what was written with what the device gives back, the flash itself
+//! over PICOBOOT, or the uf2 file read back from the volume before the bootloader reboots.
+
+use std::fmt;
+
+use anyhow::Result;
+use elf2flash_core::{
+    boards::BoardInfo,
+    uf2::{UF2_BLOCK_SIZE, Uf2Block},
+    warnings::{WarningCode, Warnings},
+};
+use serde::Serialize;
+
+/// Bytes of a uf2 block before its payload
+const UF2_HEADER_SIZE: usize = 32;
+
+/// The outcome of comparing written bytes with the ones read back.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Verification {
+    /// Bytes compared
+    pub checked: usize,
+    /// Bytes that differ, the ones missing from what was read back or added to it included
+    pub mismatched: usize,
+    /// The flash address of the first differing byte, `None` when nothing could be read back
+    pub first_mismatch: Option<u32>,
+}
+
+impl Verification {
+    /// The outcome when none of the `len` written bytes could be read back.
+    pub fn unreadable(len: usize) -> Self {
+        Self {
+            checked: len,
+            mismatched: len,
+            first_mismatch: None,
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.mismatched == 0
+    }
+
+    /// Add the outcome of another range to this one.
+    pub fn merge(&mut self, other: Verification) {
+        self.checked += other.checked;
+        self.mismatched += other.mismatched;
+        self.first_mismatch = self.first_mismatch.or(other.first_mismatch);
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.first_mismatch {
+            Some(address) if !self.passed() => write!(
+                f,
+                "Verification failed ({} bytes mismatched at {address:#010x})",
+                self.mismatched
+            ),
+            _ if !self.passed() => write!(
+                f,
+                "Verification failed ({} bytes couldn't be read back)",
+                self.mismatched
+            ),
+            _ => write!(f, "Verification passed ({} bytes)", self.checked),
+        }
+    }
+}
+
+/// Compare the `expected` bytes written at `address` with the `actual` ones read back from there.
+pub fn compare_flash(address: u32, expected: &[u8], actual: &[u8]) -> Verification {
+    let mut differing = (0..expected.len()).filter(|&i| actual.get(i) != Some(&expected[i]));
+    let first_mismatch = differing.next().map(|i| address + i as u32);
+
+    Verification {
+        checked: expected.len(),
+        mismatched: first_mismatch.map_or(0, |_| 1 + differing.count()),
+        first_mismatch,
+    }
+}
+
+/// Compare the `expected` uf2 file with the `actual` one read back from the volume, bytes read
+/// back past its end counting as mismatched. The first difference is located at the flash address
+/// its block writes to.
+pub fn compare_uf2(expected: &[u8], actual: &[u8]) -> Verification {
+    let mut verification = compare_flash(0, expected, actual);
+    let extra = actual.len().saturating_sub(expected.len());
+    if extra > 0 {
+        verification.mismatched += extra;
+        verification
+            .first_mismatch
+            .get_or_insert(expected.len() as u32);
+    }
+    verification.first_mismatch = verification.first_mismatch.map(|offset| {
+        flash_address(expected, offset as usize)
+            .or_else(|| flash_address(actual, offset as usize))
+            .unwrap_or(offset)
+    });
+    verification
+}
+
+/// The flash address the byte at `offset` of the `uf2` file is written to. A byte of a block's
+/// header counts for the start of its payload.
+fn flash_address(uf2: &[u8], offset: usize) -> Option<u32> {
+    let start = offset / UF2_BLOCK_SIZE * UF2_BLOCK_SIZE;
+    let block = uf2.get(start..start + UF2_BLOCK_SIZE)?;
+    let block = Uf2Block::from_bytes(block.try_into().ok()?).ok()?;
+    let in_payload = (offset - start).saturating_sub(UF2_HEADER_SIZE);
+    Some(block.target_addr() + in_payload as u32)
+}
+
+/// [`compare_uf2`] the `expected` uf2 file of `board` with the one `read_back` gives. Bootloaders
+/// that reboot once the whole file is written leave nothing to read, so a file that can't be read
+/// back is a warning and gives no verification.
+pub fn verify_uf2(
+    board: &dyn BoardInfo,
+    expected: &[u8],
+    read_back: impl FnOnce() -> Result<Vec<u8>>,
+    warnings: &mut Warnings,
+) -> Option<Verification> {
+    log::info!(
+        "Reading back {} from board '{}'",
+        board.uf2_filename(),
+        board.board_name()
+    );
+    match read_back() {
+        Ok(actual) => Some(compare_uf2(expected, &actual)),
+        Err(err) => {
+            warnings.push(
+                WarningCode::VerifySkipped,
+                format!(
+                    "Read-back not possible on this bootloader, {} on board '{}' wasn't verified: \
+                     {err:#}",
+                    board.uf2_filename(),
+                    board.board_name()
+                ),
+            );
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elf2flash_core::{Uf2Options, boards::RP2040};
+
+    use crate::commands::deploy::input::DeployInput;
+
+    fn uf2() -> Vec<u8> {
+        let input = DeployInput::Image(vec![(0x1000_0000, vec![0x5a; 600])]);
+        let (blocks, _) = input.blocks(&RP2040, &Uf2Options::default()).unwrap();
+        blocks.map(Result::unwrap).collect::<Vec<_>>().concat()
+    }
+
+    #[test]
+    fn flash_mismatches_are_counted_from_the_first() {
+        let verification = compare_flash(0x1000_0000, &[1, 2, 3, 4], &[1, 2, 3, 4]);
+        assert!(verification.passed());
+        assert_eq!(verification.to_string(), "Verification passed (4 bytes)");
+
+        let mut verification = compare_flash(0x1000_0000, &[1, 2, 3, 4], &[1, 0, 3]);
+        assert_eq!(
+            verification,
+            Verification {
+                checked: 4,
+                mismatched: 2,
+                first_mismatch: Some(0x1000_0001),
+            }
+        );
+
+        verification.merge(compare_flash(0x1000_0100, &[5; 8], &[0; 8]));
+        assert_eq!(
+            verification.to_string(),
+            "Verification failed (10 bytes mismatched at 0x10000001)"
+        );
+    }
+
+    #[test]
+    fn uf2_mismatches_point_into_flash() {
+        let expected = uf2();
+        assert!(compare_uf2(&expected, &expected).passed());
+
+        // A byte of the second block's payload
+        let mut actual = expected.clone();
+        actual[UF2_BLOCK_SIZE + UF2_HEADER_SIZE + 10] ^= 0xff;
+        assert_eq!(
+            compare_uf2(&expected, &actual).to_string(),
+            "Verification failed (1 bytes mismatched at 0x1000010a)"
+        );
+
+        // A file cut short by a reboot
+        let verification = compare_uf2(&expected, &expected[..2 * UF2_BLOCK_SIZE]);
+        assert_eq!(verification.mismatched, expected.len() - 2 * UF2_BLOCK_SIZE);
+        assert_eq!(verification.first_mismatch, Some(0x1000_0200));
+
+        // A file with a block too many
+        let actual = [&expected[..], &expected[..UF2_BLOCK_SIZE]].concat();
+        let verification = compare_uf2(&expected, &actual);
+        assert_eq!(verification.mismatched, UF2_BLOCK_SIZE);
+        assert_eq!(verification.first_mismatch, Some(0x1000_0000));
+    }
+
+    #[test]
+    fn unreadable_files_are_a_warning() {
+        let expected = uf2();
+        let mut warnings = Warnings::new();
+        let verification = verify_uf2(
+            &RP2040,
+            &expected,
+            || anyhow::bail!("No such file"),
+            &mut warnings,
+        );
+        assert_eq!(verification, None);
+        assert_eq!(
+            warnings.iter().map(|w| &w.message).collect::<Vec<_>>(),
+            [
+                "Read-back not possible on this bootloader, out.uf2 on board 'rp2040' wasn't \
+                 verified: No such file"
+            ]
+        );
+
+        let verification = verify_uf2(&RP2040, &expected, || Ok(expected.clone()), &mut warnings);
+        assert!(verification.unwrap().passed());
+        assert_eq!(warnings.len(), 1);
+
+        // A volume that can't be written to still fails
+        assert_eq!(
+            Verification::unreadable(1024).to_string(),
+            "Verification failed (1024 bytes couldn't be read back)"
+        );
+    }
+}
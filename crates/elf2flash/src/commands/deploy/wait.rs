@@ -0,0 +1,199 @@
+//! `deploy --wait`: polling for the bootloader of a board that was just reset into it, instead of
+//! racing its enumeration and finding nothing.
+
+use std::{
+    io::{self, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use elf2flash_core::warnings::Warnings;
+
+use crate::{
+    cancel::CancellationToken,
+    progress_bar::{self, ProgressMode},
+};
+
+/// Seconds `--wait` waits when it is given without a number
+pub const DEFAULT_WAIT_SECS: u64 = 30;
+/// Time between two looks at the plugged in devices
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+/// List the devices with `enumerate` every `interval` until `wanted` accepts the list or `timeout`
+/// has passed, and return the last list with the warnings raised while listing it. Warnings of the
+/// earlier lists are dropped, they would repeat on every poll.
+///
+/// Checks `cancel` before every poll, so Ctrl+C ends the wait with [`Cancelled`].
+///
+/// [`Cancelled`]: crate::cancel::Cancelled
+pub fn wait_for_devices<T>(
+    mut enumerate: impl FnMut(&mut Warnings) -> Result<Vec<T>>,
+    mut wanted: impl FnMut(&mut [T]) -> bool,
+    timeout: Duration,
+    interval: Duration,
+    cancel: &CancellationToken,
+) -> Result<(Vec<T>, Warnings)> {
+    let start = Instant::now();
+    let mut status = WaitStatus::new(timeout);
+
+    loop {
+        cancel.check()?;
+
+        let mut warnings = Warnings::new();
+        let mut devices = enumerate(&mut warnings)?;
+        if wanted(&mut devices) {
+            status.finish();
+            return Ok((devices, warnings));
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            status.finish();
+            log::warn!(
+                "No matching uf2 device showed up within {} seconds",
+                timeout.as_secs()
+            );
+            return Ok((devices, warnings));
+        }
+
+        status.tick(elapsed);
+        thread::sleep(interval.min(timeout - elapsed));
+    }
+}
+
+/// The line telling that the deploy is waiting, redrawn in place on a terminal and logged once
+/// otherwise.
+struct WaitStatus {
+    timeout: Duration,
+    /// Whether the line is redrawn, and needs ending once done
+    redraw: bool,
+    ticks: usize,
+}
+
+impl WaitStatus {
+    fn new(timeout: Duration) -> Self {
+        let redraw = progress_bar::mode() == ProgressMode::Bars;
+        if !redraw {
+            log::info!(
+                "Waiting up to {} seconds for a uf2 device",
+                timeout.as_secs()
+            );
+        }
+
+        Self {
+            timeout,
+            redraw,
+            ticks: 0,
+        }
+    }
+
+    fn tick(&mut self, elapsed: Duration) {
+        if !self.redraw {
+            return;
+        }
+
+        let mut stderr = io::stderr();
+        let _ = write!(
+            stderr,
+            "\r{} Waiting for a uf2 device... {}s/{}s",
+            SPINNER[self.ticks % SPINNER.len()],
+            elapsed.as_secs(),
+            self.timeout.as_secs()
+        );
+        let _ = stderr.flush();
+        self.ticks += 1;
+    }
+
+    fn finish(&mut self) {
+        if self.redraw && self.ticks > 0 {
+            eprintln!();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::Cancelled;
+    use elf2flash_core::warnings::WarningCode;
+
+    const INTERVAL: Duration = Duration::from_millis(1);
+
+    /// An enumerator returning `lists` one after the other, then the last one forever
+    fn enumerator(
+        lists: Vec<Vec<&'static str>>,
+    ) -> impl FnMut(&mut Warnings) -> Result<Vec<&'static str>> {
+        let mut polls = 0;
+        move |warnings| {
+            warnings.push(WarningCode::GenericDeviceFallback, format!("poll {polls}"));
+            let list = lists[polls.min(lists.len() - 1)].clone();
+            polls += 1;
+            Ok(list)
+        }
+    }
+
+    #[test]
+    fn waits_until_a_wanted_device_shows_up() {
+        let lists = vec![vec![], vec!["other"], vec!["other", "pico"]];
+        let (devices, warnings) = wait_for_devices(
+            enumerator(lists),
+            |devices| devices.contains(&"pico"),
+            Duration::from_secs(10),
+            INTERVAL,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+
+        assert_eq!(devices, ["other", "pico"]);
+        // Only the warnings of the list that is returned
+        assert_eq!(
+            warnings.iter().map(|w| &*w.message).collect::<Vec<_>>(),
+            ["poll 2"]
+        );
+    }
+
+    #[test]
+    fn gives_up_after_the_timeout() {
+        let mut polls = 0;
+        let (devices, _) = wait_for_devices(
+            |_: &mut Warnings| {
+                polls += 1;
+                Ok(vec!["other"])
+            },
+            |devices| devices.contains(&"pico"),
+            Duration::from_millis(20),
+            INTERVAL,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+
+        assert_eq!(devices, ["other"]);
+        assert!(polls > 1, "{polls}");
+    }
+
+    #[test]
+    fn cancelling_stops_the_wait() {
+        let cancel = CancellationToken::new();
+        let mut polls = 0;
+        let err = wait_for_devices(
+            |_: &mut Warnings| {
+                polls += 1;
+                if polls == 3 {
+                    cancel.cancel();
+                }
+                Ok(Vec::<&str>::new())
+            },
+            |devices| !devices.is_empty(),
+            Duration::from_secs(10),
+            INTERVAL,
+            &cancel,
+        )
+        .unwrap_err();
+
+        assert!(err.is::<Cancelled>());
+        assert_eq!(polls, 3);
+    }
+}
@@ -0,0 +1,215 @@
+//! Deploying again each time the input is rebuilt, for `deploy --watch`.
+
+use std::{
+    ffi::OsStr,
+    path::Path,
+    sync::mpsc::{self, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail};
+use notify::{Event, RecursiveMode, Watcher};
+
+use crate::cancel::CancellationToken;
+
+/// How long the input has to stay unchanged before it is deployed, linkers write it in several
+/// steps
+pub const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// How often Ctrl+C is checked for while waiting for a change
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+/// Collects the changes to the input and tells once they have settled.
+#[derive(Debug, Clone, Copy)]
+pub struct Debouncer {
+    quiet: Duration,
+    last_change: Option<Instant>,
+}
+
+impl Debouncer {
+    pub fn new(quiet: Duration) -> Self {
+        Self {
+            quiet,
+            last_change: None,
+        }
+    }
+
+    /// The input changed at `now`.
+    pub fn changed(&mut self, now: Instant) {
+        self.last_change = Some(now);
+    }
+
+    /// Whether the input changed and stayed unchanged since for the quiet time by `now`. Only
+    /// true once for a burst of changes.
+    pub fn settled(&mut self, now: Instant) -> bool {
+        match self.last_change {
+            Some(last) if now.duration_since(last) >= self.quiet => {
+                self.last_change = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// How long until the changes so far settle, `None` if there are none.
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.last_change
+            .map(|last| self.quiet.saturating_sub(now.duration_since(last)))
+    }
+}
+
+/// Whether `event`, from watching the input's directory, changed the file named `file_name`.
+pub fn touches(event: &Event, file_name: &OsStr) -> bool {
+    !event.kind.is_access()
+        && event
+            .paths
+            .iter()
+            .any(|path| path.file_name() == Some(file_name))
+}
+
+/// Run `deploy` once, then again every time `input` changes, until Ctrl+C. A failed deploy is
+/// printed and the next change waited for.
+pub fn watch(
+    input: &Path,
+    cancel: &CancellationToken,
+    mut deploy: impl FnMut() -> Result<()>,
+) -> Result<()> {
+    let file_name = input
+        .file_name()
+        .with_context(|| format!("{} isn't a file to watch", input.display()))?;
+    let dir = match input.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    // The directory is watched, a linker replacing the file would end a watch on the file itself
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).context("Failed to watch for changes")?;
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("Failed to watch {}", dir.display()))?;
+
+    let mut debouncer = Debouncer::new(DEBOUNCE);
+    'deploys: loop {
+        if let Err(err) = deploy() {
+            if cancel.is_cancelled() {
+                break;
+            }
+            log::error!("{err:#}");
+        }
+        if cancel.is_cancelled() {
+            break;
+        }
+
+        log::info!("Watching {} for changes, Ctrl+C to stop", input.display());
+        loop {
+            if cancel.is_cancelled() {
+                break 'deploys;
+            }
+            let timeout = debouncer
+                .remaining(Instant::now())
+                .map_or(CANCEL_POLL, |remaining| remaining.min(CANCEL_POLL));
+            match rx.recv_timeout(timeout) {
+                Ok(Ok(event)) if touches(&event, file_name) => debouncer.changed(Instant::now()),
+                Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+                Ok(Err(err)) => log::warn!("Error while watching {}: {err}", dir.display()),
+                Err(RecvTimeoutError::Disconnected) => {
+                    bail!("Stopped getting changes of {}", dir.display())
+                }
+            }
+
+            if debouncer.settled(Instant::now()) {
+                if input.is_file() {
+                    break;
+                }
+                log::info!(
+                    "{} was removed, waiting for it to be written again",
+                    input.display()
+                );
+            }
+        }
+        log::info!("{} changed, deploying again\n", input.display());
+    }
+
+    log::info!("Stopped watching {}", input.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::{
+        EventKind,
+        event::{AccessKind, CreateKind, DataChange, ModifyKind, RemoveKind},
+    };
+    use std::path::PathBuf;
+
+    const QUIET: Duration = Duration::from_millis(500);
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn a_burst_of_writes_is_deployed_once() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(QUIET);
+        assert!(!debouncer.settled(start));
+        assert_eq!(debouncer.remaining(start), None);
+
+        // The linker truncates, writes and then sets the permissions, 100 ms apart
+        for step in 0..3 {
+            let now = start + ms(step * 100);
+            debouncer.changed(now);
+            assert!(!debouncer.settled(now + ms(50)));
+        }
+        assert_eq!(debouncer.remaining(start + ms(400)), Some(ms(300)));
+        assert!(!debouncer.settled(start + ms(699)));
+        assert!(debouncer.settled(start + ms(700)));
+
+        // Deployed, nothing changes afterwards
+        assert!(!debouncer.settled(start + ms(5000)));
+        assert_eq!(debouncer.remaining(start + ms(5000)), None);
+
+        // The next build
+        debouncer.changed(start + ms(9000));
+        assert_eq!(debouncer.remaining(start + ms(9900)), Some(Duration::ZERO));
+        assert!(debouncer.settled(start + ms(9900)));
+    }
+
+    #[test]
+    fn only_changes_to_the_input_trigger() {
+        let input = OsStr::new("firmware");
+        let event = |kind, path: &str| Event::new(kind).add_path(PathBuf::from(path));
+        let written = EventKind::Modify(ModifyKind::Data(DataChange::Any));
+
+        assert!(touches(&event(written, "target/release/firmware"), input));
+        assert!(touches(
+            &event(
+                EventKind::Create(CreateKind::File),
+                "target/release/firmware"
+            ),
+            input
+        ));
+        assert!(touches(
+            &event(
+                EventKind::Remove(RemoveKind::File),
+                "target/release/firmware"
+            ),
+            input
+        ));
+
+        // Other build outputs next to it, and reading it to deploy it
+        assert!(!touches(
+            &event(written, "target/release/firmware.d"),
+            input
+        ));
+        assert!(!touches(
+            &event(
+                EventKind::Access(AccessKind::Any),
+                "target/release/firmware"
+            ),
+            input
+        ));
+    }
+}
@@ -0,0 +1,658 @@
+//! `doctor`, checking that the connected uf2 devices can be found and opened, and saying what to
+//! change when they can't. Every check takes what it looks at as arguments, so the advice can be
+//! tested without the devices or the operating system it is about.
+
+use std::{env, fmt};
+
+use anyhow::{Result, bail};
+use clap::Args;
+use serde::Serialize;
+use usbh_fatfs::{StorageUsbError, rusb, usbh_scsi::storage::UsbMassStorageError};
+
+use crate::{
+    commands::deploy::{
+        report::DeviceReport,
+        to_usb::{plugged_in_devices, usb_session},
+    },
+    diagnostics::environment,
+    output,
+};
+
+/// Where the rules from [`udev_rules`] go
+pub const UDEV_RULES_PATH: &str = "/etc/udev/rules.d/99-elf2flash.rules";
+
+/// The groups that give access on Linux, with what they give access to
+const GROUPS: [(&str, &str); 2] = [
+    ("plugdev", "the uf2 devices, through the udev rules"),
+    ("dialout", "the serial ports of --serial and --reboot"),
+];
+
+#[derive(Args, Debug)]
+pub struct DoctorArgs {
+    /// Print the checks as a JSON array instead of text
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    /// Works, but not as well as it could
+    Warning,
+    Failed,
+    /// Doesn't apply to this machine, or there was nothing to check
+    Skipped,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CheckStatus::Ok => "ok",
+            CheckStatus::Warning => "warning",
+            CheckStatus::Failed => "failed",
+            CheckStatus::Skipped => "skipped",
+        })
+    }
+}
+
+/// The outcome of one check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub message: String,
+    /// What to do about it, a step per entry
+    pub remediation: Vec<String>,
+}
+
+impl CheckResult {
+    fn new(name: &'static str, status: CheckStatus, message: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            message: message.into(),
+            remediation: Vec::new(),
+        }
+    }
+
+    fn with_steps(mut self, steps: impl IntoIterator<Item = String>) -> Self {
+        self.remediation.extend(steps);
+        self
+    }
+}
+
+/// How opening a device for raw USB access went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpenOutcome {
+    Opened,
+    /// libusb's `Access`, the user may not open the device
+    AccessDenied,
+    /// libusb's `NotSupported`, on Windows the interface isn't bound to WinUSB
+    NotSupported,
+    Failed(String),
+}
+
+/// A visible USB mass storage device and how opening it went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbedDevice {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// Name of the recognized board, `None` for generic devices
+    pub board: Option<String>,
+    pub open: OpenOutcome,
+}
+
+impl ProbedDevice {
+    fn summary(&self) -> String {
+        DeviceReport {
+            board_name: self.board.clone(),
+            vendor_id: self.vendor_id,
+            product_id: self.product_id,
+            ..Default::default()
+        }
+        .summary()
+    }
+}
+
+/// udev rules giving the logged in user, and the plugdev group, access to the devices with these
+/// USB ids, each pair once.
+pub fn udev_rules(ids: &[(u16, u16)]) -> String {
+    let mut ids = ids.to_vec();
+    ids.sort_unstable();
+    ids.dedup();
+
+    let mut rules = String::from("# uf2 bootloaders flashed by elf2flash\n");
+    for (vendor_id, product_id) in ids {
+        rules.push_str(&format!(
+            "SUBSYSTEM==\"usb\", ATTRS{{idVendor}}==\"{vendor_id:04x}\", \
+             ATTRS{{idProduct}}==\"{product_id:04x}\", MODE=\"0660\", GROUP=\"plugdev\", \
+             TAG+=\"uaccess\"\n"
+        ));
+    }
+    rules
+}
+
+/// Whether libusb could be initialized, `libusb` is its description or the error.
+pub fn check_libusb(libusb: Result<String, String>, os: &str) -> CheckResult {
+    const NAME: &str = "libusb";
+    match libusb {
+        Ok(description) => CheckResult::new(NAME, CheckStatus::Ok, description),
+        Err(err) => {
+            let install = match os {
+                "linux" => {
+                    "Install libusb, e.g. `sudo apt install libusb-1.0-0` on Debian and Ubuntu"
+                }
+                "macos" => "Install libusb with `brew install libusb`",
+                _ => "Install libusb 1.0",
+            };
+            CheckResult::new(NAME, CheckStatus::Failed, err).with_steps([
+                install.to_string(),
+                "In a container, pass the host's /dev/bus/usb through to it".to_string(),
+            ])
+        }
+    }
+}
+
+/// Whether any device looks like a uf2 bootloader.
+pub fn check_devices_visible(devices: &[ProbedDevice]) -> CheckResult {
+    const NAME: &str = "uf2 devices";
+    let boards: Vec<String> = devices
+        .iter()
+        .filter(|device| device.board.is_some())
+        .map(ProbedDevice::summary)
+        .collect();
+    if !boards.is_empty() {
+        return CheckResult::new(
+            NAME,
+            CheckStatus::Ok,
+            format!("Found {}", boards.join(", ")),
+        );
+    }
+
+    let mut steps = vec![
+        "Put the board into its bootloader, e.g. hold BOOTSEL while plugging in an RP2040 or \
+         RP2350, or double tap reset on an Adafruit board"
+            .to_string(),
+        "Try another cable, charge-only cables don't carry data".to_string(),
+        "Check that --usb-root doesn't leave the board out".to_string(),
+    ];
+    if devices.is_empty() {
+        return CheckResult::new(
+            NAME,
+            CheckStatus::Failed,
+            "No USB mass storage device found",
+        )
+        .with_steps(steps);
+    }
+
+    steps.push(
+        "For a board elf2flash doesn't know, pass --family, see `elf2flash boards` for the known \
+         ones"
+            .to_string(),
+    );
+    CheckResult::new(
+        NAME,
+        CheckStatus::Warning,
+        format!(
+            "None of the {} USB mass storage device(s) is recognized as a uf2 board",
+            devices.len()
+        ),
+    )
+    .with_steps(steps)
+}
+
+/// Whether the devices can be opened for raw USB access. Devices Windows binds to another driver
+/// are left to [`check_windows_driver`].
+pub fn check_device_access(devices: &[ProbedDevice], os: &str) -> CheckResult {
+    const NAME: &str = "device access";
+    let checked: Vec<&ProbedDevice> = devices
+        .iter()
+        .filter(|device| device.open != OpenOutcome::NotSupported)
+        .collect();
+    if checked.is_empty() {
+        return CheckResult::new(NAME, CheckStatus::Skipped, "No device to open");
+    }
+
+    let denied: Vec<&ProbedDevice> = checked
+        .iter()
+        .copied()
+        .filter(|device| device.open == OpenOutcome::AccessDenied)
+        .collect();
+    let failed: Vec<String> = checked
+        .iter()
+        .filter_map(|device| match &device.open {
+            OpenOutcome::Failed(err) => Some(format!("{}: {err}", device.summary())),
+            _ => None,
+        })
+        .collect();
+    if denied.is_empty() && failed.is_empty() {
+        return CheckResult::new(
+            NAME,
+            CheckStatus::Ok,
+            format!("Opened {} device(s)", checked.len()),
+        );
+    }
+
+    let mut problems: Vec<String> = denied
+        .iter()
+        .map(|device| format!("{}: permission denied", device.summary()))
+        .collect();
+    problems.extend(failed);
+
+    let mut steps = Vec::new();
+    if !denied.is_empty() {
+        if os == "linux" {
+            let ids: Vec<(u16, u16)> = denied
+                .iter()
+                .map(|device| (device.vendor_id, device.product_id))
+                .collect();
+            steps.push(format!(
+                "Save these udev rules as {UDEV_RULES_PATH}:\n{}",
+                udev_rules(&ids).trim_end()
+            ));
+            steps.push(
+                "Load them with `sudo udevadm control --reload-rules && sudo udevadm trigger`, \
+                 then plug the board in again"
+                    .to_string(),
+            );
+        } else {
+            steps.push("Close other programs that may hold the device".to_string());
+        }
+    }
+    steps.push(
+        "Until then, `deploy --backend mount` copies the uf2 file onto the volume the OS mounted \
+         instead"
+            .to_string(),
+    );
+
+    CheckResult::new(NAME, CheckStatus::Failed, problems.join(", ")).with_steps(steps)
+}
+
+/// Whether the user is in the groups of [`GROUPS`] that exist, on Linux. `member_of` are the
+/// groups of the user, `exists` tells whether the system has a group.
+pub fn check_groups(
+    os: &str,
+    is_root: bool,
+    member_of: &[String],
+    exists: impl Fn(&str) -> bool,
+) -> CheckResult {
+    const NAME: &str = "groups";
+    if os != "linux" {
+        return CheckResult::new(NAME, CheckStatus::Skipped, "Only checked on Linux");
+    }
+    if is_root {
+        return CheckResult::new(NAME, CheckStatus::Ok, "Running as root");
+    }
+
+    let missing: Vec<(&str, &str)> = GROUPS
+        .into_iter()
+        .filter(|(group, _)| exists(group) && !member_of.iter().any(|member| member == group))
+        .collect();
+    if missing.is_empty() {
+        return CheckResult::new(
+            NAME,
+            CheckStatus::Ok,
+            "In the groups giving access to the devices",
+        );
+    }
+
+    let message = missing
+        .iter()
+        .map(|(group, purpose)| format!("Not in {group}, which gives access to {purpose}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let groups: Vec<&str> = missing.iter().map(|(group, _)| *group).collect();
+    CheckResult::new(NAME, CheckStatus::Warning, message).with_steps([format!(
+        "Join them with `sudo usermod -aG {} $USER`, then log out and back in",
+        groups.join(",")
+    )])
+}
+
+/// Whether Windows lets libusb open the mass storage interface, which needs it bound to WinUSB
+/// instead of the USBSTOR driver it gets by default.
+pub fn check_windows_driver(os: &str, devices: &[ProbedDevice]) -> CheckResult {
+    const NAME: &str = "windows driver";
+    if os != "windows" {
+        return CheckResult::new(NAME, CheckStatus::Skipped, "Only checked on Windows");
+    }
+
+    let usbstor: Vec<&ProbedDevice> = devices
+        .iter()
+        .filter(|device| device.open == OpenOutcome::NotSupported)
+        .collect();
+    if usbstor.is_empty() {
+        return CheckResult::new(
+            NAME,
+            CheckStatus::Ok,
+            "No device needs another driver for raw USB access",
+        );
+    }
+
+    let mut steps = vec![
+        "Nothing to do for deploy, it copies the uf2 file onto the drive letter of the device \
+         instead"
+            .to_string(),
+        "For raw USB access, like backups and --backend raw, bind the mass storage interface to \
+         WinUSB with Zadig (https://zadig.akeo.ie)"
+            .to_string(),
+    ];
+    if usbstor
+        .iter()
+        .any(|device| matches!(device.board.as_deref(), Some(board) if board.starts_with("rp2")))
+    {
+        steps.push(
+            "RP2040 and RP2350 boards can be flashed with --backend picoboot without changing \
+             the driver"
+                .to_string(),
+        );
+    }
+    let devices: Vec<String> = usbstor.iter().map(|device| device.summary()).collect();
+    CheckResult::new(
+        NAME,
+        CheckStatus::Warning,
+        format!(
+            "The mass storage interface of {} is bound to USBSTOR, not WinUSB",
+            devices.join(", ")
+        ),
+    )
+    .with_steps(steps)
+}
+
+/// How opening a device failed, from the error of [`usbh_fatfs::StorageUsb::open`].
+fn open_outcome(err: StorageUsbError) -> OpenOutcome {
+    match err {
+        StorageUsbError::UsbMassStorageError(UsbMassStorageError::FailedToOpenUsbDevice(
+            rusb::Error::Access,
+        )) => OpenOutcome::AccessDenied,
+        StorageUsbError::UsbMassStorageError(UsbMassStorageError::FailedToOpenUsbDevice(
+            rusb::Error::NotSupported,
+        )) => OpenOutcome::NotSupported,
+        err => OpenOutcome::Failed(format!("{:#}", anyhow::Error::from(err))),
+    }
+}
+
+/// Try to open every plugged in USB mass storage device, releasing it again without a reset.
+fn probe_devices() -> Result<Vec<ProbedDevice>> {
+    Ok(plugged_in_devices()?
+        .into_iter()
+        .map(|(usb, board, mut storage_usb)| {
+            let open = match storage_usb.open() {
+                Ok(_) => OpenOutcome::Opened,
+                Err(err) => open_outcome(err),
+            };
+            storage_usb.release();
+            ProbedDevice {
+                vendor_id: usb.vendor_id,
+                product_id: usb.product_id,
+                board: board.map(|board| board.board_name().into_owned()),
+                open,
+            }
+        })
+        .collect())
+}
+
+/// The groups of the user, and whether it is root.
+#[cfg(target_os = "linux")]
+fn user_groups() -> (Vec<String>, bool) {
+    use nix::unistd::{Group, geteuid, getgroups};
+
+    let groups = getgroups()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|gid| Group::from_gid(gid).ok().flatten())
+        .map(|group| group.name)
+        .collect();
+    (groups, geteuid().is_root())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn user_groups() -> (Vec<String>, bool) {
+    (Vec::new(), false)
+}
+
+#[cfg(target_os = "linux")]
+fn group_exists(name: &str) -> bool {
+    nix::unistd::Group::from_name(name).is_ok_and(|group| group.is_some())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn group_exists(_name: &str) -> bool {
+    false
+}
+
+/// `checks` as text, a line for every check followed by its remediation steps.
+pub fn render_checks(checks: &[CheckResult]) -> String {
+    let mut text = String::new();
+    for check in checks {
+        text.push_str(&format!(
+            "{:<10}{}: {}\n",
+            format!("[{}]", check.status),
+            check.name,
+            check.message
+        ));
+        for step in &check.remediation {
+            let mut lines = step.lines();
+            if let Some(first) = lines.next() {
+                text.push_str(&format!("    - {first}\n"));
+            }
+            for line in lines {
+                text.push_str(&format!("      {line}\n"));
+            }
+        }
+    }
+    text
+}
+
+/// Check what elf2flash needs to flash the connected devices, and print what to change.
+pub fn doctor(args: DoctorArgs) -> Result<()> {
+    let os = env::consts::OS;
+    let libusb = usb_session()
+        .map(|_| environment().to_string())
+        .map_err(|err| format!("{err:#}"));
+    let devices = match libusb {
+        Ok(_) => Some(probe_devices()?),
+        Err(_) => None,
+    };
+
+    let mut checks = vec![check_libusb(libusb, os)];
+    match &devices {
+        Some(devices) => {
+            checks.push(check_devices_visible(devices));
+            checks.push(check_device_access(devices, os));
+        }
+        None => checks.extend(
+            ["uf2 devices", "device access"]
+                .map(|name| CheckResult::new(name, CheckStatus::Skipped, "Needs libusb")),
+        ),
+    }
+    let (groups, is_root) = user_groups();
+    checks.push(check_groups(os, is_root, &groups, group_exists));
+    checks.push(check_windows_driver(
+        os,
+        devices.as_deref().unwrap_or_default(),
+    ));
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == CheckStatus::Failed)
+        .count();
+    let error = (failed > 0).then(|| format!("{failed} check(s) failed"));
+
+    if output::is_json() {
+        output::emit("doctor", Some(&checks), error.as_deref())?;
+    } else if args.json {
+        println!("{}", serde_json::to_string_pretty(&checks)?);
+    } else {
+        print!("{}", render_checks(&checks));
+    }
+
+    if let Some(error) = error {
+        bail!(error);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(board: Option<&str>, product_id: u16, open: OpenOutcome) -> ProbedDevice {
+        ProbedDevice {
+            vendor_id: 0x2e8a,
+            product_id,
+            board: board.map(str::to_string),
+            open,
+        }
+    }
+
+    #[test]
+    fn udev_rules_list_each_device_once() {
+        assert_eq!(
+            udev_rules(&[(0x2e8a, 0x000f), (0x2e8a, 0x0003), (0x2e8a, 0x000f)]),
+            "# uf2 bootloaders flashed by elf2flash\n\
+             SUBSYSTEM==\"usb\", ATTRS{idVendor}==\"2e8a\", ATTRS{idProduct}==\"0003\", \
+             MODE=\"0660\", GROUP=\"plugdev\", TAG+=\"uaccess\"\n\
+             SUBSYSTEM==\"usb\", ATTRS{idVendor}==\"2e8a\", ATTRS{idProduct}==\"000f\", \
+             MODE=\"0660\", GROUP=\"plugdev\", TAG+=\"uaccess\"\n"
+        );
+    }
+
+    #[test]
+    fn devices_are_looked_for() {
+        assert_eq!(check_devices_visible(&[]).status, CheckStatus::Failed);
+
+        let generic = device(None, 0x1234, OpenOutcome::Opened);
+        let check = check_devices_visible(std::slice::from_ref(&generic));
+        assert_eq!(check.status, CheckStatus::Warning);
+        assert!(
+            check
+                .remediation
+                .iter()
+                .any(|step| step.contains("--family"))
+        );
+
+        let check = check_devices_visible(&[
+            generic,
+            device(Some("rp2040"), 0x0003, OpenOutcome::AccessDenied),
+        ]);
+        assert_eq!(check.status, CheckStatus::Ok);
+        assert_eq!(check.message, "Found rp2040 2e8a:0003");
+    }
+
+    #[test]
+    fn denied_devices_get_udev_rules_on_linux() {
+        let devices = [
+            device(Some("rp2040"), 0x0003, OpenOutcome::AccessDenied),
+            device(Some("rp2350"), 0x000f, OpenOutcome::Opened),
+        ];
+        let check = check_device_access(&devices, "linux");
+        assert_eq!(check.status, CheckStatus::Failed);
+        assert_eq!(check.message, "rp2040 2e8a:0003: permission denied");
+        assert!(check.remediation[0].contains(UDEV_RULES_PATH));
+        assert!(check.remediation[0].contains("ATTRS{idProduct}==\"0003\""));
+        assert!(!check.remediation[0].contains("ATTRS{idProduct}==\"000f\""));
+        assert!(
+            check
+                .remediation
+                .iter()
+                .any(|step| step.contains("--backend mount"))
+        );
+
+        let check = check_device_access(&devices, "macos");
+        assert!(!check.remediation.iter().any(|step| step.contains("udev")));
+
+        let check = check_device_access(&devices[1..], "linux");
+        assert_eq!(
+            (check.status, check.message.as_str()),
+            (CheckStatus::Ok, "Opened 1 device(s)")
+        );
+        assert_eq!(
+            check_device_access(&[], "linux").status,
+            CheckStatus::Skipped
+        );
+    }
+
+    #[test]
+    fn missing_groups_are_only_checked_on_linux() {
+        let member_of = ["users".to_string(), "plugdev".to_string()];
+        let check = check_groups("linux", false, &member_of, |_| true);
+        assert_eq!(check.status, CheckStatus::Warning);
+        assert_eq!(
+            check.message,
+            "Not in dialout, which gives access to the serial ports of --serial and --reboot"
+        );
+        assert_eq!(
+            check.remediation,
+            ["Join them with `sudo usermod -aG dialout $USER`, then log out and back in"]
+        );
+
+        // Arch has no plugdev or dialout group
+        let check = check_groups("linux", false, &[], |_| false);
+        assert_eq!(check.status, CheckStatus::Ok);
+        assert_eq!(
+            check_groups("linux", true, &[], |_| true).status,
+            CheckStatus::Ok
+        );
+        assert_eq!(
+            check_groups("macos", false, &[], |_| true).status,
+            CheckStatus::Skipped
+        );
+    }
+
+    #[test]
+    fn usbstor_devices_are_pointed_to_winusb() {
+        let devices = [
+            device(Some("rp2040"), 0x0003, OpenOutcome::NotSupported),
+            device(None, 0x1234, OpenOutcome::Opened),
+        ];
+        let check = check_windows_driver("windows", &devices);
+        assert_eq!(check.status, CheckStatus::Warning);
+        assert_eq!(
+            check.message,
+            "The mass storage interface of rp2040 2e8a:0003 is bound to USBSTOR, not WinUSB"
+        );
+        assert!(check.remediation.iter().any(|step| step.contains("Zadig")));
+        assert!(
+            check
+                .remediation
+                .iter()
+                .any(|step| step.contains("--backend picoboot"))
+        );
+
+        // Left to the driver check, not reported as an access failure
+        assert_eq!(
+            check_device_access(&devices, "windows").status,
+            CheckStatus::Ok
+        );
+        assert_eq!(
+            check_windows_driver("windows", &devices[1..]).status,
+            CheckStatus::Ok
+        );
+        assert_eq!(
+            check_windows_driver("linux", &devices).status,
+            CheckStatus::Skipped
+        );
+    }
+
+    #[test]
+    fn checks_are_rendered_with_their_steps() {
+        let checks = [
+            CheckResult::new("libusb", CheckStatus::Ok, "libusb 1.0.27"),
+            CheckResult::new("device access", CheckStatus::Failed, "denied")
+                .with_steps(["first\nsecond".to_string(), "third".to_string()]),
+        ];
+        assert_eq!(
+            render_checks(&checks),
+            "[ok]      libusb: libusb 1.0.27\n\
+             [failed]  device access: denied\n\
+             \x20   - first\n\
+             \x20     second\n\
+             \x20   - third\n"
+        );
+        assert_eq!(
+            check_libusb(Err("no context".to_string()), "macos").remediation[0],
+            "Install libusb with `brew install libusb`"
+        );
+    }
+}
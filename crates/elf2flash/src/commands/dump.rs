@@ -0,0 +1,478 @@
+use std::{
+    fmt, fs,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Context, Result};
+use clap::Args;
+use elf2flash_core::{
+    ProgressReporter,
+    progress::ProgressWrite,
+    warnings::{WarningCode, Warnings},
+};
+use fatfs::{FileSystem, FsOptions, ReadWriteSeek};
+use thiserror::Error;
+use usbh_fatfs::{
+    FatPartition, PartitionView, list_dir, read_file, rusb::UsbContext,
+    usbh_scsi::storage::block_device::UsbBlockDevice,
+};
+
+use crate::{
+    commands::deploy::{
+        report::device_reports,
+        select::{DeviceSelector, check_missing_selectors, select_devices},
+        to_usb::get_plugged_in_boards,
+    },
+    num_parser,
+    progress_bar::ProgressBarReporter,
+};
+
+/// Files and raw dumps larger than this are refused unless `--force` is passed.
+pub const MAX_DUMP_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Number of blocks requested per `READ(10)` during a raw dump
+const RAW_CHUNK_BLOCKS: u32 = 32;
+
+#[derive(Args, Debug)]
+pub struct DumpArgs {
+    /// Directory to write the dumps to, every device gets its own folder inside it
+    #[clap(short, long, value_name = "DIR")]
+    pub output: PathBuf,
+
+    /// Only dump the matching devices, same selectors as `deploy --device`
+    #[clap(long = "device", value_name = "SELECTOR", value_delimiter = ',')]
+    pub devices: Vec<DeviceSelector>,
+
+    /// Also dump the raw sectors <START>..<END> (end exclusive) into raw-<START>-<END>.bin
+    #[clap(long, value_name = "START..END")]
+    pub raw: Option<LbaRange>,
+
+    /// Dump files and raw ranges larger than 64MiB
+    #[clap(long)]
+    pub force: bool,
+}
+
+/// A range of logical block addresses, `start` inclusive and `end` exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LbaRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum LbaRangeParseError {
+    #[error("expected <start>..<end>")]
+    MissingSeparator,
+    #[error("invalid block address '{0}'")]
+    InvalidLba(String),
+    #[error("the range {start}..{end} is empty")]
+    Empty { start: u32, end: u32 },
+}
+
+impl FromStr for LbaRange {
+    type Err = LbaRangeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once("..")
+            .ok_or(LbaRangeParseError::MissingSeparator)?;
+        let parse = |lba: &str| {
+            num_parser(lba.trim()).map_err(|_| LbaRangeParseError::InvalidLba(lba.to_string()))
+        };
+        let (start, end) = (parse(start)?, parse(end)?);
+
+        if start >= end {
+            return Err(LbaRangeParseError::Empty { start, end });
+        }
+
+        Ok(Self { start, end })
+    }
+}
+
+impl fmt::Display for LbaRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum DumpError {
+    #[error("refusing to dump raw sectors of a non-removable disk")]
+    NotRemovable,
+    #[error("sectors {range} are out of bounds, the device has {block_count} blocks")]
+    OutOfBounds { range: LbaRange, block_count: u64 },
+    #[error(
+        "the dump would be {size} bytes, more than the {max_size} byte limit (pass --force to dump it anyway)"
+    )]
+    TooLarge { size: u64, max_size: u64 },
+    #[error("failed to read sectors")]
+    Io(#[from] io::Error),
+}
+
+/// Block level read access, so raw dumps don't depend on a real USB device.
+pub trait BlockRead {
+    fn block_size(&self) -> u32;
+    fn block_count(&self) -> u64;
+    /// Read `count` blocks starting at `lba`, `buf` is exactly `count` blocks long
+    fn read_blocks(&mut self, lba: u32, count: u32, buf: &mut [u8]) -> io::Result<()>;
+}
+
+impl<T: UsbContext> BlockRead for UsbBlockDevice<'_, T> {
+    fn block_size(&self) -> u32 {
+        UsbBlockDevice::block_size(self)
+    }
+
+    fn block_count(&self) -> u64 {
+        UsbBlockDevice::block_count(self)
+    }
+
+    fn read_blocks(&mut self, lba: u32, count: u32, buf: &mut [u8]) -> io::Result<()> {
+        UsbBlockDevice::read_blocks(self, 0x28, lba, count as u16, buf)
+    }
+}
+
+/// Check that the raw sectors in `range` of `device` may be dumped, returning the size of the
+/// dump in bytes.
+///
+/// Non-removable disks are refused, so a wrongly selected device can't be a hard drive. The range
+/// must lie within the device, and be at most `max_size` bytes if a limit is given.
+pub fn check_raw(
+    device: &impl BlockRead,
+    range: LbaRange,
+    removable: bool,
+    max_size: Option<u64>,
+) -> Result<u64, DumpError> {
+    if !removable {
+        return Err(DumpError::NotRemovable);
+    }
+
+    let block_count = device.block_count();
+    if range.end as u64 > block_count {
+        return Err(DumpError::OutOfBounds { range, block_count });
+    }
+
+    let block_size = device.block_size() as u64;
+    let size = (range.end - range.start) as u64 * block_size;
+    if let Some(max_size) = max_size
+        && size > max_size
+    {
+        return Err(DumpError::TooLarge { size, max_size });
+    }
+
+    Ok(size)
+}
+
+/// Copy the raw sectors in `range` of `device` into `output`, returning the number of bytes
+/// dumped.
+///
+/// The dump is checked with [`check_raw`] before anything is read.
+pub fn dump_raw(
+    device: &mut impl BlockRead,
+    range: LbaRange,
+    removable: bool,
+    max_size: Option<u64>,
+    output: impl Write,
+    mut progress: impl ProgressReporter,
+) -> Result<u64, DumpError> {
+    let size = check_raw(device, range, removable, max_size)?;
+    let block_size = device.block_size() as u64;
+
+    let mut output = ProgressWrite::new(output, &mut progress, size as usize);
+    let mut buf = Vec::new();
+    let mut lba = range.start;
+
+    while lba < range.end {
+        let count = RAW_CHUNK_BLOCKS.min(range.end - lba);
+        buf.resize(count as usize * block_size as usize, 0);
+
+        device.read_blocks(lba, count, &mut buf)?;
+        output.write_all(&buf)?;
+
+        lba += count;
+    }
+
+    output.finish()?;
+
+    Ok(size)
+}
+
+/// Copy every file in the root directory of `fatfs` into `dest`, returning the copied names.
+///
+/// Files that can't be read or are larger than `max_file_size` are skipped with a warning.
+pub fn dump_files<T: ReadWriteSeek>(
+    fatfs: &FileSystem<T>,
+    dest: &Path,
+    max_file_size: Option<u64>,
+    warnings: &mut Warnings,
+) -> Result<Vec<String>> {
+    let entries = list_dir(fatfs, "").context("Failed to list the bootloader volume")?;
+    let mut dumped = Vec::new();
+
+    for entry in entries.into_iter().filter(|entry| !entry.is_dir) {
+        let data = match read_file(fatfs, &entry.name, max_file_size)
+            .with_context(|| format!("Skipping dump of {}", entry.name))
+        {
+            Ok(data) => data,
+            Err(err) => {
+                warnings.push(WarningCode::DumpIncomplete, format!("{err:#}"));
+                continue;
+            }
+        };
+
+        let path = dest.join(&entry.name);
+        fs::write(&path, &data)
+            .with_context(|| format!("Failed to write dumped file {}", path.display()))?;
+
+        dumped.push(entry.name);
+    }
+
+    Ok(dumped)
+}
+
+pub fn dump(args: DumpArgs) -> Result<()> {
+    let DumpArgs {
+        output,
+        devices,
+        raw,
+        force,
+    } = args;
+
+    let max_size = (!force).then_some(MAX_DUMP_SIZE);
+    let mut warnings = Warnings::new();
+
+    log::info!("Getting plugged in boards\n");
+
+    let mut plugged_in_boards = get_plugged_in_boards(&mut warnings)?;
+
+    if plugged_in_boards.is_empty() {
+        log::warn!("No uf2 devices found.");
+        return Ok(());
+    }
+
+    let reports = device_reports(&mut plugged_in_boards, &devices);
+
+    let selection = (!devices.is_empty()).then(|| select_devices(&reports, &devices));
+    if let Some(selection) = &selection {
+        check_missing_selectors(selection, &devices, &reports, false, &mut warnings)?;
+    }
+
+    for ((_usb, _board, mut storage_usb), report) in plugged_in_boards.into_iter().zip(&reports) {
+        if selection
+            .as_ref()
+            .is_some_and(|s| !s.is_selected(report.index))
+        {
+            continue;
+        }
+
+        let partitions = match FatPartition::list_partitions(&mut storage_usb)
+            .with_context(|| format!("Failed to list the partitions of {}", report.summary()))
+        {
+            Ok(partitions) => partitions,
+            Err(err) => {
+                warnings.push(WarningCode::DumpIncomplete, format!("{err:#}"));
+                Vec::new()
+            }
+        };
+
+        let opened = storage_usb.open()?;
+        let removable = opened.inquiry()?.is_removable;
+        let mut block_device = opened.block_device()?;
+
+        // Refuse a bad raw range before anything is written to the output directory
+        if let Some(range) = raw {
+            check_raw(&block_device, range, removable, max_size)?;
+        }
+
+        let dest = output.join(format!(
+            "{}-{}",
+            report.index,
+            report.board_name.as_deref().unwrap_or("generic_uf2")
+        ));
+        fs::create_dir_all(&dest)
+            .with_context(|| format!("Failed to create dump directory {}", dest.display()))?;
+
+        log::info!("Dumping {} to {}", report.summary(), dest.display());
+
+        for (i, partition) in partitions.iter().enumerate() {
+            let partition_dest = if partitions.len() == 1 {
+                dest.clone()
+            } else {
+                dest.join(format!("partition-{i}"))
+            };
+            fs::create_dir_all(&partition_dest)?;
+
+            let view =
+                PartitionView::new(&mut block_device, partition.first_byte, partition.length)?;
+            let fatfs = FileSystem::new(view, FsOptions::new())?;
+
+            let dumped = dump_files(&fatfs, &partition_dest, max_size, &mut warnings)?;
+            log::info!(
+                "Dumped {} file(s) from {} to {}",
+                dumped.len(),
+                partition.volume_label.trim(),
+                partition_dest.display()
+            );
+        }
+
+        if let Some(range) = raw {
+            let path = dest.join(format!("raw-{}-{}.bin", range.start, range.end));
+            let file = BufWriter::new(
+                File::create(&path)
+                    .with_context(|| format!("Failed to create {}", path.display()))?,
+            );
+
+            let size = dump_raw(
+                &mut block_device,
+                range,
+                removable,
+                max_size,
+                file,
+                ProgressBarReporter::new(),
+            )?;
+            log::info!(
+                "Dumped {size} bytes of sectors {range} to {}",
+                path.display()
+            );
+        }
+    }
+
+    if let Some(summary) = warnings.summary() {
+        log::warn!("{summary}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FAT_IMAGE_SIZE, fat_image};
+    use elf2flash_core::NoProgress;
+
+    /// A disk image in memory with 512 byte blocks
+    struct MemoryDisk(Vec<u8>);
+
+    impl BlockRead for MemoryDisk {
+        fn block_size(&self) -> u32 {
+            512
+        }
+
+        fn block_count(&self) -> u64 {
+            self.0.len() as u64 / 512
+        }
+
+        fn read_blocks(&mut self, lba: u32, count: u32, buf: &mut [u8]) -> io::Result<()> {
+            let start = lba as usize * 512;
+            buf.copy_from_slice(&self.0[start..start + count as usize * 512]);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn parses_lba_ranges() {
+        assert_eq!("0..64".parse(), Ok(LbaRange { start: 0, end: 64 }));
+        assert_eq!("0x10..0x20".parse(), Ok(LbaRange { start: 16, end: 32 }));
+        assert_eq!(
+            "64".parse::<LbaRange>(),
+            Err(LbaRangeParseError::MissingSeparator)
+        );
+        assert_eq!(
+            "a..2".parse::<LbaRange>(),
+            Err(LbaRangeParseError::InvalidLba("a".to_string()))
+        );
+        assert_eq!(
+            "8..8".parse::<LbaRange>(),
+            Err(LbaRangeParseError::Empty { start: 8, end: 8 })
+        );
+    }
+
+    #[test]
+    fn dumps_files_from_volume() {
+        let mut image = fat_image(&[
+            ("INFO_UF2.TXT", b"UF2 Bootloader v3.0\n"),
+            ("INDEX.HTM", b"<html></html>"),
+            ("CURRENT.UF2", &[0x55; 4096]),
+        ]);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+        let dest = tempfile::tempdir().unwrap();
+
+        let mut warnings = Warnings::new();
+        let dumped = dump_files(&fatfs, dest.path(), None, &mut warnings).unwrap();
+        assert_eq!(dumped, ["INFO_UF2.TXT", "INDEX.HTM", "CURRENT.UF2"]);
+        assert!(warnings.is_empty());
+        assert_eq!(
+            fs::read(dest.path().join("INFO_UF2.TXT")).unwrap(),
+            b"UF2 Bootloader v3.0\n"
+        );
+        assert_eq!(
+            fs::read(dest.path().join("CURRENT.UF2")).unwrap(),
+            [0x55; 4096]
+        );
+
+        let dest = tempfile::tempdir().unwrap();
+        let dumped = dump_files(&fatfs, dest.path(), Some(1024), &mut warnings).unwrap();
+        assert_eq!(dumped, ["INFO_UF2.TXT", "INDEX.HTM"]);
+        assert!(warnings.contains(WarningCode::DumpIncomplete));
+    }
+
+    #[test]
+    fn dumps_raw_sectors() {
+        let image = fat_image(&[("INFO_UF2.TXT", b"info")]).into_inner();
+        let mut disk = MemoryDisk(image.clone());
+
+        let mut output = Vec::new();
+        let range = LbaRange { start: 1, end: 100 };
+        let size = dump_raw(&mut disk, range, true, None, &mut output, NoProgress).unwrap();
+
+        assert_eq!(size, 99 * 512);
+        assert_eq!(output, image[512..100 * 512]);
+    }
+
+    #[test]
+    fn raw_dump_safety_checks() {
+        let mut disk = MemoryDisk(fat_image(&[]).into_inner());
+        let blocks = (FAT_IMAGE_SIZE / 512) as u32;
+        let all = LbaRange {
+            start: 0,
+            end: blocks,
+        };
+
+        assert!(matches!(
+            check_raw(&disk, all, false, None),
+            Err(DumpError::NotRemovable)
+        ));
+        assert!(matches!(
+            dump_raw(&mut disk, all, false, None, io::sink(), NoProgress),
+            Err(DumpError::NotRemovable)
+        ));
+
+        let past_end = LbaRange {
+            start: 0,
+            end: blocks + 1,
+        };
+        assert!(matches!(
+            dump_raw(&mut disk, past_end, true, None, io::sink(), NoProgress),
+            Err(DumpError::OutOfBounds { block_count, .. }) if block_count == blocks as u64
+        ));
+
+        assert!(matches!(
+            dump_raw(&mut disk, all, true, Some(1024 * 1024), io::sink(), NoProgress),
+            Err(DumpError::TooLarge { size, .. }) if size == FAT_IMAGE_SIZE as u64
+        ));
+        assert!(matches!(
+            check_raw(&disk, past_end, true, None),
+            Err(DumpError::OutOfBounds { .. })
+        ));
+        assert_eq!(
+            check_raw(&disk, all, true, None).unwrap(),
+            FAT_IMAGE_SIZE as u64
+        );
+        assert_eq!(
+            dump_raw(&mut disk, all, true, None, io::sink(), NoProgress).unwrap(),
+            FAT_IMAGE_SIZE as u64
+        );
+    }
+}
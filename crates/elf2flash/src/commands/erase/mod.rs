@@ -0,0 +1,409 @@
+//! `erase`, for boards wedged by firmware that keeps them from being flashed: a program loaded
+//! into RAM erases the whole flash and reboots into the bootloader, like the flash_nuke example
+//! of pico-examples. It is written the way `deploy` writes firmware.
+//!
+//! The programs aren't the flash_nuke binaries of pico-examples, they are assembled from
+//! flash_nuke.S next to this file and call the same bootrom functions. The binaries are what
+//! flash_nuke.S assembles to with the commands in its header, and the tests check what can be
+//! checked without a board: the RP2350 `IMAGE_DEF` block, the bootrom functions called and the
+//! eyecatcher page. Whether they erase a board is only checked by `hardware_erases_the_flash`,
+//! which needs a board and is ignored otherwise. Until it has passed for a chip, `erase` refuses
+//! to write that chip's program without `--unverified`, and points at the flash_nuke.uf2 of
+//! pico-examples instead.
+
+use std::path::PathBuf;
+
+use anyhow::{Result, bail};
+use clap::{Args, ValueHint};
+use elf2flash_core::{
+    Uf2BlockIterator, Uf2Options,
+    boards::{BoardInfo, BoardIter, RP2040, RP2350},
+};
+
+use crate::{
+    BoardValueParser,
+    cancel::CancellationToken,
+    commands::deploy::{DeployArgs, deploy_input, input::DeployInput, select::DeviceSelector},
+    interactive,
+};
+
+/// Address the erase programs are loaded at and run from, the start of RAM
+pub const NUKE_BASE: u32 = 0x2000_0000;
+
+/// An erase program, assembled from flash_nuke.S.
+struct NukeProgram {
+    bytes: &'static [u8],
+    /// Whether `hardware_erases_the_flash` has passed with the program on a board of its chip
+    hardware_verified: bool,
+}
+
+const NUKE_RP2040: NukeProgram = NukeProgram {
+    bytes: include_bytes!("flash_nuke_rp2040.bin"),
+    hardware_verified: false,
+};
+const NUKE_RP2350: NukeProgram = NukeProgram {
+    bytes: include_bytes!("flash_nuke_rp2350.bin"),
+    hardware_verified: false,
+};
+
+#[derive(Args, Debug)]
+pub struct EraseArgs {
+    /// Board to erase: rp2040, rp2350, or a board of the boards file with the family of either
+    #[clap(short, long, value_parser = BoardValueParser, hide_possible_values = true)]
+    pub board: String,
+
+    /// Erase without asking first, needed when there is no terminal to ask on
+    #[clap(short, long)]
+    pub yes: bool,
+
+    /// Only erase the matching devices, same selectors as `deploy --device`
+    #[clap(long = "device", value_name = "SELECTOR", value_delimiter = ',')]
+    pub devices: Vec<DeviceSelector>,
+
+    /// Don't fail when a --device selector matches no device
+    #[clap(long, requires = "devices")]
+    pub allow_missing: bool,
+
+    /// Wait up to this many seconds (30 without a number) for a device to erase to show up
+    #[clap(long, value_name = "SECS", num_args = 0..=1, require_equals = true)]
+    pub wait: Option<Option<u64>>,
+
+    /// Reboot running boards into BOOTSEL first, then wait for them like --wait
+    #[clap(long)]
+    pub reboot: bool,
+
+    /// Write the erase program even though it hasn't been verified on a board of this chip yet
+    #[clap(long)]
+    pub unverified: bool,
+
+    /// Write the erase program onto the FAT volume in this image file instead of the connected
+    /// devices
+    #[clap(long, value_name = "IMAGE", value_hint = ValueHint::FilePath, hide = true)]
+    pub mock_volume: Option<PathBuf>,
+}
+
+/// The erase program of `board`, for the boards that have one.
+fn nuke_program(board: &dyn BoardInfo) -> Result<&'static NukeProgram> {
+    if board.family_id() == RP2040.family_id() {
+        Ok(&NUKE_RP2040)
+    } else if board.family_id() == RP2350.family_id() {
+        Ok(&NUKE_RP2350)
+    } else {
+        bail!(
+            "Erasing isn't supported for board '{}', only for RP2040 and RP2350 (Arm) boards",
+            board.board_name()
+        );
+    }
+}
+
+/// The uf2 file loading the erase program of `board` into RAM, for the boards that have one.
+pub fn nuke_uf2(board: &dyn BoardInfo) -> Result<Vec<u8>> {
+    let program = nuke_program(board)?.bytes;
+    let options = Uf2Options {
+        not_main_flash: true,
+        ..Default::default()
+    };
+    let image = [(u64::from(NUKE_BASE), program.to_vec())];
+    let blocks = Uf2BlockIterator::from_image(&image, board, &options)?;
+    Ok(blocks.collect::<Result<Vec<_>, _>>()?.concat())
+}
+
+/// Ask before erasing unless `--yes` was given. Without a terminal, only `--yes` erases.
+fn confirm(board: &str, yes: bool) -> Result<()> {
+    if yes {
+        return Ok(());
+    }
+    if !interactive::is_interactive() {
+        bail!(
+            "Erasing can't be undone and there is no terminal to confirm on, pass --yes to erase"
+        );
+    }
+
+    if !interactive::ask(&format!(
+        "Erase the whole flash of the connected {board} boards?"
+    ))? {
+        bail!("Not erasing, nothing was written");
+    }
+    Ok(())
+}
+
+pub fn erase(args: EraseArgs) -> Result<()> {
+    let EraseArgs {
+        board,
+        yes,
+        devices,
+        allow_missing,
+        wait,
+        reboot,
+        unverified,
+        mock_volume,
+    } = args;
+
+    let target = BoardIter::find_by_name(&board)
+        .expect("Should be impossible for unrecognized board to appear here");
+    if !nuke_program(target.as_ref())?.hardware_verified && !unverified {
+        bail!(
+            "The erase program for board '{board}' hasn't been verified on a board yet, deploy the \
+             flash_nuke.uf2 built by pico-examples instead, or pass --unverified to write it anyway"
+        );
+    }
+    let input = DeployInput::Uf2 {
+        data: nuke_uf2(target.as_ref())?,
+        families: vec![target.family_id()],
+    };
+    confirm(&board, yes)?;
+
+    let cancel = CancellationToken::ctrl_c()?;
+    let args = DeployArgs {
+        board: Some(board),
+        devices,
+        allow_missing,
+        wait,
+        reboot,
+        mock_volume,
+        ..DeployArgs::new(PathBuf::from("flash_nuke.uf2"))
+    };
+    deploy_input(&input, args, &cancel)?;
+    log::info!("The erased boards come back in their bootloader once the whole flash is erased");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        commands::deploy::to_usb::usb_session,
+        picoboot::{ExclusiveAccess, Picoboot, RusbTransport},
+    };
+    use elf2flash_core::{
+        boards::CircuitPlaygroundBluefruit,
+        uf2::{UF2_BLOCK_SIZE, UF2_FLAG_NOT_MAIN_FLASH, Uf2Block},
+    };
+    use usbh_fatfs::rusb::{Context, Device, UsbContext};
+
+    fn blocks(uf2: &[u8]) -> Vec<Uf2Block> {
+        uf2.chunks(UF2_BLOCK_SIZE)
+            .map(|block| Uf2Block::from_bytes(block.try_into().unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn nuke_uf2_loads_into_ram_with_the_board_family() {
+        // 256 bytes of the program per block, the RP2350 one has an IMAGE_DEF block in front
+        for (board, family_id, num_blocks) in [
+            (&RP2040 as &dyn BoardInfo, 0xe48bff56, 2),
+            (&RP2350, 0xe48bff59, 3),
+        ] {
+            let uf2 = nuke_uf2(board).unwrap();
+            let blocks = blocks(&uf2);
+            assert_eq!(blocks.len(), num_blocks);
+            for (index, block) in blocks.iter().enumerate() {
+                assert_eq!(block.family_id(), Some(family_id));
+                assert_eq!(block.block_no() as usize, index);
+                assert_eq!(block.num_blocks() as usize, blocks.len());
+                assert_eq!(block.target_addr(), NUKE_BASE + 256 * index as u32);
+                assert_ne!(block.flags() & UF2_FLAG_NOT_MAIN_FLASH, 0);
+            }
+        }
+    }
+
+    /// The little endian words of a program
+    fn words(program: &[u8]) -> Vec<u32> {
+        program
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn rp2350_program_starts_with_an_image_def() {
+        let words = words(NUKE_RP2350.bytes);
+        let (stack_top, entry) = (0x2008_2000, words[1]);
+        assert_eq!(words[0], stack_top);
+        // A thumb address inside the program, past the block
+        assert_eq!(entry & 1, 1);
+        assert!(
+            (NUKE_BASE + 12 * 4..NUKE_BASE + NUKE_RP2350.bytes.len() as u32).contains(&(entry - 1))
+        );
+
+        assert_eq!(
+            words[2..12],
+            [
+                0xffffded3, // PICOBIN_BLOCK_MARKER_START
+                0x10210142, // IMAGE_TYPE: executable, RP2350, Arm, secure
+                0x00000203, // VECTOR_TABLE
+                NUKE_BASE, 0x00000344, // ENTRY_POINT
+                entry, stack_top, 0x000006ff, // LAST, of the 6 words of items
+                0,          // Loops to itself
+                0xab123579, // PICOBIN_BLOCK_MARKER_END
+            ]
+        );
+    }
+
+    /// The 16 bit constants a program loads, from its literal pool or by `movw`
+    fn constants(program: &[u8]) -> Vec<u16> {
+        let halfwords: Vec<u16> = program
+            .chunks_exact(2)
+            .map(|halfword| u16::from_le_bytes(halfword.try_into().unwrap()))
+            .collect();
+        let movw = halfwords
+            .windows(2)
+            .filter(|pair| pair[0] & 0xfbf0 == 0xf240)
+            .map(|pair| {
+                (pair[0] & 0xf) << 12
+                    | (pair[0] >> 10 & 1) << 11
+                    | (pair[1] >> 12 & 7) << 8
+                    | (pair[1] & 0xff)
+            });
+        let pool = words(program)
+            .into_iter()
+            .filter(|&word| word >> 16 == 0)
+            .map(|word| word as u16);
+        movw.chain(pool).collect()
+    }
+
+    #[test]
+    fn programs_call_the_bootrom_of_their_chip() {
+        // ROM_TABLE_CODE of connect_internal_flash, flash_exit_xip, flash_range_erase,
+        // flash_range_program, flash_flush_cache and flash_enter_cmd_xip
+        let flash = [b"IF", b"EX", b"RE", b"RP", b"FC", b"CX"];
+        for (program, reboot, other) in [
+            (NUKE_RP2040.bytes, b"UB", b"RB"),
+            (NUKE_RP2350.bytes, b"RB", b"UB"),
+        ] {
+            let constants = constants(program);
+            for code in flash.iter().chain([&reboot]) {
+                assert!(
+                    constants.contains(&u16::from_le_bytes(**code)),
+                    "{}",
+                    String::from_utf8_lossy(*code)
+                );
+            }
+            assert!(!constants.contains(&u16::from_le_bytes(*other)));
+        }
+    }
+
+    #[test]
+    fn programs_end_with_the_eyecatcher_page() {
+        for program in [NUKE_RP2040.bytes, NUKE_RP2350.bytes] {
+            assert_eq!(program.len() % 4, 0);
+            let page = &program[program.len() - 256..];
+            assert_eq!(&page[..4], b"NUKE");
+            assert!(page[4..].iter().all(|&byte| byte == 0));
+        }
+    }
+
+    #[test]
+    fn other_boards_are_not_supported() {
+        let err = nuke_uf2(&CircuitPlaygroundBluefruit).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Erasing isn't supported for board 'circuit_playground_bluefruit', only for RP2040 \
+             and RP2350 (Arm) boards"
+        );
+    }
+
+    /// The board in BOOTSEL with the bootrom of an RP2040 or RP2350, and the name of its chip.
+    fn bootsel_device() -> Option<(&'static str, Device<Context>)> {
+        let devices = usb_session().ok()?.context().devices().ok()?;
+        devices.iter().find_map(|device| {
+            let descriptor = device.device_descriptor().ok()?;
+            match (descriptor.vendor_id(), descriptor.product_id()) {
+                (0x2e8a, 0x0003) => Some(("rp2040", device)),
+                (0x2e8a, 0x000f) => Some(("rp2350", device)),
+                _ => None,
+            }
+        })
+    }
+
+    fn bootsel_picoboot() -> Picoboot<RusbTransport<Context>> {
+        let started = Instant::now();
+        loop {
+            if let Some((_, device)) = bootsel_device()
+                && let Ok(transport) = RusbTransport::open(&device)
+            {
+                let mut picoboot = Picoboot::new(transport).unwrap();
+                picoboot
+                    .exclusive_access(ExclusiveAccess::Exclusive)
+                    .unwrap();
+                picoboot.exit_xip().unwrap();
+                return picoboot;
+            }
+            assert!(
+                started.elapsed() < Duration::from_secs(60),
+                "The board didn't come back in BOOTSEL"
+            );
+            thread::sleep(Duration::from_millis(250));
+        }
+    }
+
+    /// Writes a page into the first two flash sectors of the board in BOOTSEL, erases it, and
+    /// reads the sectors back over PICOBOOT. Once it passes with a board of a chip, that chip's
+    /// program can be marked `hardware_verified`. Run it with one RP2040 or RP2350 in BOOTSEL:
+    /// `cargo test -p elf2flash hardware_erases_the_flash -- --ignored`
+    #[test]
+    #[ignore = "needs an RP2040 or RP2350 in BOOTSEL, whose flash it erases"]
+    fn hardware_erases_the_flash() {
+        const FLASH_START: u32 = 0x1000_0000;
+        const SECTOR: u32 = 4096;
+        let (board, _) = bootsel_device().expect("No RP2040 or RP2350 in BOOTSEL");
+
+        let mut picoboot = bootsel_picoboot();
+        for address in [FLASH_START, FLASH_START + SECTOR] {
+            picoboot.flash_erase(address, SECTOR).unwrap();
+            picoboot.write(address, &[0xa5; 256]).unwrap();
+        }
+        picoboot
+            .exclusive_access(ExclusiveAccess::NotExclusive)
+            .unwrap();
+        drop(picoboot);
+
+        erase(EraseArgs {
+            board: board.to_string(),
+            yes: true,
+            devices: Vec::new(),
+            allow_missing: false,
+            wait: None,
+            reboot: false,
+            unverified: true,
+            mock_volume: None,
+        })
+        .unwrap();
+        // The bootloader is still listed until it reboots into the program
+        thread::sleep(Duration::from_secs(2));
+
+        let mut picoboot = bootsel_picoboot();
+        for address in [FLASH_START, FLASH_START + SECTOR] {
+            let sector = picoboot.read(address, SECTOR).unwrap();
+            assert!(
+                sector.iter().all(|&byte| byte == 0xff),
+                "The sector at {address:#010x} wasn't erased"
+            );
+        }
+    }
+
+    #[test]
+    fn unverified_programs_need_unverified() {
+        let err = erase(EraseArgs {
+            board: "rp2040".to_string(),
+            yes: true,
+            devices: Vec::new(),
+            allow_missing: false,
+            wait: None,
+            reboot: false,
+            unverified: false,
+            mock_volume: None,
+        })
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("The erase program for board 'rp2040' hasn't been verified"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,397 @@
+//! `info`, what the bootloader of each connected device says about itself and what its volume
+//! looks like, for attaching to bug reports. Volumes are mounted read-only, looking never
+//! changes them.
+
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use clap::{Args, ValueHint};
+use elf2flash_core::{info_uf2::InfoUf2, warnings::Warnings};
+use fatfs::{FatType, FileSystem, FsOptions, ReadWriteSeek};
+use serde::Serialize;
+use usbh_fatfs::{FatPartition, PartitionView, ReadOnly};
+
+use crate::{
+    commands::deploy::{
+        report::{DeviceReport, device_reports},
+        select::{DeviceSelector, check_missing_selectors, select_devices},
+        to_usb::{MAX_INFO_UF2_LEN, SessionUsb, get_plugged_in_boards},
+    },
+    output,
+};
+
+const INFO_UF2_TXT: &str = "INFO_UF2.TXT";
+const INDEX_HTM: &str = "INDEX.HTM";
+
+#[derive(Args, Debug)]
+pub struct InfoArgs {
+    /// Only show the matching devices, same selectors as `deploy --device`
+    #[clap(long = "device", value_name = "SELECTOR", value_delimiter = ',')]
+    pub devices: Vec<DeviceSelector>,
+
+    /// Print the devices as a JSON array instead of text
+    #[clap(long)]
+    pub json: bool,
+
+    /// Show the FAT volume in this image file instead of the connected devices
+    #[clap(long, value_name = "IMAGE", value_hint = ValueHint::FilePath, hide = true)]
+    pub mock_volume: Option<PathBuf>,
+}
+
+/// A FAT volume of a device, with what its bootloader says about itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct VolumeInfo {
+    pub label: String,
+    /// `FAT12`, `FAT16` or `FAT32`
+    pub fat_type: String,
+    pub cluster_size: u32,
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    pub bootloader_version: Option<String>,
+    pub model: Option<String>,
+    pub board_id: Option<String>,
+    /// Where `INDEX.HTM` sends the browser, usually a page about the board
+    pub index_url: Option<String>,
+    /// The whole `INFO_UF2.TXT`, `None` if the volume has none
+    pub info_uf2: Option<String>,
+}
+
+/// A connected device as `info` shows it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DeviceInfo {
+    pub device: DeviceReport,
+    pub volumes: Vec<VolumeInfo>,
+    /// Why the volumes of the device couldn't be read
+    pub error: Option<String>,
+}
+
+fn fat_type_name(fat_type: FatType) -> &'static str {
+    match fat_type {
+        FatType::Fat12 => "FAT12",
+        FatType::Fat16 => "FAT16",
+        FatType::Fat32 => "FAT32",
+    }
+}
+
+/// The target of the redirect in `INDEX.HTM`: a `<meta http-equiv="refresh">` URL, a
+/// `location.replace(...)` script or else the first link.
+pub fn index_url(html: &str) -> Option<String> {
+    // Lowercasing ASCII keeps the byte offsets, so they point into `html` as well
+    let lower = html.to_ascii_lowercase();
+    ["url=", "location.replace(", "href="]
+        .iter()
+        .find_map(|marker| {
+            let start = lower.find(marker)? + marker.len();
+            let rest = html[start..].trim_start();
+            let url = match rest.chars().next()? {
+                quote @ ('\'' | '"') => rest[1..].split(quote).next()?,
+                _ => rest.split(['\'', '"', '>', ';', ' ']).next()?,
+            };
+            Some(url.trim()).filter(|url| !url.is_empty())
+        })
+        .map(str::to_string)
+}
+
+/// The contents of the file `name` in the root directory, matched ignoring case, `None` if there
+/// is none. Only the first [`MAX_INFO_UF2_LEN`] bytes are read.
+fn read_root_file<T: ReadWriteSeek>(fs: &FileSystem<T>, name: &str) -> Result<Option<String>> {
+    for entry in fs.root_dir().iter() {
+        let entry = entry.context("Failed to list the root directory")?;
+        if !entry.is_file() || !entry.file_name().eq_ignore_ascii_case(name) {
+            continue;
+        }
+
+        let mut contents = Vec::new();
+        entry
+            .to_file()
+            .take(MAX_INFO_UF2_LEN)
+            .read_to_end(&mut contents)
+            .with_context(|| format!("Failed to read {name}"))?;
+        return Ok(Some(String::from_utf8_lossy(&contents).into_owned()));
+    }
+    Ok(None)
+}
+
+/// Describe a mounted volume.
+pub fn volume_info<T: ReadWriteSeek>(fs: &FileSystem<T>) -> Result<VolumeInfo> {
+    let stats = fs.stats().context("Failed to count the free clusters")?;
+    let cluster_size = stats.cluster_size();
+
+    let info_uf2 = read_root_file(fs, INFO_UF2_TXT)?;
+    let index_url = read_root_file(fs, INDEX_HTM)?.and_then(|html| index_url(&html));
+    let info = info_uf2.as_deref().map(InfoUf2::parse).unwrap_or_default();
+
+    Ok(VolumeInfo {
+        label: fs.volume_label().trim().to_string(),
+        fat_type: fat_type_name(fs.fat_type()).to_string(),
+        cluster_size,
+        total_bytes: u64::from(stats.total_clusters()) * u64::from(cluster_size),
+        free_bytes: u64::from(stats.free_clusters()) * u64::from(cluster_size),
+        bootloader_version: info.bootloader_version,
+        model: info.model,
+        board_id: info.board_id,
+        index_url,
+        info_uf2,
+    })
+}
+
+/// Describe every FAT volume of a plugged in device.
+fn device_volumes(storage_usb: &mut SessionUsb) -> Result<Vec<VolumeInfo>> {
+    let partitions =
+        FatPartition::list_partitions(storage_usb).context("Failed to list partitions")?;
+
+    let opened = storage_usb
+        .open()
+        .context("Failed to open USB mass storage")?;
+    let mut block_device = opened
+        .block_device()
+        .context("Failed to get block device")?;
+
+    partitions
+        .iter()
+        .map(|partition| {
+            let label = partition.volume_label.trim();
+            let view =
+                PartitionView::new(&mut block_device, partition.first_byte, partition.length)?;
+            let fs = FileSystem::new(ReadOnly(view), FsOptions::new())
+                .with_context(|| format!("Failed to mount volume {label}"))?;
+            volume_info(&fs).with_context(|| format!("Failed to read volume {label}"))
+        })
+        .collect()
+}
+
+/// Describe the volume in the image file `image`.
+fn image_info(image: &Path) -> Result<DeviceInfo> {
+    let file = File::open(image)
+        .with_context(|| format!("Failed to open the volume image {}", image.display()))?;
+    let fs = FileSystem::new(ReadOnly(file), FsOptions::new())
+        .with_context(|| format!("Failed to mount the volume image {}", image.display()))?;
+    let volume = volume_info(&fs)?;
+
+    Ok(DeviceInfo {
+        device: DeviceReport {
+            labels: vec![volume.label.clone()],
+            ..Default::default()
+        },
+        volumes: vec![volume],
+        error: None,
+    })
+}
+
+/// Describe the plugged in uf2 devices `selectors` match, all of them without selectors.
+fn device_infos(selectors: &[DeviceSelector], warnings: &mut Warnings) -> Result<Vec<DeviceInfo>> {
+    let mut plugged_in_boards = get_plugged_in_boards(warnings)?;
+
+    let reports = device_reports(&mut plugged_in_boards, selectors);
+
+    let selection = (!selectors.is_empty()).then(|| select_devices(&reports, selectors));
+    if let Some(selection) = &selection {
+        check_missing_selectors(selection, selectors, &reports, false, warnings)?;
+    }
+
+    Ok(plugged_in_boards
+        .into_iter()
+        .zip(reports)
+        .filter(|(_, report)| {
+            selection
+                .as_ref()
+                .is_none_or(|s| s.is_selected(report.index))
+        })
+        .map(|((_usb, _board, mut storage_usb), report)| {
+            let (volumes, error) = match device_volumes(&mut storage_usb) {
+                Ok(volumes) => (volumes, None),
+                Err(err) => (Vec::new(), Some(format!("{err:#}"))),
+            };
+            DeviceInfo {
+                device: report,
+                volumes,
+                error,
+            }
+        })
+        .collect())
+}
+
+/// `infos` as text, a line for every device followed by an indented block for each of its
+/// volumes or its error.
+pub fn render_infos(infos: &[DeviceInfo]) -> String {
+    let mut text = String::new();
+    for info in infos {
+        text.push_str(&format!(
+            "Device {}: {}\n",
+            info.device.index,
+            info.device.summary()
+        ));
+
+        for volume in &info.volumes {
+            let mut fields = vec![
+                ("Volume", volume.label.clone()),
+                ("FAT type", volume.fat_type.clone()),
+                ("Cluster size", format!("{} bytes", volume.cluster_size)),
+                (
+                    "Free space",
+                    format!("{} of {} bytes", volume.free_bytes, volume.total_bytes),
+                ),
+            ];
+            for (key, value) in [
+                ("Bootloader", &volume.bootloader_version),
+                ("Model", &volume.model),
+                ("Board-ID", &volume.board_id),
+                ("Index URL", &volume.index_url),
+            ] {
+                if let Some(value) = value {
+                    fields.push((key, value.clone()));
+                }
+            }
+            if volume.info_uf2.is_none() {
+                fields.push((INFO_UF2_TXT, "missing".to_string()));
+            }
+
+            for (key, value) in fields {
+                text.push_str(&format!("    {:<14}{value}\n", format!("{key}:")));
+            }
+        }
+        if let Some(error) = &info.error {
+            text.push_str(&format!("    error: {error}\n"));
+        }
+    }
+    text
+}
+
+/// Show the volume and bootloader details of the connected uf2 devices.
+pub fn info(args: InfoArgs) -> Result<()> {
+    let InfoArgs {
+        devices,
+        json,
+        mock_volume,
+    } = args;
+
+    let mut warnings = Warnings::new();
+    let infos = match mock_volume {
+        Some(image) => vec![image_info(&image)?],
+        None => device_infos(&devices, &mut warnings)?,
+    };
+
+    if output::is_json() {
+        output::emit("info", Some(&infos), None)?;
+    } else if json {
+        println!("{}", serde_json::to_string_pretty(&infos)?);
+    } else if infos.is_empty() {
+        log::warn!("No UF2 devices found.");
+    } else {
+        print!("{}", render_infos(&infos));
+    }
+
+    if let Some(summary) = warnings.summary() {
+        log::warn!("{summary}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_url_follows_the_redirect() {
+        // The RP2040 bootrom
+        assert_eq!(
+            index_url(
+                "<html><head><meta http-equiv=\"refresh\" \
+                 content=\"0;URL='https://raspberrypi.com/device/RP2?version=E0C9125B0D9B'\"/>\
+                 </head><body>Redirecting to <a href='https://raspberrypi.com/device/RP2?\
+                 version=E0C9125B0D9B'>raspberrypi.com</a></body></html>"
+            )
+            .as_deref(),
+            Some("https://raspberrypi.com/device/RP2?version=E0C9125B0D9B")
+        );
+        // TinyUF2
+        assert_eq!(
+            index_url(
+                "<!doctype html><html><body><script>\
+                 location.replace(\"https://circuitpython.org/board/feather_esp32s3/\");\
+                 </script></body></html>"
+            )
+            .as_deref(),
+            Some("https://circuitpython.org/board/feather_esp32s3/")
+        );
+        assert_eq!(
+            index_url("<meta http-equiv=refresh content=\"0; url=https://adafruit.com\">")
+                .as_deref(),
+            Some("https://adafruit.com")
+        );
+        assert_eq!(
+            index_url("<a href=\"https://example.com\">docs</a>").as_deref(),
+            Some("https://example.com")
+        );
+        assert_eq!(index_url("<html>Nothing to see</html>"), None);
+    }
+
+    #[test]
+    fn unreadable_devices_are_shown_with_their_error() {
+        let infos = [
+            DeviceInfo {
+                device: DeviceReport {
+                    index: 0,
+                    board_name: Some("rp2040".to_string()),
+                    vendor_id: 0x2e8a,
+                    product_id: 0x0003,
+                    labels: vec!["RPI-RP2".to_string()],
+                    ..Default::default()
+                },
+                volumes: vec![VolumeInfo {
+                    label: "RPI-RP2".to_string(),
+                    fat_type: "FAT16".to_string(),
+                    cluster_size: 4096,
+                    total_bytes: 134_184_960,
+                    free_bytes: 134_172_672,
+                    bootloader_version: Some("v3.0".to_string()),
+                    model: Some("Raspberry Pi RP2".to_string()),
+                    board_id: Some("RPI-RP2".to_string()),
+                    index_url: Some("https://raspberrypi.com/device/RP2".to_string()),
+                    info_uf2: Some("UF2 Bootloader v3.0\n".to_string()),
+                }],
+                error: None,
+            },
+            DeviceInfo {
+                device: DeviceReport {
+                    index: 1,
+                    vendor_id: 0x1234,
+                    product_id: 0x5678,
+                    ..Default::default()
+                },
+                error: Some("Failed to list partitions: listing partitions failed".to_string()),
+                ..Default::default()
+            },
+        ];
+
+        assert_eq!(
+            render_infos(&infos),
+            "Device 0: rp2040 2e8a:0003 label RPI-RP2\n\
+             \x20   Volume:       RPI-RP2\n\
+             \x20   FAT type:     FAT16\n\
+             \x20   Cluster size: 4096 bytes\n\
+             \x20   Free space:   134172672 of 134184960 bytes\n\
+             \x20   Bootloader:   v3.0\n\
+             \x20   Model:        Raspberry Pi RP2\n\
+             \x20   Board-ID:     RPI-RP2\n\
+             \x20   Index URL:    https://raspberrypi.com/device/RP2\n\
+             Device 1: generic uf2 device 1234:5678\n\
+             \x20   error: Failed to list partitions: listing partitions failed\n"
+        );
+
+        let json = serde_json::to_value(&infos).unwrap();
+        assert_eq!(json[0]["volumes"][0]["board_id"], "RPI-RP2");
+        assert_eq!(json[0]["volumes"][0]["free_bytes"], 134_172_672);
+        assert!(json[0]["error"].is_null());
+        assert_eq!(json[1]["device"]["vendor_id"], 0x1234);
+        assert_eq!(json[1]["volumes"], serde_json::json!([]));
+        assert_eq!(
+            json[1]["error"],
+            "Failed to list partitions: listing partitions failed"
+        );
+    }
+}
@@ -0,0 +1,51 @@
+//! Telling the usual mistakes with an input path apart from other I/O errors, so they get a
+//! message naming the path instead of the bare error of opening it.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum InputPathError {
+    #[error("Input file {} doesn't exist", .0.display())]
+    NotFound(PathBuf),
+    #[error("Input {} is a directory, not a file", .0.display())]
+    IsDirectory(PathBuf),
+}
+
+/// Check that the input `path` exists and isn't a directory. Other problems, like missing
+/// permissions, are left to the error of opening it.
+pub fn check_input_file(path: &Path) -> Result<(), InputPathError> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Err(InputPathError::IsDirectory(path.to_path_buf())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            Err(InputPathError::NotFound(path.to_path_buf()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_files_and_directories_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("firmware.elf");
+        assert_eq!(
+            check_input_file(&file),
+            Err(InputPathError::NotFound(file.clone()))
+        );
+
+        fs::write(&file, b"\x7fELF").unwrap();
+        assert_eq!(check_input_file(&file), Ok(()));
+        assert_eq!(
+            check_input_file(dir.path()).unwrap_err().to_string(),
+            format!("Input {} is a directory, not a file", dir.path().display())
+        );
+    }
+}
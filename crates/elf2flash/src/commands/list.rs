@@ -0,0 +1,235 @@
+use anyhow::Result;
+use clap::Args;
+use elf2flash_core::{
+    boards::{BoardInfo, UsbDevice},
+    info_uf2::InfoUf2,
+};
+use serde::Serialize;
+
+use crate::{
+    commands::deploy::to_usb::{
+        SessionUsb, board_for_info, plugged_in_devices, read_uf2_partitions,
+    },
+    output,
+};
+
+#[derive(Args, Debug)]
+pub struct ListArgs {
+    /// Print the devices as a JSON array instead of text
+    #[clap(long)]
+    pub json: bool,
+}
+
+/// A uf2 volume of a device, with what its `INFO_UF2.TXT` says.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct VolumeRow {
+    pub label: String,
+    pub board_id: Option<String>,
+    pub bootloader_version: Option<String>,
+}
+
+impl VolumeRow {
+    fn new(label: &str, info: InfoUf2) -> Self {
+        Self {
+            label: label.trim().to_string(),
+            board_id: info.board_id,
+            bootloader_version: info.bootloader_version,
+        }
+    }
+}
+
+/// The errors of the volumes that couldn't be opened or mounted as one, `None` if there are none.
+fn skipped_error(skipped: &[anyhow::Error]) -> Option<String> {
+    (!skipped.is_empty()).then(|| {
+        skipped
+            .iter()
+            .map(|err| format!("{err:#}"))
+            .collect::<Vec<_>>()
+            .join("; ")
+    })
+}
+
+/// A connected device as `list` shows it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DeviceRow {
+    pub bus_number: u8,
+    pub address: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub serial: Option<String>,
+    /// Name of the recognized board, `None` for generic devices
+    pub board: Option<String>,
+    pub volumes: Vec<VolumeRow>,
+    /// Why the device, or some of its volumes, couldn't be read
+    pub error: Option<String>,
+}
+
+impl DeviceRow {
+    /// Read the uf2 volumes of a plugged in device, failures to list its partitions or to mount
+    /// one of them are kept in [`DeviceRow::error`]. The device is released again without a reset. A device whose USB ids no board knows is
+    /// recognized from the Board-ID of its volumes.
+    pub fn new(
+        usb_device: &UsbDevice,
+        board: Option<&dyn BoardInfo>,
+        storage_usb: &mut SessionUsb,
+    ) -> Self {
+        let description = format!(
+            "device {:04x}:{:04x}",
+            usb_device.vendor_id, usb_device.product_id
+        );
+        let mut skipped = Vec::new();
+        let partitions = read_uf2_partitions(&description, storage_usb, &mut skipped);
+        storage_usb.release();
+        let board = board
+            .map(|board| board.board_name().into_owned())
+            .or_else(|| {
+                partitions.as_ref().ok()?.iter().find_map(|(_, info)| {
+                    board_for_info(info).map(|board| board.board_name().into_owned())
+                })
+            });
+        let (volumes, error) = match partitions {
+            Ok(partitions) => (
+                partitions
+                    .into_iter()
+                    .map(|(partition, info)| VolumeRow::new(&partition.volume_label, info))
+                    .collect(),
+                skipped_error(&skipped),
+            ),
+            Err(err) => (Vec::new(), Some(format!("{err:#}"))),
+        };
+
+        Self {
+            bus_number: usb_device.bus_number,
+            address: usb_device.address,
+            vendor_id: usb_device.vendor_id,
+            product_id: usb_device.product_id,
+            serial: usb_device.serial_number.clone(),
+            board,
+            volumes,
+            error,
+        }
+    }
+}
+
+/// `rows` as text, a line for every device followed by an indented line for each of its volumes
+/// or its error.
+pub fn render_devices(rows: &[DeviceRow]) -> String {
+    let mut text = String::new();
+    for row in rows {
+        text.push_str(&format!(
+            "Bus {:03} Device {:03}: {:04x}:{:04x} {}",
+            row.bus_number,
+            row.address,
+            row.vendor_id,
+            row.product_id,
+            row.board.as_deref().unwrap_or("generic"),
+        ));
+        if let Some(serial) = &row.serial {
+            text.push_str(&format!(", serial {serial}"));
+        }
+        text.push('\n');
+
+        for volume in &row.volumes {
+            text.push_str(&format!("    volume {}", volume.label));
+            if let Some(board_id) = &volume.board_id {
+                text.push_str(&format!(", Board-ID {board_id}"));
+            }
+            if let Some(version) = &volume.bootloader_version {
+                text.push_str(&format!(", bootloader {version}"));
+            }
+            text.push('\n');
+        }
+        if let Some(error) = &row.error {
+            text.push_str(&format!("    error: {error}\n"));
+        }
+    }
+    text
+}
+
+/// List the connected USB mass storage devices as deploy finds them, generic devices included.
+pub fn list(args: ListArgs) -> Result<()> {
+    let rows: Vec<DeviceRow> = plugged_in_devices()?
+        .iter_mut()
+        .map(|(usb_device, board, storage_usb)| {
+            DeviceRow::new(usb_device, board.as_deref(), storage_usb)
+        })
+        .collect();
+
+    if output::is_json() {
+        output::emit("list", Some(&rows), None)?;
+    } else if args.json {
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+    } else if rows.is_empty() {
+        log::warn!("No USB mass storage devices found.");
+    } else {
+        print!("{}", render_devices(&rows));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unreadable_devices_are_listed_with_their_error() {
+        let rows = [
+            DeviceRow {
+                bus_number: 1,
+                address: 7,
+                vendor_id: 0x2e8a,
+                product_id: 0x0003,
+                serial: Some("E6614103E7452D2F".to_string()),
+                board: Some("rp2040".to_string()),
+                volumes: vec![VolumeRow {
+                    label: "RPI-RP2".to_string(),
+                    board_id: Some("RPI-RP2".to_string()),
+                    bootloader_version: Some("v3.0".to_string()),
+                }],
+                error: None,
+            },
+            DeviceRow {
+                bus_number: 3,
+                address: 12,
+                vendor_id: 0x1234,
+                product_id: 0x5678,
+                error: Some("Failed to list partitions for device 1234:5678".to_string()),
+                ..Default::default()
+            },
+        ];
+
+        assert_eq!(
+            render_devices(&rows),
+            "Bus 001 Device 007: 2e8a:0003 rp2040, serial E6614103E7452D2F\n\
+             \x20   volume RPI-RP2, Board-ID RPI-RP2, bootloader v3.0\n\
+             Bus 003 Device 012: 1234:5678 generic\n\
+             \x20   error: Failed to list partitions for device 1234:5678\n"
+        );
+
+        let json = serde_json::to_value(&rows).unwrap();
+        assert_eq!(json[0]["volumes"][0]["board_id"], "RPI-RP2");
+        assert!(json[0]["error"].is_null());
+        assert!(json[1]["board"].is_null());
+        assert_eq!(json[1]["volumes"], serde_json::json!([]));
+        assert_eq!(
+            json[1]["error"],
+            "Failed to list partitions for device 1234:5678"
+        );
+    }
+
+    #[test]
+    fn skipped_volumes_are_an_error() {
+        assert_eq!(skipped_error(&[]), None);
+
+        let skipped = [
+            anyhow::anyhow!("Unsupported FAT type").context("Failed to mount FAT filesystem"),
+            anyhow::anyhow!("Failed to get block device"),
+        ];
+        assert_eq!(
+            skipped_error(&skipped).as_deref(),
+            Some(
+                "Failed to mount FAT filesystem: Unsupported FAT type; Failed to get block device"
+            )
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::{Context, Result, anyhow};
+use clap::Args;
+use elf2flash_core::{
+    boards::family::describe_family,
+    uf2::{self, Uf2MergeError},
+};
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::PathBuf,
+};
+
+#[derive(Args, Debug)]
+pub struct MergeArgs {
+    /// Input uf2 files, their blocks are written in this order
+    #[clap(required = true, num_args = 1..)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Output uf2 file
+    pub output: PathBuf,
+}
+
+pub fn merge(args: MergeArgs) -> Result<()> {
+    let MergeArgs { inputs, output } = args;
+
+    let mut files = Vec::new();
+    for input in &inputs {
+        log::info!("Reading uf2 file from {input:?}");
+        let file =
+            File::open(input).with_context(|| format!("Failed to open {}", input.display()))?;
+        files.push(BufReader::new(file));
+    }
+
+    // Merged in memory, so a failed merge doesn't leave a partial output behind
+    let mut merged = Vec::new();
+
+    let summary = uf2::merge(&mut files, &mut merged).map_err(|err| {
+        let name = |input: usize| inputs[input].display();
+        match err {
+            Uf2MergeError::OverlappingBlocks {
+                address,
+                first,
+                second,
+                ..
+            } => anyhow!(
+                "{} and {} both write to {address:#08x}",
+                name(first),
+                name(second)
+            ),
+            Uf2MergeError::InvalidLength { input } | Uf2MergeError::InvalidBlock { input, .. } => {
+                let context = format!("{} is not a valid uf2 file", name(input));
+                anyhow!(err).context(context)
+            }
+            err => err.into(),
+        }
+    })?;
+
+    fs::write(&output, &merged).with_context(|| format!("Failed to write {}", output.display()))?;
+
+    log::info!("Wrote {} blocks to {output:?}", summary.num_blocks);
+    for (family_id, blocks) in &summary.families {
+        match family_id {
+            Some(family_id) => log::info!(
+                "    {blocks} blocks with family id {}",
+                describe_family(*family_id)
+            ),
+            None => log::info!("    {blocks} blocks without a family id"),
+        }
+    }
+    if summary.duplicate_blocks > 0 {
+        log::info!(
+            "Left out {} blocks that were already in an earlier input",
+            summary.duplicate_blocks
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        merge: MergeArgs,
+    }
+
+    #[test]
+    fn last_path_is_the_output() {
+        let cli =
+            Cli::try_parse_from(["merge", "bootloader.uf2", "app.uf2", "combined.uf2"]).unwrap();
+        assert_eq!(
+            cli.merge.inputs,
+            [PathBuf::from("bootloader.uf2"), PathBuf::from("app.uf2")]
+        );
+        assert_eq!(cli.merge.output, PathBuf::from("combined.uf2"));
+
+        assert!(Cli::try_parse_from(["merge", "combined.uf2"]).is_err());
+    }
+}
@@ -1,2 +1,18 @@
+pub mod boards;
+pub mod compare;
+pub mod completions;
+pub mod config;
 pub mod convert;
 pub mod deploy;
+pub mod doctor;
+pub mod dump;
+pub mod erase;
+pub mod info;
+pub mod input_path;
+pub mod list;
+pub mod merge;
+pub mod monitor;
+pub mod read;
+pub mod rollback;
+pub mod verify;
+pub mod write_page;
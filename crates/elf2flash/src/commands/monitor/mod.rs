@@ -0,0 +1,563 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    process,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use clap::Args;
+use elf2flash_core::boards::BoardIter;
+use serialport::{SerialPort, SerialPortInfo, SerialPortType};
+
+use crate::{
+    cancel::CancellationToken,
+    commands::monitor::{
+        pump::{CTRL_C, InputArgs, PumpEnd, key_name, pump_keys},
+        raw_mode::RawMode,
+        reconnect::{Decision, PortIdentity, ReconnectArgs, Reconnector},
+    },
+    output,
+    serial::{self, LineStamper, SerialArgs, SerialOutputArgs, SerialSettings},
+};
+
+pub mod pump;
+pub mod raw_mode;
+pub mod reconnect;
+
+/// What `--term` writes to the port on Ctrl+C
+const TERM_MESSAGE: &[u8] = b"elf2flash-term\r\n";
+
+/// Attempts at opening the port, 200 ms apart.
+const OPEN_ATTEMPTS: u32 = 100;
+
+/// Printed when the port is back after it disappeared
+const RECONNECTED_MARKER: &str = "--- reconnected ---";
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Args, Debug)]
+pub struct MonitorArgs {
+    /// Serial port to open, e.g. /dev/ttyACM0 or COM3. Defaults to the most recently enumerated
+    /// port with the USB vendor id of a known board
+    #[clap(long, value_name = "PATH")]
+    pub port: Option<String>,
+
+    /// Send termination message on Ctrl+C
+    #[clap(short, long)]
+    pub term: bool,
+
+    /// Don't send the termination message, even when `term = true` is in the config
+    #[clap(long, conflicts_with = "term")]
+    pub no_term: bool,
+
+    #[clap(flatten)]
+    pub reconnect_options: ReconnectArgs,
+
+    #[clap(flatten)]
+    pub serial_options: SerialArgs,
+
+    #[clap(flatten)]
+    pub output_options: SerialOutputArgs,
+
+    #[clap(flatten)]
+    pub input_options: InputArgs,
+}
+
+/// How [`monitor_port`] talks to the port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MonitorOptions {
+    pub settings: SerialSettings,
+    /// Write [`TERM_MESSAGE`] to the port on Ctrl+C
+    pub term: bool,
+    /// How long to wait for the port to come back when it disappears
+    pub reconnect: Option<Duration>,
+    pub output: SerialOutputArgs,
+    /// Send the keys typed on stdin to the port, until this key is typed
+    pub exit_key: Option<u8>,
+}
+
+/// The port as the keys and the termination message are written to it, `None` while it is gone.
+type SharedPort = Arc<Mutex<Option<Box<dyn SerialPort>>>>;
+
+/// Writes to the port, if it is there. Keys typed while it is gone are dropped.
+struct PortWriter(SharedPort);
+
+impl Write for PortWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut port = self
+            .0
+            .lock()
+            .expect("Should be able to aquire lock for port");
+        if let Some(port) = port.as_mut()
+            && let Err(err) = port.write_all(buf)
+        {
+            // Losing the port is noticed by the reading side
+            log::debug!("Dropped {} typed bytes: {err}", buf.len());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut port = self
+            .0
+            .lock()
+            .expect("Should be able to aquire lock for port");
+        if let Some(port) = port.as_mut() {
+            port.flush().ok();
+        }
+        Ok(())
+    }
+}
+
+/// Where the output of the port goes: stdout, timestamped with `--timestamps`, and the
+/// `--log-file` as it was received.
+struct Sink {
+    stamper: Option<LineStamper>,
+    log_file: Option<File>,
+    stamped: Vec<u8>,
+    /// Whether the last byte printed ended a line
+    at_line_start: bool,
+}
+
+impl Sink {
+    fn open(output: &SerialOutputArgs) -> Result<Self> {
+        let log_file = match &output.log_file {
+            Some(path) => Some(
+                OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .append(output.log_append)
+                    .truncate(!output.log_append)
+                    .open(path)
+                    .with_context(|| format!("Failed to open the log file {}", path.display()))?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            stamper: output.timestamps.then(LineStamper::new),
+            log_file,
+            stamped: Vec::new(),
+            at_line_start: true,
+        })
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.print(chunk)?;
+        if let Some(log_file) = &mut self.log_file {
+            log_file.write_all(chunk)?;
+        }
+        Ok(())
+    }
+
+    /// End the line the port left unfinished, for what the monitor prints itself.
+    fn finish_line(&mut self) -> io::Result<()> {
+        if self.at_line_start {
+            return Ok(());
+        }
+        self.print(b"\n")
+    }
+
+    /// Print `marker` on a line of its own, leaving it out of the log file.
+    fn marker(&mut self, marker: &str) -> io::Result<()> {
+        self.finish_line()?;
+        self.print(format!("{marker}\n").as_bytes())
+    }
+
+    fn print(&mut self, chunk: &[u8]) -> io::Result<()> {
+        if let Some(&last) = chunk.last() {
+            self.at_line_start = last == b'\n';
+        }
+        let mut stdout = io::stdout().lock();
+        match &mut self.stamper {
+            Some(stamper) => {
+                self.stamped.clear();
+                stamper.stamp(chunk, serial::timestamp, &mut self.stamped);
+                stdout.write_all(&self.stamped)?;
+            }
+            None => stdout.write_all(chunk)?,
+        }
+        stdout.flush()
+    }
+}
+
+/// The USB vendor ids of the known boards, the built-in ones and those of the boards file.
+pub fn known_vendor_ids() -> Vec<u16> {
+    let mut vendor_ids: Vec<u16> = BoardIter::new()
+        .flat_map(|board| board.usb_matches())
+        .map(|(vendor_id, _)| vendor_id)
+        .collect();
+    vendor_ids.sort_unstable();
+    vendor_ids.dedup();
+    vendor_ids
+}
+
+/// When `port_name` showed up, from the change time of its device node: it is set when the node
+/// is created, and reading or writing the port leaves it alone. `None` for ports that aren't
+/// files, like the COM ports of Windows.
+#[cfg(unix)]
+fn enumerated_at(port_name: &str) -> Option<(i64, i64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(port_name).ok()?;
+    Some((metadata.ctime(), metadata.ctime_nsec()))
+}
+
+#[cfg(not(unix))]
+fn enumerated_at(_port_name: &str) -> Option<(i64, i64)> {
+    None
+}
+
+/// The most recently enumerated USB port in `ports` with one of `vendor_ids`, going by
+/// `enumerated_at`, or the last one listed among those it can't tell apart.
+pub fn pick_port<'a, T: Ord>(
+    ports: &'a [SerialPortInfo],
+    vendor_ids: &[u16],
+    enumerated_at: impl Fn(&str) -> Option<T>,
+) -> Option<&'a SerialPortInfo> {
+    ports
+        .iter()
+        .enumerate()
+        .filter(|(_, port)| {
+            matches!(&port.port_type, SerialPortType::UsbPort(usb) if vendor_ids.contains(&usb.vid))
+        })
+        .max_by_key(|(index, port)| (enumerated_at(&port.port_name), *index))
+        .map(|(_, port)| port)
+}
+
+/// Open `port_name` and raise DTR, which USB CDC firmware often waits for before printing.
+fn open_once(port_name: &str, settings: &SerialSettings) -> Result<Box<dyn SerialPort>> {
+    let mut port =
+        serial::open(port_name, settings).with_context(|| format!("Failed to open {port_name}"))?;
+    port.write_data_terminal_ready(true)
+        .with_context(|| format!("Failed to set DTR on {port_name}"))?;
+    Ok(port)
+}
+
+/// Open `port_name`, trying up to [`OPEN_ATTEMPTS`] times. `None` once `quit` is cancelled.
+fn open_port(
+    port_name: &str,
+    settings: &SerialSettings,
+    cancel: &CancellationToken,
+    quit: &CancellationToken,
+) -> Result<Option<Box<dyn SerialPort>>> {
+    let mut last_err = anyhow!("Failed to open {port_name}");
+    for _ in 0..OPEN_ATTEMPTS {
+        cancel.check()?;
+        if quit.is_cancelled() {
+            return Ok(None);
+        }
+        match open_once(port_name, settings) {
+            Ok(port) => return Ok(Some(port)),
+            Err(err) => last_err = err,
+        }
+        thread::sleep(RETRY_INTERVAL);
+    }
+    Err(last_err)
+}
+
+/// Wait for the port of `identity` to be listed again, under its name or another one, and open
+/// it, for as long as `reconnector` lets it. The name is tried as well, for ports the system
+/// doesn't list. `None` once `quit` is cancelled.
+fn reopen_port(
+    identity: &PortIdentity,
+    settings: &SerialSettings,
+    reconnector: &mut Reconnector,
+    cancel: &CancellationToken,
+    quit: &CancellationToken,
+) -> Result<Option<(String, Box<dyn SerialPort>)>> {
+    loop {
+        cancel.check()?;
+        if quit.is_cancelled() {
+            return Ok(None);
+        }
+        let ports = serialport::available_ports().unwrap_or_default();
+        let port_name = identity
+            .find(&ports)
+            .map_or(identity.port_name.as_str(), |port| port.port_name.as_str());
+        match open_once(port_name, settings) {
+            Ok(port) => {
+                reconnector.reconnected();
+                return Ok(Some((port_name.to_string(), port)));
+            }
+            Err(err) => log::debug!("{err:#}"),
+        }
+
+        if reconnector.failed(Instant::now()) == Decision::GiveUp {
+            bail!(
+                "{} didn't come back within {}s",
+                identity.port_name,
+                reconnector.window().unwrap_or_default().as_secs()
+            );
+        }
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+/// Why [`read_until_lost`] stopped.
+enum ReadEnd {
+    /// The exit key was typed
+    Quit,
+    /// Reading failed, as it does when the port disappears
+    Lost(io::Error),
+}
+
+/// Pass what `port` sends to `sink`, until it is lost or `quit` is cancelled.
+fn read_until_lost(
+    port: &mut dyn SerialPort,
+    writer: &SharedPort,
+    term: bool,
+    sink: &mut Sink,
+    cancel: &CancellationToken,
+    quit: &CancellationToken,
+) -> Result<ReadEnd> {
+    let mut serial_buf = [0; 1024];
+    loop {
+        match port.read(&mut serial_buf) {
+            Ok(t) => sink.write(&serial_buf[..t])?,
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
+                cancel.check()?;
+                if quit.is_cancelled() {
+                    return Ok(ReadEnd::Quit);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                if term {
+                    send_term(writer);
+                }
+                return Err(e.into());
+            }
+            Err(e) => return Ok(ReadEnd::Lost(e)),
+        }
+    }
+}
+
+/// Write [`TERM_MESSAGE`] to the port, if it is open.
+fn write_term(writer: &SharedPort) {
+    let mut writer = PortWriter(writer.clone());
+    writer.write_all(TERM_MESSAGE).ok();
+    writer.flush().ok();
+}
+
+/// Write [`TERM_MESSAGE`] to the port, if it is open, and exit.
+fn send_term(writer: &SharedPort) -> ! {
+    write_term(writer);
+    process::exit(0);
+}
+
+/// Send the keys typed on stdin to the port from another thread, until `exit_key` is typed, which
+/// cancels `quit`. In raw mode Ctrl+C is a key too: with `term` it sends the termination message
+/// and quits, otherwise the board gets it.
+fn forward_keys(writer: &SharedPort, exit_key: u8, term: bool, quit: &CancellationToken) {
+    let stop_keys = if term {
+        vec![exit_key, CTRL_C]
+    } else {
+        vec![exit_key]
+    };
+    let writer = writer.clone();
+    let quit = quit.clone();
+    thread::spawn(move || {
+        let mut port = PortWriter(writer.clone());
+        match pump_keys(&mut io::stdin().lock(), &mut port, &stop_keys) {
+            Ok(PumpEnd::Key(key)) => {
+                if term && key == CTRL_C {
+                    write_term(&writer);
+                }
+                quit.cancel();
+            }
+            Ok(PumpEnd::Closed) => log::debug!("Stdin was closed, no more keys to send"),
+            Err(err) => log::warn!("Stopped sending the typed keys to the board: {err}"),
+        }
+    });
+}
+
+/// Print what `port_name` sends to stdout, until Ctrl+C, the exit key or, without reconnecting,
+/// until the port disappears. `cancel` must not be a Ctrl+C token with `term`, the termination
+/// message takes over Ctrl+C.
+pub fn monitor_port(
+    port_name: &str,
+    options: &MonitorOptions,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let mut sink = Sink::open(&options.output)?;
+    let writer: SharedPort = Arc::new(Mutex::new(None));
+    let quit = CancellationToken::new();
+
+    // Restores the terminal when dropped, on every way out of here
+    let raw_mode = match options.exit_key {
+        Some(_) => RawMode::enable().context("Failed to put the terminal into raw mode")?,
+        None => None,
+    };
+    if options.term && raw_mode.is_none() {
+        let writer = writer.clone();
+        ctrlc::set_handler(move || send_term(&writer))
+            .context("Failed to set the Ctrl+C handler")?;
+    }
+    let stop_hint = match options.exit_key {
+        Some(exit_key) => {
+            forward_keys(&writer, exit_key, options.term && raw_mode.is_some(), &quit);
+            format!("{} to quit", key_name(exit_key))
+        }
+        None => "Ctrl+C to stop".to_string(),
+    };
+
+    let Some(mut opened) = open_port(port_name, &options.settings, cancel, &quit)? else {
+        return Ok(());
+    };
+    log::info!(
+        "Connected to {port_name} at {}, {stop_hint}",
+        options.settings
+    );
+    let mut port_name = port_name.to_string();
+    let mut reconnector = Reconnector::new(options.reconnect);
+    loop {
+        let identity = PortIdentity::of(
+            &port_name,
+            &serialport::available_ports().unwrap_or_default(),
+        );
+        let cloned = opened
+            .try_clone()
+            .with_context(|| format!("Failed to open {port_name} for writing"))?;
+        *writer
+            .lock()
+            .expect("Should be able to aquire lock for port") = Some(cloned);
+        let end = read_until_lost(
+            opened.as_mut(),
+            &writer,
+            options.term,
+            &mut sink,
+            cancel,
+            &quit,
+        );
+        *writer
+            .lock()
+            .expect("Should be able to aquire lock for port") = None;
+
+        let err = match end? {
+            ReadEnd::Quit => return Ok(()),
+            ReadEnd::Lost(err) => err,
+        };
+        if reconnector.lost(&err, Instant::now()) == Decision::GiveUp {
+            return Err(err).with_context(|| format!("Lost the connection to {port_name}"));
+        }
+        drop(opened);
+        sink.finish_line()?;
+        log::warn!(
+            "{port_name} disappeared ({err}), waiting up to {}s for it to come back",
+            reconnector.window().unwrap_or_default().as_secs()
+        );
+
+        let reopened = reopen_port(
+            &identity,
+            &options.settings,
+            &mut reconnector,
+            cancel,
+            &quit,
+        )
+        .with_context(|| format!("Lost the connection to {port_name}"))?;
+        let Some((reopened_name, reopened)) = reopened else {
+            return Ok(());
+        };
+        (port_name, opened) = (reopened_name, reopened);
+        sink.marker(RECONNECTED_MARKER)?;
+    }
+}
+
+pub fn monitor(args: MonitorArgs) -> Result<()> {
+    let MonitorArgs {
+        port,
+        term,
+        no_term: _,
+        reconnect_options,
+        serial_options,
+        output_options,
+        input_options,
+    } = args;
+
+    if output::is_json() {
+        bail!("monitor prints the board's output on stdout, it can't be used with --format json");
+    }
+
+    let port_name = match port {
+        Some(port) => port,
+        None => {
+            let ports = serialport::available_ports()?;
+            pick_port(&ports, &known_vendor_ids(), enumerated_at)
+                .map(|port| port.port_name.clone())
+                .context("No serial port of a known board found, pick one with --port")?
+        }
+    };
+
+    let cancel = if term {
+        CancellationToken::new()
+    } else {
+        CancellationToken::ctrl_c()?
+    };
+    let options = MonitorOptions {
+        settings: SerialSettings::from(serial_options),
+        term,
+        reconnect: reconnect_options.window(),
+        output: output_options,
+        exit_key: input_options.forwarding(),
+    };
+    monitor_port(&port_name, &options, &cancel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialport::UsbPortInfo;
+
+    fn usb_port(port_name: &str, vid: u16) -> SerialPortInfo {
+        SerialPortInfo {
+            port_name: port_name.to_string(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid,
+                pid: 0x000a,
+                serial_number: None,
+                manufacturer: None,
+                product: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn newest_port_of_a_known_board_is_picked() {
+        let ports = [
+            usb_port("/dev/ttyACM0", 0x2e8a),
+            usb_port("/dev/ttyACM1", 0x2e8a),
+            usb_port("/dev/ttyUSB0", 0x0403),
+            SerialPortInfo {
+                port_name: "/dev/ttyS0".to_string(),
+                port_type: SerialPortType::Unknown,
+            },
+        ];
+        let vendor_ids = [0x239a, 0x2e8a];
+
+        // ttyACM0 came back after a reset, so it is newer than ttyACM1
+        let enumerated_at = |name: &str| match name {
+            "/dev/ttyACM0" => Some(20),
+            "/dev/ttyACM1" => Some(10),
+            _ => Some(30),
+        };
+        let port = pick_port(&ports, &vendor_ids, enumerated_at).unwrap();
+        assert_eq!(port.port_name, "/dev/ttyACM0");
+
+        // Without enumeration times, the last one listed
+        let port = pick_port(&ports, &vendor_ids, |_| None::<u64>).unwrap();
+        assert_eq!(port.port_name, "/dev/ttyACM1");
+
+        assert!(pick_port(&ports, &[0x303a], |_| None::<u64>).is_none());
+        assert!(pick_port(&[], &vendor_ids, |_| None::<u64>).is_none());
+    }
+
+    #[test]
+    fn built_in_boards_have_vendor_ids() {
+        let vendor_ids = known_vendor_ids();
+        assert!(vendor_ids.contains(&0x2e8a));
+        assert!(vendor_ids.contains(&0x239a));
+        assert!(vendor_ids.is_sorted());
+    }
+}
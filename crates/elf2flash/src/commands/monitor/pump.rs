@@ -0,0 +1,199 @@
+//! Forwarding the keys typed in the terminal to the board, for firmware with a console on its
+//! serial port.
+
+use std::io::{self, Read, Write};
+
+use clap::Args;
+
+/// Ctrl+C, as a terminal in raw mode sends it instead of interrupting the process
+pub const CTRL_C: u8 = 0x03;
+
+/// Whether the keys typed in the terminal go to the board.
+#[derive(Args, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputArgs {
+    /// Only print the serial output, instead of also sending the keys typed in the terminal to the
+    /// board
+    #[clap(long)]
+    pub no_input: bool,
+
+    /// Key quitting the serial monitor while typed keys are sent to the board, which then gets
+    /// Ctrl+C like any other key
+    #[clap(long, value_name = "KEY", value_parser = exit_key_parser, default_value = "ctrl-]")]
+    pub exit_key: u8,
+}
+
+impl InputArgs {
+    /// The exit key, if typed keys are forwarded: from a terminal, unless `--no-input` is given.
+    pub fn forwarding(&self) -> Option<u8> {
+        (!self.no_input && crate::interactive::is_interactive()).then_some(self.exit_key)
+    }
+}
+
+/// Parse a key like `ctrl-]` into the byte a terminal sends for it.
+fn exit_key_parser(s: &str) -> Result<u8, String> {
+    let err = || format!("invalid key {s:?}, expected a control key like ctrl-] or ctrl-x");
+    let key = s
+        .strip_prefix("ctrl-")
+        .or_else(|| s.strip_prefix("ctrl+"))
+        .ok_or_else(err)?;
+    match key.as_bytes() {
+        [key @ (b'@'..=b'_' | b'a'..=b'z')] => Ok(key.to_ascii_uppercase() & 0x1f),
+        _ => Err(err()),
+    }
+}
+
+/// How the key sending `byte` is written, e.g. `Ctrl+]` for 0x1d.
+pub fn key_name(byte: u8) -> String {
+    match byte {
+        0x00..=0x1f => format!("Ctrl+{}", (byte | 0x40) as char),
+        _ => format!("{:?}", byte as char),
+    }
+}
+
+/// Why [`pump_keys`] stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PumpEnd {
+    /// One of the stop keys was typed, it isn't forwarded
+    Key(u8),
+    /// The input was closed
+    Closed,
+}
+
+/// Copy `keys` to `device` as they are typed, until one of `stop_keys` is typed or `keys` ends.
+/// What was typed before the stop key is still forwarded.
+pub fn pump_keys(
+    keys: &mut impl Read,
+    device: &mut impl Write,
+    stop_keys: &[u8],
+) -> io::Result<PumpEnd> {
+    let mut buf = [0; 256];
+    loop {
+        let read = match keys.read(&mut buf) {
+            Ok(0) => return Ok(PumpEnd::Closed),
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        let typed = &buf[..read];
+        let stop = typed.iter().position(|key| stop_keys.contains(key));
+        device.write_all(&typed[..stop.unwrap_or(read)])?;
+        device.flush()?;
+        if let Some(stop) = stop {
+            return Ok(PumpEnd::Key(typed[stop]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::mpsc::{Receiver, Sender, channel},
+        thread,
+    };
+
+    /// One end of an in-memory duplex stream, reading what the other end writes.
+    struct End {
+        tx: Sender<Vec<u8>>,
+        rx: Receiver<Vec<u8>>,
+        pending: Vec<u8>,
+    }
+
+    impl Read for End {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pending.is_empty() {
+                match self.rx.recv() {
+                    Ok(data) => self.pending = data,
+                    // The other end is gone
+                    Err(_) => return Ok(0),
+                }
+            }
+            let read = buf.len().min(self.pending.len());
+            buf[..read].copy_from_slice(&self.pending[..read]);
+            self.pending.drain(..read);
+            Ok(read)
+        }
+    }
+
+    impl Write for End {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.tx
+                .send(buf.to_vec())
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn duplex() -> (End, End) {
+        let (a_tx, b_rx) = channel();
+        let (b_tx, a_rx) = channel();
+        let a = End {
+            tx: a_tx,
+            rx: a_rx,
+            pending: Vec::new(),
+        };
+        let b = End {
+            tx: b_tx,
+            rx: b_rx,
+            pending: Vec::new(),
+        };
+        (a, b)
+    }
+
+    fn read_exact(end: &mut End, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        end.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn keys_are_forwarded_until_the_exit_key() {
+        let (mut terminal, mut keyboard) = duplex();
+        let (mut port, mut board) = duplex();
+        let pump = thread::spawn(move || pump_keys(&mut terminal, &mut port, &[0x1d]));
+
+        keyboard.write_all(b"help\r").unwrap();
+        assert_eq!(read_exact(&mut board, 5), b"help\r");
+
+        // Ctrl+C isn't a stop key, the board gets it
+        keyboard.write_all(&[CTRL_C]).unwrap();
+        assert_eq!(read_exact(&mut board, 1), [CTRL_C]);
+
+        keyboard.write_all(b"ls\x1dreboot\r").unwrap();
+        assert_eq!(pump.join().unwrap().unwrap(), PumpEnd::Key(0x1d));
+        // The pump dropped its end of the port, so this is everything the board got
+        let mut rest = Vec::new();
+        board.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"ls");
+    }
+
+    #[test]
+    fn closed_input_ends_the_pump() {
+        let (mut terminal, mut keyboard) = duplex();
+        let (mut port, mut board) = duplex();
+        let pump = thread::spawn(move || pump_keys(&mut terminal, &mut port, &[0x1d, CTRL_C]));
+
+        keyboard.write_all(b"status\n").unwrap();
+        drop(keyboard);
+        assert_eq!(pump.join().unwrap().unwrap(), PumpEnd::Closed);
+        let mut forwarded = Vec::new();
+        board.read_to_end(&mut forwarded).unwrap();
+        assert_eq!(forwarded, b"status\n");
+    }
+
+    #[test]
+    fn exit_keys_are_parsed() {
+        assert_eq!(exit_key_parser("ctrl-]"), Ok(0x1d));
+        assert_eq!(exit_key_parser("ctrl+x"), Ok(0x18));
+        assert_eq!(exit_key_parser("ctrl-A"), Ok(0x01));
+        assert!(exit_key_parser("x").is_err());
+        assert!(exit_key_parser("ctrl-1").is_err());
+        assert!(exit_key_parser("ctrl-ab").is_err());
+        assert_eq!(key_name(0x1d), "Ctrl+]");
+        assert_eq!(key_name(CTRL_C), "Ctrl+C");
+    }
+}
@@ -0,0 +1,54 @@
+//! The terminal mode the serial monitor forwards keys in.
+
+use std::io;
+
+/// The terminal on stdin in raw mode until dropped: each key is read as it is typed, without
+/// being echoed, and Ctrl+C is read as a byte instead of interrupting the process. The output is
+/// left alone, a `\n` from the board still starts a new line.
+#[cfg(unix)]
+pub struct RawMode {
+    original: nix::sys::termios::Termios,
+}
+
+#[cfg(unix)]
+impl RawMode {
+    pub fn enable() -> io::Result<Option<Self>> {
+        use nix::sys::termios::{
+            InputFlags, LocalFlags, SetArg, SpecialCharacterIndices, tcgetattr, tcsetattr,
+        };
+
+        let original = tcgetattr(io::stdin())?;
+        let mut raw = original.clone();
+        raw.local_flags
+            .remove(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG | LocalFlags::IEXTEN);
+        // Enter is sent as the \r it is, and Ctrl+S and Ctrl+Q go to the board
+        raw.input_flags.remove(InputFlags::ICRNL | InputFlags::IXON);
+        raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+        raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+        tcsetattr(io::stdin(), SetArg::TCSANOW, &raw)?;
+        Ok(Some(Self { original }))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        use nix::sys::termios::{SetArg, tcsetattr};
+
+        if let Err(err) = tcsetattr(io::stdin(), SetArg::TCSANOW, &self.original) {
+            log::warn!("Failed to restore the terminal: {err}");
+        }
+    }
+}
+
+/// Raw mode isn't supported here, keys are forwarded a line at a time once Enter is pressed, and
+/// Ctrl+C stops the monitor.
+#[cfg(not(unix))]
+pub struct RawMode;
+
+#[cfg(not(unix))]
+impl RawMode {
+    pub fn enable() -> io::Result<Option<Self>> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,227 @@
+//! Getting the serial port back after the board resets, as firmware does on a watchdog or a soft
+//! reboot a few seconds after it was flashed.
+
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use clap::Args;
+use serialport::{SerialPortInfo, SerialPortType, UsbPortInfo};
+
+#[derive(Args, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectArgs {
+    /// Exit when the serial port disappears, instead of waiting for it to come back as it does
+    /// when the board resets
+    #[clap(long)]
+    pub no_reconnect: bool,
+
+    /// Give up on a serial port that disappeared after this many seconds
+    #[clap(
+        long,
+        value_name = "SECS",
+        default_value_t = 30,
+        conflicts_with = "no_reconnect"
+    )]
+    pub reconnect_timeout: u64,
+}
+
+impl ReconnectArgs {
+    /// How long to wait for a port that disappeared, `None` with `--no-reconnect`.
+    pub fn window(&self) -> Option<Duration> {
+        (!self.no_reconnect).then(|| Duration::from_secs(self.reconnect_timeout))
+    }
+}
+
+/// Whether a read failing with `err` means the port is gone: a hangup, the device node removed,
+/// or access to it revoked as macOS and Windows do.
+pub fn is_disconnect(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::NotFound
+            | io::ErrorKind::PermissionDenied
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+/// What to do after the port was lost or an attempt at reopening it failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Look for the port again
+    Retry,
+    /// Fail, the error isn't a disconnection, reconnecting is off or the window is over
+    GiveUp,
+}
+
+/// Decides whether to keep trying to reopen a lost port, within `window` of losing it.
+#[derive(Debug, Clone, Copy)]
+pub struct Reconnector {
+    window: Option<Duration>,
+    lost_at: Option<Instant>,
+}
+
+impl Reconnector {
+    pub fn new(window: Option<Duration>) -> Self {
+        Self {
+            window,
+            lost_at: None,
+        }
+    }
+
+    /// How long a lost port is waited for, `None` if it isn't.
+    pub fn window(&self) -> Option<Duration> {
+        self.window
+    }
+
+    /// Reading failed with `err` at `now`.
+    pub fn lost(&mut self, err: &io::Error, now: Instant) -> Decision {
+        if self.window.is_none() || !is_disconnect(err) {
+            return Decision::GiveUp;
+        }
+        self.lost_at = Some(now);
+        Decision::Retry
+    }
+
+    /// Reopening the port failed at `now`, or it isn't listed yet.
+    pub fn failed(&self, now: Instant) -> Decision {
+        match (self.window, self.lost_at) {
+            (Some(window), Some(lost_at)) if now.duration_since(lost_at) < window => {
+                Decision::Retry
+            }
+            _ => Decision::GiveUp,
+        }
+    }
+
+    /// The port is open again, the next loss gets a full window.
+    pub fn reconnected(&mut self) {
+        self.lost_at = None;
+    }
+}
+
+/// Which port to look for after losing one: the same name, or the same USB device under another
+/// name, as a board that comes back as /dev/ttyACM1 while /dev/ttyACM0 was still held open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortIdentity {
+    pub port_name: String,
+    pub usb: Option<UsbPortInfo>,
+}
+
+impl PortIdentity {
+    /// The identity of `port_name`, with its USB ids if it is listed in `ports` as a USB port.
+    pub fn of(port_name: &str, ports: &[SerialPortInfo]) -> Self {
+        let usb = ports
+            .iter()
+            .find(|port| port.port_name == port_name)
+            .and_then(|port| match &port.port_type {
+                SerialPortType::UsbPort(usb) => Some(usb.clone()),
+                _ => None,
+            });
+        Self {
+            port_name: port_name.to_string(),
+            usb,
+        }
+    }
+
+    /// The port in `ports` this identity came back as, if it is listed.
+    pub fn find<'a>(&self, ports: &'a [SerialPortInfo]) -> Option<&'a SerialPortInfo> {
+        ports
+            .iter()
+            .find(|port| port.port_name == self.port_name)
+            .or_else(|| {
+                let usb = self.usb.as_ref()?;
+                ports.iter().find(|port| {
+                    matches!(&port.port_type, SerialPortType::UsbPort(other)
+                        if other.vid == usb.vid
+                            && other.pid == usb.pid
+                            && other.serial_number == usb.serial_number)
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn disconnections_are_retried_within_the_window() {
+        let start = Instant::now();
+        let mut reconnector = Reconnector::new(Some(WINDOW));
+
+        // The board resets: a hangup, nothing listed for a while, then it is back
+        let hangup = io::Error::from(io::ErrorKind::BrokenPipe);
+        assert_eq!(reconnector.lost(&hangup, start), Decision::Retry);
+        assert_eq!(
+            reconnector.failed(start + Duration::from_secs(1)),
+            Decision::Retry
+        );
+        assert_eq!(
+            reconnector.failed(start + Duration::from_secs(29)),
+            Decision::Retry
+        );
+        reconnector.reconnected();
+
+        // Resetting again a minute later gets a new window
+        let later = start + Duration::from_secs(60);
+        let revoked = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(reconnector.lost(&revoked, later), Decision::Retry);
+        assert_eq!(
+            reconnector.failed(later + Duration::from_secs(10)),
+            Decision::Retry
+        );
+        assert_eq!(reconnector.failed(later + WINDOW), Decision::GiveUp);
+    }
+
+    #[test]
+    fn other_errors_and_no_reconnect_give_up() {
+        let now = Instant::now();
+        let mut reconnector = Reconnector::new(Some(WINDOW));
+        let other = io::Error::other("framing error");
+        assert_eq!(reconnector.lost(&other, now), Decision::GiveUp);
+
+        let mut reconnector = Reconnector::new(None);
+        let removed = io::Error::from(io::ErrorKind::NotFound);
+        assert_eq!(reconnector.lost(&removed, now), Decision::GiveUp);
+        assert_eq!(reconnector.failed(now), Decision::GiveUp);
+    }
+
+    fn usb_port(port_name: &str, serial_number: &str) -> SerialPortInfo {
+        SerialPortInfo {
+            port_name: port_name.to_string(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid: 0x2e8a,
+                pid: 0x000a,
+                serial_number: Some(serial_number.to_string()),
+                manufacturer: None,
+                product: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn port_is_found_by_name_or_usb_device() {
+        let before = [usb_port("/dev/ttyACM0", "E6614103E7")];
+        let identity = PortIdentity::of("/dev/ttyACM0", &before);
+        assert!(identity.usb.is_some());
+
+        assert_eq!(identity.find(&before).unwrap().port_name, "/dev/ttyACM0");
+        assert!(identity.find(&[]).is_none());
+
+        // Back under another name, next to another board
+        let after = [
+            usb_port("/dev/ttyACM2", "E66141040B"),
+            usb_port("/dev/ttyACM1", "E6614103E7"),
+        ];
+        assert_eq!(identity.find(&after).unwrap().port_name, "/dev/ttyACM1");
+
+        // A port that isn't a USB device is only found by its name
+        let identity = PortIdentity::of("/dev/ttyS0", &before);
+        assert_eq!(identity.usb, None);
+        assert!(identity.find(&after).is_none());
+    }
+}
@@ -0,0 +1,427 @@
+use std::{
+    collections::HashMap,
+    fs,
+    fs::OpenOptions,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use elf2flash_core::{
+    ProgressReporter,
+    boards::family::describe_family,
+    progress::ProgressRead,
+    uf2::{UF2_ABSOLUTE_FAMILY_ID, UF2_BLOCK_SIZE, Uf2Block, to_bin},
+    warnings::{WarningCode, Warnings},
+};
+use fatfs::{FileSystem, FsOptions, ReadWriteSeek};
+use thiserror::Error;
+use usbh_fatfs::{FatError, FatPartition, PartitionView, list_dir};
+
+use crate::{
+    commands::{
+        deploy::{
+            report::device_reports,
+            select::{DeviceSelector, check_missing_selectors, select_devices},
+            to_usb::{SessionUsb, get_plugged_in_boards},
+        },
+        dump::MAX_DUMP_SIZE,
+    },
+    exit_code::CliError,
+    progress_bar::ProgressBarReporter,
+};
+
+/// Name of the file Adafruit style bootloaders expose the current application as
+pub const CURRENT_UF2: &str = "CURRENT.UF2";
+
+#[derive(Args, Debug)]
+pub struct ReadArgs {
+    /// File to save the firmware read back from the board to
+    #[clap(short, long, value_name = "FILE")]
+    pub output: PathBuf,
+
+    /// Also save the main flash contents as a flat binary, gaps between blocks are filled with 0xff
+    #[clap(long, value_name = "FILE")]
+    pub bin: Option<PathBuf>,
+
+    /// The device to read from, same selectors as `deploy --device`, required with several
+    /// devices plugged in
+    #[clap(long = "device", value_name = "SELECTOR", value_delimiter = ',')]
+    pub devices: Vec<DeviceSelector>,
+
+    /// Read a CURRENT.UF2 larger than 64MiB
+    #[clap(long)]
+    pub force: bool,
+
+    /// Read from the FAT volume in this image file instead of the connected devices
+    #[clap(long, value_name = "IMAGE", hide = true)]
+    pub mock_volume: Option<PathBuf>,
+}
+
+#[derive(Error, Debug)]
+pub enum ReadBackError {
+    #[error(
+        "the bootloader doesn't export the firmware as {CURRENT_UF2}, the volume only has: {}",
+        .files.join(", ")
+    )]
+    NotExported { files: Vec<String> },
+    #[error(
+        "{CURRENT_UF2} is {size} bytes, more than the {max_size} byte limit (pass --force to read it anyway)"
+    )]
+    TooLarge { size: u64, max_size: u64 },
+    #[error("failed to list the bootloader volume")]
+    Fat(#[from] FatError),
+    #[error("failed to read {CURRENT_UF2}")]
+    Io(#[from] io::Error),
+}
+
+/// Read `CURRENT.UF2` from the root directory of a mounted bootloader volume.
+///
+/// Whether the bootloader exports the firmware is decided from the root listing, volumes without
+/// the file fail with [`ReadBackError::NotExported`]. The data is checked with
+/// [`uf2_inconsistencies`], anything it finds is raised as a warning.
+pub fn read_current_uf2<T: ReadWriteSeek>(
+    fatfs: &FileSystem<T>,
+    max_size: Option<u64>,
+    mut progress: impl ProgressReporter,
+    warnings: &mut Warnings,
+) -> Result<Vec<u8>, ReadBackError> {
+    let entries = list_dir(fatfs, "")?;
+    let Some(entry) = entries
+        .iter()
+        .find(|entry| !entry.is_dir && entry.name.eq_ignore_ascii_case(CURRENT_UF2))
+    else {
+        return Err(ReadBackError::NotExported {
+            files: entries.into_iter().map(|entry| entry.name).collect(),
+        });
+    };
+
+    let mut file = fatfs.root_dir().open_file(&entry.name)?;
+    let size = file.seek(SeekFrom::End(0))?;
+    if let Some(max_size) = max_size
+        && size > max_size
+    {
+        return Err(ReadBackError::TooLarge { size, max_size });
+    }
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut data = Vec::with_capacity(size as usize);
+    let mut reader = ProgressRead::new(file, &mut progress, size as usize);
+    reader.read_to_end(&mut data)?;
+    reader.finish();
+
+    for problem in uf2_inconsistencies(&data) {
+        warnings.push(
+            WarningCode::ReadbackInconsistent,
+            format!("{CURRENT_UF2} {problem}"),
+        );
+    }
+
+    Ok(data)
+}
+
+/// Everything that doesn't add up in a uf2 file, empty if it is consistent.
+///
+/// Every block must be valid, and the blocks of every family must be numbered from zero to one
+/// less than the block count they all claim.
+pub fn uf2_inconsistencies(uf2: &[u8]) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if uf2.is_empty() {
+        problems.push("is empty".to_string());
+    }
+    if !uf2.len().is_multiple_of(UF2_BLOCK_SIZE) {
+        problems.push(format!(
+            "is {} bytes, not a whole number of {UF2_BLOCK_SIZE} byte blocks",
+            uf2.len()
+        ));
+    }
+
+    let mut invalid = Vec::new();
+    // Block numbers and claimed block counts of every family, in the order they first appear
+    let mut families: Vec<Option<u32>> = Vec::new();
+    let mut numbering: HashMap<Option<u32>, (Vec<u32>, Vec<u32>)> = HashMap::new();
+
+    for (index, block) in uf2.chunks_exact(UF2_BLOCK_SIZE).enumerate() {
+        let Ok(block) = Uf2Block::from_bytes(
+            block
+                .try_into()
+                .expect("chunks_exact always yields whole blocks"),
+        ) else {
+            invalid.push(index);
+            continue;
+        };
+
+        // Absolute blocks keep the numbering of the file they were merged from
+        let family_id = block.family_id();
+        if family_id == Some(UF2_ABSOLUTE_FAMILY_ID) {
+            continue;
+        }
+
+        let (block_nos, num_blocks) = numbering.entry(family_id).or_insert_with(|| {
+            families.push(family_id);
+            Default::default()
+        });
+        block_nos.push(block.block_no());
+        num_blocks.push(block.num_blocks());
+    }
+
+    if let Some(first) = invalid.first() {
+        problems.push(format!(
+            "has {} invalid block(s), the first is block {first}",
+            invalid.len()
+        ));
+    }
+
+    for family_id in families {
+        let family = match family_id {
+            Some(family_id) => format!("family {}", describe_family(family_id)),
+            None => "blocks without a family id".to_string(),
+        };
+        let (block_nos, num_blocks) = &numbering[&family_id];
+        let count = block_nos.len() as u32;
+
+        if num_blocks.iter().any(|&num_blocks| num_blocks != count) {
+            problems.push(format!(
+                "has {count} {family} blocks, but they claim {}",
+                describe_counts(num_blocks)
+            ));
+        }
+
+        let mut sorted = block_nos.clone();
+        sorted.sort_unstable();
+        if !sorted.iter().copied().eq(0..count) {
+            problems.push(format!(
+                "has {family} blocks that are not numbered 0 to {}",
+                count.saturating_sub(1)
+            ));
+        }
+    }
+
+    problems
+}
+
+/// The distinct values of `counts`, e.g. `12 and 16`
+fn describe_counts(counts: &[u32]) -> String {
+    let mut counts = counts.to_vec();
+    counts.sort_unstable();
+    counts.dedup();
+    let counts: Vec<String> = counts.iter().map(u32::to_string).collect();
+    counts.join(" and ")
+}
+
+/// Save the read back `uf2` to `output`, and its main flash contents to `bin` if given.
+fn save(uf2: &[u8], output: &Path, bin: Option<&Path>) -> Result<()> {
+    fs::write(output, uf2).with_context(|| format!("Failed to write {}", output.display()))?;
+    log::info!("Saved {} bytes to {}", uf2.len(), output.display());
+
+    if let Some(bin) = bin {
+        let image = to_bin(uf2, 0xff).context("Failed to convert the firmware to a binary")?;
+        fs::write(bin, &image.data)
+            .with_context(|| format!("Failed to write {}", bin.display()))?;
+        log::info!(
+            "Saved {} bytes starting at {:#010x} to {}",
+            image.data.len(),
+            image.base_address,
+            bin.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Read `CURRENT.UF2` from the first partition of a device that has one.
+fn read_from_partitions(
+    storage_usb: &mut SessionUsb,
+    max_size: Option<u64>,
+    warnings: &mut Warnings,
+) -> Result<Vec<u8>> {
+    let partitions = FatPartition::list_partitions(storage_usb)?;
+
+    let opened = storage_usb.open()?;
+    let mut block_device = opened.block_device()?;
+
+    let mut files = Vec::new();
+    for partition in &partitions {
+        let view = PartitionView::new(&mut block_device, partition.first_byte, partition.length)?;
+        let fatfs = FileSystem::new(view, FsOptions::new())?;
+
+        match read_current_uf2(&fatfs, max_size, ProgressBarReporter::new(), warnings) {
+            Err(ReadBackError::NotExported { files: listed }) => files.extend(listed),
+            result => return Ok(result?),
+        }
+    }
+
+    Err(ReadBackError::NotExported { files }.into())
+}
+
+pub fn read(args: ReadArgs) -> Result<()> {
+    let ReadArgs {
+        output,
+        bin,
+        devices,
+        force,
+        mock_volume,
+    } = args;
+
+    let max_size = (!force).then_some(MAX_DUMP_SIZE);
+    let mut warnings = Warnings::new();
+
+    let uf2 = if let Some(image) = mock_volume {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&image)
+            .with_context(|| format!("Failed to open the volume image {}", image.display()))?;
+        let fatfs = FileSystem::new(file, FsOptions::new())
+            .with_context(|| format!("Failed to mount the volume image {}", image.display()))?;
+
+        log::info!(
+            "Reading {CURRENT_UF2} from volume image {}",
+            image.display()
+        );
+        read_current_uf2(&fatfs, max_size, ProgressBarReporter::new(), &mut warnings)?
+    } else {
+        log::info!("Getting plugged in boards\n");
+
+        let mut plugged_in_boards = get_plugged_in_boards(&mut warnings)?;
+
+        let reports = device_reports(&mut plugged_in_boards, &devices);
+
+        let selection = (!devices.is_empty()).then(|| select_devices(&reports, &devices));
+        if let Some(selection) = &selection {
+            check_missing_selectors(selection, &devices, &reports, false, &mut warnings)?;
+        }
+
+        let mut selected: Vec<_> = plugged_in_boards
+            .into_iter()
+            .zip(&reports)
+            .filter(|(_, report)| {
+                selection
+                    .as_ref()
+                    .is_none_or(|s| s.is_selected(report.index))
+            })
+            .collect();
+
+        let ((_usb, _board, mut storage_usb), report) = match selected.len() {
+            0 => bail!(CliError::NoDevice),
+            1 => selected.remove(0),
+            count => bail!(CliError::MultipleDevices {
+                count,
+                action: "read from",
+            }),
+        };
+
+        log::info!("Reading {CURRENT_UF2} from {}", report.summary());
+        read_from_partitions(&mut storage_usb, max_size, &mut warnings)
+            .with_context(|| format!("Failed to read from {}", report.summary()))?
+    };
+
+    save(&uf2, &output, bin.as_deref())?;
+
+    if let Some(summary) = warnings.summary() {
+        log::warn!("{summary}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FailingDisk, fat_image};
+    use elf2flash_core::NoProgress;
+
+    const HELLO_USB_UF2: &[u8] =
+        include_bytes!("../../../elf2flash-core/tests/rp2040/hello_usb.uf2");
+
+    #[test]
+    fn reads_current_uf2() {
+        let mut image = fat_image(&[
+            ("INFO_UF2.TXT", b"UF2 Bootloader v3.0\n"),
+            ("CURRENT.UF2", HELLO_USB_UF2),
+        ]);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+
+        let mut warnings = Warnings::new();
+        let uf2 = read_current_uf2(&fatfs, None, NoProgress, &mut warnings).unwrap();
+        assert_eq!(uf2, HELLO_USB_UF2);
+        assert!(warnings.is_empty());
+
+        assert!(matches!(
+            read_current_uf2(&fatfs, Some(1024), NoProgress, &mut warnings),
+            Err(ReadBackError::TooLarge { max_size: 1024, .. })
+        ));
+    }
+
+    #[test]
+    fn volume_without_current_uf2_is_not_exported() {
+        let mut image = fat_image(&[("INFO_UF2.TXT", b"info"), ("INDEX.HTM", b"<html></html>")]);
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+
+        let err = read_current_uf2(&fatfs, None, NoProgress, &mut Warnings::new()).unwrap_err();
+        assert!(
+            matches!(&err, ReadBackError::NotExported { files } if files == &["INFO_UF2.TXT", "INDEX.HTM"])
+        );
+        assert_eq!(
+            err.to_string(),
+            "the bootloader doesn't export the firmware as CURRENT.UF2, the volume only has: INFO_UF2.TXT, INDEX.HTM"
+        );
+    }
+
+    #[test]
+    fn usb_failure_is_the_deepest_cause() {
+        let mut disk = FailingDisk::new(fat_image(&[("CURRENT.UF2", HELLO_USB_UF2)]));
+        let failing = disk.failing.clone();
+        let fatfs = FileSystem::new(&mut disk, FsOptions::new()).unwrap();
+        failing.set(true);
+
+        let err = read_current_uf2(&fatfs, None, NoProgress, &mut Warnings::new()).unwrap_err();
+        let err = anyhow::Error::from(err);
+        assert_eq!(
+            format!("{err:#}"),
+            "failed to list the bootloader volume: io error: READ(10) failed: bulk transfer \
+             failed: Operation timed out"
+        );
+        assert_eq!(err.root_cause().to_string(), "Operation timed out");
+    }
+
+    #[test]
+    fn inconsistent_uf2_is_reported() {
+        assert!(uf2_inconsistencies(HELLO_USB_UF2).is_empty());
+
+        // A bootloader that stopped short, the remaining blocks still claim the full count
+        let truncated = &HELLO_USB_UF2[..3 * UF2_BLOCK_SIZE + 100];
+        let blocks = HELLO_USB_UF2.len() / UF2_BLOCK_SIZE;
+        assert_eq!(
+            uf2_inconsistencies(truncated),
+            [
+                format!(
+                    "is {} bytes, not a whole number of 512 byte blocks",
+                    truncated.len()
+                ),
+                format!("has 3 family 0xe48bff56 (RP2040) blocks, but they claim {blocks}"),
+            ]
+        );
+
+        let mut shuffled = HELLO_USB_UF2.to_vec();
+        shuffled[UF2_BLOCK_SIZE..UF2_BLOCK_SIZE + 4].fill(0);
+        shuffled[2 * UF2_BLOCK_SIZE + 20..2 * UF2_BLOCK_SIZE + 24]
+            .copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(
+            uf2_inconsistencies(&shuffled),
+            [
+                "has 1 invalid block(s), the first is block 1".to_string(),
+                format!(
+                    "has {} family 0xe48bff56 (RP2040) blocks, but they claim {blocks}",
+                    blocks - 1
+                ),
+                format!(
+                    "has family 0xe48bff56 (RP2040) blocks that are not numbered 0 to {}",
+                    blocks - 2
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{Result, anyhow, bail};
+use clap::Args;
+use elf2flash_core::{
+    boards::BoardIter,
+    warnings::{WarningCode, Warnings},
+};
+
+use crate::{
+    BoardValueParser,
+    cancel::{CancellationToken, Cancelled},
+    commands::deploy::{
+        backup::{find_backup, load_backup_file},
+        mock::deploy_blocks_to_image,
+        partition::{PartitionSelector, choose_partitions},
+        report::device_reports,
+        select::{DeviceSelector, check_missing_selectors, select_devices},
+        to_usb::{WriteOptions, board_uf2_partitions, deploy_to_usb, get_plugged_in_boards},
+    },
+    exit_code::CliError,
+};
+
+#[derive(Args, Debug)]
+pub struct RollbackArgs {
+    /// Backup folder created by `deploy --backup`, or the directory containing them (the newest
+    /// backup is used)
+    #[clap(long, value_name = "DIR", default_value = ".")]
+    pub from: PathBuf,
+
+    /// File in the backup to flash
+    #[clap(long, value_name = "NAME", default_value = "CURRENT.UF2")]
+    pub file: String,
+
+    /// Board to restore onto, required for generic uf2 devices
+    #[clap(short, long, value_parser = BoardValueParser, hide_possible_values = true)]
+    pub board: Option<String>,
+
+    /// Only restore onto the matching devices, same selectors as `deploy --device`
+    #[clap(long = "device", value_name = "SELECTOR", value_delimiter = ',')]
+    pub devices: Vec<DeviceSelector>,
+
+    /// Don't fail when a --device selector matches no device
+    #[clap(long, requires = "devices")]
+    pub allow_missing: bool,
+
+    /// Restore onto the uf2 partition with this index (from 0) or volume label, instead of the one
+    /// picked for the board, when a device has several
+    #[clap(long, value_name = "INDEX|LABEL", conflicts_with = "all_partitions")]
+    pub partition: Option<PartitionSelector>,
+
+    /// Restore onto every uf2 partition of a device, not just the one picked for the board
+    #[clap(long)]
+    pub all_partitions: bool,
+
+    /// Restore onto the FAT volume in this image file instead of the connected devices
+    #[clap(long, value_name = "IMAGE", hide = true, requires = "board")]
+    pub mock_volume: Option<PathBuf>,
+}
+
+pub fn rollback(args: RollbackArgs) -> Result<()> {
+    let RollbackArgs {
+        from,
+        file,
+        board,
+        devices,
+        allow_missing,
+        partition: partition_selector,
+        all_partitions,
+        mock_volume,
+    } = args;
+
+    let cancel = CancellationToken::ctrl_c()?;
+
+    let dir = find_backup(&from)?;
+    let backup = load_backup_file(&dir, &file)?;
+
+    log::info!(
+        "Restoring {} ({} bytes, crc32 {:08x}) backed up from board '{}' in {}",
+        backup.entry.name,
+        backup.entry.size,
+        backup.entry.crc32,
+        backup.manifest.board_name,
+        dir.display()
+    );
+
+    let mut warnings = Warnings::new();
+
+    if let (Some(image), Some(board)) = (mock_volume, &board) {
+        let target_board = BoardIter::find_by_name(board)
+            .expect("Should be impossible for unrecognized board to appear here");
+        backup.check_uf2_for_family(target_board.family_id())?;
+
+        deploy_blocks_to_image(
+            backup.uf2_blocks(),
+            &image,
+            target_board.as_ref(),
+            Duration::ZERO,
+            &mut warnings,
+            &cancel,
+        )?;
+
+        if let Some(summary) = warnings.summary() {
+            log::warn!("{summary}");
+        }
+        return Ok(());
+    }
+
+    log::info!("Getting plugged in boards\n");
+
+    let mut plugged_in_boards = get_plugged_in_boards(&mut warnings)?;
+
+    if plugged_in_boards.is_empty() {
+        bail!(CliError::NoDevice);
+    }
+
+    let reports = device_reports(&mut plugged_in_boards, &devices);
+
+    let selection = (!devices.is_empty()).then(|| select_devices(&reports, &devices));
+    if let Some(selection) = &selection {
+        check_missing_selectors(selection, &devices, &reports, allow_missing, &mut warnings)?;
+    }
+
+    let mut restored = 0;
+    // The first failed write, to exit with the code of what went wrong when nothing was restored
+    let mut write_error = None;
+    for (index, (_usb, plugged_in_board, mut storage_usb)) in
+        plugged_in_boards.into_iter().enumerate()
+    {
+        if selection.as_ref().is_some_and(|s| !s.is_selected(index)) {
+            continue;
+        }
+
+        let target_board = match (plugged_in_board, &board) {
+            (Some(board), _) => board,
+            (None, Some(board)) => BoardIter::find_by_name(board)
+                .expect("Should be impossible for unrecognized board to appear here"),
+            (None, None) => {
+                warnings.push(
+                    WarningCode::DeviceSkipped,
+                    format!(
+                        "Skipped device {}, cannot restore to generic uf2 device without a board \
+                         specified",
+                        reports[index].summary()
+                    ),
+                );
+                continue;
+            }
+        };
+
+        // Refuse before touching the volume, flashing another chip's firmware can't be undone by
+        // the bootloader
+        backup.check_uf2_for_family(target_board.family_id())?;
+
+        let partitions =
+            board_uf2_partitions(target_board.as_ref(), &mut storage_usb).and_then(|partitions| {
+                choose_partitions(
+                    partitions,
+                    target_board.as_ref(),
+                    partition_selector.as_ref(),
+                    all_partitions,
+                )
+            });
+        let partitions = match partitions {
+            Ok(partitions) => partitions,
+            Err(err) => {
+                warnings.push(
+                    WarningCode::DeviceSkipped,
+                    format!("Skipped device {}, {err:#}", reports[index].summary()),
+                );
+                continue;
+            }
+        };
+
+        for partition in partitions {
+            match deploy_to_usb(
+                backup.uf2_blocks(),
+                &partition,
+                target_board.as_ref(),
+                &mut storage_usb,
+                WriteOptions::default(),
+                &mut warnings,
+                &cancel,
+            ) {
+                Ok(_) => {
+                    restored += 1;
+                    log::info!(
+                        "Restored {} onto board '{}'",
+                        backup.entry.name,
+                        target_board.board_name()
+                    );
+                }
+                Err(err) if err.is::<Cancelled>() => {
+                    storage_usb.release();
+                    return Err(err);
+                }
+                Err(err) => {
+                    warnings.push(
+                        WarningCode::WriteFailed,
+                        format!(
+                            "Failed to restore backup to board '{}' with error: {err:#}",
+                            target_board.board_name()
+                        ),
+                    );
+                    write_error.get_or_insert(err);
+                }
+            }
+        }
+    }
+
+    if let Some(summary) = warnings.summary() {
+        log::warn!("{summary}");
+    }
+
+    if restored == 0 {
+        let message = format!("{} wasn't restored onto any device", backup.entry.name);
+        return Err(match write_error {
+            Some(err) => err.context(message),
+            None => anyhow!(message),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use elf2flash_core::{
+    boards::{BoardInfo, BoardIter, family::describe_family},
+    uf2::{UF2_BLOCK_SIZE, Uf2Block, verify_against_elf},
+};
+use std::{fs, path::PathBuf};
+
+use crate::{
+    BoardValueParser,
+    commands::convert::{BoardSpec, resolve_board},
+    exit_code::CliError,
+    family_parser,
+};
+
+/// Addresses printed for every kind of difference, the rest are only counted
+const MAX_LISTED: usize = 5;
+
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// ELF file the uf2 file should have been converted from
+    pub elf: PathBuf,
+
+    /// UF2 file to check
+    pub uf2: PathBuf,
+
+    /// Explicit board, found from the family id of the uf2 file by default
+    #[clap(short, long, value_parser = BoardValueParser, hide_possible_values = true)]
+    pub board: Option<String>,
+
+    /// Override family ID, either a number or a name from the uf2 family list (e.g. SAMD51)
+    #[clap(short, long, value_parser = family_parser)]
+    pub family: Option<u32>,
+}
+
+/// The board of the first block of `uf2` with a known family id, or just its family id.
+fn spec_from_uf2(uf2: &[u8]) -> BoardSpec {
+    let family_id = uf2
+        .chunks_exact(UF2_BLOCK_SIZE)
+        .filter_map(|block| Uf2Block::from_bytes(block.try_into().ok()?).ok())
+        .find_map(|block| block.family_id());
+
+    let board = family_id.and_then(|family_id| {
+        BoardIter::new()
+            .find(|board| board.family_id() == family_id)
+            .map(|board| board.board_name().into_owned())
+    });
+    match board {
+        Some(board) => BoardSpec {
+            board: Some(board),
+            ..Default::default()
+        },
+        None => BoardSpec {
+            family: family_id,
+            ..Default::default()
+        },
+    }
+}
+
+/// Log the first addresses in `addresses`, under `kind`.
+fn list_addresses(kind: &str, addresses: &[u32]) {
+    if addresses.is_empty() {
+        return;
+    }
+
+    let listed: Vec<String> = addresses
+        .iter()
+        .take(MAX_LISTED)
+        .map(|addr| format!("{addr:#010x}"))
+        .collect();
+    let more = match addresses.len().saturating_sub(MAX_LISTED) {
+        0 => String::new(),
+        more => format!(" and {more} more"),
+    };
+    log::info!(
+        "    {} {kind}: {}{more}",
+        addresses.len(),
+        listed.join(", ")
+    );
+}
+
+pub fn verify(args: VerifyArgs) -> Result<()> {
+    let VerifyArgs {
+        elf,
+        uf2,
+        board,
+        family,
+    } = args;
+
+    let elf_data = fs::read(&elf).with_context(|| format!("Failed to read {}", elf.display()))?;
+    let uf2_data = fs::read(&uf2).with_context(|| format!("Failed to read {}", uf2.display()))?;
+
+    let spec = if board.is_none() && family.is_none() {
+        spec_from_uf2(&uf2_data)
+    } else {
+        BoardSpec {
+            board,
+            family,
+            ..Default::default()
+        }
+    };
+    let board = resolve_board(&spec)?;
+
+    log::info!(
+        "Verifying {} against {} for board '{}' (family id {})",
+        uf2.display(),
+        elf.display(),
+        board.board_name(),
+        describe_family(board.family_id())
+    );
+
+    let report = verify_against_elf(uf2_data.as_slice(), &elf_data, &board)
+        .with_context(|| format!("Failed to verify {}", uf2.display()))?;
+
+    if report.is_consistent() {
+        log::info!("All {} blocks match the ELF", report.blocks_checked);
+        return Ok(());
+    }
+
+    list_addresses("blocks differ from the ELF", &report.mismatched);
+    list_addresses("pages of the ELF are missing", &report.missing);
+    list_addresses("blocks aren't in the ELF", &report.extra);
+    bail!(CliError::VerificationFailed(format!(
+        "{} doesn't match {}, it may be stale",
+        uf2.display(),
+        elf.display()
+    )));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elf2flash_core::uf2::{BlockHeaderTemplate, FileSizeField};
+
+    #[test]
+    fn board_is_found_from_the_uf2() {
+        let uf2 = include_bytes!("../../../elf2flash-core/tests/rp2040/hello_usb.uf2");
+        assert_eq!(spec_from_uf2(uf2).board.as_deref(), Some("rp2040"));
+
+        let unknown = BlockHeaderTemplate::new(FileSizeField::FamilyId(0x12345678))
+            .block(0x10000000, 0, [0; 476])
+            .to_bytes();
+        assert_eq!(spec_from_uf2(&unknown).family, Some(0x12345678));
+        assert_eq!(spec_from_uf2(&[]), BoardSpec::default());
+    }
+}
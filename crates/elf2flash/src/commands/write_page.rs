@@ -0,0 +1,393 @@
+use std::{fs, io, path::PathBuf, str::FromStr, time::Duration};
+
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use elf2flash_core::{
+    ConversionSummary, NoProgress, Uf2Options,
+    boards::{BoardInfo, BoardIter, family::describe_family},
+    pages_to_uf2,
+    warnings::{WarningCode, Warnings},
+};
+use thiserror::Error;
+
+use crate::{
+    BoardValueParser, byte_parser,
+    cancel::{CancellationToken, Cancelled},
+    commands::deploy::{
+        mock::deploy_blocks_to_image,
+        partition::{PartitionSelector, choose_partitions},
+        report::device_reports,
+        select::{DeviceSelector, check_missing_selectors, select_devices},
+        to_usb::{
+            WriteOptions, board_uf2_partitions, deploy_to_usb, get_plugged_in_boards, uf2_blocks,
+        },
+    },
+    num_parser,
+};
+
+#[derive(Args, Debug)]
+pub struct WritePageArgs {
+    /// Flash address to write the data to, e.g. 0x101FF000
+    #[clap(long, value_parser = num_parser)]
+    pub address: u32,
+
+    /// The bytes to write, either @<FILE> to read them from a file or hex digits like 0xdeadbeef
+    #[clap(long, value_name = "@FILE|HEX")]
+    pub data: PageData,
+
+    /// Board the address belongs to, only devices of this board are written to
+    #[clap(short, long, value_parser = BoardValueParser, hide_possible_values = true)]
+    pub board: String,
+
+    /// Byte to pad the rest of the touched pages and the filler pages with, 0xff matches erased
+    /// flash
+    #[clap(long, value_name = "BYTE", value_parser = byte_parser, default_value = "0xff")]
+    pub pad_byte: u8,
+
+    /// Only write the pages the data covers, instead of filling its flash sector
+    #[clap(long)]
+    pub no_sector_fill: bool,
+
+    /// Only write to the matching devices, same selectors as `deploy --device`
+    #[clap(long = "device", value_name = "SELECTOR", value_delimiter = ',')]
+    pub devices: Vec<DeviceSelector>,
+
+    /// Write to the uf2 partition with this index (from 0) or volume label, instead of the one
+    /// picked for the board, when a device has several
+    #[clap(long, value_name = "INDEX|LABEL", conflicts_with = "all_partitions")]
+    pub partition: Option<PartitionSelector>,
+
+    /// Write to every uf2 partition of a device, not just the one picked for the board
+    #[clap(long)]
+    pub all_partitions: bool,
+
+    /// Write onto the FAT volume in this image file instead of the connected devices
+    #[clap(long, value_name = "IMAGE", hide = true)]
+    pub mock_volume: Option<PathBuf>,
+}
+
+/// The bytes to write, as given to `--data`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PageData {
+    /// `@<FILE>`, read once the command runs
+    File(PathBuf),
+    /// Hex digits, optionally prefixed with `0x`
+    Bytes(Vec<u8>),
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum PageDataParseError {
+    #[error("expected @<FILE> or hex bytes")]
+    Empty,
+    #[error("'{0}' is not a hex byte")]
+    InvalidByte(String),
+    #[error("the hex bytes have an odd number of digits")]
+    OddLength,
+}
+
+impl FromStr for PageData {
+    type Err = PageDataParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix('@') {
+            if path.is_empty() {
+                return Err(PageDataParseError::Empty);
+            }
+            return Ok(Self::File(PathBuf::from(path)));
+        }
+
+        let hex: String = s
+            .strip_prefix("0x")
+            .unwrap_or(s)
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        if hex.is_empty() {
+            return Err(PageDataParseError::Empty);
+        }
+        if !hex.len().is_multiple_of(2) {
+            return Err(PageDataParseError::OddLength);
+        }
+
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| {
+                let byte = hex
+                    .get(i..i + 2)
+                    .ok_or_else(|| PageDataParseError::InvalidByte(hex.clone()))?;
+                u8::from_str_radix(byte, 16)
+                    .map_err(|_| PageDataParseError::InvalidByte(byte.to_string()))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self::Bytes)
+    }
+}
+
+impl PageData {
+    /// The bytes to write, reading the file if one was given.
+    pub fn load(&self) -> io::Result<Vec<u8>> {
+        match self {
+            Self::File(path) => fs::read(path),
+            Self::Bytes(bytes) => Ok(bytes.clone()),
+        }
+    }
+}
+
+/// Build the uf2 file writing `data` to `address`, refusing addresses outside the board's flash.
+pub fn build_page_uf2(
+    address: u32,
+    data: &[u8],
+    board: &dyn BoardInfo,
+    options: &Uf2Options,
+) -> Result<(Vec<u8>, ConversionSummary)> {
+    let mut uf2 = Vec::new();
+    let summary = pages_to_uf2(address as u64, data, &mut uf2, board, options, NoProgress)
+        .with_context(|| {
+            format!(
+                "Can't write {} bytes to {address:#010x} on {}",
+                data.len(),
+                board.board_name()
+            )
+        })?;
+
+    if summary.not_main_flash {
+        bail!(
+            "{address:#010x} is in the RAM of {}, write-page only writes to flash",
+            board.board_name()
+        );
+    }
+
+    Ok((uf2, summary))
+}
+
+pub fn write_page(args: WritePageArgs) -> Result<()> {
+    let WritePageArgs {
+        address,
+        data,
+        board,
+        pad_byte,
+        no_sector_fill,
+        devices,
+        partition,
+        all_partitions,
+        mock_volume,
+    } = args;
+
+    let board =
+        BoardIter::find_by_name(&board).expect("Should be impossible for an unknown board here");
+    let data = data.load().context("Failed to read the --data file")?;
+
+    let options = Uf2Options {
+        block_padding_byte: pad_byte,
+        fill_sectors: !no_sector_fill,
+        ..Default::default()
+    };
+    let (uf2, summary) = build_page_uf2(address, &data, board.as_ref(), &options)?;
+
+    log::info!(
+        "Writing {} bytes to {address:#010x} in {} blocks",
+        data.len(),
+        summary.num_blocks
+    );
+    if summary.filler_blocks > 0 {
+        log::info!(
+            "{} more pages of the flash sector are written with {pad_byte:#04x}",
+            summary.filler_blocks
+        );
+    }
+
+    let cancel = CancellationToken::ctrl_c()?;
+    let mut warnings = Warnings::new();
+
+    if let Some(image) = mock_volume {
+        deploy_blocks_to_image(
+            uf2_blocks(&uf2),
+            &image,
+            board.as_ref(),
+            Duration::ZERO,
+            &mut warnings,
+            &cancel,
+        )?;
+    } else {
+        write_to_devices(
+            &uf2,
+            board.as_ref(),
+            &devices,
+            partition.as_ref(),
+            all_partitions,
+            &mut warnings,
+            &cancel,
+        )?;
+    }
+
+    if let Some(summary) = warnings.summary() {
+        log::warn!("{summary}");
+    }
+
+    Ok(())
+}
+
+/// Flash `uf2` onto the connected devices of `board` that match `selectors`, onto the uf2
+/// partitions `--partition` and `--all-partitions` choose, see [`choose_partitions`].
+fn write_to_devices(
+    uf2: &[u8],
+    board: &dyn BoardInfo,
+    selectors: &[DeviceSelector],
+    partition_selector: Option<&PartitionSelector>,
+    all_partitions: bool,
+    warnings: &mut Warnings,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    log::info!("Getting plugged in boards\n");
+
+    let mut plugged_in_boards = get_plugged_in_boards(warnings)?;
+
+    if plugged_in_boards.is_empty() {
+        log::warn!("No uf2 devices found.");
+        return Ok(());
+    }
+
+    let reports = device_reports(&mut plugged_in_boards, selectors);
+
+    let selection = (!selectors.is_empty()).then(|| select_devices(&reports, selectors));
+    if let Some(selection) = &selection {
+        check_missing_selectors(selection, selectors, &reports, false, warnings)?;
+    }
+
+    for (index, (_usb, plugged_in_board, mut storage_usb)) in
+        plugged_in_boards.into_iter().enumerate()
+    {
+        cancel.check()?;
+
+        if selection.as_ref().is_some_and(|s| !s.is_selected(index)) {
+            continue;
+        }
+
+        // Pages of another chip's flash layout could land anywhere, generic devices are trusted
+        // to be the board that was asked for
+        if let Some(plugged_in_board) =
+            plugged_in_board.filter(|plugged_in| plugged_in.family_id() != board.family_id())
+        {
+            warnings.push(
+                WarningCode::DeviceSkipped,
+                format!(
+                    "Skipped device {}, it is a {} (family id: {}) not a {}",
+                    reports[index].summary(),
+                    plugged_in_board.board_name(),
+                    describe_family(plugged_in_board.family_id()),
+                    board.board_name()
+                ),
+            );
+            continue;
+        }
+
+        let partitions = match board_uf2_partitions(board, &mut storage_usb) {
+            Ok(partitions) => partitions,
+            Err(err) => {
+                warnings.push(
+                    WarningCode::DeviceSkipped,
+                    format!(
+                        "Skipped device {}, failed to find its uf2 partition: {err:#}",
+                        reports[index].summary()
+                    ),
+                );
+                continue;
+            }
+        };
+        let partitions =
+            match choose_partitions(partitions, board, partition_selector, all_partitions) {
+                Ok(partitions) => partitions,
+                Err(err) => {
+                    warnings.push(
+                        WarningCode::DeviceSkipped,
+                        format!("Skipped device {}, {err:#}", reports[index].summary()),
+                    );
+                    continue;
+                }
+            };
+
+        for partition in partitions {
+            match deploy_to_usb(
+                uf2_blocks(uf2),
+                &partition,
+                board,
+                &mut storage_usb,
+                WriteOptions::default(),
+                warnings,
+                cancel,
+            ) {
+                Ok(_) => (),
+                Err(err) if err.is::<Cancelled>() => {
+                    storage_usb.release();
+                    return Err(err);
+                }
+                Err(err) => warnings.push(
+                    WarningCode::WriteFailed,
+                    format!(
+                        "Failed to write the pages to board '{}' with error: {err:#}",
+                        board.board_name()
+                    ),
+                ),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use elf2flash_core::boards::RP2040;
+
+    #[test]
+    fn parses_hex_data() {
+        assert_eq!(
+            "0xdeadBEEF".parse::<PageData>(),
+            Ok(PageData::Bytes(vec![0xde, 0xad, 0xbe, 0xef]))
+        );
+        assert_eq!(
+            "01 02\t03".parse::<PageData>(),
+            Ok(PageData::Bytes(vec![1, 2, 3]))
+        );
+        assert_eq!("".parse::<PageData>(), Err(PageDataParseError::Empty));
+        assert_eq!("0x".parse::<PageData>(), Err(PageDataParseError::Empty));
+        assert_eq!(
+            "abc".parse::<PageData>(),
+            Err(PageDataParseError::OddLength)
+        );
+        assert_eq!(
+            "12zz".parse::<PageData>(),
+            Err(PageDataParseError::InvalidByte("zz".to_string()))
+        );
+    }
+
+    #[test]
+    fn parses_data_file() {
+        assert_eq!(
+            "@settings.bin".parse::<PageData>(),
+            Ok(PageData::File(PathBuf::from("settings.bin")))
+        );
+        assert_eq!("@".parse::<PageData>(), Err(PageDataParseError::Empty));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("settings.bin");
+        fs::write(&path, [1, 2, 3]).unwrap();
+        assert_eq!(PageData::File(path).load().unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn page_uf2_is_flash_only() {
+        let options = Uf2Options::default();
+
+        let (uf2, summary) = build_page_uf2(0x101ff000, &[0x11; 300], &RP2040, &options).unwrap();
+        assert_eq!(summary.num_blocks, 2);
+        assert_eq!(uf2.len(), 2 * 512);
+
+        let err = build_page_uf2(0x20000000, &[0x11; 16], &RP2040, &options).unwrap_err();
+        assert!(err.to_string().contains("RAM"), "{err}");
+
+        let err = build_page_uf2(0x30000000, &[0x11; 16], &RP2040, &options).unwrap_err();
+        assert!(err.to_string().contains("0x30000000"), "{err}");
+    }
+}
@@ -0,0 +1,436 @@
+//! Defaults for the flags of `convert`, `deploy` and `monitor`, from config files instead of the command
+//! line:
+//!
+//! ```toml
+//! board = "rp2350"
+//! serial = true
+//! term = true
+//! ```
+//!
+//! A flag takes its value from, highest precedence first:
+//!
+//! 1. the command line
+//! 2. the `ELF2FLASH_*` environment variables, see [`BOARD_ENV`] and the `env` of the flags
+//! 3. the nearest `elf2flash.toml`, looking in the current directory and then its parents
+//! 4. `$XDG_CONFIG_HOME/elf2flash/config.toml`, or `~/.config/elf2flash/config.toml`
+//! 5. the built-in default
+//!
+//! The files are merged key by key. A `--board` or `--family` on the command line, or their
+//! variables, replace the board, family, page size and erase size of the files, as those describe
+//! one board together.
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    board_parser,
+    commands::{
+        convert::{ConvertArgs, batch::ManifestFamily},
+        deploy::{Backend, DeployArgs, SerialMode},
+        monitor::MonitorArgs,
+    },
+    family_parser,
+};
+
+/// The config file of a project, looked for in the current directory and its parents
+pub const PROJECT_CONFIG_FILE: &str = "elf2flash.toml";
+
+/// Default of `--board`. Read by hand rather than by clap, so that the command line's `--family`
+/// can still replace it like it replaces the board of the config files
+pub const BOARD_ENV: &str = "ELF2FLASH_BOARD";
+
+/// Default of `--family`, a number or a name from the uf2 family list
+pub const FAMILY_ENV: &str = "ELF2FLASH_FAMILY";
+
+/// `$XDG_CONFIG_HOME/elf2flash`, or `~/.config/elf2flash`
+pub fn config_dir() -> Option<PathBuf> {
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(|home| Path::new(&home).join(".config"))
+        })?;
+    Some(config.join("elf2flash"))
+}
+
+/// The config file of the user, see [`config_dir`]
+pub fn global_config_file() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.toml"))
+}
+
+/// The nearest [`PROJECT_CONFIG_FILE`] in `dir` or one of its parents.
+pub fn project_config_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+/// The keys of a config file, each the default of the flag of the same name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub board: Option<String>,
+    /// A number or a name from the uf2 family list, like `"SAMD51"`
+    pub family: Option<ManifestFamily>,
+    pub page_size: Option<u32>,
+    pub flash_sector_erase_size: Option<u64>,
+    /// Baud rate of the serial port `deploy --serial` connects to
+    pub baud: Option<u32>,
+    /// Connect to serial after deploy, like `--serial`
+    pub serial: Option<bool>,
+    pub term: Option<bool>,
+    pub backend: Option<Backend>,
+}
+
+impl Config {
+    /// Parse a config file, checking the board and family names.
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let config: Config = toml::from_str(text)?;
+        if let Some(board) = &config.board {
+            board_parser(board).map_err(|err| anyhow!(err))?;
+        }
+        config.family_id()?;
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::from_toml(&text).with_context(|| format!("Failed to load {}", path.display()))
+    }
+
+    /// The board and family of [`BOARD_ENV`] and [`FAMILY_ENV`], looked up with `var`. Empty
+    /// variables count as unset.
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let var = |name| var(name).filter(|value| !value.is_empty());
+        let board = var(BOARD_ENV)
+            .map(|board| board_parser(&board).map_err(|err| anyhow!(err)))
+            .transpose()
+            .with_context(|| format!("Invalid {BOARD_ENV}"))?;
+        let family = var(FAMILY_ENV)
+            .map(|family| family_parser(&family).map_err(|err| anyhow!("{err} '{family}'")))
+            .transpose()
+            .with_context(|| format!("Invalid {FAMILY_ENV}"))?;
+
+        Ok(Config {
+            board,
+            family: family.map(ManifestFamily::Id),
+            ..Default::default()
+        })
+    }
+
+    /// The family id of `family`, resolving a family name.
+    pub fn family_id(&self) -> Result<Option<u32>> {
+        match &self.family {
+            Some(ManifestFamily::Id(id)) => Ok(Some(*id)),
+            Some(ManifestFamily::Name(name)) => family_parser(name)
+                .map(Some)
+                .map_err(|err| anyhow!("{err} '{name}'")),
+            None => Ok(None),
+        }
+    }
+
+    /// `self`, with the keys it doesn't set taken from `lower`.
+    pub fn or(self, lower: Config) -> Config {
+        Config {
+            board: self.board.or(lower.board),
+            family: self.family.or(lower.family),
+            page_size: self.page_size.or(lower.page_size),
+            flash_sector_erase_size: self
+                .flash_sector_erase_size
+                .or(lower.flash_sector_erase_size),
+            baud: self.baud.or(lower.baud),
+            serial: self.serial.or(lower.serial),
+            term: self.term.or(lower.term),
+            backend: self.backend.or(lower.backend),
+        }
+    }
+}
+
+/// A key set by the config files, with the file it is from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConfigEntry {
+    pub key: String,
+    pub value: toml::Value,
+    pub source: PathBuf,
+}
+
+/// The config files that were found, lowest precedence first, and the environment variables
+/// above them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigLayers {
+    pub files: Vec<(PathBuf, Config)>,
+    /// What the `ELF2FLASH_*` variables read by hand set, see [`Config::from_env`]
+    pub env: Config,
+}
+
+impl ConfigLayers {
+    /// Load the global config file and the project config file of `dir`, those that exist.
+    pub fn discover(dir: &Path) -> Result<Self> {
+        let paths = global_config_file()
+            .filter(|path| path.is_file())
+            .into_iter()
+            .chain(project_config_file(dir));
+        let mut layers = ConfigLayers::default();
+        for path in paths {
+            let config = Config::load(&path)?;
+            layers.files.push((path, config));
+        }
+        Ok(layers)
+    }
+
+    /// The config the variables and files add up to.
+    pub fn effective(&self) -> Config {
+        let mut files = self
+            .files
+            .iter()
+            .rev()
+            .fold(Config::default(), |config, (_, lower)| {
+                config.or(lower.clone())
+            });
+        // Like on the command line, a board or family replaces the board of the files
+        if self.env.board.is_some() || self.env.family.is_some() {
+            files = Config {
+                board: None,
+                family: None,
+                page_size: None,
+                flash_sector_erase_size: None,
+                ..files
+            };
+        }
+        self.env.clone().or(files)
+    }
+
+    /// Every key the files set, by name, with the file its value comes from.
+    pub fn entries(&self) -> Vec<ConfigEntry> {
+        let mut entries = BTreeMap::new();
+        for (path, config) in self.files.iter().rev() {
+            let table = toml::Table::try_from(config).expect("configs serialize to a table");
+            for (key, value) in table {
+                entries.entry(key.clone()).or_insert(ConfigEntry {
+                    key,
+                    value,
+                    source: path.clone(),
+                });
+            }
+        }
+        entries.into_values().collect()
+    }
+
+    /// Fill the flags of `convert` that weren't given with the config.
+    pub fn fill_convert(&self, args: &mut ConvertArgs) {
+        if args.board.is_some() || args.family.is_some() {
+            return;
+        }
+        let config = self.effective();
+        if !args.no_family {
+            args.family = config.family_id().ok().flatten();
+        }
+        args.board = config.board;
+        args.page_size = args.page_size.or(config.page_size);
+        args.flash_sector_erase_size = args
+            .flash_sector_erase_size
+            .or(config.flash_sector_erase_size);
+    }
+
+    /// Fill the flags of `deploy` that weren't given with the config.
+    pub fn fill_deploy(&self, args: &mut DeployArgs, json: bool) {
+        let config = self.effective();
+        if args.board.is_none() && args.family.is_none() {
+            args.family = config.family_id().ok().flatten();
+            args.board = config.board;
+            args.page_size = args.page_size.or(config.page_size);
+            args.flash_sector_erase_size = args
+                .flash_sector_erase_size
+                .or(config.flash_sector_erase_size);
+        }
+        args.serial_options.baud = args.serial_options.baud.or(config.baud);
+        // The board's output would end up in the JSON document
+        if config.serial == Some(true) && !json && !args.no_serial {
+            args.serial = args.serial.or(Some(SerialMode::Auto));
+        }
+        args.term |= config.term == Some(true) && !args.no_term;
+        args.backend = args.backend.or(config.backend);
+    }
+
+    /// Fill the flags of `monitor` that weren't given with the config.
+    pub fn fill_monitor(&self, args: &mut MonitorArgs) {
+        let config = self.effective();
+        args.serial_options.baud = args.serial_options.baud.or(config.baud);
+        args.term |= config.term == Some(true) && !args.no_term;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        deploy: DeployArgs,
+    }
+
+    fn layers(files: &[(&str, &str)]) -> ConfigLayers {
+        ConfigLayers {
+            files: files
+                .iter()
+                .map(|(path, text)| (PathBuf::from(path), Config::from_toml(text).unwrap()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn project_overrides_global_and_command_line_overrides_both() {
+        let layers = layers(&[
+            (
+                "global.toml",
+                "board = \"rp2040\"\nbaud = 9600\nserial = true\n",
+            ),
+            (
+                "elf2flash.toml",
+                "board = \"rp2350\"\nbackend = \"picoboot\"\n",
+            ),
+        ]);
+
+        let effective = layers.effective();
+        assert_eq!(effective.board.as_deref(), Some("rp2350"));
+        assert_eq!(effective.baud, Some(9600));
+        assert_eq!(effective.backend, Some(Backend::Picoboot));
+        assert_eq!(
+            layers
+                .entries()
+                .iter()
+                .map(|entry| (entry.key.as_str(), entry.source.to_str().unwrap()))
+                .collect::<Vec<_>>(),
+            [
+                ("backend", "elf2flash.toml"),
+                ("baud", "global.toml"),
+                ("board", "elf2flash.toml"),
+                ("serial", "global.toml"),
+            ]
+        );
+
+        let mut args = Cli::parse_from(["deploy", "a.elf", "--baud", "115200"]).deploy;
+        layers.fill_deploy(&mut args, false);
+        assert_eq!(args.board.as_deref(), Some("rp2350"));
+        assert_eq!(args.serial_options.baud, Some(115200));
+        assert_eq!(args.serial, Some(SerialMode::Auto));
+        assert_eq!(args.backend, Some(Backend::Picoboot));
+
+        // The command line's board isn't mixed with the config's
+        let mut args = Cli::parse_from(["deploy", "a.elf", "--family", "SAMD51"]).deploy;
+        layers.fill_deploy(&mut args, true);
+        assert_eq!(args.board, None);
+        assert_eq!(args.serial, None);
+    }
+
+    #[test]
+    fn command_line_turns_off_serial_and_term_of_the_config() {
+        let layers = layers(&[("elf2flash.toml", "serial = true\nterm = true\n")]);
+
+        let mut args = Cli::parse_from(["deploy", "a.elf"]).deploy;
+        layers.fill_deploy(&mut args, false);
+        assert_eq!(args.serial, Some(SerialMode::Auto));
+        assert!(args.term);
+
+        let mut args = Cli::parse_from(["deploy", "a.elf", "--no-serial", "--no-term"]).deploy;
+        layers.fill_deploy(&mut args, false);
+        assert_eq!(args.serial, None);
+        assert!(!args.term);
+
+        #[derive(Parser)]
+        struct Monitor {
+            #[clap(flatten)]
+            monitor: MonitorArgs,
+        }
+        let mut args = Monitor::parse_from(["monitor", "--no-term"]).monitor;
+        layers.fill_monitor(&mut args);
+        assert!(!args.term);
+
+        assert!(Cli::try_parse_from(["deploy", "a.elf", "--term", "--no-term"]).is_err());
+    }
+
+    #[test]
+    fn variables_override_the_files_and_the_command_line_overrides_them() {
+        let mut layers = layers(&[(
+            "elf2flash.toml",
+            "board = \"rp2350\"\npage_size = 4096\nbaud = 9600\n",
+        )]);
+        layers.env = Config::from_env(|name| match name {
+            "ELF2FLASH_FAMILY" => Some("SAMD51".to_string()),
+            _ => None,
+        })
+        .unwrap();
+
+        // The family of the variable isn't mixed with the board of the file
+        let mut args = Cli::parse_from(["deploy", "a.elf"]).deploy;
+        layers.fill_deploy(&mut args, false);
+        assert_eq!(args.board, None);
+        assert_eq!(args.family, Some(0x55114460));
+        assert_eq!(args.page_size, None);
+        assert_eq!(args.serial_options.baud, Some(9600));
+
+        let mut args = Cli::parse_from(["deploy", "a.elf", "--board", "rp2040"]).deploy;
+        layers.fill_deploy(&mut args, false);
+        assert_eq!(args.board.as_deref(), Some("rp2040"));
+        assert_eq!(args.family, None);
+    }
+
+    #[test]
+    fn invalid_variables_are_rejected() {
+        let env = |board: &'static str, family: &'static str| {
+            Config::from_env(move |name| match name {
+                "ELF2FLASH_BOARD" => Some(board.to_string()),
+                "ELF2FLASH_FAMILY" => Some(family.to_string()),
+                _ => None,
+            })
+        };
+
+        let err = env("not_a_board", "").unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "Invalid ELF2FLASH_BOARD: Unknown board 'not_a_board'"
+        );
+        let err = env("", "NOT_A_FAMILY").unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "Invalid ELF2FLASH_FAMILY: invalid decimal number or family name 'NOT_A_FAMILY'"
+        );
+
+        // Empty variables are unset, board names are matched ignoring case
+        assert_eq!(env("", "").unwrap(), Config::default());
+        assert_eq!(env("RP2040", "").unwrap().board.as_deref(), Some("rp2040"));
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected() {
+        let err = Config::from_toml("bord = \"rp2040\"\n").unwrap_err();
+        assert!(format!("{err:#}").contains("unknown field `bord`"));
+
+        let err = Config::from_toml("board = \"not_a_board\"\n").unwrap_err();
+        assert!(format!("{err:#}").contains("Unknown board 'not_a_board'"));
+
+        assert!(Config::from_toml("backend = \"usb\"\n").is_err());
+        assert!(Config::from_toml("family = \"NOT_A_FAMILY\"\n").is_err());
+        assert!(Config::from_toml("serial = \"yes\"\n").is_err());
+
+        assert_eq!(
+            Config::from_toml("family = \"SAMD51\"\n")
+                .unwrap()
+                .family_id()
+                .unwrap(),
+            Some(0x55114460)
+        );
+    }
+}
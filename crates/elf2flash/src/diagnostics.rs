@@ -0,0 +1,170 @@
+//! What bug reports need to know about the machine elf2flash runs on, and how they are redacted.
+
+use std::{env, fmt, fs};
+
+use serde::Serialize;
+use usbh_fatfs::rusb;
+
+/// The versions and platform behind a run, libusb in particular behaves differently between
+/// versions and backends.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Environment {
+    /// Version of elf2flash
+    pub version: &'static str,
+    pub libusb_version: String,
+    /// The libusb backend, `None` on platforms where it can't be told
+    pub libusb_backend: Option<&'static str>,
+    pub os: &'static str,
+    pub os_version: Option<String>,
+    pub arch: &'static str,
+}
+
+pub fn environment() -> Environment {
+    let libusb = rusb::version();
+    let mut libusb_version = format!(
+        "{}.{}.{}.{}",
+        libusb.major(),
+        libusb.minor(),
+        libusb.micro(),
+        libusb.nano()
+    );
+    if let Some(rc) = libusb.rc() {
+        libusb_version.push_str(rc);
+    }
+
+    Environment {
+        version: env!("CARGO_PKG_VERSION"),
+        libusb_version,
+        libusb_backend: libusb_backend(),
+        os: env::consts::OS,
+        os_version: os_version(),
+        arch: env::consts::ARCH,
+    }
+}
+
+/// libusb has a single backend per platform, except on Windows where UsbDk has to be enabled
+/// explicitly, which is never done.
+fn libusb_backend() -> Option<&'static str> {
+    match env::consts::OS {
+        "linux" | "android" => Some("usbfs"),
+        "macos" => Some("IOKit"),
+        "windows" => Some("WinUSB"),
+        "freebsd" | "openbsd" | "netbsd" => Some("ugen"),
+        _ => None,
+    }
+}
+
+/// The kernel release, only known on Linux
+fn os_version() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/osrelease")
+        .ok()
+        .map(|release| release.trim().to_string())
+}
+
+impl fmt::Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "elf2flash {}, libusb {}",
+            self.version, self.libusb_version
+        )?;
+        if let Some(backend) = self.libusb_backend {
+            write!(f, " ({backend})")?;
+        }
+        write!(f, ", {}", self.os)?;
+        if let Some(os_version) = &self.os_version {
+            write!(f, " {os_version}")?;
+        }
+        write!(f, " {}", self.arch)
+    }
+}
+
+/// Replaced for everything a bug report leaves out
+pub const REDACTED: &str = "<redacted>";
+
+/// What is removed from a bug report before it is shared.
+///
+/// The home directory is always replaced by `~`, since it usually contains the user's name. USB
+/// serial numbers are removed unless `keep_serials` is set, some of them identify the owner.
+#[derive(Debug, Clone, Default)]
+pub struct Redaction {
+    pub keep_serials: bool,
+    /// Serial numbers to remove from free text, like warning messages
+    pub serials: Vec<String>,
+    pub home: Option<String>,
+}
+
+impl Redaction {
+    /// Redact the given serial numbers, and the home directory of the current user.
+    pub fn new(keep_serials: bool, serials: Vec<String>) -> Self {
+        Self {
+            keep_serials,
+            serials,
+            home: env::var("HOME")
+                .or_else(|_| env::var("USERPROFILE"))
+                .ok()
+                .filter(|home| !home.is_empty()),
+        }
+    }
+
+    pub fn serial(&self, serial: &str) -> String {
+        if self.keep_serials {
+            serial.to_string()
+        } else {
+            REDACTED.to_string()
+        }
+    }
+
+    pub fn text(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        if let Some(home) = &self.home {
+            text = text.replace(home.as_str(), "~");
+        }
+        if !self.keep_serials {
+            for serial in self.serials.iter().filter(|serial| !serial.is_empty()) {
+                text = text.replace(serial.as_str(), REDACTED);
+            }
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_the_environment() {
+        let environment = environment();
+        assert_eq!(environment.version, env!("CARGO_PKG_VERSION"));
+        assert!(
+            environment
+                .to_string()
+                .starts_with(&format!("elf2flash {}, libusb ", environment.version))
+        );
+    }
+
+    #[test]
+    fn redacts_home_and_serials() {
+        let redaction = Redaction {
+            keep_serials: false,
+            serials: vec!["E6614C311B2F".to_string()],
+            home: Some("/home/alice".to_string()),
+        };
+        assert_eq!(redaction.serial("E6614C311B2F"), REDACTED);
+        assert_eq!(
+            redaction.text("No device matched serial:E6614C311B2F, backup in /home/alice/backups"),
+            "No device matched serial:<redacted>, backup in ~/backups"
+        );
+
+        let redaction = Redaction {
+            keep_serials: true,
+            ..redaction
+        };
+        assert_eq!(redaction.serial("E6614C311B2F"), "E6614C311B2F");
+        assert_eq!(
+            redaction.text("serial E6614C311B2F in /home/alice"),
+            "serial E6614C311B2F in ~"
+        );
+    }
+}
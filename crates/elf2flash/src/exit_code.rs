@@ -0,0 +1,189 @@
+//! The exit codes telling scripts why a run failed, e.g. no board plugged in from a build that
+//! produced a broken ELF.
+
+use std::{error::Error, io, process::ExitCode};
+
+use elf2flash_core::{
+    Elf2Uf2Error,
+    address_range::AddressRangesFromElfError,
+    input::IntelHexError,
+    uf2::{Uf2BlockError, Uf2MergeError, Uf2ToBinError},
+};
+use thiserror::Error;
+use usbh_fatfs::{
+    FatError, StorageUsbError, rusb,
+    usbh_scsi::storage::{UsbMassStorageError, UsbMassStorageReadWriteError},
+};
+
+use crate::{cancel::Cancelled, picoboot::PicobootError};
+
+/// Failures the commands raise to get an exit code of their own, found in the error chain by
+/// [`ExitStatus::of`].
+#[derive(Error, Debug)]
+pub enum CliError {
+    #[error("No uf2 devices found")]
+    NoDevice,
+    #[error("No device matched {missing}, found:\n    {found}")]
+    NoDeviceMatched { missing: String, found: String },
+    #[error("{count} devices found, pick the one to {action} with --device")]
+    MultipleDevices { count: usize, action: &'static str },
+    #[error("{0}")]
+    VerificationFailed(String),
+    /// Writing the uf2 file failed on a device, raised as a warning by the write itself
+    #[error("{0}")]
+    WriteFailed(String),
+}
+
+/// How a run ended, as its exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Success = 0,
+    /// Anything without a code of its own
+    Failure = 1,
+    NoDevice = 2,
+    MultipleDevices = 3,
+    /// The ELF, hex or uf2 input couldn't be turned into uf2 blocks
+    ConversionError = 4,
+    /// Talking to the device over USB failed
+    UsbIoError = 5,
+    PermissionDenied = 6,
+    /// What was read back, or the uf2 file checked, doesn't hold what it should
+    VerificationFailed = 7,
+    /// The command line couldn't be parsed, 64 like EX_USAGE as clap's own 2 is taken
+    Usage = 64,
+    /// 128 + SIGINT, as a shell reports for a process killed by Ctrl+C
+    Cancelled = 130,
+}
+
+/// Which status wins when the chain has causes for several: a permission error inside a USB error
+/// says more about how to fix it, and a USB error cuts a conversion streamed to the device short.
+const PRECEDENCE: [ExitStatus; 7] = [
+    ExitStatus::Cancelled,
+    ExitStatus::NoDevice,
+    ExitStatus::MultipleDevices,
+    ExitStatus::VerificationFailed,
+    ExitStatus::PermissionDenied,
+    ExitStatus::UsbIoError,
+    ExitStatus::ConversionError,
+];
+
+impl ExitStatus {
+    /// The status of a run that failed with `err`.
+    pub fn of(err: &anyhow::Error) -> Self {
+        let found: Vec<Self> = err.chain().filter_map(Self::of_cause).collect();
+        PRECEDENCE
+            .into_iter()
+            .find(|status| found.contains(status))
+            .unwrap_or(Self::Failure)
+    }
+
+    fn of_cause(cause: &(dyn Error + 'static)) -> Option<Self> {
+        if cause.is::<Cancelled>() {
+            return Some(Self::Cancelled);
+        }
+        if let Some(err) = cause.downcast_ref::<CliError>() {
+            return Some(match err {
+                CliError::NoDevice | CliError::NoDeviceMatched { .. } => Self::NoDevice,
+                CliError::MultipleDevices { .. } => Self::MultipleDevices,
+                CliError::VerificationFailed(_) => Self::VerificationFailed,
+                CliError::WriteFailed(_) => Self::UsbIoError,
+            });
+        }
+        if let Some(err) = cause.downcast_ref::<Elf2Uf2Error>() {
+            return Some(match err {
+                Elf2Uf2Error::Cancelled => Self::Cancelled,
+                _ => Self::ConversionError,
+            });
+        }
+        if let Some(err) = cause.downcast_ref::<io::Error>() {
+            return (err.kind() == io::ErrorKind::PermissionDenied)
+                .then_some(Self::PermissionDenied);
+        }
+        if let Some(err) = cause.downcast_ref::<rusb::Error>() {
+            return Some(match err {
+                rusb::Error::Access => Self::PermissionDenied,
+                _ => Self::UsbIoError,
+            });
+        }
+
+        if cause.is::<PicobootError>()
+            || cause.is::<StorageUsbError>()
+            || cause.is::<FatError>()
+            || cause.is::<UsbMassStorageError>()
+            || cause.is::<UsbMassStorageReadWriteError>()
+        {
+            Some(Self::UsbIoError)
+        } else if cause.is::<AddressRangesFromElfError>()
+            || cause.is::<IntelHexError>()
+            || cause.is::<Uf2BlockError>()
+            || cause.is::<Uf2MergeError>()
+            || cause.is::<Uf2ToBinError>()
+        {
+            Some(Self::ConversionError)
+        } else {
+            None
+        }
+    }
+
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        ExitCode::from(status.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Context, anyhow};
+
+    #[test]
+    fn typed_causes_decide_the_status() {
+        let err = anyhow!(CliError::NoDevice);
+        assert_eq!(ExitStatus::of(&err), ExitStatus::NoDevice);
+
+        let err = Err::<(), _>(CliError::MultipleDevices {
+            count: 2,
+            action: "read from",
+        })
+        .context("Failed to read")
+        .unwrap_err();
+        assert_eq!(ExitStatus::of(&err), ExitStatus::MultipleDevices);
+
+        let err = anyhow!(Elf2Uf2Error::InputFileNoMemoryPagesError).context("Failed to convert");
+        assert_eq!(ExitStatus::of(&err), ExitStatus::ConversionError);
+        let err = anyhow!(Elf2Uf2Error::Cancelled);
+        assert_eq!(ExitStatus::of(&err), ExitStatus::Cancelled);
+
+        let err = anyhow!(CliError::WriteFailed("Failed to write".to_string()))
+            .context("No device was flashed");
+        assert_eq!(ExitStatus::of(&err), ExitStatus::UsbIoError);
+
+        let err = anyhow!("Unknown board 'lab_board'");
+        assert_eq!(ExitStatus::of(&err), ExitStatus::Failure);
+        let err = anyhow!(io::Error::from(io::ErrorKind::NotFound)).context("Failed to read a.elf");
+        assert_eq!(ExitStatus::of(&err), ExitStatus::Failure);
+    }
+
+    #[test]
+    fn permission_errors_win_over_the_usb_error_around_them() {
+        let err = anyhow!(PicobootError::from(rusb::Error::Access)).context("Failed to flash");
+        assert_eq!(ExitStatus::of(&err), ExitStatus::PermissionDenied);
+        let err = anyhow!(PicobootError::from(rusb::Error::Timeout));
+        assert_eq!(ExitStatus::of(&err), ExitStatus::UsbIoError);
+
+        let err = anyhow!(FatError::StdIo(io::Error::from(
+            io::ErrorKind::PermissionDenied
+        )));
+        assert_eq!(ExitStatus::of(&err), ExitStatus::PermissionDenied);
+
+        // A conversion streamed to the device, cut short by the device going away
+        let usb = UsbMassStorageReadWriteError::UsbDeviceBulkFailed(rusb::Error::NoDevice);
+        let err = anyhow!(Elf2Uf2Error::RealizePageError(io::Error::other(usb)));
+        assert_eq!(ExitStatus::of(&err), ExitStatus::UsbIoError);
+    }
+}
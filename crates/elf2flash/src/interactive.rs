@@ -0,0 +1,49 @@
+//! Whether someone is watching the run from a terminal. A deploy started by a systemd unit, a
+//! Windows service or a CI job has no terminal to redraw a progress bar on and possibly no console
+//! to deliver Ctrl+C, so those runs log plain lines instead.
+
+use std::{
+    io::{self, BufRead, IsTerminal, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static INTERACTIVE: AtomicBool = AtomicBool::new(true);
+
+/// Decide once at startup, the run is interactive unless `--non-interactive` is given or stdin or
+/// stdout isn't a terminal.
+pub fn init(non_interactive: bool) {
+    let interactive = !non_interactive && io::stdin().is_terminal() && io::stdout().is_terminal();
+    INTERACTIVE.store(interactive, Ordering::Relaxed);
+}
+
+pub fn is_interactive() -> bool {
+    INTERACTIVE.load(Ordering::Relaxed)
+}
+
+/// Ask `question` on the terminal, followed by ` [y/N] `. Only a yes is a yes.
+pub fn ask(question: &str) -> io::Result<bool> {
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(is_yes(&answer))
+}
+
+/// Whether `answer` to a question is a yes, anything else is a no.
+fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_yes_confirms() {
+        assert!(is_yes("y\n"));
+        assert!(is_yes("Yes\r\n"));
+        assert!(!is_yes("\n"));
+        assert!(!is_yes("no\n"));
+        assert!(!is_yes("yep\n"));
+    }
+}
@@ -1,16 +1,66 @@
-use elf2flash_core::boards::BoardIter;
-use env_logger::Env;
-use log::Level;
-use std::{error::Error, io::Write};
+use elf2flash_core::boards::{BoardIter, family::family_by_name};
+use env_logger::{Env, WriteStyle};
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    io::Write,
+    path::PathBuf,
+    process::ExitCode,
+    time::Duration,
+};
 
 use log::LevelFilter;
 
-use clap::{Parser, ValueEnum};
+use clap::{
+    CommandFactory, Parser, ValueEnum,
+    builder::{PossibleValue, StringValueParser, TypedValueParser},
+};
+use usbh_fatfs::usbh_scsi::select::PortPath;
 
-use crate::commands::{convert::convert, deploy::deploy};
+use crate::{
+    cli_output::ColorChoice,
+    commands::{
+        boards::{BoardsArgs, boards},
+        compare::{CompareArgs, compare},
+        completions::{CompletionsArgs, completions},
+        config::{ConfigArgs, config},
+        convert::{ConvertArgs, conversion_hint, convert, is_stdio},
+        deploy::{
+            DeployArgs, deploy,
+            to_usb::{set_usb_roots, set_usb_timeout},
+        },
+        doctor::{DoctorArgs, doctor},
+        dump::{DumpArgs, dump},
+        erase::{EraseArgs, erase},
+        info::{InfoArgs, info},
+        list::{ListArgs, list},
+        merge::{MergeArgs, merge},
+        monitor::{MonitorArgs, monitor},
+        read::{ReadArgs, read},
+        rollback::{RollbackArgs, rollback},
+        verify::{VerifyArgs, verify},
+        write_page::{WritePageArgs, write_page},
+    },
+    config_file::{Config, ConfigLayers},
+    exit_code::ExitStatus,
+    output::OutputFormat,
+    progress_bar::ProgressMode,
+};
 
+pub mod boards_file;
+pub mod cancel;
+pub mod cli_output;
 pub mod commands;
+pub mod config_file;
+pub mod diagnostics;
+pub mod exit_code;
+pub mod interactive;
+pub mod output;
+pub mod picoboot;
 pub mod progress_bar;
+pub mod serial;
+#[cfg(test)]
+mod test_support;
 
 #[derive(Copy, Clone, Debug, ValueEnum)]
 enum LogLevel {
@@ -24,12 +74,76 @@ enum LogLevel {
 
 #[derive(Parser, Debug)]
 #[clap(version, about, long_about = None, author = "Bjorn Beishline")]
-#[command(arg_required_else_help = true)]
+#[command(
+    arg_required_else_help = true,
+    after_help = "`elf2flash <FILE>` is short for `elf2flash deploy <FILE>`, so cargo can run \
+                  `runner = \"elf2flash\"`\n\n\
+                  Flags take their value from the command line, then from the ELF2FLASH_* \
+                  environment variables (ELF2FLASH_BOARD, ELF2FLASH_FAMILY, ELF2FLASH_BACKEND, \
+                  ELF2FLASH_SERIAL_BAUD and ELF2FLASH_LOG), then from elf2flash.toml and \
+                  ~/.config/elf2flash/config.toml"
+)]
 struct Cli {
     /// Set the logging verbosity
-    #[clap(short, long, value_enum, global = true, default_value_t = LogLevel::Info)]
+    #[clap(
+        short,
+        long,
+        value_enum,
+        global = true,
+        default_value_t = LogLevel::Info,
+        env = "ELF2FLASH_LOG"
+    )]
     verbose: LogLevel,
 
+    /// Only print warnings and errors, without progress. Lowers --verbose to warn at most
+    #[clap(short, long, global = true)]
+    quiet: bool,
+
+    /// Don't show progress, neither as bars nor as lines, keeping the other logs
+    #[clap(long, global = true)]
+    no_progress: bool,
+
+    /// Never draw progress bars or expect a console, as when stdin or stdout isn't a terminal
+    #[clap(long, global = true)]
+    non_interactive: bool,
+
+    /// Print a JSON document with the result on stdout once done, instead of the logs, which go to
+    /// stderr. Implies --non-interactive
+    #[clap(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Color errors red, warnings yellow and the summary of the devices written green, by default
+    /// on a terminal unless NO_COLOR is set
+    #[clap(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Only look at the USB devices at or behind this location, e.g. 3-1 for everything behind
+    /// port 1 of bus 3. Can be repeated, every other device is skipped before its descriptors are
+    /// read
+    #[clap(long, global = true, value_name = "BUS[-PORT.PORT...]")]
+    usb_root: Vec<PortPath>,
+
+    /// Give up on a USB transfer, and on finding the serial port of --serial, after this many
+    /// milliseconds instead of the default of each (10 seconds for most transfers, 20 for the
+    /// serial port)
+    #[clap(
+        long,
+        global = true,
+        value_name = "MS",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    usb_timeout_ms: Option<u64>,
+
+    /// Load extra board definitions from this TOML file, instead of from
+    /// ~/.config/elf2flash/boards.toml
+    #[clap(long, global = true, value_name = "FILE")]
+    boards_file: Option<PathBuf>,
+
+    /// Ignore elf2flash.toml and ~/.config/elf2flash/config.toml, using only the flags given and
+    /// the ELF2FLASH_* variables
+    #[clap(long, global = true)]
+    no_config: bool,
+
     #[clap(subcommand)]
     command: Option<Command>,
 }
@@ -37,70 +151,127 @@ struct Cli {
 #[derive(Parser, Debug)]
 enum Command {
     /// Convert ELF to UF2 file on disk
-    Convert {
-        /// Input ELF file
-        input: String,
+    Convert(ConvertArgs),
+    /// Deploy an ELF, uf2, Intel HEX or binary file directly to a connected board
+    Deploy(DeployArgs),
+    /// Print the serial output of a running board without flashing it, reconnecting when it resets
+    Monitor(MonitorArgs),
+    /// Flash a uf2 file saved by `deploy --backup` back onto a connected board
+    Rollback(RollbackArgs),
+    /// Copy the files, and optionally raw sectors, of a connected bootloader volume into a directory
+    Dump(DumpArgs),
+    /// Combine several uf2 files, e.g. a bootloader and an application, into one
+    Merge(MergeArgs),
+    /// Save the firmware a connected board exports as CURRENT.UF2
+    Read(ReadArgs),
+    /// Overwrite a few flash pages, e.g. a settings sector, without flashing the whole firmware
+    WritePage(WritePageArgs),
+    /// Erase the whole flash of a connected RP2040 or RP2350 board, e.g. one wedged by firmware
+    /// that keeps it from being flashed
+    Erase(EraseArgs),
+    /// Compare two uf2 files page by page, telling differences that don't change what is flashed
+    /// from those that do
+    Compare(CompareArgs),
+    /// Check that a uf2 file holds what an ELF loads, to catch one left behind by a failed build
+    Verify(VerifyArgs),
+    /// List the boards `--board` accepts, with their family id, page sizes and USB ids
+    Boards(BoardsArgs),
+    /// List the connected USB mass storage devices, the board each is recognized as and what its
+    /// bootloader reports about itself
+    List(ListArgs),
+    /// Show what the bootloader of each connected uf2 device says about itself and the size and
+    /// free space of its volume, mounted read-only
+    Info(InfoArgs),
+    /// Check that the connected uf2 devices can be found and opened, and print what to change
+    /// when they can't, like the udev rules they need on Linux
+    Doctor(DoctorArgs),
+    /// Inspect the defaults taken from elf2flash.toml and ~/.config/elf2flash/config.toml
+    Config(ConfigArgs),
+    /// Print the completion script of a shell, with the boards of the boards file
+    Completions(CompletionsArgs),
+}
 
-        /// Output UF2 file
-        output: String,
+/// Let `elf2flash firmware.elf` stand for `elf2flash deploy firmware.elf`, which is how cargo
+/// runs a `runner = "elf2flash"`. The first argument that is neither a global flag nor its value
+/// names the subcommand, `deploy` is put in front of it when it names none. A `--` before it is
+/// dropped, as cargo can put one there.
+fn deploy_by_default(mut args: Vec<OsString>) -> Vec<OsString> {
+    let cli = Cli::command();
+    let takes_value = |flag: &str| {
+        cli.get_arguments().any(|arg| {
+            arg.get_action().takes_values()
+                && (arg
+                    .get_long()
+                    .is_some_and(|long| flag.strip_prefix("--") == Some(long))
+                    || arg.get_short().is_some_and(|short| {
+                        flag.strip_prefix('-').and_then(|s| s.parse().ok()) == Some(short)
+                    }))
+        })
+    };
 
-        /// Explicit board (rp2040, rp2350, circuit_playground_bluefruit, etc.)
-        #[clap(short, long, value_parser = board_parser)]
-        board: Option<String>,
+    let mut index = 1;
+    while let Some(arg) = args.get(index) {
+        let arg = arg.to_string_lossy();
+        if arg == "--" {
+            args.remove(index);
+            break;
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            break;
+        }
+        index += if takes_value(&arg) { 2 } else { 1 };
+    }
 
-        /// Override family ID
-        #[clap(short, long, value_parser = num_parser)]
-        family: Option<u32>,
+    if let Some(first) = args.get(index) {
+        let first = first.to_string_lossy();
+        if first != "help" && cli.find_subcommand(first.as_ref()).is_none() {
+            args.insert(index, OsString::from("deploy"));
+        }
+    }
+    args
+}
 
-        /// Flash erase sector size
-        #[clap(short = 'e', long, value_parser = num_parser)]
-        flash_sector_erase_size: Option<u64>,
+/// The names `--board` accepts: the built-in boards and those of the boards file
+pub(crate) fn board_names() -> Vec<String> {
+    BoardIter::new()
+        .map(|board| board.board_name().into_owned())
+        .collect()
+}
 
-        /// Page size
-        #[clap(short, long, value_parser = num_parser)]
-        page_size: Option<u32>,
-    },
-    /// Deploy ELF directly to a connected board
-    Deploy {
-        /// Input ELF file
-        input: String,
-
-        /// Same options as convert…
-        #[clap(short, long, value_parser = board_parser)]
-        board: Option<String>,
-
-        /// Override family ID
-        #[clap(short, long, value_parser = num_parser)]
-        family: Option<u32>,
-
-        /// Flash erase sector size
-        #[clap(short = 'e', long, value_parser = num_parser)]
-        flash_sector_erase_size: Option<u64>,
-
-        /// Page size
-        #[clap(short, long, value_parser = num_parser)]
-        page_size: Option<u32>,
-
-        /// Connect to serial after deploy
-        #[clap(short, long)]
-        serial: bool,
-
-        /// Send termination message on Ctrl+C
-        #[clap(short, long)]
-        term: bool,
-    },
+pub(crate) fn board_parser(s: &str) -> Result<String, String> {
+    board_names()
+        .into_iter()
+        .find(|name| name.eq_ignore_ascii_case(s))
+        .ok_or_else(|| format!("Unknown board '{}'", s))
 }
 
-fn board_parser(s: &str) -> Result<String, String> {
-    if let Some(board) = BoardIter::find_by_name(s) {
-        Ok(board.board_name().to_string())
-    } else {
-        Err(format!("Unknown board '{}'", s))
+/// The value parser of `--board`, [`board_parser`] with [`board_names`] as its possible values
+/// for the shell completions. The boards file is registered before the command line is parsed, so
+/// its boards are completed too. Arguments using it hide the possible values from their help.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct BoardValueParser;
+
+impl TypedValueParser for BoardValueParser {
+    type Value = String;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        arg: Option<&clap::Arg>,
+        value: &OsStr,
+    ) -> Result<String, clap::Error> {
+        StringValueParser::new()
+            .try_map(|s| board_parser(&s))
+            .parse_ref(cmd, arg, value)
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        Some(Box::new(board_names().into_iter().map(PossibleValue::new)))
     }
 }
 
 // allow user to pass hex formatted numbers (typically the format used by family ids)
-fn num_parser(s: &str) -> Result<u32, &'static str> {
+pub(crate) fn num_parser(s: &str) -> Result<u32, &'static str> {
     match s.get(0..2) {
         Some("0x") => u32::from_str_radix(&s[2..], 16).map_err(|_| "invalid hex number"),
         Some("0b") => u32::from_str_radix(&s[2..], 2).map_err(|_| "invalid binary number"),
@@ -108,6 +279,102 @@ fn num_parser(s: &str) -> Result<u32, &'static str> {
     }
 }
 
+/// A family id as [`num_parser`] takes it, or the name of one like RP2040
+pub(crate) fn family_parser(s: &str) -> Result<u32, &'static str> {
+    num_parser(s).or_else(|_| family_by_name(s).ok_or("invalid decimal number or family name"))
+}
+
+/// [`num_parser`] for the sizes kept as a `u64`, like the flash sector erase size
+pub(crate) fn size_parser(s: &str) -> Result<u64, &'static str> {
+    num_parser(s).map(u64::from)
+}
+
+/// A single byte, decimal or hex like `0xff`
+pub(crate) fn byte_parser(s: &str) -> Result<u8, &'static str> {
+    match s.get(0..2) {
+        Some("0x") => u8::from_str_radix(&s[2..], 16).map_err(|_| "invalid hex byte"),
+        _ => s
+            .parse::<u8>()
+            .map_err(|_| "invalid byte, expected 0-255 or 0x00-0xff"),
+    }
+}
+
+/// A USB vendor or product id, decimal or hex like `0x2e8a`
+pub(crate) fn usb_id_parser(s: &str) -> Result<u16, &'static str> {
+    match s.get(0..2) {
+        Some("0x") => u16::from_str_radix(&s[2..], 16).map_err(|_| "invalid hex USB id"),
+        _ => s
+            .parse::<u16>()
+            .map_err(|_| "invalid USB id, expected 0-65535 or 0x0000-0xffff"),
+    }
+}
+
+impl Command {
+    /// Whether the command writes its output file to stdout, which then can't take the logs
+    fn writes_to_stdout(&self) -> bool {
+        matches!(self, Command::Convert(args) if args.output.as_deref().is_some_and(is_stdio))
+    }
+
+    /// The subcommand's name, as given on the command line
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Convert(_) => "convert",
+            Command::Deploy(_) => "deploy",
+            Command::Monitor(_) => "monitor",
+            Command::Rollback(_) => "rollback",
+            Command::Dump(_) => "dump",
+            Command::Merge(_) => "merge",
+            Command::Read(_) => "read",
+            Command::WritePage(_) => "write-page",
+            Command::Erase(_) => "erase",
+            Command::Compare(_) => "compare",
+            Command::Verify(_) => "verify",
+            Command::Boards(_) => "boards",
+            Command::List(_) => "list",
+            Command::Info(_) => "info",
+            Command::Doctor(_) => "doctor",
+            Command::Config(_) => "config",
+            Command::Completions(_) => "completions",
+        }
+    }
+}
+
+/// What is printed while a command runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Verbosity {
+    log_level: LevelFilter,
+    progress: ProgressMode,
+}
+
+/// Every flag deciding what is printed while a command runs, in one place. `interactive` is
+/// whether stdin and stdout are terminals the run may draw on, see [`interactive::init`].
+fn verbosity(
+    verbose: LogLevel,
+    quiet: bool,
+    no_progress: bool,
+    format: OutputFormat,
+    interactive: bool,
+) -> Verbosity {
+    let mut log_level = LevelFilter::from(verbose);
+    if quiet {
+        log_level = log_level.min(LevelFilter::Warn);
+    }
+
+    // Progress is logged at info, it goes when info logs do
+    let progress = if no_progress || log_level < LevelFilter::Info {
+        ProgressMode::Off
+    } else if interactive && format == OutputFormat::Text {
+        ProgressMode::Bars
+    } else {
+        ProgressMode::Lines
+    };
+
+    Verbosity {
+        log_level,
+        progress,
+    }
+}
+
 impl From<LogLevel> for LevelFilter {
     fn from(level: LogLevel) -> Self {
         match level {
@@ -121,63 +388,339 @@ impl From<LogLevel> for LevelFilter {
     }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let cli = Cli::parse();
+/// The config files to take defaults from, none with `--no-config`, and the variables above them.
+fn load_config(no_config: bool) -> anyhow::Result<ConfigLayers> {
+    let variables = Config::from_env(|name| env::var(name).ok())?;
+    if no_config {
+        return Ok(ConfigLayers {
+            env: variables,
+            ..Default::default()
+        });
+    }
+    let mut layers = ConfigLayers::discover(&env::current_dir()?)?;
+    for (path, _) in &layers.files {
+        log::debug!("Loaded config from {}", path.display());
+    }
+    layers.env = variables;
+    Ok(layers)
+}
+
+fn run(mut command: Command, layers: &ConfigLayers, json: bool) -> anyhow::Result<()> {
+    match &mut command {
+        Command::Convert(args) => layers.fill_convert(args),
+        Command::Deploy(args) => layers.fill_deploy(args, json),
+        Command::Monitor(args) => layers.fill_monitor(args),
+        _ => {}
+    }
+
+    match command {
+        Command::Convert(args) => convert(args),
+        Command::Deploy(args) => deploy(args),
+        Command::Monitor(args) => monitor(args),
+        Command::Rollback(args) => rollback(args),
+        Command::Dump(args) => dump(args),
+        Command::Merge(args) => merge(args),
+        Command::Read(args) => read(args),
+        Command::WritePage(args) => write_page(args),
+        Command::Erase(args) => erase(args),
+        Command::Compare(args) => compare(args),
+        Command::Verify(args) => verify(args),
+        Command::Boards(args) => boards(args),
+        Command::List(args) => list(args),
+        Command::Info(args) => info(args),
+        Command::Doctor(args) => doctor(args),
+        Command::Config(args) => config(args, layers),
+        Command::Completions(args) => completions(args),
+    }
+}
+
+/// Print the error a run failed with as a single line, with the chain behind it at debug level.
+fn report_error(err: &anyhow::Error) -> ExitStatus {
+    let status = ExitStatus::of(err);
+    for cause in err.chain().skip(1) {
+        log::debug!("Caused by: {cause}");
+    }
+    if status == ExitStatus::Cancelled {
+        log::warn!("{err}");
+        return status;
+    }
+    if let Some(hint) = conversion_hint(err) {
+        log::warn!("{hint}");
+    }
+    if status == ExitStatus::PermissionDenied {
+        log::warn!("Run `elf2flash doctor` to see what access to the device needs");
+    }
+    eprintln!(
+        "{}",
+        cli_output::format_error(format_args!("{err:#}"), cli_output::stderr_color())
+    );
+    status
+}
 
-    env_logger::Builder::from_env(Env::default())
-        .filter_level(LevelFilter::from(cli.verbose))
-        .target(env_logger::Target::Stdout)
-        .format(|buf, record| {
-            let level = record.level();
-            if level == Level::Info {
-                writeln!(buf, "{}", record.args())
-            } else {
-                writeln!(buf, "{}: {}", record.level(), record.args())
-            }
+fn main() -> ExitCode {
+    let args = deploy_by_default(env::args_os().collect());
+    let boards_file = match boards_file::register_boards_file(&args) {
+        Ok(path) => path,
+        Err(err) => {
+            eprintln!("Error: {err:#}");
+            return ExitStatus::Failure.into();
+        }
+    };
+    let cli = match Cli::try_parse_from(args) {
+        Ok(cli) => cli,
+        // --help and --version are "errors" that succeed
+        Err(err) if !err.use_stderr() => {
+            let _ = err.print();
+            return ExitStatus::Success.into();
+        }
+        Err(err) => {
+            let _ = err.print();
+            return ExitStatus::Usage.into();
+        }
+    };
+    // Progress bars and logs would end up in the JSON document or the uf2 file
+    let stdout_taken = cli.format == OutputFormat::Json
+        || cli.command.as_ref().is_some_and(Command::writes_to_stdout);
+    interactive::init(cli.non_interactive || stdout_taken);
+    output::init(cli.format);
+    cli_output::init(cli.color);
+    set_usb_roots(cli.usb_root);
+    if let Some(ms) = cli.usb_timeout_ms {
+        set_usb_timeout(Duration::from_millis(ms));
+    }
+    let verbosity = verbosity(
+        cli.verbose,
+        cli.quiet,
+        cli.no_progress,
+        cli.format,
+        interactive::is_interactive(),
+    );
+    progress_bar::init(verbosity.progress);
+
+    let color = if stdout_taken {
+        cli_output::stderr_color()
+    } else {
+        cli_output::stdout_color()
+    };
+    let logger = env_logger::Builder::from_env(Env::default())
+        .filter_level(verbosity.log_level)
+        .target(if stdout_taken {
+            env_logger::Target::Stderr
+        } else {
+            env_logger::Target::Stdout
+        })
+        // Whether to color is decided above, the records are written as they are formatted
+        .write_style(if color {
+            WriteStyle::Always
+        } else {
+            WriteStyle::Never
         })
-        .init();
+        .format(move |buf, record| {
+            writeln!(
+                buf,
+                "{}",
+                cli_output::format_record(record.level(), record.args(), color)
+            )
+        })
+        .build();
+    progress_bar::init_logger(logger);
+
+    log::debug!("{}", diagnostics::environment());
+    if let Some(path) = boards_file {
+        log::debug!("Loaded boards from {}", path.display());
+    }
 
     let command = match cli.command {
         Some(command) => command,
-        None => return Ok(()),
+        None => return ExitStatus::Success.into(),
     };
 
-    match command {
-        Command::Convert {
-            input,
-            output,
-            board,
-            family,
-            flash_sector_erase_size,
-            page_size,
-        } => {
-            return Ok(convert(
-                input,
-                output,
-                board,
-                family,
-                flash_sector_erase_size,
-                page_size,
-            )?);
+    let name = command.name();
+    let json = cli.format == OutputFormat::Json;
+    let result = load_config(cli.no_config).and_then(|layers| run(command, &layers, json));
+
+    // Commands without a result of their own still tell whether they succeeded
+    let error = result.as_ref().err().map(|err| format!("{err:#}"));
+    let emitted = output::emit::<()>(name, None, error.as_deref());
+
+    match result.and(emitted) {
+        Ok(()) => ExitStatus::Success,
+        Err(err) => report_error(&err),
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn family_names_are_only_taken_for_family_ids() {
+        assert_eq!(family_parser("SAMD51"), Ok(0x55114460));
+        assert_eq!(family_parser("0xe48bff56"), Ok(0xe48bff56));
+        assert_eq!(
+            family_parser("NOPE"),
+            Err("invalid decimal number or family name")
+        );
+        assert_eq!(num_parser("SAMD51"), Err("invalid decimal number"));
+        assert_eq!(num_parser("256"), Ok(256));
+    }
+
+    #[test]
+    fn verbosity_matrix() {
+        use OutputFormat::{Json, Text};
+        use ProgressMode::{Bars, Lines, Off};
+
+        // (verbose, quiet, no_progress, format, interactive) => (log level, progress)
+        let cases = [
+            (
+                (LogLevel::Info, false, false, Text, true),
+                (LevelFilter::Info, Bars),
+            ),
+            (
+                (LogLevel::Info, false, false, Text, false),
+                (LevelFilter::Info, Lines),
+            ),
+            (
+                (LogLevel::Debug, false, false, Text, true),
+                (LevelFilter::Debug, Bars),
+            ),
+            (
+                (LogLevel::Info, false, true, Text, true),
+                (LevelFilter::Info, Off),
+            ),
+            (
+                (LogLevel::Info, false, true, Text, false),
+                (LevelFilter::Info, Off),
+            ),
+            // --quiet keeps warnings and errors, and wins over a higher --verbose
+            (
+                (LogLevel::Info, true, false, Text, true),
+                (LevelFilter::Warn, Off),
+            ),
+            (
+                (LogLevel::Trace, true, false, Text, true),
+                (LevelFilter::Warn, Off),
+            ),
+            (
+                (LogLevel::Error, true, false, Text, true),
+                (LevelFilter::Error, Off),
+            ),
+            (
+                (LogLevel::Warn, false, false, Text, true),
+                (LevelFilter::Warn, Off),
+            ),
+            (
+                (LogLevel::Off, false, false, Text, true),
+                (LevelFilter::Off, Off),
+            ),
+            // JSON keeps stdout for the document, progress is logged to stderr
+            (
+                (LogLevel::Info, false, false, Json, true),
+                (LevelFilter::Info, Lines),
+            ),
+            (
+                (LogLevel::Info, false, true, Json, false),
+                (LevelFilter::Info, Off),
+            ),
+            (
+                (LogLevel::Info, true, false, Json, false),
+                (LevelFilter::Warn, Off),
+            ),
+        ];
+        for ((verbose, quiet, no_progress, format, interactive), (log_level, progress)) in cases {
+            assert_eq!(
+                verbosity(verbose, quiet, no_progress, format, interactive),
+                Verbosity {
+                    log_level,
+                    progress
+                },
+                "--verbose {verbose:?}, quiet {quiet}, no_progress {no_progress}, \
+                 {format:?}, interactive {interactive}"
+            );
         }
-        Command::Deploy {
-            input,
-            board,
-            family,
-            flash_sector_erase_size,
-            page_size,
-            serial,
-            term,
-        } => {
-            return Ok(deploy(
-                input,
-                board,
-                family,
-                flash_sector_erase_size,
-                page_size,
-                serial,
-                term,
-            )?);
+    }
+
+    #[test]
+    fn a_file_alone_is_deployed() {
+        let command = |args: &[&str]| {
+            let args = args.iter().map(OsString::from).collect();
+            let cli = Cli::try_parse_from(deploy_by_default(args)).unwrap();
+            format!("{:?}", cli.command)
+        };
+
+        let deploy = command(&["elf2flash", "deploy", "firmware.elf"]);
+        assert!(deploy.starts_with("Some(Deploy("), "{deploy}");
+        assert_eq!(command(&["elf2flash", "firmware.elf"]), deploy);
+        assert_eq!(command(&["elf2flash", "--", "firmware.elf"]), deploy);
+        assert_eq!(
+            command(&[
+                "elf2flash",
+                "-v",
+                "debug",
+                "--usb-root",
+                "3-1",
+                "--",
+                "firmware.elf"
+            ]),
+            deploy
+        );
+
+        let with_flags = command(&["elf2flash", "deploy", "firmware.elf", "-t", "-s"]);
+        assert_eq!(
+            command(&["elf2flash", "--quiet", "firmware.elf", "-t", "-s"]),
+            with_flags
+        );
+
+        // Subcommands, help and flags on their own are left alone
+        for args in [
+            &["elf2flash", "list"][..],
+            &["elf2flash", "--format", "json", "boards"],
+            &["elf2flash", "help", "deploy"],
+            &["elf2flash", "--help"],
+            &["elf2flash"],
+        ] {
+            let parsed = deploy_by_default(args.iter().map(OsString::from).collect());
+            assert_eq!(parsed, args, "{args:?}");
         }
     }
+
+    #[test]
+    fn usb_roots_can_be_repeated() {
+        let cli = Cli::try_parse_from([
+            "elf2flash",
+            "--usb-root",
+            "3-1",
+            "--usb-root=4",
+            "read",
+            "--output",
+            "out.uf2",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.usb_root,
+            [
+                PortPath {
+                    bus: 3,
+                    ports: vec![1]
+                },
+                PortPath {
+                    bus: 4,
+                    ports: vec![]
+                },
+            ]
+        );
+
+        assert!(
+            Cli::try_parse_from([
+                "elf2flash",
+                "--usb-root",
+                "3-x",
+                "read",
+                "--output",
+                "out.uf2"
+            ])
+            .is_err()
+        );
+    }
 }
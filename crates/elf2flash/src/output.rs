@@ -0,0 +1,193 @@
+//! `--format json`: one JSON document on stdout per run, for IDE plugins and CI wrappers. The logs
+//! go to stderr and progress bars are left out, so stdout holds nothing but the document.
+//!
+//! Every document has the same envelope, see [`Document`]. Its `version` is bumped whenever a
+//! field is renamed or removed, new fields can be added without it.
+
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::commands::deploy::{report::DeviceReport, verify::Verification};
+
+/// Version of the documents, see the module docs
+pub const FORMAT_VERSION: u32 = 1;
+
+/// What the CLI prints on stdout.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Log lines and progress bars for a person to read
+    #[default]
+    Text,
+    /// A JSON document once the command is done, the logs go to stderr
+    Json,
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+static EMITTED: AtomicBool = AtomicBool::new(false);
+
+/// Decide once at startup what is printed on stdout.
+pub fn init(format: OutputFormat) {
+    JSON.store(format == OutputFormat::Json, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// What every document is wrapped in.
+#[derive(Debug, Serialize)]
+pub struct Document<'a, T> {
+    /// [`FORMAT_VERSION`]
+    pub version: u32,
+    /// The subcommand that ran, e.g. `deploy`
+    pub command: &'a str,
+    pub success: bool,
+    /// Why the command failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'a str>,
+    /// What the command did, see the structs of this module
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<T>,
+}
+
+impl<'a, T: Serialize> Document<'a, T> {
+    pub fn new(command: &'a str, result: Option<T>, error: Option<&'a str>) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            command,
+            success: error.is_none(),
+            error,
+            result,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// Print the document of `command` on stdout, failed with `error` if given. Nothing is printed
+/// without `--format json`, and only the first document of a run is.
+pub fn emit<T: Serialize>(command: &str, result: Option<T>, error: Option<&str>) -> Result<()> {
+    if !is_json() || EMITTED.swap(true, Ordering::Relaxed) {
+        return Ok(());
+    }
+    let mut stdout = io::stdout().lock();
+    writeln!(
+        stdout,
+        "{}",
+        Document::new(command, result, error).to_json()?
+    )?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// What `convert` wrote.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConvertOutput {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Blocks in the uf2 file
+    pub blocks: u32,
+    /// Size of the uf2 file
+    pub bytes: u64,
+    pub family_id: u32,
+}
+
+/// How the deploy to one device went.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeployOutput {
+    pub device: DeviceReport,
+    /// The board the device was flashed as
+    pub board: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Size of the uf2 file, also when writing it failed
+    pub bytes: u64,
+    /// How long converting and writing took
+    pub elapsed_ms: u64,
+    /// The outcome of `--verify`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Verification>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deploy_results() -> Vec<DeployOutput> {
+        let device = DeviceReport {
+            index: 0,
+            board_name: Some("rp2040".to_string()),
+            vendor_id: 0x2e8a,
+            product_id: 0x0003,
+            bus_number: 3,
+            address: 7,
+            port: Some("3-1.4".to_string()),
+            serial: Some("E6614C311B2F".to_string()),
+            labels: vec!["RPI-RP2".to_string()],
+        };
+        vec![
+            DeployOutput {
+                device: device.clone(),
+                board: "rp2040".to_string(),
+                success: true,
+                error: None,
+                bytes: 45568,
+                elapsed_ms: 1520,
+                verification: Some(Verification {
+                    checked: 45568,
+                    mismatched: 0,
+                    first_mismatch: None,
+                }),
+            },
+            DeployOutput {
+                device: DeviceReport {
+                    index: 1,
+                    address: 8,
+                    port: Some("3-1.5".to_string()),
+                    serial: None,
+                    ..device
+                },
+                board: "rp2040".to_string(),
+                success: false,
+                error: Some("Failed to open USB mass storage".to_string()),
+                bytes: 45568,
+                elapsed_ms: 12,
+                verification: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn deploy_document_matches_the_snapshot() {
+        let results = deploy_results();
+        let document = Document::new("deploy", Some(&results), Some("Failed on 1 device(s)"));
+        assert_eq!(
+            document.to_json().unwrap(),
+            include_str!("../tests/golden/deploy.json").trim_end()
+        );
+    }
+
+    #[test]
+    fn failed_commands_have_no_result() {
+        let document = Document::<()>::new("convert", None, Some("Failed to open a.elf"));
+        assert_eq!(
+            serde_json::to_value(&document).unwrap(),
+            serde_json::json!({
+                "version": FORMAT_VERSION,
+                "command": "convert",
+                "success": false,
+                "error": "Failed to open a.elf",
+            })
+        );
+    }
+}
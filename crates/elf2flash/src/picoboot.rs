@@ -0,0 +1,529 @@
+//! The PICOBOOT protocol of the RP2040 and RP2350 bootroms, the one picotool talks: 32 byte
+//! commands sent to the bulk out endpoint of a vendor interface, each followed by the data it
+//! sends and an empty packet acknowledging it on the bulk in endpoint.
+//!
+//! Commands reading from the device get their data on the bulk in endpoint instead, and are
+//! acknowledged with an empty packet on the bulk out endpoint.
+//!
+//! A failing command stalls the endpoints instead of being acknowledged. Its status is then read
+//! with a control request, and the interface is reset before the next command.
+
+use std::time::Duration;
+
+use thiserror::Error;
+use usbh_fatfs::rusb::{
+    self, Device, DeviceHandle, Direction, Recipient, RequestType, TransferType, UsbContext,
+};
+
+use crate::commands::deploy::to_usb::usb_timeout;
+
+/// First field of every PICOBOOT command
+pub const PICOBOOT_MAGIC: u32 = 0x431f_d10b;
+
+const VENDOR_CLASS: u8 = 0xff;
+
+const PC_EXCLUSIVE_ACCESS: u8 = 0x01;
+const PC_REBOOT: u8 = 0x02;
+const PC_FLASH_ERASE: u8 = 0x03;
+const PC_WRITE: u8 = 0x05;
+const PC_EXIT_XIP: u8 = 0x06;
+const PC_READ: u8 = 0x84;
+const PC_REBOOT2: u8 = 0x0a;
+
+/// Control request resetting the interface, clearing a stalled command
+const PICOBOOT_IF_RESET: u8 = 0x41;
+/// Control request reading the status of the last command
+const PICOBOOT_IF_CMD_STATUS: u8 = 0x42;
+
+const REBOOT2_FLAG_REBOOT_TYPE_NORMAL: u32 = 0x0;
+const REBOOT2_FLAG_REBOOT_TYPE_BOOTSEL: u32 = 0x2;
+
+/// Erasing a large range takes a while before it is acknowledged
+const BULK_TIMEOUT: Duration = Duration::from_secs(10);
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The `--usb-timeout-ms` if given, `default` otherwise
+fn timeout(default: Duration) -> Duration {
+    usb_timeout().unwrap_or(default)
+}
+
+#[derive(Error, Debug)]
+pub enum PicobootError {
+    #[error("The device has no PICOBOOT interface")]
+    NoInterface,
+    #[error("USB transfer failed: {0}")]
+    Usb(#[from] rusb::Error),
+    #[error("Only {done} of the {len} bytes were transferred")]
+    ShortTransfer { done: usize, len: usize },
+    #[error("PICOBOOT command {cmd_id:#04x} failed with status {}", describe_status(*code))]
+    Command { cmd_id: u8, code: u32 },
+}
+
+/// Who else may use the device while PICOBOOT commands are sent, see
+/// [`PicobootCommand::exclusive_access`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExclusiveAccess {
+    /// The mass storage interface keeps working
+    NotExclusive = 0,
+    /// Writes through the mass storage interface fail
+    Exclusive = 1,
+    /// Like `Exclusive`, and the volume is ejected from the host
+    ExclusiveAndEject = 2,
+}
+
+/// A PICOBOOT command, sent as 32 bytes with the token of the connection it goes through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PicobootCommand {
+    /// The command, with the top bit set for commands that read from the device
+    pub id: u8,
+    /// The arguments of the command, `args_len` of them used
+    pub args: [u8; 16],
+    pub args_len: u8,
+    /// Bytes sent after the command, or read after it for commands reading from the device
+    pub transfer_length: u32,
+}
+
+impl PicobootCommand {
+    fn new(id: u8, args: &[u32], transfer_length: u32) -> Self {
+        let mut bytes = [0; 16];
+        for (chunk, arg) in bytes.chunks_exact_mut(4).zip(args) {
+            chunk.copy_from_slice(&arg.to_le_bytes());
+        }
+
+        Self {
+            id,
+            args: bytes,
+            args_len: (args.len() * 4) as u8,
+            transfer_length,
+        }
+    }
+
+    pub fn exclusive_access(access: ExclusiveAccess) -> Self {
+        let mut args = [0; 16];
+        args[0] = access as u8;
+        Self {
+            id: PC_EXCLUSIVE_ACCESS,
+            args,
+            args_len: 1,
+            transfer_length: 0,
+        }
+    }
+
+    /// Erase `size` bytes of flash at `address`, both multiples of the 4 KiB sectors.
+    pub fn flash_erase(address: u32, size: u32) -> Self {
+        Self::new(PC_FLASH_ERASE, &[address, size], 0)
+    }
+
+    /// Write the `size` bytes sent after the command at `address`. Flash is written in whole
+    /// 256 byte pages.
+    pub fn write(address: u32, size: u32) -> Self {
+        Self::new(PC_WRITE, &[address, size], size)
+    }
+
+    /// Read `size` bytes at `address`, flash reads need [`PicobootCommand::exit_xip`] first.
+    pub fn read(address: u32, size: u32) -> Self {
+        Self::new(PC_READ, &[address, size], size)
+    }
+
+    /// Leave execute-in-place mode, needed before erasing or writing flash.
+    pub fn exit_xip() -> Self {
+        Self::new(PC_EXIT_XIP, &[], 0)
+    }
+
+    /// Reboot after `delay_ms`, into flash when `pc` is 0, or running the code at `pc` with the
+    /// stack at `sp`.
+    pub fn reboot(pc: u32, sp: u32, delay_ms: u32) -> Self {
+        Self::new(PC_REBOOT, &[pc, sp, delay_ms], 0)
+    }
+
+    /// The RP2350 reboot after `delay_ms` into the flashed program.
+    pub fn reboot2_normal(delay_ms: u32) -> Self {
+        Self::new(
+            PC_REBOOT2,
+            &[REBOOT2_FLAG_REBOOT_TYPE_NORMAL, delay_ms, 0, 0],
+            0,
+        )
+    }
+
+    /// The RP2350 reboot after `delay_ms` into BOOTSEL, with both the mass storage and PICOBOOT
+    /// interfaces turned on and no activity LED.
+    pub fn reboot2_bootsel(delay_ms: u32) -> Self {
+        Self::new(
+            PC_REBOOT2,
+            &[REBOOT2_FLAG_REBOOT_TYPE_BOOTSEL, delay_ms, 0, 0],
+            0,
+        )
+    }
+
+    pub fn to_bytes(&self, token: u32) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        bytes[0..4].copy_from_slice(&PICOBOOT_MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&token.to_le_bytes());
+        bytes[8] = self.id;
+        bytes[9] = self.args_len;
+        bytes[12..16].copy_from_slice(&self.transfer_length.to_le_bytes());
+        bytes[16..32].copy_from_slice(&self.args);
+        bytes
+    }
+}
+
+/// The status of the last command, read after it failed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandStatus {
+    pub token: u32,
+    pub code: u32,
+    pub cmd_id: u8,
+    pub in_progress: bool,
+}
+
+impl CommandStatus {
+    pub fn from_bytes(bytes: &[u8; 16]) -> Self {
+        Self {
+            token: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            code: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            cmd_id: bytes[8],
+            in_progress: bytes[9] != 0,
+        }
+    }
+}
+
+/// The name of a PICOBOOT status code, with the number for codes this doesn't know.
+pub fn describe_status(code: u32) -> String {
+    let name = match code {
+        0 => "OK",
+        1 => "UNKNOWN_CMD",
+        2 => "INVALID_CMD_LENGTH",
+        3 => "INVALID_TRANSFER_LENGTH",
+        4 => "INVALID_ADDRESS",
+        5 => "BAD_ALIGNMENT",
+        6 => "INTERLEAVED_WRITE",
+        7 => "REBOOTING",
+        8 => "UNKNOWN_ERROR",
+        9 => "INVALID_STATE",
+        10 => "NOT_PERMITTED",
+        11 => "INVALID_ARG",
+        12 => "BUFFER_TOO_SMALL",
+        13 => "PRECONDITION_NOT_MET",
+        14 => "MODIFIED_DATA",
+        15 => "INVALID_DATA",
+        16 => "NOT_FOUND",
+        17 => "UNSUPPORTED_MODIFICATION",
+        _ => return code.to_string(),
+    };
+    format!("{code} ({name})")
+}
+
+/// The endpoints a [`Picoboot`] connection talks through, a claimed USB interface or a mock.
+pub trait Transport {
+    fn write_bulk(&mut self, data: &[u8]) -> Result<usize, rusb::Error>;
+    fn read_bulk(&mut self, buf: &mut [u8]) -> Result<usize, rusb::Error>;
+    /// Send the vendor control request `request` to the interface, reading its answer into `buf`.
+    fn control_in(&mut self, request: u8, buf: &mut [u8]) -> Result<usize, rusb::Error>;
+    /// Send the vendor control request `request` to the interface.
+    fn control_out(&mut self, request: u8) -> Result<(), rusb::Error>;
+}
+
+/// The claimed PICOBOOT interface of a device, released when dropped.
+pub struct RusbTransport<T: UsbContext> {
+    handle: DeviceHandle<T>,
+    interface: u8,
+    bulk_out: u8,
+    bulk_in: u8,
+}
+
+impl<T: UsbContext> RusbTransport<T> {
+    /// Open `device` and claim its PICOBOOT interface.
+    pub fn open(device: &Device<T>) -> Result<Self, PicobootError> {
+        let config = device.active_config_descriptor()?;
+        let (interface, bulk_out, bulk_in) = config
+            .interfaces()
+            .flat_map(|interface| interface.descriptors())
+            .filter(|d| {
+                d.class_code() == VENDOR_CLASS && d.sub_class_code() == 0 && d.protocol_code() == 0
+            })
+            .find_map(|d| {
+                let bulk = |direction| {
+                    d.endpoint_descriptors()
+                        .find(|e| {
+                            e.transfer_type() == TransferType::Bulk && e.direction() == direction
+                        })
+                        .map(|e| e.address())
+                };
+                Some((
+                    d.interface_number(),
+                    bulk(Direction::Out)?,
+                    bulk(Direction::In)?,
+                ))
+            })
+            .ok_or(PicobootError::NoInterface)?;
+
+        let handle = device.open()?;
+        // Only needed where the OS bound a driver to the interface
+        let _ = handle.set_auto_detach_kernel_driver(true);
+        handle.claim_interface(interface)?;
+
+        Ok(Self {
+            handle,
+            interface,
+            bulk_out,
+            bulk_in,
+        })
+    }
+}
+
+impl<T: UsbContext> Transport for RusbTransport<T> {
+    fn write_bulk(&mut self, data: &[u8]) -> Result<usize, rusb::Error> {
+        self.handle
+            .write_bulk(self.bulk_out, data, timeout(BULK_TIMEOUT))
+    }
+
+    fn read_bulk(&mut self, buf: &mut [u8]) -> Result<usize, rusb::Error> {
+        self.handle
+            .read_bulk(self.bulk_in, buf, timeout(BULK_TIMEOUT))
+    }
+
+    fn control_in(&mut self, request: u8, buf: &mut [u8]) -> Result<usize, rusb::Error> {
+        let request_type =
+            rusb::request_type(Direction::In, RequestType::Vendor, Recipient::Interface);
+        self.handle.read_control(
+            request_type,
+            request,
+            0,
+            u16::from(self.interface),
+            buf,
+            timeout(CONTROL_TIMEOUT),
+        )
+    }
+
+    fn control_out(&mut self, request: u8) -> Result<(), rusb::Error> {
+        let request_type =
+            rusb::request_type(Direction::Out, RequestType::Vendor, Recipient::Interface);
+        self.handle.write_control(
+            request_type,
+            request,
+            0,
+            u16::from(self.interface),
+            &[],
+            timeout(CONTROL_TIMEOUT),
+        )?;
+        Ok(())
+    }
+}
+
+/// A PICOBOOT connection, numbering its commands with increasing tokens.
+pub struct Picoboot<T> {
+    transport: T,
+    token: u32,
+}
+
+impl<T: Transport> Picoboot<T> {
+    /// Talk through `transport`, resetting the interface first so an earlier session's stalled
+    /// command doesn't fail the first one.
+    pub fn new(mut transport: T) -> Result<Self, PicobootError> {
+        transport.control_out(PICOBOOT_IF_RESET)?;
+        Ok(Self {
+            transport,
+            token: 0,
+        })
+    }
+
+    /// Send `command` followed by `data`, its `transfer_length` bytes, and wait for the
+    /// acknowledgement.
+    pub fn command(&mut self, command: &PicobootCommand, data: &[u8]) -> Result<(), PicobootError> {
+        debug_assert_eq!(data.len(), command.transfer_length as usize);
+        self.send(command, |picoboot| {
+            picoboot.write_all(data)?;
+            picoboot.transport.read_bulk(&mut [0; 64])?;
+            Ok(())
+        })
+    }
+
+    /// Send `command`, read the `transfer_length` bytes it answers with, and acknowledge them.
+    pub fn command_in(&mut self, command: &PicobootCommand) -> Result<Vec<u8>, PicobootError> {
+        self.send(command, |picoboot| {
+            let mut data = vec![0; command.transfer_length as usize];
+            let mut done = 0;
+            while done < data.len() {
+                match picoboot.transport.read_bulk(&mut data[done..])? {
+                    0 => {
+                        return Err(PicobootError::ShortTransfer {
+                            done,
+                            len: data.len(),
+                        });
+                    }
+                    read => done += read,
+                }
+            }
+            picoboot.transport.write_bulk(&[])?;
+            Ok(data)
+        })
+    }
+
+    /// Send `command` with the next token, then run the rest of the `exchange`. A stalled
+    /// exchange fails with the status of the command, and the interface is reset.
+    fn send<R>(
+        &mut self,
+        command: &PicobootCommand,
+        exchange: impl FnOnce(&mut Self) -> Result<R, PicobootError>,
+    ) -> Result<R, PicobootError> {
+        self.token = self.token.wrapping_add(1);
+        let result = self
+            .write_all(&command.to_bytes(self.token))
+            .and_then(|()| exchange(self));
+
+        match result {
+            // The device stalls the endpoints of a failing command
+            Err(PicobootError::Usb(rusb::Error::Pipe)) => {
+                let status = self.status()?;
+                self.transport.control_out(PICOBOOT_IF_RESET)?;
+                Err(PicobootError::Command {
+                    cmd_id: command.id,
+                    code: status.code,
+                })
+            }
+            result => result,
+        }
+    }
+
+    fn write_all(&mut self, mut data: &[u8]) -> Result<(), PicobootError> {
+        let len = data.len();
+        while !data.is_empty() {
+            let sent = self.transport.write_bulk(data)?;
+            if sent == 0 {
+                return Err(PicobootError::ShortTransfer {
+                    done: len - data.len(),
+                    len,
+                });
+            }
+            data = &data[sent..];
+        }
+        Ok(())
+    }
+
+    /// The status of the last command.
+    pub fn status(&mut self) -> Result<CommandStatus, PicobootError> {
+        let mut bytes = [0; 16];
+        self.transport
+            .control_in(PICOBOOT_IF_CMD_STATUS, &mut bytes)?;
+        Ok(CommandStatus::from_bytes(&bytes))
+    }
+
+    pub fn exclusive_access(&mut self, access: ExclusiveAccess) -> Result<(), PicobootError> {
+        self.command(&PicobootCommand::exclusive_access(access), &[])
+    }
+
+    pub fn exit_xip(&mut self) -> Result<(), PicobootError> {
+        self.command(&PicobootCommand::exit_xip(), &[])
+    }
+
+    pub fn flash_erase(&mut self, address: u32, size: u32) -> Result<(), PicobootError> {
+        self.command(&PicobootCommand::flash_erase(address, size), &[])
+    }
+
+    pub fn write(&mut self, address: u32, data: &[u8]) -> Result<(), PicobootError> {
+        self.command(&PicobootCommand::write(address, data.len() as u32), data)
+    }
+
+    pub fn read(&mut self, address: u32, size: u32) -> Result<Vec<u8>, PicobootError> {
+        self.command_in(&PicobootCommand::read(address, size))
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockTransport;
+
+    #[test]
+    fn commands_are_framed_like_picotool() {
+        assert_eq!(
+            PicobootCommand::reboot2_bootsel(500).to_bytes(0x1234),
+            [
+                0x0b, 0xd1, 0x1f, 0x43, // magic
+                0x34, 0x12, 0x00, 0x00, // token
+                0x0a, 0x10, 0x00, 0x00, // REBOOT2, 16 bytes of arguments
+                0x00, 0x00, 0x00, 0x00, // nothing transferred
+                0x02, 0x00, 0x00, 0x00, // reboot into BOOTSEL
+                0xf4, 0x01, 0x00, 0x00, // after 500 ms
+                0x00, 0x00, 0x00, 0x00, // every bootloader interface on
+                0x00, 0x00, 0x00, 0x00, // no activity LED
+            ]
+        );
+
+        let write = PicobootCommand::write(0x1000_0100, 0x200).to_bytes(7);
+        assert_eq!(write[8..16], [0x05, 0x08, 0, 0, 0x00, 0x02, 0, 0]);
+        assert_eq!(write[16..24], [0x00, 0x01, 0x00, 0x10, 0x00, 0x02, 0, 0]);
+
+        let exclusive = PicobootCommand::exclusive_access(ExclusiveAccess::ExclusiveAndEject);
+        assert_eq!(
+            exclusive.to_bytes(1)[8..17],
+            [0x01, 0x01, 0, 0, 0, 0, 0, 0, 0x02]
+        );
+    }
+
+    #[test]
+    fn commands_are_sent_with_their_data_and_acknowledged() {
+        let mut picoboot = Picoboot::new(MockTransport::default()).unwrap();
+        picoboot.exit_xip().unwrap();
+        picoboot.write(0x1000_0000, &[0xaa; 256]).unwrap();
+
+        let transport = picoboot.into_inner();
+        assert_eq!(transport.control_out, [PICOBOOT_IF_RESET]);
+        assert_eq!(transport.written.len(), 3);
+        assert_eq!(
+            transport.written[0],
+            PicobootCommand::exit_xip().to_bytes(1)
+        );
+        assert_eq!(
+            transport.written[1],
+            PicobootCommand::write(0x1000_0000, 256).to_bytes(2)
+        );
+        assert_eq!(transport.written[2], [0xaa; 256]);
+        assert_eq!(transport.acks, 2);
+    }
+
+    #[test]
+    fn reads_are_answered_and_acknowledged() {
+        let mut picoboot = Picoboot::new(MockTransport::default()).unwrap();
+        picoboot.write(0x1000_0000, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(picoboot.read(0x1000_0002, 4).unwrap(), [3, 4, 0xff, 0xff]);
+
+        let transport = picoboot.into_inner();
+        assert_eq!(
+            transport.written[2],
+            PicobootCommand::read(0x1000_0002, 4).to_bytes(2)
+        );
+        // The read is acknowledged with an empty packet
+        assert!(transport.written[3].is_empty());
+    }
+
+    #[test]
+    fn failing_command_reads_its_status_and_resets() {
+        let transport = MockTransport {
+            fail_command: Some(2),
+            status: CommandStatus {
+                token: 2,
+                code: 4,
+                cmd_id: PC_FLASH_ERASE,
+                in_progress: false,
+            },
+            ..Default::default()
+        };
+
+        let mut picoboot = Picoboot::new(transport).unwrap();
+        picoboot.exit_xip().unwrap();
+        let err = picoboot.flash_erase(0x2000_0000, 4096).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "PICOBOOT command 0x03 failed with status 4 (INVALID_ADDRESS)"
+        );
+        assert_eq!(
+            picoboot.into_inner().control_out,
+            [PICOBOOT_IF_RESET, PICOBOOT_IF_RESET]
+        );
+    }
+}
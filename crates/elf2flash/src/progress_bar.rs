@@ -1,48 +1,349 @@
-use std::io::Stdout;
+use std::sync::{LazyLock, OnceLock};
 
-use elf2flash_core::ProgressReporter;
-use log::{LevelFilter, max_level};
-use pbr::{ProgressBar, Units};
+use elf2flash_core::{
+    ProgressDetail, ProgressPhase, ProgressReporter,
+    events::{ConversionEvent, IgnoreReason},
+};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{Log, Metadata, Record};
+
+use crate::cancel::CancellationToken;
+
+/// How progress is shown, decided once at startup from the flags and the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Not at all
+    Off,
+    /// A log line every quarter of the way, for output that ends up in a file or a journal
+    Lines,
+    /// Bars redrawn in place on the terminal
+    Bars,
+}
+
+static MODE: OnceLock<ProgressMode> = OnceLock::new();
+
+pub fn init(mode: ProgressMode) {
+    let _ = MODE.set(mode);
+}
+
+/// The mode given to [`init`], no progress at all before that.
+pub fn mode() -> ProgressMode {
+    MODE.get().copied().unwrap_or(ProgressMode::Off)
+}
+
+/// Every bar on the terminal, each on a line of its own, with the logs printed above them
+static BARS: LazyLock<MultiProgress> =
+    LazyLock::new(|| MultiProgress::with_draw_target(ProgressDrawTarget::stdout()));
+
+/// The bar takes what the label, the message and the counts leave of the terminal's width
+const BAR_TEMPLATE: &str = "{prefix}{msg} [{wide_bar}] {bytes}/{total_bytes} {bytes_per_sec}";
+
+/// A logger writing through `inner` with the bars cleared, so the record ends up above them
+/// instead of in the middle of one.
+struct LogAboveBars<L> {
+    inner: L,
+}
+
+impl<L: Log> Log for LogAboveBars<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.enabled(record.metadata()) {
+            BARS.suspend(|| self.inner.log(record));
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Install `logger` as the logger, with its records kept clear of the progress bars.
+pub fn init_logger(logger: env_logger::Logger) {
+    let max_level = logger.filter();
+    log::set_boxed_logger(Box::new(LogAboveBars { inner: logger }))
+        .expect("The logger is only set once");
+    log::set_max_level(max_level);
+}
+
+/// Run `f` with the bars cleared, for printing past the logger without ending up in a bar.
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    BARS.suspend(f)
+}
+
+/// The label of the progress of a USB device, its board name and where it is plugged in.
+pub fn device_label(board_name: &str, bus_number: u8, address: u8) -> String {
+    format!("{board_name} {bus_number:03}:{address:03}")
+}
 
 pub struct ProgressBarReporter {
-    pb: Option<ProgressBar<Stdout>>,
+    output: Output,
+    /// Which device the progress is about, in front of every bar and line
+    label: Option<String>,
+    /// What the progress is about, from the last phase
+    message: String,
+    cancel: Option<CancellationToken>,
+}
+
+enum Output {
+    /// Progress is turned off
+    Quiet,
+    /// A bar redrawn in place on the terminal, below the logs and the bars before it
+    Bar(ProgressBar),
+    /// A log line every quarter of the way, for output that ends up in a file or a journal
+    Lines(ProgressLines),
+}
+
+struct ProgressLines {
+    message: String,
+    total: usize,
+    done: usize,
+    /// Quarters of `total` already logged
+    logged: usize,
+}
+
+impl ProgressLines {
+    /// Count `bytes` more as done, returning the line to log if that crossed a quarter.
+    fn advance(&mut self, bytes: usize) -> Option<String> {
+        self.done += bytes;
+        if self.total == 0 {
+            return None;
+        }
+
+        let quarters = (self.done * 4 / self.total).min(4);
+        if quarters > self.logged && quarters < 4 {
+            self.logged = quarters;
+            return Some(format!(
+                "{}: {}% ({}/{} bytes)",
+                self.message,
+                quarters * 25,
+                self.done,
+                self.total
+            ));
+        }
+        None
+    }
 }
 
 impl ProgressReporter for ProgressBarReporter {
+    fn phase(&mut self, phase: ProgressPhase) {
+        let message = match phase {
+            ProgressPhase::Converting => "Converting".to_string(),
+            ProgressPhase::Writing { board_name } => format!("Writing to {board_name}"),
+            ProgressPhase::Verifying => "Verifying".to_string(),
+        };
+
+        match &mut self.output {
+            Output::Quiet => (),
+            Output::Bar(pb) => pb.set_message(message.clone()),
+            Output::Lines(_) => (),
+        }
+        self.message = message;
+        let described = self.described();
+        if let Output::Lines(lines) = &mut self.output {
+            lines.message = described;
+        }
+    }
+
+    fn detail(&mut self, detail: ProgressDetail) {
+        // Lines are only logged every quarter of the way, the address would be stale by then
+        if let Output::Bar(pb) = &mut self.output {
+            pb.set_message(format!("{} {:#010x}", self.message, detail.target_addr));
+        }
+    }
+
+    fn event(&mut self, event: ConversionEvent) {
+        log_event(&event);
+    }
+
     fn start(&mut self, total_bytes: usize) {
-        if let Some(pb) = self.pb.as_mut() {
-            pb.total = total_bytes as u64;
-            pb.set_units(Units::Bytes);
+        match &mut self.output {
+            Output::Quiet => (),
+            Output::Bar(pb) => {
+                pb.set_length(total_bytes as u64);
+                pb.set_position(0);
+            }
+            Output::Lines(lines) => {
+                lines.total = total_bytes;
+                lines.done = 0;
+                lines.logged = 0;
+            }
         }
     }
 
     fn advance(&mut self, bytes: usize) {
-        if let Some(pb) = self.pb.as_mut() {
-            pb.add(bytes as u64);
+        match &mut self.output {
+            Output::Quiet => (),
+            Output::Bar(pb) => pb.inc(bytes as u64),
+            Output::Lines(lines) => {
+                if let Some(line) = lines.advance(bytes) {
+                    log::info!("{line}");
+                }
+            }
         }
     }
 
     fn finish(&mut self) {
-        if let Some(pb) = self.pb.as_mut() {
-            pb.finish();
+        let described = self.described();
+        match &mut self.output {
+            Output::Quiet => (),
+            // A finished bar would be cleared by the next log record, its outcome is logged
+            // instead, above the bars still going
+            Output::Bar(pb) => {
+                pb.finish_and_clear();
+                log::info!("{described}: done, {} bytes", pb.position());
+            }
+            Output::Lines(lines) => log::info!("{}: done, {} bytes", lines.message, lines.done),
         }
     }
+
+    fn cancel(&mut self) {
+        let described = self.described();
+        match &mut self.output {
+            Output::Quiet => (),
+            Output::Bar(pb) => {
+                pb.finish_and_clear();
+                log::info!(
+                    "{described}: cancelled after {}/{} bytes",
+                    pb.position(),
+                    pb.length().unwrap_or_default()
+                );
+            }
+            Output::Lines(lines) => log::info!(
+                "{}: cancelled after {}/{} bytes",
+                lines.message,
+                lines.done,
+                lines.total
+            ),
+        }
+    }
+
+    fn should_cancel(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+}
+
+/// Log a conversion event. Contents being dropped deserve a warning, padding and the like are
+/// expected and only logged for debugging.
+pub fn log_event(event: &ConversionEvent) {
+    match event {
+        ConversionEvent::SegmentIgnored {
+            reason: IgnoreReason::IgnoredRange,
+            ..
+        } => log::warn!("{event}"),
+        _ => log::debug!("{event}"),
+    }
+}
+
+impl Default for ProgressBarReporter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ProgressBarReporter {
+    /// A reporter without a label, for progress that isn't about one of several devices.
     pub fn new() -> Self {
-        let should_log = if max_level() >= LevelFilter::Info {
-            true
-        } else {
-            false
-        };
+        Self::with_label(None)
+    }
+
+    /// A reporter with `label`, e.g. from [`device_label`], in front of its bar or lines, so the
+    /// progress of several devices can be told apart.
+    pub fn labeled(label: impl Into<String>) -> Self {
+        Self::with_label(Some(label.into()))
+    }
 
-        if should_log {
-            Self {
-                pb: Some(ProgressBar::new(0)),
+    fn with_label(label: Option<String>) -> Self {
+        let output = match mode() {
+            ProgressMode::Off => Output::Quiet,
+            ProgressMode::Bars => {
+                let style = ProgressStyle::with_template(BAR_TEMPLATE)
+                    .expect("The bar template is valid")
+                    .progress_chars("=> ");
+                let pb = BARS.add(ProgressBar::new(0).with_style(style));
+                if let Some(label) = &label {
+                    pb.set_prefix(format!("{label}: "));
+                }
+                Output::Bar(pb)
             }
-        } else {
-            Self { pb: None }
+            ProgressMode::Lines => Output::Lines(ProgressLines {
+                message: "Progress".to_string(),
+                total: 0,
+                done: 0,
+                logged: 0,
+            }),
+        };
+
+        Self {
+            output,
+            label,
+            message: String::new(),
+            cancel: None,
+        }
+    }
+
+    /// The message of the last phase, behind the label if there is one.
+    fn described(&self) -> String {
+        match &self.label {
+            Some(label) => format!("{label}: {}", self.message),
+            None => self.message.clone(),
         }
     }
+
+    /// Ask whatever is reporting to stop once `cancel` is cancelled.
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_logged_every_quarter() {
+        let mut lines = ProgressLines {
+            message: "rp2040 001:012: Writing to rp2040".to_string(),
+            total: 1000,
+            done: 0,
+            logged: 0,
+        };
+        let logged: Vec<String> = [100, 200, 300, 150, 250]
+            .into_iter()
+            .filter_map(|bytes| lines.advance(bytes))
+            .collect();
+        assert_eq!(
+            logged,
+            [
+                "rp2040 001:012: Writing to rp2040: 25% (300/1000 bytes)",
+                "rp2040 001:012: Writing to rp2040: 50% (600/1000 bytes)",
+                "rp2040 001:012: Writing to rp2040: 75% (750/1000 bytes)",
+            ]
+        );
+        // Done is logged by finish, not as 100%
+        assert_eq!(lines.done, 1000);
+    }
+
+    #[test]
+    fn labeled_reporter_prefixes_its_lines() {
+        let mut reporter = ProgressBarReporter::labeled(device_label("rp2040", 1, 12));
+        reporter.output = Output::Lines(ProgressLines {
+            message: String::new(),
+            total: 0,
+            done: 0,
+            logged: 0,
+        });
+        reporter.phase(ProgressPhase::Writing {
+            board_name: "rp2040".to_string(),
+        });
+        let Output::Lines(lines) = &reporter.output else {
+            unreachable!()
+        };
+        assert_eq!(lines.message, "rp2040 001:012: Writing to rp2040");
+    }
 }
@@ -0,0 +1,332 @@
+//! The serial connection to the flashed firmware, as `deploy --serial` and `monitor` open it.
+
+use std::{fmt, path::PathBuf, time::Duration};
+
+use clap::{Args, ValueEnum, ValueHint};
+use serialport::{DataBits, FlowControl, Parity, SerialPort, SerialPortBuilder, StopBits};
+
+/// Baud rate without `--baud`
+pub const DEFAULT_BAUD: u32 = 115200;
+
+/// How long a read waits for data, Ctrl+C is checked in between
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How to talk to the serial port of the firmware, 115200 8N1 without flow control by default.
+#[derive(Args, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialArgs {
+    /// Baud rate of the serial connection, 115200 by default
+    #[clap(
+        long,
+        value_name = "BAUD",
+        value_parser = baud_parser,
+        env = "ELF2FLASH_SERIAL_BAUD"
+    )]
+    pub baud: Option<u32>,
+
+    /// Bits per character of the serial connection, 5 to 8
+    #[clap(long, value_name = "BITS", value_parser = data_bits_parser, default_value = "8")]
+    pub data_bits: DataBits,
+
+    /// Parity bit of the serial connection
+    #[clap(long, value_enum, default_value_t = SerialParity::None)]
+    pub parity: SerialParity,
+
+    /// Stop bits of the serial connection, 1 or 2
+    #[clap(long, value_name = "BITS", value_parser = stop_bits_parser, default_value = "1")]
+    pub stop_bits: StopBits,
+
+    /// Flow control of the serial connection: none, XON/XOFF characters, or the RTS and CTS lines
+    #[clap(long, value_enum, default_value_t = SerialFlowControl::None)]
+    pub flow_control: SerialFlowControl,
+}
+
+/// What happens to the output of the firmware besides printing it.
+#[derive(Args, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SerialOutputArgs {
+    /// Prefix every line of the serial output with the time it started arriving, as
+    /// [HH:MM:SS.mmm]
+    #[clap(long)]
+    pub timestamps: bool,
+
+    /// Also write the serial output to this file, as it was received without timestamps. The file
+    /// is truncated first, unless --log-append is given
+    #[clap(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub log_file: Option<PathBuf>,
+
+    /// Append to the --log-file instead of truncating it
+    #[clap(long, requires = "log_file")]
+    pub log_append: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SerialParity {
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SerialFlowControl {
+    None,
+    Software,
+    Hardware,
+}
+
+fn baud_parser(s: &str) -> Result<u32, &'static str> {
+    match s.parse::<u32>() {
+        Ok(0) => Err("the baud rate must be above 0"),
+        Ok(baud) => Ok(baud),
+        Err(_) => Err("invalid baud rate, expected a number like 115200"),
+    }
+}
+
+fn data_bits_parser(s: &str) -> Result<DataBits, &'static str> {
+    match s {
+        "5" => Ok(DataBits::Five),
+        "6" => Ok(DataBits::Six),
+        "7" => Ok(DataBits::Seven),
+        "8" => Ok(DataBits::Eight),
+        _ => Err("expected 5, 6, 7 or 8 data bits"),
+    }
+}
+
+fn stop_bits_parser(s: &str) -> Result<StopBits, &'static str> {
+    match s {
+        "1" => Ok(StopBits::One),
+        "2" => Ok(StopBits::Two),
+        _ => Err("expected 1 or 2 stop bits"),
+    }
+}
+
+/// Everything [`open`] configures the port with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialSettings {
+    pub baud: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+}
+
+impl Default for SerialSettings {
+    fn default() -> Self {
+        Self {
+            baud: DEFAULT_BAUD,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+        }
+    }
+}
+
+impl From<SerialArgs> for SerialSettings {
+    fn from(args: SerialArgs) -> Self {
+        Self {
+            baud: args.baud.unwrap_or(DEFAULT_BAUD),
+            data_bits: args.data_bits,
+            parity: match args.parity {
+                SerialParity::None => Parity::None,
+                SerialParity::Odd => Parity::Odd,
+                SerialParity::Even => Parity::Even,
+            },
+            stop_bits: args.stop_bits,
+            flow_control: match args.flow_control {
+                SerialFlowControl::None => FlowControl::None,
+                SerialFlowControl::Software => FlowControl::Software,
+                SerialFlowControl::Hardware => FlowControl::Hardware,
+            },
+        }
+    }
+}
+
+/// The usual short form, e.g. `115200 8N1`
+impl fmt::Display for SerialSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Odd => 'O',
+            Parity::Even => 'E',
+        };
+        write!(
+            f,
+            "{} {}{parity}{}",
+            self.baud,
+            u8::from(self.data_bits),
+            u8::from(self.stop_bits)
+        )?;
+        match self.flow_control {
+            FlowControl::None => Ok(()),
+            FlowControl::Software => write!(f, " XON/XOFF"),
+            FlowControl::Hardware => write!(f, " RTS/CTS"),
+        }
+    }
+}
+
+/// The builder [`open`] opens `port_name` with.
+pub fn builder(port_name: &str, settings: &SerialSettings) -> SerialPortBuilder {
+    serialport::new(port_name, settings.baud)
+        .data_bits(settings.data_bits)
+        .parity(settings.parity)
+        .stop_bits(settings.stop_bits)
+        .flow_control(settings.flow_control)
+        .timeout(READ_TIMEOUT)
+}
+
+/// Open the serial port `port_name` with `settings`.
+pub fn open(port_name: &str, settings: &SerialSettings) -> serialport::Result<Box<dyn SerialPort>> {
+    builder(port_name, settings).open()
+}
+
+/// Puts a timestamp in front of every line of a byte stream that arrives in chunks of any size.
+///
+/// A line starts after a `\n`, and its timestamp is only written once its first byte arrives, so
+/// a `\r\n` split across two reads stays together and the timestamp is the time the line
+/// started arriving. Nothing is inserted in front of a byte other than after a `\n`, which keeps
+/// UTF-8 sequences intact however the reads split them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineStamper {
+    at_line_start: bool,
+}
+
+impl Default for LineStamper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineStamper {
+    pub fn new() -> Self {
+        Self {
+            at_line_start: true,
+        }
+    }
+
+    /// Append `chunk` to `out`, with `timestamp()` in front of each line starting in it.
+    pub fn stamp(&mut self, chunk: &[u8], timestamp: impl Fn() -> String, out: &mut Vec<u8>) {
+        for line in chunk.split_inclusive(|&byte| byte == b'\n') {
+            if self.at_line_start {
+                out.extend_from_slice(timestamp().as_bytes());
+            }
+            out.extend_from_slice(line);
+            self.at_line_start = line.ends_with(b"\n");
+        }
+    }
+}
+
+/// The host's local time as `[HH:MM:SS.mmm] `, the prefix of `--timestamps`
+pub fn timestamp() -> String {
+    chrono::Local::now().format("[%H:%M:%S%.3f] ").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        serial: SerialArgs,
+    }
+
+    #[test]
+    fn flags_configure_the_builder() {
+        let cli = Cli::parse_from(["deploy"]);
+        let settings = SerialSettings::from(cli.serial);
+        assert_eq!(settings, SerialSettings::default());
+        assert_eq!(settings.to_string(), "115200 8N1");
+        assert_eq!(
+            builder("/dev/ttyACM0", &settings),
+            serialport::new("/dev/ttyACM0", 115200)
+                .flow_control(FlowControl::None)
+                .timeout(READ_TIMEOUT)
+        );
+
+        let cli = Cli::parse_from([
+            "deploy",
+            "--baud",
+            "921600",
+            "--data-bits",
+            "7",
+            "--parity",
+            "even",
+            "--stop-bits",
+            "2",
+            "--flow-control",
+            "hardware",
+        ]);
+        let settings = SerialSettings::from(cli.serial);
+        assert_eq!(settings.to_string(), "921600 7E2 RTS/CTS");
+        assert_eq!(
+            builder("COM3", &settings),
+            serialport::new("COM3", 921600)
+                .data_bits(DataBits::Seven)
+                .parity(Parity::Even)
+                .stop_bits(StopBits::Two)
+                .flow_control(FlowControl::Hardware)
+                .timeout(READ_TIMEOUT)
+        );
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        for (flag, value, message) in [
+            ("--baud", "0", "the baud rate must be above 0"),
+            ("--baud", "fast", "invalid baud rate"),
+            ("--data-bits", "9", "expected 5, 6, 7 or 8 data bits"),
+            ("--stop-bits", "1.5", "expected 1 or 2 stop bits"),
+            ("--parity", "mark", "possible values: none, odd, even"),
+        ] {
+            let err = Cli::try_parse_from(["deploy", flag, value])
+                .err()
+                .unwrap()
+                .to_string();
+            assert!(err.contains(message), "{flag} {value}: {err}");
+        }
+    }
+
+    fn stamp_chunks(chunks: &[&[u8]]) -> Vec<u8> {
+        let mut stamper = LineStamper::new();
+        let mut out = Vec::new();
+        for (read, chunk) in chunks.iter().enumerate() {
+            stamper.stamp(chunk, || format!("<{read}>"), &mut out);
+        }
+        out
+    }
+
+    #[test]
+    fn lines_are_stamped_across_split_reads() {
+        assert_eq!(stamp_chunks(&[b"boot\nready\n"]), b"<0>boot\n<0>ready\n");
+        assert_eq!(stamp_chunks(&[]), b"");
+        assert_eq!(stamp_chunks(&[b"", b"a"]), b"<1>a");
+
+        // A line is stamped with the read its first byte came in
+        assert_eq!(
+            stamp_chunks(&[b"bo", b"ot\nre", b"ady", b"\n"]),
+            b"<0>boot\n<1>ready\n"
+        );
+
+        // \r\n split between two reads, and the next line only arriving with a third
+        assert_eq!(
+            stamp_chunks(&[b"one\r", b"\n", b"two\r\n"]),
+            b"<0>one\r\n<2>two\r\n"
+        );
+
+        // A UTF-8 sequence split between two reads comes out whole
+        let temperature = "25 \u{b0}C\n".as_bytes();
+        let (first, second) = temperature.split_at(4);
+        let out = stamp_chunks(&[first, second]);
+        assert_eq!(String::from_utf8(out).unwrap(), "<0>25 \u{b0}C\n");
+
+        // A lone \r doesn't end a line
+        assert_eq!(stamp_chunks(&[b"50%\r", b"100%\n"]), b"<0>50%\r100%\n");
+    }
+
+    #[test]
+    fn timestamp_is_wall_clock_time_of_day() {
+        let timestamp = timestamp();
+        assert_eq!(timestamp.len(), "[12:34:56.789] ".len(), "{timestamp}");
+        assert!(timestamp.starts_with('[') && timestamp.ends_with("] "));
+    }
+}
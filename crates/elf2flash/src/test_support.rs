@@ -0,0 +1,240 @@
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
+    rc::Rc,
+};
+
+use elf2flash_core::boards::{BoardInfo, BoardRegistry, UsbDevice, UsbVersion};
+use fatfs::{FatType, FileSystem, FormatVolumeOptions, FsOptions};
+use usbh_fatfs::{
+    rusb,
+    usbh_scsi::storage::{UsbMassStorageReadWriteError, block_device::CommandFailed},
+};
+
+use crate::picoboot::{CommandStatus, PICOBOOT_MAGIC, Transport};
+
+/// Size of the in-memory volumes created by [`fat_image`].
+pub const FAT_IMAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// Size of the in-memory volumes created by [`fat12_image`].
+pub const FAT12_IMAGE_SIZE: usize = 1024 * 1024;
+
+/// Root directory entries of the volumes created by [`fat12_image`].
+pub const FAT12_ROOT_DIR_ENTRIES: u16 = 16;
+
+/// Create an in-memory FAT volume, like the one a UF2 bootloader exposes, containing `files` in
+/// its root directory.
+pub fn fat_image(files: &[(&str, &[u8])]) -> Cursor<Vec<u8>> {
+    formatted_image(FAT_IMAGE_SIZE, FormatVolumeOptions::new(), files)
+}
+
+/// Create an in-memory FAT12 volume with a small root directory, like the smallest UF2
+/// bootloaders expose, containing `files` in its root directory.
+pub fn fat12_image(files: &[(&str, &[u8])]) -> Cursor<Vec<u8>> {
+    let options = FormatVolumeOptions::new()
+        .fat_type(FatType::Fat12)
+        .max_root_dir_entries(FAT12_ROOT_DIR_ENTRIES);
+    formatted_image(FAT12_IMAGE_SIZE, options, files)
+}
+
+fn formatted_image(
+    size: usize,
+    options: FormatVolumeOptions,
+    files: &[(&str, &[u8])],
+) -> Cursor<Vec<u8>> {
+    let mut image = Cursor::new(vec![0u8; size]);
+    fatfs::format_volume(&mut image, options).unwrap();
+
+    {
+        let fatfs = FileSystem::new(&mut image, FsOptions::new()).unwrap();
+        for (name, data) in files {
+            let mut file = fatfs.root_dir().create_file(name).unwrap();
+            file.write_all(data).unwrap();
+        }
+    }
+
+    image.set_position(0);
+    image
+}
+
+/// A device with these USB ids and no serial number.
+pub fn usb_device(vendor_id: u16, product_id: u16) -> UsbDevice {
+    UsbDevice {
+        bus_number: 1,
+        address: 4,
+        vendor_id,
+        product_id,
+        version: UsbVersion(1, 0, 0),
+        serial_number: None,
+    }
+}
+
+/// Removes the board called `.0` from the [`BoardRegistry`] when dropped. Every test shares the
+/// registry, so a board registered by one is taken out again once it is done, passed or not.
+pub struct Unregister(pub &'static str);
+
+impl Drop for Unregister {
+    fn drop(&mut self) {
+        BoardRegistry::unregister(self.0);
+    }
+}
+
+/// Register `board` until the returned guard is dropped, see [`Unregister`]. Use a name no other
+/// test registers.
+pub fn register_board(name: &'static str, board: impl BoardInfo + 'static) -> Unregister {
+    assert_eq!(board.board_name(), name);
+    BoardRegistry::register(board);
+    Unregister(name)
+}
+
+/// An in-memory volume whose reads and writes time out like a USB device that stopped
+/// responding, once `failing` is set.
+pub struct FailingDisk {
+    pub image: Cursor<Vec<u8>>,
+    pub failing: Rc<Cell<bool>>,
+}
+
+impl FailingDisk {
+    pub fn new(image: Cursor<Vec<u8>>) -> Self {
+        Self {
+            image,
+            failing: Rc::new(Cell::new(false)),
+        }
+    }
+
+    /// The error a `UsbBlockDevice` returns when `command` times out
+    fn timeout(command: &'static str) -> io::Error {
+        CommandFailed {
+            command,
+            source: UsbMassStorageReadWriteError::UsbDeviceBulkFailed(rusb::Error::Timeout),
+        }
+        .into()
+    }
+}
+
+impl Read for FailingDisk {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.failing.get() {
+            return Err(Self::timeout("READ(10)"));
+        }
+        self.image.read(buf)
+    }
+}
+
+impl Write for FailingDisk {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.failing.get() {
+            return Err(Self::timeout("WRITE(10)"));
+        }
+        self.image.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.image.flush()
+    }
+}
+
+impl Seek for FailingDisk {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.image.seek(pos)
+    }
+}
+
+/// A PICOBOOT device acknowledging every command, recording what is sent to it. WRITE, READ and
+/// FLASH_ERASE commands act on its `flash`.
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    /// Every bulk write, the commands and the data sent after them
+    pub written: Vec<Vec<u8>>,
+    /// The control requests sent to the interface
+    pub control_out: Vec<u8>,
+    /// Acknowledged commands
+    pub acks: usize,
+    /// The command, counting from 1, that stalls instead of being acknowledged
+    pub fail_command: Option<usize>,
+    /// The status read after the failing command
+    pub status: CommandStatus,
+    /// The written bytes by address, the others read as erased
+    pub flash: BTreeMap<u32, u8>,
+    /// The id, address and size of the last command, until its data is transferred
+    pub pending: Option<(u8, u32, u32)>,
+}
+
+impl MockTransport {
+    /// The id and arguments of every command sent, in order
+    pub fn commands(&self) -> Vec<(u8, Vec<u32>)> {
+        self.written
+            .iter()
+            .filter_map(|bytes| Self::command(bytes))
+            .collect()
+    }
+
+    fn command(bytes: &[u8]) -> Option<(u8, Vec<u32>)> {
+        if bytes.len() != 32 || bytes[0..4] != PICOBOOT_MAGIC.to_le_bytes() {
+            return None;
+        }
+        let args = bytes[16..16 + usize::from(bytes[9] / 4) * 4]
+            .chunks_exact(4)
+            .map(|arg| u32::from_le_bytes(arg.try_into().unwrap()))
+            .collect();
+        Some((bytes[8], args))
+    }
+}
+
+impl Transport for MockTransport {
+    fn write_bulk(&mut self, data: &[u8]) -> Result<usize, rusb::Error> {
+        self.written.push(data.to_vec());
+        match (Self::command(data), self.pending.take()) {
+            (Some((id, args)), _) => match (id, args.as_slice()) {
+                // FLASH_ERASE
+                (0x03, &[address, size]) => {
+                    let end = u64::from(address) + u64::from(size);
+                    self.flash.retain(|&byte, _| {
+                        u64::from(byte) < u64::from(address) || u64::from(byte) >= end
+                    });
+                }
+                // WRITE and READ
+                (0x05 | 0x84, &[address, size]) => self.pending = Some((id, address, size)),
+                _ => {}
+            },
+            (None, Some((0x05, address, _))) => {
+                for (offset, byte) in data.iter().enumerate() {
+                    self.flash.insert(address + offset as u32, *byte);
+                }
+            }
+            // The acknowledgement of a READ
+            (None, _) => self.acks += 1,
+        }
+        Ok(data.len())
+    }
+
+    fn read_bulk(&mut self, buf: &mut [u8]) -> Result<usize, rusb::Error> {
+        if let Some((0x84, address, size)) = self.pending {
+            self.pending = None;
+            for (offset, byte) in buf[..size as usize].iter_mut().enumerate() {
+                *byte = *self.flash.get(&(address + offset as u32)).unwrap_or(&0xff);
+            }
+            return Ok(size as usize);
+        }
+        if self.fail_command == Some(self.acks + 1) {
+            self.fail_command = None;
+            return Err(rusb::Error::Pipe);
+        }
+        self.acks += 1;
+        Ok(0)
+    }
+
+    fn control_in(&mut self, _request: u8, buf: &mut [u8]) -> Result<usize, rusb::Error> {
+        buf[0..4].copy_from_slice(&self.status.token.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.status.code.to_le_bytes());
+        buf[8] = self.status.cmd_id;
+        buf[9] = u8::from(self.status.in_progress);
+        Ok(16)
+    }
+
+    fn control_out(&mut self, request: u8) -> Result<(), rusb::Error> {
+        self.control_out.push(request);
+        Ok(())
+    }
+}
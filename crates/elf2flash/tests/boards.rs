@@ -0,0 +1,56 @@
+//! Listing the boards `--board` accepts.
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+/// `elf2flash boards`, with `config` as the config directory so only the boards of `args` are
+/// added to the built-in ones.
+fn boards(config: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .env("XDG_CONFIG_HOME", config)
+        .arg("boards")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn built_in_boards_are_listed() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = boards(dir.path(), &[]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        include_str!("golden/boards.txt")
+    );
+}
+
+#[test]
+fn boards_file_boards_are_listed_as_json() {
+    let dir = tempfile::tempdir().unwrap();
+    let boards_file = dir.path().join("lab.toml");
+    fs::write(
+        &boards_file,
+        "[[board]]\nname = \"lab_board\"\nvendor_id = 0x1209\nproduct_id = [0x4c42, 0x4c43]\n\
+         family_id = \"SAMD21\"\n",
+    )
+    .unwrap();
+
+    let output = boards(
+        dir.path(),
+        &["--json", "--boards-file", boards_file.to_str().unwrap()],
+    );
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let boards = json.as_array().unwrap();
+
+    // Boards from the file come first, like they win over the built-in ones
+    assert_eq!(boards[0]["name"], "lab_board");
+    assert_eq!(boards[0]["family"], "SAMD21");
+    assert_eq!(boards[0]["family_id"], 0x68ed2b88);
+    assert_eq!(boards[0]["usb_ids"][1]["product_id"], 0x4c43);
+    assert!(boards.iter().any(|board| board["name"] == "rp2040"));
+}
@@ -0,0 +1,84 @@
+//! Boards defined in a TOML file, instead of built into elf2flash.
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+const HELLO_USB: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../elf2flash-core/tests/rp2040/hello_usb.elf"
+);
+
+const BOARDS: &str = r#"
+[[board]]
+name = "lab_board"
+vendor_id = 0x1209
+product_id = 0x4c42
+family_id = "SAMD21"
+"#;
+
+/// Convert hello_usb for `lab_board`, with `config` as the config directory.
+fn convert(config: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .env("XDG_CONFIG_HOME", config)
+        .args(args)
+        .args(["convert", "--board", "lab_board", HELLO_USB])
+        .arg(config.join("out.uf2"))
+        .output()
+        .unwrap()
+}
+
+/// The family id of the first block of the uf2 file at `path`
+fn family_id(path: &Path) -> u32 {
+    let uf2 = fs::read(path).unwrap();
+    u32::from_le_bytes(uf2[28..32].try_into().unwrap())
+}
+
+#[test]
+fn boards_file_flag_defines_boards() {
+    let dir = tempfile::tempdir().unwrap();
+    let boards = dir.path().join("lab.toml");
+    fs::write(&boards, BOARDS).unwrap();
+
+    let output = convert(dir.path(), &[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown board 'lab_board'"));
+
+    let output = convert(dir.path(), &["--boards-file", boards.to_str().unwrap()]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(family_id(&dir.path().join("out.uf2")), 0x68ed2b88);
+}
+
+#[test]
+fn boards_file_is_found_in_the_config_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("elf2flash")).unwrap();
+    fs::write(dir.path().join("elf2flash/boards.toml"), BOARDS).unwrap();
+
+    let output = convert(dir.path(), &[]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(family_id(&dir.path().join("out.uf2")), 0x68ed2b88);
+
+    // Errors in the file name the board
+    fs::write(
+        dir.path().join("elf2flash/boards.toml"),
+        "[[board]]\nname = \"lab_board\"\nvendor_id = 1\nproduct_id = 2\n",
+    )
+    .unwrap();
+    let output = convert(dir.path(), &[]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("Line 1: board 'lab_board' has no family_id")
+    );
+}
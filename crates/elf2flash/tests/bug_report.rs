@@ -0,0 +1,36 @@
+//! `deploy --bug-report` saving its report on error exits as well.
+
+use std::{fs, process::Command};
+
+const HELLO_USB: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../elf2flash-core/tests/rp2040/hello_usb.elf"
+);
+
+#[test]
+fn bug_report_is_saved_when_the_deploy_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let report = dir.path().join("report.json");
+    // Whether or not devices are found, none has this serial number
+    let output = Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .env("XDG_CONFIG_HOME", dir.path())
+        .current_dir(dir.path())
+        .args([
+            "deploy",
+            HELLO_USB,
+            "--board",
+            "rp2040",
+            "--device",
+            "serial:E6614C311B2F",
+            "--bug-report",
+        ])
+        .arg(&report)
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "{output:?}");
+
+    let text = fs::read_to_string(&report).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert!(json["error"].is_string(), "{text}");
+    assert!(!text.contains("E6614C311B2F"), "{text}");
+}
@@ -0,0 +1,63 @@
+//! Ctrl+C in the middle of a deploy, run against a volume image instead of a device.
+#![cfg(unix)]
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    process::{Command, Stdio},
+};
+
+use fatfs::FormatVolumeOptions;
+
+const HELLO_USB: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../elf2flash-core/tests/rp2040/hello_usb.elf"
+);
+
+#[test]
+fn ctrl_c_during_deploy_exits_cancelled() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = dir.path().join("volume.img");
+    let mut volume = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&image)
+        .unwrap();
+    volume.set_len(2 * 1024 * 1024).unwrap();
+    fatfs::format_volume(&mut volume, FormatVolumeOptions::new()).unwrap();
+    drop(volume);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .args(["deploy", HELLO_USB, "--board", "rp2040", "--mock-volume"])
+        .arg(&image)
+        // Slow enough that the three chunks of out.uf2 take well over a second
+        .args(["--mock-write-delay", "100"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut output = String::new();
+    loop {
+        let mut line = String::new();
+        let read = stdout.read_line(&mut line).unwrap();
+        output.push_str(&line);
+        assert_ne!(read, 0, "deploy ended before writing:\n{output}");
+        if line.starts_with("Writing firmware") {
+            break;
+        }
+    }
+
+    let killed = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+
+    stdout.read_to_string(&mut output).unwrap();
+    let status = child.wait().unwrap();
+
+    assert_eq!(status.code(), Some(130), "{output}");
+    assert!(output.ends_with("Cancelled by Ctrl+C\n"), "{output}");
+}
@@ -0,0 +1,92 @@
+//! Defaults for the flags from elf2flash.toml and ~/.config/elf2flash/config.toml.
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+const HELLO_USB: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../elf2flash-core/tests/rp2040/hello_usb.elf"
+);
+
+/// Run elf2flash in `project`, with `config` as the config directory.
+fn run(project: &Path, config: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .current_dir(project)
+        .env("XDG_CONFIG_HOME", config)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// The family id of the last block of the uf2 file at `path`, RP2350 files start with the
+/// absolute block
+fn family_id(path: &Path) -> u32 {
+    let uf2 = fs::read(path).unwrap();
+    let last = uf2.len() - 512;
+    u32::from_le_bytes(uf2[last + 28..last + 32].try_into().unwrap())
+}
+
+#[test]
+fn project_config_is_found_in_a_parent_directory() {
+    let config = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    let build = project.path().join("target/debug");
+    fs::create_dir_all(&build).unwrap();
+    fs::write(
+        project.path().join("elf2flash.toml"),
+        "board = \"rp2350\"\n",
+    )
+    .unwrap();
+
+    let output = run(&build, config.path(), &["convert", HELLO_USB, "out.uf2"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(family_id(&build.join("out.uf2")), 0xe48bff59);
+
+    // The command line wins
+    let output = run(
+        &build,
+        config.path(),
+        &["convert", "--board", "rp2040", HELLO_USB, "out.uf2"],
+    );
+    assert!(output.status.success());
+    assert_eq!(family_id(&build.join("out.uf2")), 0xe48bff56);
+
+    let output = run(
+        &build,
+        config.path(),
+        &["--no-config", "convert", HELLO_USB, "out.uf2"],
+    );
+    assert!(!output.status.success());
+}
+
+#[test]
+fn show_names_the_source_of_every_value() {
+    let config = tempfile::tempdir().unwrap();
+    let project = tempfile::tempdir().unwrap();
+    fs::create_dir(config.path().join("elf2flash")).unwrap();
+    let global = config.path().join("elf2flash/config.toml");
+    fs::write(&global, "board = \"rp2040\"\nserial = true\n").unwrap();
+    let local = project.path().join("elf2flash.toml");
+    fs::write(&local, "board = \"rp2350\"\n").unwrap();
+
+    let output = run(project.path(), config.path(), &["config", "show"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!("board = \"rp2350\"  # {}", local.display())));
+    assert!(stdout.contains(&format!("serial = true     # {}", global.display())));
+
+    // Mistakes in a file fail every command, naming the file
+    fs::write(&local, "bord = \"rp2350\"\n").unwrap();
+    let output = run(project.path(), config.path(), &["config", "show"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("Failed to load {}", local.display())));
+    assert!(stderr.contains("unknown field `bord`"));
+}
@@ -0,0 +1,64 @@
+//! Converting through pipes, with `-` for stdin and stdout.
+
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+const FIXTURES: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../elf2flash-core/tests/rp2040"
+);
+
+/// `elf2flash convert --board rp2040 <input> <output>` with `stdin` piped in.
+fn convert(input: &str, output: &str, stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .args(["convert", "--board", "rp2040", input, output])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn elf_is_piped_through() {
+    let elf = fs::read(format!("{FIXTURES}/hello_usb.elf")).unwrap();
+    let output = convert("-", "-", &elf);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        output.stdout,
+        fs::read(format!("{FIXTURES}/hello_usb.uf2")).unwrap()
+    );
+    // The logs went to stderr, out of the uf2's way
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Wrote UF2 to stdout"));
+}
+
+#[test]
+fn stdin_can_be_written_to_a_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let uf2 = dir.path().join("hello_usb.uf2");
+    let elf = fs::read(format!("{FIXTURES}/hello_usb.elf")).unwrap();
+
+    let output = convert("-", uf2.to_str().unwrap(), &elf);
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read(&uf2).unwrap(),
+        fs::read(format!("{FIXTURES}/hello_usb.uf2")).unwrap()
+    );
+}
+
+#[test]
+fn truncated_stdin_fails_without_output() {
+    let elf = fs::read(format!("{FIXTURES}/hello_usb.elf")).unwrap();
+    let output = convert("-", "-", &elf[..64]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
@@ -0,0 +1,135 @@
+//! Deploying inputs that aren't ELF files onto a volume image.
+
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use fatfs::{FileSystem, FormatVolumeOptions, FsOptions};
+
+const HELLO_USB_UF2: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../elf2flash-core/tests/rp2040/hello_usb.uf2"
+);
+
+fn volume_image(dir: &Path) -> PathBuf {
+    let image = dir.join("volume.img");
+    let mut volume = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&image)
+        .unwrap();
+    volume.set_len(2 * 1024 * 1024).unwrap();
+    fatfs::format_volume(&mut volume, FormatVolumeOptions::new()).unwrap();
+    image
+}
+
+fn deploy(input: &Path, image: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .arg("deploy")
+        .arg(input)
+        .args(args)
+        .arg("--mock-volume")
+        .arg(image)
+        .output()
+        .unwrap()
+}
+
+fn written_uf2(image: &Path) -> Vec<u8> {
+    let fs = FileSystem::new(File::open(image).unwrap(), FsOptions::new()).unwrap();
+    let mut uf2 = Vec::new();
+    fs.root_dir()
+        .open_file("out.uf2")
+        .unwrap()
+        .read_to_end(&mut uf2)
+        .unwrap();
+    uf2
+}
+
+#[test]
+fn uf2_input_is_written_as_it_is() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume_image(dir.path());
+    // Named like a binary, the contents tell it is a uf2 file
+    let input = dir.path().join("firmware.bin");
+    fs::copy(HELLO_USB_UF2, &input).unwrap();
+
+    let output = deploy(&input, &image, &["--board", "rp2040"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(written_uf2(&image), fs::read(HELLO_USB_UF2).unwrap());
+}
+
+#[test]
+fn written_uf2_is_verified() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume_image(dir.path());
+
+    let output = deploy(
+        Path::new(HELLO_USB_UF2),
+        &image,
+        &["--board", "rp2040", "--verify"],
+    );
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let size = fs::metadata(HELLO_USB_UF2).unwrap().len();
+    assert!(
+        stdout.contains(&format!("Verification passed ({size} bytes)")),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn uf2_input_for_another_family_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume_image(dir.path());
+
+    let output = deploy(
+        Path::new(HELLO_USB_UF2),
+        &image,
+        &["--board", "circuit_playground_bluefruit"],
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("0xe48bff56 (RP2040)"), "{stderr}");
+    assert!(stderr.contains("0xada52840"), "{stderr}");
+
+    let output = deploy(
+        Path::new(HELLO_USB_UF2),
+        &image,
+        &["--board", "circuit_playground_bluefruit", "--force-family"],
+    );
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(written_uf2(&image), fs::read(HELLO_USB_UF2).unwrap());
+}
+
+#[test]
+fn binary_input_is_loaded_at_the_base() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume_image(dir.path());
+    let input = dir.path().join("firmware.bin");
+    fs::write(&input, [0x5a; 300]).unwrap();
+
+    let output = deploy(&input, &image, &["--board", "rp2040"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--base"));
+
+    let output = deploy(
+        &input,
+        &image,
+        &[
+            "--board",
+            "rp2040",
+            "--base",
+            "0x10000000",
+            "--no-sector-fill",
+        ],
+    );
+    assert!(output.status.success(), "{output:?}");
+    let uf2 = written_uf2(&image);
+    assert_eq!(uf2.len(), 2 * 512);
+    // The target address of the first block
+    assert_eq!(uf2[12..16], 0x10000000u32.to_le_bytes());
+}
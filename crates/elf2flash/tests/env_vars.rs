@@ -0,0 +1,87 @@
+//! Defaults for the flags from the `ELF2FLASH_*` environment variables, above the config files.
+
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use fatfs::{FileSystem, FormatVolumeOptions, FsOptions};
+
+const HELLO_USB: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../elf2flash-core/tests/rp2040/hello_usb.elf"
+);
+
+fn volume_image(dir: &Path) -> PathBuf {
+    let image = dir.join("volume.img");
+    let mut volume = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&image)
+        .unwrap();
+    volume.set_len(2 * 1024 * 1024).unwrap();
+    fatfs::format_volume(&mut volume, FormatVolumeOptions::new()).unwrap();
+    image
+}
+
+/// Deploy to `image` from `project`, with `ELF2FLASH_BOARD` set to `board`.
+fn deploy(project: &Path, image: &Path, board: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .current_dir(project)
+        .env("XDG_CONFIG_HOME", project)
+        .env("ELF2FLASH_BOARD", board)
+        .arg("deploy")
+        .arg(HELLO_USB)
+        .arg("--mock-volume")
+        .arg(image)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// The family id of the last block written to `image`, RP2350 files start with the absolute
+/// block
+fn family_id(image: &Path) -> u32 {
+    let fs = FileSystem::new(File::open(image).unwrap(), FsOptions::new()).unwrap();
+    let mut uf2 = Vec::new();
+    fs.root_dir()
+        .open_file("out.uf2")
+        .unwrap()
+        .read_to_end(&mut uf2)
+        .unwrap();
+    let last = uf2.len() - 512;
+    u32::from_le_bytes(uf2[last + 28..last + 32].try_into().unwrap())
+}
+
+#[test]
+fn board_variable_beats_the_config_file_but_not_the_command_line() {
+    let project = tempfile::tempdir().unwrap();
+    fs::write(
+        project.path().join("elf2flash.toml"),
+        "board = \"rp2040\"\n",
+    )
+    .unwrap();
+
+    let image = volume_image(project.path());
+    let output = deploy(project.path(), &image, "rp2350", &[]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(family_id(&image), 0xe48bff59);
+
+    let cli = project.path().join("cli");
+    fs::create_dir(&cli).unwrap();
+    let image = volume_image(&cli);
+    let output = deploy(project.path(), &image, "rp2350", &["--board", "rp2040"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(family_id(&image), 0xe48bff56);
+
+    let output = deploy(project.path(), &image, "not_a_board", &[]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Error: Invalid ELF2FLASH_BOARD: Unknown board 'not_a_board'"),
+        "{stderr}"
+    );
+}
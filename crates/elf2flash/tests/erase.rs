@@ -0,0 +1,84 @@
+//! `erase` writing the erase program the way a deploy writes firmware, onto a volume image.
+
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use elf2flash_core::uf2::{UF2_BLOCK_SIZE, Uf2Block};
+use fatfs::{FileSystem, FormatVolumeOptions, FsOptions};
+
+fn volume_image(dir: &Path) -> PathBuf {
+    let image = dir.join("volume.img");
+    let mut volume = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&image)
+        .unwrap();
+    volume.set_len(2 * 1024 * 1024).unwrap();
+    fatfs::format_volume(&mut volume, FormatVolumeOptions::new()).unwrap();
+    image
+}
+
+fn erase(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .arg("erase")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn written_uf2(image: &Path) -> Vec<u8> {
+    let fs = FileSystem::new(File::open(image).unwrap(), FsOptions::new()).unwrap();
+    let mut uf2 = Vec::new();
+    fs.root_dir()
+        .open_file("out.uf2")
+        .unwrap()
+        .read_to_end(&mut uf2)
+        .unwrap();
+    uf2
+}
+
+#[test]
+fn erase_program_is_written_once_confirmed() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume_image(dir.path());
+    let mock = [
+        "--board",
+        "rp2350",
+        "--unverified",
+        "--mock-volume",
+        image.to_str().unwrap(),
+    ];
+
+    // There is no terminal to ask on
+    let output = erase(&mock);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("pass --yes to erase"), "{stderr}");
+
+    let output = erase(&[&mock[..], &["--yes"]].concat());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{output:?}");
+    assert!(stdout.contains(": 1536 bytes in "), "{stdout}");
+
+    let uf2 = written_uf2(&image);
+    assert_eq!(uf2.len(), 3 * UF2_BLOCK_SIZE);
+    let first = Uf2Block::from_bytes(uf2[..UF2_BLOCK_SIZE].try_into().unwrap()).unwrap();
+    assert_eq!(first.family_id(), Some(0xe48bff59));
+    assert_eq!(first.target_addr(), 0x20000000);
+}
+
+#[test]
+fn other_boards_are_refused() {
+    let output = erase(&["--board", "esp32s3", "--yes"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Error: Erasing isn't supported for board 'esp32s3'"),
+        "{stderr}"
+    );
+}
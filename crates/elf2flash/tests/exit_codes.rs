@@ -0,0 +1,153 @@
+//! The exit codes scripts tell failures apart by.
+
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use fatfs::{FileSystem, FormatVolumeOptions, FsOptions};
+
+const FIXTURES: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../elf2flash-core/tests/rp2040"
+);
+
+fn elf2flash(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn usage_errors_exit_with_64() {
+    let output = elf2flash(&["convert", "--no-such-flag"]);
+    assert_eq!(output.status.code(), Some(64));
+
+    let output = elf2flash(&["--help"]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn conversion_errors_exit_with_4() {
+    let dir = tempfile::tempdir().unwrap();
+    let elf = dir.path().join("broken.elf");
+    fs::write(&elf, b"\x7fELF, but only the magic").unwrap();
+    let uf2 = dir.path().join("broken.uf2");
+
+    let output = elf2flash(&[
+        "convert",
+        elf.to_str().unwrap(),
+        uf2.to_str().unwrap(),
+        "--board",
+        "rp2040",
+    ]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(4), "{stderr}");
+    // A single line with the whole chain
+    assert_eq!(
+        stderr.lines().filter(|l| l.starts_with("Error: ")).count(),
+        1
+    );
+    assert!(
+        stderr.contains("Error: Failed to parse elf file: "),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn stale_uf2_exits_with_7() {
+    let output = elf2flash(&[
+        "verify",
+        &format!("{FIXTURES}/hello_usb.elf"),
+        &format!("{FIXTURES}/hello_serial.uf2"),
+    ]);
+    assert_eq!(output.status.code(), Some(7));
+}
+
+/// A volume image without room for a uf2 file, every cluster taken by another file
+fn full_volume_image(dir: &Path) -> PathBuf {
+    let image = dir.join("full.img");
+    let mut volume = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&image)
+        .unwrap();
+    volume.set_len(2 * 1024 * 1024).unwrap();
+    fatfs::format_volume(&mut volume, FormatVolumeOptions::new()).unwrap();
+
+    let fs = FileSystem::new(volume, FsOptions::new()).unwrap();
+    let mut file = fs.root_dir().create_file("FILL.BIN").unwrap();
+    while file.write_all(&[0; 4096]).is_ok() {}
+    image
+}
+
+#[test]
+fn failed_writes_exit_with_5() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = full_volume_image(dir.path());
+
+    let output = elf2flash(&[
+        "deploy",
+        &format!("{FIXTURES}/hello_usb.elf"),
+        "--board",
+        "rp2040",
+        "--mock-volume",
+        image.to_str().unwrap(),
+    ]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(5), "{stderr}");
+    assert!(
+        stderr.contains("Error: Skipped writing to board 'rp2040'"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn no_device_exits_with_2() {
+    // No bus has this number, so no device is found behind it
+    let output = elf2flash(&[
+        "--usb-root",
+        "255",
+        "deploy",
+        &format!("{FIXTURES}/hello_usb.elf"),
+        "--board",
+        "rp2040",
+    ]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // Without libusb, as in some sandboxes, no device can be looked for at all
+    if stderr.contains("Failed to initialize libusb") {
+        return;
+    }
+    assert_eq!(output.status.code(), Some(2), "{stderr}");
+    assert!(stderr.contains("Error: No uf2 devices found"), "{stderr}");
+
+    let output = elf2flash(&[
+        "--usb-root",
+        "255",
+        "deploy",
+        &format!("{FIXTURES}/hello_usb.elf"),
+        "--board",
+        "rp2040",
+        "--allow-no-device",
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn other_failures_exit_with_1() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing.elf");
+    let uf2 = dir.path().join("out.uf2");
+    let output = elf2flash(&[
+        "convert",
+        missing.to_str().unwrap(),
+        uf2.to_str().unwrap(),
+        "--board",
+        "rp2040",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+}
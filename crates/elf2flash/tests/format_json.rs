@@ -0,0 +1,134 @@
+//! `--format json`, with stdout holding nothing but the document.
+
+use std::{
+    fs::File,
+    path::Path,
+    process::{Command, Output},
+};
+
+use fatfs::FormatVolumeOptions;
+use serde_json::Value;
+
+const HELLO_USB: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../elf2flash-core/tests/rp2040/hello_usb.elf"
+);
+
+fn run(args: &[&str]) -> (Output, Value) {
+    let output = Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .args(["--format", "json"])
+        .args(args)
+        .output()
+        .unwrap();
+    let document = serde_json::from_slice(&output.stdout).unwrap_or_else(|err| {
+        panic!("{err}: {}", String::from_utf8_lossy(&output.stdout));
+    });
+    (output, document)
+}
+
+/// An empty FAT volume for `--mock-volume`.
+fn volume_image(dir: &Path) -> String {
+    let image = dir.join("volume.img");
+    let mut volume = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&image)
+        .unwrap();
+    volume.set_len(2 * 1024 * 1024).unwrap();
+    fatfs::format_volume(&mut volume, FormatVolumeOptions::new()).unwrap();
+    image.to_str().unwrap().to_owned()
+}
+
+#[test]
+fn convert_reports_what_it_wrote() {
+    let dir = tempfile::tempdir().unwrap();
+    let uf2 = dir.path().join("hello_usb.uf2");
+
+    let (output, document) = run(&[
+        "convert",
+        "--board",
+        "rp2040",
+        HELLO_USB,
+        uf2.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(document["version"], 1);
+    assert_eq!(document["command"], "convert");
+    assert_eq!(document["success"], true);
+    assert_eq!(document["result"]["family_id"], 0xe48bff56_u32);
+    assert_eq!(
+        document["result"]["bytes"],
+        std::fs::metadata(&uf2).unwrap().len()
+    );
+    // The logs went to stderr
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Wrote UF2"));
+}
+
+#[test]
+fn failures_are_reported_too() {
+    let (output, document) = run(&["convert", "--board", "rp2040", "missing.elf", "out.uf2"]);
+    assert!(!output.status.success());
+    assert_eq!(document["success"], false);
+    assert!(
+        document["error"]
+            .as_str()
+            .unwrap()
+            .contains("Input file missing.elf doesn't exist")
+    );
+    assert!(document.get("result").is_none());
+}
+
+#[test]
+fn deploy_reports_every_device() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume_image(dir.path());
+
+    let (output, document) = run(&[
+        "deploy",
+        HELLO_USB,
+        "--board",
+        "rp2040",
+        "--verify",
+        "--mock-volume",
+        &image,
+    ]);
+    assert!(output.status.success(), "{document}");
+    let results = document["result"].as_array().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["board"], "rp2040");
+    assert_eq!(results[0]["success"], true);
+    assert_eq!(results[0]["verification"]["mismatched"], 0);
+    assert!(results[0]["elapsed_ms"].is_u64());
+}
+
+#[test]
+fn deploy_json_flag_gives_way_to_the_document() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume_image(dir.path());
+    let args = [
+        "deploy",
+        HELLO_USB,
+        "--board",
+        "rp2040",
+        "--json",
+        "--mock-volume",
+        &image,
+    ];
+
+    // A single document, `run` fails on anything trailing it
+    let (output, document) = run(&args);
+    assert!(output.status.success(), "{document}");
+    assert_eq!(document["command"], "deploy");
+
+    // Without --format json, --json prints the deploy report after the summary
+    let output = Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let report: Value = serde_json::from_str(&stdout[stdout.find("\n{").unwrap()..]).unwrap();
+    assert_eq!(report["devices"][0]["board_name"], "rp2040");
+    assert!(report["warnings"].as_array().unwrap().is_empty());
+}
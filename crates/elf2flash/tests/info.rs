@@ -0,0 +1,84 @@
+//! `info` describing a bootloader volume, run against a volume image instead of a device.
+
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use fatfs::{FileSystem, FormatVolumeOptions, FsOptions};
+
+const INFO_UF2: &str = "UF2 Bootloader v3.0\nModel: Raspberry Pi RP2\nBoard-ID: RPI-RP2\n";
+
+const INDEX_HTM: &str = "<html><head><meta http-equiv=\"refresh\" \
+    content=\"0;URL='https://raspberrypi.com/device/RP2?version=E0C9125B0D9B'\"/></head></html>";
+
+/// A FAT16 volume with the files of the RP2040 bootloader.
+fn bootloader_volume(dir: &Path) -> PathBuf {
+    let image = dir.join("volume.img");
+    let mut volume = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&image)
+        .unwrap();
+    volume.set_len(8 * 1024 * 1024).unwrap();
+    fatfs::format_volume(
+        &mut volume,
+        FormatVolumeOptions::new().volume_label(*b"RPI-RP2    "),
+    )
+    .unwrap();
+
+    let fs = FileSystem::new(volume, FsOptions::new()).unwrap();
+    for (name, contents) in [("INFO_UF2.TXT", INFO_UF2), ("INDEX.HTM", INDEX_HTM)] {
+        let mut file = fs.root_dir().create_file(name).unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+    }
+    drop(fs);
+    image
+}
+
+fn info(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .arg("info")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn bootloader_details_are_shown_without_touching_the_volume() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = bootloader_volume(dir.path());
+    let before = fs::read(&image).unwrap();
+
+    let output = info(&["--mock-volume", image.to_str().unwrap()]);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for line in [
+        "    Volume:       RPI-RP2\n",
+        "    FAT type:     FAT16\n",
+        "    Bootloader:   v3.0\n",
+        "    Model:        Raspberry Pi RP2\n",
+        "    Board-ID:     RPI-RP2\n",
+        "    Index URL:    https://raspberrypi.com/device/RP2?version=E0C9125B0D9B\n",
+    ] {
+        assert!(stdout.contains(line), "{stdout}");
+    }
+
+    let output = info(&["--mock-volume", image.to_str().unwrap(), "--json"]);
+    assert!(output.status.success(), "{output:?}");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let volume = &json[0]["volumes"][0];
+    assert_eq!(volume["label"], "RPI-RP2");
+    assert_eq!(volume["board_id"], "RPI-RP2");
+    assert_eq!(volume["info_uf2"], INFO_UF2);
+    assert!(volume["free_bytes"].as_u64().unwrap() > 0);
+    assert!(volume["free_bytes"].as_u64() < volume["total_bytes"].as_u64());
+
+    assert!(
+        fs::read(&image).unwrap() == before,
+        "info changed the volume"
+    );
+}
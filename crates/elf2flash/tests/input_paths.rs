@@ -0,0 +1,63 @@
+//! Input paths that can't be read, refused with a message naming them.
+
+use std::process::{Command, Output};
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn missing_input_is_named() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing.elf");
+    let uf2 = dir.path().join("out.uf2");
+
+    for output in [
+        run(&[
+            "convert",
+            "--board",
+            "rp2040",
+            missing.to_str().unwrap(),
+            uf2.to_str().unwrap(),
+        ]),
+        run(&["deploy", "--board", "rp2040", missing.to_str().unwrap()]),
+    ] {
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains(&format!("Input file {} doesn't exist", missing.display())),
+            "{stderr}"
+        );
+    }
+    assert!(!uf2.exists());
+}
+
+#[test]
+fn directory_input_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let uf2 = dir.path().join("out.uf2");
+
+    for output in [
+        run(&[
+            "convert",
+            "--board",
+            "rp2040",
+            dir.path().to_str().unwrap(),
+            uf2.to_str().unwrap(),
+        ]),
+        run(&["deploy", "--board", "rp2040", dir.path().to_str().unwrap()]),
+    ] {
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains(&format!(
+                "Input {} is a directory, not a file",
+                dir.path().display()
+            )),
+            "{stderr}"
+        );
+    }
+}
@@ -0,0 +1,57 @@
+//! Merging uf2 files.
+
+use std::{fs, process::Command};
+
+const HELLO_USB_UF2: &[u8] = include_bytes!("../../elf2flash-core/tests/rp2040/hello_usb.uf2");
+const SETTINGS_SECTOR_UF2: &[u8] =
+    include_bytes!("../../elf2flash-core/tests/rp2040/settings_sector.uf2");
+
+#[test]
+fn failed_merge_keeps_the_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = dir.path().join("app.uf2");
+    let broken = dir.path().join("broken.uf2");
+    let output = dir.path().join("combined.uf2");
+    fs::write(&app, HELLO_USB_UF2).unwrap();
+    fs::write(&broken, &SETTINGS_SECTOR_UF2[..100]).unwrap();
+    fs::write(&output, b"previous build").unwrap();
+
+    let merge = Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .arg("merge")
+        .args([&app, &broken, &output])
+        .output()
+        .unwrap();
+    assert!(!merge.status.success());
+    assert!(String::from_utf8_lossy(&merge.stderr).contains("is not a valid uf2 file"));
+    assert_eq!(fs::read(&output).unwrap(), b"previous build");
+
+    let missing = dir.path().join("missing.uf2");
+    let merge = Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .arg("merge")
+        .args([&app, &broken, &missing])
+        .output()
+        .unwrap();
+    assert!(!merge.status.success());
+    assert!(!missing.exists());
+}
+
+#[test]
+fn merges_into_the_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = dir.path().join("app.uf2");
+    let settings = dir.path().join("settings.uf2");
+    let output = dir.path().join("combined.uf2");
+    fs::write(&app, HELLO_USB_UF2).unwrap();
+    fs::write(&settings, SETTINGS_SECTOR_UF2).unwrap();
+
+    let merge = Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .arg("merge")
+        .args([&app, &settings, &output])
+        .output()
+        .unwrap();
+    assert!(merge.status.success(), "{merge:?}");
+    assert_eq!(
+        fs::read(&output).unwrap().len(),
+        HELLO_USB_UF2.len() + SETTINGS_SECTOR_UF2.len()
+    );
+}
@@ -0,0 +1,73 @@
+//! A deploy without a terminal, as from a service, run against a volume image instead of a
+//! device.
+
+use std::{
+    fs::{self, File},
+    process::{Command, Stdio},
+};
+
+use fatfs::FormatVolumeOptions;
+
+const HELLO_USB: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../elf2flash-core/tests/rp2040/hello_usb.elf"
+);
+
+/// Deploy hello_usb to a fresh volume image with `flags`, and return what was printed on stdout.
+fn deploy_to_image(flags: &[&str]) -> String {
+    let dir = tempfile::tempdir().unwrap();
+    let image = dir.path().join("volume.img");
+    let mut volume = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&image)
+        .unwrap();
+    volume.set_len(2 * 1024 * 1024).unwrap();
+    fatfs::format_volume(&mut volume, FormatVolumeOptions::new()).unwrap();
+    drop(volume);
+
+    let stdin = dir.path().join("stdin");
+    let stdout = dir.path().join("stdout");
+    fs::write(&stdin, "").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .args(["deploy", HELLO_USB, "--board", "rp2040", "--mock-volume"])
+        .arg(&image)
+        .args(flags)
+        .stdin(File::open(&stdin).unwrap())
+        .stdout(File::create(&stdout).unwrap())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+
+    let output = fs::read_to_string(&stdout).unwrap();
+    assert!(status.success(), "{output}");
+    output
+}
+
+#[test]
+fn deploy_logs_plain_progress_lines() {
+    let output = deploy_to_image(&[]);
+
+    // No bar redrawn with carriage returns or escape codes, only whole lines
+    assert!(!output.contains(['\r', '\x1b']), "{output:?}");
+    assert!(output.contains("Writing to rp2040: 50% ("), "{output}");
+    assert!(output.contains("Writing to rp2040: done, "), "{output}");
+}
+
+#[test]
+fn quiet_and_no_progress_leave_out_the_progress() {
+    let output = deploy_to_image(&["--no-progress"]);
+    assert!(
+        output.contains("Writing firmware to board 'rp2040'"),
+        "{output}"
+    );
+    assert!(!output.contains("Writing to rp2040:"), "{output}");
+
+    // Nothing went wrong, only the summary is printed
+    let output = deploy_to_image(&["--quiet"]);
+    assert_eq!(output.lines().count(), 1, "{output}");
+    assert!(output.starts_with("rp2040 ("), "{output}");
+    assert!(output.contains(" bytes in "), "{output}");
+}
@@ -0,0 +1,116 @@
+//! Reading the firmware back from a bootloader volume, run against a volume image that serves a
+//! synthetic CURRENT.UF2 instead of a device.
+
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    process::{Command, Output},
+};
+
+use fatfs::{FileSystem, FormatVolumeOptions, FsOptions};
+
+const HELLO_USB_UF2: &[u8] = include_bytes!("../../elf2flash-core/tests/rp2040/hello_usb.uf2");
+
+/// Create a volume image holding `files`, like a bootloader exposes
+fn volume(dir: &Path, files: &[(&str, &[u8])]) -> std::path::PathBuf {
+    let image = dir.join("volume.img");
+    let mut volume = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&image)
+        .unwrap();
+    volume.set_len(2 * 1024 * 1024).unwrap();
+    fatfs::format_volume(&mut volume, FormatVolumeOptions::new()).unwrap();
+
+    let fatfs = FileSystem::new(&mut volume, FsOptions::new()).unwrap();
+    for (name, data) in files {
+        fatfs
+            .root_dir()
+            .create_file(name)
+            .unwrap()
+            .write_all(data)
+            .unwrap();
+    }
+    drop(fatfs);
+
+    image
+}
+
+fn read(image: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .arg("read")
+        .arg("--mock-volume")
+        .arg(image)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn reads_current_uf2_and_bin() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume(
+        dir.path(),
+        &[
+            ("INFO_UF2.TXT", b"UF2 Bootloader v3.0\n"),
+            ("CURRENT.UF2", HELLO_USB_UF2),
+        ],
+    );
+    let output = dir.path().join("current.uf2");
+    let bin = dir.path().join("current.bin");
+
+    let result = read(
+        &image,
+        &[
+            "--output",
+            output.to_str().unwrap(),
+            "--bin",
+            bin.to_str().unwrap(),
+        ],
+    );
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(result.status.success(), "{stdout}");
+    assert!(!stdout.contains("warning"), "{stdout}");
+
+    assert_eq!(fs::read(&output).unwrap(), HELLO_USB_UF2);
+    // Every block of hello_usb.uf2 carries 256 bytes, back to back from the start of flash
+    let bin = fs::read(&bin).unwrap();
+    assert_eq!(bin.len(), HELLO_USB_UF2.len() / 2);
+    assert_eq!(bin[..256], HELLO_USB_UF2[32..32 + 256]);
+}
+
+#[test]
+fn inconsistent_current_uf2_is_saved_with_a_warning() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume(dir.path(), &[("CURRENT.UF2", &HELLO_USB_UF2[..4 * 512])]);
+    let output = dir.path().join("current.uf2");
+
+    let result = read(&image, &["--output", output.to_str().unwrap()]);
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(result.status.success(), "{stdout}");
+    assert!(stdout.contains("[readback-inconsistent]"), "{stdout}");
+    assert_eq!(fs::read(&output).unwrap(), HELLO_USB_UF2[..4 * 512]);
+}
+
+#[test]
+fn bootloader_without_current_uf2_fails_cleanly() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume(
+        dir.path(),
+        &[("INFO_UF2.TXT", b"info"), ("INDEX.HTM", b"<html></html>")],
+    );
+    let output = dir.path().join("current.uf2");
+
+    let result = read(&image, &["--output", output.to_str().unwrap()]);
+    assert!(!result.status.success());
+    assert!(
+        String::from_utf8_lossy(&result.stderr).contains(
+            "the bootloader doesn't export the firmware as CURRENT.UF2, the volume only has: INFO_UF2.TXT, INDEX.HTM"
+        ),
+        "{}",
+        String::from_utf8_lossy(&result.stderr)
+    );
+    assert!(!output.exists());
+}
@@ -0,0 +1,118 @@
+//! Restoring a backup, run against a volume image instead of a device.
+
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use fatfs::{FileSystem, FormatVolumeOptions, FsOptions};
+
+const HELLO_USB_UF2: &[u8] = include_bytes!("../../elf2flash-core/tests/rp2040/hello_usb.uf2");
+
+/// An empty volume image, like a bootloader exposes
+fn volume(dir: &Path) -> PathBuf {
+    let image = dir.join("volume.img");
+    let mut volume = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&image)
+        .unwrap();
+    volume.set_len(2 * 1024 * 1024).unwrap();
+    fatfs::format_volume(&mut volume, FormatVolumeOptions::new()).unwrap();
+
+    image
+}
+
+/// A backup of an RP2040 whose `CURRENT.UF2` is `current`, like `deploy --backup` leaves
+fn backup(dir: &Path, current: &[u8]) -> PathBuf {
+    let backup = dir.join("20250101-120000-rp2040");
+    fs::create_dir(&backup).unwrap();
+    fs::write(backup.join("CURRENT.UF2"), current).unwrap();
+    fs::write(
+        backup.join("manifest.txt"),
+        format!(
+            "# board: rp2040\n# family: 0xe48bff56\n{:08x} {} CURRENT.UF2\n",
+            crc32fast::hash(current),
+            current.len()
+        ),
+    )
+    .unwrap();
+
+    backup
+}
+
+fn rollback(backup: &Path, image: &Path, board: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .arg("rollback")
+        .arg("--from")
+        .arg(backup)
+        .arg("--mock-volume")
+        .arg(image)
+        .args(["--board", board])
+        .output()
+        .unwrap()
+}
+
+fn root_files(image: &Path) -> Vec<String> {
+    let fatfs = FileSystem::new(File::open(image).unwrap(), FsOptions::new()).unwrap();
+    fatfs
+        .root_dir()
+        .iter()
+        .map(|entry| entry.unwrap().file_name())
+        .collect()
+}
+
+#[test]
+fn restores_the_backed_up_firmware() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume(dir.path());
+    backup(dir.path(), HELLO_USB_UF2);
+
+    // The newest backup in the directory is found
+    let output = rollback(dir.path(), &image, "rp2040");
+    assert!(output.status.success(), "{output:?}");
+
+    let fatfs = FileSystem::new(File::open(&image).unwrap(), FsOptions::new()).unwrap();
+    let mut restored = Vec::new();
+    fatfs
+        .root_dir()
+        .open_file("out.uf2")
+        .unwrap()
+        .read_to_end(&mut restored)
+        .unwrap();
+    assert_eq!(restored, HELLO_USB_UF2);
+}
+
+#[test]
+fn refuses_a_board_of_another_family() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume(dir.path());
+    let backup = backup(dir.path(), HELLO_USB_UF2);
+
+    let output = rollback(&backup, &image, "rp2350");
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("family id"),
+        "{output:?}"
+    );
+    assert!(root_files(&image).is_empty());
+}
+
+#[test]
+fn refuses_a_corrupt_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume(dir.path());
+    let backup = backup(dir.path(), HELLO_USB_UF2);
+    fs::write(backup.join("CURRENT.UF2"), &HELLO_USB_UF2[..1024]).unwrap();
+
+    let output = rollback(&backup, &image, "rp2040");
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("is corrupt"),
+        "{output:?}"
+    );
+    assert!(root_files(&image).is_empty());
+}
@@ -0,0 +1,134 @@
+//! `deploy --save-uf2` keeping a copy of the flashed uf2 file, deploying onto a volume image.
+
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use fatfs::{FileSystem, FormatVolumeOptions, FsOptions};
+
+const HELLO_USB: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../elf2flash-core/tests/rp2040/hello_usb.elf"
+);
+
+fn volume_image(dir: &Path) -> PathBuf {
+    let image = dir.join("volume.img");
+    let mut volume = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&image)
+        .unwrap();
+    volume.set_len(2 * 1024 * 1024).unwrap();
+    fatfs::format_volume(&mut volume, FormatVolumeOptions::new()).unwrap();
+    image
+}
+
+/// Run elf2flash in `dir`, away from any config file.
+fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .current_dir(dir)
+        .env("XDG_CONFIG_HOME", dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn written_uf2(image: &Path) -> Vec<u8> {
+    let fs = FileSystem::new(File::open(image).unwrap(), FsOptions::new()).unwrap();
+    let mut uf2 = Vec::new();
+    fs.root_dir()
+        .open_file("out.uf2")
+        .unwrap()
+        .read_to_end(&mut uf2)
+        .unwrap();
+    uf2
+}
+
+#[test]
+fn saved_uf2_matches_the_flashed_and_converted_ones() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume_image(dir.path());
+    let deploy = [
+        "deploy",
+        HELLO_USB,
+        "--board",
+        "rp2040",
+        "--mock-volume",
+        image.to_str().unwrap(),
+        "--save-uf2",
+        "build/uf2/saved.uf2",
+    ];
+
+    let output = run(dir.path(), &deploy);
+    assert!(output.status.success(), "{output:?}");
+    let saved = fs::read(dir.path().join("build/uf2/saved.uf2")).unwrap();
+    assert!(saved == written_uf2(&image), "the saved uf2 wasn't flashed");
+
+    let output = run(
+        dir.path(),
+        &["convert", "--board", "rp2040", HELLO_USB, "converted.uf2"],
+    );
+    assert!(output.status.success(), "{output:?}");
+    let converted = fs::read(dir.path().join("converted.uf2")).unwrap();
+    assert!(saved == converted, "the saved uf2 differs from convert's");
+
+    // An existing file is refused before anything is flashed
+    fs::write(dir.path().join("build/uf2/saved.uf2"), b"kept").unwrap();
+    let output = run(dir.path(), &deploy);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("pass --overwrite to replace it"),
+        "{stderr}"
+    );
+    assert_eq!(
+        fs::read(dir.path().join("build/uf2/saved.uf2")).unwrap(),
+        b"kept"
+    );
+
+    let output = run(dir.path(), &[&deploy[..], &["--overwrite"]].concat());
+    assert!(output.status.success(), "{output:?}");
+    assert!(fs::read(dir.path().join("build/uf2/saved.uf2")).unwrap() == converted);
+}
+
+#[test]
+fn dry_run_saves_the_uf2_without_writing() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume_image(dir.path());
+    let output = run(
+        dir.path(),
+        &[
+            "deploy",
+            HELLO_USB,
+            "--board",
+            "rp2040",
+            "--mock-volume",
+            image.to_str().unwrap(),
+            "--save-uf2",
+            "saved.uf2",
+            "--dry-run",
+        ],
+    );
+    assert!(output.status.success(), "{output:?}");
+    let fs = FileSystem::new(File::open(&image).unwrap(), FsOptions::new()).unwrap();
+    assert_eq!(
+        fs.root_dir().iter().count(),
+        0,
+        "the dry run wrote the volume"
+    );
+
+    let output = run(
+        dir.path(),
+        &["convert", "--board", "rp2040", HELLO_USB, "converted.uf2"],
+    );
+    assert!(output.status.success(), "{output:?}");
+    assert!(
+        fs::read(dir.path().join("saved.uf2")).unwrap()
+            == fs::read(dir.path().join("converted.uf2")).unwrap(),
+        "the saved uf2 differs from convert's"
+    );
+}
@@ -0,0 +1,98 @@
+//! `--flash-sector-erase-size` as given on the command line, checked before converting.
+
+use std::{
+    fs::{self, File},
+    path::Path,
+    process::{Command, Output},
+};
+
+use fatfs::FormatVolumeOptions;
+
+const FIXTURES: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../elf2flash-core/tests/rp2040"
+);
+
+/// Run elf2flash in `dir`, away from any config file.
+fn run(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .current_dir(dir)
+        .env("XDG_CONFIG_HOME", dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn convert(dir: &Path, erase_size: &str) -> Output {
+    run(
+        dir,
+        &[
+            "convert",
+            &format!("{FIXTURES}/hello_usb.elf"),
+            "out.uf2",
+            "--board",
+            "rp2040",
+            "-e",
+            erase_size,
+        ],
+    )
+}
+
+#[test]
+fn erase_size_is_taken_in_decimal_and_hex() {
+    let dir = tempfile::tempdir().unwrap();
+
+    // The size the RP2040 erases anyway
+    let output = convert(dir.path(), "4096");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        fs::read(dir.path().join("out.uf2")).unwrap(),
+        fs::read(format!("{FIXTURES}/hello_usb.uf2")).unwrap()
+    );
+
+    let output = convert(dir.path(), "0x10000");
+    assert!(output.status.success(), "{output:?}");
+}
+
+#[test]
+fn erase_size_that_isnt_whole_pages_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let output = convert(dir.path(), "1000");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr
+            .contains("flash_sector_erase_size 1000 must be a non-zero multiple of the page size"),
+        "{stderr}"
+    );
+    assert!(!dir.path().join("out.uf2").exists());
+
+    let output = convert(dir.path(), "lots");
+    assert_eq!(output.status.code(), Some(64));
+}
+
+#[test]
+fn deploy_takes_the_erase_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = dir.path().join("volume.img");
+    let mut volume = File::create_new(&image).unwrap();
+    volume.set_len(2 * 1024 * 1024).unwrap();
+    fatfs::format_volume(&mut volume, FormatVolumeOptions::new()).unwrap();
+    drop(volume);
+
+    let output = run(
+        dir.path(),
+        &[
+            "deploy",
+            &format!("{FIXTURES}/hello_usb.elf"),
+            "--board",
+            "rp2040",
+            "-e",
+            "4096",
+            "--mock-volume",
+            image.to_str().unwrap(),
+        ],
+    );
+    assert!(output.status.success(), "{output:?}");
+}
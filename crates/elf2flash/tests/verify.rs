@@ -0,0 +1,37 @@
+//! Checking uf2 files against the ELF they should have been converted from.
+
+use std::process::{Command, Output};
+
+const FIXTURES: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../elf2flash-core/tests/rp2040"
+);
+
+fn verify(elf: &str, uf2: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .arg("verify")
+        .arg(format!("{FIXTURES}/{elf}"))
+        .arg(format!("{FIXTURES}/{uf2}"))
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn matching_uf2_passes() {
+    let output = verify("hello_usb.elf", "hello_usb.uf2");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("All 89 blocks match the ELF"), "{stdout}");
+}
+
+#[test]
+fn stale_uf2_fails_with_the_first_addresses() {
+    let output = verify("hello_usb.elf", "hello_serial.uf2");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success(), "{stdout}");
+    assert!(
+        stdout.contains("blocks differ from the ELF: 0x10000100"),
+        "{stdout}"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("it may be stale"));
+}
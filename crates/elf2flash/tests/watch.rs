@@ -0,0 +1,82 @@
+//! `deploy --watch` deploying a rebuilt ELF again, run against a volume image instead of a device.
+#![cfg(unix)]
+
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, Read},
+    process::{ChildStdout, Command, Stdio},
+};
+
+use fatfs::FormatVolumeOptions;
+
+const FIXTURES: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../elf2flash-core/tests/rp2040"
+);
+
+/// Read lines until one starting with `prefix`, returning all of them.
+fn read_until(stdout: &mut BufReader<ChildStdout>, prefix: &str) -> String {
+    let mut output = String::new();
+    loop {
+        let mut line = String::new();
+        let read = stdout.read_line(&mut line).unwrap();
+        output.push_str(&line);
+        assert_ne!(read, 0, "deploy ended before {prefix:?}:\n{output}");
+        if line.starts_with(prefix) {
+            return output;
+        }
+    }
+}
+
+#[test]
+fn rebuilt_elf_is_deployed_again_until_ctrl_c() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = dir.path().join("volume.img");
+    let mut volume = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&image)
+        .unwrap();
+    volume.set_len(2 * 1024 * 1024).unwrap();
+    fatfs::format_volume(&mut volume, FormatVolumeOptions::new()).unwrap();
+    drop(volume);
+
+    let elf = dir.path().join("firmware.elf");
+    fs::copy(format!("{FIXTURES}/hello_usb.elf"), &elf).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .arg("deploy")
+        .arg(&elf)
+        .args(["--board", "rp2040", "--watch", "--mock-volume"])
+        .arg(&image)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+
+    let first = read_until(&mut stdout, "Watching");
+    assert!(first.contains(": 45568 bytes in "), "{first}");
+
+    // A broken build is reported, and the next one deployed
+    fs::write(&elf, b"not an ELF").unwrap();
+    let broken = read_until(&mut stdout, "Watching");
+    assert!(broken.contains("ERROR: The input isn't an ELF"), "{broken}");
+
+    fs::copy(format!("{FIXTURES}/hello_serial.elf"), &elf).unwrap();
+    let second = read_until(&mut stdout, "Watching");
+    assert!(second.contains("changed, deploying again"), "{second}");
+    assert!(second.contains(": 26624 bytes in "), "{second}");
+
+    let killed = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+
+    let mut rest = String::new();
+    stdout.read_to_string(&mut rest).unwrap();
+    let status = child.wait().unwrap();
+    assert!(status.success(), "{rest}");
+    assert!(rest.starts_with("Stopped watching"), "{rest}");
+}
@@ -0,0 +1,96 @@
+//! Writing a few flash pages, run against a volume image instead of a device.
+
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::Path,
+    process::{Command, Output},
+};
+
+use fatfs::{FileSystem, FormatVolumeOptions, FsOptions};
+
+const SETTINGS_SECTOR_UF2: &[u8] =
+    include_bytes!("../../elf2flash-core/tests/rp2040/settings_sector.uf2");
+
+/// An empty volume image, like a bootloader exposes
+fn volume(dir: &Path) -> std::path::PathBuf {
+    let image = dir.join("volume.img");
+    let mut volume = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&image)
+        .unwrap();
+    volume.set_len(2 * 1024 * 1024).unwrap();
+    fatfs::format_volume(&mut volume, FormatVolumeOptions::new()).unwrap();
+
+    image
+}
+
+fn write_page(image: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_elf2flash"))
+        .arg("write-page")
+        .arg("--mock-volume")
+        .arg(image)
+        .args(["--board", "rp2040"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn written_uf2(image: &Path) -> Vec<u8> {
+    let fatfs = FileSystem::new(File::open(image).unwrap(), FsOptions::new()).unwrap();
+    let mut uf2 = Vec::new();
+    fatfs
+        .root_dir()
+        .open_file("out.uf2")
+        .unwrap()
+        .read_to_end(&mut uf2)
+        .unwrap();
+    uf2
+}
+
+#[test]
+fn writes_settings_sector() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume(dir.path());
+    let settings = dir.path().join("settings.bin");
+    let data: Vec<u8> = b"elf2flash settings v1\0"
+        .iter()
+        .copied()
+        .cycle()
+        .take(512)
+        .collect();
+    fs::write(&settings, data).unwrap();
+
+    let result = write_page(
+        &image,
+        &[
+            "--address",
+            "0x101FF800",
+            "--data",
+            &format!("@{}", settings.display()),
+            "--pad-byte",
+            "0",
+        ],
+    );
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(result.status.success(), "{stdout}");
+
+    assert_eq!(written_uf2(&image), SETTINGS_SECTOR_UF2);
+}
+
+#[test]
+fn refuses_addresses_outside_flash() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = volume(dir.path());
+
+    for address in ["0x20000000", "0x30000000"] {
+        let result = write_page(&image, &["--address", address, "--data", "0x0102"]);
+        assert!(!result.status.success());
+    }
+
+    // Nothing was written
+    let fatfs = FileSystem::new(File::open(&image).unwrap(), FsOptions::new()).unwrap();
+    assert_eq!(fatfs.root_dir().iter().count(), 0);
+}
@@ -1,12 +1,21 @@
 #![doc = include_str!("../README.md")]
 
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::{
+    fmt,
+    io::{Read, Seek, SeekFrom, Write},
+    time::Duration,
+};
 
-use fatfs::FatType;
-use rusb::{Device, GlobalContext};
+use fatfs::{FatType, FileSystem, ReadWriteSeek};
+use rusb::{Device, GlobalContext, UsbContext};
 use thiserror::Error;
-use usbh_scsi::storage::{
-    Closed, Opened, UsbMassStorage, UsbMassStorageError, UsbMassStorageReadWriteError,
+use usbh_scsi::{
+    select::ListOptions,
+    session::UsbSession,
+    storage::{
+        Closed, DEFAULT_TIMEOUT, Opened, UsbMassStorage, UsbMassStorageError,
+        UsbMassStorageReadWriteError,
+    },
 };
 
 /// Re-export of the `bootsector` crate for partition parsing.
@@ -15,15 +24,20 @@ pub use bootsector;
 pub use fatfs;
 /// Re-export of the `rusb` crate for raw USB device handling.
 pub use rusb;
+/// Re-export of the `usbh-scsi` crate for the underlying block device.
+pub use usbh_scsi;
 
 /// Represents a USB mass-storage device connected to the system.
 ///
 /// Holds both the USB device handle (`rusb::Device`) and
 /// the current state of its storage interface (`StorageUsbInner`).
-#[derive(Debug)]
-pub struct StorageUsb {
-    pub inner: StorageUsbInner,
-    pub usb_device: Device<GlobalContext>,
+/// Devices listed through a [`UsbSession`] belong to its context instead of `rusb`'s
+/// [`GlobalContext`].
+pub struct StorageUsb<T: UsbContext = GlobalContext> {
+    pub inner: StorageUsbInner<T>,
+    pub usb_device: Device<T>,
+    /// How long a transfer may take once the device is opened
+    timeout: Duration,
 }
 
 /// Represents the state of a `StorageUsb` device.
@@ -31,13 +45,33 @@ pub struct StorageUsb {
 /// - `Closed`: The device is detected but not yet opened for I/O.
 /// - `Opened`: The device is ready for block-level access.
 /// - `ClosedDummy`: Temporary placeholder state during transitions.
-#[derive(Debug)]
-pub enum StorageUsbInner {
-    Closed(UsbMassStorage<Closed>),
-    Opened(UsbMassStorage<Opened>),
+pub enum StorageUsbInner<T: UsbContext = GlobalContext> {
+    Closed(UsbMassStorage<Closed, T>),
+    Opened(UsbMassStorage<Opened<T>, T>),
     ClosedDummy,
 }
 
+// Not derived, that would only cover contexts that are `Debug` themselves
+impl<T: UsbContext> fmt::Debug for StorageUsb<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StorageUsb")
+            .field("inner", &self.inner)
+            .field("usb_device", &self.usb_device)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl<T: UsbContext> fmt::Debug for StorageUsbInner<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Closed(usb) => f.debug_tuple("Closed").field(usb).finish(),
+            Self::Opened(usb) => f.debug_tuple("Opened").field(usb).finish(),
+            Self::ClosedDummy => f.write_str("ClosedDummy"),
+        }
+    }
+}
+
 /// Errors that can occur when working with [`StorageUsb`] or partitions.
 #[derive(Error, Debug)]
 pub enum StorageUsbError {
@@ -47,11 +81,15 @@ pub enum StorageUsbError {
 
     /// Failed to open a block device interface.
     #[error("failed to open as block device")]
-    BlockDeviceOpenFail,
+    BlockDeviceOpenFail(#[source] std::io::Error),
 
     /// Partition listing failed (invalid or unreadable partition table).
     #[error("listing partitions failed")]
-    ListingPartitionFail,
+    ListingPartitionFail(#[source] bootsector::Error),
+
+    /// A listed partition couldn't be accessed.
+    #[error("failed to access partition")]
+    PartitionViewFail(#[from] FatError),
 }
 
 impl StorageUsb {
@@ -59,19 +97,61 @@ impl StorageUsb {
     ///
     /// Returns a vector of `StorageUsb` instances, all starting in the `Closed` state.
     pub fn list_usbs() -> Result<Vec<Self>, StorageUsbError> {
-        let usbs: Vec<_> = UsbMassStorage::list()?
+        Ok(UsbMassStorage::list()?.into_iter().map(Self::new).collect())
+    }
+
+    /// List the connected USB mass-storage devices that `options` includes, see
+    /// [`StorageUsb::list_usbs`].
+    pub fn list_usbs_with(options: &ListOptions) -> Result<Vec<Self>, StorageUsbError> {
+        Ok(UsbMassStorage::list_with(options)?
             .into_iter()
-            .map(|usb| {
-                let device = usb.device.clone();
+            .map(Self::new)
+            .collect())
+    }
+}
 
-                Self {
-                    inner: StorageUsbInner::Closed(usb),
-                    usb_device: device,
-                }
-            })
-            .collect();
+impl StorageUsb<rusb::Context> {
+    /// List the connected USB mass-storage devices of `session`, see [`StorageUsb::list_usbs`].
+    pub fn list_usbs_in(session: &UsbSession) -> Result<Vec<Self>, StorageUsbError> {
+        Ok(session
+            .list_mass_storage()?
+            .into_iter()
+            .map(Self::new)
+            .collect())
+    }
 
-        Ok(usbs)
+    /// List the connected USB mass-storage devices of `session` that `options` includes, see
+    /// [`StorageUsb::list_usbs`].
+    pub fn list_usbs_in_with(
+        session: &UsbSession,
+        options: &ListOptions,
+    ) -> Result<Vec<Self>, StorageUsbError> {
+        Ok(session
+            .list_mass_storage_with(options)?
+            .into_iter()
+            .map(Self::new)
+            .collect())
+    }
+}
+
+impl<T: UsbContext> StorageUsb<T> {
+    fn new(usb: UsbMassStorage<Closed, T>) -> Self {
+        let device = usb.device.clone();
+
+        Self {
+            inner: StorageUsbInner::Closed(usb),
+            usb_device: device,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Change how long a transfer may take before it fails, [`DEFAULT_TIMEOUT`] unless set. Applies
+    /// to the device right away if it is open, and whenever it is opened again.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+        if let StorageUsbInner::Opened(opened) = &mut self.inner {
+            opened.set_timeout(timeout);
+        }
     }
 
     /// Open the USB mass-storage device for I/O.
@@ -79,11 +159,13 @@ impl StorageUsb {
     /// If the device is already open, it will simply return the existing `Opened` instance.
     ///
     /// Returns a mutable reference to the `UsbMassStorage<Opened>` object for performing block I/O.
-    pub fn open(&mut self) -> Result<&mut UsbMassStorage<Opened>, StorageUsbError> {
+    pub fn open(&mut self) -> Result<&mut UsbMassStorage<Opened<T>, T>, StorageUsbError> {
         // Take ownership safely by swapping with None
         let inner = std::mem::replace(&mut self.inner, StorageUsbInner::ClosedDummy);
         self.inner = match inner {
-            StorageUsbInner::Closed(closed) => StorageUsbInner::Opened(closed.open()?),
+            StorageUsbInner::Closed(closed) => {
+                StorageUsbInner::Opened(closed.open_with_timeout(self.timeout)?)
+            }
             opened @ StorageUsbInner::Opened(_) => opened,
             _ => unreachable!(),
         };
@@ -93,6 +175,18 @@ impl StorageUsb {
             _ => unreachable!(),
         }
     }
+
+    /// Release the claimed interface without resetting the device, see
+    /// [`UsbMassStorage::release`].
+    ///
+    /// Does nothing if the device isn't open.
+    pub fn release(&mut self) {
+        let inner = std::mem::replace(&mut self.inner, StorageUsbInner::ClosedDummy);
+        self.inner = match inner {
+            StorageUsbInner::Opened(opened) => StorageUsbInner::Closed(opened.release()),
+            inner => inner,
+        };
+    }
 }
 
 /// Represents a FAT partition discovered on a USB mass-storage device.
@@ -123,23 +217,25 @@ impl FatPartition {
     /// 3. Mount each partition as a FAT filesystem.
     ///
     /// Returns only valid FAT partitions (others are skipped).
-    pub fn list_partitions(usb: &mut StorageUsb) -> Result<Vec<Self>, StorageUsbError> {
+    pub fn list_partitions<T: UsbContext>(
+        usb: &mut StorageUsb<T>,
+    ) -> Result<Vec<Self>, StorageUsbError> {
         let opened = usb.open()?;
 
         let mut block_device = opened
             .block_device()
-            .map_err(|_| StorageUsbError::BlockDeviceOpenFail)?;
+            .map_err(StorageUsbError::BlockDeviceOpenFail)?;
 
         let partitions =
             bootsector::list_partitions(&block_device, &bootsector::Options::default())
-                .map_err(|_| StorageUsbError::ListingPartitionFail)?;
+                .map_err(StorageUsbError::ListingPartitionFail)?;
 
         let mut results = Vec::new();
 
         for partition in partitions {
             let first_byte = partition.first_byte;
             let length = partition.len;
-            let view = PartitionView::new(&mut block_device, first_byte, length).unwrap();
+            let view = PartitionView::new(&mut block_device, first_byte, length)?;
 
             let fs = match fatfs::FileSystem::new(view, fatfs::FsOptions::new()) {
                 Ok(fs) => fs,
@@ -164,6 +260,73 @@ impl FatPartition {
     }
 }
 
+/// Metadata of a single directory entry, detached from the filesystem borrow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntryInfo {
+    /// Long file name if present, otherwise the short (8.3) name.
+    pub name: String,
+    /// File size in bytes (`0` for directories).
+    pub len: u64,
+    /// Whether the entry is a directory.
+    pub is_dir: bool,
+}
+
+/// List the entries of the directory at `path` on a mounted FAT filesystem.
+///
+/// Pass `""` to list the root directory. The `.` and `..` entries are skipped.
+pub fn list_dir<T: ReadWriteSeek>(
+    fs: &FileSystem<T>,
+    path: &str,
+) -> Result<Vec<DirEntryInfo>, FatError> {
+    let root = fs.root_dir();
+    let dir = if path.is_empty() || path == "/" {
+        root
+    } else {
+        root.open_dir(path)?
+    };
+
+    let mut entries = Vec::new();
+    for entry in dir.iter() {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == "." || name == ".." {
+            continue;
+        }
+
+        entries.push(DirEntryInfo {
+            name,
+            len: entry.len(),
+            is_dir: entry.is_dir(),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Read the whole file at `path` on a mounted FAT filesystem into memory.
+///
+/// If `max_len` is given, files larger than it are rejected with
+/// [`FatError::FileTooLarge`] before any data is read.
+pub fn read_file<T: ReadWriteSeek>(
+    fs: &FileSystem<T>,
+    path: &str,
+    max_len: Option<u64>,
+) -> Result<Vec<u8>, FatError> {
+    let mut file = fs.root_dir().open_file(path)?;
+
+    let len = file.seek(SeekFrom::End(0))?;
+    if let Some(max_len) = max_len
+        && len > max_len
+    {
+        return Err(FatError::FileTooLarge { len, max_len });
+    }
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut buf = Vec::with_capacity(len as usize);
+    file.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
 /// Provides a "window" into a block device, restricted to a single partition.
 ///
 /// Wraps a seekable/readable/writable device and clamps all operations
@@ -276,6 +439,41 @@ impl<D: Seek> Seek for PartitionView<D> {
     }
 }
 
+/// Mounts a device read-only: reads and seeks pass through, every write fails.
+///
+/// Wrap a [`PartitionView`] in it to inspect a volume without any chance of changing it, e.g.
+/// `FileSystem::new(ReadOnly(view), FsOptions::new())`. `fatfs` doesn't write on mount, only
+/// when files are changed or when it updates the FSInfo sector of a FAT32 volume on unmount.
+#[derive(Debug)]
+pub struct ReadOnly<D>(pub D);
+
+impl<D: Read> Read for ReadOnly<D> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<D> Write for ReadOnly<D> {
+    /// Always fails with [`std::io::ErrorKind::ReadOnlyFilesystem`].
+    fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::ReadOnlyFilesystem,
+            "the volume is mounted read-only",
+        ))
+    }
+
+    /// Nothing is ever written, so there is nothing to flush.
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<D: Seek> Seek for ReadOnly<D> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
 /// Errors that can occur when reading/writing FAT partitions.
 #[derive(Error, Debug)]
 pub enum FatError {
@@ -292,10 +490,38 @@ pub enum FatError {
     WriteZero,
 
     /// USB-level I/O error during read/write.
-    #[error("usb read/write failed: {0}")]
+    #[error("usb read/write failed")]
     UsbIo(#[from] UsbMassStorageReadWriteError),
 
-    /// Generic I/O error from the standard library.
-    #[error("io error: {0}")]
+    /// Generic I/O error from the standard library, for a USB device usually wrapping a
+    /// [`CommandFailed`](usbh_scsi::storage::block_device::CommandFailed).
+    #[error("io error")]
     StdIo(#[from] std::io::Error),
+
+    /// The file is larger than the caller allowed.
+    #[error("file is {len} bytes, larger than the {max_len} byte limit")]
+    FileTooLarge { len: u64, max_len: u64 },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a USB mass storage device plugged in"]
+    fn set_timeout_applies_before_and_after_opening() {
+        let mut usb = StorageUsb::list_usbs()
+            .unwrap()
+            .into_iter()
+            .next()
+            .expect("Plug in a USB mass storage device, like a board in its bootloader");
+
+        usb.set_timeout(Duration::from_secs(3));
+        assert_eq!(usb.open().unwrap().timeout(), Duration::from_secs(3));
+
+        usb.set_timeout(Duration::from_secs(5));
+        assert_eq!(usb.open().unwrap().timeout(), Duration::from_secs(5));
+
+        usb.release();
+    }
 }
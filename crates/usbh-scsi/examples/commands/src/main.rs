@@ -1,64 +1,218 @@
-use std::error::Error;
-
-use usbh_scsi::commands::inquiry::InquiryData;
-use usbh_scsi::commands::read_capacity::{ReadCapacity10Command, ReadCapacity10Data};
-use usbh_scsi::commands::{cbw::Direction, inquiry::InquiryCommand, read10::Read10Command};
-use usbh_scsi::storage::UsbMassStorage;
-
-fn main() -> Result<(), Box<dyn Error>> {
-    // List all available USB Mass Storage devices.
-    let mut devices = UsbMassStorage::list()?;
-    let Some(closed) = devices.pop() else {
-        eprintln!("No USB mass storage devices found.");
-        return Ok(());
+//! Walks through the SCSI commands of usbh-scsi against a USB flash drive.
+//!
+//! Without arguments every mass storage device is listed, nothing else is done:
+//!
+//! ```text
+//! cargo run -p commands
+//! cargo run -p commands -- --device serial:E6614C311B2F
+//! cargo run -p commands -- --device port:3-1.4 --write
+//! ```
+//!
+//! `--device` takes the same selectors as `elf2flash deploy --device` and must match exactly one
+//! device. `--write` rewrites the last block of the device with its own contents, which is
+//! refused for non-removable or large disks unless `--unsafe` is passed as well.
+
+use std::{env, error::Error, process};
+
+use usbh_scsi::{
+    commands::{
+        CommandBlock,
+        cbw::Direction,
+        read_capacity::{
+            ReadCapacity10Command, ReadCapacity10Data, ReadCapacity16Command, ReadCapacity16Data,
+        },
+        read10::Read10Command,
+        request_sense::{RequestSenseCommand, SenseData},
+        test_unit_ready::TestUnitReadyCommand,
+        write10::Write10Command,
+    },
+    safety::check_write_allowed,
+    select::{DeviceIdentity, DeviceSelector},
+    storage::{Opened, UsbMassStorage},
+};
+
+const USAGE: &str = "usage: commands [--device <SELECTOR> [--write [--unsafe]]]";
+
+struct Args {
+    device: Option<DeviceSelector>,
+    write: bool,
+    unsafe_write: bool,
+}
+
+fn parse_args() -> Result<Args, Box<dyn Error>> {
+    let mut args = Args {
+        device: None,
+        write: false,
+        unsafe_write: false,
     };
 
-    // Open the first device.
-    let mut dev = closed.open()?;
+    let mut argv = env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--device" => {
+                let selector = argv.next().ok_or("--device needs a selector")?;
+                args.device = Some(selector.parse()?);
+            }
+            "--write" => args.write = true,
+            "--unsafe" => args.unsafe_write = true,
+            _ => return Err(format!("unknown argument '{arg}'\n{USAGE}").into()),
+        }
+    }
+
+    Ok(args)
+}
 
-    let inquiry = InquiryCommand::new(0);
-    let mut inquiry_buf = [0u8; 36];
-    dev.execute_command(
-        0, // logical unit number (LUN 0)
-        inquiry_buf.len() as u32,
-        Direction::In,
-        &inquiry,
-        Some(&mut inquiry_buf),
-    )?;
-    let inquiry_data = InquiryData::parse(&inquiry_buf).unwrap();
-    println!(
-        "Inquiry:\n    product: '{}'\n    vendor: '{}'\n    revision: '{}'",
-        inquiry_data.product(),
-        inquiry_data.vendor(),
-        inquiry_data.revision()
-    );
+/// An opcode no device implements, to have something for REQUEST SENSE to explain
+struct InvalidCommand;
 
+impl CommandBlock for InvalidCommand {
+    fn to_bytes(&self) -> [u8; 16] {
+        let mut cdb = [0u8; 16];
+        cdb[0] = 0xFF;
+        cdb
+    }
+
+    fn len(&self) -> u8 {
+        6
+    }
+}
+
+fn request_sense(dev: &mut UsbMassStorage<Opened>) -> Result<SenseData, Box<dyn Error>> {
+    let mut buf = [0u8; 18];
+    let cmd = RequestSenseCommand::new(buf.len() as u8);
+    dev.execute_command(0x03, buf.len() as u32, Direction::In, &cmd, Some(&mut buf))?;
+    Ok(SenseData::parse(&buf).ok_or("short sense data")?)
+}
+
+fn read_capacity(dev: &mut UsbMassStorage<Opened>) -> Result<ReadCapacity10Data, Box<dyn Error>> {
     let mut buf = [0u8; 8];
-    let rc10 = ReadCapacity10Command::new(0);
-    dev.execute_command(0x10, buf.len() as u32, Direction::In, &rc10, Some(&mut buf))?;
-    let read_capacity_data = ReadCapacity10Data::parse(&buf).unwrap();
+    let cmd = ReadCapacity10Command::new(0);
+    dev.execute_command(0x25, buf.len() as u32, Direction::In, &cmd, Some(&mut buf))?;
+    Ok(ReadCapacity10Data::parse(&buf).ok_or("short capacity data")?)
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("error: {err}");
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let args = parse_args()?;
+
+    // List every device with its identity, nothing is written here
+    let devices = UsbMassStorage::list()?;
+    if devices.is_empty() {
+        println!("No USB mass storage devices found.");
+        return Ok(());
+    }
 
+    let identities: Vec<DeviceIdentity> = devices
+        .iter()
+        .enumerate()
+        .map(|(index, device)| DeviceIdentity::read(index, device))
+        .collect();
+
+    for (identity, device) in identities.iter().zip(&devices) {
+        println!("{identity}");
+        let mut dev = match device.clone().open() {
+            Ok(dev) => dev,
+            Err(err) => {
+                println!("    can't open: {err}");
+                continue;
+            }
+        };
+        match dev.inquiry() {
+            Ok(inquiry) => println!(
+                "    {} {} {}, removable: {}",
+                inquiry.vendor(),
+                inquiry.product(),
+                inquiry.revision(),
+                inquiry.is_removable
+            ),
+            Err(err) => println!("    INQUIRY failed: {err}"),
+        }
+        match read_capacity(&mut dev) {
+            Ok(capacity) => println!("    {} bytes", capacity.total_capacity_bytes()),
+            Err(err) => println!("    READ CAPACITY (10) failed: {err}"),
+        }
+        dev.release();
+    }
+
+    let Some(selector) = args.device else {
+        println!("\nPick a device with --device <SELECTOR> to run the examples on it.\n{USAGE}");
+        return Ok(());
+    };
+
+    let selected: Vec<usize> = identities
+        .iter()
+        .filter(|identity| selector.matches(*identity))
+        .map(|identity| identity.index)
+        .collect();
+    let index = match selected[..] {
+        [index] => index,
+        [] => return Err(format!("no device matched {selector}").into()),
+        _ => return Err(format!("{selector} matched {} devices", selected.len()).into()),
+    };
+
+    println!("\nUsing {}", identities[index]);
+    let mut dev = devices[index].clone().open()?;
+
+    // TEST UNIT READY has no data, whether the unit is ready shows in the sense data
+    dev.execute_command(0x00, 0, Direction::In, &TestUnitReadyCommand::new(0), None)?;
+    println!("TEST UNIT READY: {:?}", request_sense(&mut dev)?.sense_key);
+
+    // READ CAPACITY (16) is optional for small disks, not every drive implements it
+    let mut buf = [0u8; 32];
+    let rc16 = ReadCapacity16Command::new(buf.len() as u32);
+    let capacity = match dev
+        .execute_command(0x9E, buf.len() as u32, Direction::In, &rc16, Some(&mut buf))
+        .ok()
+        .and_then(|_| ReadCapacity16Data::parse(&buf))
+    {
+        Some(capacity) if capacity.block_length_bytes != 0 => {
+            println!(
+                "READ CAPACITY (16): {} blocks of {} bytes",
+                capacity.last_logical_block_address + 1,
+                capacity.block_length_bytes
+            );
+            capacity
+        }
+        _ => {
+            let capacity = read_capacity(&mut dev)?;
+            println!(
+                "READ CAPACITY (16) unsupported, READ CAPACITY (10): {} blocks of {} bytes",
+                capacity.last_logical_block_address as u64 + 1,
+                capacity.block_length_bytes
+            );
+            ReadCapacity16Data {
+                last_logical_block_address: capacity.last_logical_block_address as u64,
+                block_length_bytes: capacity.block_length_bytes,
+            }
+        }
+    };
+
+    // A failed command leaves sense data explaining the failure
+    dev.execute_command(0xFF, 0, Direction::In, &InvalidCommand, None)?;
+    let sense = request_sense(&mut dev)?;
     println!(
-        "\nCapacity: {} bytes, block size: {} bytes",
-        read_capacity_data.total_capacity_bytes(),
-        read_capacity_data.block_length_bytes,
+        "REQUEST SENSE after an invalid command: {:?}, ASC {:#04x} ASCQ {:#04x}",
+        sense.sense_key, sense.additional_sense_code, sense.additional_sense_code_qualifier
     );
 
-    // --- Read first 512 bytes (assuming block size = 512) ---
-    let block_size = read_capacity_data.block_length_bytes;
-    let mut block_buf = vec![0u8; block_size as usize];
-
-    let read_cmd = Read10Command::new(0, 0, 1); // LUN 0, LBA 0, count 1 block
+    // READ(10) of the first block
+    let mut block = vec![0u8; capacity.block_length_bytes as usize];
+    let read = Read10Command::new(0, 0, 1);
     dev.execute_command(
-        0x20, // new tag for this command
-        block_buf.len() as u32,
+        0x28,
+        block.len() as u32,
         Direction::In,
-        &read_cmd,
-        Some(&mut block_buf),
+        &read,
+        Some(&mut block),
     )?;
-
-    println!("\nFirst {} bytes from device:", block_buf.len());
-    for (i, byte) in block_buf.iter().enumerate() {
+    println!("\nFirst 64 bytes of block 0:");
+    for (i, byte) in block.iter().take(64).enumerate() {
         if i % 16 == 0 {
             print!("\n{:04x}: ", i);
         }
@@ -66,5 +220,41 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
     println!();
 
+    if !args.write {
+        dev.release();
+        return Ok(());
+    }
+
+    let inquiry = dev.inquiry()?;
+    if let Err(err) = check_write_allowed(&inquiry, capacity.total_capacity_bytes()) {
+        if !args.unsafe_write {
+            dev.release();
+            return Err(format!("{err}, pass --unsafe to write anyway").into());
+        }
+        println!("\nWriting anyway because of --unsafe: {err}");
+    }
+
+    // WRITE(10) the last block back with its own contents, so the drive is left unchanged
+    let lba = u32::try_from(capacity.last_logical_block_address)
+        .map_err(|_| "the last block is out of reach of WRITE(10)")?;
+    let read = Read10Command::new(0, lba, 1);
+    dev.execute_command(
+        0x28,
+        block.len() as u32,
+        Direction::In,
+        &read,
+        Some(&mut block),
+    )?;
+    let write = Write10Command::new(0, lba, 1);
+    dev.execute_command(
+        0x2A,
+        block.len() as u32,
+        Direction::Out,
+        &write,
+        Some(&mut block),
+    )?;
+    println!("\nRewrote block {lba} with its own contents");
+
+    dev.release();
     Ok(())
 }
@@ -4,7 +4,7 @@
 //! descriptor blocks (CDBs) to be sent over USB Mass Storage Bulk-Only
 //! Transport.
 //!
-//! Each supported command (e.g. INQUIRY, READ(10), WRITE(10)) lives in
+//! Each supported command (e.g. INQUIRY, READ(10), WRITE(10), REQUEST SENSE) lives in
 //! its own submodule. All implement the [`CommandBlock`] trait, which
 //! allows them to be executed through [`UsbMassStorage::execute_command`](crate::storage::UsbMassStorage::execute_command).
 //!
@@ -24,6 +24,9 @@ pub mod cbw;
 pub mod inquiry;
 pub mod read10;
 pub mod read_capacity;
+pub mod request_sense;
+pub mod start_stop_unit;
+pub mod test_unit_ready;
 pub mod write10;
 
 /// Trait for any SCSI Command Block (CDB).
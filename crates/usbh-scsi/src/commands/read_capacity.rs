@@ -85,3 +85,78 @@ impl ReadCapacity10Data {
         (self.last_logical_block_address as u64 + 1) * self.block_length_bytes as u64
     }
 }
+
+/// SCSI **READ CAPACITY (16)** command (SERVICE ACTION IN (16) with service action `0x10`).
+///
+/// Same as [`ReadCapacity10Command`], with a 64-bit last logical block address. Disks of 2 TiB
+/// and more report `0xFFFFFFFF` to READ CAPACITY (10), and have to be asked with this command.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadCapacity16Command {
+    /// Allocation length: how many bytes the host expects back (the full response is 32).
+    pub alloc_len: u32,
+}
+
+impl ReadCapacity16Command {
+    /// Construct a new `READ CAPACITY (16)` command with the given expected response size.
+    pub fn new(alloc_len: u32) -> Self {
+        Self { alloc_len }
+    }
+}
+
+impl CommandBlock for ReadCapacity16Command {
+    fn to_bytes(&self) -> [u8; 16] {
+        let mut cdb = [0u8; 16];
+        cdb[0] = 0x9E; // SERVICE ACTION IN (16) opcode
+        cdb[1] = 0x10; // READ CAPACITY (16) service action
+
+        // Allocation length (big-endian)
+        cdb[10..14].copy_from_slice(&self.alloc_len.to_be_bytes());
+        cdb
+    }
+
+    fn len(&self) -> u8 {
+        16 // READ CAPACITY (16) uses a 16-byte CDB
+    }
+}
+
+/// Parsed response to a **READ CAPACITY (16)** command (first 12 of the 32 bytes).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadCapacity16Data {
+    /// Address of the last logical block (zero-based).
+    pub last_logical_block_address: u64,
+    /// Block size in bytes (e.g. `512`).
+    pub block_length_bytes: u32,
+}
+
+impl ReadCapacity16Data {
+    /// Parse a READ CAPACITY (16) response buffer.
+    ///
+    /// Returns `None` if the buffer is shorter than 12 bytes.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < 12 {
+            return None;
+        }
+
+        let last_logical_block_address = u64::from_be_bytes(buf[0..8].try_into().ok()?);
+        let block_length_bytes = u32::from_be_bytes(buf[8..12].try_into().ok()?);
+
+        Some(Self {
+            last_logical_block_address,
+            block_length_bytes,
+        })
+    }
+
+    /// Compute the total capacity of the device in bytes.
+    ///
+    /// ```
+    /// # use usbh_scsi::commands::read_capacity::ReadCapacity16Data;
+    /// let mut buf = [0u8; 32];
+    /// buf[0..8].copy_from_slice(&0x1_0000_0000u64.to_be_bytes());
+    /// buf[8..12].copy_from_slice(&512u32.to_be_bytes());
+    /// let data = ReadCapacity16Data::parse(&buf).unwrap();
+    /// assert_eq!(data.total_capacity_bytes(), (0x1_0000_0000 + 1) * 512);
+    /// ```
+    pub fn total_capacity_bytes(&self) -> u64 {
+        (self.last_logical_block_address + 1) * self.block_length_bytes as u64
+    }
+}
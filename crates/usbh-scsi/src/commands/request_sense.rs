@@ -0,0 +1,114 @@
+use crate::commands::CommandBlock;
+
+/// SCSI **REQUEST SENSE** command.
+///
+/// Fetches the sense data describing why the previous command failed. Devices only keep the
+/// sense data until the next command, so it has to be issued right after the failure.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestSenseCommand {
+    /// Allocation length: how many bytes of sense data the host expects back (usually 18).
+    pub alloc_len: u8,
+}
+
+impl RequestSenseCommand {
+    /// Construct a new `REQUEST SENSE` command with the given expected response size.
+    pub fn new(alloc_len: u8) -> Self {
+        Self { alloc_len }
+    }
+}
+
+impl CommandBlock for RequestSenseCommand {
+    fn to_bytes(&self) -> [u8; 16] {
+        let mut cdb = [0u8; 16];
+        cdb[0] = 0x03; // REQUEST SENSE opcode
+        cdb[4] = self.alloc_len; // allocation length
+        cdb
+    }
+
+    fn len(&self) -> u8 {
+        6 // REQUEST SENSE uses a 6-byte CDB
+    }
+}
+
+/// SCSI sense key, the general category of an error (byte 2 of fixed format sense data).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SenseKey {
+    /// No error to report.
+    NoSense, // 0x00
+    /// The command succeeded after recovery.
+    RecoveredError, // 0x01
+    /// The unit can't be accessed, e.g. no medium is present.
+    NotReady, // 0x02
+    /// The medium has a flaw, or reading it failed.
+    MediumError, // 0x03
+    /// The device failed.
+    HardwareError, // 0x04
+    /// The command or its parameters are invalid.
+    IllegalRequest, // 0x05
+    /// The medium was changed or the device reset since the last command.
+    UnitAttention, // 0x06
+    /// The medium is write protected.
+    DataProtect, // 0x07
+    /// The command was aborted by the device.
+    AbortedCommand, // 0x0B
+    /// Other or unrecognized value.
+    Other(u8),
+}
+
+impl From<u8> for SenseKey {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => SenseKey::NoSense,
+            0x01 => SenseKey::RecoveredError,
+            0x02 => SenseKey::NotReady,
+            0x03 => SenseKey::MediumError,
+            0x04 => SenseKey::HardwareError,
+            0x05 => SenseKey::IllegalRequest,
+            0x06 => SenseKey::UnitAttention,
+            0x07 => SenseKey::DataProtect,
+            0x0B => SenseKey::AbortedCommand,
+            other => SenseKey::Other(other),
+        }
+    }
+}
+
+/// Parsed fixed format sense data, the response to a **REQUEST SENSE** command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SenseData {
+    /// Response code (byte 0, without the valid bit), `0x70` for current errors.
+    pub response_code: u8,
+    pub sense_key: SenseKey,
+    /// Additional Sense Code (byte 12), the specific error within the sense key.
+    pub additional_sense_code: u8,
+    /// Additional Sense Code Qualifier (byte 13), more detail on the additional sense code.
+    pub additional_sense_code_qualifier: u8,
+}
+
+impl SenseData {
+    /// Parse fixed format sense data.
+    ///
+    /// Returns `None` if the buffer is shorter than 14 bytes.
+    ///
+    /// ```
+    /// # use usbh_scsi::commands::request_sense::{SenseData, SenseKey};
+    /// let mut buf = [0u8; 18];
+    /// buf[0] = 0x70;
+    /// buf[2] = 0x05; // ILLEGAL REQUEST
+    /// buf[12] = 0x20; // INVALID COMMAND OPERATION CODE
+    /// let sense = SenseData::parse(&buf).unwrap();
+    /// assert_eq!(sense.sense_key, SenseKey::IllegalRequest);
+    /// assert_eq!(sense.additional_sense_code, 0x20);
+    /// ```
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < 14 {
+            return None;
+        }
+
+        Some(Self {
+            response_code: buf[0] & 0x7F,
+            sense_key: SenseKey::from(buf[2] & 0x0F),
+            additional_sense_code: buf[12],
+            additional_sense_code_qualifier: buf[13],
+        })
+    }
+}
@@ -0,0 +1,53 @@
+use crate::commands::CommandBlock;
+
+/// SCSI **START STOP UNIT** command.
+///
+/// Starts or stops the medium, and with `load_eject` set loads or ejects it. There is no data
+/// phase. Bootloaders exposing a uf2 drive, like TinyUF2, take an eject as the signal that the
+/// host is done and reboot into the new firmware.
+///
+/// ```
+/// use usbh_scsi::commands::{CommandBlock, start_stop_unit::StartStopUnitCommand};
+///
+/// let cmd = StartStopUnitCommand::eject(0);
+/// assert_eq!(&cmd.to_bytes()[..cmd.len() as usize], &[0x1B, 0, 0, 0, 0x02, 0]);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct StartStopUnitCommand {
+    /// Logical Unit Number (LUN). Usually `0` for single-LUN devices.
+    pub logical_unit_number: u8,
+    /// Make the medium ready (`true`) or stop it (`false`)
+    pub start: bool,
+    /// Load the medium when starting, eject it when stopping
+    pub load_eject: bool,
+}
+
+impl StartStopUnitCommand {
+    /// Construct a new `START STOP UNIT` command for a given LUN.
+    pub fn new(logical_unit_number: u8, start: bool, load_eject: bool) -> Self {
+        Self {
+            logical_unit_number,
+            start,
+            load_eject,
+        }
+    }
+
+    /// Stop the unit and eject its medium, `LoEj=1, Start=0`.
+    pub fn eject(logical_unit_number: u8) -> Self {
+        Self::new(logical_unit_number, false, true)
+    }
+}
+
+impl CommandBlock for StartStopUnitCommand {
+    fn to_bytes(&self) -> [u8; 16] {
+        let mut cdb = [0u8; 16];
+        cdb[0] = 0x1B; // START STOP UNIT opcode
+        cdb[1] = (self.logical_unit_number & 0x07) << 5;
+        cdb[4] = ((self.load_eject as u8) << 1) | self.start as u8;
+        cdb
+    }
+
+    fn len(&self) -> u8 {
+        6 // START STOP UNIT uses a 6-byte CDB
+    }
+}
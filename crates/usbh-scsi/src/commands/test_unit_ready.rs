@@ -0,0 +1,35 @@
+use crate::commands::CommandBlock;
+
+/// SCSI **TEST UNIT READY** command.
+///
+/// Asks whether the logical unit can accept medium access commands. There is no data phase, the
+/// answer is the command status: a failed status means the unit isn't ready, and
+/// [`RequestSenseCommand`](crate::commands::request_sense::RequestSenseCommand) tells why (e.g.
+/// no medium present).
+#[derive(Debug, Clone, Copy)]
+pub struct TestUnitReadyCommand {
+    /// Logical Unit Number (LUN). Usually `0` for single-LUN devices.
+    pub logical_unit_number: u8,
+}
+
+impl TestUnitReadyCommand {
+    /// Construct a new `TEST UNIT READY` command for a given LUN.
+    pub fn new(logical_unit_number: u8) -> Self {
+        Self {
+            logical_unit_number,
+        }
+    }
+}
+
+impl CommandBlock for TestUnitReadyCommand {
+    fn to_bytes(&self) -> [u8; 16] {
+        let mut cdb = [0u8; 16];
+        cdb[0] = 0x00; // TEST UNIT READY opcode
+        cdb[1] = (self.logical_unit_number & 0x07) << 5;
+        cdb
+    }
+
+    fn len(&self) -> u8 {
+        6 // TEST UNIT READY uses a 6-byte CDB
+    }
+}
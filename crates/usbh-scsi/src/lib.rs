@@ -1,4 +1,7 @@
 #![doc = include_str!("../README.md")]
 
 pub mod commands;
+pub mod safety;
+pub mod select;
+pub mod session;
 pub mod storage;
@@ -0,0 +1,57 @@
+//! Guards against writing to the wrong disk.
+//!
+//! Raw SCSI writes bypass the operating system, nothing stops them from overwriting a backup
+//! drive that happened to be plugged in. [`check_write_allowed`] refuses disks that don't look
+//! like a flash drive or bootloader volume, unless the caller explicitly opts out.
+
+use thiserror::Error;
+
+use crate::commands::inquiry::InquiryData;
+
+/// Largest disk [`check_write_allowed`] accepts, bootloader volumes and throwaway flash drives
+/// are well below it
+pub const MAX_SAFE_CAPACITY: u64 = 32 * 1024 * 1024 * 1024;
+
+/// Why writing to a disk was refused.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum UnsafeWriteError {
+    /// The INQUIRY data doesn't have the removable medium bit set.
+    #[error("refusing to write to a non-removable disk")]
+    NotRemovable,
+    /// The disk is larger than [`MAX_SAFE_CAPACITY`].
+    #[error("refusing to write to a {capacity} byte disk, larger than {max_capacity} bytes")]
+    TooLarge { capacity: u64, max_capacity: u64 },
+}
+
+/// Check that a disk with the given INQUIRY data and capacity is safe to write to.
+///
+/// Only removable disks of at most [`MAX_SAFE_CAPACITY`] bytes are allowed.
+///
+/// ```
+/// use usbh_scsi::commands::inquiry::InquiryData;
+/// use usbh_scsi::safety::{UnsafeWriteError, check_write_allowed};
+///
+/// let mut inquiry = [0u8; 36];
+/// let fixed = InquiryData::parse(&inquiry).unwrap();
+/// assert_eq!(
+///     check_write_allowed(&fixed, 128 * 1024 * 1024),
+///     Err(UnsafeWriteError::NotRemovable)
+/// );
+///
+/// inquiry[1] = 0x80; // RMB, removable medium
+/// let removable = InquiryData::parse(&inquiry).unwrap();
+/// assert_eq!(check_write_allowed(&removable, 128 * 1024 * 1024), Ok(()));
+/// assert!(check_write_allowed(&removable, 2 << 40).is_err());
+/// ```
+pub fn check_write_allowed(inquiry: &InquiryData, capacity: u64) -> Result<(), UnsafeWriteError> {
+    if !inquiry.is_removable {
+        return Err(UnsafeWriteError::NotRemovable);
+    }
+    if capacity > MAX_SAFE_CAPACITY {
+        return Err(UnsafeWriteError::TooLarge {
+            capacity,
+            max_capacity: MAX_SAFE_CAPACITY,
+        });
+    }
+    Ok(())
+}
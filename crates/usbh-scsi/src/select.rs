@@ -0,0 +1,351 @@
+//! Picking out devices by serial number, port, vendor/product id, volume label or position.
+//!
+//! Selectors are written as `<kind>:<value>`, e.g. `serial:E6614C311B2F`, `port:3-1.4`,
+//! `vidpid:2e8a:0003`, `label:RPI-RP2` or `index:0`. They are matched against anything
+//! implementing [`SelectorTarget`], such as the [`DeviceIdentity`] of a listed device.
+//!
+//! # Example
+//! ```no_run
+//! use usbh_scsi::select::{DeviceIdentity, DeviceSelector};
+//! use usbh_scsi::storage::UsbMassStorage;
+//!
+//! let selector: DeviceSelector = "vidpid:2e8a:0003".parse().unwrap();
+//! for (index, device) in UsbMassStorage::list().unwrap().iter().enumerate() {
+//!     let identity = DeviceIdentity::read(index, device);
+//!     if selector.matches(&identity) {
+//!         println!("{identity}");
+//!     }
+//! }
+//! ```
+
+use std::{fmt, str::FromStr};
+
+use rusb::{Device, UsbContext};
+use thiserror::Error;
+
+use crate::storage::{Closed, UsbMassStorage};
+
+/// Picks out plugged in devices, parsed from `<kind>:<value>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// `serial:<usb serial number>`
+    Serial(String),
+    /// `port:<bus>-<port>[.<port>...]`, e.g. `port:3-1.4`
+    Port(String),
+    /// `vidpid:<vendor id>:<product id>` in hex, e.g. `vidpid:2e8a:0003`
+    VidPid(u16, u16),
+    /// `label:<volume label>`, e.g. `label:RPI-RP2`
+    Label(String),
+    /// `index:<n>`, the position in the list of found devices
+    Index(usize),
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum DeviceSelectorParseError {
+    #[error("expected <kind>:<value>, where kind is one of serial, port, vidpid, label or index")]
+    MissingKind,
+    #[error("unknown selector kind '{0}', expected one of serial, port, vidpid, label or index")]
+    UnknownKind(String),
+    #[error("selector value is empty")]
+    EmptyValue,
+    #[error("invalid vendor/product id '{0}', expected <vid>:<pid> in hex")]
+    InvalidVidPid(String),
+    #[error("invalid index '{0}'")]
+    InvalidIndex(String),
+}
+
+impl FromStr for DeviceSelector {
+    type Err = DeviceSelectorParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, value) = s
+            .trim()
+            .split_once(':')
+            .ok_or(DeviceSelectorParseError::MissingKind)?;
+
+        if value.is_empty() {
+            return Err(DeviceSelectorParseError::EmptyValue);
+        }
+
+        match kind.to_ascii_lowercase().as_str() {
+            "serial" => Ok(Self::Serial(value.to_string())),
+            "port" => Ok(Self::Port(value.to_string())),
+            "label" => Ok(Self::Label(value.to_string())),
+            "vidpid" => {
+                let invalid = || DeviceSelectorParseError::InvalidVidPid(value.to_string());
+                let (vid, pid) = value.split_once(':').ok_or_else(invalid)?;
+                let parse = |id: &str| {
+                    u16::from_str_radix(id.trim_start_matches("0x"), 16).map_err(|_| invalid())
+                };
+                Ok(Self::VidPid(parse(vid)?, parse(pid)?))
+            }
+            "index" => value
+                .parse()
+                .map(Self::Index)
+                .map_err(|_| DeviceSelectorParseError::InvalidIndex(value.to_string())),
+            _ => Err(DeviceSelectorParseError::UnknownKind(kind.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Serial(serial) => write!(f, "serial:{serial}"),
+            Self::Port(port) => write!(f, "port:{port}"),
+            Self::VidPid(vid, pid) => write!(f, "vidpid:{vid:04x}:{pid:04x}"),
+            Self::Label(label) => write!(f, "label:{label}"),
+            Self::Index(index) => write!(f, "index:{index}"),
+        }
+    }
+}
+
+impl DeviceSelector {
+    /// Whether `device` is picked out by this selector.
+    ///
+    /// ```
+    /// use usbh_scsi::select::{DeviceIdentity, DeviceSelector};
+    ///
+    /// let device = DeviceIdentity {
+    ///     index: 1,
+    ///     vendor_id: 0x2e8a,
+    ///     product_id: 0x0003,
+    ///     port: Some("3-1.4".to_string()),
+    ///     serial: None,
+    /// };
+    /// assert!(DeviceSelector::Port("3-1.4".to_string()).matches(&device));
+    /// assert!(!DeviceSelector::Serial("E661".to_string()).matches(&device));
+    /// // Volume labels are only known once a filesystem is mounted
+    /// assert!(!DeviceSelector::Label("RPI-RP2".to_string()).matches(&device));
+    /// ```
+    pub fn matches(&self, device: &impl SelectorTarget) -> bool {
+        match self {
+            Self::Serial(serial) => device.serial() == Some(serial.as_str()),
+            Self::Port(port) => device.port() == Some(port.as_str()),
+            Self::VidPid(vid, pid) => device.vendor_id() == *vid && device.product_id() == *pid,
+            Self::Label(label) => device
+                .labels()
+                .iter()
+                .any(|l| l.eq_ignore_ascii_case(label)),
+            Self::Index(index) => device.index() == *index,
+        }
+    }
+}
+
+/// What a [`DeviceSelector`] is matched against.
+pub trait SelectorTarget {
+    /// Position of the device in the list of found devices
+    fn index(&self) -> usize;
+    fn vendor_id(&self) -> u16;
+    fn product_id(&self) -> u16;
+    /// Physical port path, see [`port_path`]
+    fn port(&self) -> Option<&str>;
+    fn serial(&self) -> Option<&str>;
+    /// Volume labels of the filesystems on the device, none by default
+    fn labels(&self) -> &[String] {
+        &[]
+    }
+}
+
+/// What can be told about a listed device without claiming it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceIdentity {
+    /// Position of the device in [`UsbMassStorage::list`]
+    pub index: usize,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// Physical port path, see [`port_path`]
+    pub port: Option<String>,
+    /// USB serial number string, if the device reports one
+    pub serial: Option<String>,
+}
+
+impl DeviceIdentity {
+    /// Read the identity of the device at `index` of [`UsbMassStorage::list`], fields that can't
+    /// be read are left empty.
+    pub fn read<T: UsbContext>(index: usize, storage: &UsbMassStorage<Closed, T>) -> Self {
+        let device = &storage.device;
+        let (vendor_id, product_id) = device
+            .device_descriptor()
+            .map(|desc| (desc.vendor_id(), desc.product_id()))
+            .unwrap_or_default();
+
+        Self {
+            index,
+            vendor_id,
+            product_id,
+            port: port_path(device),
+            serial: serial_number(device),
+        }
+    }
+}
+
+impl SelectorTarget for DeviceIdentity {
+    fn index(&self) -> usize {
+        self.index
+    }
+
+    fn vendor_id(&self) -> u16 {
+        self.vendor_id
+    }
+
+    fn product_id(&self) -> u16 {
+        self.product_id
+    }
+
+    fn port(&self) -> Option<&str> {
+        self.port.as_deref()
+    }
+
+    fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+}
+
+impl fmt::Display for DeviceIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "index {} {:04x}:{:04x}",
+            self.index, self.vendor_id, self.product_id
+        )?;
+        if let Some(port) = &self.port {
+            write!(f, " port {port}")?;
+        }
+        if let Some(serial) = &self.serial {
+            write!(f, " serial {serial}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A location in the USB tree: a bus, then the hub ports leading to a device or hub. Written like
+/// [`port_path`], `3-1.4`, or just `3` for a whole bus.
+///
+/// ```
+/// use usbh_scsi::select::PortPath;
+///
+/// let hub: PortPath = "3-1".parse().unwrap();
+/// assert!(hub.contains(&"3-1.4".parse().unwrap()));
+/// assert!(hub.contains(&hub));
+/// assert!(!hub.contains(&"3-2".parse().unwrap()));
+/// assert!(!hub.contains(&"4-1.4".parse().unwrap()));
+/// assert_eq!(hub.to_string(), "3-1");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PortPath {
+    pub bus: u8,
+    /// Hub ports from the root hub down, empty for the bus itself
+    pub ports: Vec<u8>,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("invalid port path '{0}', expected <bus>[-<port>[.<port>...]], e.g. 3-1.4")]
+pub struct PortPathParseError(String);
+
+impl PortPath {
+    /// The location of `device`, `None` if its ports can't be read. Reading it doesn't open the
+    /// device or read its descriptors.
+    pub fn of<T: UsbContext>(device: &Device<T>) -> Option<Self> {
+        Some(Self {
+            bus: device.bus_number(),
+            ports: device.port_numbers().ok()?,
+        })
+    }
+
+    /// Whether `other` is this location, or a device somewhere behind it.
+    pub fn contains(&self, other: &PortPath) -> bool {
+        self.bus == other.bus && other.ports.starts_with(&self.ports)
+    }
+}
+
+impl FromStr for PortPath {
+    type Err = PortPathParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PortPathParseError(s.to_string());
+        let s = s.trim();
+        let (bus, ports) = match s.split_once('-') {
+            Some((bus, ports)) => (bus, Some(ports)),
+            None => (s, None),
+        };
+
+        let bus = bus.parse().map_err(|_| invalid())?;
+        let ports = match ports {
+            Some(ports) => ports
+                .split('.')
+                .map(|port| port.parse().map_err(|_| invalid()))
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        Ok(Self { bus, ports })
+    }
+}
+
+impl fmt::Display for PortPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.bus)?;
+        for (i, port) in self.ports.iter().enumerate() {
+            let separator = if i == 0 { '-' } else { '.' };
+            write!(f, "{separator}{port}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Which devices [`UsbMassStorage::list_with`] enumerates.
+///
+/// On a rack where every board hangs off one hub, the devices elsewhere are skipped before any of
+/// their descriptors are read:
+///
+/// ```
+/// use usbh_scsi::select::{ListOptions, PortPath};
+///
+/// // 40 devices, 8 behind each of the hubs on ports 1 to 5 of bus 3
+/// let rack: Vec<PortPath> = (1..=5)
+///     .flat_map(|hub| (1..=8).map(move |port| PortPath { bus: 3, ports: vec![hub, port] }))
+///     .collect();
+///
+/// let options = ListOptions::default();
+/// assert_eq!(rack.iter().filter(|path| options.includes(Some(path))).count(), 40);
+///
+/// let options = ListOptions {
+///     roots: vec!["3-2".parse().unwrap(), "3-5.1".parse().unwrap()],
+/// };
+/// assert_eq!(rack.iter().filter(|path| options.includes(Some(path))).count(), 9);
+/// assert!(!options.includes(None));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListOptions {
+    /// Only list devices at or behind one of these locations, every device when empty
+    pub roots: Vec<PortPath>,
+}
+
+impl ListOptions {
+    /// Whether a device at `location` is listed. Devices whose location can't be read are only
+    /// listed without roots.
+    pub fn includes(&self, location: Option<&PortPath>) -> bool {
+        if self.roots.is_empty() {
+            return true;
+        }
+        location.is_some_and(|location| self.roots.iter().any(|root| root.contains(location)))
+    }
+}
+
+/// Physical port path of `device`, formatted like `3-1.4` (bus, then the hub ports).
+pub fn port_path<T: UsbContext>(device: &Device<T>) -> Option<String> {
+    let ports = device.port_numbers().ok()?;
+    let ports: Vec<String> = ports.iter().map(|port| port.to_string()).collect();
+    Some(format!("{}-{}", device.bus_number(), ports.join(".")))
+}
+
+/// USB serial number string of `device`, reading it briefly opens the device.
+pub fn serial_number<T: UsbContext>(device: &Device<T>) -> Option<String> {
+    device
+        .device_descriptor()
+        .and_then(|desc| {
+            let handle = device.open()?;
+            handle.read_serial_number_string_ascii(&desc)
+        })
+        .ok()
+}
@@ -0,0 +1,82 @@
+//! A dedicated libusb context for everything a program does over USB.
+//!
+//! [`UsbMassStorage::list`] uses `rusb`'s process wide [`GlobalContext`](rusb::GlobalContext),
+//! which can't be configured or isolated from other users in the same process. A [`UsbSession`]
+//! owns its own [`Context`] instead. Clones of a session share that one context, so devices
+//! found on one thread can be opened and written on another.
+//!
+//! # Example
+//!
+//! Enumerating from several threads at once:
+//!
+//! ```no_run
+//! use std::thread;
+//! use usbh_scsi::session::UsbSession;
+//!
+//! let session = UsbSession::new().unwrap();
+//! let counts: Vec<usize> = thread::scope(|scope| {
+//!     let threads: Vec<_> = (0..4)
+//!         .map(|_| {
+//!             let session = session.clone();
+//!             scope.spawn(move || session.list_mass_storage().unwrap().len())
+//!         })
+//!         .collect();
+//!     threads.into_iter().map(|t| t.join().unwrap()).collect()
+//! });
+//! assert!(counts.iter().all(|&count| count == counts[0]));
+//! ```
+
+use rusb::{Context, LogLevel, UsbContext, UsbOption};
+
+use crate::{
+    select::ListOptions,
+    storage::{Closed, UsbMassStorage, UsbMassStorageError},
+};
+
+/// Owns a libusb [`Context`], cloning the session shares it.
+#[derive(Debug, Clone)]
+pub struct UsbSession {
+    // rusb reference counts the context, a clone is another handle to the same one
+    context: Context,
+}
+
+impl UsbSession {
+    /// Create a session with a new libusb context.
+    pub fn new() -> Result<Self, UsbMassStorageError> {
+        Self::with_options(&[])
+    }
+
+    /// Create a session with libusb `options`, e.g. [`UsbOption::use_usbdk`] on Windows.
+    pub fn with_options(options: &[UsbOption]) -> Result<Self, UsbMassStorageError> {
+        let context =
+            Context::with_options(options).map_err(UsbMassStorageError::FailedToCreateContext)?;
+        Ok(Self { context })
+    }
+
+    /// Set how much libusb logs, only for this session's context.
+    pub fn with_log_level(mut self, level: LogLevel) -> Self {
+        self.context.set_log_level(level);
+        self
+    }
+
+    /// The context devices of this session belong to.
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// Enumerate the connected USB Mass Storage devices, see [`UsbMassStorage::list`].
+    pub fn list_mass_storage(
+        &self,
+    ) -> Result<Vec<UsbMassStorage<Closed, Context>>, UsbMassStorageError> {
+        UsbMassStorage::list_in(&self.context)
+    }
+
+    /// Enumerate the connected USB Mass Storage devices that `options` includes, see
+    /// [`UsbMassStorage::list_in_with`].
+    pub fn list_mass_storage_with(
+        &self,
+        options: &ListOptions,
+    ) -> Result<Vec<UsbMassStorage<Closed, Context>>, UsbMassStorageError> {
+        UsbMassStorage::list_in_with(&self.context, options)
+    }
+}
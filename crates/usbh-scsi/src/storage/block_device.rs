@@ -17,10 +17,13 @@ use crate::commands::{
     read10::Read10Command,
     write10::Write10Command,
 };
+use rusb::{GlobalContext, UsbContext};
 use std::{
     cell::RefCell,
+    fmt,
     io::{self, Read as IoRead, Seek as IoSeek, SeekFrom, Write as IoWrite},
 };
+use thiserror::Error;
 
 use crate::storage::{Opened, UsbMassStorage, UsbMassStorageReadWriteError};
 
@@ -29,24 +32,23 @@ use crate::storage::{Opened, UsbMassStorage, UsbMassStorageReadWriteError};
 /// - Queries the device with `READ CAPACITY(10)` to determine block size and total capacity.
 /// - Provides convenience methods for reading/writing whole blocks.
 /// - Implements standard `Read`, `Write`, `Seek` traits to integrate with Rust I/O ecosystem.
-#[derive(Debug)]
-pub struct UsbBlockDevice<'a> {
-    usb: RefCell<&'a mut UsbMassStorage<Opened>>,
+pub struct UsbBlockDevice<'a, T: UsbContext = GlobalContext> {
+    usb: RefCell<&'a mut UsbMassStorage<Opened<T>, T>>,
     block_size: u32,
     max_lba: u64,
     pos: u64,
 }
 
-impl<'a> UsbBlockDevice<'a> {
+impl<'a, T: UsbContext> UsbBlockDevice<'a, T> {
     /// Create a new block device wrapper by issuing a `READ CAPACITY(10)` command.
     ///
     /// This determines the device’s block size and last usable LBA.
-    pub fn new(usb: &'a mut UsbMassStorage<Opened>) -> io::Result<Self> {
+    pub fn new(usb: &'a mut UsbMassStorage<Opened<T>, T>) -> io::Result<Self> {
         // Query capacity to learn block size & last LBA
         let mut buf = [0u8; 8];
         let rc10 = ReadCapacity10Command::new(0);
         usb.execute_command(0x10, buf.len() as u32, Direction::In, &rc10, Some(&mut buf))
-            .map_err(to_io_err)?;
+            .map_err(CommandFailed::with("READ CAPACITY(10)"))?;
         let cap = ReadCapacity10Data::parse(&buf).ok_or_else(|| {
             io::Error::new(io::ErrorKind::InvalidData, "READ CAPACITY(10) parse failed")
         })?;
@@ -62,6 +64,16 @@ impl<'a> UsbBlockDevice<'a> {
         })
     }
 
+    /// Size of a single block in bytes, as reported by `READ CAPACITY(10)`.
+    pub fn block_size(&self) -> u32 {
+        self.block_size
+    }
+
+    /// Number of addressable blocks on the device.
+    pub fn block_count(&self) -> u64 {
+        self.max_lba + 1
+    }
+
     /// Returns the total size of the disk (in bytes).
     #[inline]
     fn disk_size(&self) -> u64 {
@@ -84,7 +96,7 @@ impl<'a> UsbBlockDevice<'a> {
         self.usb
             .get_mut()
             .execute_command(tag, buf.len() as u32, Direction::Out, &cmd, Some(&mut tmp))
-            .map_err(to_io_err)
+            .map_err(CommandFailed::with("WRITE(10)"))
     }
 
     /// Read `count` consecutive blocks starting at `lba` into `buf`.
@@ -106,7 +118,7 @@ impl<'a> UsbBlockDevice<'a> {
         self.usb
             .get_mut()
             .execute_command(tag, buf.len() as u32, Direction::In, &cmd, Some(buf))
-            .map_err(to_io_err)
+            .map_err(CommandFailed::with("READ(10)"))
     }
 
     /// Low-level helper: read arbitrary bytes starting at `pos` (absolute).
@@ -146,18 +158,63 @@ impl<'a> UsbBlockDevice<'a> {
                 &read10,
                 Some(&mut tmp),
             )
-            .map_err(to_io_err)?;
+            .map_err(CommandFailed::with("READ(10)"))?;
 
         buf[..want].copy_from_slice(&tmp[offset_in_block..offset_in_block + want]);
         Ok(want)
     }
 }
 
-fn to_io_err(e: UsbMassStorageReadWriteError) -> io::Error {
-    io::Error::new(io::ErrorKind::Other, e)
+impl<T: UsbContext> fmt::Debug for UsbBlockDevice<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UsbBlockDevice")
+            .field("usb", &self.usb)
+            .field("block_size", &self.block_size)
+            .field("max_lba", &self.max_lba)
+            .field("pos", &self.pos)
+            .finish()
+    }
+}
+
+/// A SCSI command of a [`UsbBlockDevice`] that failed.
+///
+/// The `Read`/`Write`/`Seek` implementations return it wrapped in an [`io::Error`], which keeps
+/// the transfer error as its source:
+///
+/// ```
+/// use std::{error::Error, io};
+/// use usbh_scsi::storage::{UsbMassStorageReadWriteError, block_device::CommandFailed};
+///
+/// let err = io::Error::from(CommandFailed {
+///     command: "READ(10)",
+///     source: UsbMassStorageReadWriteError::UsbDeviceBulkFailed(rusb::Error::Timeout),
+/// });
+/// assert_eq!(err.to_string(), "READ(10) failed");
+/// assert_eq!(err.source().unwrap().to_string(), "bulk transfer failed");
+/// ```
+#[derive(Error, Debug)]
+#[error("{command} failed")]
+pub struct CommandFailed {
+    /// Name of the SCSI command, e.g. `READ(10)`
+    pub command: &'static str,
+    /// Why the command failed
+    pub source: UsbMassStorageReadWriteError,
+}
+
+impl CommandFailed {
+    /// Build the error of a failed `command` for `map_err`.
+    fn with(command: &'static str) -> impl FnOnce(UsbMassStorageReadWriteError) -> io::Error {
+        move |source| CommandFailed { command, source }.into()
+    }
+}
+
+impl From<CommandFailed> for io::Error {
+    fn from(err: CommandFailed) -> Self {
+        io::Error::other(err)
+    }
 }
 
-impl<'a> IoRead for UsbBlockDevice<'a> {
+impl<'a, T: UsbContext> IoRead for UsbBlockDevice<'a, T> {
     /// Reads up to `out.len()` bytes from the current cursor position,
     /// advancing the cursor. Will not cross past the end of the disk.
     fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
@@ -189,7 +246,7 @@ impl<'a> IoRead for UsbBlockDevice<'a> {
     }
 }
 
-impl<'a> IoWrite for UsbBlockDevice<'a> {
+impl<'a, T: UsbContext> IoWrite for UsbBlockDevice<'a, T> {
     /// Writes bytes starting at the current cursor position, advancing
     /// the cursor. May perform read-modify-write cycles when writes are
     /// not block-aligned.
@@ -275,7 +332,7 @@ impl<'a> IoWrite for UsbBlockDevice<'a> {
     }
 }
 
-impl<'a> IoSeek for UsbBlockDevice<'a> {
+impl<'a, T: UsbContext> IoSeek for UsbBlockDevice<'a, T> {
     /// Seeks to an absolute or relative position, clamping at disk boundaries.
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
         let disk = self.disk_size() as i128;
@@ -297,7 +354,7 @@ impl<'a> IoSeek for UsbBlockDevice<'a> {
     }
 }
 
-impl<'a> ReadAt for UsbBlockDevice<'a> {
+impl<'a, T: UsbContext> ReadAt for UsbBlockDevice<'a, T> {
     /// Reads bytes starting at an absolute `pos` without altering the cursor.
     fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
         self.read_at(pos, buf)
@@ -17,6 +17,10 @@
 //!   filters those that expose a Mass Storage interface (class code
 //!   `0x08`). Results are returned in the `Closed` state.
 //!
+//! - Devices belong to a libusb context, `rusb`'s [`GlobalContext`] by
+//!   default. [`UsbMassStorage::list_in`] lists the devices of any other
+//!   context, like the one of a [`UsbSession`](crate::session::UsbSession).
+//!
 //! - A `Closed` device can be transitioned to [`Opened`] by calling
 //!   [`UsbMassStorage::open`]. This will:
 //!   - Claim the Mass Storage interface.
@@ -65,44 +69,64 @@
 //! - Only Bulk-Only Transport (protocol code `0x50`) is supported, if you want other transport methods, create an issue, I'll be happy to implement it.
 //! - `GET_MAX_LUN` is provided via [`UsbMassStorage::get_max_lun`],
 //!   though most devices report only `0`.
-//! - All timeouts default to 10 seconds but may be tuned via the
-//!   [`Opened`] state.
+//! - All timeouts default to [`DEFAULT_TIMEOUT`], 10 seconds, but may be tuned with
+//!   [`UsbMassStorage::open_with_timeout`] or [`UsbMassStorage::set_timeout`].
 //!
 //! [`write`]: UsbMassStorage::write
 //! [`read`]: UsbMassStorage::read
 //! [`execute_command`]: UsbMassStorage::execute_command
 
-use rusb::{ConfigDescriptor, Device, DeviceHandle, Direction, GlobalContext, TransferType};
+use rusb::{
+    ConfigDescriptor, Device, DeviceHandle, Direction, GlobalContext, TransferType, UsbContext,
+};
+use std::fmt;
 use thiserror::Error;
 
 use crate::{
-    commands::{self, CommandBlock, cbw::Cbw},
+    commands::{
+        self, CommandBlock,
+        cbw::Cbw,
+        inquiry::{InquiryCommand, InquiryData},
+        start_stop_unit::StartStopUnitCommand,
+    },
+    select::{ListOptions, PortPath},
     storage::block_device::UsbBlockDevice,
 };
 
 pub mod block_device;
 
+/// How long a transfer may take before it fails, unless another timeout is given with
+/// [`UsbMassStorage::open_with_timeout`] or [`UsbMassStorage::set_timeout`].
+pub const DEFAULT_TIMEOUT: core::time::Duration = core::time::Duration::from_secs(10);
+
 /// Errors that can occur while enumerating or opening USB Mass Storage devices.
 #[derive(Error, Debug)]
 pub enum UsbMassStorageError {
+    /// Failed to create a libusb context for a [`UsbSession`](crate::session::UsbSession).
+    #[error("failed to create a libusb context")]
+    FailedToCreateContext(#[source] rusb::Error),
     /// Failed to retrieve device list from rusb.
     #[error("failed to get usb devices from rusb")]
-    FailedToGetUsbDevices,
+    FailedToGetUsbDevices(#[source] rusb::Error),
     /// Failed to open a selected device.
     #[error("failed to open usb devices from rusb")]
-    FailedToOpenUsbDevice,
+    FailedToOpenUsbDevice(#[source] rusb::Error),
+    /// The device doesn't have the configuration it was enumerated with anymore.
+    #[error("usb device has no configuration {0}")]
+    MissingConfiguration(u8),
     /// Failed to claim interface.
-    #[error("failed to claim interface for usb devices from rusb `{0}`")]
-    FailedToClaimInterfaceFromUsbDevice(rusb::Error),
+    #[error("failed to claim interface for usb devices from rusb")]
+    FailedToClaimInterfaceFromUsbDevice(#[source] rusb::Error),
 }
 
-/// A USB Mass Storage device, parameterized by its state (`Closed` or `Opened`).
+/// A USB Mass Storage device, parameterized by its state (`Closed` or `Opened`) and the libusb
+/// context it was listed in.
 ///
 /// - In `Closed` state, the device is enumerated but not opened.
 /// - In `Opened` state, the device is claimed and ready for I/O.
-#[derive(Debug, Clone)]
-pub struct UsbMassStorage<S = Closed> {
-    pub device: Device<GlobalContext>,
+#[derive(Clone)]
+pub struct UsbMassStorage<S = Closed, T: UsbContext = GlobalContext> {
+    pub device: Device<T>,
     pub device_config_number: u8,
     pub extra: S,
 }
@@ -111,11 +135,34 @@ pub struct UsbMassStorage<S = Closed> {
 ///
 /// Holds the active `DeviceHandle`, transport information,
 /// and a default timeout duration.
-#[derive(Debug)]
-pub struct Opened {
-    pub handle: DeviceHandle<GlobalContext>,
+pub struct Opened<T: UsbContext = GlobalContext> {
+    pub handle: DeviceHandle<T>,
     pub bulk_only_transport: Option<BulkOnlyTransport>,
     pub timeout_duration: core::time::Duration,
+    /// Whether the handle is reset before the interface is released on drop
+    reset_on_drop: bool,
+}
+
+// Written out, a derive would require the context to be `Debug`, which `GlobalContext` isn't
+impl<S: fmt::Debug, T: UsbContext> fmt::Debug for UsbMassStorage<S, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UsbMassStorage")
+            .field("device", &self.device)
+            .field("device_config_number", &self.device_config_number)
+            .field("extra", &self.extra)
+            .finish()
+    }
+}
+
+impl<T: UsbContext> fmt::Debug for Opened<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Opened")
+            .field("handle", &self.handle)
+            .field("bulk_only_transport", &self.bulk_only_transport)
+            .field("timeout_duration", &self.timeout_duration)
+            .field("reset_on_drop", &self.reset_on_drop)
+            .finish()
+    }
 }
 
 /// Marker type representing a closed USB Mass Storage device.
@@ -134,13 +181,23 @@ pub struct BulkOnlyTransport {
     pub interface_number: u8,
 }
 
-impl UsbMassStorage<Closed> {
+impl<T: UsbContext> UsbMassStorage<Closed, T> {
     /// Attempt to open the device and transition it into the [`Opened`] state.
     ///
     /// - Claims the MSC interface.
     /// - Locates IN/OUT bulk endpoints.
     /// - Configures the active configuration and alternate setting.
-    pub fn open(self) -> Result<UsbMassStorage<Opened>, UsbMassStorageError> {
+    ///
+    /// Transfers time out after [`DEFAULT_TIMEOUT`].
+    pub fn open(self) -> Result<UsbMassStorage<Opened<T>, T>, UsbMassStorageError> {
+        self.open_with_timeout(DEFAULT_TIMEOUT)
+    }
+
+    /// Same as [`open`](Self::open), with transfers timing out after `timeout`.
+    pub fn open_with_timeout(
+        self,
+        timeout: core::time::Duration,
+    ) -> Result<UsbMassStorage<Opened<T>, T>, UsbMassStorageError> {
         let handle = match self
             .device
             .open() {
@@ -153,7 +210,7 @@ impl UsbMassStorage<Closed> {
                          _ => (),
                     }
 
-                    return Err(UsbMassStorageError::FailedToOpenUsbDevice)
+                    return Err(UsbMassStorageError::FailedToOpenUsbDevice(err))
                 },
             };
 
@@ -168,8 +225,8 @@ impl UsbMassStorage<Closed> {
         let config = self
             .device
             .config_descriptor_by_number(self.device_config_number)
-            .map_err(|_| UsbMassStorageError::FailedToOpenUsbDevice)?
-            .ok_or(UsbMassStorageError::FailedToOpenUsbDevice)?;
+            .map_err(UsbMassStorageError::FailedToOpenUsbDevice)?
+            .ok_or(UsbMassStorageError::MissingConfiguration(self.device_config_number))?;
         for interface in config.interfaces() {
             for interface_descriptor in interface.descriptors() {
                 // Check if class is not mass storage interface or not a SCSI transparent command set
@@ -241,28 +298,49 @@ impl UsbMassStorage<Closed> {
             handle.clear_halt(bulk_only_transport.out_address).ok();
         }
 
-        Ok(UsbMassStorage::<Opened> {
+        Ok(UsbMassStorage::<Opened<T>, T> {
             device: self.device,
             device_config_number: self.device_config_number,
             extra: Opened {
                 handle,
                 bulk_only_transport,
-                timeout_duration: core::time::Duration::from_secs(10),
+                timeout_duration: timeout,
+                reset_on_drop: true,
             },
         })
     }
 }
 
-impl UsbMassStorage<Opened> {
+impl<T: UsbContext> UsbMassStorage<Opened<T>, T> {
     /// Close the device, releasing any claimed interfaces.
-    pub fn close(self) -> UsbMassStorage<Closed> {
-        UsbMassStorage::<Closed> {
+    pub fn close(self) -> UsbMassStorage<Closed, T> {
+        UsbMassStorage::<Closed, T> {
             device: self.device,
             device_config_number: self.device_config_number,
             extra: Closed,
         }
     }
 
+    /// Close the device, releasing the claimed interface without resetting the handle.
+    ///
+    /// [`close`](Self::close) resets the device, which can take the kernel a few seconds to
+    /// recover from. Use this after an interrupted transfer, so the device can be claimed again
+    /// right away.
+    pub fn release(mut self) -> UsbMassStorage<Closed, T> {
+        self.extra.reset_on_drop = false;
+        self.close()
+    }
+
+    /// How long a transfer may take before it fails.
+    pub fn timeout(&self) -> core::time::Duration {
+        self.extra.timeout_duration
+    }
+
+    /// Change how long the following transfers may take before they fail.
+    pub fn set_timeout(&mut self, timeout: core::time::Duration) {
+        self.extra.timeout_duration = timeout;
+    }
+
     /// Write raw bytes to the bulk OUT endpoint.
     ///
     /// Returns the number of bytes successfully sent.
@@ -279,7 +357,7 @@ impl UsbMassStorage<Opened> {
         let n = self.extra.handle.write_bulk(
             bulk_only_transport.out_address,
             data,
-            self.extra.timeout_duration,
+            self.timeout(),
         )?;
         Ok(n)
     }
@@ -300,7 +378,7 @@ impl UsbMassStorage<Opened> {
         let n = self.extra.handle.read_bulk(
             bulk_only_transport.in_address,
             buf,
-            self.extra.timeout_duration,
+            self.timeout(),
         )?;
         Ok(n)
     }
@@ -310,12 +388,12 @@ impl UsbMassStorage<Opened> {
     /// - Sends a Command Block Wrapper (CBW).
     /// - Performs the data phase (if any).
     /// - Reads and validates the Command Status Wrapper (CSW).
-    pub fn execute_command<T: CommandBlock>(
+    pub fn execute_command<C: CommandBlock>(
         &mut self,
         tag: u32,
         data_len: u32,
         direction: commands::cbw::Direction,
-        cmd: &T,
+        cmd: &C,
         data_buf: Option<&mut [u8]>,
     ) -> Result<(), UsbMassStorageReadWriteError> {
         // 1. Send CBW
@@ -370,7 +448,7 @@ impl UsbMassStorage<Opened> {
             w_value,
             w_index,
             &mut buf,
-            self.extra.timeout_duration,
+            self.timeout(),
         ) {
             Ok(1) => Ok(buf[0]),
             Ok(_) => Ok(0), // if unexpected size, fallback to 0
@@ -382,8 +460,29 @@ impl UsbMassStorage<Opened> {
         }
     }
 
+    /// Send a standard `INQUIRY`, e.g. to check whether the medium is removable.
+    pub fn inquiry(&mut self) -> Result<InquiryData, UsbMassStorageReadWriteError> {
+        let mut buf = [0u8; 36];
+        let cmd = InquiryCommand::new(buf.len() as u8);
+        self.execute_command(
+            0x12,
+            buf.len() as u32,
+            commands::cbw::Direction::In,
+            &cmd,
+            Some(&mut buf),
+        )?;
+        InquiryData::parse(&buf).ok_or(UsbMassStorageReadWriteError::InvalidResponse)
+    }
+
+    /// Send `START STOP UNIT` with `LoEj=1, Start=0`, ejecting the medium as an operating system
+    /// does when the drive is ejected.
+    pub fn eject(&mut self) -> Result<(), UsbMassStorageReadWriteError> {
+        let cmd = StartStopUnitCommand::eject(0);
+        self.execute_command(0x1B, 0, commands::cbw::Direction::Out, &cmd, None)
+    }
+
     /// Create a [`UsbBlockDevice`] abstraction for block-level I/O.
-    pub fn block_device<'a>(&'a mut self) -> std::io::Result<UsbBlockDevice<'a>> {
+    pub fn block_device<'a>(&'a mut self) -> std::io::Result<UsbBlockDevice<'a, T>> {
         UsbBlockDevice::new(self)
     }
 }
@@ -395,14 +494,20 @@ pub enum UsbMassStorageReadWriteError {
     #[error("there is no defined transportation method")]
     NoKnownTransportationMethod,
     /// Low-level bulk transfer failed.
-    #[error("bulk read error")]
+    #[error("bulk transfer failed")]
     UsbDeviceBulkFailed(#[from] rusb::Error),
+    /// The device answered a command with data that couldn't be parsed.
+    #[error("invalid response from device")]
+    InvalidResponse,
 }
 
-impl Drop for Opened {
-    /// Resets the handle and releases the claimed interface on drop.
+impl<T: UsbContext> Drop for Opened<T> {
+    /// Resets the handle, unless [`UsbMassStorage::release`] was used, and releases the claimed
+    /// interface on drop.
     fn drop(&mut self) {
-        let _ = self.handle.reset();
+        if self.reset_on_drop {
+            let _ = self.handle.reset();
+        }
         if let Some(bulk_only_transport) = &self.bulk_only_transport {
             let _ = self
                 .handle
@@ -416,11 +521,43 @@ impl UsbMassStorage {
     ///
     /// Filters by class code `0x08` (MSC). Returns devices in the `Closed` state.
     pub fn list() -> Result<Vec<UsbMassStorage<Closed>>, UsbMassStorageError> {
+        Self::list_in(&GlobalContext::default())
+    }
+
+    /// Enumerate the connected USB Mass Storage devices that `options` includes, see
+    /// [`UsbMassStorage::list`].
+    pub fn list_with(
+        options: &ListOptions,
+    ) -> Result<Vec<UsbMassStorage<Closed>>, UsbMassStorageError> {
+        Self::list_in_with(&GlobalContext::default(), options)
+    }
+}
+
+impl<T: UsbContext> UsbMassStorage<Closed, T> {
+    /// Enumerate the connected USB Mass Storage devices of the libusb `context`, see
+    /// [`UsbMassStorage::list`].
+    pub fn list_in(context: &T) -> Result<Vec<Self>, UsbMassStorageError> {
+        Self::list_in_with(context, &ListOptions::default())
+    }
+
+    /// Enumerate the connected USB Mass Storage devices of the libusb `context` that `options`
+    /// includes. Devices outside of its roots are skipped before their descriptors are read.
+    pub fn list_in_with(
+        context: &T,
+        options: &ListOptions,
+    ) -> Result<Vec<Self>, UsbMassStorageError> {
         let mut devices = Vec::new();
-        let rusb_devices =
-            rusb::devices().map_err(|_| UsbMassStorageError::FailedToGetUsbDevices)?;
+        let rusb_devices = context
+            .devices()
+            .map_err(UsbMassStorageError::FailedToGetUsbDevices)?;
 
+        let mut skipped = 0;
         for device in rusb_devices.iter() {
+            if !options.includes(PortPath::of(&device).as_ref()) {
+                skipped += 1;
+                continue;
+            }
+
             let desc = match device.device_descriptor() {
                 Ok(desc) => desc,
                 Err(_) => continue,
@@ -448,6 +585,9 @@ impl UsbMassStorage {
             }
         }
 
+        if skipped > 0 {
+            log::debug!("Skipped {skipped} usb devices outside of the roots to list");
+        }
         Ok(devices)
     }
 }
@@ -457,7 +597,7 @@ pub trait ConfigDescriptorExt {
     fn config_descriptor_by_number(&self, number: u8) -> rusb::Result<Option<ConfigDescriptor>>;
 }
 
-impl ConfigDescriptorExt for Device<GlobalContext> {
+impl<T: UsbContext> ConfigDescriptorExt for Device<T> {
     fn config_descriptor_by_number(&self, number: u8) -> rusb::Result<Option<ConfigDescriptor>> {
         let desc = self.device_descriptor()?;
         for idx in 0..desc.num_configurations() {
@@ -469,3 +609,46 @@ impl ConfigDescriptorExt for Device<GlobalContext> {
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::read10::Read10Command;
+    use core::time::Duration;
+
+    #[test]
+    #[ignore = "needs a USB mass storage device plugged in"]
+    fn set_timeout_applies_to_transfers() {
+        let mut usb = UsbMassStorage::list()
+            .unwrap()
+            .into_iter()
+            .next()
+            .expect("Plug in a USB mass storage device, like a board in its bootloader")
+            .open()
+            .unwrap();
+        assert_eq!(usb.timeout(), DEFAULT_TIMEOUT);
+
+        usb.set_timeout(Duration::from_millis(1));
+        assert_eq!(usb.timeout(), Duration::from_millis(1));
+
+        // Reading 1 MiB takes far longer than a millisecond, even on a high speed bus
+        let mut buf = vec![0; 2048 * 512];
+        let read = Read10Command::new(0, 0, 2048);
+        let err = usb
+            .execute_command(
+                1,
+                buf.len() as u32,
+                commands::cbw::Direction::In,
+                &read,
+                Some(&mut buf),
+            )
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                UsbMassStorageReadWriteError::UsbDeviceBulkFailed(rusb::Error::Timeout)
+            ),
+            "{err:?}"
+        );
+    }
+}